@@ -0,0 +1,716 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Background block authoring service.
+//!
+//! The [`AuthorService`] manages a background task dedicated to producing new blocks on top of
+//! the chain, using the keys found in a [`keystore::Keystore`]. Newly-produced blocks are written
+//! to the database and announced to the peers that the node is connected to.
+//!
+//! Transactions included in the blocks are gathered from the network and stored in a
+//! [`transactions::pool::Pool`]. They are validated against the best block before each block is
+//! authored.
+//!
+//! Importantly, the authored blocks are built on top of each other, starting from the finalized
+//! block found in the database when the service starts. Blocks received from the network are
+//! ignored by this service. As such, it is at the moment only suitable for chains where the local
+//! node is the only block producer, such as development chains.
+
+// TODO: take blocks received from the network into account
+
+use crate::{keystore, network_service};
+
+use core::{iter, mem, num::NonZeroU64, pin::Pin, time::Duration};
+use futures::{channel::mpsc, prelude::*};
+use smoldot::{
+    author,
+    chain::chain_information::{self, babe_config},
+    database::full_sqlite,
+    executor, header,
+    informant::HashDisplay,
+    libp2p::PeerId,
+    network, transactions,
+    trie::calculate_root,
+    verify,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    time::SystemTime,
+};
+use tracing::Instrument as _;
+
+/// Maximum number of transactions waiting to be included in a block. Transactions received
+/// while the pool is full are discarded.
+const MAX_PENDING_TRANSACTIONS: usize = 8192;
+
+/// Configuration for an [`AuthorService`].
+pub struct Config {
+    /// Closure that spawns background tasks.
+    pub tasks_executor: Box<dyn FnMut(Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+
+    /// Database to read the chain from and write authored blocks to.
+    pub database: Arc<full_sqlite::SqliteFullDatabase>,
+
+    /// Access to the network, and index of the chain to announce blocks to from the point of
+    /// view of the network service.
+    pub network_service: (Arc<network_service::NetworkService>, usize),
+
+    /// Receiver for events coming from the network, as returned by
+    /// [`network_service::NetworkService::new`]. Used to gather transactions.
+    pub network_events_receiver: mpsc::Receiver<network_service::Event>,
+
+    /// Keys used to sign the authored blocks.
+    pub keystore: Arc<keystore::Keystore>,
+}
+
+/// Background task that authors blocks.
+pub struct AuthorService {}
+
+impl AuthorService {
+    /// Initializes the [`AuthorService`] with the given configuration.
+    #[tracing::instrument(skip(config))]
+    pub async fn new(mut config: Config) -> Arc<Self> {
+        let finalized_block_hash = config.database.finalized_block_hash().unwrap();
+
+        (config.tasks_executor)(Box::pin(
+            start_author(
+                config.database,
+                config.network_service.0,
+                config.network_service.1,
+                config.network_events_receiver,
+                config.keystore,
+            )
+            .instrument(
+                tracing::debug_span!(parent: None, "author", root = %HashDisplay(&finalized_block_hash)),
+            ),
+        ));
+
+        Arc::new(AuthorService {})
+    }
+}
+
+/// State of the chain the blocks are authored on top of.
+struct BestBlock {
+    /// Hash of the block that new blocks will be built upon.
+    hash: [u8; 32],
+    /// SCALE-encoded header of the block that new blocks will be built upon.
+    scale_encoded_header: Vec<u8>,
+    /// Storage of the block that new blocks will be built upon.
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// Runtime of the block that new blocks will be built upon. `None` if the runtime has been
+    /// lost due to an error during the authoring, in which case it must be rebuilt from
+    /// [`BestBlock::storage`].
+    runtime: Option<executor::host::HostVmPrototype>,
+    /// Cache of the calculation of the storage trie root of the block.
+    top_trie_root_calculation_cache: Option<calculate_root::CalculationCache>,
+    /// Consensus-related information necessary to author the children of the block.
+    consensus: Consensus,
+}
+
+/// See [`BestBlock::consensus`].
+enum Consensus {
+    Aura {
+        /// List of Aura authorities allowed to author the children of the block.
+        authorities: Vec<header::AuraAuthority>,
+    },
+    Babe {
+        /// Number of slots per epoch in the Babe configuration.
+        slots_per_epoch: NonZeroU64,
+        /// Epoch the block belongs to. `None` if the block is the genesis block.
+        block_epoch: Option<chain_information::BabeEpochInformation>,
+        /// Epoch that follows [`Consensus::Babe::block_epoch`].
+        next_epoch: chain_information::BabeEpochInformation,
+    },
+}
+
+/// Returns the background task of the author service.
+async fn start_author(
+    database: Arc<full_sqlite::SqliteFullDatabase>,
+    network_service: Arc<network_service::NetworkService>,
+    network_chain_index: usize,
+    mut from_network_service: mpsc::Receiver<network_service::Event>,
+    keystore: Arc<keystore::Keystore>,
+) {
+    let finalized_block_hash = database.finalized_block_hash().unwrap();
+
+    let chain_information = database
+        .to_chain_information(&finalized_block_hash)
+        .unwrap();
+
+    let storage: BTreeMap<Vec<u8>, Vec<u8>> = database
+        .finalized_block_storage_top_trie(&finalized_block_hash)
+        .unwrap();
+
+    let (consensus, slot_duration, key_type) = match chain_information.consensus {
+        chain_information::ChainInformationConsensus::Aura {
+            finalized_authorities_list,
+            slot_duration,
+        } => (
+            Consensus::Aura {
+                authorities: finalized_authorities_list,
+            },
+            slot_duration,
+            keystore::KEY_TYPE_AURA,
+        ),
+        chain_information::ChainInformationConsensus::Babe {
+            slots_per_epoch,
+            finalized_block_epoch_information,
+            finalized_next_epoch_transition,
+        } => {
+            // The slot duration isn't part of the chain information, and must be obtained from
+            // the runtime.
+            let babe_config =
+                match babe_config::BabeGenesisConfiguration::from_genesis_storage(|key| {
+                    storage.get(key).cloned()
+                }) {
+                    Ok(cfg) => cfg,
+                    Err(error) => {
+                        tracing::error!(%error, "babe-configuration-error");
+                        return;
+                    }
+                };
+
+            (
+                Consensus::Babe {
+                    slots_per_epoch,
+                    block_epoch: finalized_block_epoch_information,
+                    next_epoch: finalized_next_epoch_transition,
+                },
+                babe_config.slot_duration,
+                keystore::KEY_TYPE_BABE,
+            )
+        }
+        chain_information::ChainInformationConsensus::AllAuthorized => {
+            tracing::warn!("block-authoring-unsupported-consensus");
+            return;
+        }
+    };
+
+    let mut best_block = BestBlock {
+        hash: finalized_block_hash,
+        scale_encoded_header: database
+            .block_scale_encoded_header(&finalized_block_hash)
+            .unwrap()
+            .unwrap(),
+        storage,
+        runtime: None,
+        top_trie_root_calculation_cache: None,
+        consensus,
+    };
+
+    // Transactions waiting to be included in a block, alongside with the peer that has sent
+    // them.
+    let mut transactions_pool =
+        transactions::pool::Pool::<PeerId>::new(transactions::pool::Config {
+            capacity: MAX_PENDING_TRANSACTIONS,
+            best_block_hash: best_block.hash,
+            best_block_height: header::decode(&best_block.scale_encoded_header)
+                .unwrap()
+                .number,
+        });
+
+    // Local keys, alongside with their index within the keystore.
+    let local_keys = keystore
+        .keys(&key_type)
+        .map(|(index, key)| (index, *key))
+        .collect::<Vec<_>>();
+    if local_keys.is_empty() {
+        tracing::warn!(key_type = %String::from_utf8_lossy(&key_type), "no-key-in-keystore");
+    }
+
+    // What to pass to the block builder for each local key. Aura only requires the public keys,
+    // while Babe requires the secret seeds in order to generate VRF outputs.
+    let local_authorities = local_keys
+        .iter()
+        .map(|(index, public_key)| match best_block.consensus {
+            Consensus::Aura { .. } => *public_key,
+            Consensus::Babe { .. } => *keystore.sr25519_seed(*index),
+        })
+        .collect::<Vec<_>>();
+
+    loop {
+        let now = unix_time();
+
+        let builder = author::build::Builder::new(author::build::Config {
+            consensus: match &best_block.consensus {
+                Consensus::Aura { authorities } => author::build::ConfigConsensus::Aura {
+                    now_from_unix_epoch: now,
+                    slot_duration,
+                    current_authorities: header::AuraAuthoritiesIter::from_slice(authorities),
+                    local_authorities: local_authorities.iter(),
+                },
+                Consensus::Babe {
+                    slots_per_epoch,
+                    block_epoch,
+                    next_epoch,
+                } => author::build::ConfigConsensus::Babe {
+                    now_from_unix_epoch: now,
+                    slot_duration,
+                    slots_per_epoch: *slots_per_epoch,
+                    parent_slot_number: header::decode(&best_block.scale_encoded_header)
+                        .unwrap()
+                        .digest
+                        .babe_pre_runtime()
+                        .map(|pre_digest| pre_digest.slot_number()),
+                    parent_block_epoch: block_epoch.as_ref().map(Into::into),
+                    parent_block_next_epoch: next_epoch.into(),
+                    local_authorities: local_authorities.iter(),
+                    randomness_seed: rand::random(),
+                },
+            },
+        });
+
+        // Wait until the slot starts, or until the next slot if none of the local keys can
+        // author. While waiting, keep gathering transactions from the network.
+        let authoring_start = match builder {
+            author::build::Builder::Idle => {
+                let next_try = now + Duration::from_millis(slot_duration.get());
+                wait_until(
+                    next_try,
+                    &mut from_network_service,
+                    network_chain_index,
+                    &mut transactions_pool,
+                )
+                .await;
+                continue;
+            }
+            author::build::Builder::WaitSlot(wait) => {
+                wait_until(
+                    wait.when(),
+                    &mut from_network_service,
+                    network_chain_index,
+                    &mut transactions_pool,
+                )
+                .await;
+                wait.start()
+            }
+            author::build::Builder::Ready(start) => start,
+            author::build::Builder::Authoring(_) => unreachable!(),
+        };
+
+        // Build the runtime of the parent if necessary.
+        let parent_runtime = match best_block.runtime.take() {
+            Some(r) => r,
+            None => match build_runtime(&best_block.storage) {
+                Ok(r) => r,
+                Err(error) => {
+                    tracing::error!(%error, "runtime-build-error");
+                    return;
+                }
+            },
+        };
+
+        // Validate against the parent the transactions that haven't been validated against it
+        // yet, and report the peers that have sent invalid ones.
+        let (parent_runtime, invalid_transactions_sources) =
+            validate_transactions(&mut transactions_pool, &best_block, parent_runtime);
+        for source in invalid_transactions_sources {
+            network_service
+                .report_peer(
+                    source,
+                    network::service::ReputationChange::InvalidTransaction,
+                )
+                .await;
+        }
+
+        let parent_number = header::decode(&best_block.scale_encoded_header)
+            .unwrap()
+            .number;
+
+        // Transactions are no longer pushed after half of the slot has elapsed, in order to
+        // leave enough time for the block to be propagated.
+        let authoring_deadline = unix_time() + Duration::from_millis(slot_duration.get() / 2);
+
+        // Transactions to include in the block, in order, and the one that is currently being
+        // applied.
+        let mut ready_transactions = transactions_pool
+            .ready_transactions()
+            .map(|(id, _)| id)
+            .collect::<VecDeque<_>>();
+        let mut applied_transaction = None;
+
+        let mut block_authoring = authoring_start.start(author::build::AuthoringStartConfig {
+            parent_hash: &best_block.hash,
+            parent_number,
+            now_from_unix_epoch: unix_time(),
+            parent_runtime,
+            top_trie_root_calculation_cache: best_block.top_trie_root_calculation_cache.take(),
+        });
+
+        let block = loop {
+            match block_authoring {
+                author::build::BuilderAuthoring::Error(error) => {
+                    tracing::warn!(%error, "block-authoring-error");
+                    break None;
+                }
+                author::build::BuilderAuthoring::ApplyExtrinsic(apply) => {
+                    block_authoring = match ready_transactions.pop_front() {
+                        Some(id) if unix_time() < authoring_deadline => {
+                            applied_transaction = Some(id);
+                            apply.add_extrinsic(transactions_pool.scale_encoding(id).to_vec())
+                        }
+                        _ => apply.finish(),
+                    };
+                }
+                author::build::BuilderAuthoring::ApplyExtrinsicResult { result, resume } => {
+                    let id = applied_transaction.take().unwrap();
+                    if let Err(error) = result {
+                        let (_, source) = transactions_pool.remove(id);
+                        tracing::debug!(%error, peer_id = %source, "transaction-invalid");
+                        network_service
+                            .report_peer(
+                                source,
+                                network::service::ReputationChange::InvalidTransaction,
+                            )
+                            .await;
+                    }
+                    block_authoring = author::build::BuilderAuthoring::ApplyExtrinsic(resume);
+                }
+                author::build::BuilderAuthoring::StorageGet(get) => {
+                    let value = best_block
+                        .storage
+                        .get(&get.key_as_vec())
+                        .map(|v| iter::once(&v[..]));
+                    block_authoring = get.inject_value(value);
+                }
+                author::build::BuilderAuthoring::NextKey(req) => {
+                    let next_key = best_block
+                        .storage
+                        .range(req.key().as_ref().to_vec()..)
+                        .find(|(k, _)| k[..] > *req.key().as_ref())
+                        .map(|(k, _)| k.clone());
+                    block_authoring = req.inject_key(next_key);
+                }
+                author::build::BuilderAuthoring::PrefixKeys(req) => {
+                    let prefix = req.prefix().as_ref().to_vec();
+                    let keys = best_block
+                        .storage
+                        .range(prefix.clone()..)
+                        .take_while(|(k, _)| k.starts_with(&prefix))
+                        .map(|(k, _)| k.clone())
+                        .collect::<Vec<_>>();
+                    block_authoring = req.inject_keys(keys.into_iter());
+                }
+                author::build::BuilderAuthoring::Seal(seal) => {
+                    let local_key_index = local_keys[seal.authority_index()].0;
+                    let signature = keystore.sign_sr25519(local_key_index, &seal.to_sign());
+                    break Some(seal.inject_sr25519_signature(signature));
+                }
+            }
+        };
+
+        let block = match block {
+            Some(b) => b,
+            None => {
+                // The runtime has been lost as part of the error. It will be rebuilt at the next
+                // iteration. Wait a bit in order to not try again within the same slot.
+                wait_until(
+                    unix_time() + Duration::from_millis(slot_duration.get()),
+                    &mut from_network_service,
+                    network_chain_index,
+                    &mut transactions_pool,
+                )
+                .await;
+                continue;
+            }
+        };
+
+        let new_block_hash = header::hash_from_scale_encoded_header(&block.scale_encoded_header);
+        let decoded_header = header::decode(&block.scale_encoded_header).unwrap();
+
+        // Update the consensus-related information for the children of the new block.
+        // In the case of Babe, this is done by verifying the newly-authored block, which
+        // additionally guarantees that the authoring has been done properly.
+        let slot_number = match &mut best_block.consensus {
+            Consensus::Aura { authorities } => {
+                for log in decoded_header.digest.logs() {
+                    if let header::DigestItemRef::AuraConsensus(
+                        header::AuraConsensusLogRef::AuthoritiesChange(list),
+                    ) = log
+                    {
+                        *authorities = list.map(|a| a.into()).collect();
+                    }
+                }
+
+                decoded_header
+                    .digest
+                    .aura_pre_runtime()
+                    .unwrap()
+                    .slot_number
+            }
+            Consensus::Babe {
+                slots_per_epoch,
+                block_epoch,
+                next_epoch,
+            } => {
+                let verify_result = verify::babe::verify_header(verify::babe::VerifyConfig {
+                    header: decoded_header.clone(),
+                    parent_block_header: header::decode(&best_block.scale_encoded_header).unwrap(),
+                    now_from_unix_epoch: unix_time(),
+                    slots_per_epoch: *slots_per_epoch,
+                    parent_block_epoch: block_epoch.as_ref().map(Into::into),
+                    parent_block_next_epoch: (&*next_epoch).into(),
+                });
+
+                match verify_result {
+                    Ok(success) => {
+                        if let Some(epoch_transition_target) = success.epoch_transition_target {
+                            *block_epoch = Some(mem::replace(next_epoch, epoch_transition_target));
+                        }
+                        success.slot_number
+                    }
+                    Err(error) => {
+                        tracing::error!(%error, "authored-block-verification-error");
+                        return;
+                    }
+                }
+            }
+        };
+        tracing::info!(
+            hash = %HashDisplay(&new_block_hash),
+            number = decoded_header.number,
+            num_extrinsics = block.body.len(),
+            "block-authored"
+        );
+
+        // Write the new block to the database.
+        if let Err(error) = database.insert(
+            &block.scale_encoded_header,
+            true,
+            block.body.iter(),
+            block
+                .storage_top_trie_changes
+                .iter()
+                .map(|(k, v)| (k, v.as_ref())),
+        ) {
+            tracing::error!(%error, "database-insert-error");
+            return;
+        }
+
+        network_service
+            .announce_block(network_chain_index, &block.scale_encoded_header, true)
+            .await;
+
+        // Remove from the pool the transactions that have been included, and the ones that have
+        // expired. The others are revalidated before the next block is authored.
+        transactions_pool.remove_included(block.body.iter());
+        let expired = transactions_pool.set_best_block(new_block_hash, decoded_header.number);
+        if !expired.is_empty() {
+            tracing::debug!(num = expired.len(), "transactions-expired");
+        }
+
+        // Update the rest of `best_block` to the newly-authored block.
+        let runtime_changed = block.storage_top_trie_changes.contains_key(&b":code"[..])
+            || block
+                .storage_top_trie_changes
+                .contains_key(&b":heappages"[..]);
+        for (key, value) in block.storage_top_trie_changes {
+            if let Some(value) = value {
+                best_block.storage.insert(key, value);
+            } else {
+                best_block.storage.remove(&key);
+            }
+        }
+        best_block.hash = new_block_hash;
+        best_block.runtime = if runtime_changed {
+            None
+        } else {
+            Some(block.parent_runtime)
+        };
+        best_block.top_trie_root_calculation_cache = Some(block.top_trie_root_calculation_cache);
+        best_block.scale_encoded_header = block.scale_encoded_header;
+
+        // Wait for the end of the slot that has just been used, as it is forbidden to author
+        // two blocks within the same slot.
+        wait_until(
+            Duration::from_millis(
+                slot_number
+                    .saturating_add(1)
+                    .saturating_mul(slot_duration.get()),
+            ),
+            &mut from_network_service,
+            network_chain_index,
+            &mut transactions_pool,
+        )
+        .await;
+    }
+}
+
+/// Waits until the given UNIX time. While waiting, adds the transactions received from the
+/// network to `transactions_pool`.
+async fn wait_until(
+    when: Duration,
+    from_network_service: &mut mpsc::Receiver<network_service::Event>,
+    network_chain_index: usize,
+    transactions_pool: &mut transactions::pool::Pool<PeerId>,
+) {
+    let mut timer = futures_timer::Delay::new(when.saturating_sub(unix_time())).fuse();
+
+    loop {
+        futures::select! {
+            _ = timer => return,
+            network_event = from_network_service.next() => {
+                match network_event {
+                    Some(network_service::Event::Transactions { chain_index, peer_id, transactions })
+                        if chain_index == network_chain_index =>
+                    {
+                        let transactions = transactions.decode();
+                        tracing::debug!(%peer_id, num = transactions.len(), "transactions-received");
+                        for transaction in transactions {
+                            if transactions_pool.len() >= MAX_PENDING_TRANSACTIONS {
+                                break;
+                            }
+                            // Transactions already in the pool are ignored.
+                            let _ = transactions_pool
+                                .add_unvalidated(transaction.to_vec(), peer_id.clone());
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        // The network service has shut down. Just wait for the timer.
+                        timer.await;
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Validates against `best_block` all the transactions of the pool that haven't been validated
+/// against it yet. Invalid transactions are removed from the pool.
+///
+/// Returns the runtime passed as parameter, and the peers that have sent the invalid
+/// transactions.
+fn validate_transactions(
+    transactions_pool: &mut transactions::pool::Pool<PeerId>,
+    best_block: &BestBlock,
+    mut runtime: executor::host::HostVmPrototype,
+) -> (executor::host::HostVmPrototype, Vec<PeerId>) {
+    let best_block_height = transactions_pool.best_block().1;
+    let mut invalid_transactions_sources = Vec::new();
+
+    let to_validate = transactions_pool
+        .unvalidated_transactions()
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+
+    for id in to_validate {
+        let mut query =
+            transactions::validate::validate_transaction(transactions::validate::Config {
+                runtime,
+                block_hash: &best_block.hash,
+                source: transactions::validate::TransactionSource::External,
+                scale_encoded_transaction: transactions_pool.scale_encoding(id),
+            });
+
+        let result = loop {
+            match query {
+                transactions::validate::Query::Finished {
+                    result,
+                    virtual_machine,
+                } => {
+                    runtime = virtual_machine;
+                    break result;
+                }
+                transactions::validate::Query::StorageGet(get) => {
+                    let value = best_block
+                        .storage
+                        .get(&get.key_as_vec())
+                        .map(|v| iter::once(&v[..]));
+                    query = get.inject_value(value);
+                }
+                transactions::validate::Query::NextKey(req) => {
+                    let next_key = best_block
+                        .storage
+                        .range(req.key().as_ref().to_vec()..)
+                        .find(|(k, _)| k[..] > *req.key().as_ref())
+                        .map(|(k, _)| k.clone());
+                    query = req.inject_key(next_key);
+                }
+                transactions::validate::Query::PrefixKeys(req) => {
+                    let prefix = req.prefix().as_ref().to_vec();
+                    let keys = best_block
+                        .storage
+                        .range(prefix.clone()..)
+                        .take_while(|(k, _)| k.starts_with(&prefix))
+                        .map(|(k, _)| k.clone())
+                        .collect::<Vec<_>>();
+                    query = req.inject_keys(keys.into_iter());
+                }
+            }
+        };
+
+        match result {
+            Ok(Ok(valid)) => {
+                transactions_pool.set_validation_result(
+                    id,
+                    best_block.hash,
+                    best_block_height,
+                    valid,
+                );
+            }
+            Ok(Err(error)) => {
+                let (_, source) = transactions_pool.remove(id);
+                tracing::debug!(%error, peer_id = %source, "transaction-invalid");
+                invalid_transactions_sources.push(source);
+            }
+            Err(error) => {
+                let (_, source) = transactions_pool.remove(id);
+                tracing::warn!(%error, peer_id = %source, "transaction-validation-error");
+            }
+        }
+    }
+
+    (runtime, invalid_transactions_sources)
+}
+
+/// Builds the runtime corresponding to the given storage.
+fn build_runtime(
+    storage: &BTreeMap<Vec<u8>, Vec<u8>>,
+) -> Result<executor::host::HostVmPrototype, BuildRuntimeError> {
+    let code = storage
+        .get(&b":code"[..])
+        .ok_or(BuildRuntimeError::MissingCode)?;
+    let heap_pages =
+        executor::storage_heap_pages_to_value(storage.get(&b":heappages"[..]).map(|v| &v[..]))
+            .map_err(BuildRuntimeError::HeapPages)?;
+    executor::host::HostVmPrototype::new(
+        code,
+        heap_pages,
+        executor::vm::ExecHint::CompileAheadOfTime,
+    )
+    .map_err(BuildRuntimeError::Vm)
+}
+
+#[derive(Debug, derive_more::Display)]
+enum BuildRuntimeError {
+    #[display(fmt = "Missing :code in storage")]
+    MissingCode,
+    #[display(fmt = "{}", _0)]
+    HeapPages(executor::InvalidHeapPagesError),
+    #[display(fmt = "{}", _0)]
+    Vm(executor::host::NewErr),
+}
+
+/// Returns the time elapsed since the UNIX epoch.
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+}
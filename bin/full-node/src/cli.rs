@@ -59,6 +59,27 @@ pub struct CliOptions {
     /// Do not load or store anything on disk.
     #[structopt(long)]
     pub tmp: bool,
+    /// If the database is empty, start from the checkpoint found in the chain specification, if
+    /// any, instead of the genesis block. The storage of the checkpoint is downloaded from the
+    /// network.
+    #[structopt(long)]
+    pub checkpoint_sync: bool,
+    /// Author blocks using the keys found in the keystore.
+    #[structopt(long)]
+    pub validator: bool,
+    /// Directory containing the keys used to author blocks. Defaults to a directory specific to
+    /// the chain.
+    #[structopt(long, parse(from_os_str))]
+    pub keystore_path: Option<PathBuf>,
+    /// Maximum number of peers that can open substreams towards the node, per chain.
+    #[structopt(long, default_value = "25")]
+    pub in_peers: u32,
+    /// Maximum number of peers the node tries to open substreams with, per chain.
+    #[structopt(long, default_value = "25")]
+    pub out_peers: u32,
+    /// Maximum number of simultaneous attempts at reaching a node, per chain.
+    #[structopt(long, default_value = "8")]
+    pub max_concurrent_dials: usize,
 }
 
 #[derive(Debug)]
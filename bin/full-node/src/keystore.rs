@@ -0,0 +1,181 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Collection of sr25519 keys used by the node when acting as a validator.
+//!
+//! The keys are loaded from a directory on the filesystem. The format of this directory mimics
+//! the one of Substrate: each file of the directory contains one key. The name of the file is
+//! the hexadecimal representation of the four bytes identifying the usage of the key (for
+//! example `aura` or `babe`), followed with the hexadecimal representation of the public key.
+//! The content of the file is a JSON string containing the hexadecimal representation, prefixed
+//! with `0x`, of the 32-bytes seed of the key.
+//!
+//! > **Note**: Contrary to Substrate, mnemonic phrases and derivation paths aren't supported.
+
+use std::{fs, io, path::Path};
+
+/// Identifier of the Aura keys. Equal to the ASCII representation of `aura`.
+pub const KEY_TYPE_AURA: [u8; 4] = *b"aura";
+/// Identifier of the Babe keys. Equal to the ASCII representation of `babe`.
+pub const KEY_TYPE_BABE: [u8; 4] = *b"babe";
+
+/// Collection of sr25519 keys loaded from the disk.
+pub struct Keystore {
+    /// List of keys. Never modified after the [`Keystore`] has been loaded.
+    keys: Vec<Key>,
+}
+
+struct Key {
+    /// See [`KEY_TYPE_AURA`] and [`KEY_TYPE_BABE`].
+    key_type: [u8; 4],
+    /// Public key. Redundant with `keypair`, but returned by reference by the API.
+    public_key: [u8; 32],
+    /// Secret seed the key has been derived from.
+    seed: [u8; 32],
+    keypair: schnorrkel::Keypair,
+}
+
+impl Keystore {
+    /// Loads all the keys found in the given directory.
+    ///
+    /// The directory is created if it doesn't exist. Files whose name doesn't follow the expected
+    /// format are ignored, while files with a corrupted content lead to an error.
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        fs::create_dir_all(path).map_err(LoadError::Io)?;
+
+        let mut keys = Vec::new();
+
+        for entry in fs::read_dir(path).map_err(LoadError::Io)? {
+            let entry = entry.map_err(LoadError::Io)?;
+
+            // Parse the file name into a key type and a public key.
+            let (key_type, public_key) = {
+                let file_name = entry.file_name();
+                let decoded = match file_name.to_str().and_then(|n| hex::decode(n).ok()) {
+                    Some(d) if d.len() == 36 => d,
+                    _ => continue,
+                };
+
+                let mut key_type = [0; 4];
+                key_type.copy_from_slice(&decoded[..4]);
+                let mut public_key = [0; 32];
+                public_key.copy_from_slice(&decoded[4..]);
+                (key_type, public_key)
+            };
+
+            if key_type != KEY_TYPE_AURA && key_type != KEY_TYPE_BABE {
+                continue;
+            }
+
+            let content = fs::read_to_string(entry.path()).map_err(LoadError::Io)?;
+            let (seed, keypair) = parse_seed(&content)
+                .map_err(|()| LoadError::BadContent(entry.file_name().to_string_lossy().into()))?;
+
+            // Make sure that the name of the file matches the actual key. This protects against
+            // mistakes such as copy-pasting the wrong file.
+            if keypair.public.to_bytes() != public_key {
+                return Err(LoadError::PublicKeyMismatch(
+                    entry.file_name().to_string_lossy().into(),
+                ));
+            }
+
+            keys.push(Key {
+                key_type,
+                public_key,
+                seed,
+                keypair,
+            });
+        }
+
+        // Sort the keys in order to give a deterministic behaviour to the node.
+        keys.sort_by_key(|k| (k.key_type, k.public_key));
+
+        Ok(Keystore { keys })
+    }
+
+    /// Returns the list of public keys of the given type, alongside with their index. The index
+    /// can later be passed to [`Keystore::sign_sr25519`] or [`Keystore::sr25519_seed`].
+    pub fn keys<'a>(
+        &'a self,
+        key_type: &'a [u8; 4],
+    ) -> impl Iterator<Item = (usize, &'a [u8; 32])> + Clone + 'a {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter(move |(_, k)| k.key_type == *key_type)
+            .map(|(n, k)| (n, &k.public_key))
+    }
+
+    /// Signs the given message with the key of the given index, using the `substrate` signing
+    /// context.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `index` is out of range.
+    ///
+    pub fn sign_sr25519(&self, index: usize, message: &[u8]) -> [u8; 64] {
+        self.keys[index]
+            .keypair
+            .sign_simple(b"substrate", message)
+            .to_bytes()
+    }
+
+    /// Returns the secret seed of the key of the given index.
+    ///
+    /// Necessary for Babe, as claiming a slot requires generating a VRF output.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `index` is out of range.
+    ///
+    pub fn sr25519_seed(&self, index: usize) -> &[u8; 32] {
+        &self.keys[index].seed
+    }
+}
+
+/// Parses the content of a key file.
+fn parse_seed(content: &str) -> Result<([u8; 32], schnorrkel::Keypair), ()> {
+    let content = content.trim();
+    let content = content
+        .strip_prefix('"')
+        .and_then(|c| c.strip_suffix('"'))
+        .ok_or(())?;
+    let content = content.strip_prefix("0x").ok_or(())?;
+
+    let mut seed = [0; 32];
+    hex::decode_to_slice(content, &mut seed).map_err(|_| ())?;
+    let mini_secret = schnorrkel::MiniSecretKey::from_bytes(&seed).map_err(|_| ())?;
+    // Substrate uses the "ed25519" expansion mode when building sr25519 keys from seeds.
+    Ok((
+        seed,
+        mini_secret.expand_to_keypair(schnorrkel::ExpansionMode::Ed25519),
+    ))
+}
+
+/// Error potentially returned by [`Keystore::load`].
+#[derive(Debug, derive_more::Display)]
+pub enum LoadError {
+    /// Error while accessing the filesystem.
+    #[display(fmt = "{}", _0)]
+    Io(io::Error),
+    /// The content of the given file isn't a valid key.
+    #[display(fmt = "Invalid key in file {}", _0)]
+    BadContent(String),
+    /// The content of the given file doesn't match the public key in its name.
+    #[display(fmt = "Public key mismatch in file {}", _0)]
+    PublicKeyMismatch(String),
+}
@@ -28,13 +28,21 @@ use smoldot::{
     libp2p::{connection, multiaddr, peer_id::PeerId},
 };
 use std::{
-    borrow::Cow, convert::TryFrom as _, fs, io, iter, path::PathBuf, sync::Arc, thread,
-    time::Duration,
+    borrow::Cow,
+    collections::BTreeMap,
+    convert::TryFrom as _,
+    fs, io, iter,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
 };
 use structopt::StructOpt as _;
 use tracing::Instrument as _;
 
+mod author_service;
 mod cli;
+mod keystore;
 mod network_service;
 mod sync_service;
 
@@ -98,7 +106,7 @@ async fn async_main() {
         chain::chain_information::ChainInformation::from_chain_spec(&chain_spec).unwrap();
 
     // If `chain_spec` define a parachain, also load the specs of the relay chain.
-    let (relay_chain_spec, _parachain_id) =
+    let (relay_chain_spec, parachain_id) =
         if let Some((relay_chain_name, parachain_id)) = chain_spec.relay_chain() {
             let json: Cow<[u8]> = match &cli_options.chain {
                 cli::CliChain::Custom(parachain_path) => {
@@ -138,13 +146,20 @@ async fn async_main() {
         .create()
         .unwrap();
 
-    let database = open_database(&chain_spec, &genesis_chain_information, cli_options.tmp).await;
+    let database = open_database(
+        &chain_spec,
+        &genesis_chain_information,
+        cli_options.tmp,
+        cli_options.checkpoint_sync,
+    )
+    .await;
     let relay_chain_database = if let Some(relay_chain_spec) = &relay_chain_spec {
         Some(
             open_database(
                 &relay_chain_spec,
                 relay_genesis_chain_information.as_ref().unwrap(),
                 cli_options.tmp,
+                cli_options.checkpoint_sync,
             )
             .await,
         )
@@ -167,16 +182,36 @@ async fn async_main() {
         smoldot::metadata::decode(&metadata).unwrap()
     );*/
 
+    // If the node is a validator, load the keys used to author blocks.
+    let keystore = if cli_options.validator {
+        let path = if let Some(path) = &cli_options.keystore_path {
+            path.clone()
+        } else {
+            app_dirs::app_dir(app_dirs::AppDataType::UserData, &cli::APP_INFO, "keystore")
+                .unwrap()
+                .join(chain_spec.id())
+        };
+
+        let keystore = keystore::Keystore::load(&path).expect("Failed to load keystore");
+        Some(Arc::new(keystore))
+    } else {
+        None
+    };
+
     let (network_service, network_events_receivers) =
         network_service::NetworkService::new(network_service::Config {
             listen_addresses: Vec::new(),
-            num_events_receivers: 2 + if relay_chain_database.is_some() { 1 } else { 0 },
+            num_events_receivers: 2
+                + if relay_chain_database.is_some() { 1 } else { 0 }
+                + if keystore.is_some() { 1 } else { 0 },
             chains: iter::once(network_service::ChainConfig {
                 protocol_id: chain_spec.protocol_id().to_owned(),
                 has_grandpa_protocol: matches!(
                     genesis_chain_information.finality,
                     chain::chain_information::ChainInformationFinality::Grandpa { .. }
                 ),
+                in_slots: cli_options.in_peers,
+                out_slots: cli_options.out_peers,
                 genesis_block_hash: genesis_chain_information.finalized_block_header.hash(),
                 best_block: {
                     let hash = database.finalized_block_hash().unwrap();
@@ -208,6 +243,8 @@ async fn async_main() {
                                 relay_genesis_chain_information.as_ref().unwrap().finality,
                                 chain::chain_information::ChainInformationFinality::Grandpa { .. }
                             ),
+                            in_slots: cli_options.in_peers,
+                            out_slots: cli_options.out_peers,
                             genesis_block_hash: relay_genesis_chain_information
                                 .as_ref()
                                 .unwrap()
@@ -245,6 +282,11 @@ async fn async_main() {
                 // TODO: load from disk or something instead
                 connection::NoiseKey::new(&rand::random())
             },
+            max_concurrent_dials: cli_options.max_concurrent_dials,
+            dial_backoff_base: Duration::from_secs(5),
+            dial_backoff_max: Duration::from_secs(300),
+            ban_threshold: -1000,
+            ban_duration: Duration::from_secs(300),
             tasks_executor: {
                 let threads_pool = threads_pool.clone();
                 Box::new(move |task| threads_pool.spawn_ok(task))
@@ -256,19 +298,28 @@ async fn async_main() {
 
     let mut network_events_receivers = network_events_receivers.into_iter();
 
-    let sync_service = sync_service::SyncService::new(sync_service::Config {
-        tasks_executor: {
-            let threads_pool = threads_pool.clone();
-            Box::new(move |task| threads_pool.spawn_ok(task))
-        },
-        network_events_receiver: network_events_receivers.next().unwrap(),
-        network_service: (network_service.clone(), 0),
-        database,
-    })
-    .instrument(tracing::debug_span!("sync-service-init"))
-    .await;
+    let _author_service = if let Some(keystore) = keystore {
+        Some(
+            author_service::AuthorService::new(author_service::Config {
+                tasks_executor: {
+                    let threads_pool = threads_pool.clone();
+                    Box::new(move |task| threads_pool.spawn_ok(task))
+                },
+                network_events_receiver: network_events_receivers.next().unwrap(),
+                network_service: (network_service.clone(), 0),
+                database: database.clone(),
+                keystore,
+            })
+            .instrument(tracing::debug_span!("author-service-init"))
+            .await,
+        )
+    } else {
+        None
+    };
 
-    let relay_chain_sync_service = if let Some(relay_chain_database) = relay_chain_database {
+    // The relay chain sync service, if any, is started first, as the sync service of the
+    // parachain relies on the database it maintains.
+    let relay_chain_sync_service = if let Some(relay_chain_database) = &relay_chain_database {
         Some(
             sync_service::SyncService::new(sync_service::Config {
                 tasks_executor: {
@@ -277,7 +328,15 @@ async fn async_main() {
                 },
                 network_events_receiver: network_events_receivers.next().unwrap(),
                 network_service: (network_service.clone(), 1),
-                database: relay_chain_database,
+                database: relay_chain_database.clone(),
+                bad_blocks: relay_chain_spec
+                    .as_ref()
+                    .unwrap()
+                    .bad_blocks_hashes()
+                    .copied()
+                    .collect(),
+                fork_blocks: relay_chain_spec.as_ref().unwrap().fork_blocks().collect(),
+                parachain: None,
             })
             .instrument(tracing::debug_span!("relay-chain-sync-service-init"))
             .await,
@@ -286,6 +345,25 @@ async fn async_main() {
         None
     };
 
+    let sync_service = sync_service::SyncService::new(sync_service::Config {
+        tasks_executor: {
+            let threads_pool = threads_pool.clone();
+            Box::new(move |task| threads_pool.spawn_ok(task))
+        },
+        network_events_receiver: network_events_receivers.next().unwrap(),
+        network_service: (network_service.clone(), 0),
+        database,
+        bad_blocks: chain_spec.bad_blocks_hashes().copied().collect(),
+        fork_blocks: chain_spec.fork_blocks().collect(),
+        parachain: relay_chain_database.map(|relay_chain_database| sync_service::ConfigParachain {
+            relay_chain_database,
+            relay_chain_sync_service: relay_chain_sync_service.clone().unwrap(),
+            parachain_id: parachain_id.unwrap(),
+        }),
+    })
+    .instrument(tracing::debug_span!("sync-service-init"))
+    .await;
+
     /*let mut telemetry = {
         let endpoints = chain_spec
             .telemetry_endpoints()
@@ -335,12 +413,32 @@ async fn async_main() {
     );
 
     let mut network_known_best = None;
+
+    // Total bandwidth and instant when it was measured, used to calculate the bandwidth per
+    // second. Also contains the latest calculated values in bytes per second.
+    let mut bandwidth_measure = (network_service.total_bandwidth().await, Instant::now());
+    let mut bandwidth_per_sec: (Option<u64>, Option<u64>) = (None, None);
     let mut main_network_events_receiver = network_events_receivers.next().unwrap();
     debug_assert!(network_events_receivers.next().is_none());
 
     loop {
         futures::select! {
             _ = informant_timer.next() => {
+                {
+                    let new_measure = (network_service.total_bandwidth().await, Instant::now());
+                    let elapsed = new_measure.1 - bandwidth_measure.1;
+                    if elapsed >= Duration::from_millis(500) {
+                        let per_sec = |bytes: u64| {
+                            u64::try_from(u128::from(bytes) * 1000 / elapsed.as_millis()).ok()
+                        };
+                        bandwidth_per_sec = (
+                            per_sec(new_measure.0.bytes_received.saturating_sub(bandwidth_measure.0.bytes_received)),
+                            per_sec(new_measure.0.bytes_sent.saturating_sub(bandwidth_measure.0.bytes_sent)),
+                        );
+                        bandwidth_measure = new_measure;
+                    }
+                }
+
                 if matches!(cli_options.output, cli::Output::Informant) {
                     // We end the informant line with a `\r` so that it overwrites itself every time.
                     // If any other line gets printed, it will overwrite the informant, and the
@@ -369,7 +467,34 @@ async fn async_main() {
                         best_hash: &sync_state.best_block_hash,
                         finalized_hash: &sync_state.finalized_block_hash,
                         network_known_best,
+                        bandwidth_download: bandwidth_per_sec.0,
+                        bandwidth_upload: bandwidth_per_sec.1,
                     });
+                } else {
+                    tracing::debug!(
+                        download_per_sec = ?bandwidth_per_sec.0,
+                        upload_per_sec = ?bandwidth_per_sec.1,
+                        "bandwidth"
+                    );
+
+                    // Sum the number of bytes transferred on each protocol over all the
+                    // connections.
+                    let mut protocols = BTreeMap::<String, smoldot::libp2p::Bandwidth>::new();
+                    for connection in network_service.connections_bandwidth().await {
+                        for (protocol, bandwidth) in connection.protocols {
+                            let entry = protocols.entry(protocol).or_default();
+                            entry.bytes_received += bandwidth.bytes_received;
+                            entry.bytes_sent += bandwidth.bytes_sent;
+                        }
+                    }
+                    for (protocol, bandwidth) in protocols {
+                        tracing::trace!(
+                            %protocol,
+                            bytes_received = bandwidth.bytes_received,
+                            bytes_sent = bandwidth.bytes_sent,
+                            "protocol-bandwidth"
+                        );
+                    }
                 }
             },
 
@@ -406,8 +531,8 @@ async fn async_main() {
                     },
                     memory: None,
                     cpu: None,
-                    bandwidth_upload: Some(0.0), // TODO:
-                    bandwidth_download: Some(0.0), // TODO:
+                    bandwidth_upload: bandwidth_per_sec.1.map(|b| b as f64),
+                    bandwidth_download: bandwidth_per_sec.0.map(|b| b as f64),
                     finalized_height: Some(sync_state.finalized_block_number),
                     finalized_hash: Some(sync_state.finalized_block_hash.into()),
                     block: smoldot::telemetry::message::Block {
@@ -445,6 +570,7 @@ async fn open_database(
     chain_spec: &chain_spec::ChainSpec,
     genesis_chain_information: &chain::chain_information::ChainInformation,
     tmp: bool,
+    checkpoint_sync: bool,
 ) -> Arc<full_sqlite::SqliteFullDatabase> {
     Arc::new({
         // Directory supposed to contain the database.
@@ -477,16 +603,38 @@ async fn open_database(
 
             // The database doesn't exist or is empty.
             full_sqlite::DatabaseOpen::Empty(empty) => {
-                // The finalized block is the genesis block. As such, it has an empty body and
-                // no justification.
-                empty
-                    .initialize(
-                        genesis_chain_information,
-                        iter::empty(),
-                        None,
-                        chain_spec.genesis_storage(),
-                    )
-                    .unwrap()
+                let checkpoint = if checkpoint_sync {
+                    let checkpoint = chain_spec.light_sync_state();
+                    if checkpoint.is_none() {
+                        eprintln!("Chain specification doesn't contain any checkpoint");
+                    }
+                    checkpoint
+                } else {
+                    None
+                };
+
+                match checkpoint {
+                    // The storage, body and justification of the checkpoint aren't known. The
+                    // storage is later downloaded from the network by the sync service.
+                    Some(checkpoint) => empty
+                        .initialize(
+                            &checkpoint.as_chain_information(),
+                            iter::empty(),
+                            None,
+                            iter::empty(),
+                        )
+                        .unwrap(),
+                    // The finalized block is the genesis block. As such, it has an empty body
+                    // and no justification.
+                    None => empty
+                        .initialize(
+                            genesis_chain_information,
+                            iter::empty(),
+                            None,
+                            chain_spec.genesis_storage(),
+                        )
+                        .unwrap(),
+                }
             }
         }
     })
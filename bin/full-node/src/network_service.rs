@@ -23,13 +23,28 @@
 //! The [`NetworkService`] spawns one background task (using the [`Config::tasks_executor`]) for
 //! each active TCP socket, plus one for each TCP listening socket. Messages are exchanged between
 //! the service and these background tasks.
+//!
+//! For each chain, a background task is dedicated to opening outgoing connections. This task
+//! keeps up to [`Config::max_concurrent_dials`] dialing attempts in progress at any given time.
+//! Addresses that have failed to be reached are put in a backoff period by the underlying
+//! [`service::ChainNetwork`] (see [`Config::dial_backoff_base`]). The task is woken up whenever
+//! a connection ends or new nodes are discovered.
+//!
+//! Connections can use either plain TCP (multiaddresses of the form `/ip4/.../tcp/...`) or
+//! WebSocket on top of TCP (multiaddresses of the form `/ip4/.../tcp/.../ws`), the latter being
+//! the only transport that browser-based nodes are capable of.
 
 // TODO: doc
 // TODO: re-review this once finished
 
 use core::{cmp, pin::Pin, time::Duration};
-use futures::{channel::mpsc, prelude::*};
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+    stream::FuturesUnordered,
+};
 use smoldot::{
+    header,
     informant::HashDisplay,
     libp2p::{
         connection,
@@ -41,8 +56,16 @@ use smoldot::{
 use std::{io, net::SocketAddr, num::NonZeroUsize, sync::Arc, time::Instant};
 use tracing::Instrument as _;
 
+mod dns;
+mod websocket;
 mod with_buffers;
 
+/// Maximum duration between two attempts at finding new nodes to dial.
+///
+/// The task dedicated to opening connections is normally woken up by the events that might make
+/// new nodes dialable. Backoff periods, however, end without any event being generated.
+const DIAL_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
 /// Configuration for a [`NetworkService`].
 pub struct Config {
     /// Closure that spawns background tasks.
@@ -61,6 +84,26 @@ pub struct Config {
     /// This is a Noise static key, according to the Noise specifications.
     /// Signed using the actual libp2p key.
     pub noise_key: connection::NoiseKey,
+
+    /// Maximum number of simultaneous attempts at reaching a node, per chain.
+    ///
+    /// Only the phase of reaching the target is taken into account, and not the handshake that
+    /// follows.
+    pub max_concurrent_dials: usize,
+
+    /// Duration during which an address isn't dialed again after a first failed dialing
+    /// attempt. Doubled after each consecutive failure.
+    pub dial_backoff_base: Duration,
+
+    /// Maximum duration during which an address isn't dialed again after a failed dialing
+    /// attempt.
+    pub dial_backoff_max: Duration,
+
+    /// Reputation below which a peer gets banned. See [`NetworkService::report_peer`].
+    pub ban_threshold: i32,
+
+    /// Duration during which a banned peer isn't connected to.
+    pub ban_duration: Duration,
 }
 
 /// Configuration for one chain.
@@ -84,6 +127,12 @@ pub struct ChainConfig {
 
     /// If true, the chain uses the GrandPa networking protocol.
     pub has_grandpa_protocol: bool,
+
+    /// Maximum number of peers that can open substreams towards the local node on this chain.
+    pub in_slots: u32,
+
+    /// Maximum number of peers the local node tries to open substreams with on this chain.
+    pub out_slots: u32,
 }
 
 /// Event generated by the events reporters returned by [`NetworkService::new`].
@@ -103,6 +152,11 @@ pub enum Event {
         peer_id: PeerId,
         announce: service::EncodedBlockAnnounce,
     },
+    Transactions {
+        chain_index: usize,
+        peer_id: PeerId,
+        transactions: service::EncodedTransactions,
+    },
 }
 
 pub struct NetworkService {
@@ -113,28 +167,37 @@ pub struct NetworkService {
 
     /// Data structure holding the entire state of the networking.
     network: service::ChainNetwork<Instant, (), ()>,
+
+    /// Resolver for the host names found in `/dns`, `/dns4` and `/dns6` multiaddresses.
+    resolver: Arc<dns::Resolver>,
 }
 
 /// Fields of [`NetworkService`] behind a mutex.
 struct Guarded {
     /// See [`Config::tasks_executor`].
     tasks_executor: Box<dyn FnMut(Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+
+    /// For each chain, sender that wakes up the task dedicated to opening connections.
+    /// See [`NetworkService::wake_up_dialers`].
+    dialers_wake_up: Vec<mpsc::Sender<()>>,
 }
 
 impl NetworkService {
     /// Initializes the network service with the given configuration.
-    pub async fn new(
-        mut config: Config,
-    ) -> Result<(Arc<Self>, Vec<mpsc::Receiver<Event>>), InitError> {
+    pub async fn new(config: Config) -> Result<(Arc<Self>, Vec<mpsc::Receiver<Event>>), InitError> {
+        let max_concurrent_dials = config.max_concurrent_dials;
+
         let (mut senders, receivers): (Vec<_>, Vec<_>) = (0..config.num_events_receivers)
             .map(|_| mpsc::channel(16))
             .unzip();
 
-        // For each listening address in the configuration, create a background task dedicated to
-        // listening on that address.
+        // For each listening address in the configuration, create the corresponding listening
+        // socket. The tasks dedicated to these listeners are spawned once the network service
+        // has been created.
+        let mut listeners = Vec::with_capacity(config.listen_addresses.len());
         for listen_address in config.listen_addresses {
             // Try to parse the requested address and create the corresponding listening socket.
-            let tcp_listener: async_std::net::TcpListener = {
+            let (tcp_listener, is_websocket) = {
                 let mut iter = listen_address.iter();
                 let proto1 = match iter.next() {
                     Some(p) => p,
@@ -145,6 +208,12 @@ impl NetworkService {
                     None => return Err(InitError::BadListenMultiaddr(listen_address)),
                 };
 
+                let is_websocket = match iter.next() {
+                    None => false,
+                    Some(Protocol::Ws(_)) => true,
+                    Some(_) => return Err(InitError::BadListenMultiaddr(listen_address)),
+                };
+
                 if iter.next().is_some() {
                     return Err(InitError::BadListenMultiaddr(listen_address));
                 }
@@ -156,40 +225,19 @@ impl NetworkService {
                 };
 
                 match async_std::net::TcpListener::bind(addr).await {
-                    Ok(l) => l,
+                    Ok(l) => (l, is_websocket),
                     Err(err) => {
                         return Err(InitError::ListenerIo(listen_address, err));
                     }
                 }
             };
 
-            // Spawn a background task dedicated to this listener.
-            (config.tasks_executor)(Box::pin(
-                async move {
-                    loop {
-                        // TODO: add a way to immediately interrupt the listener if the network service is destroyed (or fails to create altogether), in order to immediately liberate the port
-
-                        let (_socket, _addr) = match tcp_listener.accept().await {
-                            Ok(v) => v,
-                            Err(_) => {
-                                // Errors here can happen if the accept failed, for example if no file
-                                // descriptor is available.
-                                // A wait is added in order to avoid having a busy-loop failing to
-                                // accept connections.
-                                futures_timer::Delay::new(Duration::from_secs(2)).await;
-                                continue;
-                            }
-                        };
-
-                        todo!() // TODO: report new connection
-                    }
-                }
-                .instrument(
-                    tracing::debug_span!(parent: None, "listener", address = %listen_address),
-                ),
-            ))
+            listeners.push((tcp_listener, is_websocket, listen_address));
         }
 
+        let (dialers_wake_up, dialers_wake_up_rx): (Vec<_>, Vec<_>) =
+            (0..config.chains.len()).map(|_| mpsc::channel(0)).unzip();
+
         // TODO: code is messy
         let mut known_nodes =
             Vec::with_capacity(config.chains.iter().map(|c| c.bootstrap_nodes.len()).sum());
@@ -203,8 +251,8 @@ impl NetworkService {
 
             chains.push(service::ChainConfig {
                 bootstrap_nodes,
-                in_slots: 25,
-                out_slots: 25,
+                in_slots: chain.in_slots,
+                out_slots: chain.out_slots,
                 protocol_id: chain.protocol_id,
                 best_hash: chain.best_block.1,
                 best_number: chain.best_block.0,
@@ -227,6 +275,7 @@ impl NetworkService {
         let network_service = Arc::new(NetworkService {
             guarded: parking_lot::Mutex::new(Guarded {
                 tasks_executor: config.tasks_executor,
+                dialers_wake_up,
             }),
             network: service::ChainNetwork::new(service::Config {
                 chains,
@@ -237,7 +286,12 @@ impl NetworkService {
                 // once the issue is solved, this should be restored to a smaller value, such as 64
                 pending_api_events_buffer_size: NonZeroUsize::new(2048).unwrap(),
                 randomness_seed: rand::random(),
+                dial_backoff_base: config.dial_backoff_base,
+                dial_backoff_max: config.dial_backoff_max,
+                ban_threshold: config.ban_threshold,
+                ban_duration: config.ban_duration,
             }),
+            resolver: Arc::new(dns::Resolver::new()),
         });
 
         // Spawn a task pulling events from the network and transmitting them to the event senders.
@@ -263,6 +317,7 @@ impl NetworkService {
                                 chain_indices,
                             } => {
                                 tracing::debug!(%peer_id, "disconnected");
+                                network_service.wake_up_dialers();
                                 if !chain_indices.is_empty() {
                                     debug_assert_eq!(chain_indices.len(), 1); // TODO: not implemented
                                     break Event::Disconnected {
@@ -308,16 +363,91 @@ impl NetworkService {
                                 tracing::debug!(%peer_id, "identify-request");
                                 request.respond("smoldot").await;
                             }
+                            service::Event::GrandpaNeighborPacket {
+                                chain_index,
+                                peer_id,
+                                state,
+                            } => {
+                                tracing::debug!(
+                                    %chain_index, %peer_id,
+                                    set_id = %state.set_id,
+                                    round_number = %state.round_number,
+                                    commit_finalized_height = %state.commit_finalized_height,
+                                    "grandpa-neighbor-packet"
+                                );
+                            }
+                            service::Event::GrandpaVote {
+                                chain_index,
+                                peer_id,
+                                message,
+                            } => {
+                                tracing::debug!(
+                                    %chain_index, %peer_id,
+                                    round_number = %message.decode().round_number,
+                                    "grandpa-vote"
+                                );
+                            }
                             service::Event::GrandpaCommitMessage {
                                 chain_index,
+                                peer_id,
                                 message,
                             } => {
                                 tracing::debug!(
-                                    %chain_index,
+                                    %chain_index, %peer_id,
                                     target_hash = %HashDisplay(message.decode().message.target_hash),
                                     "grandpa-commit-message"
                                 );
                             }
+                            service::Event::GrandpaCatchUpRequest {
+                                chain_index,
+                                peer_id,
+                                request,
+                            } => {
+                                tracing::debug!(
+                                    %chain_index, %peer_id,
+                                    round_number = %request.round_number,
+                                    "grandpa-catch-up-request"
+                                );
+                            }
+                            service::Event::GrandpaCatchUp {
+                                chain_index,
+                                peer_id,
+                                message,
+                            } => {
+                                tracing::debug!(
+                                    %chain_index, %peer_id,
+                                    round_number = %message.decode().round_number,
+                                    "grandpa-catch-up"
+                                );
+                            }
+                            service::Event::Transactions {
+                                chain_index,
+                                peer_id,
+                                transactions,
+                            } => {
+                                tracing::debug!(%chain_index, %peer_id, ?transactions, "transactions");
+                                break Event::Transactions {
+                                    chain_index,
+                                    peer_id,
+                                    transactions,
+                                };
+                            }
+                            service::Event::PingOutSuccess { peer_id, ping_time } => {
+                                tracing::trace!(%peer_id, ?ping_time, "ping-success");
+                            }
+                            service::Event::PingOutFailed { peer_id } => {
+                                tracing::debug!(%peer_id, "ping-failed");
+                            }
+                            service::Event::ProtocolMisbehavior {
+                                chain_index,
+                                peer_id,
+                                reputation_change,
+                            } => {
+                                tracing::debug!(%chain_index, %peer_id, ?reputation_change, "protocol-misbehavior");
+                                network_service
+                                    .report_peer(peer_id, reputation_change)
+                                    .await;
+                            }
                         }
                     };
 
@@ -334,6 +464,58 @@ impl NetworkService {
             }
         }));
 
+        // Spawn a background task dedicated to each listener.
+        for (tcp_listener, is_websocket, listen_address) in listeners {
+            (network_service.guarded.try_lock().unwrap().tasks_executor)(Box::pin({
+                let network_service = Arc::downgrade(&network_service);
+                async move {
+                    loop {
+                        // TODO: add a way to immediately interrupt the listener if the network service is destroyed, in order to immediately liberate the port
+
+                        let (socket, addr) = match tcp_listener.accept().await {
+                            Ok(v) => v,
+                            Err(_) => {
+                                // Errors here can happen if the accept failed, for example if no file
+                                // descriptor is available.
+                                // A wait is added in order to avoid having a busy-loop failing to
+                                // accept connections.
+                                futures_timer::Delay::new(Duration::from_secs(2)).await;
+                                continue;
+                            }
+                        };
+
+                        let network_service = match network_service.upgrade() {
+                            Some(ns) => ns,
+                            None => {
+                                tracing::debug!("task-finish");
+                                return;
+                            }
+                        };
+
+                        // See the corresponding comment in `multiaddr_to_socket`.
+                        let _ = socket.set_nodelay(true);
+
+                        // Each connection is processed by a separate task, so that a slow remote
+                        // doesn't delay accepting other connections.
+                        let network_service2 = network_service.clone();
+                        (network_service.guarded.lock().tasks_executor)(Box::pin(
+                            incoming_connection_task(socket, is_websocket, network_service2)
+                                .instrument(tracing::trace_span!(
+                                    parent: None,
+                                    "connection",
+                                    address = %addr
+                                )),
+                        ));
+                    }
+                }
+                .instrument(tracing::debug_span!(
+                    parent: None,
+                    "listener",
+                    address = %listen_address
+                ))
+            }));
+        }
+
         // Spawn tasks dedicated to the Kademlia discovery.
         for chain_index in 0..network_service.network.num_chains() {
             (network_service.guarded.try_lock().unwrap().tasks_executor)(Box::pin({
@@ -362,7 +544,8 @@ impl NetworkService {
                                 insert
                                     .insert(|_| ())
                                     .instrument(tracing::debug_span!("insert"))
-                                    .await
+                                    .await;
+                                network_service.wake_up_dialers();
                             }
                             Err(error) => {
                                 tracing::debug!(%error, "discovery-error")
@@ -375,15 +558,15 @@ impl NetworkService {
         }
 
         // Spawn tasks dedicated to opening connections.
-        // TODO: spawn several, or do things asynchronously, so that we try open multiple connections simultaneously
-        for chain_index in 0..network_service.network.num_chains() {
+        for (chain_index, mut wake_up_rx) in dialers_wake_up_rx.into_iter().enumerate() {
             (network_service.guarded.try_lock().unwrap().tasks_executor)(Box::pin({
                 let network_service = Arc::downgrade(&network_service);
                 async move {
-                    loop {
-                        // TODO: very crappy way of not spamming the network service ; instead we should wake this task up when a disconnect or a discovery happens
-                        futures_timer::Delay::new(Duration::from_secs(1)).await;
+                    // Attempts at reaching a node that are in progress. Each future finishes when
+                    // the corresponding attempt has either succeeded or failed.
+                    let mut pending_dials = FuturesUnordered::new();
 
+                    loop {
                         let network_service = match network_service.upgrade() {
                             Some(ns) => ns,
                             None => {
@@ -392,33 +575,66 @@ impl NetworkService {
                             }
                         };
 
-                        let start_connect = match network_service.network.fill_out_slots(chain_index).await {
-                            Some(sc) => sc,
-                            None => continue,
-                        };
-
-                        let span = tracing::debug_span!("start-connect", ?start_connect.id, %start_connect.multiaddr);
-                        let _enter = span.enter();
-
-                        // Convert the `multiaddr` (typically of the form `/ip4/a.b.c.d/tcp/d`) into
-                        // a `Future<dyn Output = Result<TcpStream, ...>>`.
-                        let socket = match multiaddr_to_socket(&start_connect.multiaddr) {
-                            Ok(socket) => socket,
-                            Err(_) => {
-                                tracing::debug!(%start_connect.multiaddr, "not-tcp");
-                                network_service.network.pending_outcome_err(start_connect.id).await;
-                                continue;
-                            }
-                        };
+                        while pending_dials.len() < max_concurrent_dials {
+                            let start_connect = match network_service
+                                .network
+                                .fill_out_slots(chain_index, Instant::now())
+                                .await
+                            {
+                                Some(sc) => sc,
+                                None => break,
+                            };
+
+                            let span = tracing::debug_span!("start-connect", ?start_connect.id, %start_connect.multiaddr);
+                            let _enter = span.enter();
+
+                            // Convert the `multiaddr` (typically of the form `/ip4/a.b.c.d/tcp/d`
+                            // or `/ip4/a.b.c.d/tcp/d/ws`) into a
+                            // `Future<dyn Output = Result<Socket, ...>>`.
+                            let socket = match multiaddr_to_socket(
+                                &start_connect.multiaddr,
+                                network_service.resolver.clone(),
+                            ) {
+                                Ok(socket) => socket,
+                                Err(_) => {
+                                    tracing::debug!(%start_connect.multiaddr, "not-tcp-or-websocket");
+                                    network_service
+                                        .network
+                                        .pending_outcome_err(start_connect.id, Instant::now())
+                                        .await;
+                                    continue;
+                                }
+                            };
+
+                            let (dial_finished_tx, dial_finished_rx) = oneshot::channel();
+                            pending_dials.push(dial_finished_rx);
+
+                            let network_service2 = network_service.clone();
+                            (network_service.guarded.lock().tasks_executor)(Box::pin({
+                                connection_task(
+                                    socket,
+                                    start_connect.timeout,
+                                    dial_finished_tx,
+                                    network_service2,
+                                    start_connect.id,
+                                )
+                                .instrument(tracing::trace_span!(
+                                    parent: None,
+                                    "connection",
+                                    address = %start_connect.multiaddr
+                                ))
+                            }));
+                        }
 
-                        // TODO: handle dialing timeout here
+                        // Don't keep the network service alive while sleeping.
+                        drop(network_service);
 
-                        let network_service2 = network_service.clone();
-                        (network_service.guarded.lock().tasks_executor)(Box::pin({
-                            connection_task(socket, network_service2, start_connect.id).instrument(
-                                tracing::trace_span!(parent: None, "connection", address = %start_connect.multiaddr),
-                            )
-                        }));
+                        let mut retry = futures_timer::Delay::new(DIAL_RETRY_INTERVAL).fuse();
+                        futures::select! {
+                            _ = pending_dials.select_next_some() => {},
+                            _ = wake_up_rx.select_next_some() => {},
+                            () = retry => {},
+                        }
                     }
                 }
                 .instrument(tracing::debug_span!(parent: None, "tcp-dial"))
@@ -444,11 +660,33 @@ impl NetworkService {
         Ok((network_service, receivers))
     }
 
+    /// Wakes up the tasks dedicated to opening connections, so that they check whether new
+    /// nodes can be dialed.
+    fn wake_up_dialers(&self) {
+        for wake_up in &mut self.guarded.lock().dialers_wake_up {
+            // An error means that the channel is full, in which case the task is already going
+            // to wake up.
+            let _ = wake_up.try_send(());
+        }
+    }
+
     /// Returns the number of established TCP connections, both incoming and outgoing.
     pub async fn num_established_connections(&self) -> usize {
         self.network.num_established_connections().await
     }
 
+    /// Returns the number of bytes transferred on all the connections since the network service
+    /// has started.
+    pub async fn total_bandwidth(&self) -> smoldot::libp2p::Bandwidth {
+        self.network.total_bandwidth().await
+    }
+
+    /// Returns the number of bytes transferred on each established connection, in total and
+    /// for each protocol.
+    pub async fn connections_bandwidth(&self) -> Vec<service::ConnectionBandwidth> {
+        self.network.connections_bandwidth().await
+    }
+
     /// Sends a blocks request to the given peer.
     // TODO: more docs
     // TODO: proper error type
@@ -463,6 +701,93 @@ impl NetworkService {
             .blocks_request(Instant::now(), target, chain_index, config)
             .await
     }
+
+    /// Sends a state request to the given peer.
+    ///
+    /// See [`service::ChainNetwork::state_request`].
+    #[tracing::instrument(skip(self, config))]
+    pub async fn state_request(
+        self: Arc<Self>,
+        target: PeerId,
+        chain_index: usize,
+        config: protocol::StateRequestConfig<impl Iterator<Item = impl AsRef<[u8]>>>,
+    ) -> Result<Vec<protocol::StateResponseEntry>, service::StateRequestError> {
+        self.network
+            .state_request(Instant::now(), target, chain_index, config)
+            .await
+    }
+
+    /// Sends a storage proof request to the given peer.
+    #[tracing::instrument(skip(self, config))]
+    pub async fn storage_proof_request(
+        self: Arc<Self>,
+        target: PeerId,
+        chain_index: usize,
+        config: protocol::StorageProofRequestConfig<impl Iterator<Item = impl AsRef<[u8]>>>,
+    ) -> Result<Vec<Vec<u8>>, service::StorageProofRequestError> {
+        self.network
+            .storage_proof_request(Instant::now(), target, chain_index, config)
+            .await
+    }
+
+    /// Sends a storage proof request concerning a child trie to the given peer.
+    #[tracing::instrument(skip(self, config))]
+    pub async fn child_storage_proof_request(
+        self: Arc<Self>,
+        target: PeerId,
+        chain_index: usize,
+        config: protocol::ChildStorageProofRequestConfig<
+            impl AsRef<[u8]>,
+            impl Iterator<Item = impl AsRef<[u8]>>,
+        >,
+    ) -> Result<Vec<Vec<u8>>, service::StorageProofRequestError> {
+        self.network
+            .child_storage_proof_request(Instant::now(), target, chain_index, config)
+            .await
+    }
+
+    /// Modifies the reputation of the given peer. Connections to this peer are closed if its
+    /// reputation falls below a certain threshold.
+    #[tracing::instrument(skip(self))]
+    pub async fn report_peer(&self, peer_id: PeerId, reputation_change: service::ReputationChange) {
+        let banned = self
+            .network
+            .report_peer(Instant::now(), &peer_id, reputation_change)
+            .await;
+        if banned {
+            tracing::debug!(%peer_id, "banned");
+        }
+    }
+
+    /// Sends a block announce to all the peers we are connected to on the given chain.
+    ///
+    /// Peers with which no block announces substream is open are silently skipped.
+    #[tracing::instrument(skip(self, scale_encoded_header))]
+    pub async fn announce_block(
+        &self,
+        chain_index: usize,
+        scale_encoded_header: &[u8],
+        is_best: bool,
+    ) {
+        let header = match header::decode(scale_encoded_header) {
+            Ok(h) => h,
+            Err(error) => {
+                tracing::warn!(%error, "announce-invalid-header");
+                return;
+            }
+        };
+
+        // The list of peers is collected ahead of time in order to not hold a lock on the
+        // network state while sending.
+        let targets = self.network.peers_list().await.collect::<Vec<_>>();
+        for target in targets {
+            // Errors are ignored, as they only indicate that no substream is open.
+            let _ = self
+                .network
+                .send_block_announce(&target, chain_index, header.clone(), is_best)
+                .await;
+        }
+    }
 }
 
 /// Error when initializing the network service.
@@ -475,32 +800,104 @@ pub enum InitError {
     BadListenMultiaddr(Multiaddr),
 }
 
+/// Socket of a connection, either a plain TCP socket or a WebSocket connection on top of a TCP
+/// socket.
+type Socket = future::Either<async_std::net::TcpStream, websocket::Connection>;
+
 /// Asynchronous task managing a specific TCP connection.
-#[tracing::instrument(skip(tcp_socket, network_service))]
+///
+/// `dial_finished` is signalled once `tcp_socket` has finished, successfully or not, or once
+/// `timeout` has been reached.
+#[tracing::instrument(skip(tcp_socket, dial_finished, network_service))]
 async fn connection_task(
-    tcp_socket: impl Future<Output = Result<async_std::net::TcpStream, io::Error>>,
+    tcp_socket: impl Future<Output = Result<Socket, DialError>>,
+    timeout: Instant,
+    dial_finished: oneshot::Sender<()>,
     network_service: Arc<NetworkService>,
     id: service::PendingId,
 ) {
     // Finishing ongoing connection process.
-    let tcp_socket = match tcp_socket.await {
-        Ok(s) => s,
-        Err(_) => {
-            network_service.network.pending_outcome_err(id).await;
+    let tcp_socket = {
+        let tcp_socket = tcp_socket.fuse();
+        futures::pin_mut!(tcp_socket);
+        let mut timeout =
+            futures_timer::Delay::new(timeout.saturating_duration_since(Instant::now())).fuse();
+        let result = futures::select! {
+            result = tcp_socket => Some(result),
+            () = timeout => None,
+        };
+        let _ = dial_finished.send(());
+        result
+    };
+
+    let tcp_socket = match tcp_socket {
+        Some(Ok(s)) => s,
+        Some(Err(DialError::Resolution(error))) => {
+            tracing::debug!(%error, "resolution-failed");
+            network_service
+                .network
+                .pending_outcome_resolution_err(id, Instant::now())
+                .await;
+            return;
+        }
+        Some(Err(DialError::Io(error))) => {
+            tracing::debug!(%error, "dial-failed");
+            network_service
+                .network
+                .pending_outcome_err(id, Instant::now())
+                .await;
+            return;
+        }
+        None => {
+            tracing::debug!("dial-timeout");
+            network_service
+                .network
+                .pending_outcome_err(id, Instant::now())
+                .await;
             return;
         }
     };
 
     let id = network_service.network.pending_outcome_ok(id, ()).await;
+    established_connection_task(tcp_socket, network_service, id).await
+}
 
-    // The Nagle algorithm, implemented in the kernel, consists in buffering the data to be sent
-    // out and waiting a bit before actually sending it out, in order to potentially merge
-    // multiple writes in a row into one packet. In the implementation below, it is guaranteed
-    // that the buffer in `WithBuffers` is filled with as much data as possible before the
-    // operating system gets involved. As such, we disable the Nagle algorithm, in order to avoid
-    // adding an artificial delay to all sends.
-    let _ = tcp_socket.set_nodelay(true);
+/// Asynchronous task managing a specific incoming TCP connection.
+///
+/// If `is_websocket` is `true`, the WebSocket handshake is performed before the connection is
+/// reported to the network service.
+#[tracing::instrument(skip(tcp_socket, network_service))]
+async fn incoming_connection_task(
+    tcp_socket: async_std::net::TcpStream,
+    is_websocket: bool,
+    network_service: Arc<NetworkService>,
+) {
+    let socket = if is_websocket {
+        match websocket::server_handshake(tcp_socket).await {
+            Ok(s) => future::Either::Right(s),
+            Err(error) => {
+                tracing::debug!(%error, "websocket-handshake-failed");
+                return;
+            }
+        }
+    } else {
+        future::Either::Left(tcp_socket)
+    };
+
+    let id = network_service
+        .network
+        .add_incoming_connection(Instant::now(), ())
+        .await;
+    established_connection_task(socket, network_service, id).await
+}
 
+/// Drives the given socket, whose connection has been reported to the network service under the
+/// given identifier, until the connection is closed.
+async fn established_connection_task(
+    tcp_socket: Socket,
+    network_service: Arc<NetworkService>,
+    id: service::ConnectionId,
+) {
     // The socket is wrapped around a `WithBuffers` object containing a read buffer and a write
     // buffer. These are the buffers whose pointer is passed to `read(2)` and `write(2)` when
     // reading/writing the socket.
@@ -593,44 +990,86 @@ async fn connection_task(
 /// protocols aren't supported.
 fn multiaddr_to_socket(
     addr: &Multiaddr,
-) -> Result<impl Future<Output = Result<async_std::net::TcpStream, io::Error>>, ()> {
+    resolver: Arc<dns::Resolver>,
+) -> Result<impl Future<Output = Result<Socket, DialError>>, ()> {
     let mut iter = addr.iter();
     let proto1 = iter.next().ok_or(())?;
     let proto2 = iter.next().ok_or(())?;
+    let proto3 = iter.next();
 
     if iter.next().is_some() {
         return Err(());
     }
 
     // Ensure ahead of time that the multiaddress is supported.
-    match (&proto1, &proto2) {
-        (Protocol::Ip4(_), Protocol::Tcp(_))
-        | (Protocol::Ip6(_), Protocol::Tcp(_))
-        | (Protocol::Dns(_), Protocol::Tcp(_))
-        | (Protocol::Dns4(_), Protocol::Tcp(_))
-        | (Protocol::Dns6(_), Protocol::Tcp(_)) => {}
+    match (&proto1, &proto2, &proto3) {
+        (Protocol::Ip4(_), Protocol::Tcp(_), None | Some(Protocol::Ws(_)))
+        | (Protocol::Ip6(_), Protocol::Tcp(_), None | Some(Protocol::Ws(_)))
+        | (Protocol::Dns(_), Protocol::Tcp(_), None | Some(Protocol::Ws(_)))
+        | (Protocol::Dns4(_), Protocol::Tcp(_), None | Some(Protocol::Ws(_)))
+        | (Protocol::Dns6(_), Protocol::Tcp(_), None | Some(Protocol::Ws(_))) => {}
         _ => return Err(()),
     }
 
+    let dns_family = match proto1 {
+        Protocol::Dns(_) => dns::AddressFamily::Any,
+        Protocol::Dns4(_) => dns::AddressFamily::Ipv4,
+        Protocol::Dns6(_) => dns::AddressFamily::Ipv6,
+        _ => dns::AddressFamily::Any, // Unused.
+    };
+
     let proto1 = proto1.acquire();
     let proto2 = proto2.acquire();
+    let websocket_path = match proto3 {
+        Some(Protocol::Ws(path)) => Some(path.into_owned()),
+        _ => None,
+    };
 
     Ok(async move {
-        match (proto1, proto2) {
-            (Protocol::Ip4(ip), Protocol::Tcp(port)) => {
-                async_std::net::TcpStream::connect(SocketAddr::new(ip.into(), port)).await
-            }
-            (Protocol::Ip6(ip), Protocol::Tcp(port)) => {
-                async_std::net::TcpStream::connect(SocketAddr::new(ip.into(), port)).await
-            }
-            // TODO: for DNS, do things a bit more explicitly? with for example a library that does the resolution?
-            // TODO: differences between DNS, DNS4, DNS6 not respected
+        let (tcp_socket, host) = match (proto1, proto2) {
+            (Protocol::Ip4(ip), Protocol::Tcp(port)) => (
+                async_std::net::TcpStream::connect(SocketAddr::new(ip.into(), port)).await?,
+                format!("{}:{}", ip, port),
+            ),
+            (Protocol::Ip6(ip), Protocol::Tcp(port)) => (
+                async_std::net::TcpStream::connect(SocketAddr::new(ip.into(), port)).await?,
+                format!("[{}]:{}", ip, port),
+            ),
             (Protocol::Dns(addr), Protocol::Tcp(port))
             | (Protocol::Dns4(addr), Protocol::Tcp(port))
             | (Protocol::Dns6(addr), Protocol::Tcp(port)) => {
-                async_std::net::TcpStream::connect((&*addr, port)).await
+                let addresses = resolver
+                    .resolve(&addr, port, dns_family)
+                    .await
+                    .map_err(DialError::Resolution)?;
+                (dns::connect(addresses).await?, format!("{}:{}", addr, port))
             }
             _ => unreachable!(),
+        };
+
+        // The Nagle algorithm, implemented in the kernel, consists in buffering the data to be
+        // sent out and waiting a bit before actually sending it out, in order to potentially
+        // merge multiple writes in a row into one packet. In the implementation of the
+        // connection task, it is guaranteed that the buffer in `WithBuffers` is filled with as
+        // much data as possible before the operating system gets involved. As such, we disable
+        // the Nagle algorithm, in order to avoid adding an artificial delay to all sends.
+        let _ = tcp_socket.set_nodelay(true);
+
+        if let Some(websocket_path) = websocket_path {
+            let connection =
+                websocket::client_handshake(tcp_socket, &host, &websocket_path).await?;
+            Ok(future::Either::Right(connection))
+        } else {
+            Ok(future::Either::Left(tcp_socket))
         }
     })
 }
+
+/// Error while dialing a multiaddress.
+#[derive(Debug, derive_more::Display, derive_more::From)]
+enum DialError {
+    /// Failed to resolve the host name of the multiaddress.
+    Resolution(dns::ResolveError),
+    /// Failed to connect to the target, or to perform the WebSocket handshake.
+    Io(io::Error),
+}
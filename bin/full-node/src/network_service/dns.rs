@@ -0,0 +1,199 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Resolution of host names found in `/dns`, `/dns4` and `/dns6` multiaddresses, and
+//! establishment of TCP connections towards the resolved addresses.
+//!
+//! The resolution itself is delegated to the operating system. Since the operating system
+//! doesn't report the TTL of the DNS records, results are cached for a fixed duration. Failed
+//! resolutions are cached as well, for a shorter duration, in order to not hammer the resolver
+//! when dialing the same unreachable host name repeatedly.
+//!
+//! Use [`connect`] in order to try all the resolved addresses one after the other, giving each
+//! attempt a head start before starting the next one in parallel, in the spirit of the "Happy
+//! Eyeballs" algorithm (RFC 8305).
+
+use core::time::Duration;
+use futures::{prelude::*, stream::FuturesUnordered};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    time::Instant,
+};
+
+/// Duration during which a successful resolution is cached.
+const POSITIVE_TTL: Duration = Duration::from_secs(60);
+
+/// Duration during which a failed resolution is cached.
+const NEGATIVE_TTL: Duration = Duration::from_secs(10);
+
+/// Delay after which the next address is tried if the previous connection attempts haven't
+/// finished yet.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Address family to restrict a resolution to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// Corresponds to `/dns`. Both IPv4 and IPv6 addresses are acceptable.
+    Any,
+    /// Corresponds to `/dns4`. Only IPv4 addresses are acceptable.
+    Ipv4,
+    /// Corresponds to `/dns6`. Only IPv6 addresses are acceptable.
+    Ipv6,
+}
+
+/// Host names resolver with a cache.
+pub struct Resolver {
+    /// Results of the previous resolutions, indexed by host name.
+    cache: parking_lot::Mutex<hashbrown::HashMap<String, CacheEntry, fnv::FnvBuildHasher>>,
+}
+
+struct CacheEntry {
+    /// When the entry must be discarded.
+    expiration: Instant,
+    /// Result of the resolution. `None` if the resolution has failed.
+    addresses: Option<Vec<IpAddr>>,
+}
+
+impl Resolver {
+    /// Initializes a new resolver with an empty cache.
+    pub fn new() -> Self {
+        Resolver {
+            cache: parking_lot::Mutex::new(Default::default()),
+        }
+    }
+
+    /// Resolves the given host name into a list of socket addresses of the given family.
+    ///
+    /// The returned list is never empty, and is ordered in the order in which connection
+    /// attempts should be made. When both IPv4 and IPv6 addresses are acceptable, the two
+    /// families are interleaved, starting with IPv6.
+    pub async fn resolve(
+        &self,
+        host_name: &str,
+        port: u16,
+        family: AddressFamily,
+    ) -> Result<Vec<SocketAddr>, ResolveError> {
+        let cached = {
+            let mut cache = self.cache.lock();
+            let now = Instant::now();
+            cache.retain(|_, entry| entry.expiration > now);
+            cache.get(host_name).map(|entry| entry.addresses.clone())
+        };
+
+        let addresses = match cached {
+            Some(addresses) => addresses,
+            None => {
+                let addresses = async_std::net::ToSocketAddrs::to_socket_addrs(&(host_name, 0))
+                    .await
+                    .ok()
+                    .map(|addrs| addrs.map(|addr| addr.ip()).collect::<Vec<_>>())
+                    .filter(|addrs| !addrs.is_empty());
+
+                let ttl = if addresses.is_some() {
+                    POSITIVE_TTL
+                } else {
+                    NEGATIVE_TTL
+                };
+
+                self.cache.lock().insert(
+                    host_name.to_owned(),
+                    CacheEntry {
+                        expiration: Instant::now() + ttl,
+                        addresses: addresses.clone(),
+                    },
+                );
+
+                addresses
+            }
+        };
+
+        let addresses = addresses.ok_or(ResolveError::NotFound)?;
+
+        let mut ipv4 = addresses.iter().filter(|a| a.is_ipv4()).copied().fuse();
+        let mut ipv6 = addresses.iter().filter(|a| a.is_ipv6()).copied().fuse();
+
+        let mut out = Vec::with_capacity(addresses.len());
+        match family {
+            AddressFamily::Any => loop {
+                let (v6, v4) = (ipv6.next(), ipv4.next());
+                if v6.is_none() && v4.is_none() {
+                    break;
+                }
+                out.extend(v6.into_iter().chain(v4));
+            },
+            AddressFamily::Ipv4 => out.extend(ipv4),
+            AddressFamily::Ipv6 => out.extend(ipv6),
+        }
+
+        if out.is_empty() {
+            return Err(ResolveError::NoAddressOfFamily);
+        }
+
+        Ok(out
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+}
+
+/// Error potentially returned by [`Resolver::resolve`].
+#[derive(Debug, derive_more::Display)]
+pub enum ResolveError {
+    /// The host name couldn't be resolved.
+    #[display(fmt = "Failed to resolve host name")]
+    NotFound,
+    /// The host name has been resolved, but no address of the requested family was found.
+    #[display(fmt = "No address of the requested family")]
+    NoAddressOfFamily,
+}
+
+/// Connects to the given list of addresses, in order.
+///
+/// Each connection attempt is given [`CONNECTION_ATTEMPT_DELAY`] to succeed before the next
+/// attempt starts in parallel. A failed attempt immediately starts the next one. The first
+/// successful connection is returned, and the others are dropped.
+///
+/// # Panic
+///
+/// Panics if `addresses` is empty.
+///
+pub async fn connect(addresses: Vec<SocketAddr>) -> Result<async_std::net::TcpStream, io::Error> {
+    assert!(!addresses.is_empty());
+
+    let mut addresses = addresses.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    loop {
+        match addresses.next() {
+            Some(address) => attempts.push(async_std::net::TcpStream::connect(address)),
+            None if attempts.is_empty() => return Err(last_error.unwrap()),
+            None => {}
+        }
+
+        // Wait until either an attempt finishes or the delay to start the next attempt elapses.
+        let mut next_attempt = futures_timer::Delay::new(CONNECTION_ATTEMPT_DELAY).fuse();
+        futures::select! {
+            result = attempts.select_next_some() => match result {
+                Ok(socket) => return Ok(socket),
+                Err(err) => last_error = Some(err),
+            },
+            () = next_attempt => {},
+        }
+    }
+}
@@ -0,0 +1,220 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! WebSocket framing layer on top of an implementation of `AsyncRead` and `AsyncWrite`.
+//!
+//! The [`Connection`] produced by [`client_handshake`] or [`server_handshake`] implements
+//! `AsyncRead` and `AsyncWrite` as well. Each write is sent out as a binary WebSocket frame, and
+//! the content of the binary frames received from the remote is concatenated and can be read.
+//! This corresponds to how libp2p uses WebSocket for `/ws` multiaddresses.
+//!
+//! While this module is generic, the targeted use-case is TCP connections.
+
+use core::{fmt, pin::Pin, task::Poll};
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    prelude::*,
+    ready,
+};
+use std::io;
+
+/// Performs the client side of the WebSocket handshake on the given socket.
+///
+/// `host` is the value of the `Host` HTTP header, and `path` the requested HTTP resource.
+pub async fn client_handshake<T>(socket: T, host: &str, path: &str) -> Result<Connection, io::Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut client = soketto::handshake::Client::new(socket, host, path);
+
+    match client.handshake().await.map_err(to_io_error)? {
+        soketto::handshake::ServerResponse::Accepted { .. } => {}
+        soketto::handshake::ServerResponse::Redirect { status_code, .. }
+        | soketto::handshake::ServerResponse::Rejected { status_code } => {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "WebSocket handshake refused with status code {}",
+                    status_code
+                ),
+            ))
+        }
+    }
+
+    let (sender, receiver) = client.into_builder().finish();
+    Ok(Connection::from_sender_receiver(sender, receiver))
+}
+
+/// Performs the server side of the WebSocket handshake on the given socket. All requests are
+/// accepted, no matter the HTTP resource being requested.
+pub async fn server_handshake<T>(socket: T) -> Result<Connection, io::Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut server = soketto::handshake::Server::new(socket);
+
+    let websocket_key = server
+        .receive_request()
+        .await
+        .map_err(to_io_error)?
+        .into_key();
+
+    server
+        .send_response(&soketto::handshake::server::Response::Accept {
+            key: &websocket_key,
+            protocol: None,
+        })
+        .await
+        .map_err(to_io_error)?;
+
+    let (sender, receiver) = server.into_builder().finish();
+    Ok(Connection::from_sender_receiver(sender, receiver))
+}
+
+/// WebSocket connection whose handshake has been performed.
+pub struct Connection {
+    /// Stream of the binary messages received from the remote.
+    receiver: Pin<Box<dyn Stream<Item = Result<Vec<u8>, io::Error>> + Send>>,
+    /// Last message received from the remote.
+    read_buffer: Vec<u8>,
+    /// Offset within [`Connection::read_buffer`] of the next byte to return to the reader.
+    read_buffer_offset: usize,
+    /// Sink of the messages to send to the remote. `None` closes the connection.
+    sender: Pin<Box<dyn Sink<Option<Vec<u8>>, Error = io::Error> + Send>>,
+    /// True if a `None` has been sent to [`Connection::sender`].
+    close_queued: bool,
+}
+
+impl Connection {
+    fn from_sender_receiver<T>(sender: soketto::Sender<T>, receiver: soketto::Receiver<T>) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let receiver = stream::unfold(receiver, |mut receiver| async move {
+            let mut message = Vec::new();
+            match receiver.receive_data(&mut message).await {
+                Ok(soketto::Data::Binary(_)) => Some((Ok(message), receiver)),
+                Ok(soketto::Data::Text(_)) => Some((
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected WebSocket text frame",
+                    )),
+                    receiver,
+                )),
+                Err(soketto::connection::Error::Closed) => None,
+                Err(err) => Some((Err(to_io_error(err)), receiver)),
+            }
+        });
+
+        let sender = sink::unfold(sender, |mut sender, message: Option<Vec<u8>>| async move {
+            match message {
+                Some(mut message) => {
+                    sender
+                        .send_binary_mut(&mut message)
+                        .await
+                        .map_err(to_io_error)?;
+                    sender.flush().await.map_err(to_io_error)?;
+                }
+                None => sender.close().await.map_err(to_io_error)?,
+            }
+            Ok::<_, io::Error>(sender)
+        });
+
+        Connection {
+            receiver: Box::pin(receiver),
+            read_buffer: Vec::new(),
+            read_buffer_offset: 0,
+            sender: Box::pin(sender),
+            close_queued: false,
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        loop {
+            let this = &mut *self;
+
+            if this.read_buffer_offset < this.read_buffer.len() {
+                let available = &this.read_buffer[this.read_buffer_offset..];
+                let num_copied = core::cmp::min(available.len(), buf.len());
+                buf[..num_copied].copy_from_slice(&available[..num_copied]);
+                this.read_buffer_offset += num_copied;
+                return Poll::Ready(Ok(num_copied));
+            }
+
+            match ready!(this.receiver.as_mut().poll_next(cx)) {
+                Some(Ok(message)) => {
+                    this.read_buffer = message;
+                    this.read_buffer_offset = 0;
+                }
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        ready!(self.sender.as_mut().poll_ready(cx))?;
+        self.sender.as_mut().start_send(Some(buf.to_vec()))?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        self.sender.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        if !self.close_queued {
+            ready!(self.sender.as_mut().poll_ready(cx))?;
+            self.sender.as_mut().start_send(None)?;
+            self.close_queued = true;
+        }
+
+        self.sender.as_mut().poll_close(cx)
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Connection").finish()
+    }
+}
+
+fn to_io_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
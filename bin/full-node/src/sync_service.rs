@@ -20,15 +20,29 @@
 //! The [`SyncService`] manages a background task dedicated to synchronizing the chain with the
 //! network.
 //! Importantly, its design is oriented towards the particular use case of the full node.
+//!
+//! If the chain is a parachain, its finalized and best blocks are determined by calling the
+//! `ParachainHost_persisted_validation_data` runtime function on respectively the finalized and
+//! best blocks of the relay chain. See [`ConfigParachain`].
 
 // TODO: doc
 // TODO: re-review this once finished
 
 use crate::network_service;
 
-use core::{num::NonZeroU32, pin::Pin};
-use futures::{channel::mpsc, lock::Mutex, prelude::*};
-use smoldot::{database::full_sqlite, executor, header, libp2p, network, sync::optimistic};
+use core::{iter, num::NonZeroU32, ops::Bound, pin::Pin, time::Duration};
+use futures::{
+    channel::{mpsc, oneshot},
+    lock::Mutex,
+    prelude::*,
+};
+use smoldot::{
+    chain::blocks_tree,
+    database::full_sqlite,
+    executor::{self, read_only_runtime_host},
+    header, libp2p, network,
+    sync::{optimistic, para, state},
+};
 use std::{collections::BTreeMap, sync::Arc, time::SystemTime};
 use tracing::Instrument as _;
 
@@ -47,6 +61,40 @@ pub struct Config {
     /// Receiver for events coming from the network, as returned by
     /// [`network_service::NetworkService::new`].
     pub network_events_receiver: mpsc::Receiver<network_service::Event>,
+
+    /// List of hashes of blocks that must never be part of the chain. Typically found in the
+    /// chain specification.
+    pub bad_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes that must be part of the chain. Typically found in the
+    /// chain specification.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
+    /// Extra fields used when the chain is a parachain.
+    /// If `None`, the chain is a standalone chain or a relay chain.
+    pub parachain: Option<ConfigParachain>,
+}
+
+/// See [`Config::parachain`].
+pub struct ConfigParachain {
+    /// Database of the relay chain of this parachain, kept up-to-date by a different
+    /// [`SyncService`].
+    ///
+    /// The finalized block of the parachain is found by looking at the storage of the finalized
+    /// block of the relay chain.
+    pub relay_chain_database: Arc<full_sqlite::SqliteFullDatabase>,
+
+    /// Sync service of the relay chain of this parachain.
+    ///
+    /// The best block of the parachain is found by looking at the storage of the best block of
+    /// the relay chain, which the database doesn't contain.
+    pub relay_chain_sync_service: Arc<SyncService>,
+
+    /// Id of the parachain within the relay chain.
+    ///
+    /// This is an arbitrary number used to identify the parachain within the storage of the
+    /// relay chain.
+    pub parachain_id: u32,
 }
 
 /// Identifier for a blocks request to be performed.
@@ -62,10 +110,30 @@ pub struct SyncState {
     pub finalized_block_hash: [u8; 32],
 }
 
+/// Storage of a block, in the form of the changes it performs on the storage of the finalized
+/// block.
+#[derive(Debug, Clone)]
+pub struct BlockStorage {
+    /// Hash of the finalized block whose storage [`BlockStorage::storage_diff`] applies to.
+    ///
+    /// > **Note**: The finalized block of the database might lag behind this block.
+    pub finalized_block_hash: [u8; 32],
+
+    /// SCALE-encoded header of the block.
+    pub scale_encoded_header: Vec<u8>,
+
+    /// Changes that the block and its non-finalized ancestors perform on the storage of the
+    /// finalized block. Values are `None` if the key has been erased.
+    pub storage_diff: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
 /// Background task that verifies blocks and emits requests.
 pub struct SyncService {
     /// State kept up-to-date with the background task.
     sync_state: Arc<Mutex<SyncState>>,
+
+    /// Sender of messages towards the background task.
+    to_background: mpsc::Sender<ToBackground>,
 }
 
 impl SyncService {
@@ -73,6 +141,7 @@ impl SyncService {
     #[tracing::instrument(skip(config))]
     pub async fn new(mut config: Config) -> Arc<Self> {
         let (to_database, messages_rx) = mpsc::channel(4);
+        let (to_background, from_foreground) = mpsc::channel(4);
 
         let finalized_block_hash = config.database.finalized_block_hash().unwrap();
         let best_block_hash = config.database.best_block_hash().unwrap();
@@ -100,22 +169,122 @@ impl SyncService {
             .number,
         }));
 
-        (config.tasks_executor)(Box::pin(start_sync(
-            config.database.clone(),
-            sync_state.clone(),
-            config.network_service.0,
-            config.network_service.1,
-            config.network_events_receiver,
-            to_database,
-        )));
+        // Holds, in parallel of the database, the storage of the latest finalized block.
+        // At the time of writing, this state is stable around ~3MiB for Polkadot, meaning that it
+        // is completely acceptable to hold it entirely in memory.
+        // While reading the storage from the database is an option, doing so considerably slows
+        // down the verification, and also makes it impossible to insert blocks in the database
+        // in parallel of this verification.
+        let finalized_block_storage: BTreeMap<Vec<u8>, Vec<u8>> = config
+            .database
+            .finalized_block_storage_top_trie(&finalized_block_hash)
+            .unwrap();
 
         (config.tasks_executor)(Box::pin(
-            start_database_write(config.database, messages_rx).instrument(
+            start_database_write(config.database.clone(), messages_rx).instrument(
                 tracing::debug_span!(parent: None, "database-write", root = ?finalized_block_hash), // TDOO: better display
             ),
         ));
 
-        Arc::new(SyncService { sync_state })
+        let database = config.database;
+        let network_service = config.network_service;
+        let mut network_events_receiver = config.network_events_receiver;
+        let bad_blocks = config.bad_blocks;
+        let fork_blocks = config.fork_blocks;
+        let parachain = config.parachain;
+        let sync_state_clone = sync_state.clone();
+
+        (config.tasks_executor)(Box::pin(async move {
+            // The database doesn't contain the storage of the finalized block if it has been
+            // initialized from a checkpoint. This storage is downloaded from the network before
+            // syncing can start.
+            let (finalized_block_storage, connected_peers) =
+                if finalized_block_storage.contains_key(&b":code"[..]) {
+                    (finalized_block_storage, Vec::new())
+                } else {
+                    match download_finalized_storage(
+                        &database,
+                        (&network_service.0, network_service.1),
+                        &mut network_events_receiver,
+                    )
+                    .await
+                    {
+                        Some(v) => v,
+                        None => return,
+                    }
+                };
+
+            // Peers that have connected while the storage was being downloaded are reported
+            // again to the syncing.
+            let network_chain_index = network_service.1;
+            let network_events = stream::iter(connected_peers.into_iter().map(
+                move |(peer_id, best_block_number)| network_service::Event::Connected {
+                    chain_index: network_chain_index,
+                    peer_id,
+                    best_block_number,
+                },
+            ))
+            .chain(network_events_receiver);
+
+            let sync =
+                optimistic::OptimisticSync::<_, libp2p::PeerId, ()>::new(optimistic::Config {
+                    chain_information: database
+                        .to_chain_information(&finalized_block_hash)
+                        .unwrap(),
+                    sources_capacity: 32,
+                    blocks_capacity: {
+                        // This is the maximum number of blocks between two consecutive
+                        // justifications.
+                        1024
+                    },
+                    source_selection_randomness_seed: rand::random(),
+                    blocks_request_granularity: NonZeroU32::new(128).unwrap(),
+                    download_ahead_blocks: {
+                        // Assuming a verification speed of 1k blocks/sec and a 95% latency of
+                        // one second, the number of blocks to download ahead of time in order
+                        // to not block is 1000.
+                        1024
+                    },
+                    bad_blocks,
+                    fork_blocks,
+                    full: Some(optimistic::ConfigFull {
+                        finalized_runtime: {
+                            // Builds the runtime of the finalized block.
+                            // Assumed to always be valid, otherwise the block wouldn't have
+                            // been saved in the database, hence the large number of unwraps here.
+                            let module = finalized_block_storage.get(&b":code"[..]).unwrap();
+                            let heap_pages = executor::storage_heap_pages_to_value(
+                                finalized_block_storage
+                                    .get(&b":heappages"[..])
+                                    .map(|v| &v[..]),
+                            )
+                            .unwrap();
+                            executor::host::HostVmPrototype::new(
+                                module,
+                                heap_pages,
+                                executor::vm::ExecHint::CompileAheadOfTime, // TODO: probably should be decided by the optimisticsync
+                            )
+                            .unwrap()
+                        },
+                    }),
+                });
+
+            start_sync(
+                (sync_state_clone, from_foreground),
+                sync,
+                finalized_block_storage,
+                network_service,
+                network_events,
+                to_database,
+                parachain,
+            )
+            .await
+        }));
+
+        Arc::new(SyncService {
+            sync_state,
+            to_background,
+        })
     }
 
     /// Returns a summary of the state of the service.
@@ -126,6 +295,28 @@ impl SyncService {
     pub async fn sync_state(&self) -> SyncState {
         self.sync_state.lock().await.clone()
     }
+
+    /// Returns the storage of the current best block.
+    ///
+    /// If the storage of the finalized block is being downloaded, the answer is delayed until
+    /// this download is over.
+    #[tracing::instrument(skip(self))]
+    pub async fn best_block_storage(&self) -> BlockStorage {
+        let (send_back, rx) = oneshot::channel();
+        self.to_background
+            .clone()
+            .send(ToBackground::BestBlockStorage { send_back })
+            .await
+            .unwrap();
+        rx.await.unwrap()
+    }
+}
+
+enum ToBackground {
+    /// See [`SyncService::best_block_storage`].
+    BestBlockStorage {
+        send_back: oneshot::Sender<BlockStorage>,
+    },
 }
 
 enum ToDatabase {
@@ -133,73 +324,81 @@ enum ToDatabase {
 }
 
 /// Returns the background task of the sync service.
+///
+/// `finalized_block_storage` must contain the storage of the finalized block of `sync`.
+///
+/// `sync_state` and `from_foreground` are the state shared with, and the messages sent by, the
+/// [`SyncService`].
 #[tracing::instrument(skip(
-    database,
     sync_state,
+    sync,
+    finalized_block_storage,
     network_service,
     from_network_service,
-    to_database
+    from_foreground,
+    to_database,
+    parachain
 ))]
 fn start_sync(
-    database: Arc<full_sqlite::SqliteFullDatabase>,
-    sync_state: Arc<Mutex<SyncState>>,
-    network_service: Arc<network_service::NetworkService>,
-    network_chain_index: usize,
-    mut from_network_service: mpsc::Receiver<network_service::Event>,
+    (sync_state, mut from_foreground): (Arc<Mutex<SyncState>>, mpsc::Receiver<ToBackground>),
+    mut sync: optimistic::OptimisticSync<future::AbortHandle, libp2p::PeerId, ()>,
+    mut finalized_block_storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    (network_service, network_chain_index): (Arc<network_service::NetworkService>, usize),
+    mut from_network_service: impl stream::FusedStream<Item = network_service::Event> + Unpin,
     mut to_database: mpsc::Sender<ToDatabase>,
+    parachain: Option<ConfigParachain>,
 ) -> impl Future<Output = ()> {
-    // Holds, in parallel of the database, the storage of the latest finalized block.
-    // At the time of writing, this state is stable around ~3MiB for Polkadot, meaning that it is
-    // completely acceptable to hold it entirely in memory.
-    // While reading the storage from the database is an option, doing so considerably slows down
-    // the verification, and also makes it impossible to insert blocks in the database in
-    // parallel of this verification.
-    let mut finalized_block_storage: BTreeMap<Vec<u8>, Vec<u8>> = database
-        .finalized_block_storage_top_trie(&database.finalized_block_hash().unwrap())
-        .unwrap();
-
-    let mut sync = optimistic::OptimisticSync::<_, libp2p::PeerId, ()>::new(optimistic::Config {
-        chain_information: database
-            .to_chain_information(&database.finalized_block_hash().unwrap())
-            .unwrap(),
-        sources_capacity: 32,
-        blocks_capacity: {
-            // This is the maximum number of blocks between two consecutive justifications.
-            1024
-        },
-        source_selection_randomness_seed: rand::random(),
-        blocks_request_granularity: NonZeroU32::new(128).unwrap(),
-        download_ahead_blocks: {
-            // Assuming a verification speed of 1k blocks/sec and a 95% latency of one second,
-            // the number of blocks to download ahead of time in order to not block is 1000.
-            1024
-        },
-        full: Some(optimistic::ConfigFull {
-            finalized_runtime: {
-                // Builds the runtime of the finalized block.
-                // Assumed to always be valid, otherwise the block wouldn't have been saved in the
-                // database, hence the large number of unwraps here.
-                let module = finalized_block_storage.get(&b":code"[..]).unwrap();
-                let heap_pages = executor::storage_heap_pages_to_value(
-                    finalized_block_storage
-                        .get(&b":heappages"[..])
-                        .map(|v| &v[..]),
-                )
-                .unwrap();
-                executor::host::HostVmPrototype::new(
-                    module,
-                    heap_pages,
-                    executor::vm::ExecHint::CompileAheadOfTime, // TODO: probably should be decided by the optimisticsync
-                )
-                .unwrap()
-            },
-        }),
-    });
-
     async move {
-        let mut peers_source_id_map = hashbrown::HashMap::<_, _, fnv::FnvBuildHasher>::default();
+        let mut peers_source_id_map = hashbrown::HashMap::<
+            libp2p::PeerId,
+            optimistic::SourceId,
+            fnv::FnvBuildHasher,
+        >::default();
         let mut block_requests_finished = stream::FuturesUnordered::new();
 
+        // If the chain is a parachain, the finalized and best blocks of the relay chain are
+        // regularly checked in order to determine the finalized and best blocks of the parachain.
+        // The stream yields the storage of the best block of the relay chain.
+        let mut relay_chain_poll = if let Some(parachain) = &parachain {
+            stream::unfold(
+                parachain.relay_chain_sync_service.clone(),
+                |relay_chain_sync_service| async move {
+                    futures_timer::Delay::new(Duration::from_secs(2)).await;
+                    let best_block_storage = relay_chain_sync_service.best_block_storage().await;
+                    Some((best_block_storage, relay_chain_sync_service))
+                },
+            )
+            .boxed()
+            .fuse()
+        } else {
+            stream::pending().boxed().fuse()
+        };
+
+        // Hash of the latest finalized block of the relay chain whose storage has been looked
+        // at.
+        let mut relay_chain_finalized_checked = None::<[u8; 32]>;
+
+        // Hash of the latest best block of the relay chain whose storage has been looked at.
+        let mut relay_chain_best_checked = None::<[u8; 32]>;
+
+        // `true` if the head of the parachain couldn't be found in the storage of the latest
+        // finalized block of the relay chain that has been looked at. Used in order to not
+        // repeatedly print the same warning.
+        let mut parachain_head_missing = false;
+
+        // Compiled runtime of the latest block of the relay chain that has been looked at, if
+        // any.
+        let mut relay_chain_runtime = None::<RelayChainRuntime>;
+
+        // Hash of the parachain block that the relay chain considers as finalized, if it hasn't
+        // been finalized in `sync` yet.
+        let mut parachain_finalized_target = None::<[u8; 32]>;
+
+        // Hash and number of the parachain block included in the best block of the relay chain,
+        // if it hasn't been verified by `sync` yet. Once verified, it becomes the best block of
+        // the parachain.
+        let mut parachain_best_target = None::<([u8; 32], u64)>;
+
         loop {
             let unix_time = SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
@@ -216,54 +415,79 @@ fn start_sync(
                     optimistic::ProcessOne::Reset {
                         sync: s,
                         previous_best_height,
+                        source_id,
                         reason,
                     } => {
                         tracing::warn!(%reason, %previous_best_height, "failed-block-verification");
+
+                        let reputation_change = match &reason {
+                            optimistic::ResetCause::JustificationError(_) => {
+                                Some(network::service::ReputationChange::BadJustification)
+                            }
+                            optimistic::ResetCause::InvalidHeader(_)
+                            | optimistic::ResetCause::HeaderError(_)
+                            | optimistic::ResetCause::HeaderBodyError(_) => {
+                                Some(network::service::ReputationChange::BadBlock)
+                            }
+                            optimistic::ResetCause::NonCanonical
+                            | optimistic::ResetCause::UnexpectedBlockNumber { .. } => None,
+                        };
+
+                        // The source might have been removed in the meanwhile, in which case
+                        // it isn't reported.
+                        if let Some(reputation_change) = reputation_change {
+                            if let Some(peer_id) = peers_source_id_map
+                                .iter()
+                                .find(|(_, id)| **id == source_id)
+                                .map(|(peer_id, _)| peer_id.clone())
+                            {
+                                network_service
+                                    .report_peer(peer_id, reputation_change)
+                                    .await;
+                            }
+                        }
+
                         process = s.process_one(unix_time);
                     }
                     optimistic::ProcessOne::Finalized {
                         sync: s,
                         finalized_blocks,
+                        equivocation,
                     } => {
-                        process = s.process_one(unix_time);
-
-                        if let Some(last_finalized) = finalized_blocks.last() {
-                            let mut lock = sync_state.lock().await;
-                            lock.finalized_block_hash = last_finalized.header.hash();
-                            lock.finalized_block_number = last_finalized.header.number;
+                        if let Some(equivocation) = equivocation {
+                            report_equivocation(&equivocation);
                         }
 
-                        // TODO: maybe write in a separate task? but then we can't access the finalized storage immediately after?
-                        for block in &finalized_blocks {
-                            for (key, value) in &block.storage_top_trie_changes {
-                                if let Some(value) = value {
-                                    finalized_block_storage.insert(key.clone(), value.clone());
-                                } else {
-                                    let _was_there = finalized_block_storage.remove(key);
-                                    // TODO: if a block inserts a new value, then removes it in the next block, the key will remain in `finalized_block_storage`; either solve this or document this
-                                    // assert!(_was_there.is_some());
-                                }
-                            }
-                        }
+                        process = s.process_one(unix_time);
 
-                        to_database
-                            .send(ToDatabase::FinalizedBlocks(finalized_blocks))
-                            .await
-                            .unwrap();
+                        report_finalized_blocks(
+                            finalized_blocks,
+                            &sync_state,
+                            &mut finalized_block_storage,
+                            &mut to_database,
+                        )
+                        .await;
                     }
 
                     optimistic::ProcessOne::NewBest {
                         sync: s,
                         new_best_hash,
                         new_best_number,
+                        equivocation,
                     } => {
+                        if let Some(equivocation) = equivocation {
+                            report_equivocation(&equivocation);
+                        }
+
                         // Processing has made a step forward.
                         // There is nothing to do, but this is used to update to best block
-                        // shown on the informant.
-                        let mut lock = sync_state.lock().await;
-                        lock.best_block_hash = new_best_hash;
-                        lock.best_block_number = new_best_number;
-                        drop(lock);
+                        // shown on the informant. The best block of a parachain is instead
+                        // determined by the relay chain.
+                        if parachain.is_none() {
+                            let mut lock = sync_state.lock().await;
+                            lock.best_block_hash = new_best_hash;
+                            lock.best_block_number = new_best_number;
+                        }
 
                         process = s.process_one(unix_time);
                     }
@@ -297,11 +521,40 @@ fn start_sync(
                 }
             }
 
+            // If the relay chain has finalized a parachain block that has been verified in the
+            // meanwhile, finalize it as well.
+            if let Some(target_hash) = parachain_finalized_target {
+                match sync.set_finalized_block(&target_hash) {
+                    Ok(finalized_blocks) => {
+                        parachain_finalized_target = None;
+                        report_finalized_blocks(
+                            finalized_blocks,
+                            &sync_state,
+                            &mut finalized_block_storage,
+                            &mut to_database,
+                        )
+                        .await;
+                    }
+                    Err(blocks_tree::SetFinalizedError::UnknownBlock) => {
+                        // Block hasn't been downloaded and verified yet.
+                    }
+                }
+            }
+
             // Update the current best block, used for CLI-related purposes.
-            {
+            // The best block of a parachain is the block included in the best block of the relay
+            // chain, once it has been verified.
+            if parachain.is_none() {
                 let mut lock = sync_state.lock().await;
                 lock.best_block_hash = sync.best_block_hash();
                 lock.best_block_number = sync.best_block_number();
+            } else if let Some((hash, number)) = parachain_best_target {
+                if sync.has_verified_block(&hash) {
+                    parachain_best_target = None;
+                    let mut lock = sync_state.lock().await;
+                    lock.best_block_hash = hash;
+                    lock.best_block_number = number;
+                }
             }
 
             // Start requests that need to be started.
@@ -379,18 +632,143 @@ fn start_sync(
                     }
                 },
 
+                message = from_foreground.select_next_some() => {
+                    match message {
+                        ToBackground::BestBlockStorage { send_back } => {
+                            let _ = send_back.send(BlockStorage {
+                                finalized_block_hash: sync.finalized_block_header().hash(),
+                                scale_encoded_header: sync.best_block_header().scale_encoding_vec(),
+                                storage_diff: sync
+                                    .best_block_storage_diff()
+                                    .map(|(key, value)| (key.to_vec(), value.map(|v| v.to_vec())))
+                                    .collect(),
+                            });
+                        }
+                    }
+                },
+
+                relay_chain_best = relay_chain_poll.select_next_some() => {
+                    // `relay_chain_poll` never yields anything if `parachain` is `None`.
+                    let parachain = parachain.as_ref().unwrap();
+
+                    // The best block of the relay chain is looked at first, as the code handling
+                    // the finalized block below stops early in various situations.
+                    let relay_chain_best_hash =
+                        header::hash_from_scale_encoded_header(&relay_chain_best.scale_encoded_header);
+                    if relay_chain_best_checked != Some(relay_chain_best_hash) {
+                        match parachain_head_data(
+                            &parachain.relay_chain_database,
+                            &relay_chain_best,
+                            parachain.parachain_id,
+                            &mut relay_chain_runtime,
+                        ) {
+                            Ok(head_data) => {
+                                relay_chain_best_checked = Some(relay_chain_best_hash);
+                                // A missing head data is reported below when looking at the
+                                // finalized block, and a head data that isn't a header is
+                                // reported once it is finalized.
+                                let header = head_data.as_ref().and_then(|h| header::decode(h).ok());
+                                if let Some(header) = header {
+                                    parachain_best_target = Some((header.hash(), header.number));
+                                }
+                            }
+                            Err(ParachainHeadError::Database(full_sqlite::FinalizedAccessError::Obsolete)) => {
+                                // The finalized block of the relay chain in the database isn't
+                                // the one the storage diff applies to, most likely because it
+                                // hasn't been written yet. Try again next time.
+                            }
+                            Err(error) => {
+                                tracing::warn!(%error, "parachain-best-head-fetch-error");
+                                relay_chain_best_checked = Some(relay_chain_best_hash);
+                            }
+                        }
+                    }
+
+                    let relay_chain_finalized = parachain.relay_chain_database.finalized_block_hash().unwrap();
+                    if relay_chain_finalized_checked == Some(relay_chain_finalized) {
+                        continue;
+                    }
+
+                    let relay_chain_finalized_storage = BlockStorage {
+                        finalized_block_hash: relay_chain_finalized,
+                        scale_encoded_header: parachain
+                            .relay_chain_database
+                            .block_scale_encoded_header(&relay_chain_finalized)
+                            .unwrap()
+                            .unwrap(),
+                        storage_diff: BTreeMap::new(),
+                    };
+
+                    let head_data = match parachain_head_data(
+                        &parachain.relay_chain_database,
+                        &relay_chain_finalized_storage,
+                        parachain.parachain_id,
+                        &mut relay_chain_runtime,
+                    ) {
+                        Ok(Some(head_data)) => {
+                            parachain_head_missing = false;
+                            head_data
+                        }
+                        Ok(None) => {
+                            // The parachain likely doesn't occupy a core. This is only reported
+                            // once until the head of the parachain is found again.
+                            if !parachain_head_missing {
+                                tracing::warn!("parachain-head-not-found");
+                                parachain_head_missing = true;
+                            }
+                            relay_chain_finalized_checked = Some(relay_chain_finalized);
+                            continue;
+                        }
+                        Err(ParachainHeadError::Database(full_sqlite::FinalizedAccessError::Obsolete)) => {
+                            // The finalized block of the relay chain has been updated while the
+                            // runtime call was in progress. Try again next time.
+                            continue;
+                        }
+                        Err(error) => {
+                            tracing::warn!(%error, "parachain-head-fetch-error");
+                            relay_chain_finalized_checked = Some(relay_chain_finalized);
+                            continue;
+                        }
+                    };
+
+                    relay_chain_finalized_checked = Some(relay_chain_finalized);
+
+                    // The meaning of the head data depends on the parachain. It can represent
+                    // anything. In practice, however, it is most of the time a block header.
+                    let number = match header::decode(&head_data) {
+                        Ok(header) => header.number,
+                        Err(_) => {
+                            // This isn't supported by smoldot.
+                            tracing::warn!("parachain-head-data-not-header");
+                            continue;
+                        }
+                    };
+
+                    // The block is downloaded and verified through the usual means, and is finalized
+                    // when it has been verified.
+                    if number > sync.finalized_block_header().number {
+                        parachain_finalized_target =
+                            Some(header::hash_from_scale_encoded_header(&head_data));
+                    }
+                },
+
                 (request_id, result) = block_requests_finished.select_next_some() => {
                     // `result` is an error if the block request got cancelled by the sync state
                     // machine.
                     // TODO: clarify this piece of code
                     if let Ok(result) = result {
                         let result = result.map_err(|_| ());
-                        let _ = sync.finish_request(request_id, result.map(|v| v.into_iter().map(|block| optimistic::RequestSuccessBlock {
+                        let (_, outcome) = sync.finish_request(request_id, result.map(|v| v.into_iter().map(|block| optimistic::RequestSuccessBlock {
                             scale_encoded_header: block.header.unwrap(), // TODO: don't unwrap
                             scale_encoded_extrinsics: block.body.unwrap(), // TODO: don't unwrap
                             scale_encoded_justification: block.justification,
                             user_data: (),
                         })).map_err(|()| optimistic::RequestFail::BlocksUnavailable));
+
+                        if let optimistic::FinishRequestOutcome::SourcePunished(peer_id) = outcome {
+                            let peer_id = peer_id.clone();
+                            network_service.report_peer(peer_id, network::service::ReputationChange::RequestFailed).await;
+                        }
                     }
                 },
             }
@@ -398,6 +776,428 @@ fn start_sync(
     }
 }
 
+/// Downloads from the network the storage of the finalized block of the database, and writes it
+/// in the database.
+///
+/// Returns the storage of the main trie, and the list of peers that are connected at the end of
+/// the download and their best block number. Returns `None` if the network service has shut
+/// down or if the storage couldn't be written.
+#[tracing::instrument(skip(database, network_service, from_network_service))]
+async fn download_finalized_storage(
+    database: &full_sqlite::SqliteFullDatabase,
+    (network_service, network_chain_index): (&Arc<network_service::NetworkService>, usize),
+    from_network_service: &mut mpsc::Receiver<network_service::Event>,
+) -> Option<(BTreeMap<Vec<u8>, Vec<u8>>, Vec<(libp2p::PeerId, u64)>)> {
+    enum Response {
+        State(Result<Vec<network::protocol::StateResponseEntry>, ()>),
+        StorageProof(Result<Vec<Vec<u8>>, ()>),
+    }
+
+    let finalized_block_hash = database.finalized_block_hash().unwrap();
+    let state_root = *header::decode(
+        &database
+            .block_scale_encoded_header(&finalized_block_hash)
+            .unwrap()
+            .unwrap(),
+    )
+    .unwrap()
+    .state_root;
+
+    tracing::info!(hash = ?finalized_block_hash, "finalized-storage-download-start");
+
+    let mut sync = state::StateSync::<libp2p::PeerId, future::AbortHandle>::new(state::Config {
+        block_hash: finalized_block_hash,
+        state_root,
+        sources_capacity: 32,
+        // The storage is held entirely in memory anyway. This limit is high enough to fit the
+        // storage of Polkadot and Kusama.
+        max_state_entries: 16 * 1024 * 1024,
+    });
+
+    // List of peers that are connected, with their source id within `sync` and their best block
+    // number.
+    let mut peers =
+        hashbrown::HashMap::<libp2p::PeerId, (state::SourceId, u64), fnv::FnvBuildHasher>::default(
+        );
+
+    let mut requests_finished = stream::FuturesUnordered::new();
+
+    loop {
+        if let Some((source_id, peer_id, detail)) = sync.desired_request() {
+            let peer_id = peer_id.clone();
+            let request = match detail {
+                state::RequestDetail::State { block_hash, start } => network_service
+                    .clone()
+                    .state_request(
+                        peer_id.clone(),
+                        network_chain_index,
+                        network::protocol::StateRequestConfig {
+                            block_hash,
+                            start: start.into_iter(),
+                        },
+                    )
+                    .map(|r| Response::State(r.map_err(|_| ())))
+                    .boxed(),
+                state::RequestDetail::StorageProof { block_hash, keys } => network_service
+                    .clone()
+                    .storage_proof_request(
+                        peer_id.clone(),
+                        network_chain_index,
+                        network::protocol::StorageProofRequestConfig {
+                            block_hash,
+                            keys: keys.into_iter(),
+                        },
+                    )
+                    .map(|r| Response::StorageProof(r.map_err(|_| ())))
+                    .boxed(),
+                state::RequestDetail::ChildStorageProof {
+                    block_hash,
+                    child_trie,
+                    keys,
+                } => network_service
+                    .clone()
+                    .child_storage_proof_request(
+                        peer_id.clone(),
+                        network_chain_index,
+                        network::protocol::ChildStorageProofRequestConfig {
+                            block_hash,
+                            child_trie,
+                            keys: keys.into_iter(),
+                        },
+                    )
+                    .map(|r| Response::StorageProof(r.map_err(|_| ())))
+                    .boxed(),
+            };
+
+            let (request, abort) = future::abortable(request);
+            sync.add_request(source_id, abort);
+            requests_finished.push(request.map(move |r| (peer_id, r)));
+        }
+
+        futures::select! {
+            network_event = from_network_service.next() => {
+                match network_event? {
+                    network_service::Event::Connected { chain_index, peer_id, best_block_number }
+                        if chain_index == network_chain_index =>
+                    {
+                        let id = sync.add_source(peer_id.clone());
+                        peers.insert(peer_id, (id, best_block_number));
+                    }
+                    network_service::Event::Disconnected { chain_index, peer_id }
+                        if chain_index == network_chain_index =>
+                    {
+                        let (id, _) = peers.remove(&peer_id).unwrap();
+                        if let (_, Some(request)) = sync.remove_source(id) {
+                            request.abort();
+                        }
+                    }
+                    network_service::Event::BlockAnnounce { chain_index, peer_id, announce }
+                        if chain_index == network_chain_index =>
+                    {
+                        let decoded = announce.decode();
+                        let (_, best_block_number) = peers.get_mut(&peer_id).unwrap();
+                        *best_block_number = (*best_block_number).max(decoded.header.number);
+                    }
+                    _ => {}
+                }
+            },
+
+            (peer_id, result) = requests_finished.select_next_some() => {
+                // `result` is an error if the request got cancelled because the source has
+                // disconnected.
+                let response = match result {
+                    Ok(r) => r,
+                    Err(future::Aborted) => continue,
+                };
+
+                let (_, outcome) = match response {
+                    Response::State(response) => sync.state_response(response),
+                    Response::StorageProof(response) => sync.storage_proof_response(response),
+                };
+
+                sync = match outcome {
+                    state::ResponseOutcome::Queued(sync) => sync,
+                    state::ResponseOutcome::Error { sync, error } => {
+                        tracing::debug!(%peer_id, %error, "finalized-storage-download-error");
+                        network_service
+                            .report_peer(peer_id, network::service::ReputationChange::RequestFailed)
+                            .await;
+                        sync
+                    }
+                    state::ResponseOutcome::Finished(success) => {
+                        if let Err(error) = database.set_finalized_storage(
+                            &finalized_block_hash,
+                            success.main_trie.iter().map(|(k, v)| (&k[..], &v[..])),
+                            success.child_tries.iter().map(|(child_trie, entries)| {
+                                (
+                                    &child_trie[..],
+                                    entries.iter().map(|(k, v)| (&k[..], &v[..])),
+                                )
+                            }),
+                        ) {
+                            tracing::error!(%error, "finalized-storage-write-error");
+                            return None;
+                        }
+
+                        tracing::info!(
+                            hash = ?finalized_block_hash,
+                            entries = success.main_trie.len(),
+                            "finalized-storage-download-finished"
+                        );
+
+                        let connected_peers = peers
+                            .into_iter()
+                            .map(|(peer_id, (_, best_block_number))| (peer_id, best_block_number))
+                            .collect();
+                        return Some((success.main_trie, connected_peers));
+                    }
+                };
+            },
+        }
+    }
+}
+
+/// Reports an authority that has produced two different blocks in the same slot.
+// TODO: submit the proof to the runtime in order for the offender to be punished
+fn report_equivocation(equivocation: &blocks_tree::EquivocationProof) {
+    tracing::warn!(
+        engine = ?equivocation.consensus_engine,
+        offender = ?equivocation.offender,
+        slot = %equivocation.slot_number,
+        first_hash = ?equivocation.first_header.hash(),
+        second_hash = ?equivocation.second_header.hash(),
+        "equivocation-detected"
+    );
+}
+
+/// Reports blocks that have been finalized to the rest of the node, and updates the storage of
+/// the finalized block accordingly.
+async fn report_finalized_blocks(
+    finalized_blocks: Vec<optimistic::Block<()>>,
+    sync_state: &Mutex<SyncState>,
+    finalized_block_storage: &mut BTreeMap<Vec<u8>, Vec<u8>>,
+    to_database: &mut mpsc::Sender<ToDatabase>,
+) {
+    if let Some(last_finalized) = finalized_blocks.last() {
+        let mut lock = sync_state.lock().await;
+        lock.finalized_block_hash = last_finalized.header.hash();
+        lock.finalized_block_number = last_finalized.header.number;
+
+        // The best block of a parachain isn't updated at the same time as its finalized block,
+        // and might lag behind.
+        if lock.best_block_number < lock.finalized_block_number {
+            lock.best_block_hash = lock.finalized_block_hash;
+            lock.best_block_number = lock.finalized_block_number;
+        }
+    }
+
+    // TODO: maybe write in a separate task? but then we can't access the finalized storage immediately after?
+    for block in &finalized_blocks {
+        for (key, value) in &block.storage_top_trie_changes {
+            if let Some(value) = value {
+                finalized_block_storage.insert(key.clone(), value.clone());
+            } else {
+                let _was_there = finalized_block_storage.remove(key);
+                // TODO: if a block inserts a new value, then removes it in the next block, the key will remain in `finalized_block_storage`; either solve this or document this
+                // assert!(_was_there.is_some());
+            }
+        }
+    }
+
+    to_database
+        .send(ToDatabase::FinalizedBlocks(finalized_blocks))
+        .await
+        .unwrap();
+}
+
+/// Runtime of a block of a relay chain, and the storage values it has been built from. Used in
+/// order to not compile the runtime again if it hasn't been modified.
+struct RelayChainRuntime {
+    /// Value of `:code` in the storage.
+    code: Vec<u8>,
+    /// Value of `:heappages` in the storage.
+    heap_pages: Option<Vec<u8>>,
+    /// Runtime built from [`RelayChainRuntime::code`] and [`RelayChainRuntime::heap_pages`].
+    runtime: executor::host::HostVmPrototype,
+}
+
+/// Calls `ParachainHost_persisted_validation_data` on the given block of the relay chain, and
+/// returns the head data of the given parachain.
+///
+/// The storage of the finalized block of the relay chain is read from `relay_chain_database`, and
+/// [`BlockStorage::storage_diff`] is applied on top of it.
+///
+/// Returns `Ok(None)` if the parachain doesn't occupy any core.
+///
+/// `relay_chain_runtime` is used as a cache, and is updated if the runtime of the relay chain has
+/// been modified.
+fn parachain_head_data(
+    relay_chain_database: &full_sqlite::SqliteFullDatabase,
+    relay_chain_block: &BlockStorage,
+    parachain_id: u32,
+    relay_chain_runtime: &mut Option<RelayChainRuntime>,
+) -> Result<Option<Vec<u8>>, ParachainHeadError> {
+    let finalized_block_hash = &relay_chain_block.finalized_block_hash;
+    let storage_diff = &relay_chain_block.storage_diff;
+
+    let storage_get = |key: &[u8]| match storage_diff.get(key) {
+        Some(value) => Ok(value.clone()),
+        None => {
+            relay_chain_database.finalized_block_storage_top_trie_get(finalized_block_hash, key)
+        }
+    };
+
+    let storage_next_key = |key: &[u8]| {
+        // Next key in the storage of the finalized block, skipping the keys that have been
+        // erased since then.
+        let mut cursor = key.to_vec();
+        let in_finalized = loop {
+            match relay_chain_database
+                .finalized_block_storage_top_trie_next_key(finalized_block_hash, &cursor)?
+            {
+                Some(k) if matches!(storage_diff.get(&k), Some(None)) => cursor = k,
+                k => break k,
+            }
+        };
+
+        let in_diff = storage_diff
+            .range::<[u8], _>((Bound::Excluded(key), Bound::Unbounded))
+            .find(|(_, value)| value.is_some())
+            .map(|(k, _)| k.clone());
+
+        Ok(match (in_finalized, in_diff) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        })
+    };
+
+    let code = storage_get(b":code")?.ok_or(ParachainHeadError::MissingCode)?;
+    let heap_pages = storage_get(b":heappages")?;
+
+    let runtime = match relay_chain_runtime.take() {
+        Some(cache) if cache.code == code && cache.heap_pages == heap_pages => cache.runtime,
+        _ => executor::host::HostVmPrototype::new(
+            &code,
+            executor::storage_heap_pages_to_value(heap_pages.as_deref())
+                .map_err(ParachainHeadError::HeapPages)?,
+            executor::vm::ExecHint::CompileAheadOfTime,
+        )
+        .map_err(ParachainHeadError::Vm)?,
+    };
+
+    let state_trie_root = *header::decode(&relay_chain_block.scale_encoded_header)
+        .map_err(ParachainHeadError::InvalidHeader)?
+        .state_root;
+
+    let vm = read_only_runtime_host::run(read_only_runtime_host::Config {
+        virtual_machine: runtime,
+        function_to_call: "ParachainHost_persisted_validation_data",
+        parameter: para::persisted_validation_data_parameters(
+            parachain_id,
+            para::OccupiedCoreAssumption::TimedOut,
+        ),
+    });
+
+    let mut vm = match vm {
+        Ok(vm) => vm,
+        Err((error, runtime)) => {
+            *relay_chain_runtime = Some(RelayChainRuntime {
+                code,
+                heap_pages,
+                runtime,
+            });
+            return Err(ParachainHeadError::Start(error));
+        }
+    };
+
+    let (runtime, result) = loop {
+        match vm {
+            read_only_runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                let output = success.virtual_machine.value().as_ref().to_vec();
+                break (success.virtual_machine.into_prototype(), Ok(output));
+            }
+            read_only_runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                break (
+                    error.prototype,
+                    Err(ParachainHeadError::Execution(error.detail)),
+                );
+            }
+            read_only_runtime_host::RuntimeHostVm::StorageGet(req) => {
+                match storage_get(&req.key_as_vec()) {
+                    Ok(value) => vm = req.inject_value(value.as_ref().map(iter::once)),
+                    Err(error) => {
+                        break (
+                            read_only_runtime_host::RuntimeHostVm::StorageGet(req).into_prototype(),
+                            Err(ParachainHeadError::Database(error)),
+                        )
+                    }
+                }
+            }
+            read_only_runtime_host::RuntimeHostVm::NextKey(req) => {
+                let next_key = storage_next_key(req.key().as_ref());
+                match next_key {
+                    Ok(key) => vm = req.inject_key(key),
+                    Err(error) => {
+                        break (
+                            read_only_runtime_host::RuntimeHostVm::NextKey(req).into_prototype(),
+                            Err(ParachainHeadError::Database(error)),
+                        )
+                    }
+                }
+            }
+            read_only_runtime_host::RuntimeHostVm::StorageRoot(req) => {
+                vm = req.resume(&state_trie_root);
+            }
+        }
+    };
+
+    *relay_chain_runtime = Some(RelayChainRuntime {
+        code,
+        heap_pages,
+        runtime,
+    });
+
+    match para::decode_persisted_validation_data_return_value(&result?) {
+        Ok(Some(pvd)) => Ok(Some(pvd.parent_head.to_vec())),
+        Ok(None) => Ok(None),
+        Err(_) => Err(ParachainHeadError::InvalidPersistedValidationData),
+    }
+}
+
+/// Error potentially returned by [`parachain_head_data`].
+#[derive(Debug, derive_more::Display, derive_more::From)]
+enum ParachainHeadError {
+    /// Error while accessing the storage of the relay chain.
+    #[display(fmt = "{}", _0)]
+    Database(full_sqlite::FinalizedAccessError),
+    /// Failed to decode the header of the block of the relay chain.
+    #[display(fmt = "Invalid relay chain block header: {}", _0)]
+    #[from(ignore)]
+    InvalidHeader(header::Error),
+    /// The storage of the relay chain doesn't contain any runtime code.
+    #[display(fmt = "Missing :code in relay chain storage")]
+    MissingCode,
+    /// Invalid value of `:heappages` in the storage of the relay chain.
+    #[display(fmt = "{}", _0)]
+    #[from(ignore)]
+    HeapPages(executor::InvalidHeapPagesError),
+    /// Error while compiling the runtime of the relay chain.
+    #[display(fmt = "{}", _0)]
+    #[from(ignore)]
+    Vm(executor::host::NewErr),
+    /// Error while starting the call to `ParachainHost_persisted_validation_data`.
+    #[display(fmt = "{}", _0)]
+    #[from(ignore)]
+    Start(executor::host::StartErr),
+    /// Error while executing `ParachainHost_persisted_validation_data`.
+    #[display(fmt = "{}", _0)]
+    #[from(ignore)]
+    Execution(read_only_runtime_host::ErrorDetail),
+    /// The output of `ParachainHost_persisted_validation_data` couldn't be decoded.
+    #[display(fmt = "Failed to decode the persisted validation data")]
+    InvalidPersistedValidationData,
+}
+
 /// Starts the task that writes blocks to the database.
 #[tracing::instrument(skip(database, messages_rx))]
 async fn start_database_write(
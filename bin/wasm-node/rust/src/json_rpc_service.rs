@@ -37,7 +37,7 @@ use smoldot::{
     network::protocol,
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     convert::TryFrom as _,
     iter,
     pin::Pin,
@@ -800,22 +800,60 @@ impl JsonRpcService {
                         .to_json_response(request_id),
                 );
             }
+            methods::MethodCall::system_networkState {} => {
+                let total = self.network_service.total_bandwidth().await;
+
+                // Multiple connections to the same peer, and protocols with the same name, are
+                // merged together.
+                let mut connected_peers = BTreeMap::<String, methods::NetworkStatePeer>::new();
+                for connection in self.network_service.connections_bandwidth().await {
+                    let peer = connected_peers
+                        .entry(connection.peer_id.to_string())
+                        .or_insert_with(|| methods::NetworkStatePeer {
+                            bytes_inbound: 0,
+                            bytes_outbound: 0,
+                            protocols: BTreeMap::new(),
+                        });
+                    peer.bytes_inbound += connection.total.bytes_received;
+                    peer.bytes_outbound += connection.total.bytes_sent;
+                    for (protocol_name, bandwidth) in connection.protocols {
+                        let protocol = peer.protocols.entry(protocol_name).or_insert(
+                            methods::NetworkStateProtocol {
+                                bytes_inbound: 0,
+                                bytes_outbound: 0,
+                            },
+                        );
+                        protocol.bytes_inbound += bandwidth.bytes_received;
+                        protocol.bytes_outbound += bandwidth.bytes_sent;
+                    }
+                }
+
+                self.send_back(
+                    &methods::Response::system_networkState(methods::NetworkState {
+                        total_bytes_inbound: total.bytes_received,
+                        total_bytes_outbound: total.bytes_sent,
+                        connected_peers,
+                    })
+                    .to_json_response(request_id),
+                );
+            }
             methods::MethodCall::system_peers {} => {
                 // TODO: return proper response
+                let mut peers = Vec::new();
+                for peer_id in self.network_service.peers_list().await {
+                    let ping_time = self.network_service.ping_time(&peer_id).await;
+                    peers.push(methods::SystemPeer {
+                        peer_id: peer_id.to_string(),
+                        roles: "unknown".to_string(),
+                        best_hash: methods::HashHexString([0x0; 32]),
+                        best_number: 0,
+                        ping_time_ms: ping_time
+                            .map(|t| u64::try_from(t.as_millis()).unwrap_or(u64::max_value())),
+                    });
+                }
+
                 self.send_back(
-                    &methods::Response::system_peers(
-                        self.network_service
-                            .peers_list()
-                            .await
-                            .map(|peer_id| methods::SystemPeer {
-                                peer_id: peer_id.to_string(),
-                                roles: "unknown".to_string(),
-                                best_hash: methods::HashHexString([0x0; 32]),
-                                best_number: 0,
-                            })
-                            .collect(),
-                    )
-                    .to_json_response(request_id),
+                    &methods::Response::system_peers(peers).to_json_response(request_id),
                 );
             }
             methods::MethodCall::system_properties {} => {
@@ -268,6 +268,8 @@ async fn start_services(
                 }),
                 network_service: (network_service.clone(), chain_index),
                 network_events_receiver: network_event_receivers.pop().unwrap(),
+                bad_blocks: chain_spec.bad_blocks_hashes().copied().collect(),
+                fork_blocks: chain_spec.fork_blocks().collect(),
                 parachain: None,
             })
             .await,
@@ -336,6 +338,8 @@ async fn start_services(
                 }),
                 network_service: (network_service.clone(), chain_index),
                 network_events_receiver: network_event_receivers.pop().unwrap(),
+                bad_blocks: chain_spec.bad_blocks_hashes().copied().collect(),
+                fork_blocks: chain_spec.fork_blocks().collect(),
                 parachain: Some(sync_service::ConfigParachain {
                     parachain_id,
                     relay_chain_sync: relay_chain_services.1.clone(),
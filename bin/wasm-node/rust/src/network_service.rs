@@ -52,7 +52,10 @@ use smoldot::{
     network::{protocol, service},
     trie::{self, prefix_proof, proof_verify},
 };
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 /// Configuration for a [`NetworkService`].
 pub struct Config {
@@ -105,6 +108,9 @@ pub struct NetworkService {
 struct Guarded {
     /// See [`Config::tasks_executor`].
     tasks_executor: Box<dyn FnMut(Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+
+    /// Round-trip time of the latest successful ping of each connected peer.
+    ping_times: HashMap<PeerId, Duration, fnv::FnvBuildHasher>,
 }
 
 impl NetworkService {
@@ -165,6 +171,7 @@ impl NetworkService {
         let network_service = Arc::new(NetworkService {
             guarded: Mutex::new(Guarded {
                 tasks_executor: config.tasks_executor,
+                ping_times: HashMap::default(),
             }),
             network: service::ChainNetwork::new(service::Config {
                 chains,
@@ -175,6 +182,10 @@ impl NetworkService {
                 // once the issue is solved, this should be restored to a smaller value, such as 16
                 pending_api_events_buffer_size: NonZeroUsize::new(2048).unwrap(),
                 randomness_seed: rand::random(),
+                dial_backoff_base: Duration::from_secs(5),
+                dial_backoff_max: Duration::from_secs(300),
+                ban_threshold: -1000,
+                ban_duration: Duration::from_secs(300),
             }),
             important_nodes,
         });
@@ -202,6 +213,12 @@ impl NetworkService {
                                 chain_indices,
                             } => {
                                 log::info!(target: "network", "Disconnected from {} (chains: {:?})", peer_id, chain_indices);
+                                network_service
+                                    .guarded
+                                    .lock()
+                                    .await
+                                    .ping_times
+                                    .remove(&peer_id);
                                 if !chain_indices.is_empty() {
                                     // TODO: properly implement when multiple chains
                                     if chain_indices.len() == 1 {
@@ -238,15 +255,17 @@ impl NetworkService {
                                 chain_index,
                                 best_number,
                                 best_hash,
+                                block_announces_protocol,
                                 ..
                             } => {
                                 log::debug!(
                                     target: "network",
-                                    "Connection({}) => ChainConnected({}, {}, {})",
+                                    "Connection({}) => ChainConnected({}, {}, {}, {})",
                                     peer_id,
                                     chain_index,
                                     best_number,
-                                    HashDisplay(&best_hash)
+                                    HashDisplay(&best_hash),
+                                    block_announces_protocol
                                 );
                                 break Event::Connected {
                                     peer_id,
@@ -280,11 +299,13 @@ impl NetworkService {
                             }
                             service::Event::GrandpaCommitMessage {
                                 chain_index,
+                                peer_id,
                                 message,
                             } => {
                                 log::debug!(
                                     target: "network",
-                                    "Connection(?) => GrandpaCommitMessage({}, {})",
+                                    "Connection({}) => GrandpaCommitMessage({}, {})",
+                                    peer_id,
                                     chain_index,
                                     HashDisplay(message.decode().message.target_hash),
                                 );
@@ -293,6 +314,79 @@ impl NetworkService {
                                     message,
                                 };
                             }
+                            service::Event::GrandpaNeighborPacket {
+                                chain_index,
+                                peer_id,
+                                state,
+                            } => {
+                                log::debug!(
+                                    target: "network",
+                                    "Connection({}) => GrandpaNeighborPacket({}, set_id: {}, commit_finalized_height: {})",
+                                    peer_id,
+                                    chain_index,
+                                    state.set_id,
+                                    state.commit_finalized_height,
+                                );
+                            }
+                            service::Event::GrandpaVote { .. }
+                            | service::Event::GrandpaCatchUpRequest { .. }
+                            | service::Event::GrandpaCatchUp { .. } => {
+                                // Light clients don't participate in GrandPa rounds, and thus
+                                // ignore votes and catch-up messages.
+                            }
+                            service::Event::Transactions {
+                                chain_index,
+                                peer_id,
+                                transactions,
+                            } => {
+                                // Light clients don't have a transactions pool, and thus ignore
+                                // transactions gossiped by other nodes.
+                                log::debug!(
+                                    target: "network",
+                                    "Connection({}) => Transactions({}, num = {})",
+                                    peer_id,
+                                    chain_index,
+                                    transactions.decode().len(),
+                                );
+                            }
+                            service::Event::PingOutSuccess { peer_id, ping_time } => {
+                                log::trace!(
+                                    target: "network",
+                                    "Connection({}) => PingOutSuccess({:?})",
+                                    peer_id,
+                                    ping_time,
+                                );
+                                network_service
+                                    .guarded
+                                    .lock()
+                                    .await
+                                    .ping_times
+                                    .insert(peer_id, ping_time);
+                            }
+                            service::Event::PingOutFailed { peer_id } => {
+                                log::debug!(
+                                    target: "network",
+                                    "Connection({}) => PingOutFailed",
+                                    peer_id,
+                                );
+                            }
+                            service::Event::ProtocolMisbehavior {
+                                chain_index,
+                                peer_id,
+                                reputation_change,
+                            } => {
+                                log::debug!(
+                                    target: "network",
+                                    "Connection({}) => ProtocolMisbehavior({}, {:?})",
+                                    peer_id,
+                                    chain_index,
+                                    reputation_change,
+                                );
+                                network_service
+                                    .network
+                                    .report_peer(ffi::Instant::now(), &peer_id, reputation_change)
+                                    .await;
+                            }
                         }
                     };
 
@@ -327,11 +421,14 @@ impl NetworkService {
                             }
                         };
 
-                        let start_connect =
-                            match network_service.network.fill_out_slots(chain_index).await {
-                                Some(sc) => sc,
-                                None => continue,
-                            };
+                        let start_connect = match network_service
+                            .network
+                            .fill_out_slots(chain_index, ffi::Instant::now())
+                            .await
+                        {
+                            Some(sc) => sc,
+                            None => continue,
+                        };
 
                         let is_important_peer = network_service
                             .important_nodes
@@ -772,6 +869,18 @@ impl NetworkService {
         result
     }
 
+    /// Modifies the reputation of the given peer. Connections to this peer are closed if its
+    /// reputation falls below a certain threshold.
+    pub async fn report_peer(&self, peer_id: PeerId, reputation_change: service::ReputationChange) {
+        let banned = self
+            .network
+            .report_peer(ffi::Instant::now(), &peer_id, reputation_change)
+            .await;
+        if banned {
+            log::debug!(target: "network", "Banned {}", peer_id);
+        }
+    }
+
     /// Announces transaction to the peers we are connected to.
     ///
     /// Returns a list of peers that we have sent the transaction to. Can return an empty `Vec`
@@ -809,6 +918,24 @@ impl NetworkService {
     pub async fn peers_list(&self) -> impl Iterator<Item = PeerId> {
         self.network.peers_list().await
     }
+
+    /// Returns the number of bytes transferred on all the connections since the network service
+    /// has started.
+    pub async fn total_bandwidth(&self) -> smoldot::libp2p::Bandwidth {
+        self.network.total_bandwidth().await
+    }
+
+    /// Returns the number of bytes transferred on each established connection, in total and
+    /// for each protocol.
+    pub async fn connections_bandwidth(&self) -> Vec<service::ConnectionBandwidth> {
+        self.network.connections_bandwidth().await
+    }
+
+    /// Returns the round-trip time of the latest successful ping sent to the given peer, or
+    /// `None` if no ping has succeeded yet or if we aren't connected to this peer.
+    pub async fn ping_time(&self, peer_id: &PeerId) -> Option<Duration> {
+        self.guarded.lock().await.ping_times.get(peer_id).copied()
+    }
 }
 
 /// Event that can happen on the network service.
@@ -929,7 +1056,7 @@ async fn connection_task(
 
             network_service
                 .network
-                .pending_outcome_err(pending_id)
+                .pending_outcome_err(pending_id, ffi::Instant::now())
                 .await;
 
             return;
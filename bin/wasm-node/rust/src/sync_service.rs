@@ -42,7 +42,10 @@ use smoldot::{
     sync::{all, para},
     trie::proof_verify,
 };
-use std::{collections::HashMap, convert::TryFrom as _, num::NonZeroU32, pin::Pin, sync::Arc};
+use std::{
+    collections::HashMap, convert::TryFrom as _, num::NonZeroU32, pin::Pin, sync::Arc,
+    time::Duration,
+};
 
 pub use crate::lossy_channel::Receiver as NotificationsReceiver;
 
@@ -62,6 +65,17 @@ pub struct Config {
     /// [`network_service::NetworkService::new`].
     pub network_events_receiver: mpsc::Receiver<network_service::Event>,
 
+    /// List of hashes of blocks that must never be part of the chain. Typically found in the
+    /// chain specification.
+    pub bad_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes that must be part of the chain. Typically found in the
+    /// chain specification.
+    ///
+    /// > **Note**: This field and [`Config::bad_blocks`] are ignored for parachains at the
+    /// >           moment.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
     /// Extra fields used when the chain is a parachain.
     /// If `None`, this chain is a standalone chain or a relay chain.
     pub parachain: Option<ConfigParachain>,
@@ -113,6 +127,8 @@ impl SyncService {
                     config.network_service.0,
                     config.network_service.1,
                     config.network_events_receiver,
+                    config.bad_blocks,
+                    config.fork_blocks,
                 )
                 .await,
             ));
@@ -183,6 +199,8 @@ async fn start_relay_chain(
     network_service: Arc<network_service::NetworkService>,
     network_chain_index: usize,
     mut from_network_service: mpsc::Receiver<network_service::Event>,
+    bad_blocks: Vec<[u8; 32]>,
+    fork_blocks: Vec<(u64, [u8; 32])>,
 ) -> impl Future<Output = ()> {
     // TODO: implicit generics
     let mut sync = all::AllSync::<(), libp2p::PeerId, ()>::new(all::Config {
@@ -210,6 +228,10 @@ async fn start_relay_chain(
             // is 5k.
             5000
         },
+        bad_blocks,
+        fork_blocks,
+        // The light client never downloads nor verifies block bodies. The code below relies on
+        // this.
         full: None,
     });
 
@@ -239,6 +261,10 @@ async fn start_relay_chain(
         let mut has_new_best = false;
         let mut has_new_finalized = false;
 
+        // Timer that fires when the justifications that are still missing must be requested
+        // again.
+        let mut justifications_retry = ffi::Delay::new(Duration::from_secs(10)).fuse();
+
         // Main loop of the syncing logic.
         loop {
             // Drain the content of `requests_to_start` to actually start the requests that have
@@ -396,10 +422,21 @@ async fn start_relay_chain(
                         next_actions,
                         is_new_best,
                         is_new_finalized,
-                        ..
+                        equivocation,
                     } => {
                         requests_to_start.extend(next_actions);
 
+                        if let Some(equivocation) = equivocation {
+                            log::warn!(
+                                target: "sync-verify",
+                                "Authority {:?} has produced blocks {:?} and {:?} in slot {}",
+                                equivocation.offender,
+                                equivocation.first_header.hash(),
+                                equivocation.second_header.hash(),
+                                equivocation.slot_number
+                            );
+                        }
+
                         if is_new_best {
                             has_new_best = true;
                         }
@@ -553,6 +590,14 @@ async fn start_relay_chain(
                                 all::BlockAnnounceOutcome::NotFinalizedChain(idle) => {
                                     sync = idle.into();
                                 },
+                                all::BlockAnnounceOutcome::BadBlock(idle) => {
+                                    log::debug!(
+                                        target: "sync-verify",
+                                        "Ignoring announce of bad block from {}",
+                                        peer_id
+                                    );
+                                    sync = idle.into();
+                                },
                                 all::BlockAnnounceOutcome::Disjoint { sync: sync_idle, next_actions, .. } => {
                                     requests_to_start.extend(next_actions);
                                     sync = sync_idle.into();
@@ -635,6 +680,9 @@ async fn start_relay_chain(
                             all::BlocksRequestResponseOutcome::VerifyHeader(verify) => {
                                 sync = verify.into();
                             },
+                            // `full` is `None` in the configuration of the `AllSync`, and
+                            // block bodies are thus never verified.
+                            all::BlocksRequestResponseOutcome::VerifyBody(_) => unreachable!(),
                             all::BlocksRequestResponseOutcome::Queued { sync: sync_idle, next_actions } => {
                                 requests_to_start.extend(next_actions);
                                 sync = sync_idle.into();
@@ -643,12 +691,26 @@ async fn start_relay_chain(
                                 requests_to_start.extend(next_actions);
                                 sync = sync_idle.into();
                             },
-                            all::BlocksRequestResponseOutcome::Inconclusive { sync: sync_idle, next_actions, .. } => {
+                            all::BlocksRequestResponseOutcome::Inconclusive { sync: mut sync_idle, next_actions, penalized_source } => {
+                                if let Some(source_id) = penalized_source {
+                                    let peer_id = sync_idle.source_user_data_mut(source_id).clone();
+                                    log::debug!(
+                                        target: "sync-verify",
+                                        "Source {} failed to provide an announced block",
+                                        peer_id
+                                    );
+                                    let reputation_change =
+                                        network::service::ReputationChange::RequestFailed;
+                                    network_service.report_peer(peer_id, reputation_change).await;
+                                }
                                 requests_to_start.extend(next_actions);
                                 sync = sync_idle.into();
                             },
-                            all::BlocksRequestResponseOutcome::AllAlreadyInChain { sync: sync_idle, next_actions, .. } => {
+                            all::BlocksRequestResponseOutcome::AllAlreadyInChain { sync: sync_idle, next_actions, is_new_finalized } => {
                                 requests_to_start.extend(next_actions);
+                                if is_new_finalized {
+                                    has_new_finalized = true;
+                                }
                                 sync = sync_idle.into();
                             },
                         }
@@ -734,6 +796,12 @@ async fn start_relay_chain(
                         sync = sync_idle.into();
                     }
                 },
+
+                () = justifications_retry => {
+                    justifications_retry = ffi::Delay::new(Duration::from_secs(10)).fuse();
+                    requests_to_start.extend(sync_idle.retry_justification_requests());
+                    sync = sync_idle.into();
+                },
             }
         }
     }
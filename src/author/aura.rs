@@ -54,12 +54,18 @@ pub fn next_slot_claim<'a>(
     config: Config<'a, impl Iterator<Item = &'a [u8; 32]>>,
 ) -> Option<SlotClaim> {
     let num_current_authorities = config.current_authorities.clone().count();
+    if num_current_authorities == 0 {
+        return None;
+    }
 
-    let current_slot = config.now_from_unix_epoch.as_secs() / config.slot_duration.get();
+    let current_slot = u64::try_from(config.now_from_unix_epoch.as_millis())
+        .unwrap_or(u64::max_value())
+        / config.slot_duration.get();
 
+    // Index within `current_authorities` of the authority allowed to produce a block during
+    // `current_slot`.
     let current_slot_index =
-        usize::try_from(current_slot.checked_div(u64::try_from(num_current_authorities).unwrap())?)
-            .unwrap();
+        usize::try_from(current_slot % u64::try_from(num_current_authorities).unwrap()).unwrap();
 
     let mut claim = None;
 
@@ -88,10 +94,10 @@ pub fn next_slot_claim<'a>(
 
     if let Some((slot_number, local_authorities_index)) = claim {
         let slot_start_from_unix_epoch =
-            Duration::from_secs(slot_number * config.slot_duration.get());
+            Duration::from_millis(slot_number.saturating_mul(config.slot_duration.get()));
         let slot_end_from_unix_epoch =
-            slot_start_from_unix_epoch + Duration::from_secs(config.slot_duration.get());
-        debug_assert!(slot_end_from_unix_epoch < config.now_from_unix_epoch);
+            slot_start_from_unix_epoch + Duration::from_millis(config.slot_duration.get());
+        debug_assert!(slot_end_from_unix_epoch > config.now_from_unix_epoch);
 
         Some(SlotClaim {
             slot_start_from_unix_epoch,
@@ -113,7 +119,7 @@ pub struct SlotClaim {
     /// UNIX time when the slot starts. Can be inferior to the value passed to
     /// [`Config::now_from_unix_epoch`] if the slot has already started.
     pub slot_start_from_unix_epoch: Duration,
-    /// UNIX time when the slot ends. Always inferior to the value passed to
+    /// UNIX time when the slot ends. Always superior to the value passed to
     /// [`Config::now_from_unix_epoch`].
     pub slot_end_from_unix_epoch: Duration,
     /// Slot number of the claim. Used when building the block.
@@ -121,3 +127,83 @@ pub struct SlotClaim {
     /// Index within [`Config::local_authorities`] of the authority that can produce the block.
     pub local_authorities_index: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::header;
+    use core::{num::NonZeroU64, time::Duration};
+
+    #[test]
+    fn slot_claim_basic() {
+        let authorities = [
+            header::AuraAuthority {
+                public_key: [1; 32],
+            },
+            header::AuraAuthority {
+                public_key: [2; 32],
+            },
+            header::AuraAuthority {
+                public_key: [3; 32],
+            },
+        ];
+
+        // Slot 1000 belongs to authority `1000 % 3 == 1`, which is `[2; 32]`. The next slot of
+        // `[1; 32]` is therefore 1002.
+        let claim = super::next_slot_claim(super::Config {
+            now_from_unix_epoch: Duration::from_millis(6000 * 1000 + 1),
+            slot_duration: NonZeroU64::new(6000).unwrap(),
+            current_authorities: header::AuraAuthoritiesIter::from_slice(&authorities),
+            local_authorities: [[1; 32]].iter(),
+        })
+        .unwrap();
+
+        assert_eq!(claim.slot_number, 1002);
+        assert_eq!(claim.local_authorities_index, 0);
+        assert_eq!(
+            claim.slot_start_from_unix_epoch,
+            Duration::from_millis(6000 * 1002)
+        );
+        assert_eq!(
+            claim.slot_end_from_unix_epoch,
+            Duration::from_millis(6000 * 1003)
+        );
+    }
+
+    #[test]
+    fn slot_claim_current_slot() {
+        let authorities = [
+            header::AuraAuthority {
+                public_key: [1; 32],
+            },
+            header::AuraAuthority {
+                public_key: [2; 32],
+            },
+        ];
+
+        let claim = super::next_slot_claim(super::Config {
+            now_from_unix_epoch: Duration::from_millis(6000 * 1001 + 50),
+            slot_duration: NonZeroU64::new(6000).unwrap(),
+            current_authorities: header::AuraAuthoritiesIter::from_slice(&authorities),
+            local_authorities: [[5; 32], [2; 32]].iter(),
+        })
+        .unwrap();
+
+        assert_eq!(claim.slot_number, 1001);
+        assert_eq!(claim.local_authorities_index, 1);
+    }
+
+    #[test]
+    fn no_claim_if_not_authority() {
+        let authorities = [header::AuraAuthority {
+            public_key: [1; 32],
+        }];
+
+        assert!(super::next_slot_claim(super::Config {
+            now_from_unix_epoch: Duration::from_secs(12),
+            slot_duration: NonZeroU64::new(6000).unwrap(),
+            current_authorities: header::AuraAuthoritiesIter::from_slice(&authorities),
+            local_authorities: [[2; 32]].iter(),
+        })
+        .is_none());
+    }
+}
@@ -0,0 +1,460 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Claiming BABE slots.
+//!
+//! See the [`crate::verify::babe`] module for an overview of the BABE algorithm.
+//!
+//! Contrary to Aura, determining whether an authority is allowed to claim a slot requires
+//! generating a VRF output, which in turn requires the secret key of this authority. For this
+//! reason, the local authorities must be provided in the form of their secret seeds.
+
+use crate::{chain::chain_information, header, verify::babe as verify};
+
+use alloc::vec::Vec;
+use core::{convert::TryFrom as _, num::NonZeroU64, time::Duration};
+use rand::SeedableRng as _;
+
+/// Configuration for [`next_slot_claim`].
+pub struct Config<'a, TLocAuth> {
+    /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
+    /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
+    pub now_from_unix_epoch: Duration,
+
+    /// Duration, in milliseconds, of a Babe slot.
+    pub slot_duration: NonZeroU64,
+
+    /// Number of slots per epoch in the Babe configuration.
+    pub slots_per_epoch: NonZeroU64,
+
+    /// Slot number of the block that the new block will be built upon. Must be `None` if and
+    /// only if this parent block is the genesis block, as block #0 doesn't have any slot number.
+    pub parent_slot_number: Option<u64>,
+
+    /// Epoch the parent block belongs to. Must be `None` if and only if the parent block is the
+    /// genesis block.
+    ///
+    /// See [`crate::verify::babe::VerifyConfig::parent_block_epoch`].
+    pub parent_block_epoch: Option<chain_information::BabeEpochInformationRef<'a>>,
+
+    /// Epoch that follows the epoch the parent block belongs to.
+    ///
+    /// See [`crate::verify::babe::VerifyConfig::parent_block_next_epoch`].
+    pub parent_block_next_epoch: chain_information::BabeEpochInformationRef<'a>,
+
+    /// Iterator to the list of sr25519 secret seeds (also known as "mini secret keys") of the
+    /// authorities available locally. The keys are expanded using the same method as Substrate.
+    ///
+    /// Must implement `Iterator<Item = &[u8; 32]>`.
+    pub local_authorities: TLocAuth,
+
+    /// Seed used for the randomness necessary to generate VRF proofs.
+    pub randomness_seed: [u8; 32],
+}
+
+/// Calculates the earliest slot that one of the authorities in [`Config::local_authorities`] is
+/// allowed to claim.
+///
+/// Returns `None` if none of the local authorities are allowed to produce blocks in the current
+/// epoch or in the one that follows.
+///
+/// Primary slot claims are preferred over secondary slot claims.
+///
+/// > **Note**: Determining whether a slot can be claimed requires generating one VRF output per
+/// >           local authority and per slot. The number of slots that are examined is bounded to
+/// >           [`Config::slots_per_epoch`], but this function is nonetheless relatively costly.
+///
+/// # Panic
+///
+/// Panics if `config.parent_block_epoch` is `None` while `config.parent_slot_number` is `Some`,
+/// or vice versa.
+///
+pub fn next_slot_claim<'a>(
+    config: Config<'a, impl Iterator<Item = &'a [u8; 32]>>,
+) -> Option<SlotClaim> {
+    assert_eq!(
+        config.parent_block_epoch.is_some(),
+        config.parent_slot_number.is_some()
+    );
+
+    let local_keypairs = config
+        .local_authorities
+        .map(|seed| {
+            // A mini secret key can be built from any 32 bytes.
+            schnorrkel::MiniSecretKey::from_bytes(&seed[..])
+                .unwrap()
+                .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+        })
+        .collect::<Vec<_>>();
+    if local_keypairs.is_empty() {
+        return None;
+    }
+
+    let mut rng = rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed);
+    // Separate source of randomness for the "extra" transcripts of the VRF proofs. See below.
+    let mut extra_rng = rand_chacha::ChaCha20Rng::from_rng(&mut rng).unwrap();
+
+    let current_slot = u64::try_from(config.now_from_unix_epoch.as_millis())
+        .unwrap_or(u64::max_value())
+        / config.slot_duration.get();
+
+    // Slot numbers must be strictly increasing between a parent and its child.
+    let first_slot = match config.parent_slot_number {
+        Some(parent_slot) => current_slot.max(parent_slot.saturating_add(1)),
+        None => current_slot,
+    };
+
+    for slot_number in first_slot..first_slot.saturating_add(config.slots_per_epoch.get()) {
+        // Determine the epoch the slot belongs to.
+        let epoch = match (
+            &config.parent_block_epoch,
+            config.parent_block_next_epoch.start_slot_number,
+        ) {
+            (None, _) => &config.parent_block_next_epoch,
+            (Some(_), Some(next_epoch_start))
+                if slot_number >= next_epoch_start.saturating_add(config.slots_per_epoch.get()) =>
+            {
+                // Authoring a block in this slot would skip an entire epoch, which isn't
+                // supported.
+                break;
+            }
+            (Some(_), Some(next_epoch_start)) if slot_number >= next_epoch_start => {
+                &config.parent_block_next_epoch
+            }
+            (Some(parent_epoch), _) => parent_epoch,
+        };
+
+        if epoch.authorities.len() == 0 {
+            continue;
+        }
+
+        // Local authorities that belong to the epoch, with their index within
+        // `local_keypairs`, their index within the epoch authorities, and their weight.
+        let candidates = local_keypairs
+            .iter()
+            .enumerate()
+            .filter_map(|(local_index, keypair)| {
+                let public_key = keypair.public.to_bytes();
+                // TODO: O(n) complexity
+                epoch
+                    .authorities
+                    .clone()
+                    .enumerate()
+                    .find(|(_, a)| *a.public_key == public_key)
+                    .map(|(authority_index, a)| (local_index, authority_index, a.weight))
+            })
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            continue;
+        }
+
+        // Index within the epoch authorities of the authority allowed to claim the slot as a
+        // secondary slot.
+        let secondary_slot_author = verify::calculate_secondary_slot_author(
+            epoch.randomness,
+            slot_number,
+            epoch.authorities.len(),
+        );
+
+        let mut secondary_claim = None;
+
+        for (local_index, authority_index, weight) in candidates {
+            let authority_index_u32 = match u32::try_from(authority_index) {
+                Ok(i) => i,
+                Err(_) => continue,
+            };
+
+            // Both the main transcript and the "extra" transcript used internally to generate the
+            // proof need a source of randomness. Without one, schnorrkel would fall back to the
+            // system randomness, which isn't available.
+            let (vrf_in_out, vrf_proof, _) = local_keypairs[local_index].vrf_sign_extra(
+                schnorrkel::context::attach_rng(
+                    verify::vrf_transcript(epoch.randomness, slot_number, epoch.epoch_index),
+                    &mut rng,
+                ),
+                schnorrkel::context::attach_rng(merlin::Transcript::new(b"VRF"), &mut extra_rng),
+            );
+            let vrf_output = vrf_in_out.to_preout().to_bytes();
+            let vrf_proof = vrf_proof.to_bytes();
+
+            // Primary slot claim.
+            // The threshold calculation panics if the weight is 0, in which case the authority
+            // can never claim a primary slot anyway.
+            if weight != 0 {
+                let threshold = verify::calculate_primary_threshold(
+                    epoch.c,
+                    epoch.authorities.clone().map(|a| a.weight),
+                    weight,
+                );
+
+                if verify::vrf_output_to_u128(&vrf_in_out) < threshold {
+                    return Some(SlotClaim::new(
+                        config.slot_duration,
+                        slot_number,
+                        local_index,
+                        header::BabePreDigest::Primary(header::BabePrimaryPreDigest {
+                            authority_index: authority_index_u32,
+                            slot_number,
+                            vrf_output,
+                            vrf_proof,
+                        }),
+                    ));
+                }
+            }
+
+            // Secondary slot claim. Only kept for later, as a primary slot claim by another local
+            // authority is preferred.
+            if authority_index != secondary_slot_author || secondary_claim.is_some() {
+                continue;
+            }
+
+            secondary_claim = match epoch.allowed_slots {
+                header::BabeAllowedSlots::PrimarySlots => None,
+                header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots => Some((
+                    local_index,
+                    header::BabePreDigest::SecondaryPlain(header::BabeSecondaryPlainPreDigest {
+                        authority_index: authority_index_u32,
+                        slot_number,
+                    }),
+                )),
+                header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots => Some((
+                    local_index,
+                    header::BabePreDigest::SecondaryVRF(header::BabeSecondaryVRFPreDigest {
+                        authority_index: authority_index_u32,
+                        slot_number,
+                        vrf_output,
+                        vrf_proof,
+                    }),
+                )),
+            };
+        }
+
+        if let Some((local_index, pre_digest)) = secondary_claim {
+            return Some(SlotClaim::new(
+                config.slot_duration,
+                slot_number,
+                local_index,
+                pre_digest,
+            ));
+        }
+    }
+
+    None
+}
+
+/// Slot happening now or in the future and that can be attributed to one of the authorities in
+/// [`Config::local_authorities`].
+///
+/// See also [`next_slot_claim`].
+#[derive(Debug, Clone)]
+pub struct SlotClaim {
+    /// UNIX time when the slot starts. Can be inferior to the value passed to
+    /// [`Config::now_from_unix_epoch`] if the slot has already started.
+    pub slot_start_from_unix_epoch: Duration,
+    /// UNIX time when the slot ends. Always superior to the value passed to
+    /// [`Config::now_from_unix_epoch`].
+    pub slot_end_from_unix_epoch: Duration,
+    /// Slot number of the claim. Used when building the block.
+    pub slot_number: u64,
+    /// Index within [`Config::local_authorities`] of the key that can claim the slot.
+    pub local_authorities_index: usize,
+    /// Pre-runtime digest item to put in the header of the block.
+    pub pre_digest: header::BabePreDigest,
+}
+
+impl SlotClaim {
+    fn new(
+        slot_duration: NonZeroU64,
+        slot_number: u64,
+        local_authorities_index: usize,
+        pre_digest: header::BabePreDigest,
+    ) -> Self {
+        let slot_start_from_unix_epoch =
+            Duration::from_millis(slot_number.saturating_mul(slot_duration.get()));
+        let slot_end_from_unix_epoch =
+            slot_start_from_unix_epoch + Duration::from_millis(slot_duration.get());
+
+        SlotClaim {
+            slot_start_from_unix_epoch,
+            slot_end_from_unix_epoch,
+            slot_number,
+            local_authorities_index,
+            pre_digest,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::chain_information::BabeEpochInformation;
+
+    fn epoch(
+        epoch_index: u64,
+        start_slot_number: Option<u64>,
+        authorities: &[[u8; 32]],
+        c: (u64, u64),
+        allowed_slots: header::BabeAllowedSlots,
+    ) -> BabeEpochInformation {
+        BabeEpochInformation {
+            epoch_index,
+            start_slot_number,
+            authorities: authorities
+                .iter()
+                .map(|pk| header::BabeAuthority {
+                    public_key: *pk,
+                    weight: 1,
+                })
+                .collect(),
+            randomness: [7; 32],
+            c,
+            allowed_slots,
+        }
+    }
+
+    fn public_key(seed: &[u8; 32]) -> [u8; 32] {
+        schnorrkel::MiniSecretKey::from_bytes(&seed[..])
+            .unwrap()
+            .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+            .public
+            .to_bytes()
+    }
+
+    #[test]
+    fn claims_verify_with_secondary_plain() {
+        let seed = [1; 32];
+        let next_epoch = epoch(
+            0,
+            None,
+            &[public_key(&seed), [0; 32], [1; 32]],
+            (1, 4),
+            header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots,
+        );
+
+        let claim = next_slot_claim(Config {
+            now_from_unix_epoch: Duration::from_secs(6000),
+            slot_duration: NonZeroU64::new(6000).unwrap(),
+            slots_per_epoch: NonZeroU64::new(200).unwrap(),
+            parent_slot_number: None,
+            parent_block_epoch: None,
+            parent_block_next_epoch: (&next_epoch).into(),
+            local_authorities: [seed].iter(),
+            randomness_seed: [0; 32],
+        })
+        .unwrap();
+
+        assert!(claim.slot_number >= 1000);
+        assert!(claim.slot_number < 1200);
+        assert_eq!(claim.local_authorities_index, 0);
+        assert_eq!(
+            claim.slot_start_from_unix_epoch,
+            Duration::from_millis(claim.slot_number * 6000)
+        );
+
+        match &claim.pre_digest {
+            header::BabePreDigest::Primary(digest) => {
+                assert_eq!(digest.authority_index, 0);
+                let keypair = schnorrkel::MiniSecretKey::from_bytes(&seed[..])
+                    .unwrap()
+                    .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519);
+                let (in_out, _) = keypair
+                    .public
+                    .vrf_verify(
+                        verify::vrf_transcript(&[7; 32], claim.slot_number, 0),
+                        &schnorrkel::vrf::VRFPreOut::from_bytes(&digest.vrf_output).unwrap(),
+                        &schnorrkel::vrf::VRFProof::from_bytes(&digest.vrf_proof).unwrap(),
+                    )
+                    .unwrap();
+                let threshold =
+                    verify::calculate_primary_threshold((1, 4), [1, 1, 1].iter().copied(), 1);
+                assert!(verify::vrf_output_to_u128(&in_out) < threshold);
+            }
+            header::BabePreDigest::SecondaryPlain(digest) => {
+                assert_eq!(digest.authority_index, 0);
+                assert_eq!(
+                    verify::calculate_secondary_slot_author(&[7; 32], claim.slot_number, 3),
+                    0
+                );
+            }
+            header::BabePreDigest::SecondaryVRF(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn no_claim_if_not_authority() {
+        let next_epoch = epoch(
+            0,
+            None,
+            &[[0; 32], [1; 32]],
+            (1, 4),
+            header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        );
+
+        let claim = next_slot_claim(Config {
+            now_from_unix_epoch: Duration::from_secs(6000),
+            slot_duration: NonZeroU64::new(6000).unwrap(),
+            slots_per_epoch: NonZeroU64::new(200).unwrap(),
+            parent_slot_number: None,
+            parent_block_epoch: None,
+            parent_block_next_epoch: (&next_epoch).into(),
+            local_authorities: [[1; 32]].iter(),
+            randomness_seed: [0; 32],
+        });
+
+        assert!(claim.is_none());
+    }
+
+    #[test]
+    fn slot_after_parent() {
+        let seed = [3; 32];
+        let current_epoch = epoch(
+            4,
+            Some(900),
+            &[public_key(&seed)],
+            (1, 4),
+            header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        );
+        let next_epoch = epoch(
+            5,
+            Some(1100),
+            &[public_key(&seed)],
+            (1, 4),
+            header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        );
+
+        // The single authority is always allowed to claim secondary slots. As such, the claimed
+        // slot is always the one right after the parent.
+        let claim = next_slot_claim(Config {
+            now_from_unix_epoch: Duration::from_secs(6000),
+            slot_duration: NonZeroU64::new(6000).unwrap(),
+            slots_per_epoch: NonZeroU64::new(200).unwrap(),
+            parent_slot_number: Some(1050),
+            parent_block_epoch: Some((&current_epoch).into()),
+            parent_block_next_epoch: (&next_epoch).into(),
+            local_authorities: [seed].iter(),
+            randomness_seed: [0; 32],
+        })
+        .unwrap();
+
+        assert_eq!(claim.slot_number, 1051);
+        assert!(matches!(
+            claim.pre_digest,
+            header::BabePreDigest::Primary(_) | header::BabePreDigest::SecondaryVRF(_)
+        ));
+    }
+}
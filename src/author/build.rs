@@ -18,7 +18,8 @@
 // TODO: docs
 
 use crate::{
-    author::{aura, runtime},
+    author::{aura, babe, runtime},
+    chain::chain_information,
     executor::host,
     header,
     trie::calculate_root,
@@ -54,7 +55,39 @@ pub enum ConfigConsensus<'a, TLocAuth> {
         /// Must implement `Iterator<Item = &[u8; 32]>`.
         local_authorities: TLocAuth,
     },
-    // TODO: Babe isn't supported yet
+
+    /// Chain is using the Babe consensus algorithm.
+    Babe {
+        /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
+        /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
+        now_from_unix_epoch: Duration,
+
+        /// Duration, in milliseconds, of a Babe slot.
+        slot_duration: NonZeroU64,
+
+        /// Number of slots per epoch in the Babe configuration.
+        slots_per_epoch: NonZeroU64,
+
+        /// Slot number of the parent of the block to generate. Must be `None` if and only if the
+        /// parent is the genesis block.
+        parent_slot_number: Option<u64>,
+
+        /// Epoch the parent of the block to generate belongs to. Must be `None` if and only if
+        /// the parent is the genesis block.
+        parent_block_epoch: Option<chain_information::BabeEpochInformationRef<'a>>,
+
+        /// Epoch that follows the epoch the parent of the block to generate belongs to.
+        parent_block_next_epoch: chain_information::BabeEpochInformationRef<'a>,
+
+        /// Iterator to the list of sr25519 secret seeds of the authorities available locally.
+        /// Contrary to Aura, secret keys are necessary in order to generate VRF outputs.
+        ///
+        /// Must implement `Iterator<Item = &[u8; 32]>`.
+        local_authorities: TLocAuth,
+
+        /// Seed used for the randomness necessary to generate VRF proofs.
+        randomness_seed: [u8; 32],
+    },
 }
 
 /// Current state of the block building process.
@@ -102,6 +135,35 @@ impl Builder {
 
                 (WaitSlotConsensus::Aura(consensus), ready)
             }
+            ConfigConsensus::Babe {
+                now_from_unix_epoch,
+                slot_duration,
+                slots_per_epoch,
+                parent_slot_number,
+                parent_block_epoch,
+                parent_block_next_epoch,
+                local_authorities,
+                randomness_seed,
+            } => {
+                let consensus = match babe::next_slot_claim(babe::Config {
+                    now_from_unix_epoch,
+                    slot_duration,
+                    slots_per_epoch,
+                    parent_slot_number,
+                    parent_block_epoch,
+                    parent_block_next_epoch,
+                    local_authorities,
+                    randomness_seed,
+                }) {
+                    Some(c) => c,
+                    None => return Builder::Idle,
+                };
+
+                debug_assert!(now_from_unix_epoch < consensus.slot_end_from_unix_epoch);
+                let ready = now_from_unix_epoch >= consensus.slot_start_from_unix_epoch;
+
+                (WaitSlotConsensus::Babe(consensus), ready)
+            }
         };
 
         if ready {
@@ -162,6 +224,7 @@ pub struct WaitSlot {
 #[derive(Debug)]
 enum WaitSlotConsensus {
     Aura(aura::SlotClaim),
+    Babe(babe::SlotClaim),
 }
 
 impl WaitSlot {
@@ -169,8 +232,9 @@ impl WaitSlot {
     /// the UNIX epoch, ignoring leap seconds).
     pub fn when(&self) -> Duration {
         // TODO: we can actually start building the block before our slot in some situations?
-        match self.consensus {
+        match &self.consensus {
             WaitSlotConsensus::Aura(claim) => claim.slot_start_from_unix_epoch,
+            WaitSlotConsensus::Babe(claim) => claim.slot_start_from_unix_epoch,
         }
     }
 
@@ -199,22 +263,28 @@ impl AuthoringStart {
             parent_number: config.parent_number,
             parent_runtime: config.parent_runtime,
             top_trie_root_calculation_cache: config.top_trie_root_calculation_cache,
-            consensus_digest_log_item: match self.consensus {
+            consensus_digest_log_item: match &self.consensus {
                 WaitSlotConsensus::Aura(slot) => {
                     runtime::ConfigPreRuntime::Aura(header::AuraPreDigest {
                         slot_number: slot.slot_number,
                     })
                 }
+                WaitSlotConsensus::Babe(slot) => {
+                    runtime::ConfigPreRuntime::Babe((&slot.pre_digest).into())
+                }
             },
         });
 
         let inherent_data = runtime::InherentData {
             timestamp: u64::try_from(config.now_from_unix_epoch.as_millis())
                 .unwrap_or(u64::max_value()),
-            consensus: match self.consensus {
+            consensus: match &self.consensus {
                 WaitSlotConsensus::Aura(slot) => runtime::InherentDataConsensus::Aura {
                     slot_number: slot.slot_number,
                 },
+                WaitSlotConsensus::Babe(slot) => runtime::InherentDataConsensus::Babe {
+                    slot_number: slot.slot_number,
+                },
             },
         };
 
@@ -346,18 +416,30 @@ pub struct Seal {
 }
 
 impl Seal {
-    /// Returns the SCALE-encoded header that must be signed.
+    /// Returns the SCALE-encoded header whose hash must be signed.
+    ///
+    /// See also [`Seal::to_sign`].
     pub fn scale_encoded_header(&self) -> &[u8] {
         &self.block.scale_encoded_header
     }
 
+    /// Returns the hash that must be signed by the authority in order to seal the block. This is
+    /// the hash of the value returned by [`Seal::scale_encoded_header`].
+    ///
+    /// The signature must be an sr25519 signature using the `substrate` signing context.
+    pub fn to_sign(&self) -> [u8; 32] {
+        header::hash_from_scale_encoded_header(&self.block.scale_encoded_header)
+    }
+
     /// Returns the index within the list of authorities of the authority that must sign the
     /// block.
     ///
-    /// See [`ConfigConsensus::Aura::local_authorities`].
+    /// See [`ConfigConsensus::Aura::local_authorities`] and
+    /// [`ConfigConsensus::Babe::local_authorities`].
     pub fn authority_index(&self) -> usize {
-        match self.shared.slot_claim {
+        match &self.shared.slot_claim {
             WaitSlotConsensus::Aura(slot) => slot.local_authorities_index,
+            WaitSlotConsensus::Babe(slot) => slot.local_authorities_index,
         }
     }
 
@@ -370,9 +452,13 @@ impl Seal {
             .unwrap()
             .into();
 
-        // `push_aura_seal` error if there is already an Aura seal, indicating that the runtime
-        // code is misbehaving. This condition is already verified when the `Seal` is created.
-        header.digest.push_aura_seal(signature).unwrap();
+        // `push_aura_seal` and `push_babe_seal` error if there is already a seal, indicating
+        // that the runtime code is misbehaving. This condition is already verified when the
+        // `Seal` is created.
+        match self.shared.slot_claim {
+            WaitSlotConsensus::Aura(_) => header.digest.push_aura_seal(signature).unwrap(),
+            WaitSlotConsensus::Babe(_) => header.digest.push_babe_seal(signature).unwrap(),
+        }
 
         self.block.scale_encoded_header = header.scale_encoding().fold(Vec::new(), |mut a, b| {
             a.extend_from_slice(b.as_ref());
@@ -50,6 +50,7 @@ mod tests;
 use crate::{
     executor::{host, runtime_host},
     header,
+    transactions::validate,
     trie::calculate_root,
     util,
 };
@@ -58,6 +59,8 @@ use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
 use core::{iter, mem};
 use hashbrown::HashMap;
 
+pub use validate::{InvalidTransaction, TransactionValidityError, UnknownTransaction};
+
 /// Configuration for a block generation.
 pub struct Config<'a> {
     /// Hash of the parent of the block to generate.
@@ -743,67 +746,6 @@ fn parse_apply_extrinsic_output(
         .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| Error::BadApplyExtrinsicOutput)
 }
 
-// TODO: some parsers below are common with the tx-pool ; figure out how/whether they should be merged
-
-/// Errors that can occur while checking the validity of a transaction.
-#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
-pub enum TransactionValidityError {
-    /// The transaction is invalid.
-    Invalid(InvalidTransaction),
-    /// Transaction validity can't be determined.
-    Unknown(UnknownTransaction),
-}
-
-/// An invalid transaction validity.
-#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
-pub enum InvalidTransaction {
-    /// The call of the transaction is not expected.
-    Call,
-    /// General error to do with the inability to pay some fees (e.g. account balance too low).
-    Payment,
-    /// General error to do with the transaction not yet being valid (e.g. nonce too high).
-    Future,
-    /// General error to do with the transaction being outdated (e.g. nonce too low).
-    Stale,
-    /// General error to do with the transaction's proofs (e.g. signature).
-    ///
-    /// # Possible causes
-    ///
-    /// When using a signed extension that provides additional data for signing, it is required
-    /// that the signing and the verifying side use the same additional data. Additional
-    /// data will only be used to generate the signature, but will not be part of the transaction
-    /// itself. As the verifying side does not know which additional data was used while signing
-    /// it will only be able to assume a bad signature and cannot express a more meaningful error.
-    BadProof,
-    /// The transaction birth block is ancient.
-    AncientBirthBlock,
-    /// The transaction would exhaust the resources of current block.
-    ///
-    /// The transaction might be valid, but there are not enough resources
-    /// left in the current block.
-    ExhaustsResources,
-    /// Any other custom invalid validity that is not covered by this enum.
-    Custom(u8),
-    /// An extrinsic with a Mandatory dispatch resulted in Error. This is indicative of either a
-    /// malicious validator or a buggy `provide_inherent`. In any case, it can result in dangerously
-    /// overweight blocks and therefore if found, invalidates the block.
-    BadMandatory,
-    /// A transaction with a mandatory dispatch. This is invalid; only inherent extrinsics are
-    /// allowed to have mandatory dispatches.
-    MandatoryDispatch,
-}
-
-/// An unknown transaction validity.
-#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
-pub enum UnknownTransaction {
-    /// Could not lookup some information that is required to validate the transaction.
-    CannotLookup,
-    /// No validator found for the given unsigned transaction.
-    NoUnsignedValidator,
-    /// Any other custom unknown validity that is not covered by this enum.
-    Custom(u8),
-}
-
 /// Reason why a dispatch call failed.
 #[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
 pub enum DispatchError {
@@ -834,7 +776,7 @@ fn apply_extrinsic_result(
             nom::combinator::map(
                 nom::sequence::preceded(
                     nom::bytes::complete::tag(&[1]),
-                    transaction_validity_error,
+                    validate::transaction_validity_error,
                 ),
                 Err,
             ),
@@ -875,82 +817,3 @@ fn dispatch_error(bytes: &[u8]) -> nom::IResult<&[u8], DispatchError> {
         )),
     )(bytes)
 }
-
-fn transaction_validity_error(bytes: &[u8]) -> nom::IResult<&[u8], TransactionValidityError> {
-    nom::error::context(
-        "transaction validity error",
-        nom::branch::alt((
-            nom::combinator::map(
-                nom::sequence::preceded(nom::bytes::complete::tag(&[0]), invalid_transaction),
-                TransactionValidityError::Invalid,
-            ),
-            nom::combinator::map(
-                nom::sequence::preceded(nom::bytes::complete::tag(&[1]), unknown_transaction),
-                TransactionValidityError::Unknown,
-            ),
-        )),
-    )(bytes)
-}
-
-fn invalid_transaction(bytes: &[u8]) -> nom::IResult<&[u8], InvalidTransaction> {
-    nom::error::context(
-        "invalid transaction",
-        nom::branch::alt((
-            nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| {
-                InvalidTransaction::Call
-            }),
-            nom::combinator::map(nom::bytes::complete::tag(&[1]), |_| {
-                InvalidTransaction::Payment
-            }),
-            nom::combinator::map(nom::bytes::complete::tag(&[2]), |_| {
-                InvalidTransaction::Future
-            }),
-            nom::combinator::map(nom::bytes::complete::tag(&[3]), |_| {
-                InvalidTransaction::Stale
-            }),
-            nom::combinator::map(nom::bytes::complete::tag(&[4]), |_| {
-                InvalidTransaction::BadProof
-            }),
-            nom::combinator::map(nom::bytes::complete::tag(&[5]), |_| {
-                InvalidTransaction::AncientBirthBlock
-            }),
-            nom::combinator::map(nom::bytes::complete::tag(&[6]), |_| {
-                InvalidTransaction::ExhaustsResources
-            }),
-            nom::combinator::map(
-                nom::sequence::preceded(
-                    nom::bytes::complete::tag(&[7]),
-                    nom::bytes::complete::take(1u32),
-                ),
-                |n: &[u8]| InvalidTransaction::Custom(n[0]),
-            ),
-            nom::combinator::map(nom::bytes::complete::tag(&[8]), |_| {
-                InvalidTransaction::BadMandatory
-            }),
-            nom::combinator::map(nom::bytes::complete::tag(&[9]), |_| {
-                InvalidTransaction::MandatoryDispatch
-            }),
-        )),
-    )(bytes)
-}
-
-fn unknown_transaction(bytes: &[u8]) -> nom::IResult<&[u8], UnknownTransaction> {
-    nom::error::context(
-        "unknown transaction",
-        nom::branch::alt((
-            nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| {
-                UnknownTransaction::CannotLookup
-            }),
-            nom::combinator::map(nom::bytes::complete::tag(&[1]), |_| {
-                UnknownTransaction::NoUnsignedValidator
-            }),
-            nom::combinator::map(
-                nom::sequence::preceded(
-                    nom::bytes::complete::tag(&[2]),
-                    nom::bytes::complete::take(1u32),
-                ),
-                |n: &[u8]| UnknownTransaction::Custom(n[0]),
-            ),
-        )),
-    )(bytes)
-}
@@ -57,24 +57,37 @@
 //!
 //! Additionally, a [`NonFinalizedTree::verify_justification`] method is provided in order to
 //! verify the correctness of a [justification](crate::finality::justification).
+//!
+//! # Bad blocks and forced forks
+//!
+//! The [`Config`] can contain a list of blocks that are known to be bad and that must never be
+//! part of the chain, and a list of blocks that must be part of the chain at a certain height.
+//! These lists are typically found in the chain specification and are used in order to recover
+//! from incidents on the chain. Verifying a block that is marked as bad, or that is at the height
+//! of a forced block but doesn't match its hash, fails with [`HeaderVerifyError::BadBlock`] or
+//! [`BodyVerifyStep1::BadBlock`].
 
 // TODO: expand this doc ^
 // TODO: this module is an essential part of the code and needs clean up and testing
 
 use crate::{
     chain::{chain_information, fork_tree},
-    finality::justification,
+    finality::{grandpa, justification},
     header,
 };
 
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
 use core::{cmp, convert::TryFrom as _, fmt, mem, num::NonZeroU64, time::Duration};
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 
 mod best_block;
+mod equivocation;
 mod finality;
+mod tests;
 mod verify;
 
+pub use self::best_block::ForkChoice;
+pub use self::equivocation::{EquivocationConsensusEngine, EquivocationProof};
 pub use self::finality::*;
 pub use self::verify::*;
 
@@ -86,6 +99,17 @@ pub struct Config {
 
     /// Pre-allocated size of the chain, in number of non-finalized blocks.
     pub blocks_capacity: usize,
+
+    /// List of hashes of blocks that must never be part of the chain.
+    pub bad_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes. At each of these heights, only the block with the
+    /// corresponding hash can be part of the chain. Used to force the chain onto a fork.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
+    /// Rule used to determine the best block of the chain. If `None`, the rule is derived from
+    /// the consensus algorithm of the chain using [`ForkChoice::from_consensus`].
+    pub fork_choice: Option<ForkChoice>,
 }
 
 /// Holds state about the current state of the chain for the purpose of verifying headers.
@@ -143,6 +167,9 @@ impl<T> NonFinalizedTree<T> {
         // TODO: also check that babe_finalized_block_epoch_information is None if and only if block is in epoch #0
 
         let finalized_block_hash = config.chain_information.finalized_block_header.hash();
+        let fork_choice = config
+            .fork_choice
+            .unwrap_or_else(|| ForkChoice::from_consensus(&config.chain_information.consensus));
 
         NonFinalizedTree {
             inner: Some(NonFinalizedTreeInner {
@@ -181,8 +208,12 @@ impl<T> NonFinalizedTree<T> {
                         next_epoch_transition: Arc::new(finalized_next_epoch_transition),
                     },
                 },
+                fork_choice,
                 blocks: fork_tree::ForkTree::with_capacity(config.blocks_capacity),
                 current_best: None,
+                bad_blocks: config.bad_blocks.into_iter().collect(),
+                fork_blocks: config.fork_blocks.into_iter().collect(),
+                slot_claims: BTreeMap::new(),
             }),
         }
     }
@@ -327,12 +358,22 @@ struct NonFinalizedTreeInner<T> {
 
     /// State of the consensus of the finalized block.
     finalized_consensus: FinalizedConsensus,
+    /// Rule used to determine the best block. See [`Config::fork_choice`].
+    fork_choice: ForkChoice,
 
     /// Container for non-finalized blocks.
     blocks: fork_tree::ForkTree<Block<T>>,
     /// Index within [`NonFinalizedTreeInner::blocks`] of the current best block. `None` if and
     /// only if the fork tree is empty.
     current_best: Option<fork_tree::NodeIndex>,
+    /// See [`Config::bad_blocks`].
+    bad_blocks: HashSet<[u8; 32], fnv::FnvBuildHasher>,
+    /// See [`Config::fork_blocks`]. Keys are block heights and values are block hashes.
+    fork_blocks: HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+    /// Headers of the blocks that have been inserted in the tree, indexed by slot number and
+    /// public key of the authority that has claimed the slot. Used in order to detect
+    /// equivocations. Entries are removed when the finalized block moves past their slot.
+    slot_claims: BTreeMap<(u64, [u8; 32]), header::Header>,
 }
 
 /// State of the consensus of the finalized block.
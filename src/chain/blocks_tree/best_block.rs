@@ -16,15 +16,109 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 //! Extension module containing the best block determination.
+//!
+//! All the blocks stored in a [`NonFinalizedTree`] are descendants of the latest finalized
+//! block. The best block is therefore always the best descendant of the latest finalized block,
+//! according to the [`ForkChoice`] rule of the tree.
 
 use super::*;
 
+/// Rule used to determine which block of the [`NonFinalizedTree`] is the best block.
+///
+/// Whatever the rule, a block is always preferred to its ancestors, and the current best block
+/// is kept in case of equality.
+///
+/// When the finalized block is updated and the current best block isn't a descendant of the new
+/// finalized block, a new best block is chosen amongst the descendants of the new finalized
+/// block according to the same rule.
+///
+/// GRANDPA finality isn't part of the rule itself. Blocks that aren't descendants of the latest
+/// finalized block are removed from the tree, and can thus never be the best block, but a chain
+/// isn't preferred over another because it contains a block that is close to being finalized.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ForkChoice {
+    /// The block with the highest height is the best block.
+    ///
+    /// This is the rule used by Aura chains.
+    LongestChain,
+
+    /// The chain containing the highest number of blocks claimed in a Babe primary slot is the
+    /// best chain. If two chains have the same number of primary slot claims, the longest one is
+    /// preferred.
+    ///
+    /// Blocks that don't contain any Babe pre-runtime digest, such as blocks produced before a
+    /// chain has switched to Babe, don't count as primary slot claims but are still taken into
+    /// account when comparing the length of the chains.
+    BabePrimaryWeight,
+}
+
+impl ForkChoice {
+    /// Returns the fork choice rule appropriate for the given consensus algorithm.
+    pub fn from_consensus(consensus: &chain_information::ChainInformationConsensus) -> Self {
+        match consensus {
+            chain_information::ChainInformationConsensus::AllAuthorized
+            | chain_information::ChainInformationConsensus::Aura { .. } => ForkChoice::LongestChain,
+            chain_information::ChainInformationConsensus::Babe { .. } => {
+                ForkChoice::BabePrimaryWeight
+            }
+        }
+    }
+
+    /// Returns true if `candidate` is strictly heavier than `current`, where both weights are
+    /// calculated starting from the same common ancestor.
+    fn is_heavier(&self, candidate: &ChainWeight, current: &ChainWeight) -> bool {
+        match self {
+            ForkChoice::LongestChain => candidate.num_blocks > current.num_blocks,
+            ForkChoice::BabePrimaryWeight => {
+                (candidate.babe_primary_slots, candidate.num_blocks)
+                    > (current.babe_primary_slots, current.num_blocks)
+            }
+        }
+    }
+}
+
+/// Weight of a chain of blocks, starting from a certain ancestor.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+struct ChainWeight {
+    /// Number of blocks in the chain.
+    num_blocks: u64,
+    /// Number of blocks of the chain that have been claimed in a Babe primary slot.
+    babe_primary_slots: u64,
+}
+
+impl ChainWeight {
+    /// Builds the weight of the given list of blocks.
+    fn from_nodes<T>(
+        blocks: &fork_tree::ForkTree<Block<T>>,
+        nodes: impl Iterator<Item = fork_tree::NodeIndex>,
+    ) -> Self {
+        let mut weight = ChainWeight::default();
+        for node in nodes {
+            weight.add((&blocks.get(node).unwrap().header.digest).into());
+        }
+        weight
+    }
+
+    /// Adds a block with the given digest to the chain.
+    fn add(&mut self, digest: header::DigestRef) {
+        self.num_blocks += 1;
+        if digest
+            .babe_pre_runtime()
+            .map_or(false, |pr| pr.is_primary())
+        {
+            self.babe_primary_slots += 1;
+        }
+    }
+}
+
 /// Accepts as parameter a container of blocks and indices within this container.
 ///
 /// Returns true if `maybe_new_best` on top of `maybe_new_best_parent` is a better block compared
-/// to `old_best`.
+/// to `old_best`. A value of `None` for `maybe_new_best_parent` means that the parent is the
+/// latest finalized block.
 pub(super) fn is_better_block<T>(
     blocks: &fork_tree::ForkTree<Block<T>>,
+    fork_choice: ForkChoice,
     old_best: fork_tree::NodeIndex,
     maybe_new_best_parent: Option<fork_tree::NodeIndex>,
     maybe_new_best: header::HeaderRef,
@@ -36,71 +130,168 @@ pub(super) fn is_better_block<T>(
 
     if maybe_new_best_parent.map_or(false, |p_idx| blocks.is_ancestor(old_best, p_idx)) {
         // A descendant is always preferred to its ancestor.
-        true
-    } else {
-        // In order to determine whether the new block is our new best:
-        //
-        // - Find the common ancestor between the current best and the new block's parent.
-        // - Count the number of Babe primary slot claims between the common ancestor and
-        //   the current best.
-        // - Count the number of Babe primary slot claims between the common ancestor and
-        //   the new block's parent. Add one if the new block has a Babe primary slot
-        //   claim.
-        // - If the number for the new block is strictly superior, then the new block is
-        //   out new best.
-        //
-        let (ascend, descend) = blocks.ascend_and_descend(old_best, maybe_new_best_parent.unwrap());
-
-        // TODO: update for Aura?
-        // TODO: what if there's a mix of Babe and non-Babe blocks here?
-
-        let curr_best_primary_slots: usize = ascend
-            .map(|i| {
-                if blocks
-                    .get(i)
-                    .unwrap()
-                    .header
-                    .digest
-                    .babe_pre_runtime()
-                    .map_or(false, |pr| pr.is_primary())
-                {
-                    1
-                } else {
-                    0
-                }
-            })
-            .sum();
-
-        let new_block_primary_slots = {
-            if maybe_new_best
-                .digest
-                .babe_pre_runtime()
-                .map_or(false, |pr| pr.is_primary())
-            {
-                1
-            } else {
-                0
-            }
+        return true;
+    }
+
+    // In order to determine whether the new block is our new best:
+    //
+    // - Find the common ancestor between the current best and the new block's parent.
+    // - Calculate the weight of the chain between the common ancestor and the current best.
+    // - Calculate the weight of the chain between the common ancestor and the new block,
+    //   including the new block.
+    // - If the weight of the new block's chain is strictly superior, then the new block is our
+    //   new best.
+    let (curr_best_weight, mut new_block_weight) = match maybe_new_best_parent {
+        Some(parent) => {
+            let (ascend, descend) = blocks.ascend_and_descend(old_best, parent);
+            (
+                ChainWeight::from_nodes(blocks, ascend),
+                ChainWeight::from_nodes(blocks, descend),
+            )
+        }
+        None => (
+            ChainWeight::from_nodes(blocks, blocks.node_to_root_path(old_best)),
+            ChainWeight::default(),
+        ),
+    };
+    new_block_weight.add(maybe_new_best.digest);
+
+    fork_choice.is_heavier(&new_block_weight, &curr_best_weight)
+}
+
+/// Returns the best block amongst the strict descendants of `ancestor`, or `None` if `ancestor`
+/// doesn't have any descendant.
+pub(super) fn best_descendant<T>(
+    blocks: &fork_tree::ForkTree<Block<T>>,
+    fork_choice: ForkChoice,
+    ancestor: fork_tree::NodeIndex,
+) -> Option<fork_tree::NodeIndex> {
+    // Each descendant is visited exactly once, alongside with the weight of the chain between
+    // `ancestor` (excluded) and this descendant. Since all these weights are calculated starting
+    // from the same block, they can be compared with each other directly.
+    let mut best: Option<(fork_tree::NodeIndex, ChainWeight)> = None;
+    let mut to_visit = blocks
+        .children(Some(ancestor))
+        .map(|child| (child, ChainWeight::default()))
+        .collect::<Vec<_>>();
+
+    while let Some((node, mut weight)) = to_visit.pop() {
+        weight.add((&blocks.get(node).unwrap().header.digest).into());
+
+        let is_better = match best {
+            Some((_, best_weight)) => fork_choice.is_heavier(&weight, &best_weight),
+            None => true,
         };
+        if is_better {
+            best = Some((node, weight));
+        }
+
+        to_visit.extend(blocks.children(Some(node)).map(|child| (child, weight)));
+    }
+
+    best.map(|(node, _)| node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChainWeight, ForkChoice};
+    use crate::{chain::chain_information, header};
+
+    fn babe_primary() -> header::DigestItem {
+        header::DigestItem::BabePreDigest(header::BabePreDigest::Primary(
+            header::BabePrimaryPreDigest {
+                authority_index: 0,
+                slot_number: 1,
+                vrf_output: [0; 32],
+                vrf_proof: [0; 64],
+            },
+        ))
+    }
+
+    fn babe_secondary() -> header::DigestItem {
+        header::DigestItem::BabePreDigest(header::BabePreDigest::SecondaryPlain(
+            header::BabeSecondaryPlainPreDigest {
+                authority_index: 0,
+                slot_number: 1,
+            },
+        ))
+    }
+
+    fn aura() -> header::DigestItem {
+        header::DigestItem::AuraPreDigest(header::AuraPreDigest { slot_number: 1 })
+    }
+
+    fn weight(chain: &[Option<header::DigestItem>]) -> ChainWeight {
+        let mut weight = ChainWeight::default();
+        for item in chain {
+            let items = item.iter().cloned().collect::<Vec<_>>();
+            weight.add(header::DigestRef::from_slice(&items).unwrap());
+        }
+        weight
+    }
+
+    #[test]
+    fn from_consensus() {
+        assert_eq!(
+            ForkChoice::from_consensus(
+                &chain_information::ChainInformationConsensus::AllAuthorized
+            ),
+            ForkChoice::LongestChain
+        );
+        assert_eq!(
+            ForkChoice::from_consensus(&chain_information::ChainInformationConsensus::Aura {
+                finalized_authorities_list: Vec::new(),
+                slot_duration: core::num::NonZeroU64::new(6000).unwrap(),
+            }),
+            ForkChoice::LongestChain
+        );
+    }
+
+    #[test]
+    fn longest_chain_ignores_babe() {
+        let short = weight(&[Some(babe_primary()), Some(babe_primary())]);
+        let long = weight(&[Some(aura()), Some(aura()), Some(aura())]);
+        assert!(ForkChoice::LongestChain.is_heavier(&long, &short));
+        assert!(!ForkChoice::LongestChain.is_heavier(&short, &long));
+    }
+
+    #[test]
+    fn equal_weights_keep_current() {
+        let a = weight(&[Some(babe_primary()), Some(babe_secondary())]);
+        let b = weight(&[Some(babe_secondary()), Some(babe_primary())]);
+        assert!(!ForkChoice::LongestChain.is_heavier(&a, &b));
+        assert!(!ForkChoice::BabePrimaryWeight.is_heavier(&a, &b));
+        assert!(!ForkChoice::BabePrimaryWeight.is_heavier(&b, &a));
+    }
+
+    #[test]
+    fn babe_primary_slots_win_over_length() {
+        let primary = weight(&[Some(babe_primary()), Some(babe_primary())]);
+        let secondary = weight(&[
+            Some(babe_secondary()),
+            Some(babe_secondary()),
+            Some(babe_secondary()),
+        ]);
+        assert!(ForkChoice::BabePrimaryWeight.is_heavier(&primary, &secondary));
+        assert!(!ForkChoice::BabePrimaryWeight.is_heavier(&secondary, &primary));
+    }
+
+    #[test]
+    fn mixed_engines_transition_to_babe() {
+        // Chain that switches from Aura to Babe, compared with a chain that stays on Aura for
+        // longer. Aura blocks don't count as primary slot claims.
+        let switched = weight(&[Some(aura()), Some(babe_primary())]);
+        let not_switched = weight(&[Some(aura()), Some(aura()), Some(aura())]);
+        assert!(ForkChoice::BabePrimaryWeight.is_heavier(&switched, &not_switched));
+        assert!(ForkChoice::LongestChain.is_heavier(&not_switched, &switched));
+    }
 
-        let parent_primary_slots: usize = descend
-            .map(|i| {
-                if blocks
-                    .get(i)
-                    .unwrap()
-                    .header
-                    .digest
-                    .babe_pre_runtime()
-                    .map_or(false, |pr| pr.is_primary())
-                {
-                    1
-                } else {
-                    0
-                }
-            })
-            .sum();
-
-        // Note the strictly superior. If there is an equality, we keep the current best.
-        parent_primary_slots + new_block_primary_slots > curr_best_primary_slots
+    #[test]
+    fn mixed_engines_no_primary_falls_back_to_length() {
+        // No primary slot claim at all on either side: the length of the chain decides.
+        let short = weight(&[None, Some(babe_secondary())]);
+        let long = weight(&[None, Some(aura()), Some(babe_secondary())]);
+        assert!(ForkChoice::BabePrimaryWeight.is_heavier(&long, &short));
+        assert!(!ForkChoice::BabePrimaryWeight.is_heavier(&short, &long));
     }
 }
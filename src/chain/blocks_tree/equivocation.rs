@@ -0,0 +1,179 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Extension module containing the detection of equivocations.
+//!
+//! With the Aura and Babe consensus algorithms, an authority is allowed to produce at most one
+//! block per slot. An authority that produces two different blocks in the same slot is said to
+//! *equivocate*, and can be reported to the runtime in order to be punished.
+//!
+//! The [`NonFinalizedTree`] keeps track of the slots claimed by the blocks that are inserted in
+//! it, including blocks that have later been discarded because they were on a fork that has been
+//! abandoned. When a block that claims a slot already claimed by another block of the same
+//! authority is verified, an [`EquivocationProof`] containing both headers is generated.
+//!
+//! The list of claimed slots is cleaned up when the finalized block is updated. Equivocations
+//! involving a block whose slot is anterior to the one of the latest finalized block are no
+//! longer detected.
+
+use super::*;
+
+/// Proof that an authority has produced two different blocks in the same slot.
+#[derive(Debug, Clone)]
+pub struct EquivocationProof {
+    /// Consensus algorithm the equivocation is about.
+    pub consensus_engine: EquivocationConsensusEngine,
+    /// Public key of the authority that has equivocated.
+    pub offender: [u8; 32],
+    /// Slot number both blocks belong to.
+    pub slot_number: u64,
+    /// Header of the block that has been inserted in the tree first.
+    pub first_header: header::Header,
+    /// Header of the block that has been verified the most recently.
+    pub second_header: header::Header,
+}
+
+impl EquivocationProof {
+    /// Returns the SCALE encoding of the proof.
+    ///
+    /// For Babe chains, this encoding is the `equivocation_proof` parameter expected by the
+    /// `BabeApi_submit_report_equivocation_unsigned_extrinsic` runtime function. The other
+    /// parameter of this function, the proof of ownership of the key of the offender, can be
+    /// obtained by calling the `BabeApi_generate_key_ownership_proof` runtime function with
+    /// [`EquivocationProof::slot_number`] and [`EquivocationProof::offender`].
+    pub fn scale_encoding_vec(&self) -> Vec<u8> {
+        let first_header = self.first_header.scale_encoding_vec();
+        let second_header = self.second_header.scale_encoding_vec();
+
+        let mut out = Vec::with_capacity(32 + 8 + first_header.len() + second_header.len());
+        out.extend_from_slice(&self.offender);
+        out.extend_from_slice(&self.slot_number.to_le_bytes());
+        out.extend_from_slice(&first_header);
+        out.extend_from_slice(&second_header);
+        out
+    }
+}
+
+/// Consensus algorithm an [`EquivocationProof`] is about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EquivocationConsensusEngine {
+    /// Both blocks have been produced using the Aura consensus algorithm.
+    Aura,
+    /// Both blocks have been produced using the Babe consensus algorithm.
+    Babe,
+}
+
+/// Slot claimed by a block that has been successfully verified.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct SlotClaim {
+    /// Consensus algorithm of the block.
+    pub(super) consensus_engine: EquivocationConsensusEngine,
+    /// Slot number the block belongs to.
+    pub(super) slot_number: u64,
+    /// Public key of the authority that has signed the block.
+    pub(super) authority_public_key: [u8; 32],
+}
+
+impl<T> NonFinalizedTreeInner<T> {
+    /// Returns an [`EquivocationProof`] if the given slot claim of the given header conflicts with
+    /// a block that has been inserted in the tree in the past.
+    pub(super) fn find_equivocation(
+        &self,
+        claim: &SlotClaim,
+        header: &header::Header,
+    ) -> Option<EquivocationProof> {
+        let first_header = self
+            .slot_claims
+            .get(&(claim.slot_number, claim.authority_public_key))?;
+
+        if first_header.hash() == header.hash() {
+            return None;
+        }
+
+        Some(EquivocationProof {
+            consensus_engine: claim.consensus_engine,
+            offender: claim.authority_public_key,
+            slot_number: claim.slot_number,
+            first_header: first_header.clone(),
+            second_header: header.clone(),
+        })
+    }
+
+    /// Registers the slot claimed by a block that is being inserted in the tree.
+    ///
+    /// If the slot was already claimed by the same authority, the header that was registered
+    /// first is kept.
+    pub(super) fn register_slot_claim(&mut self, claim: &SlotClaim, header: &header::Header) {
+        self.slot_claims
+            .entry((claim.slot_number, claim.authority_public_key))
+            .or_insert_with(|| header.clone());
+    }
+
+    /// Removes from the list of claimed slots the entries whose slot is strictly inferior to the
+    /// slot of the latest finalized block.
+    pub(super) fn prune_slot_claims(&mut self) {
+        let digest = &self.finalized_block_header.digest;
+        let finalized_slot_number = if let Some(pre_runtime) = digest.babe_pre_runtime() {
+            pre_runtime.slot_number()
+        } else if let Some(pre_runtime) = header::DigestRef::from(digest).aura_pre_runtime() {
+            pre_runtime.slot_number
+        } else {
+            return;
+        };
+
+        self.slot_claims = self
+            .slot_claims
+            .split_off(&(finalized_slot_number, [0; 32]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EquivocationConsensusEngine, EquivocationProof};
+    use crate::header;
+
+    #[test]
+    fn proof_scale_encoding() {
+        let first_header = header::Header {
+            parent_hash: [1; 32],
+            number: 5,
+            state_root: [2; 32],
+            extrinsics_root: [3; 32],
+            digest: header::DigestRef::empty().into(),
+        };
+        let second_header = header::Header {
+            state_root: [4; 32],
+            ..first_header.clone()
+        };
+
+        let proof = EquivocationProof {
+            consensus_engine: EquivocationConsensusEngine::Babe,
+            offender: [0xaa; 32],
+            slot_number: 0x0102,
+            first_header: first_header.clone(),
+            second_header: second_header.clone(),
+        };
+
+        let encoded = proof.scale_encoding_vec();
+        assert_eq!(&encoded[..32], &[0xaa; 32][..]);
+        assert_eq!(&encoded[32..40], &[0x02, 0x01, 0, 0, 0, 0, 0, 0][..]);
+
+        let (decoded_first, remain) = header::decode_partial(&encoded[40..]).unwrap();
+        assert_eq!(decoded_first.hash(), first_header.hash());
+        assert_eq!(header::decode(remain).unwrap().hash(), second_header.hash());
+    }
+}
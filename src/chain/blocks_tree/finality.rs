@@ -183,7 +183,7 @@ impl<T> NonFinalizedTreeInner<T> {
                 // As per above check, we know that the authorities of the target block are either the
                 // same as the ones of the latest finalized block, or the ones contained in the header of
                 // the latest finalized block.
-                justification::verify::verify(justification::verify::Config {
+                let equivocations = justification::verify::verify(justification::verify::Config {
                     justification: decoded,
                     authorities_set_id: *after_finalized_block_authorities_set_id,
                     authorities_list: authorities_list.iter().map(|a| a.public_key),
@@ -194,6 +194,7 @@ impl<T> NonFinalizedTreeInner<T> {
                 Ok(JustificationApply {
                     chain: self,
                     to_finalize: block_index,
+                    equivocations,
                 })
             }
         }
@@ -254,6 +255,7 @@ impl<T> NonFinalizedTreeInner<T> {
             &mut self.finalized_consensus,
             &new_finalized_block.consensus,
         ) {
+            (FinalizedConsensus::AllAuthorized, BlockConsensus::AllAuthorized) => {}
             (
                 FinalizedConsensus::Aura {
                     authorities_list, ..
@@ -288,10 +290,22 @@ impl<T> NonFinalizedTreeInner<T> {
             &mut new_finalized_block.header,
         );
         self.finalized_block_hash = self.finalized_block_header.hash();
+        self.prune_slot_claims();
+
+        // If the current best block isn't a descendant of the new finalized block, it is about to
+        // be pruned and a new best block is chosen amongst the descendants of the new finalized
+        // block. This is done before pruning, as the node indices of the blocks that aren't
+        // removed remain valid.
+        self.current_best = match self.current_best {
+            Some(best) if best != block_index && self.blocks.is_ancestor(block_index, best) => {
+                Some(best)
+            }
+            _ => best_block::best_descendant(&self.blocks, self.fork_choice, block_index),
+        };
 
         SetFinalizedBlockIter {
             iter: self.blocks.prune_ancestors(block_index),
-            current_best: &mut self.current_best,
+            current_best: self.current_best,
         }
     }
 }
@@ -304,6 +318,7 @@ impl<T> NonFinalizedTreeInner<T> {
 pub struct JustificationApply<'c, T> {
     chain: &'c mut NonFinalizedTreeInner<T>,
     to_finalize: fork_tree::NodeIndex,
+    equivocations: Vec<grandpa::equivocation::EquivocationProof>,
 }
 
 impl<'c, T> JustificationApply<'c, T> {
@@ -325,6 +340,12 @@ impl<'c, T> JustificationApply<'c, T> {
             .user_data
     }
 
+    /// Returns the proofs of the equivocations found in the justification. Equivocations don't
+    /// make a justification invalid, but should be reported to the chain.
+    pub fn equivocations(&self) -> &[grandpa::equivocation::EquivocationProof] {
+        &self.equivocations
+    }
+
     /// Returns true if the block to be finalized is the current best block.
     pub fn is_current_best_block(&self) -> bool {
         Some(self.to_finalize) == self.chain.current_best
@@ -377,7 +398,8 @@ pub enum JustificationVerifyError {
 /// is updated.
 pub struct SetFinalizedBlockIter<'a, T> {
     iter: fork_tree::PruneAncestorsIter<'a, Block<T>>,
-    current_best: &'a mut Option<fork_tree::NodeIndex>,
+    /// Best block after the finalization. Only used for debugging purposes.
+    current_best: Option<fork_tree::NodeIndex>,
 }
 
 impl<'a, T> Iterator for SetFinalizedBlockIter<'a, T> {
@@ -386,9 +408,7 @@ impl<'a, T> Iterator for SetFinalizedBlockIter<'a, T> {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let pruned = self.iter.next()?;
-            if Some(pruned.index) == *self.current_best {
-                *self.current_best = None;
-            }
+            debug_assert_ne!(Some(pruned.index), self.current_best);
             if !pruned.is_prune_target_ancestor {
                 continue;
             }
@@ -405,8 +425,6 @@ impl<'a, T> Drop for SetFinalizedBlockIter<'a, T> {
     fn drop(&mut self) {
         // Make sure the iteration goes to the end.
         for _ in self {}
-
-        // TODO: update current_best with the new best block
     }
 }
 
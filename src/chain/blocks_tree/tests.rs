@@ -0,0 +1,316 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{
+    Config, EquivocationConsensusEngine, EquivocationProof, ForkChoice, HeaderVerifyError,
+    HeaderVerifySuccess, NonFinalizedTree,
+};
+use crate::{chain::chain_information, header};
+
+use core::{num::NonZeroU64, time::Duration};
+use rand::SeedableRng as _;
+
+fn genesis() -> header::Header {
+    header::Header {
+        parent_hash: [0; 32],
+        number: 0,
+        state_root: [0; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::empty().into(),
+    }
+}
+
+/// Builds a child of `parent`. Children of the same parent built with a different `fork` have
+/// a different hash.
+fn child(parent: &header::Header, fork: u8) -> header::Header {
+    header::Header {
+        parent_hash: parent.hash(),
+        number: parent.number + 1,
+        state_root: [fork; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::empty().into(),
+    }
+}
+
+fn new_tree(bad_blocks: Vec<[u8; 32]>, fork_blocks: Vec<(u64, [u8; 32])>) -> NonFinalizedTree<()> {
+    new_tree_with_fork_choice(bad_blocks, fork_blocks, None)
+}
+
+fn new_tree_with_fork_choice(
+    bad_blocks: Vec<[u8; 32]>,
+    fork_blocks: Vec<(u64, [u8; 32])>,
+    fork_choice: Option<ForkChoice>,
+) -> NonFinalizedTree<()> {
+    NonFinalizedTree::new(Config {
+        chain_information: chain_information::ChainInformation {
+            finalized_block_header: genesis(),
+            consensus: chain_information::ChainInformationConsensus::AllAuthorized,
+            finality: chain_information::ChainInformationFinality::Outsourced,
+        },
+        blocks_capacity: 16,
+        bad_blocks,
+        fork_blocks,
+        fork_choice,
+    })
+}
+
+/// Builds a tree of an Aura chain whose only authority is `authority`.
+fn new_aura_tree(authority: &schnorrkel::Keypair) -> NonFinalizedTree<()> {
+    NonFinalizedTree::new(Config {
+        chain_information: chain_information::ChainInformation {
+            finalized_block_header: genesis(),
+            consensus: chain_information::ChainInformationConsensus::Aura {
+                finalized_authorities_list: vec![header::AuraAuthority {
+                    public_key: authority.public.to_bytes(),
+                }],
+                slot_duration: NonZeroU64::new(1000).unwrap(),
+            },
+            finality: chain_information::ChainInformationFinality::Outsourced,
+        },
+        blocks_capacity: 16,
+        bad_blocks: Vec::new(),
+        fork_blocks: Vec::new(),
+        fork_choice: None,
+    })
+}
+
+fn aura_authority(seed: u8) -> schnorrkel::Keypair {
+    schnorrkel::MiniSecretKey::from_bytes(&[seed; 32])
+        .unwrap()
+        .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+}
+
+/// Builds a child of `parent` claiming the given Aura slot and sealed by `authority`.
+fn aura_child(
+    parent: &header::Header,
+    fork: u8,
+    slot_number: u64,
+    authority: &schnorrkel::Keypair,
+) -> header::Header {
+    let mut header = child(parent, fork);
+    header.digest = header::DigestRef::from_slice(&[header::DigestItem::AuraPreDigest(
+        header::AuraPreDigest { slot_number },
+    )])
+    .unwrap()
+    .into();
+
+    // The system randomness isn't available, and a seeded RNG is used instead.
+    let signature = authority.sign(schnorrkel::context::attach_rng(
+        schnorrkel::signing_context(b"substrate").bytes(&header.hash()),
+        rand_chacha::ChaCha20Rng::from_seed([0; 32]),
+    ));
+    header.digest.push_aura_seal(signature.to_bytes()).unwrap();
+    header
+}
+
+/// Verifies the given header and inserts it in the tree. Returns the equivocation detected
+/// during the verification, if any.
+fn insert(
+    tree: &mut NonFinalizedTree<()>,
+    header: &header::Header,
+) -> Result<Option<EquivocationProof>, HeaderVerifyError> {
+    match tree.verify_header(header.scale_encoding_vec(), Duration::new(0, 0))? {
+        HeaderVerifySuccess::Insert {
+            insert,
+            equivocation,
+            ..
+        } => {
+            insert.insert(());
+            Ok(equivocation)
+        }
+        HeaderVerifySuccess::Duplicate => panic!(),
+    }
+}
+
+#[test]
+fn bad_block_rejected() {
+    let block1 = child(&genesis(), 0);
+    let bad = child(&block1, 1);
+    let good = child(&block1, 2);
+
+    let mut tree = new_tree(vec![bad.hash()], Vec::new());
+    insert(&mut tree, &block1).unwrap();
+    assert!(matches!(
+        insert(&mut tree, &bad),
+        Err(HeaderVerifyError::BadBlock)
+    ));
+    insert(&mut tree, &good).unwrap();
+
+    assert_eq!(tree.len(), 2);
+    assert_eq!(tree.best_block_hash(), good.hash());
+}
+
+#[test]
+fn fork_block_enforced() {
+    let block1 = child(&genesis(), 0);
+    let forced = child(&block1, 1);
+    let other = child(&block1, 2);
+
+    let mut tree = new_tree(Vec::new(), vec![(2, forced.hash())]);
+    insert(&mut tree, &block1).unwrap();
+
+    // Blocks at the height of the forced block are rejected, unless they are the forced block.
+    assert!(matches!(
+        insert(&mut tree, &other),
+        Err(HeaderVerifyError::BadBlock)
+    ));
+    insert(&mut tree, &forced).unwrap();
+
+    // Blocks at other heights are unaffected.
+    insert(&mut tree, &child(&forced, 0)).unwrap();
+    assert_eq!(tree.len(), 3);
+}
+
+#[test]
+fn best_block_is_longest_chain() {
+    let a1 = child(&genesis(), 0);
+    let a2 = child(&a1, 0);
+    let b2 = child(&a1, 1);
+    let b3 = child(&b2, 0);
+    let a3 = child(&a2, 0);
+    let a4 = child(&a3, 0);
+
+    let mut tree = new_tree(Vec::new(), Vec::new());
+    insert(&mut tree, &a1).unwrap();
+    insert(&mut tree, &a2).unwrap();
+    assert_eq!(tree.best_block_hash(), a2.hash());
+
+    // A fork of the same length doesn't replace the current best block.
+    insert(&mut tree, &b2).unwrap();
+    assert_eq!(tree.best_block_hash(), a2.hash());
+
+    insert(&mut tree, &b3).unwrap();
+    assert_eq!(tree.best_block_hash(), b3.hash());
+
+    insert(&mut tree, &a3).unwrap();
+    assert_eq!(tree.best_block_hash(), b3.hash());
+    insert(&mut tree, &a4).unwrap();
+    assert_eq!(tree.best_block_hash(), a4.hash());
+}
+
+#[test]
+fn best_block_repicked_after_finality() {
+    let a1 = child(&genesis(), 0);
+    let a2 = child(&a1, 0);
+    let a3 = child(&a2, 0);
+    let a4 = child(&a3, 0);
+    let b2 = child(&a1, 1);
+    let b3 = child(&b2, 0);
+    let c3 = child(&b2, 1);
+    let c4 = child(&c3, 0);
+
+    let mut tree = new_tree(Vec::new(), Vec::new());
+    for block in &[&a1, &a2, &a3, &a4, &b2, &b3, &c3, &c4] {
+        insert(&mut tree, block).unwrap();
+    }
+    assert_eq!(tree.best_block_hash(), a4.hash());
+
+    // Finalizing an ancestor of the best block doesn't modify the best block.
+    tree.set_finalized_block(&a1.hash()).unwrap();
+    assert_eq!(tree.best_block_hash(), a4.hash());
+
+    // Finalizing a block on another fork picks the best descendant of that block.
+    tree.set_finalized_block(&b2.hash()).unwrap();
+    assert_eq!(tree.finalized_block_hash(), b2.hash());
+    assert_eq!(tree.best_block_hash(), c4.hash());
+    assert_eq!(tree.len(), 3);
+
+    // Finalizing a block without any descendant makes it the best block.
+    tree.set_finalized_block(&b3.hash()).unwrap();
+    assert_eq!(tree.best_block_hash(), b3.hash());
+    assert_eq!(tree.len(), 0);
+}
+
+#[test]
+fn babe_fork_choice_before_babe_transition() {
+    // Before a chain switches to Babe, its blocks don't contain any Babe digest. The Babe fork
+    // choice rule must then fall back to comparing the length of the chains.
+    let a1 = child(&genesis(), 0);
+    let a2 = child(&a1, 0);
+    let b1 = child(&genesis(), 1);
+    let b2 = child(&b1, 0);
+    let b3 = child(&b2, 0);
+
+    let mut tree =
+        new_tree_with_fork_choice(Vec::new(), Vec::new(), Some(ForkChoice::BabePrimaryWeight));
+    for block in &[&a1, &a2, &b1, &b2] {
+        insert(&mut tree, block).unwrap();
+    }
+    assert_eq!(tree.best_block_hash(), a2.hash());
+
+    insert(&mut tree, &b3).unwrap();
+    assert_eq!(tree.best_block_hash(), b3.hash());
+
+    tree.set_finalized_block(&a1.hash()).unwrap();
+    assert_eq!(tree.best_block_hash(), a2.hash());
+}
+
+#[test]
+fn aura_equivocation_detected() {
+    let authority = aura_authority(1);
+    let a1 = aura_child(&genesis(), 0, 1, &authority);
+    let b1 = aura_child(&genesis(), 1, 1, &authority);
+
+    let mut tree = new_aura_tree(&authority);
+    assert!(insert(&mut tree, &a1).unwrap().is_none());
+
+    let proof = insert(&mut tree, &b1).unwrap().unwrap();
+    assert_eq!(proof.consensus_engine, EquivocationConsensusEngine::Aura);
+    assert_eq!(proof.offender, authority.public.to_bytes());
+    assert_eq!(proof.slot_number, 1);
+    assert_eq!(proof.first_header.hash(), a1.hash());
+    assert_eq!(proof.second_header.hash(), b1.hash());
+}
+
+#[test]
+fn aura_different_slots_no_equivocation() {
+    let authority = aura_authority(1);
+    let a1 = aura_child(&genesis(), 0, 1, &authority);
+    let b1 = aura_child(&genesis(), 1, 2, &authority);
+    let a2 = aura_child(&a1, 0, 3, &authority);
+
+    let mut tree = new_aura_tree(&authority);
+    for block in &[&a1, &b1, &a2] {
+        assert!(insert(&mut tree, block).unwrap().is_none());
+    }
+}
+
+#[test]
+fn slot_claims_pruned_on_finality() {
+    let authority = aura_authority(1);
+    let a1 = aura_child(&genesis(), 0, 1, &authority);
+    let b1 = aura_child(&genesis(), 1, 1, &authority);
+    let a2 = aura_child(&a1, 0, 3, &authority);
+    let a3 = aura_child(&a2, 0, 4, &authority);
+    let b3 = aura_child(&a2, 1, 4, &authority);
+
+    let mut tree = new_aura_tree(&authority);
+    insert(&mut tree, &a1).unwrap();
+    assert!(insert(&mut tree, &b1).unwrap().is_some());
+    insert(&mut tree, &a2).unwrap();
+
+    tree.set_finalized_block(&a2.hash()).unwrap();
+    let slot_claims = &tree.inner.as_ref().unwrap().slot_claims;
+    assert!(!slot_claims.is_empty());
+    assert!(slot_claims.keys().all(|(slot_number, _)| *slot_number >= 3));
+
+    // Equivocations in slots after the finalized block are still detected.
+    assert!(insert(&mut tree, &a3).unwrap().is_none());
+    assert!(insert(&mut tree, &b3).unwrap().is_some());
+}
@@ -57,6 +57,7 @@ impl<T> NonFinalizedTree<T> {
                 Ok(HeaderVerifySuccess::Insert {
                     block_height: context.header.number,
                     is_new_best,
+                    equivocation: context.equivocation(),
                     insert: HeaderInsert {
                         chain: self,
                         context: Some(context),
@@ -138,6 +139,23 @@ impl<T> NonFinalizedTreeInner<T> {
             };
         }
 
+        // Reject blocks that are marked as bad, or that are at the height of a forced fork
+        // block but aren't that block.
+        if self.bad_blocks.contains(&hash)
+            || self
+                .fork_blocks
+                .get(&decoded_header.number)
+                .map_or(false, |expected| *expected != hash)
+        {
+            return if full {
+                VerifyOut::Body(BodyVerifyStep1::BadBlock(NonFinalizedTree {
+                    inner: Some(self),
+                }))
+            } else {
+                VerifyOut::HeaderErr(self, HeaderVerifyError::BadBlock)
+            };
+        }
+
         // Try to find the parent block in the tree of known blocks.
         // `Some` with an index of the parent within the tree of unfinalized blocks.
         // `None` means that the parent is the finalized block.
@@ -207,6 +225,7 @@ impl<T> NonFinalizedTreeInner<T> {
             header: decoded_header.into(),
             parent_tree_index,
             consensus,
+            slot_claim: None,
         };
 
         if full {
@@ -283,6 +302,8 @@ struct VerifyContext<T> {
     parent_tree_index: Option<fork_tree::NodeIndex>,
     header: header::Header,
     consensus: VerifyConsensusSpecific,
+    /// Slot claimed by the block. Filled after a successful verification.
+    slot_claim: Option<equivocation::SlotClaim>,
 }
 
 impl<T> VerifyContext<T> {
@@ -294,15 +315,23 @@ impl<T> VerifyContext<T> {
             verify::header_only::Success::AllAuthorized => {
                 verify::header_body::SuccessConsensus::AllAuthorized
             }
-            verify::header_only::Success::Aura { authorities_change } => {
-                verify::header_body::SuccessConsensus::Aura { authorities_change }
-            }
+            verify::header_only::Success::Aura {
+                authorities_change,
+                slot_number,
+                authority_public_key,
+            } => verify::header_body::SuccessConsensus::Aura {
+                authorities_change,
+                slot_number,
+                authority_public_key,
+            },
             verify::header_only::Success::Babe {
                 epoch_transition_target,
                 slot_number,
+                authority_public_key,
             } => verify::header_body::SuccessConsensus::Babe {
                 epoch_transition_target,
                 slot_number,
+                authority_public_key,
             },
         };
 
@@ -313,9 +342,32 @@ impl<T> VerifyContext<T> {
         &mut self,
         success_consensus: verify::header_body::SuccessConsensus,
     ) -> (bool, BlockConsensus) {
+        self.slot_claim = match &success_consensus {
+            verify::header_body::SuccessConsensus::AllAuthorized => None,
+            verify::header_body::SuccessConsensus::Aura {
+                slot_number,
+                authority_public_key,
+                ..
+            } => Some(equivocation::SlotClaim {
+                consensus_engine: EquivocationConsensusEngine::Aura,
+                slot_number: *slot_number,
+                authority_public_key: *authority_public_key,
+            }),
+            verify::header_body::SuccessConsensus::Babe {
+                slot_number,
+                authority_public_key,
+                ..
+            } => Some(equivocation::SlotClaim {
+                consensus_engine: EquivocationConsensusEngine::Babe,
+                slot_number: *slot_number,
+                authority_public_key: *authority_public_key,
+            }),
+        };
+
         let is_new_best = if let Some(current_best) = self.chain.current_best {
             best_block::is_better_block(
                 &self.chain.blocks,
+                self.chain.fork_choice,
                 current_best,
                 self.parent_tree_index,
                 (&self.header).into(),
@@ -338,7 +390,9 @@ impl<T> VerifyContext<T> {
                 _,
             ) => BlockConsensus::AllAuthorized,
             (
-                verify::header_body::SuccessConsensus::Aura { authorities_change },
+                verify::header_body::SuccessConsensus::Aura {
+                    authorities_change, ..
+                },
                 VerifyConsensusSpecific::Aura {
                     authorities_list: parent_authorities,
                 },
@@ -426,6 +480,20 @@ impl<T> VerifyContext<T> {
         (is_new_best, consensus)
     }
 
+    /// Returns the equivocation that the verified block is part of, if any.
+    fn equivocation(&self) -> Option<EquivocationProof> {
+        let slot_claim = self.slot_claim.as_ref()?;
+        self.chain.find_equivocation(slot_claim, &self.header)
+    }
+
+    /// Registers the slot claimed by the verified block. Must be called when the block is
+    /// inserted in the tree.
+    fn register_slot_claim(&mut self) {
+        if let Some(slot_claim) = &self.slot_claim {
+            self.chain.register_slot_claim(slot_claim, &self.header);
+        }
+    }
+
     fn with_body_verify(mut self, inner: verify::header_body::Verify) -> BodyVerifyStep2<T> {
         match inner {
             verify::header_body::Verify::Finished(Ok(success)) => {
@@ -505,6 +573,10 @@ pub enum BodyVerifyStep1<T> {
         parent_hash: [u8; 32],
     },
 
+    /// The block is in the list of [`Config::bad_blocks`], or conflicts with an entry of
+    /// [`Config::fork_blocks`].
+    BadBlock(NonFinalizedTree<T>),
+
     /// Verification is pending. In order to continue, a [`host::HostVmPrototype`] of the
     /// runtime of the parent block must be provided.
     ParentRuntimeRequired(BodyVerifyRuntimeRequired<T>),
@@ -925,6 +997,9 @@ pub enum HeaderVerifySuccess<'c, T> {
         block_height: u64,
         /// True if the verified block will become the new "best" block after being inserted.
         is_new_best: bool,
+        /// If `Some`, the author of the verified block has already produced a different block in
+        /// the same slot. The block is nonetheless valid and can be inserted.
+        equivocation: Option<EquivocationProof>,
         /// Use this struct to insert the block in the chain after its successful verification.
         insert: HeaderInsert<'c, T>,
     },
@@ -945,6 +1020,7 @@ impl<'c, T> HeaderInsert<'c, T> {
     /// Inserts the block with the given user data.
     pub fn insert(mut self, user_data: T) {
         let mut context = self.context.take().unwrap();
+        context.register_slot_claim();
 
         let new_node_index = context.chain.blocks.insert(
             context.parent_tree_index,
@@ -1005,6 +1081,10 @@ pub enum HeaderVerifyError {
         /// Hash of the parent block in question.
         parent_hash: [u8; 32],
     },
+    /// The block is in the list of [`Config::bad_blocks`], or conflicts with an entry of
+    /// [`Config::fork_blocks`].
+    #[display(fmt = "The block has been marked as bad.")]
+    BadBlock,
     /// The block verification has failed. The block is invalid and should be thrown away.
     VerificationFailed(verify::header_only::Error),
 }
@@ -1024,8 +1104,20 @@ impl<T> BodyInsert<T> {
         (&self.context.header).into()
     }
 
+    /// Returns true if the block will become the new "best" block after being inserted.
+    pub fn is_new_best(&self) -> bool {
+        self.is_new_best
+    }
+
+    /// If the author of the block has already produced a different block in the same slot,
+    /// returns a proof of this equivocation. The block is nonetheless valid and can be inserted.
+    pub fn equivocation(&self) -> Option<EquivocationProof> {
+        self.context.equivocation()
+    }
+
     /// Inserts the block with the given user data.
     pub fn insert(mut self, user_data: T) -> NonFinalizedTree<T> {
+        self.context.register_slot_claim();
         let new_node_index = self.context.chain.blocks.insert(
             self.context.parent_tree_index,
             Block {
@@ -29,6 +29,8 @@ use parity_scale_codec::DecodeAll as _;
 /// The way a chain configures BABE is stored in its runtime.
 #[derive(Debug, Clone)]
 pub struct BabeGenesisConfiguration {
+    /// Duration, in milliseconds, of a slot.
+    pub slot_duration: NonZeroU64,
     pub slots_per_epoch: NonZeroU64,
     pub epoch0_configuration: header::BabeNextConfig,
     pub epoch0_information: header::BabeNextEpoch,
@@ -110,6 +112,7 @@ impl BabeGenesisConfiguration {
         };
 
         let outcome = BabeGenesisConfiguration {
+            slot_duration: inner.slot_duration,
             slots_per_epoch: inner.epoch_length,
             epoch0_configuration,
             epoch0_information,
@@ -175,7 +178,7 @@ impl FromVmPrototypeError {
 // TODO: don't use scale_codec?
 #[derive(Debug, Clone, PartialEq, Eq, parity_scale_codec::Encode, parity_scale_codec::Decode)]
 struct OwnedGenesisConfiguration {
-    slot_duration: u64,
+    slot_duration: NonZeroU64,
     epoch_length: NonZeroU64,
     c: (u64, u64),
     genesis_authorities: Vec<([u8; 32], u64)>,
@@ -126,6 +126,12 @@ impl<T> ForkTree<T> {
         self.nodes.iter().map(|n| &n.1.data)
     }
 
+    /// Returns an iterator to all the nodes, alongside with their index, without any specific
+    /// order.
+    pub fn iter_unordered(&self) -> impl Iterator<Item = (NodeIndex, &T)> {
+        self.nodes.iter().map(|n| (NodeIndex(n.0), &n.1.data))
+    }
+
     /// Returns the value of the node with the given index.
     pub fn get(&self, index: NodeIndex) -> Option<&T> {
         self.nodes.get(index.0).map(|n| &n.data)
@@ -309,6 +315,25 @@ impl<T> ForkTree<T> {
         .skip(1)
     }
 
+    /// Returns the list of children of the given node, or the roots of the tree if `None` is
+    /// passed.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`NodeIndex`] is invalid.
+    ///
+    pub fn children<'a>(
+        &'a self,
+        node_index: Option<NodeIndex>,
+    ) -> impl Iterator<Item = NodeIndex> + 'a {
+        let first = match node_index {
+            Some(node_index) => self.nodes[node_index.0].first_child,
+            None => self.first_root,
+        };
+
+        iter::successors(first, move |n| self.nodes[*n].next_sibling).map(NodeIndex)
+    }
+
     /// Finds the first node in the tree that matches the given condition.
     pub fn find(&self, mut cond: impl FnMut(&T) -> bool) -> Option<NodeIndex> {
         self.nodes
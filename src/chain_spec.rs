@@ -206,6 +206,31 @@ impl ChainSpec {
         self.client_spec.protocol_id.as_deref().unwrap_or("sup")
     }
 
+    /// Returns the list of blocks that must be part of the chain, as a list of block heights
+    /// and hashes.
+    ///
+    /// At each of these heights, blocks whose hash isn't the one indicated must be rejected.
+    /// This is used in order to force the chain onto a specific fork after an incident.
+    pub fn fork_blocks(&self) -> impl ExactSizeIterator<Item = (u64, [u8; 32])> + '_ {
+        self.client_spec
+            .fork_blocks
+            .as_ref()
+            .map(|l| &l[..])
+            .unwrap_or(&[])
+            .iter()
+            .map(|(n, h)| (*n, h.0))
+    }
+
+    /// Returns the list of hashes of blocks that are known to be bad and must never be part of
+    /// the chain.
+    pub fn bad_blocks_hashes(&self) -> impl Iterator<Item = &[u8; 32]> + '_ {
+        self.client_spec
+            .bad_blocks
+            .as_ref()
+            .into_iter()
+            .flat_map(|l| l.iter().map(|h| &h.0))
+    }
+
     // TODO: this API is probably unstable, as the meaning of the string is unclear
     pub fn relay_chain(&self) -> Option<(&str, u32)> {
         self.client_spec
@@ -35,6 +35,9 @@
 //! Any block that isn't an ancestor or descendant will be removed. Reverting finalization is
 //! not supported.
 //!
+//! Use [`SqliteFullDatabase::set_finalized_storage`] to replace the storage of the finalized block,
+//! for example after it has been downloaded from the network.
+//!
 //! In order to minimize disk usage, it is not possible to efficiently retrieve the storage items
 //! of blocks that are ancestors of the finalized block. When a block is finalized, the storage of
 //! its ancestors is lost, and the only way to reconstruct it is to execute all blocks starting
@@ -501,6 +504,17 @@ impl SqliteFullDatabase {
                     AccessError::Corrupted(CorruptedError::MissingBlockHeader),
                 ))?;
 
+            // Changes to child tries aren't tracked. The content of a child trie whose root is
+            // modified by this block is no longer accurate, and is thus removed.
+            let mut statement = connection
+                .prepare(
+                    "DELETE FROM finalized_storage_child_trie
+                WHERE trie IN (SELECT key FROM non_finalized_changes WHERE hash = ?);",
+                )
+                .unwrap();
+            statement.bind(1, &block_hash[..]).unwrap();
+            statement.next().unwrap();
+
             let mut statement = connection
                 .prepare(
                     "DELETE FROM finalized_storage_top_trie
@@ -629,6 +643,70 @@ impl SqliteFullDatabase {
         Ok(())
     }
 
+    /// Replaces the storage of the finalized block with the one passed as parameter.
+    ///
+    /// This is typically used after the storage of the finalized block has been downloaded from
+    /// the network, for example using [`crate::sync::state`]. The storage isn't verified in any
+    /// way.
+    ///
+    /// `child_tries` contains, for each child trie, the key of the child trie within the main
+    /// trie and the keys and values of this child trie.
+    ///
+    /// In order to avoid race conditions, the known finalized block hash must be passed as
+    /// parameter. If the finalized block in the database doesn't match the hash passed as
+    /// parameter, most likely because it has been updated in a parallel thread, a
+    /// [`FinalizedAccessError::Obsolete`] error is returned.
+    pub fn set_finalized_storage<'a>(
+        &self,
+        finalized_block_hash: &[u8; 32],
+        main_trie: impl Iterator<Item = (&'a [u8], &'a [u8])>,
+        child_tries: impl Iterator<Item = (&'a [u8], impl Iterator<Item = (&'a [u8], &'a [u8])>)>,
+    ) -> Result<(), FinalizedAccessError> {
+        let connection = self.database.lock();
+
+        if finalized_hash(&connection)? != *finalized_block_hash {
+            return Err(FinalizedAccessError::Obsolete);
+        }
+
+        let sqlite_err = |err| {
+            FinalizedAccessError::Access(AccessError::Corrupted(CorruptedError::Internal(
+                InternalError(err),
+            )))
+        };
+
+        connection
+            .execute(
+                "DELETE FROM finalized_storage_top_trie; DELETE FROM finalized_storage_child_trie;",
+            )
+            .map_err(sqlite_err)?;
+
+        let mut statement = connection
+            .prepare("INSERT INTO finalized_storage_top_trie(key, value) VALUES(?, ?)")
+            .map_err(sqlite_err)?;
+        for (key, value) in main_trie {
+            statement.bind(1, key).map_err(sqlite_err)?;
+            statement.bind(2, value).map_err(sqlite_err)?;
+            statement.next().map_err(sqlite_err)?;
+            statement.reset().map_err(sqlite_err)?;
+        }
+
+        let mut statement = connection
+            .prepare("INSERT INTO finalized_storage_child_trie(trie, key, value) VALUES(?, ?, ?)")
+            .map_err(sqlite_err)?;
+        for (child_trie, entries) in child_tries {
+            for (key, value) in entries {
+                statement.bind(1, child_trie).map_err(sqlite_err)?;
+                statement.bind(2, key).map_err(sqlite_err)?;
+                statement.bind(3, value).map_err(sqlite_err)?;
+                statement.next().map_err(sqlite_err)?;
+                statement.reset().map_err(sqlite_err)?;
+            }
+        }
+
+        flush(&connection)?;
+        Ok(())
+    }
+
     /// Returns all the keys and values in the storage of the finalized block.
     ///
     /// In order to avoid race conditions, the known finalized block hash must be passed as
@@ -707,6 +785,52 @@ impl SqliteFullDatabase {
         Ok(Some(value))
     }
 
+    /// Returns the value associated to a key in a child trie of the storage of the finalized
+    /// block. `child_trie` is the key of the child trie within the main trie, including its
+    /// `:child_storage:` prefix.
+    ///
+    /// Child tries are only known if they have been passed to
+    /// [`SqliteFullDatabase::set_finalized_storage`], and are forgotten as soon as a newly
+    /// finalized block modifies them. `None` is returned if the child trie or the key is unknown.
+    ///
+    /// In order to avoid race conditions, the known finalized block hash must be passed as
+    /// parameter. If the finalized block in the database doesn't match the hash passed as
+    /// parameter, most likely because it has been updated in a parallel thread, a
+    /// [`FinalizedAccessError::Obsolete`] error is returned.
+    pub fn finalized_block_storage_child_trie_get(
+        &self,
+        finalized_block_hash: &[u8; 32],
+        child_trie: &[u8],
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, FinalizedAccessError> {
+        let connection = self.database.lock();
+
+        if finalized_hash(&connection)? != *finalized_block_hash {
+            return Err(FinalizedAccessError::Obsolete);
+        }
+
+        let mut statement = connection
+            .prepare(r#"SELECT value FROM finalized_storage_child_trie WHERE trie = ? AND key = ?"#)
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)
+            .map_err(AccessError::Corrupted)
+            .map_err(FinalizedAccessError::Access)?;
+        statement.bind(1, child_trie).unwrap();
+        statement.bind(2, key).unwrap();
+
+        if !matches!(statement.next().unwrap(), sqlite::State::Row) {
+            return Ok(None);
+        }
+
+        let value = statement
+            .read::<Vec<u8>>(0)
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)
+            .map_err(AccessError::Corrupted)
+            .map_err(FinalizedAccessError::Access)?;
+        Ok(Some(value))
+    }
+
     /// Returns the key in the storage of the finalized block that immediately follows the key
     /// passed as parameter.
     ///
@@ -144,6 +144,20 @@ CREATE TABLE IF NOT EXISTS finalized_storage_top_trie(
     value BLOB NOT NULL
 );
 
+/*
+Storage of the child tries at the highest block that is considered finalized. `trie` is the key
+of the child trie within `finalized_storage_top_trie`, including its `:child_storage:` prefix.
+Only populated when the storage is downloaded from the network. Changes to child tries performed
+by non-finalized blocks aren't tracked. Instead, when a block that modifies the root of a child
+trie gets finalized, the content of this child trie is removed from this table.
+*/
+CREATE TABLE IF NOT EXISTS finalized_storage_child_trie(
+    trie BLOB NOT NULL,
+    key BLOB NOT NULL,
+    value BLOB NOT NULL,
+    PRIMARY KEY(trie, key)
+);
+
 /*
 For non-finalized blocks (i.e. blocks that descend from the finalized block), contains changes
 that this block performs on the storage.
@@ -16,4 +16,6 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod chain_config;
+pub mod equivocation;
+pub mod voter;
 pub mod warp_sync;
@@ -0,0 +1,126 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! GrandPa equivocations.
+//!
+//! During each round, each GrandPa authority is allowed to emit at most one prevote and at most
+//! one precommit. An authority that emits two prevotes or two precommits targeting different
+//! blocks during the same round is said to *equivocate*.
+//!
+//! An [`EquivocationProof`] contains the two conflicting votes and their signatures. It can be
+//! reported to the runtime by calling the
+//! `GrandpaApi_submit_report_equivocation_unsigned_extrinsic` runtime function with the SCALE
+//! encoding of the proof and a proof of ownership of the key of the offender. The latter can be
+//! obtained by calling the `GrandpaApi_generate_key_ownership_proof` runtime function.
+
+use alloc::vec::Vec;
+
+/// Proof that a GrandPa authority has emitted two conflicting votes during the same round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EquivocationProof {
+    /// Identifier of the authorities set the offender belongs to.
+    pub set_id: u64,
+    /// Round during which the votes have been emitted.
+    pub round_number: u64,
+    /// Kind of the two votes.
+    pub kind: VoteKind,
+    /// Public key of the authority that has equivocated.
+    pub authority_public_key: [u8; 32],
+    /// First vote of the authority.
+    pub first: EquivocationVote,
+    /// Second vote of the authority. Targets a different block than [`EquivocationProof::first`].
+    pub second: EquivocationVote,
+}
+
+/// Kind of vote an [`EquivocationProof`] is about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VoteKind {
+    Prevote,
+    Precommit,
+}
+
+/// One of the two votes of an [`EquivocationProof`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EquivocationVote {
+    /// Hash of the block targeted by the vote.
+    pub target_hash: [u8; 32],
+    /// Height of the block targeted by the vote.
+    pub target_number: u32,
+    /// Ed25519 signature of the vote made with [`EquivocationProof::authority_public_key`].
+    pub signature: [u8; 64],
+}
+
+impl EquivocationProof {
+    /// Returns the SCALE encoding of the proof, as expected by the
+    /// `GrandpaApi_submit_report_equivocation_unsigned_extrinsic` runtime function.
+    pub fn scale_encoding_vec(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(8 + 1 + 8 + 32 + 2 * (32 + 4 + 64));
+        out.extend_from_slice(&self.set_id.to_le_bytes());
+        out.push(match self.kind {
+            VoteKind::Prevote => 0,
+            VoteKind::Precommit => 1,
+        });
+        out.extend_from_slice(&self.round_number.to_le_bytes());
+        out.extend_from_slice(&self.authority_public_key);
+        for vote in [&self.first, &self.second] {
+            out.extend_from_slice(&vote.target_hash);
+            out.extend_from_slice(&vote.target_number.to_le_bytes());
+            out.extend_from_slice(&vote.signature);
+        }
+        debug_assert_eq!(out.len(), out.capacity());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EquivocationProof, EquivocationVote, VoteKind};
+
+    #[test]
+    fn scale_encoding() {
+        let proof = EquivocationProof {
+            set_id: 3,
+            round_number: 7,
+            kind: VoteKind::Precommit,
+            authority_public_key: [0xaa; 32],
+            first: EquivocationVote {
+                target_hash: [1; 32],
+                target_number: 0x0102,
+                signature: [0x11; 64],
+            },
+            second: EquivocationVote {
+                target_hash: [2; 32],
+                target_number: 0x0203,
+                signature: [0x22; 64],
+            },
+        };
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0]);
+        expected.push(1);
+        expected.extend_from_slice(&[7, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[0xaa; 32]);
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(&[0x02, 0x01, 0, 0]);
+        expected.extend_from_slice(&[0x11; 64]);
+        expected.extend_from_slice(&[2; 32]);
+        expected.extend_from_slice(&[0x03, 0x02, 0, 0]);
+        expected.extend_from_slice(&[0x22; 64]);
+
+        assert_eq!(proof.scale_encoding_vec(), expected);
+    }
+}
@@ -0,0 +1,1269 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! GrandPa voter.
+//!
+//! GrandPa is the finality algorithm used by Substrate-based chains. The authorities of the
+//! current authorities set participate in successive *rounds*. During each round, each authority
+//! emits a *prevote* and a *precommit*, each targeting a block. A block is considered finalized
+//! once a super-majority (strictly more than two thirds, in terms of weight) of the authorities
+//! have precommitted for this block or one of its descendants.
+//!
+//! The [`Voter`] is a state machine that tracks the progress of the rounds, verifies the votes
+//! received from the network, and, if a local key is available, casts votes. It doesn't perform
+//! any networking and doesn't have access to the chain: the user is expected to inform the
+//! [`Voter`] of the blocks of the chain using [`Voter::insert_block`] and
+//! [`Voter::set_best_block`], and to propagate the messages that the voter generates.
+//!
+//! # Usage
+//!
+//! A [`Voter`] is only valid for one specific authorities set. Whenever the authorities set
+//! changes, a new [`Voter`] must be created.
+//!
+//! Call [`Voter::next_event`] after each call to a method that modifies the state of the
+//! [`Voter`], and every time the moment returned by [`Voter::next_wake_up`] is reached. Events
+//! are generated when the local authority has voted, when a block has been finalized, when
+//! a commit message must be sent out, and when an authority has been caught equivocating.
+
+// TODO: the votes targeting blocks that aren't known locally are ignored when determining the
+//       state of a round; these votes should be taken into account once the block is inserted,
+//       which is currently the case, but there is no mechanism to request these blocks
+
+use crate::{
+    finality::{
+        grandpa::equivocation::{EquivocationProof, EquivocationVote, VoteKind},
+        justification::decode,
+    },
+    header,
+    network::protocol::{
+        vote_signed_message, CommitMessageRef, CompactCommitRef, MessageRef, PrimaryProposeRef,
+        UnsignedPrecommitRef, UnsignedPrevoteRef, VoteMessageRef,
+    },
+};
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use core::{
+    convert::TryFrom as _,
+    ops::{Add, Sub},
+    time::Duration,
+};
+
+/// Configuration for a [`Voter`].
+pub struct Config<'a> {
+    /// Identifier of the authorities set the voter is part of.
+    pub authorities_set_id: u64,
+
+    /// List of authorities of the set. Must not be empty.
+    pub authorities: header::GrandpaAuthoritiesIter<'a>,
+
+    /// Hash of the latest finalized block.
+    pub finalized_block_hash: [u8; 32],
+
+    /// Height of the latest finalized block.
+    pub finalized_block_number: u64,
+
+    /// Number of the round to start with. Round numbers start at 1 for each new authorities
+    /// set.
+    pub start_round_number: u64,
+
+    /// Ed25519 secret key of the local authority, if any. If `None`, or if the corresponding
+    /// public key isn't part of [`Config::authorities`], then the [`Voter`] only observes the
+    /// rounds and never votes.
+    pub local_secret_key: Option<[u8; 32]>,
+
+    /// Expected time it takes for a message to be propagated to all the authorities. The length
+    /// of a round is based on this value.
+    pub gossip_duration: Duration,
+}
+
+/// GrandPa voter. See [the module-level documentation](..).
+pub struct Voter<TNow> {
+    /// See [`Config::authorities_set_id`].
+    set_id: u64,
+
+    /// See [`Config::authorities`].
+    authorities: Vec<header::GrandpaAuthority>,
+
+    /// Sum of the weights of all the authorities.
+    total_weight: u64,
+
+    /// Minimum weight necessary for a super-majority.
+    threshold: u64,
+
+    /// Index within [`Voter::authorities`] and signing key of the local authority.
+    local_authority: Option<(usize, ed25519_zebra::SigningKey)>,
+
+    /// See [`Config::gossip_duration`].
+    gossip_duration: Duration,
+
+    /// Hash and number of the latest finalized block. Either passed through the configuration,
+    /// or finalized by the voter, or passed to [`Voter::set_finalized_block`].
+    finalized_block: ([u8; 32], u64),
+
+    /// Hash of the current best block. Must be either [`Voter::finalized_block`] or one of the
+    /// blocks in [`Voter::blocks`], otherwise it is ignored.
+    best_block_hash: [u8; 32],
+
+    /// List of blocks that descend from [`Voter::finalized_block`]. Indexed by hash.
+    blocks: hashbrown::HashMap<[u8; 32], Block, fnv::FnvBuildHasher>,
+
+    /// Round before [`Voter::current_round`], if any. Votes are still accepted for that round,
+    /// as it might not have finalized its estimate yet.
+    previous_round: Option<Round<TNow>>,
+
+    /// Round currently in progress.
+    current_round: Round<TNow>,
+
+    /// If `Some`, the voter must move on to the given round as soon as possible. Set when
+    /// receiving a commit message targeting a round superior or equal to the current round.
+    round_skip: Option<u64>,
+
+    /// Events waiting to be returned by [`Voter::next_event`].
+    pending_events: VecDeque<Event>,
+}
+
+struct Block {
+    number: u64,
+    parent_hash: [u8; 32],
+}
+
+struct Round<TNow> {
+    number: u64,
+    /// Moment when the round has started. `None` if the round has been started through
+    /// [`Voter::round_skip`] and [`Voter::next_event`] hasn't been called yet.
+    start: Option<TNow>,
+    /// Block proposed by the primary of the round, if any.
+    primary_proposal: Option<([u8; 32], u64)>,
+    /// `true` if the local authority is the primary of the round and has already proposed a
+    /// block, or has decided not to.
+    primary_propose_done: bool,
+    stage: RoundStage,
+    prevotes: Votes,
+    precommits: Votes,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum RoundStage {
+    Start,
+    Prevoted,
+    Precommitted,
+}
+
+/// Votes of a certain kind (prevotes or precommits) of a round.
+#[derive(Default)]
+struct Votes {
+    /// First vote of each authority, indexed by authority index.
+    votes: BTreeMap<usize, Vote>,
+    /// Second vote of the authorities that have equivocated, indexed by authority index.
+    /// Authorities that equivocate are considered as having voted for every block.
+    equivocations: BTreeMap<usize, Vote>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Vote {
+    target_hash: [u8; 32],
+    target_number: u64,
+    signature: [u8; 64],
+}
+
+impl<TNow> Voter<TNow>
+where
+    TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+{
+    /// Initializes a new [`Voter`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`Config::authorities`] is empty.
+    ///
+    pub fn new(config: Config) -> Self {
+        let authorities = config
+            .authorities
+            .map(header::GrandpaAuthority::from)
+            .collect::<Vec<_>>();
+        assert!(!authorities.is_empty());
+
+        let total_weight = authorities
+            .iter()
+            .fold(0u64, |acc, a| acc.saturating_add(a.weight.get()));
+        let faulty = (total_weight - 1) / 3;
+        let threshold = total_weight - faulty;
+
+        let local_authority = config.local_secret_key.and_then(|secret_key| {
+            let signing_key = ed25519_zebra::SigningKey::from(secret_key);
+            let public_key: [u8; 32] = ed25519_zebra::VerificationKeyBytes::from(
+                ed25519_zebra::VerificationKey::from(&signing_key),
+            )
+            .into();
+            authorities
+                .iter()
+                .position(|a| a.public_key == public_key)
+                .map(|index| (index, signing_key))
+        });
+
+        Voter {
+            set_id: config.authorities_set_id,
+            authorities,
+            total_weight,
+            threshold,
+            local_authority,
+            gossip_duration: config.gossip_duration,
+            finalized_block: (config.finalized_block_hash, config.finalized_block_number),
+            best_block_hash: config.finalized_block_hash,
+            blocks: hashbrown::HashMap::with_capacity_and_hasher(0, Default::default()),
+            previous_round: None,
+            current_round: Round::new(config.start_round_number, None),
+            round_skip: None,
+            pending_events: VecDeque::new(),
+        }
+    }
+
+    /// Returns the identifier of the authorities set of this voter.
+    pub fn authorities_set_id(&self) -> u64 {
+        self.set_id
+    }
+
+    /// Returns the number of the round currently in progress.
+    pub fn current_round_number(&self) -> u64 {
+        self.current_round.number
+    }
+
+    /// Returns the hash and height of the latest finalized block.
+    pub fn finalized_block(&self) -> (&[u8; 32], u64) {
+        (&self.finalized_block.0, self.finalized_block.1)
+    }
+
+    /// Returns `true` if the local node is one of the authorities of the set.
+    pub fn is_authority(&self) -> bool {
+        self.local_authority.is_some()
+    }
+
+    /// Informs the voter of the existence of a block.
+    ///
+    /// Blocks whose height is inferior or equal to the one of the latest finalized block are
+    /// ignored. Blocks can be inserted in any order.
+    pub fn insert_block(&mut self, hash: [u8; 32], number: u64, parent_hash: [u8; 32]) {
+        // Block numbers that don't fit in a `u32` can't be voted upon.
+        if number <= self.finalized_block.1 || u32::try_from(number).is_err() {
+            return;
+        }
+
+        self.blocks.insert(
+            hash,
+            Block {
+                number,
+                parent_hash,
+            },
+        );
+    }
+
+    /// Sets the current best block of the chain. Prevotes target this block if possible.
+    ///
+    /// The block should have been inserted with [`Voter::insert_block`], otherwise it is
+    /// ignored.
+    pub fn set_best_block(&mut self, hash: [u8; 32]) {
+        self.best_block_hash = hash;
+    }
+
+    /// Informs the voter that a block has been finalized by other means than the voter itself,
+    /// for example by receiving a justification.
+    ///
+    /// Has no effect if the block is unknown or isn't a descendant of the current finalized
+    /// block.
+    pub fn set_finalized_block(&mut self, hash: &[u8; 32]) {
+        let number = match self.blocks.get(hash) {
+            Some(b) => b.number,
+            None => return,
+        };
+
+        if !self.is_descendant_or_equal((hash, number), (&self.finalized_block.0, 0)) {
+            return;
+        }
+
+        self.finalized_block = (*hash, number);
+        self.prune_blocks();
+    }
+
+    /// Returns the moment when [`Voter::next_event`] should be called again, assuming that no
+    /// other method is called in between.
+    ///
+    /// Returns `None` if there is no time-related change to process.
+    pub fn next_wake_up(&self) -> Option<TNow> {
+        let start = self.current_round.start.as_ref()?;
+        match self.current_round.stage {
+            RoundStage::Start => Some(start.clone() + self.gossip_duration * 2),
+            RoundStage::Prevoted => Some(start.clone() + self.gossip_duration * 4),
+            RoundStage::Precommitted => None,
+        }
+    }
+
+    /// Verifies and processes a vote received from the network.
+    ///
+    /// On success, the vote should be propagated to the other nodes.
+    pub fn inject_vote(&mut self, vote: VoteMessageRef) -> Result<(), VoteError> {
+        if vote.set_id != self.set_id {
+            return Err(VoteError::BadSetId);
+        }
+
+        if vote.round_number > self.current_round.number {
+            return Err(VoteError::FutureRound);
+        }
+
+        let is_current_round = vote.round_number == self.current_round.number;
+        if !is_current_round
+            && self
+                .previous_round
+                .as_ref()
+                .map_or(true, |r| r.number != vote.round_number)
+        {
+            return Err(VoteError::ObsoleteRound);
+        }
+
+        let authority_index = self
+            .authorities
+            .iter()
+            .position(|a| a.public_key == *vote.authority_public_key)
+            .ok_or(VoteError::NotAuthority)?;
+
+        let (kind, target_hash, target_number) = match &vote.message {
+            MessageRef::Prevote(m) => (MessageKind::Prevote, m.target_hash, m.target_number),
+            MessageRef::Precommit(m) => (MessageKind::Precommit, m.target_hash, m.target_number),
+            MessageRef::PrimaryPropose(m) => {
+                (MessageKind::PrimaryPropose, m.target_hash, m.target_number)
+            }
+        };
+
+        if !vote.is_signature_valid() {
+            return Err(VoteError::BadSignature);
+        }
+
+        let primary_index = self.primary_index(vote.round_number);
+        let round = if is_current_round {
+            &mut self.current_round
+        } else {
+            self.previous_round.as_mut().unwrap()
+        };
+
+        let new_vote = Vote {
+            target_hash: *target_hash,
+            target_number: u64::from(target_number),
+            signature: *vote.signature,
+        };
+
+        let (kind, votes) = match kind {
+            MessageKind::Prevote => (VoteKind::Prevote, &mut round.prevotes),
+            MessageKind::Precommit => (VoteKind::Precommit, &mut round.precommits),
+            MessageKind::PrimaryPropose => {
+                if authority_index != primary_index {
+                    return Err(VoteError::NotPrimary);
+                }
+                if round.primary_proposal.is_some() {
+                    return Err(VoteError::Duplicate);
+                }
+                round.primary_proposal = Some((*target_hash, u64::from(target_number)));
+                return Ok(());
+            }
+        };
+
+        if let Some(first) = votes.insert(authority_index, new_vote.clone())? {
+            self.pending_events
+                .push_back(Event::Equivocation(equivocation_proof(
+                    self.set_id,
+                    vote.round_number,
+                    kind,
+                    *vote.authority_public_key,
+                    &first,
+                    &new_vote,
+                )));
+        }
+
+        Ok(())
+    }
+
+    /// Verifies and processes a commit message received from the network.
+    ///
+    /// On success, the commit should be propagated to the other nodes, and a
+    /// [`Event::Finalized`] might later be generated.
+    pub fn inject_commit(&mut self, commit: CommitMessageRef) -> Result<(), CommitError> {
+        if commit.set_id != self.set_id {
+            return Err(CommitError::BadSetId);
+        }
+
+        if commit.message.precommits.len() != commit.message.auth_data.len() {
+            return Err(CommitError::BadFormat);
+        }
+
+        let target = (
+            commit.message.target_hash,
+            u64::from(commit.message.target_number),
+        );
+
+        let mut voters = Vec::with_capacity(commit.message.precommits.len());
+        let mut weighted_voters = Vec::with_capacity(commit.message.precommits.len());
+        let mut weight = 0u64;
+
+        // Indices within the commit of the two precommits of each equivocating authority.
+        let mut equivocations = Vec::new();
+
+        for (precommit, (signature, public_key)) in commit
+            .message
+            .precommits
+            .iter()
+            .zip(commit.message.auth_data.iter())
+        {
+            let authority_index = self
+                .authorities
+                .iter()
+                .position(|a| a.public_key == **public_key)
+                .ok_or(CommitError::NotAuthority)?;
+
+            // An authority that has precommitted twice for different blocks is an equivocator.
+            // Its precommits are both kept, as its weight counts towards the threshold.
+            if let Some(first_index) = voters.iter().position(|v| *v == authority_index) {
+                let first = &commit.message.precommits[first_index];
+                if first.target_hash == precommit.target_hash
+                    || equivocations.iter().any(|(f, _)| *f == first_index)
+                {
+                    return Err(CommitError::DuplicateSignature);
+                }
+                equivocations.push((first_index, voters.len()));
+            }
+            voters.push(authority_index);
+
+            if !verify_signature(
+                public_key,
+                signature,
+                &signed_message(
+                    MessageKind::Precommit,
+                    precommit.target_hash,
+                    precommit.target_number,
+                    commit.round_number,
+                    self.set_id,
+                ),
+            ) {
+                return Err(CommitError::BadSignature);
+            }
+
+            if !weighted_voters.contains(&authority_index)
+                && self.is_descendant_or_equal(
+                    (precommit.target_hash, u64::from(precommit.target_number)),
+                    target,
+                )
+            {
+                weighted_voters.push(authority_index);
+                weight = weight.saturating_add(self.authorities[authority_index].weight.get());
+            }
+        }
+
+        if weight < self.threshold {
+            return Err(CommitError::NotEnoughWeight);
+        }
+
+        if target.1 > self.finalized_block.1 && !self.blocks.contains_key(target.0) {
+            return Err(CommitError::UnknownTarget);
+        }
+
+        // The commit is valid. If its round is still tracked, its precommits are imported in order
+        // to detect equivocations. Otherwise, only the equivocations within the commit itself are
+        // reported.
+        let round = if commit.round_number == self.current_round.number {
+            Some(&mut self.current_round)
+        } else {
+            self.previous_round
+                .as_mut()
+                .filter(|r| r.number == commit.round_number)
+        };
+        if let Some(round) = round {
+            for ((precommit, (signature, public_key)), authority_index) in commit
+                .message
+                .precommits
+                .iter()
+                .zip(commit.message.auth_data.iter())
+                .zip(voters.iter())
+            {
+                let new_vote = Vote {
+                    target_hash: *precommit.target_hash,
+                    target_number: u64::from(precommit.target_number),
+                    signature: **signature,
+                };
+
+                if let Ok(Some(first)) = round.precommits.insert(*authority_index, new_vote.clone())
+                {
+                    self.pending_events
+                        .push_back(Event::Equivocation(equivocation_proof(
+                            self.set_id,
+                            commit.round_number,
+                            VoteKind::Precommit,
+                            **public_key,
+                            &first,
+                            &new_vote,
+                        )));
+                }
+            }
+        } else {
+            let to_vote = |index: usize| Vote {
+                target_hash: *commit.message.precommits[index].target_hash,
+                target_number: u64::from(commit.message.precommits[index].target_number),
+                signature: *commit.message.auth_data[index].0,
+            };
+
+            for (first_index, second_index) in equivocations {
+                self.pending_events
+                    .push_back(Event::Equivocation(equivocation_proof(
+                        self.set_id,
+                        commit.round_number,
+                        VoteKind::Precommit,
+                        *commit.message.auth_data[first_index].1,
+                        &to_vote(first_index),
+                        &to_vote(second_index),
+                    )));
+            }
+        }
+
+        if target.1 <= self.finalized_block.1 {
+            return Ok(());
+        }
+
+        let justification = decode::Justification {
+            round: commit.round_number,
+            target_hash: *commit.message.target_hash,
+            target_number: commit.message.target_number,
+            precommits: commit
+                .message
+                .precommits
+                .iter()
+                .zip(commit.message.auth_data.iter())
+                .map(|(precommit, (signature, public_key))| decode::Precommit {
+                    target_hash: *precommit.target_hash,
+                    target_number: precommit.target_number,
+                    signature: **signature,
+                    authority_public_key: **public_key,
+                })
+                .collect(),
+        };
+
+        self.finalize(*target.0, target.1, justification);
+
+        if commit.round_number >= self.current_round.number {
+            self.round_skip = Some(commit.round_number + 1);
+        }
+
+        Ok(())
+    }
+
+    /// Processes the state of the voter and returns the next event, if any.
+    ///
+    /// Must be called repeatedly until it returns `None`.
+    pub fn next_event(&mut self, now: &TNow) -> Option<Event> {
+        loop {
+            if let Some(event) = self.pending_events.pop_front() {
+                return Some(event);
+            }
+
+            if let Some(round_number) = self.round_skip.take() {
+                if round_number > self.current_round.number {
+                    self.previous_round = None;
+                    self.current_round = Round::new(round_number, Some(now.clone()));
+                    continue;
+                }
+            }
+
+            if self.current_round.start.is_none() {
+                self.current_round.start = Some(now.clone());
+            }
+
+            if self.process_rounds(now) {
+                continue;
+            }
+
+            return None;
+        }
+    }
+
+    /// Performs one state transition, if any. Returns `true` if something has been done.
+    fn process_rounds(&mut self, now: &TNow) -> bool {
+        let round_start = self.current_round.start.clone().unwrap();
+        let last_round_estimate = self.previous_round_estimate();
+
+        // Primary proposal.
+        if !self.current_round.primary_propose_done {
+            self.current_round.primary_propose_done = true;
+
+            if let Some((local_index, _)) = &self.local_authority {
+                if *local_index == self.primary_index(self.current_round.number)
+                    && last_round_estimate.1 > self.finalized_block.1
+                {
+                    let (hash, number) = last_round_estimate;
+                    self.current_round.primary_proposal = Some((hash, number));
+                    let vote = self.sign(MessageKind::PrimaryPropose, hash, number);
+                    self.pending_events.push_back(Event::Vote(vote));
+                    return true;
+                }
+            }
+        }
+
+        let completable = self.is_completable(&self.current_round);
+
+        // Prevote.
+        if self.current_round.stage == RoundStage::Start
+            && (*now >= round_start.clone() + self.gossip_duration * 2 || completable)
+        {
+            self.current_round.stage = RoundStage::Prevoted;
+
+            if let Some((local_index, _)) = &self.local_authority {
+                let local_index = *local_index;
+                let (hash, number) = self.prevote_target();
+                let vote = self.sign(MessageKind::Prevote, hash, number);
+                let _ = self.current_round.prevotes.insert(
+                    local_index,
+                    Vote {
+                        target_hash: hash,
+                        target_number: number,
+                        signature: vote.signature,
+                    },
+                );
+                self.pending_events.push_back(Event::Vote(vote));
+            }
+
+            return true;
+        }
+
+        // Precommit.
+        if self.current_round.stage == RoundStage::Prevoted
+            && (*now >= round_start + self.gossip_duration * 4 || completable)
+        {
+            if let Some(prevote_ghost) = self.ghost(&self.current_round.prevotes) {
+                let last_round_estimate = (last_round_estimate.0, last_round_estimate.1);
+                if self.is_descendant_or_equal(
+                    (&prevote_ghost.0, prevote_ghost.1),
+                    (&last_round_estimate.0, last_round_estimate.1),
+                ) {
+                    self.current_round.stage = RoundStage::Precommitted;
+
+                    if let Some((local_index, _)) = &self.local_authority {
+                        let local_index = *local_index;
+                        let vote =
+                            self.sign(MessageKind::Precommit, prevote_ghost.0, prevote_ghost.1);
+                        let _ = self.current_round.precommits.insert(
+                            local_index,
+                            Vote {
+                                target_hash: prevote_ghost.0,
+                                target_number: prevote_ghost.1,
+                                signature: vote.signature,
+                            },
+                        );
+                        self.pending_events.push_back(Event::Vote(vote));
+                    }
+
+                    return true;
+                }
+            }
+        }
+
+        // Finalization.
+        let to_finalize = self
+            .previous_round
+            .iter()
+            .chain(Some(&self.current_round))
+            .find_map(|round| {
+                let (hash, number) = self.ghost(&round.precommits)?;
+                if number <= self.finalized_block.1 {
+                    return None;
+                }
+                Some((
+                    hash,
+                    number,
+                    self.build_justification(round, (&hash, number)),
+                ))
+            });
+        if let Some((hash, number, justification)) = to_finalize {
+            if self.local_authority.is_some() {
+                self.pending_events.push_back(Event::Commit(Commit {
+                    round_number: justification.round,
+                    set_id: self.set_id,
+                    target_hash: hash,
+                    target_number: justification.target_number,
+                    precommits: justification.precommits.clone(),
+                }));
+            }
+
+            self.finalize(hash, number, justification);
+            return true;
+        }
+
+        // Move on to the next round if the current one is completable.
+        if completable
+            && (self.current_round.stage == RoundStage::Precommitted
+                || self.local_authority.is_none())
+        {
+            let next_round = Round::new(self.current_round.number + 1, Some(now.clone()));
+            self.previous_round = Some(core::mem::replace(&mut self.current_round, next_round));
+            return true;
+        }
+
+        false
+    }
+
+    /// Index within [`Voter::authorities`] of the primary of the given round.
+    fn primary_index(&self, round_number: u64) -> usize {
+        // The modulo guarantees that the value fits in a `usize`.
+        usize::try_from(round_number % u64::try_from(self.authorities.len()).unwrap()).unwrap()
+    }
+
+    /// Returns the estimate of the previous round, or the finalized block if there is no
+    /// previous round or if the previous round doesn't have any estimate.
+    fn previous_round_estimate(&self) -> ([u8; 32], u64) {
+        self.previous_round
+            .as_ref()
+            .and_then(|r| self.estimate(r))
+            .filter(|e| e.1 >= self.finalized_block.1)
+            .unwrap_or(self.finalized_block)
+    }
+
+    /// Determines the block to prevote for.
+    fn prevote_target(&self) -> ([u8; 32], u64) {
+        let last_round_estimate = self.previous_round_estimate();
+
+        // The primary proposal is used if it is strictly after the last round estimate and is
+        // an ancestor of the previous round's prevote GHOST.
+        let previous_prevote_ghost = self
+            .previous_round
+            .as_ref()
+            .and_then(|r| self.ghost(&r.prevotes));
+        let find_descendant_of =
+            match (&self.current_round.primary_proposal, previous_prevote_ghost) {
+                (Some(proposal), Some(ghost))
+                    if proposal.1 > last_round_estimate.1
+                        && self.is_descendant_or_equal(
+                            (&ghost.0, ghost.1),
+                            (&proposal.0, proposal.1),
+                        ) =>
+                {
+                    *proposal
+                }
+                _ => last_round_estimate,
+            };
+
+        // TODO: apply voting rules, such as not voting for blocks too close to the head of the chain
+        let best_block_number = if self.best_block_hash == self.finalized_block.0 {
+            Some(self.finalized_block.1)
+        } else {
+            self.blocks.get(&self.best_block_hash).map(|b| b.number)
+        };
+
+        match best_block_number {
+            Some(best_number)
+                if self.is_descendant_or_equal(
+                    (&self.best_block_hash, best_number),
+                    (&find_descendant_of.0, find_descendant_of.1),
+                ) =>
+            {
+                (self.best_block_hash, best_number)
+            }
+            _ => find_descendant_of,
+        }
+    }
+
+    /// Returns `true` if `descendant` is equal to `ancestor` or one of its descendants.
+    ///
+    /// Only the blocks inserted with [`Voter::insert_block`] are taken into account. If the
+    /// ancestry can't be determined, `false` is returned.
+    fn is_descendant_or_equal(
+        &self,
+        descendant: (&[u8; 32], u64),
+        ancestor: (&[u8; 32], u64),
+    ) -> bool {
+        // `ancestor.1` is ignored if `ancestor` is the finalized block, which makes it possible
+        // for `set_finalized_block` to check the ancestry without knowing the finalized block
+        // number.
+        let ancestor_number = if *ancestor.0 == self.finalized_block.0 {
+            self.finalized_block.1
+        } else {
+            ancestor.1
+        };
+
+        if descendant.1 < ancestor_number {
+            return false;
+        }
+
+        let mut iter = (*descendant.0, descendant.1);
+        loop {
+            if iter.1 == ancestor_number {
+                return iter.0 == *ancestor.0;
+            }
+
+            match self.blocks.get(&iter.0) {
+                Some(block) if block.number == iter.1 => {
+                    iter = (block.parent_hash, iter.1 - 1);
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    /// Returns the total weight of the votes targeting the given block or one of its
+    /// descendants. Authorities that have equivocated count as voting for every block.
+    fn votes_weight(&self, votes: &Votes, block: (&[u8; 32], u64)) -> u64 {
+        let mut weight = 0u64;
+        for (authority_index, vote) in &votes.votes {
+            if votes.equivocations.contains_key(authority_index)
+                || self.is_descendant_or_equal((&vote.target_hash, vote.target_number), block)
+            {
+                weight = weight.saturating_add(self.authorities[*authority_index].weight.get());
+            }
+        }
+        weight
+    }
+
+    /// Total weight of the authorities that have cast a vote.
+    fn cast_weight(&self, votes: &Votes) -> u64 {
+        votes.votes.keys().fold(0u64, |acc, authority_index| {
+            acc.saturating_add(self.authorities[*authority_index].weight.get())
+        })
+    }
+
+    /// Returns the highest block that has been voted for by a super-majority of the authorities.
+    // TODO: O(n^2) complexity in the number of non-finalized blocks
+    fn ghost(&self, votes: &Votes) -> Option<([u8; 32], u64)> {
+        let candidates = self
+            .blocks
+            .iter()
+            .map(|(hash, block)| (*hash, block.number))
+            .chain(core::iter::once(self.finalized_block));
+
+        let mut best: Option<([u8; 32], u64)> = None;
+        for candidate in candidates {
+            if best.map_or(false, |b| (b.1, b.0) >= (candidate.1, candidate.0)) {
+                continue;
+            }
+
+            if self.votes_weight(votes, (&candidate.0, candidate.1)) >= self.threshold {
+                best = Some(candidate);
+            }
+        }
+
+        best
+    }
+
+    /// Returns the estimate of the given round, in other words the highest ancestor of the
+    /// prevote GHOST (inclusive) that could still possibly be finalized by the round.
+    fn estimate(&self, round: &Round<TNow>) -> Option<([u8; 32], u64)> {
+        let prevote_ghost = self.ghost(&round.prevotes)?;
+        let uncast_weight = self
+            .total_weight
+            .saturating_sub(self.cast_weight(&round.precommits));
+
+        let mut iter = prevote_ghost;
+        loop {
+            let possible = self
+                .votes_weight(&round.precommits, (&iter.0, iter.1))
+                .saturating_add(uncast_weight);
+            if possible >= self.threshold {
+                return Some(iter);
+            }
+
+            if iter.0 == self.finalized_block.0 {
+                return None;
+            }
+
+            let block = self.blocks.get(&iter.0)?;
+            iter = (block.parent_hash, block.number - 1);
+        }
+    }
+
+    /// Returns `true` if the given round is completable, in other words if its estimate can no
+    /// longer change.
+    fn is_completable(&self, round: &Round<TNow>) -> bool {
+        let prevote_ghost = match self.ghost(&round.prevotes) {
+            Some(g) => g,
+            None => return false,
+        };
+
+        let estimate = match self.estimate(round) {
+            Some(e) => e,
+            None => return false,
+        };
+
+        if estimate != prevote_ghost {
+            return true;
+        }
+
+        // The estimate is equal to the prevote GHOST. The round is completable only if none of
+        // the children of the prevote GHOST can possibly be finalized.
+        let uncast_weight = self
+            .total_weight
+            .saturating_sub(self.cast_weight(&round.precommits));
+        !self
+            .blocks
+            .iter()
+            .filter(|(_, b)| b.parent_hash == prevote_ghost.0 && b.number == prevote_ghost.1 + 1)
+            .any(|(hash, b)| {
+                self.votes_weight(&round.precommits, (hash, b.number))
+                    .saturating_add(uncast_weight)
+                    >= self.threshold
+            })
+    }
+
+    /// Builds a justification proving the finality of the given block using the precommits of
+    /// the given round.
+    fn build_justification(
+        &self,
+        round: &Round<TNow>,
+        target: (&[u8; 32], u64),
+    ) -> decode::Justification {
+        // At most one precommit per authority is included. For authorities that have
+        // equivocated, whichever of their two precommits targets the block is picked.
+        let precommits = round
+            .precommits
+            .votes
+            .iter()
+            .filter_map(|(authority_index, vote)| {
+                let descends = |vote: &Vote| {
+                    self.is_descendant_or_equal((&vote.target_hash, vote.target_number), target)
+                };
+                if descends(vote) {
+                    return Some((authority_index, vote));
+                }
+                round
+                    .precommits
+                    .equivocations
+                    .get(authority_index)
+                    .filter(|v| descends(v))
+                    .map(|v| (authority_index, v))
+            })
+            .map(|(authority_index, vote)| decode::Precommit {
+                target_hash: vote.target_hash,
+                // Block numbers that don't fit in a `u32` are never inserted.
+                target_number: u32::try_from(vote.target_number).unwrap(),
+                signature: vote.signature,
+                authority_public_key: self.authorities[*authority_index].public_key,
+            })
+            .collect();
+
+        decode::Justification {
+            round: round.number,
+            target_hash: *target.0,
+            target_number: u32::try_from(target.1).unwrap(),
+            precommits,
+        }
+    }
+
+    /// Updates the finalized block and generates a [`Event::Finalized`].
+    fn finalize(&mut self, hash: [u8; 32], number: u64, justification: decode::Justification) {
+        debug_assert!(number > self.finalized_block.1);
+        self.finalized_block = (hash, number);
+        self.prune_blocks();
+        self.pending_events.push_back(Event::Finalized {
+            hash,
+            number,
+            justification,
+        });
+    }
+
+    /// Removes from [`Voter::blocks`] all the blocks that don't descend from the finalized
+    /// block.
+    fn prune_blocks(&mut self) {
+        let to_remove = self
+            .blocks
+            .iter()
+            .filter(|(hash, block)| {
+                block.number <= self.finalized_block.1
+                    || !self.is_descendant_or_equal(
+                        (hash, block.number),
+                        (&self.finalized_block.0, self.finalized_block.1),
+                    )
+            })
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+
+        for hash in to_remove {
+            self.blocks.remove(&hash);
+        }
+    }
+
+    /// Signs a message of the current round with the local key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if there is no local authority.
+    ///
+    fn sign(&self, kind: MessageKind, target_hash: [u8; 32], target_number: u64) -> SignedVote {
+        let (local_index, signing_key) = self.local_authority.as_ref().unwrap();
+        // Block numbers that don't fit in a `u32` are never inserted.
+        let target_number = u32::try_from(target_number).unwrap();
+
+        let message = signed_message(
+            kind,
+            &target_hash,
+            target_number,
+            self.current_round.number,
+            self.set_id,
+        );
+
+        SignedVote {
+            round_number: self.current_round.number,
+            set_id: self.set_id,
+            kind,
+            target_hash,
+            target_number,
+            signature: signing_key.sign(&message).into(),
+            authority_public_key: self.authorities[*local_index].public_key,
+        }
+    }
+}
+
+impl<TNow> Round<TNow> {
+    fn new(number: u64, start: Option<TNow>) -> Self {
+        Round {
+            number,
+            start,
+            primary_proposal: None,
+            primary_propose_done: false,
+            stage: RoundStage::Start,
+            prevotes: Votes::default(),
+            precommits: Votes::default(),
+        }
+    }
+}
+
+impl Votes {
+    /// Inserts a vote. If the authority had already voted for a different block, the vote is
+    /// recorded as an equivocation and the first vote of the authority is returned.
+    fn insert(&mut self, authority_index: usize, vote: Vote) -> Result<Option<Vote>, VoteError> {
+        match self.votes.get(&authority_index) {
+            None => {
+                self.votes.insert(authority_index, vote);
+                Ok(None)
+            }
+            Some(existing) if existing.target_hash == vote.target_hash => Err(VoteError::Duplicate),
+            Some(existing) => {
+                if self.equivocations.contains_key(&authority_index) {
+                    return Err(VoteError::Duplicate);
+                }
+                let existing = existing.clone();
+                self.equivocations.insert(authority_index, vote);
+                Ok(Some(existing))
+            }
+        }
+    }
+}
+
+/// Event generated by [`Voter::next_event`].
+#[derive(Debug)]
+pub enum Event {
+    /// The local authority has cast a vote. This vote must be sent to the other nodes of the
+    /// network.
+    Vote(SignedVote),
+
+    /// A commit message must be sent to the other nodes of the network.
+    Commit(Commit),
+
+    /// A block has been finalized.
+    Finalized {
+        /// Hash of the newly-finalized block.
+        hash: [u8; 32],
+        /// Height of the newly-finalized block.
+        number: u64,
+        /// Justification proving the finality of the block.
+        justification: decode::Justification,
+    },
+
+    /// An authority has emitted two votes of the same kind targeting different blocks during the
+    /// same round. The proof can be reported to the runtime.
+    Equivocation(EquivocationProof),
+}
+
+/// Kind of message signed by an authority.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MessageKind {
+    Prevote,
+    Precommit,
+    PrimaryPropose,
+}
+
+/// Vote signed by the local authority.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedVote {
+    pub round_number: u64,
+    pub set_id: u64,
+    pub kind: MessageKind,
+    pub target_hash: [u8; 32],
+    pub target_number: u32,
+    pub signature: [u8; 64],
+    pub authority_public_key: [u8; 32],
+}
+
+impl SignedVote {
+    /// Returns the message to send on the network.
+    pub fn as_message(&self) -> VoteMessageRef {
+        VoteMessageRef {
+            round_number: self.round_number,
+            set_id: self.set_id,
+            message: match self.kind {
+                MessageKind::Prevote => MessageRef::Prevote(UnsignedPrevoteRef {
+                    target_hash: &self.target_hash,
+                    target_number: self.target_number,
+                }),
+                MessageKind::Precommit => MessageRef::Precommit(UnsignedPrecommitRef {
+                    target_hash: &self.target_hash,
+                    target_number: self.target_number,
+                }),
+                MessageKind::PrimaryPropose => MessageRef::PrimaryPropose(PrimaryProposeRef {
+                    target_hash: &self.target_hash,
+                    target_number: self.target_number,
+                }),
+            },
+            signature: &self.signature,
+            authority_public_key: &self.authority_public_key,
+        }
+    }
+}
+
+/// Commit message generated by the local authority.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub round_number: u64,
+    pub set_id: u64,
+    pub target_hash: [u8; 32],
+    pub target_number: u32,
+    pub precommits: Vec<decode::Precommit>,
+}
+
+impl Commit {
+    /// Returns the message to send on the network.
+    pub fn as_message(&self) -> CommitMessageRef {
+        CommitMessageRef {
+            round_number: self.round_number,
+            set_id: self.set_id,
+            message: CompactCommitRef {
+                target_hash: &self.target_hash,
+                target_number: self.target_number,
+                precommits: self
+                    .precommits
+                    .iter()
+                    .map(|p| UnsignedPrecommitRef {
+                        target_hash: &p.target_hash,
+                        target_number: p.target_number,
+                    })
+                    .collect(),
+                auth_data: self
+                    .precommits
+                    .iter()
+                    .map(|p| (&p.signature, &p.authority_public_key))
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// Error potentially returned by [`Voter::inject_vote`].
+#[derive(Debug, derive_more::Display)]
+pub enum VoteError {
+    /// Vote concerns a different authorities set.
+    BadSetId,
+    /// Vote concerns a round that is no longer tracked.
+    ObsoleteRound,
+    /// Vote concerns a round that hasn't started yet locally.
+    FutureRound,
+    /// Public key of the vote isn't part of the authorities set.
+    NotAuthority,
+    /// Signature of the vote is invalid.
+    BadSignature,
+    /// Primary proposal emitted by an authority that isn't the primary of the round.
+    NotPrimary,
+    /// Vote has already been received.
+    Duplicate,
+}
+
+/// Error potentially returned by [`Voter::inject_commit`].
+#[derive(Debug, derive_more::Display)]
+pub enum CommitError {
+    /// Commit concerns a different authorities set.
+    BadSetId,
+    /// Number of precommits doesn't match number of signatures.
+    BadFormat,
+    /// One of the public keys isn't part of the authorities set.
+    NotAuthority,
+    /// One authority has produced two signatures for the same block.
+    DuplicateSignature,
+    /// One of the signatures is invalid.
+    BadSignature,
+    /// Precommits don't reach the super-majority threshold.
+    NotEnoughWeight,
+    /// Block targeted by the commit isn't known locally.
+    UnknownTarget,
+}
+
+/// Builds an [`EquivocationProof`] from two votes of the same authority.
+fn equivocation_proof(
+    set_id: u64,
+    round_number: u64,
+    kind: VoteKind,
+    authority_public_key: [u8; 32],
+    first: &Vote,
+    second: &Vote,
+) -> EquivocationProof {
+    // Block numbers that don't fit in a `u32` are never inserted.
+    let to_proof_vote = |vote: &Vote| EquivocationVote {
+        target_hash: vote.target_hash,
+        target_number: u32::try_from(vote.target_number).unwrap(),
+        signature: vote.signature,
+    };
+
+    EquivocationProof {
+        set_id,
+        round_number,
+        kind,
+        authority_public_key,
+        first: to_proof_vote(first),
+        second: to_proof_vote(second),
+    }
+}
+
+/// Builds the message that is signed by authorities when voting.
+fn signed_message(
+    kind: MessageKind,
+    target_hash: &[u8; 32],
+    target_number: u32,
+    round_number: u64,
+    set_id: u64,
+) -> [u8; 1 + 32 + 4 + 8 + 8] {
+    let kind = match kind {
+        MessageKind::Prevote => 0,
+        MessageKind::Precommit => 1,
+        MessageKind::PrimaryPropose => 2,
+    };
+    vote_signed_message(kind, target_hash, target_number, round_number, set_id)
+}
+
+/// Returns `true` if the given ed25519 signature is valid.
+fn verify_signature(public_key: &[u8; 32], signature: &[u8; 64], message: &[u8]) -> bool {
+    let public_key = match ed25519_zebra::VerificationKey::try_from(*public_key) {
+        Ok(pk) => pk,
+        Err(_) => return false,
+    };
+
+    public_key
+        .verify(&ed25519_zebra::Signature::from(*signature), message)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests;
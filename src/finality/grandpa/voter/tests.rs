@@ -0,0 +1,560 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{signed_message, CommitError, Config, Event, MessageKind, VoteError, Voter};
+use crate::{
+    finality::{grandpa::equivocation::VoteKind, justification},
+    header,
+    network::protocol::{
+        CommitMessageRef, CompactCommitRef, MessageRef, UnsignedPrecommitRef, UnsignedPrevoteRef,
+        VoteMessageRef,
+    },
+};
+
+use core::{num::NonZeroU64, time::Duration};
+
+const GOSSIP_DURATION: Duration = Duration::from_secs(1);
+
+fn secret_key(n: u8) -> [u8; 32] {
+    [n; 32]
+}
+
+fn public_key(n: u8) -> [u8; 32] {
+    let signing_key = ed25519_zebra::SigningKey::from(secret_key(n));
+    ed25519_zebra::VerificationKeyBytes::from(ed25519_zebra::VerificationKey::from(&signing_key))
+        .into()
+}
+
+fn authorities(num: u8) -> Vec<header::GrandpaAuthority> {
+    (0..num)
+        .map(|n| header::GrandpaAuthority {
+            public_key: public_key(n),
+            weight: NonZeroU64::new(1).unwrap(),
+        })
+        .collect()
+}
+
+fn sign(
+    authority: u8,
+    kind: MessageKind,
+    target: &([u8; 32], u32),
+    round_number: u64,
+    set_id: u64,
+) -> [u8; 64] {
+    let message = signed_message(kind, &target.0, target.1, round_number, set_id);
+    ed25519_zebra::SigningKey::from(secret_key(authority))
+        .sign(&message)
+        .into()
+}
+
+/// Builds a voter with `num_authorities` authorities and a linear chain of `chain_len` blocks
+/// above the genesis. Block `n` has hash `[n; 32]`.
+fn build_voter(
+    authorities: &[header::GrandpaAuthority],
+    local_secret_key: Option<[u8; 32]>,
+    chain_len: u8,
+) -> Voter<Duration> {
+    let mut voter = Voter::new(Config {
+        authorities_set_id: 0,
+        authorities: header::GrandpaAuthoritiesIter::new(authorities),
+        finalized_block_hash: [0; 32],
+        finalized_block_number: 0,
+        start_round_number: 1,
+        local_secret_key,
+        gossip_duration: GOSSIP_DURATION,
+    });
+
+    for n in 1..=chain_len {
+        voter.insert_block([n; 32], u64::from(n), [n - 1; 32]);
+    }
+    voter.set_best_block([chain_len; 32]);
+    voter
+}
+
+#[test]
+fn single_local_authority_finalizes() {
+    let authorities = authorities(1);
+    let mut voter = build_voter(&authorities, Some(secret_key(0)), 3);
+    assert!(voter.is_authority());
+
+    assert!(voter.next_event(&Duration::from_secs(0)).is_none());
+    assert_eq!(voter.next_wake_up(), Some(Duration::from_secs(2)));
+
+    let now = Duration::from_secs(2);
+
+    match voter.next_event(&now) {
+        Some(Event::Vote(vote)) => {
+            assert_eq!(vote.kind, MessageKind::Prevote);
+            assert_eq!(vote.target_hash, [3; 32]);
+            assert_eq!(vote.target_number, 3);
+        }
+        _ => panic!(),
+    }
+
+    match voter.next_event(&now) {
+        Some(Event::Vote(vote)) => {
+            assert_eq!(vote.kind, MessageKind::Precommit);
+            assert_eq!(vote.target_hash, [3; 32]);
+        }
+        _ => panic!(),
+    }
+
+    match voter.next_event(&now) {
+        Some(Event::Commit(commit)) => {
+            assert_eq!(commit.round_number, 1);
+            assert_eq!(commit.target_hash, [3; 32]);
+            assert_eq!(commit.precommits.len(), 1);
+        }
+        _ => panic!(),
+    }
+
+    match voter.next_event(&now) {
+        Some(Event::Finalized {
+            hash,
+            number,
+            justification,
+        }) => {
+            assert_eq!(hash, [3; 32]);
+            assert_eq!(number, 3);
+            justification::verify::verify(justification::verify::Config {
+                justification: (&justification).into(),
+                authorities_set_id: 0,
+                authorities_list: authorities.iter().map(|a| a.public_key),
+            })
+            .unwrap();
+        }
+        _ => panic!(),
+    }
+
+    assert!(voter.next_event(&now).is_none());
+    assert_eq!(voter.current_round_number(), 2);
+    assert_eq!(voter.finalized_block(), (&[3; 32], 3));
+}
+
+#[test]
+fn observer_finalizes_with_super_majority() {
+    let authorities = authorities(4);
+    let mut voter = build_voter(&authorities, None, 2);
+    assert!(!voter.is_authority());
+    let now = Duration::from_secs(0);
+    assert!(voter.next_event(&now).is_none());
+
+    let target = ([2; 32], 2);
+
+    for authority in 0..3 {
+        voter
+            .inject_vote(VoteMessageRef {
+                round_number: 1,
+                set_id: 0,
+                message: MessageRef::Prevote(UnsignedPrevoteRef {
+                    target_hash: &target.0,
+                    target_number: target.1,
+                }),
+                signature: &sign(authority, MessageKind::Prevote, &target, 1, 0),
+                authority_public_key: &authorities[usize::from(authority)].public_key,
+            })
+            .unwrap();
+    }
+
+    // Two precommits aren't enough to finalize anything.
+    for authority in 0..2 {
+        voter
+            .inject_vote(VoteMessageRef {
+                round_number: 1,
+                set_id: 0,
+                message: MessageRef::Precommit(UnsignedPrecommitRef {
+                    target_hash: &target.0,
+                    target_number: target.1,
+                }),
+                signature: &sign(authority, MessageKind::Precommit, &target, 1, 0),
+                authority_public_key: &authorities[usize::from(authority)].public_key,
+            })
+            .unwrap();
+    }
+
+    assert!(voter.next_event(&now).is_none());
+    assert_eq!(voter.finalized_block(), (&[0; 32], 0));
+
+    voter
+        .inject_vote(VoteMessageRef {
+            round_number: 1,
+            set_id: 0,
+            message: MessageRef::Precommit(UnsignedPrecommitRef {
+                target_hash: &target.0,
+                target_number: target.1,
+            }),
+            signature: &sign(2, MessageKind::Precommit, &target, 1, 0),
+            authority_public_key: &authorities[2].public_key,
+        })
+        .unwrap();
+
+    match voter.next_event(&now) {
+        Some(Event::Finalized {
+            hash,
+            justification,
+            ..
+        }) => {
+            assert_eq!(hash, [2; 32]);
+            assert_eq!(justification.precommits.len(), 3);
+            justification::verify::verify(justification::verify::Config {
+                justification: (&justification).into(),
+                authorities_set_id: 0,
+                authorities_list: authorities.iter().map(|a| a.public_key),
+            })
+            .unwrap();
+        }
+        _ => panic!(),
+    }
+
+    // The round is completable, and the voter moves on to the next one.
+    assert!(voter.next_event(&now).is_none());
+    assert_eq!(voter.current_round_number(), 2);
+}
+
+#[test]
+fn invalid_votes_rejected() {
+    let authorities = authorities(4);
+    let mut voter = build_voter(&authorities, None, 2);
+    let target = ([2; 32], 2);
+
+    let vote = |authority: u8, round_number, set_id, signer: u8| {
+        (
+            round_number,
+            set_id,
+            sign(signer, MessageKind::Prevote, &target, round_number, set_id),
+            authorities
+                .get(usize::from(authority))
+                .map_or([0xff; 32], |a| a.public_key),
+        )
+    };
+
+    let mut inject =
+        |(round_number, set_id, signature, public_key): (u64, u64, [u8; 64], [u8; 32])| {
+            voter.inject_vote(VoteMessageRef {
+                round_number,
+                set_id,
+                message: MessageRef::Prevote(UnsignedPrevoteRef {
+                    target_hash: &target.0,
+                    target_number: target.1,
+                }),
+                signature: &signature,
+                authority_public_key: &public_key,
+            })
+        };
+
+    assert!(matches!(inject(vote(0, 1, 1, 0)), Err(VoteError::BadSetId)));
+    assert!(matches!(
+        inject(vote(0, 2, 0, 0)),
+        Err(VoteError::FutureRound)
+    ));
+    assert!(matches!(
+        inject(vote(7, 1, 0, 7)),
+        Err(VoteError::NotAuthority)
+    ));
+    assert!(matches!(
+        inject(vote(0, 1, 0, 1)),
+        Err(VoteError::BadSignature)
+    ));
+    assert!(inject(vote(0, 1, 0, 0)).is_ok());
+    assert!(matches!(
+        inject(vote(0, 1, 0, 0)),
+        Err(VoteError::Duplicate)
+    ));
+}
+
+#[test]
+fn commit_finalizes() {
+    let authorities = authorities(4);
+    let mut voter = build_voter(&authorities, None, 2);
+    let target = ([1; 32], 1);
+    let now = Duration::from_secs(0);
+
+    let signatures = (0..3u8)
+        .map(|n| sign(n, MessageKind::Precommit, &target, 5, 0))
+        .collect::<Vec<_>>();
+
+    // Not enough signatures.
+    assert!(matches!(
+        voter.inject_commit(CommitMessageRef {
+            round_number: 5,
+            set_id: 0,
+            message: CompactCommitRef {
+                target_hash: &target.0,
+                target_number: target.1,
+                precommits: (0..2)
+                    .map(|_| UnsignedPrecommitRef {
+                        target_hash: &target.0,
+                        target_number: target.1,
+                    })
+                    .collect(),
+                auth_data: (0..2)
+                    .map(|n| (&signatures[n], &authorities[n].public_key))
+                    .collect(),
+            },
+        }),
+        Err(CommitError::NotEnoughWeight)
+    ));
+
+    voter
+        .inject_commit(CommitMessageRef {
+            round_number: 5,
+            set_id: 0,
+            message: CompactCommitRef {
+                target_hash: &target.0,
+                target_number: target.1,
+                precommits: (0..3)
+                    .map(|_| UnsignedPrecommitRef {
+                        target_hash: &target.0,
+                        target_number: target.1,
+                    })
+                    .collect(),
+                auth_data: (0..3)
+                    .map(|n| (&signatures[n], &authorities[n].public_key))
+                    .collect(),
+            },
+        })
+        .unwrap();
+
+    match voter.next_event(&now) {
+        Some(Event::Finalized { hash, number, .. }) => {
+            assert_eq!(hash, [1; 32]);
+            assert_eq!(number, 1);
+        }
+        _ => panic!(),
+    }
+
+    assert!(voter.next_event(&now).is_none());
+    assert_eq!(voter.current_round_number(), 6);
+}
+
+#[test]
+fn double_prevote_reported() {
+    let authorities = authorities(4);
+    let mut voter = build_voter(&authorities, None, 2);
+    let now = Duration::from_secs(0);
+    assert!(voter.next_event(&now).is_none());
+
+    let mut prevote = |target: ([u8; 32], u32)| {
+        voter.inject_vote(VoteMessageRef {
+            round_number: 1,
+            set_id: 0,
+            message: MessageRef::Prevote(UnsignedPrevoteRef {
+                target_hash: &target.0,
+                target_number: target.1,
+            }),
+            signature: &sign(1, MessageKind::Prevote, &target, 1, 0),
+            authority_public_key: &authorities[1].public_key,
+        })
+    };
+
+    prevote(([1; 32], 1)).unwrap();
+    prevote(([2; 32], 2)).unwrap();
+    assert!(matches!(prevote(([2; 32], 2)), Err(VoteError::Duplicate)));
+
+    match voter.next_event(&now) {
+        Some(Event::Equivocation(proof)) => {
+            assert_eq!(proof.set_id, 0);
+            assert_eq!(proof.round_number, 1);
+            assert_eq!(proof.kind, VoteKind::Prevote);
+            assert_eq!(proof.authority_public_key, authorities[1].public_key);
+            assert_eq!(proof.first.target_hash, [1; 32]);
+            assert_eq!(proof.second.target_hash, [2; 32]);
+            assert_eq!(
+                proof.second.signature,
+                sign(1, MessageKind::Prevote, &([2; 32], 2), 1, 0)
+            );
+        }
+        _ => panic!(),
+    }
+
+    assert!(voter.next_event(&now).is_none());
+}
+
+#[test]
+fn commit_conflicting_with_precommit_reported() {
+    let authorities = authorities(4);
+    let mut voter = build_voter(&authorities, None, 2);
+    let now = Duration::from_secs(0);
+    assert!(voter.next_event(&now).is_none());
+
+    let first_target = ([1; 32], 1);
+    voter
+        .inject_vote(VoteMessageRef {
+            round_number: 1,
+            set_id: 0,
+            message: MessageRef::Precommit(UnsignedPrecommitRef {
+                target_hash: &first_target.0,
+                target_number: first_target.1,
+            }),
+            signature: &sign(0, MessageKind::Precommit, &first_target, 1, 0),
+            authority_public_key: &authorities[0].public_key,
+        })
+        .unwrap();
+
+    let target = ([2; 32], 2);
+    let signatures = (0..3u8)
+        .map(|n| sign(n, MessageKind::Precommit, &target, 1, 0))
+        .collect::<Vec<_>>();
+
+    // A commit without enough weight is refused, and its precommits aren't imported.
+    assert!(matches!(
+        voter.inject_commit(CommitMessageRef {
+            round_number: 1,
+            set_id: 0,
+            message: CompactCommitRef {
+                target_hash: &target.0,
+                target_number: target.1,
+                precommits: (0..2)
+                    .map(|_| UnsignedPrecommitRef {
+                        target_hash: &target.0,
+                        target_number: target.1,
+                    })
+                    .collect(),
+                auth_data: (0..2)
+                    .map(|n| (&signatures[n], &authorities[n].public_key))
+                    .collect(),
+            },
+        }),
+        Err(CommitError::NotEnoughWeight)
+    ));
+    assert!(voter.next_event(&now).is_none());
+
+    voter
+        .inject_commit(CommitMessageRef {
+            round_number: 1,
+            set_id: 0,
+            message: CompactCommitRef {
+                target_hash: &target.0,
+                target_number: target.1,
+                precommits: (0..3)
+                    .map(|_| UnsignedPrecommitRef {
+                        target_hash: &target.0,
+                        target_number: target.1,
+                    })
+                    .collect(),
+                auth_data: (0..3)
+                    .map(|n| (&signatures[n], &authorities[n].public_key))
+                    .collect(),
+            },
+        })
+        .unwrap();
+
+    match voter.next_event(&now) {
+        Some(Event::Equivocation(proof)) => {
+            assert_eq!(proof.kind, VoteKind::Precommit);
+            assert_eq!(proof.authority_public_key, authorities[0].public_key);
+            assert_eq!(proof.first.target_hash, first_target.0);
+            assert_eq!(proof.second.target_hash, target.0);
+        }
+        _ => panic!(),
+    }
+
+    match voter.next_event(&now) {
+        Some(Event::Finalized { hash, .. }) => assert_eq!(hash, target.0),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn commit_equivocation_reported() {
+    let authorities = authorities(4);
+    let mut voter = build_voter(&authorities, None, 2);
+    let now = Duration::from_secs(0);
+    assert!(voter.next_event(&now).is_none());
+
+    // Authority 0 precommits for both blocks 1 and 2 in a round that isn't tracked by the voter.
+    // Its weight counts towards the threshold.
+    let target = ([1; 32], 1);
+    let other_target = ([2; 32], 2);
+    let votes = [
+        (0u8, target),
+        (1, other_target),
+        (0, other_target),
+        (2, target),
+    ];
+    let signatures = votes
+        .iter()
+        .map(|(n, t)| sign(*n, MessageKind::Precommit, t, 5, 0))
+        .collect::<Vec<_>>();
+
+    // Builds a commit containing the votes at the given indices.
+    let commit = |indices: &[usize]| CommitMessageRef {
+        round_number: 5,
+        set_id: 0,
+        message: CompactCommitRef {
+            target_hash: &target.0,
+            target_number: target.1,
+            precommits: indices
+                .iter()
+                .map(|i| UnsignedPrecommitRef {
+                    target_hash: &votes[*i].1 .0,
+                    target_number: votes[*i].1 .1,
+                })
+                .collect(),
+            auth_data: indices
+                .iter()
+                .map(|i| {
+                    (
+                        &signatures[*i],
+                        &authorities[usize::from(votes[*i].0)].public_key,
+                    )
+                })
+                .collect(),
+        },
+    };
+
+    // Two precommits of the same authority for the same block are refused.
+    assert!(matches!(
+        voter.inject_commit(commit(&[0, 1, 0, 3])),
+        Err(CommitError::DuplicateSignature)
+    ));
+    assert!(voter.next_event(&now).is_none());
+
+    voter.inject_commit(commit(&[0, 1, 2, 3])).unwrap();
+
+    match voter.next_event(&now) {
+        Some(Event::Equivocation(proof)) => {
+            assert_eq!(proof.round_number, 5);
+            assert_eq!(proof.kind, VoteKind::Precommit);
+            assert_eq!(proof.authority_public_key, authorities[0].public_key);
+            assert_eq!(proof.first.target_hash, target.0);
+            assert_eq!(proof.second.target_hash, other_target.0);
+            assert_eq!(proof.second.signature, signatures[2]);
+        }
+        _ => panic!(),
+    }
+
+    match voter.next_event(&now) {
+        Some(Event::Finalized {
+            hash,
+            justification,
+            ..
+        }) => {
+            assert_eq!(hash, target.0);
+            let equivocations = justification::verify::verify(justification::verify::Config {
+                justification: (&justification).into(),
+                authorities_set_id: 0,
+                authorities_list: authorities.iter().map(|a| a.public_key),
+            })
+            .unwrap();
+            assert_eq!(equivocations.len(), 1);
+        }
+        _ => panic!(),
+    }
+}
@@ -15,9 +15,16 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::finality::justification::decode;
+use crate::{
+    finality::{
+        grandpa::equivocation::{EquivocationProof, EquivocationVote, VoteKind},
+        justification::decode,
+    },
+    network::protocol,
+};
 
 use alloc::vec::Vec;
+use core::convert::TryFrom as _;
 
 /// Configuration for a justification verification process.
 #[derive(Debug)]
@@ -37,17 +44,25 @@ pub struct Config<'a, I> {
 // TODO: rewrite as a generator-style process?
 
 /// Verifies that a justification is valid.
-pub fn verify(config: Config<impl Iterator<Item = impl AsRef<[u8]>> + Clone>) -> Result<(), Error> {
+///
+/// An authority that has precommitted for two different blocks is an equivocator. Its weight
+/// still counts towards the threshold, and the justification is considered valid. On success,
+/// returns the proofs of the equivocations found in the justification.
+pub fn verify(
+    config: Config<impl Iterator<Item = impl AsRef<[u8]>> + Clone>,
+) -> Result<Vec<EquivocationProof>, Error> {
+    // The logic of the check is `actual >= (expected * 2 / 3) + 1`.
+    let threshold = (config.authorities_list.clone().count() * 2 / 3) + 1;
+
     // Check that justification contains a number of signatures equal to at least 2/3rd of the
     // number of authorities.
     // Duplicate signatures are checked below.
-    // The logic of the check is `actual >= (expected * 2 / 3) + 1`.
-    if config.justification.precommits.iter().count()
-        < (config.authorities_list.clone().count() * 2 / 3) + 1
-    {
+    if config.justification.precommits.iter().count() < threshold {
         return Err(Error::NotEnoughSignatures);
     }
 
+    let mut equivocations = Vec::new();
+
     // Verifying all the signatures together brings better performances than verifying them one
     // by one.
     // Note that batched ed25519 verification has some issues. The code below uses a special
@@ -65,26 +80,42 @@ pub fn verify(config: Config<impl Iterator<Item = impl AsRef<[u8]>> + Clone>) ->
             return Err(Error::NotAuthority(*precommit.authority_public_key));
         }
 
-        if config
+        if let Some(first) = config
             .justification
             .precommits
             .iter()
-            .skip(precommit_num.saturating_add(1))
-            .any(|pc| pc.authority_public_key == precommit.authority_public_key)
+            .take(precommit_num)
+            .find(|pc| pc.authority_public_key == precommit.authority_public_key)
         {
-            return Err(Error::DuplicateSignature(*precommit.authority_public_key));
+            // Two precommits targeting different blocks are an equivocation. Both signatures are
+            // verified below alongside the other ones.
+            if first.target_hash == precommit.target_hash
+                || equivocations.iter().any(|e: &EquivocationProof| {
+                    e.authority_public_key == *precommit.authority_public_key
+                })
+            {
+                return Err(Error::DuplicateSignature(*precommit.authority_public_key));
+            }
+
+            let to_proof_vote = |pc: &decode::PrecommitRef| EquivocationVote {
+                target_hash: *pc.target_hash,
+                target_number: pc.target_number,
+                signature: *pc.signature,
+            };
+
+            equivocations.push(EquivocationProof {
+                set_id: config.authorities_set_id,
+                round_number: config.justification.round,
+                kind: VoteKind::Precommit,
+                authority_public_key: *precommit.authority_public_key,
+                first: to_proof_vote(&first),
+                second: to_proof_vote(&precommit),
+            });
         }
 
         // TODO: must check signed block ancestry using `votes_ancestries`
 
-        let mut msg = Vec::with_capacity(1 + 32 + 4 + 8 + 8);
-        msg.push(1u8); // This `1` indicates which kind of message is being signed.
-        msg.extend_from_slice(&precommit.target_hash[..]);
-        msg.extend_from_slice(&u32::to_le_bytes(precommit.target_number)[..]);
-        msg.extend_from_slice(&u64::to_le_bytes(config.justification.round)[..]);
-        msg.extend_from_slice(&u64::to_le_bytes(config.authorities_set_id)[..]);
-        debug_assert_eq!(msg.len(), msg.capacity());
-
+        let msg = signed_message(&config, &precommit);
         batch.queue(ed25519_zebra::batch::Item::from((
             ed25519_zebra::VerificationKeyBytes::from(*precommit.authority_public_key),
             ed25519_zebra::Signature::from(*precommit.signature),
@@ -92,17 +123,60 @@ pub fn verify(config: Config<impl Iterator<Item = impl AsRef<[u8]>> + Clone>) ->
         )));
     }
 
+    // Equivocators are only counted once.
+    if config.justification.precommits.iter().count() - equivocations.len() < threshold {
+        return Err(Error::NotEnoughSignatures);
+    }
+
     // Actual signatures verification performed here.
     // TODO: thread_rng()?!?! what to do here?
     // TODO: ed25519_zebra depends on rand_core 0.5, which forces us to use an older version of rand; really annoying
-    batch
-        .verify(rand7::thread_rng())
-        .map_err(|_| Error::BadSignature)?;
+    if batch.verify(rand7::thread_rng()).is_err() {
+        // Find out which signature is invalid in order to report it.
+        let authority_public_key = config
+            .justification
+            .precommits
+            .iter()
+            .find(|pc| !is_signature_valid(&config, pc))
+            .map(|pc| *pc.authority_public_key);
+        return Err(Error::BadSignature(authority_public_key));
+    }
 
     // TODO: must check that votes_ancestries doesn't contain any unused entry
     // TODO: there's also a "ghost" thing?
 
-    Ok(())
+    Ok(equivocations)
+}
+
+/// Builds the message signed by the authority that has emitted the given precommit.
+fn signed_message<I>(
+    config: &Config<I>,
+    precommit: &decode::PrecommitRef,
+) -> [u8; 1 + 32 + 4 + 8 + 8] {
+    // The `1` indicates that the message being signed is a precommit.
+    protocol::vote_signed_message(
+        1,
+        precommit.target_hash,
+        precommit.target_number,
+        config.justification.round,
+        config.authorities_set_id,
+    )
+}
+
+/// Verifies the signature of a single precommit.
+fn is_signature_valid<I>(config: &Config<I>, precommit: &decode::PrecommitRef) -> bool {
+    let public_key = match ed25519_zebra::VerificationKey::try_from(*precommit.authority_public_key)
+    {
+        Ok(pk) => pk,
+        Err(_) => return false,
+    };
+
+    public_key
+        .verify(
+            &ed25519_zebra::Signature::from(*precommit.signature),
+            &signed_message(config, precommit),
+        )
+        .is_ok()
 }
 
 /// Error that can happen while verifying a justification.
@@ -110,9 +184,12 @@ pub fn verify(config: Config<impl Iterator<Item = impl AsRef<[u8]>> + Clone>) ->
 pub enum Error {
     /// One of the public keys is invalid.
     BadPublicKey,
-    /// One of the signatures can't be verified.
-    BadSignature,
-    /// One authority has produced two signatures.
+    /// One of the signatures can't be verified. Contains the public key of the authority whose
+    /// signature is invalid, if it could be determined.
+    #[display(fmt = "One of the signatures can't be verified")]
+    BadSignature(Option<[u8; 32]>),
+    /// One authority has produced two signatures for the same block, or more than two
+    /// signatures.
     #[display(fmt = "One authority has produced two signatures")]
     DuplicateSignature([u8; 32]),
     /// One of the public keys isn't in the list of authorities.
@@ -121,3 +198,118 @@ pub enum Error {
     /// Justification doesn't contain enough authorities signatures to be valid.
     NotEnoughSignatures,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, Config, Error};
+    use crate::{
+        finality::{
+            grandpa::equivocation::{EquivocationProof, VoteKind},
+            justification::decode,
+        },
+        network::protocol,
+    };
+
+    fn secret_key(n: u8) -> [u8; 32] {
+        [n; 32]
+    }
+
+    fn public_key(n: u8) -> [u8; 32] {
+        let signing_key = ed25519_zebra::SigningKey::from(secret_key(n));
+        ed25519_zebra::VerificationKeyBytes::from(ed25519_zebra::VerificationKey::from(
+            &signing_key,
+        ))
+        .into()
+    }
+
+    /// Builds a precommit of round 1 of set 0, signed by the given authority.
+    fn precommit(authority: u8, signer: u8, target: ([u8; 32], u32)) -> decode::Precommit {
+        let message = protocol::vote_signed_message(1, &target.0, target.1, 1, 0);
+        decode::Precommit {
+            target_hash: target.0,
+            target_number: target.1,
+            signature: ed25519_zebra::SigningKey::from(secret_key(signer))
+                .sign(&message)
+                .into(),
+            authority_public_key: public_key(authority),
+        }
+    }
+
+    fn verify_with_4_authorities(
+        justification: &decode::Justification,
+    ) -> Result<Vec<EquivocationProof>, Error> {
+        let authorities = (0..4).map(public_key).collect::<Vec<_>>();
+        verify(Config {
+            justification: justification.into(),
+            authorities_set_id: 0,
+            authorities_list: authorities.iter(),
+        })
+    }
+
+    #[test]
+    fn valid_justification() {
+        let justification = decode::Justification {
+            round: 1,
+            target_hash: [2; 32],
+            target_number: 2,
+            precommits: (0..3).map(|n| precommit(n, n, ([2; 32], 2))).collect(),
+        };
+
+        assert!(verify_with_4_authorities(&justification)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn justification_equivocation_detected() {
+        let mut justification = decode::Justification {
+            round: 1,
+            target_hash: [2; 32],
+            target_number: 2,
+            precommits: vec![
+                precommit(0, 0, ([2; 32], 2)),
+                precommit(1, 1, ([2; 32], 2)),
+                precommit(2, 2, ([2; 32], 2)),
+                precommit(1, 1, ([1; 32], 1)),
+            ],
+        };
+
+        // The equivocator counts towards the threshold, and the justification is valid.
+        let equivocations = verify_with_4_authorities(&justification).unwrap();
+        assert_eq!(equivocations.len(), 1);
+        assert_eq!(equivocations[0].round_number, 1);
+        assert_eq!(equivocations[0].kind, VoteKind::Precommit);
+        assert_eq!(equivocations[0].authority_public_key, public_key(1));
+        assert_eq!(equivocations[0].first.target_hash, [2; 32]);
+        assert_eq!(equivocations[0].second.target_hash, [1; 32]);
+
+        // The equivocator is only counted once.
+        justification.precommits.remove(2);
+        assert!(matches!(
+            verify_with_4_authorities(&justification),
+            Err(Error::NotEnoughSignatures)
+        ));
+
+        // Two precommits of the same authority for the same block aren't an equivocation.
+        justification.precommits[2] = precommit(1, 1, ([2; 32], 2));
+        justification.precommits.push(precommit(2, 2, ([2; 32], 2)));
+        assert!(matches!(
+            verify_with_4_authorities(&justification),
+            Err(Error::DuplicateSignature(key)) if key == public_key(1)
+        ));
+
+        // A precommit with an invalid signature is reported as such, even if it equivocates.
+        justification.precommits[2] = precommit(1, 3, ([1; 32], 1));
+        match verify_with_4_authorities(&justification) {
+            Err(Error::BadSignature(Some(key))) => assert_eq!(key, public_key(1)),
+            _ => panic!(),
+        }
+
+        justification.precommits.remove(2);
+        justification.precommits[2] = precommit(2, 3, ([2; 32], 2));
+        match verify_with_4_authorities(&justification) {
+            Err(Error::BadSignature(Some(key))) => assert_eq!(key, public_key(2)),
+            _ => panic!(),
+        }
+    }
+}
@@ -385,6 +385,21 @@ impl<'a> DigestRef<'a> {
         self.logs().any(|l| l.is_babe())
     }
 
+    /// Returns true if the list contains a GrandPa scheduled or forced change of the list of
+    /// authorities.
+    ///
+    /// > **Note**: The justification of a block containing such a change is necessary in order
+    /// >           to verify the justifications of its descendants.
+    pub fn has_grandpa_authorities_change(&self) -> bool {
+        self.logs().any(|l| {
+            matches!(
+                l,
+                DigestItemRef::GrandpaConsensus(GrandpaConsensusLogRef::ScheduledChange(_))
+                    | DigestItemRef::GrandpaConsensus(GrandpaConsensusLogRef::ForcedChange { .. })
+            )
+        })
+    }
+
     /// Returns the Aura seal digest item, if any.
     pub fn aura_seal(&self) -> Option<&'a [u8; 64]> {
         if let Some(aura_seal_index) = self.aura_seal_index {
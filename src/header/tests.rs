@@ -40,3 +40,25 @@ fn decode_rococo() {
     ])
     .unwrap();
 }
+
+#[test]
+fn grandpa_authorities_change_detected() {
+    assert!(!super::DigestRef::empty().has_grandpa_authorities_change());
+
+    let items = [super::DigestItem::GrandpaConsensus(
+        super::GrandpaConsensusLog::ScheduledChange(super::GrandpaScheduledChange {
+            next_authorities: Vec::new(),
+            delay: 0,
+        }),
+    )];
+    assert!(super::DigestRef::from_slice(&items)
+        .unwrap()
+        .has_grandpa_authorities_change());
+
+    let items = [super::DigestItem::GrandpaConsensus(
+        super::GrandpaConsensusLog::OnDisabled(0),
+    )];
+    assert!(!super::DigestRef::from_slice(&items)
+        .unwrap()
+        .has_grandpa_authorities_change());
+}
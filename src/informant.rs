@@ -41,6 +41,8 @@
 //!     best_hash: &[0x12, 0x34, 0x56, 0x76],
 //!     finalized_hash: &[0xaa, 0xbb, 0xcc, 0xdd],
 //!     network_known_best: Some(224),
+//!     bandwidth_download: Some(23_000),
+//!     bandwidth_upload: Some(4_500),
 //! });
 //! ```
 
@@ -74,6 +76,10 @@ pub struct InformantLine<'a> {
     pub finalized_number: u64,
     /// Hash of the latest finalized block we have locally.
     pub finalized_hash: &'a [u8],
+    /// Number of bytes per second received from the network. `None` if unknown.
+    pub bandwidth_download: Option<u64>,
+    /// Number of bytes per second sent to the network. `None` if unknown.
+    pub bandwidth_upload: Option<u64>,
 }
 
 /// Extra fields if a relay chain exists.
@@ -125,20 +131,32 @@ impl<'a> fmt::Display for InformantLine<'a> {
             (header, header_len)
         };
 
+        let bandwidth = match (self.bandwidth_download, self.bandwidth_upload) {
+            (None, None) => String::new(),
+            (download, upload) => format!(
+                " ⬇ {:>9} ⬆ {:>9}",
+                BandwidthDisplay(download),
+                BandwidthDisplay(upload)
+            ),
+        };
+
         // TODO: it's a bit of a clusterfuck to properly align because the emoji eats a whitespace
         let trailer = format!(
-            "] {white_bold}#{network_best}{reset} (🌐{white_bold}{connec:>4}{reset})   ",
+            "] {white_bold}#{network_best}{reset} (🌐{white_bold}{connec:>4}{reset}){bandwidth}   ",
             network_best = self.network_known_best.unwrap_or(0),
             connec = self.num_network_connections,
+            bandwidth = bandwidth,
             white_bold = white_bold,
             reset = reset,
         );
         let trailer_len = format!(
-            "] #{network_best} (  {connec:>4})   ",
+            "] #{network_best} (  {connec:>4}){bandwidth}   ",
             network_best = self.network_known_best.unwrap_or(0),
             connec = self.num_network_connections,
+            bandwidth = bandwidth,
         )
-        .len();
+        .chars()
+        .count();
 
         let bar_width = self
             .max_line_width
@@ -189,6 +207,28 @@ impl<'a> fmt::Display for InformantLine<'a> {
     }
 }
 
+/// Implements `fmt::Display` and displays a number of bytes per second in a human-readable way.
+struct BandwidthDisplay(Option<u64>);
+
+impl fmt::Display for BandwidthDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes_per_sec = match self.0 {
+            Some(b) => b,
+            None => return f.pad("?"),
+        };
+
+        let string = if bytes_per_sec < 1024 {
+            format!("{} B/s", bytes_per_sec)
+        } else if bytes_per_sec < 1024 * 1024 {
+            format!("{:.1} kiB/s", bytes_per_sec as f64 / 1024.0)
+        } else {
+            format!("{:.1} MiB/s", bytes_per_sec as f64 / (1024.0 * 1024.0))
+        };
+
+        f.pad(&string)
+    }
+}
+
 /// Implements `fmt::Display` and displays hashes in a nice way.
 pub struct HashDisplay<'a>(pub &'a [u8]);
 
@@ -22,6 +22,7 @@ use crate::util;
 
 use alloc::{
     boxed::Box,
+    collections::BTreeMap,
     format,
     string::{String, ToString as _},
     vec::Vec,
@@ -315,7 +316,7 @@ define_methods! {
     system_localListenAddresses() -> Vec<String>,
     system_localPeerId() -> &'a str,
     system_name() -> &'a str,
-    system_networkState() -> (), // TODO:
+    system_networkState() -> NetworkState,
     system_nodeRoles() -> (), // TODO:
     system_peers() -> Vec<SystemPeer>,
     system_properties() -> Box<serde_json::value::RawValue>,
@@ -478,6 +479,40 @@ pub struct SystemHealth {
     pub should_have_peers: bool,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NetworkState {
+    /// Number of bytes received on all the connections since the node has started.
+    #[serde(rename = "totalBytesInbound")]
+    pub total_bytes_inbound: u64,
+    /// Number of bytes sent on all the connections since the node has started.
+    #[serde(rename = "totalBytesOutbound")]
+    pub total_bytes_outbound: u64,
+    /// State of each connected peer, indexed by its base58-encoded `PeerId`.
+    #[serde(rename = "connectedPeers")]
+    pub connected_peers: BTreeMap<String, NetworkStatePeer>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NetworkStatePeer {
+    /// Number of bytes received from this peer on the currently open connections.
+    #[serde(rename = "bytesInbound")]
+    pub bytes_inbound: u64,
+    /// Number of bytes sent to this peer on the currently open connections.
+    #[serde(rename = "bytesOutbound")]
+    pub bytes_outbound: u64,
+    /// Number of bytes transferred on each protocol, indexed by protocol name.
+    /// Non-standard field.
+    pub protocols: BTreeMap<String, NetworkStateProtocol>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NetworkStateProtocol {
+    #[serde(rename = "bytesInbound")]
+    pub bytes_inbound: u64,
+    #[serde(rename = "bytesOutbound")]
+    pub bytes_outbound: u64,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct SystemPeer {
     #[serde(rename = "peerId")]
@@ -487,6 +522,10 @@ pub struct SystemPeer {
     pub best_hash: HashHexString,
     #[serde(rename = "bestNumber")]
     pub best_number: u64,
+    /// Round-trip time, in milliseconds, of the latest successful ping sent to this peer.
+    /// Non-standard field.
+    #[serde(rename = "pingTimeMs", skip_serializing_if = "Option::is_none")]
+    pub ping_time_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -97,8 +97,8 @@
 use alloc::{string::String, sync::Arc, vec::Vec};
 use connection::established;
 use core::{
-    iter, mem,
-    num::NonZeroUsize,
+    mem,
+    num::{NonZeroU32, NonZeroUsize},
     ops::{Add, Sub},
     pin::Pin,
     task::{Context, Poll},
@@ -117,7 +117,7 @@ pub mod discovery;
 pub mod peer_id;
 pub mod peerset;
 
-pub use established::{ConfigRequestResponse, ConfigRequestResponseIn};
+pub use established::{Bandwidth, ConfigRequestResponse, ConfigRequestResponseIn};
 pub use multiaddr::Multiaddr;
 #[doc(inline)]
 pub use parity_multiaddr as multiaddr;
@@ -143,6 +143,46 @@ pub struct Config<TPeer> {
     /// Name of the ping protocol on the network.
     pub ping_protocol: String,
 
+    /// Time between two consecutive pings sent to each connection.
+    pub ping_interval: Duration,
+
+    /// Maximum time a remote can take to answer a ping before the ping is considered failed.
+    pub ping_timeout: Duration,
+
+    /// Number of consecutive failed pings after which a connection is closed.
+    pub max_ping_failures: NonZeroU32,
+
+    /// Maximum number of substreams simultaneously open on each connection, both inbound and
+    /// outbound substreams combined. Substreams that a remote tries to open beyond this limit
+    /// are automatically refused.
+    pub max_simultaneous_substreams: usize,
+
+    /// Number of bytes that a remote is allowed to send on each yamux substream before having
+    /// to wait for the local node to process the data. Has no effect on mplex connections.
+    ///
+    /// The yamux protocol gives an implicit window of 256 kiB to each new substream. Values
+    /// below 256 kiB are equivalent to 256 kiB.
+    pub yamux_substream_receive_window: u64,
+
+    /// Maximum time between the moment when [`Network::fill_out_slots`] starts a connection
+    /// attempt and the end of the handshake of this connection. Includes the time necessary to
+    /// reach the target (see [`StartConnect::timeout`]).
+    pub handshake_timeout: Duration,
+
+    /// Duration during which an address isn't dialed again after a first failed dialing
+    /// attempt. Doubled after each consecutive failure.
+    pub dial_backoff_base: Duration,
+
+    /// Maximum duration during which an address isn't dialed again after a failed dialing
+    /// attempt.
+    pub dial_backoff_max: Duration,
+
+    /// Reputation below which a node gets banned. See [`Network::report_peer`].
+    pub ban_threshold: i32,
+
+    /// Duration during which a node that has been banned isn't connected to.
+    pub ban_duration: Duration,
+
     pub known_nodes: Vec<(TPeer, PeerId, Multiaddr)>,
 
     /// Key used for the encryption layer.
@@ -199,7 +239,16 @@ pub struct PendingId(peerset::ConnectionId); // TODO: must never be reused /!\
 
 /// Identifier of a connection spawned by the [`Network`].
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct ConnectionId(peerset::ConnectionId); // TODO: must never be reused /!\
+pub struct ConnectionId(ConnectionIdInner); // TODO: must never be reused /!\
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum ConnectionIdInner {
+    /// Connection stored in [`Guarded::peerset`].
+    Peerset(peerset::ConnectionId),
+    /// Connection added through [`Network::add_incoming_connection`]. Index within
+    /// [`Guarded::incoming_connections`].
+    Incoming(usize),
+}
 
 /// Data structure containing the list of all connections, pending or not, and their latest known
 /// state. See also [the module-level documentation](..).
@@ -219,11 +268,33 @@ pub struct Network<TNow, TPeer, TConn> {
     /// See [`Config::ping_protocol`].
     ping_protocol: String,
 
+    /// See [`Config::ping_interval`].
+    ping_interval: Duration,
+
+    /// See [`Config::ping_timeout`].
+    ping_timeout: Duration,
+
+    /// See [`Config::max_ping_failures`].
+    max_ping_failures: NonZeroU32,
+
+    /// See [`Config::max_simultaneous_substreams`].
+    max_simultaneous_substreams: usize,
+
+    /// See [`Config::yamux_substream_receive_window`].
+    yamux_substream_receive_window: u64,
+
+    /// See [`Config::handshake_timeout`].
+    handshake_timeout: Duration,
+
     /// Generator for randomness seeds given to the established connections.
     randomness_seeds: Mutex<ChaCha20Rng>,
 
     /// Receiver connected to [`Guarded::events_tx`].
     events_rx: Mutex<mpsc::Receiver<Event<TConn>>>,
+
+    /// Number of bytes transferred on all the connections, including connections that have
+    /// been closed and handshakes. See [`Network::total_bandwidth`].
+    total_bandwidth: Mutex<Bandwidth>,
 }
 
 /// State of a specific overlay network.
@@ -245,12 +316,63 @@ struct Guarded<TNow, TPeer, TConn> {
     /// Holds the state of all the known nodes of the network, and of all the connections (pending
     /// or not).
     peerset: peerset::Peerset<
+        TNow,
         TPeer,
         Arc<Mutex<Connection<TNow, TConn>>>,
-        Arc<Mutex<Option<(connection::handshake::HealthyHandshake, TConn)>>>,
+        PendingConnection<TNow, TConn>,
         established::SubstreamId,
         established::SubstreamId,
     >,
+
+    /// List of connections added through [`Network::add_incoming_connection`]. The identity of
+    /// the remote of an incoming connection is only known once its handshake has finished, and
+    /// the connection can only be inserted in [`Guarded::peerset`] at this point.
+    incoming_connections: slab::Slab<IncomingConnection<TNow, TPeer, TConn>>,
+}
+
+impl<TNow, TPeer, TConn> Guarded<TNow, TPeer, TConn> {
+    /// Returns the identifier within the peerset of the given connection, or `None` if this is
+    /// an incoming connection whose handshake is still in progress or that no longer exists.
+    fn peerset_connection_id(&self, id: ConnectionId) -> Option<peerset::ConnectionId> {
+        match id.0 {
+            ConnectionIdInner::Peerset(id) => Some(id),
+            ConnectionIdInner::Incoming(index) => match self.incoming_connections.get(index)? {
+                IncomingConnection::Handshake { .. } => None,
+                IncomingConnection::Established(id) => Some(*id),
+            },
+        }
+    }
+}
+
+/// State of a pending connection, as stored in the peerset.
+struct PendingConnection<TNow, TConn> {
+    /// Moment after which the connection attempt is considered failed if the handshake hasn't
+    /// finished yet.
+    timeout: TNow,
+
+    /// `None` until the success of the dialing attempt is reported with
+    /// [`Network::pending_outcome_ok`].
+    handshake: Arc<Mutex<Option<(connection::handshake::HealthyHandshake, TConn)>>>,
+}
+
+/// State of a connection added through [`Network::add_incoming_connection`].
+enum IncomingConnection<TNow, TPeer, TConn> {
+    /// Handshake is still in progress.
+    Handshake {
+        /// Moment after which the handshake is considered failed.
+        timeout: TNow,
+
+        /// Handshake in progress. Always `Some`, except temporarily while the handshake is
+        /// being processed.
+        handshake: Arc<Mutex<Option<(connection::handshake::HealthyHandshake, TConn)>>>,
+
+        /// User data to insert in the peerset if the remote turns out to not be known yet.
+        /// Always `Some`, except temporarily when the handshake finishes.
+        peer_user_data: Option<TPeer>,
+    },
+
+    /// Handshake has finished. The connection is now in [`Guarded::peerset`].
+    Established(peerset::ConnectionId),
 }
 
 impl<TNow, TPeer, TConn> Network<TNow, TPeer, TConn>
@@ -265,6 +387,10 @@ where
             randomness_seed: config.randomness_seed,
             peers_capacity: 50, // TODO: ?
             overlay_networks_capacity: config.overlay_networks.len(),
+            dial_backoff_base: config.dial_backoff_base,
+            dial_backoff_max: config.dial_backoff_max,
+            ban_threshold: config.ban_threshold,
+            ban_duration: config.ban_duration,
         });
 
         let overlay_networks = config
@@ -299,9 +425,20 @@ where
             overlay_networks,
             request_response_protocols: config.request_response_protocols,
             ping_protocol: config.ping_protocol,
+            ping_interval: config.ping_interval,
+            ping_timeout: config.ping_timeout,
+            max_ping_failures: config.max_ping_failures,
+            max_simultaneous_substreams: config.max_simultaneous_substreams,
+            yamux_substream_receive_window: config.yamux_substream_receive_window,
+            handshake_timeout: config.handshake_timeout,
             events_rx: Mutex::new(events_rx),
-            guarded: Mutex::new(Guarded { peerset, events_tx }),
+            guarded: Mutex::new(Guarded {
+                peerset,
+                events_tx,
+                incoming_connections: slab::Slab::new(),
+            }),
             randomness_seeds: Mutex::new(ChaCha20Rng::from_seed(config.randomness_seed)),
+            total_bandwidth: Mutex::new(Bandwidth::default()),
         }
     }
 
@@ -362,13 +499,133 @@ where
         node.add_to_overlay(self.overlay_networks[overlay_network_index].peerset_id);
     }
 
-    pub fn add_incoming_connection(
-        &self,
-        local_listen_address: &Multiaddr,
-        remote_addr: Multiaddr,
-        user_data: TConn,
-    ) -> ConnectionId {
-        todo!()
+    /// Returns the number of bytes transferred on all the connections since the [`Network`] has
+    /// been created, including the handshakes and the connections that have since been closed.
+    pub async fn total_bandwidth(&self) -> Bandwidth {
+        *self.total_bandwidth.lock().await
+    }
+
+    /// Returns the number of bytes transferred on each established connection, in total and
+    /// for each protocol.
+    pub async fn connections_bandwidth(&self) -> Vec<ConnectionBandwidth> {
+        let connections = {
+            let mut guarded = self.guarded.lock().await;
+            let ids = guarded
+                .peerset
+                .connections_peer_ids()
+                .map(|(id, peer_id)| (id, peer_id.clone()))
+                .collect::<Vec<_>>();
+            ids.into_iter()
+                .map(|(id, peer_id)| {
+                    let connection = guarded
+                        .peerset
+                        .connection_mut(id)
+                        .unwrap()
+                        .user_data_mut()
+                        .clone();
+                    (id, peer_id, connection)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Note that `guarded` must not be locked while locking a connection.
+        let mut out = Vec::with_capacity(connections.len());
+        for (id, peer_id, connection) in connections {
+            let mut connection = connection.lock().await;
+            let total = connection.bandwidth;
+            let established = match connection.connection.as_alive() {
+                Some(c) => c,
+                None => continue,
+            };
+
+            out.push(ConnectionBandwidth {
+                id: ConnectionId(ConnectionIdInner::Peerset(id)),
+                peer_id,
+                total,
+                overlay_networks: (0..self.overlay_networks.len())
+                    .map(|n| established.notifications_protocol_bandwidth(n))
+                    .collect(),
+                request_response_protocols: (0..self.request_response_protocols.len())
+                    .map(|n| established.request_protocol_bandwidth(n))
+                    .collect(),
+            });
+        }
+        out
+    }
+
+    /// Adds `reputation_change` to the reputation of the given node. The reputation of a node
+    /// decays towards 0 over time.
+    ///
+    /// If the reputation falls below [`Config::ban_threshold`], the node gets banned for
+    /// [`Config::ban_duration`]. All the existing connections to this node are then closed, and
+    /// no new connection to this node is opened until the end of the ban. Returns `true` if the
+    /// node is banned.
+    ///
+    /// Has no effect and returns `false` if the node isn't known.
+    pub async fn report_peer(&self, now: TNow, peer_id: &PeerId, reputation_change: i32) -> bool {
+        let connections = {
+            let mut guarded = self.guarded.lock().await;
+            // TODO: clone :-/
+            let mut node = match guarded.peerset.node_mut(peer_id.clone()).into_known() {
+                Some(n) => n,
+                None => return false,
+            };
+
+            if !node.change_reputation(&now, reputation_change) {
+                return false;
+            }
+
+            let connection_ids = node.connections().collect::<Vec<_>>();
+            connection_ids
+                .into_iter()
+                .map(|id| {
+                    guarded
+                        .peerset
+                        .connection_mut(id)
+                        .unwrap()
+                        .user_data_mut()
+                        .clone()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The connections are closed the next time `read_write` is called on them. Note that
+        // `guarded` must not be locked while locking a connection.
+        for connection in connections {
+            let mut connection = connection.lock().await;
+            connection.banned = true;
+            if let Some(waker) = connection.waker.take() {
+                let _ = waker.send(());
+            }
+        }
+
+        true
+    }
+
+    /// Adds an incoming connection, for example a socket that has been accepted by a
+    /// listener, to the collection. The handshake is performed through [`Network::read_write`],
+    /// similarly to outgoing connections.
+    ///
+    /// The identity of the remote is only known once the handshake has finished, at which point
+    /// the remote is inserted in the list of known nodes (with a default user data) if it isn't
+    /// known yet, and an [`Event::Connected`] is generated if this is the only connection with
+    /// this node. The connection is refused if the remote is banned.
+    pub async fn add_incoming_connection(&self, now: TNow, user_data: TConn) -> ConnectionId
+    where
+        TPeer: Default,
+    {
+        let mut guarded = self.guarded.lock().await;
+        let index = guarded
+            .incoming_connections
+            .insert(IncomingConnection::Handshake {
+                timeout: now + self.handshake_timeout,
+                handshake: Arc::new(Mutex::new(Some((
+                    connection::handshake::HealthyHandshake::new(false, None),
+                    user_data,
+                )))),
+                peer_user_data: Some(Default::default()),
+            });
+        ConnectionId(ConnectionIdInner::Incoming(index))
     }
 
     /// Sends a request to the given peer, and waits for a response.
@@ -571,44 +828,65 @@ where
     ///
     /// Panics if the [`PendingId`] is invalid.
     ///
-    // TODO: timeout?
     pub async fn pending_outcome_ok(&self, id: PendingId, user_data: TConn) -> ConnectionId {
-        let conn = self
-            .guarded
-            .lock()
-            .await
-            .peerset
-            .pending_mut(id.0)
-            .unwrap()
-            .user_data_mut()
-            .clone();
+        let (conn, expected_peer_id) = {
+            let mut guarded = self.guarded.lock().await;
+            let mut pending = guarded.peerset.pending_mut(id.0).unwrap();
+            let expected_peer_id = pending.peer_id().clone();
+            (pending.user_data_mut().handshake.clone(), expected_peer_id)
+        };
 
+        // Passing the expected `PeerId` to the handshake makes it possible to abort the
+        // connection as soon as the remote has revealed its identity.
         let mut conn = conn.try_lock().unwrap();
         assert!(conn.is_none());
         *conn = Some((
-            connection::handshake::HealthyHandshake::new(true),
+            connection::handshake::HealthyHandshake::new(true, Some(expected_peer_id)),
             user_data,
         ));
-        ConnectionId(id.0)
+        ConnectionId(ConnectionIdInner::Peerset(id.0))
     }
 
     /// After calling [`Network::fill_out_slots`], notifies the [`Network`] of the failure of the
     /// dialing attempt.
     ///
+    /// The target address isn't dialed again until the end of a backoff period. See
+    /// [`Config::dial_backoff_base`] and [`Config::dial_backoff_max`].
+    ///
     /// See also [`Network::pending_outcome_ok`].
     ///
     /// # Panic
     ///
     /// Panics if the [`PendingId`] is invalid.
     ///
-    // TODO: timeout?
-    pub async fn pending_outcome_err(&self, id: PendingId) {
+    pub async fn pending_outcome_err(&self, id: PendingId, now: TNow) {
+        let mut guarded = self.guarded.lock().await;
+        guarded
+            .peerset
+            .pending_mut(id.0)
+            .unwrap()
+            .remove_and_backoff(&now);
+    }
+
+    /// After calling [`Network::fill_out_slots`], notifies the [`Network`] that the target
+    /// address of the dialing attempt couldn't be resolved, for example because of a DNS
+    /// failure.
+    ///
+    /// Similarly to [`Network::pending_outcome_err`], the address isn't dialed again until the
+    /// end of a backoff period. It is kept in the list of known addresses of the node, as
+    /// resolution failures are frequently temporary.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`PendingId`] is invalid.
+    ///
+    pub async fn pending_outcome_resolution_err(&self, id: PendingId, now: TNow) {
         let mut guarded = self.guarded.lock().await;
         guarded
             .peerset
             .pending_mut(id.0)
             .unwrap()
-            .remove_and_purge_address();
+            .remove_and_backoff(&now);
     }
 
     pub async fn accept_notifications_in(
@@ -622,7 +900,10 @@ where
             let mut guarded = self.guarded.lock().await;
 
             // TODO: we use defensive programming here because the concurrency model is still blurry
-            let mut connection = match guarded.peerset.connection_mut(id.0) {
+            let mut connection = match guarded
+                .peerset_connection_id(id)
+                .and_then(|id| guarded.peerset.connection_mut(id))
+            {
                 Some(c) => c,
                 None => return,
             };
@@ -699,7 +980,10 @@ where
         let connection_arc: Arc<Mutex<Connection<_, _>>> = {
             let mut guarded = self.guarded.lock().await;
 
-            match guarded.peerset.connection_mut(id.0) {
+            match guarded
+                .peerset_connection_id(id)
+                .and_then(|id| guarded.peerset.connection_mut(id))
+            {
                 Some(mut c) => c.user_data_mut().clone(),
                 None => return,
             }
@@ -765,6 +1049,50 @@ where
         now: TNow,
         incoming_buffer: Option<&[u8]>,
         outgoing_buffer: (&'a mut [u8], &'a mut [u8]),
+    ) -> Result<ReadWrite<TNow>, ConnectionError> {
+        let index = match connection_id.0 {
+            ConnectionIdInner::Peerset(id) => {
+                return self
+                    .read_write_peerset(id, now, incoming_buffer, outgoing_buffer)
+                    .await
+            }
+            ConnectionIdInner::Incoming(index) => index,
+        };
+
+        let peerset_id = match self.guarded.lock().await.incoming_connections[index] {
+            IncomingConnection::Handshake { .. } => None,
+            IncomingConnection::Established(id) => Some(id),
+        };
+
+        let result = match peerset_id {
+            Some(id) => {
+                self.read_write_peerset(id, now, incoming_buffer, outgoing_buffer)
+                    .await
+            }
+            None => {
+                self.read_write_incoming_handshake(index, now, incoming_buffer, outgoing_buffer)
+                    .await
+            }
+        };
+
+        // If the connection is over, the `ConnectionId` is now invalid and must be cleaned up.
+        if result
+            .as_ref()
+            .map_or(true, |rw| rw.write_close && incoming_buffer.is_none())
+        {
+            self.guarded.lock().await.incoming_connections.remove(index);
+        }
+
+        result
+    }
+
+    /// Same as [`Network::read_write`], for a connection found in [`Guarded::peerset`].
+    async fn read_write_peerset<'a>(
+        &self,
+        connection_id: peerset::ConnectionId,
+        now: TNow,
+        incoming_buffer: Option<&[u8]>,
+        outgoing_buffer: (&'a mut [u8], &'a mut [u8]),
     ) -> Result<ReadWrite<TNow>, ConnectionError> {
         let (tx, rx) = oneshot::channel();
 
@@ -776,16 +1104,20 @@ where
             write_close: false,
         };
 
+        // Result of the call to `read_write` on the established connection, if any.
+        let mut established_result = Ok(());
+
         // TODO: ideally we wouldn't need to lock `guarded`, to reduce the possibility of lock contention
 
         let mut guarded = self.guarded.lock().await;
         match guarded
             .peerset
-            .pending_or_connection_mut(connection_id.0)
+            .pending_or_connection_mut(connection_id)
             .unwrap()
         {
             peerset::PendingOrConnectionMut::Pending(mut pending) => {
-                let pending = pending.user_data_mut().clone();
+                let timeout = pending.user_data_mut().timeout.clone();
+                let pending = pending.user_data_mut().handshake.clone();
                 drop(guarded);
 
                 let mut pending = pending.lock().await;
@@ -796,9 +1128,9 @@ where
                         let mut guarded = self.guarded.lock().await;
                         guarded
                             .peerset
-                            .pending_mut(connection_id.0)
+                            .pending_mut(connection_id)
                             .unwrap()
-                            .remove_and_purge_address();
+                            .remove_and_backoff(&now);
 
                         debug_assert_eq!(read_write.read_bytes, 0);
                         read_write.write_close = true;
@@ -806,23 +1138,42 @@ where
                     }
                 };
 
+                if now >= timeout {
+                    let mut guarded = self.guarded.lock().await;
+                    guarded
+                        .peerset
+                        .pending_mut(connection_id)
+                        .unwrap()
+                        .remove_and_backoff(&now);
+                    return Err(ConnectionError::HandshakeTimeout);
+                }
+
                 let (handshake, user_data) = pending.take().unwrap();
 
                 let mut tx = Some(tx);
 
-                // TODO: check timeout
-
                 let mut result = {
                     let (result, num_read, num_written) =
                         match handshake.read_write(incoming_buffer, outgoing_buffer) {
                             Ok(rw) => rw,
-                            Err(err) => {
+                            Err(connection::handshake::HandshakeError::NoiseHandshake(
+                                connection::noise::HandshakeError::PeerIdMismatch { .. },
+                            )) => {
                                 let mut guarded = self.guarded.lock().await;
                                 guarded
                                     .peerset
-                                    .pending_mut(connection_id.0)
+                                    .pending_mut(connection_id)
                                     .unwrap()
                                     .remove_and_purge_address();
+                                return Err(ConnectionError::PeerIdMismatch);
+                            }
+                            Err(err) => {
+                                let mut guarded = self.guarded.lock().await;
+                                guarded
+                                    .peerset
+                                    .pending_mut(connection_id)
+                                    .unwrap()
+                                    .remove_and_backoff(&now);
                                 return Err(ConnectionError::Handshake(err));
                             }
                         };
@@ -840,19 +1191,36 @@ where
                     match result {
                         connection::handshake::Handshake::Healthy(updated_handshake) => {
                             *pending = Some((updated_handshake, user_data));
+                            read_write.wake_up_after = Some(timeout);
                             break;
                         }
                         connection::handshake::Handshake::Success {
                             remote_peer_id,
                             connection,
+                            ..
                         } => {
                             let mut guarded = self.guarded.lock().await;
-                            let pending = guarded.peerset.pending_mut(connection_id.0).unwrap();
+                            let pending = guarded.peerset.pending_mut(connection_id).unwrap();
                             if *pending.peer_id() != remote_peer_id {
                                 pending.remove_and_purge_address();
                                 return Err(ConnectionError::PeerIdMismatch);
                             }
 
+                            // The node might have been banned while the handshake was in
+                            // progress.
+                            if guarded
+                                .peerset
+                                .node_mut(remote_peer_id.clone()) // TODO: clone :-/
+                                .into_known()
+                                .unwrap()
+                                .is_banned(&now)
+                            {
+                                guarded.peerset.pending_mut(connection_id).unwrap().remove();
+                                return Err(ConnectionError::Banned);
+                            }
+
+                            let pending = guarded.peerset.pending_mut(connection_id).unwrap();
+
                             pending.into_established({
                                 let config = self.build_connection_config().await;
                                 move |_| {
@@ -860,10 +1228,12 @@ where
                                     Arc::new(Mutex::new(Connection {
                                         connection: ConnectionInner::Alive(established),
                                         overlay_networks: self.overlay_networks.clone(),
-                                        id: connection_id.0,
+                                        id: connection_id,
                                         user_data: Some(user_data),
                                         pending_event: None,
                                         waker: None,
+                                        banned: false,
+                                        bandwidth: Bandwidth::default(),
                                     }))
                                 }
                             });
@@ -913,10 +1283,164 @@ where
                     debug_assert!(established.pending_event.is_none());
                 }
 
-                established.read_write(now, incoming_buffer, outgoing_buffer, &mut read_write)?;
+                // Bytes are counted even if an error is returned, as they might have been
+                // transferred before the error happened.
+                established_result =
+                    established.read_write(now, incoming_buffer, outgoing_buffer, &mut read_write);
+                established.bandwidth.add_received(read_write.read_bytes);
+                established.bandwidth.add_sent(read_write.written_bytes);
             }
         }
 
+        {
+            let mut total_bandwidth = self.total_bandwidth.lock().await;
+            total_bandwidth.add_received(read_write.read_bytes);
+            total_bandwidth.add_sent(read_write.written_bytes);
+        }
+
+        established_result?;
+        Ok(read_write)
+    }
+
+    /// Same as [`Network::read_write`], for a connection found in
+    /// [`Guarded::incoming_connections`] whose handshake is in progress.
+    ///
+    /// The entry in [`Guarded::incoming_connections`] is left untouched in case of error.
+    async fn read_write_incoming_handshake<'a>(
+        &self,
+        index: usize,
+        now: TNow,
+        incoming_buffer: Option<&[u8]>,
+        outgoing_buffer: (&'a mut [u8], &'a mut [u8]),
+    ) -> Result<ReadWrite<TNow>, ConnectionError> {
+        let (tx, rx) = oneshot::channel();
+
+        let mut read_write = ReadWrite {
+            read_bytes: 0,
+            written_bytes: 0,
+            wake_up_after: None,
+            wake_up_future: ConnectionReadyFuture(rx),
+            write_close: false,
+        };
+
+        let (timeout, pending) = match &self.guarded.lock().await.incoming_connections[index] {
+            IncomingConnection::Handshake {
+                timeout, handshake, ..
+            } => (timeout.clone(), handshake.clone()),
+            IncomingConnection::Established(_) => unreachable!(),
+        };
+
+        let mut pending = pending.lock().await;
+
+        let incoming_buffer = match incoming_buffer {
+            Some(b) => b,
+            None => {
+                read_write.write_close = true;
+                return Ok(read_write);
+            }
+        };
+
+        if now >= timeout {
+            return Err(ConnectionError::HandshakeTimeout);
+        }
+
+        let (handshake, user_data) = pending.take().unwrap();
+
+        let mut tx = Some(tx);
+
+        let (mut result, num_read, num_written) = handshake
+            .read_write(incoming_buffer, outgoing_buffer)
+            .map_err(ConnectionError::Handshake)?;
+        read_write.read_bytes += num_read;
+        read_write.written_bytes += num_written;
+        if num_read != 0 || num_written != 0 {
+            if let Some(tx) = tx.take() {
+                let _ = tx.send(());
+            }
+        }
+
+        loop {
+            match result {
+                connection::handshake::Handshake::Healthy(updated_handshake) => {
+                    *pending = Some((updated_handshake, user_data));
+                    read_write.wake_up_after = Some(timeout);
+                    break;
+                }
+                connection::handshake::Handshake::Success {
+                    remote_peer_id,
+                    connection,
+                    ..
+                } => {
+                    let config = self.build_connection_config().await;
+
+                    let mut guarded = self.guarded.lock().await;
+                    let peer_user_data = match &mut guarded.incoming_connections[index] {
+                        IncomingConnection::Handshake { peer_user_data, .. } => {
+                            peer_user_data.take().unwrap()
+                        }
+                        IncomingConnection::Established(_) => unreachable!(),
+                    };
+
+                    // TODO: clone :-/
+                    let mut node = guarded
+                        .peerset
+                        .node_mut(remote_peer_id.clone())
+                        .or_insert_with(move || peer_user_data);
+                    if node.is_banned(&now) {
+                        drop(guarded);
+                        let mut total_bandwidth = self.total_bandwidth.lock().await;
+                        total_bandwidth.add_received(read_write.read_bytes);
+                        total_bandwidth.add_sent(read_write.written_bytes);
+                        return Err(ConnectionError::Banned);
+                    }
+
+                    let overlay_networks = self.overlay_networks.clone();
+                    let peerset_id = node.add_inbound_connection(move |id| {
+                        Arc::new(Mutex::new(Connection {
+                            connection: ConnectionInner::Alive(connection.into_connection(config)),
+                            overlay_networks,
+                            id,
+                            user_data: Some(user_data),
+                            pending_event: None,
+                            waker: None,
+                            banned: false,
+                            bandwidth: Bandwidth::default(),
+                        }))
+                    });
+
+                    // Send a `Connected` event if and only if this is the first active
+                    // connection to that peer.
+                    let is_first_connection = node.connections().count() == 1;
+
+                    guarded.incoming_connections[index] =
+                        IncomingConnection::Established(peerset_id);
+
+                    if is_first_connection {
+                        // TODO: must convert this code to thread-safe design
+                        guarded
+                            .events_tx
+                            .send(Event::Connected(remote_peer_id))
+                            .await
+                            .unwrap();
+                    }
+
+                    if let Some(tx) = tx.take() {
+                        let _ = tx.send(());
+                    }
+                    break;
+                }
+                connection::handshake::Handshake::NoiseKeyRequired(key) => {
+                    result = key.resume(&self.noise_key).into();
+                }
+            }
+        }
+
+        {
+            let mut total_bandwidth = self.total_bandwidth.lock().await;
+            total_bandwidth.add_received(read_write.read_bytes);
+            total_bandwidth.add_sent(read_write.written_bytes);
+        }
+
         Ok(read_write)
     }
 
@@ -926,23 +1450,23 @@ where
             notifications_protocols: self
                 .overlay_networks
                 .iter()
-                .flat_map(|net| {
-                    let max_handshake_size = net.config.max_handshake_size;
-                    let max_notification_size = net.config.max_notification_size;
-                    iter::once(&net.config.protocol_name)
-                        .chain(net.config.fallback_protocol_names.iter())
-                        .map(move |name| {
-                            established::ConfigNotifications {
-                                name: name.clone(), // TODO: cloning :-/
-                                max_handshake_size,
-                                max_notification_size,
-                            }
-                        })
+                .map(|net| {
+                    established::ConfigNotifications {
+                        name: net.config.protocol_name.clone(), // TODO: cloning :-/
+                        fallback_protocol_names: net.config.fallback_protocol_names.clone(),
+                        max_handshake_size: net.config.max_handshake_size,
+                        max_notification_size: net.config.max_notification_size,
+                    }
                 })
                 .collect(),
             request_protocols: self.request_response_protocols.clone(),
             randomness_seed,
             ping_protocol: self.ping_protocol.clone(), // TODO: cloning :-/
+            ping_interval: self.ping_interval,
+            ping_timeout: self.ping_timeout,
+            max_ping_failures: self.max_ping_failures,
+            max_simultaneous_substreams: self.max_simultaneous_substreams,
+            yamux_substream_receive_window: self.yamux_substream_receive_window,
         }
     }
 
@@ -970,8 +1494,19 @@ where
     }
 
     /// Spawns new outgoing connections in order to fill empty outgoing slots.
+    ///
+    /// Nodes towards which a connection or connection attempt already exists, and addresses
+    /// that are in a backoff period following a failed dialing attempt, are ignored.
+    ///
+    /// Returns `None` if no node is available for dialing. Calling this function again later
+    /// might return `Some`, for example after new nodes have been discovered, a node has been
+    /// disconnected, or a backoff period has ended.
     // TODO: give more control, with number of slots and node choice
-    pub async fn fill_out_slots<'a>(&self, overlay_network_index: usize) -> Option<StartConnect> {
+    pub async fn fill_out_slots<'a>(
+        &self,
+        overlay_network_index: usize,
+        now: TNow,
+    ) -> Option<StartConnect<TNow>> {
         let mut guarded = self.guarded.lock().await;
         // Solves borrow checking errors regarding the borrow of multiple different fields at the
         // same time.
@@ -980,17 +1515,25 @@ where
         // TODO: limit number of slots
 
         // TODO: very wip
-        while let Some(mut node) = guarded
-            .peerset
-            .random_not_connected(self.overlay_networks[overlay_network_index].peerset_id)
-        {
-            let first_addr = node.known_addresses().cloned().next();
+        while let Some(mut node) = guarded.peerset.random_not_connected(
+            &now,
+            self.overlay_networks[overlay_network_index].peerset_id,
+        ) {
+            let first_addr = node.dialable_addresses(&now).cloned().next();
             if let Some(multiaddr) = first_addr {
-                let id = node.add_outbound_attempt(multiaddr.clone(), Arc::new(Mutex::new(None)));
+                let timeout = now + self.handshake_timeout;
+                let id = node.add_outbound_attempt(
+                    multiaddr.clone(),
+                    PendingConnection {
+                        timeout: timeout.clone(),
+                        handshake: Arc::new(Mutex::new(None)),
+                    },
+                );
                 return Some(StartConnect {
                     id: PendingId(id),
                     multiaddr,
                     expected_peer_id: node.peer_id().clone(),
+                    timeout,
                 });
             }
         }
@@ -1025,6 +1568,13 @@ struct Connection<TNow, TConn> {
     /// Send a value on that channel in order to notify that data is potentially available to be
     /// sent on the socket, or that the user should call [`Network::read_write`] in general.
     waker: Option<oneshot::Sender<()>>,
+
+    /// If `true`, the remote has been banned with [`Network::report_peer`] and the connection
+    /// must be closed.
+    banned: bool,
+
+    /// Number of bytes transferred on the socket since the end of the handshake.
+    bandwidth: Bandwidth,
 }
 
 enum ConnectionInner<TNow> {
@@ -1064,6 +1614,31 @@ where
         outgoing_buffer: (&'a mut [u8], &'a mut [u8]),
         read_write: &mut ReadWrite<TNow>,
     ) -> Result<(), ConnectionError> {
+        if self.banned && matches!(self.connection, ConnectionInner::Alive(_)) {
+            if let Some(waker) = self.waker.take() {
+                let _ = waker.send(());
+            }
+
+            // Before closing the connection, notify the remote that no new substream will be
+            // accepted, and try to write out this notification. This is a best-effort attempt,
+            // as the connection is closed no matter whether writing succeeds. Events generated
+            // during this last call are discarded, as they concern a connection that is about to
+            // be closed.
+            if let ConnectionInner::Alive(mut connection) =
+                mem::replace(&mut self.connection, ConnectionInner::Poisoned)
+            {
+                connection.send_goaway();
+                if let Ok(result) = connection.read_write(now, Some(&[]), outgoing_buffer) {
+                    read_write.written_bytes += result.written_bytes;
+                }
+            }
+
+            debug_assert!(self.pending_event.is_none());
+            self.connection = ConnectionInner::Errored(ConnectionError::Banned);
+            self.pending_event = Some(PendingEvent::Disconnect);
+            return Ok(());
+        }
+
         let connection = match mem::replace(&mut self.connection, ConnectionInner::Poisoned) {
             ConnectionInner::Alive(c) => c,
             ConnectionInner::Errored(err) => return Err(err),
@@ -1150,7 +1725,7 @@ where
                 guarded
                     .events_tx
                     .try_send(Event::RequestIn {
-                        id: ConnectionId(self.id),
+                        id: ConnectionId(ConnectionIdInner::Peerset(self.id)),
                         substream_id,
                         peer_id,
                         protocol_index,
@@ -1168,6 +1743,7 @@ where
             PendingEvent::Inner(established::Event::NotificationsInOpen {
                 id,
                 protocol_index: overlay_network_index,
+                negotiated_protocol,
                 handshake,
             }) => {
                 guarded
@@ -1184,8 +1760,9 @@ where
                 guarded
                     .events_tx
                     .try_send(Event::NotificationsInOpen {
-                        id: ConnectionId(self.id),
+                        id: ConnectionId(ConnectionIdInner::Peerset(self.id)),
                         overlay_network_index,
+                        negotiated_protocol,
                         remote_handshake: handshake,
                     })
                     .unwrap();
@@ -1222,7 +1799,7 @@ where
                 guarded
                     .events_tx
                     .try_send(Event::NotificationsIn {
-                        id: ConnectionId(self.id),
+                        id: ConnectionId(ConnectionIdInner::Peerset(self.id)),
                         peer_id,
                         has_symmetric_substream,
                         overlay_network_index,
@@ -1232,6 +1809,7 @@ where
             }
             PendingEvent::Inner(established::Event::NotificationsOutAccept {
                 id,
+                negotiated_protocol,
                 remote_handshake,
             }) => {
                 let overlay_network_index = *self
@@ -1254,9 +1832,10 @@ where
                 guarded
                     .events_tx
                     .try_send(Event::NotificationsOutAccept {
-                        id: ConnectionId(self.id),
+                        id: ConnectionId(ConnectionIdInner::Peerset(self.id)),
                         peer_id,
                         overlay_network_index,
+                        negotiated_protocol,
                         remote_handshake,
                     })
                     .unwrap();
@@ -1278,7 +1857,7 @@ where
                 guarded
                     .events_tx
                     .try_send(Event::NotificationsOutReject {
-                        id: ConnectionId(self.id),
+                        id: ConnectionId(ConnectionIdInner::Peerset(self.id)),
                         peer_id,
                         overlay_network_index,
                     })
@@ -1304,12 +1883,45 @@ where
                 guarded
                     .events_tx
                     .try_send(Event::NotificationsOutClose {
-                        id: ConnectionId(self.id),
+                        id: ConnectionId(ConnectionIdInner::Peerset(self.id)),
                         overlay_network_index,
                         peer_id,
                     })
                     .unwrap();
             }
+            PendingEvent::Inner(established::Event::PingOutSuccess { ping_time }) => {
+                let peer_id = guarded
+                    .peerset
+                    .connection_mut(self.id)
+                    .unwrap()
+                    .peer_id()
+                    .clone();
+
+                guarded
+                    .events_tx
+                    .try_send(Event::PingOutSuccess {
+                        id: ConnectionId(ConnectionIdInner::Peerset(self.id)),
+                        peer_id,
+                        ping_time,
+                    })
+                    .unwrap();
+            }
+            PendingEvent::Inner(established::Event::PingOutFailed) => {
+                let peer_id = guarded
+                    .peerset
+                    .connection_mut(self.id)
+                    .unwrap()
+                    .peer_id()
+                    .clone();
+
+                guarded
+                    .events_tx
+                    .try_send(Event::PingOutFailed {
+                        id: ConnectionId(ConnectionIdInner::Peerset(self.id)),
+                        peer_id,
+                    })
+                    .unwrap();
+            }
             PendingEvent::Disconnect => {
                 let mut out_overlay_network_indices =
                     Vec::with_capacity(guarded.peerset.num_overlay_networks());
@@ -1370,11 +1982,15 @@ where
 /// called in order to inform of the outcome of the connection.
 #[derive(Debug)]
 #[must_use]
-pub struct StartConnect {
+pub struct StartConnect<TNow> {
     pub id: PendingId,
     pub multiaddr: Multiaddr,
     /// [`PeerId`] that is expected to be reached with this connection attempt.
     pub expected_peer_id: PeerId,
+    /// When the connection attempt, including the handshake, is considered failed. If the
+    /// target hasn't been reached at this point, the API user should give up and call
+    /// [`Network::pending_outcome_err`].
+    pub timeout: TNow,
 }
 
 /// Event generated by [`Network::next_event`].
@@ -1405,15 +2021,17 @@ pub enum Event<TConn> {
     NotificationsOutAccept {
         id: ConnectionId,
         peer_id: PeerId,
-        // TODO: what if fallback?
         overlay_network_index: usize,
+        /// Name of the protocol that was negotiated. Can be either
+        /// [`OverlayNetworkConfig::protocol_name`] or one of the
+        /// [`OverlayNetworkConfig::fallback_protocol_names`].
+        negotiated_protocol: String,
         remote_handshake: Vec<u8>,
     },
 
     NotificationsOutReject {
         id: ConnectionId,
         peer_id: PeerId,
-        // TODO: what if fallback?
         overlay_network_index: usize,
     },
 
@@ -1427,6 +2045,10 @@ pub enum Event<TConn> {
     NotificationsInOpen {
         id: ConnectionId,
         overlay_network_index: usize,
+        /// Name of the protocol that was negotiated. Can be either
+        /// [`OverlayNetworkConfig::protocol_name`] or one of the
+        /// [`OverlayNetworkConfig::fallback_protocol_names`].
+        negotiated_protocol: String,
         remote_handshake: Vec<u8>,
     },
 
@@ -1440,6 +2062,18 @@ pub enum Event<TConn> {
         overlay_network_index: usize,
         notification: Vec<u8>,
     },
+
+    /// A ping sent on a connection has been answered by the remote.
+    PingOutSuccess {
+        id: ConnectionId,
+        peer_id: PeerId,
+        /// Round-trip time of the ping.
+        ping_time: Duration,
+    },
+
+    /// A ping sent on a connection has failed. The connection is automatically closed after too
+    /// many consecutive failures. See [`Config::max_ping_failures`].
+    PingOutFailed { id: ConnectionId, peer_id: PeerId },
 }
 
 /// Outcome of calling [`Network::read_write`].
@@ -1486,6 +2120,25 @@ impl Future for ConnectionReadyFuture {
     }
 }
 
+/// Number of bytes transferred on an established connection. See
+/// [`Network::connections_bandwidth`].
+#[derive(Debug, Clone)]
+pub struct ConnectionBandwidth {
+    /// Identifier of the connection.
+    pub id: ConnectionId,
+    /// Identity of the remote.
+    pub peer_id: PeerId,
+    /// Number of bytes transferred on the socket since the end of the handshake, including the
+    /// overhead of the encryption and multiplexing layers.
+    pub total: Bandwidth,
+    /// Number of bytes transferred on the substreams of each overlay network. Indices are the
+    /// same as in [`Config::overlay_networks`].
+    pub overlay_networks: Vec<Bandwidth>,
+    /// Number of bytes transferred on the substreams of each request-response protocol. Indices
+    /// are the same as in [`Config::request_response_protocols`].
+    pub request_response_protocols: Vec<Bandwidth>,
+}
+
 /// Protocol error within the context of a connection. See [`Network::read_write`].
 #[derive(Debug, derive_more::Display)]
 pub enum ConnectionError {
@@ -1498,6 +2151,12 @@ pub enum ConnectionError {
     /// Mismatch between the actual [`PeerId`] and the [`PeerId`] expected by the local node.
     #[display(fmt = "Mismatch between the actual PeerId and PeerId expected by the local node")]
     PeerIdMismatch,
+    /// Handshake hasn't finished before [`StartConnect::timeout`].
+    #[display(fmt = "Handshake timeout")]
+    HandshakeTimeout,
+    /// Remote has been banned. See [`Network::report_peer`].
+    #[display(fmt = "Remote has been banned")]
+    Banned,
 }
 
 pub struct SubstreamOpen<'a, TNow, TPeer, TConn> {
@@ -1596,3 +2255,187 @@ pub enum QueueNotificationError {
     /// Queue of notifications with that peer is full.
     QueueFull,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        connection, peer_id, Bandwidth, Config, ConnectionId, Event, Network, OverlayNetworkConfig,
+        PeerId,
+    };
+    use core::{
+        num::{NonZeroU32, NonZeroUsize},
+        time::Duration,
+    };
+    use futures::prelude::*;
+
+    fn peer_id_of(libp2p_key: &[u8; 32]) -> PeerId {
+        let noise_key = connection::NoiseKey::new(libp2p_key);
+        PeerId::from_public_key(&peer_id::PublicKey::Ed25519(
+            *noise_key.libp2p_public_ed25519_key(),
+        ))
+    }
+
+    fn new_network(libp2p_key: &[u8; 32], known_nodes: Vec<PeerId>) -> Network<Duration, (), ()> {
+        Network::new(Config {
+            randomness_seed: [0; 32],
+            listen_addresses: Vec::new(),
+            overlay_networks: vec![OverlayNetworkConfig {
+                protocol_name: "/test/1".into(),
+                fallback_protocol_names: Vec::new(),
+                max_handshake_size: 1024,
+                max_notification_size: 1024,
+                bootstrap_nodes: (0..known_nodes.len()).collect(),
+                in_slots: 25,
+                out_slots: 25,
+            }],
+            request_response_protocols: Vec::new(),
+            ping_protocol: "/ipfs/ping/1.0.0".into(),
+            ping_interval: Duration::from_secs(15),
+            ping_timeout: Duration::from_secs(20),
+            max_ping_failures: NonZeroU32::new(3).unwrap(),
+            max_simultaneous_substreams: 256,
+            yamux_substream_receive_window: 256 * 1024,
+            handshake_timeout: Duration::from_secs(8),
+            dial_backoff_base: Duration::from_secs(5),
+            dial_backoff_max: Duration::from_secs(300),
+            ban_threshold: -1000,
+            ban_duration: Duration::from_secs(300),
+            known_nodes: known_nodes
+                .into_iter()
+                .map(|peer_id| ((), peer_id, "/ip4/127.0.0.1/tcp/30333".parse().unwrap()))
+                .collect(),
+            noise_key: connection::NoiseKey::new(libp2p_key),
+            pending_api_events_buffer_size: NonZeroUsize::new(64).unwrap(),
+        })
+    }
+
+    /// Transfers data back and forth between two connections, and returns the number of bytes
+    /// that `read_write` has reported for the dialer and the listener.
+    async fn transfer(
+        (dialer, dialer_id): (&Network<Duration, (), ()>, ConnectionId),
+        (listener, listener_id): (&Network<Duration, (), ()>, ConnectionId),
+        now: Duration,
+    ) -> (Bandwidth, Bandwidth) {
+        let mut dialer_bandwidth = Bandwidth::default();
+        let mut listener_bandwidth = Bandwidth::default();
+
+        let mut to_listener = Vec::new();
+        let mut to_dialer = Vec::new();
+        for _ in 0..32 {
+            let mut out = vec![0; 4096];
+            let rw = dialer
+                .read_write(dialer_id, now, Some(&to_dialer), (&mut out, &mut []))
+                .await
+                .unwrap();
+            to_dialer.drain(..rw.read_bytes);
+            to_listener.extend_from_slice(&out[..rw.written_bytes]);
+            dialer_bandwidth.add_received(rw.read_bytes);
+            dialer_bandwidth.add_sent(rw.written_bytes);
+
+            let mut out = vec![0; 4096];
+            let rw = listener
+                .read_write(listener_id, now, Some(&to_listener), (&mut out, &mut []))
+                .await
+                .unwrap();
+            to_listener.drain(..rw.read_bytes);
+            to_dialer.extend_from_slice(&out[..rw.written_bytes]);
+            listener_bandwidth.add_received(rw.read_bytes);
+            listener_bandwidth.add_sent(rw.written_bytes);
+        }
+
+        assert!(to_listener.is_empty());
+        assert!(to_dialer.is_empty());
+        (dialer_bandwidth, listener_bandwidth)
+    }
+
+    #[test]
+    fn incoming_connection_handshake() {
+        futures::executor::block_on(async move {
+            let now = Duration::from_secs(0);
+            let listener = new_network(&[1; 32], Vec::new());
+            let dialer = new_network(&[2; 32], vec![peer_id_of(&[1; 32])]);
+
+            let start_connect = dialer.fill_out_slots(0, now).await.unwrap();
+            let dialer_id = dialer.pending_outcome_ok(start_connect.id, ()).await;
+            let listener_id = listener.add_incoming_connection(now, ()).await;
+
+            // Transfer data between the two connections until both handshakes are finished.
+            transfer((&dialer, dialer_id), (&listener, listener_id), now).await;
+
+            match listener.next_event().now_or_never() {
+                Some(Event::Connected(peer_id)) => {
+                    assert_eq!(peer_id, peer_id_of(&[2; 32]))
+                }
+                _ => panic!(),
+            }
+            match dialer.next_event().now_or_never() {
+                Some(Event::Connected(peer_id)) => {
+                    assert_eq!(peer_id, peer_id_of(&[1; 32]))
+                }
+                _ => panic!(),
+            }
+            assert_eq!(listener.num_established_connections().await, 1);
+        });
+    }
+
+    #[test]
+    fn bandwidth_counters() {
+        futures::executor::block_on(async move {
+            let now = Duration::from_secs(0);
+            let listener = new_network(&[1; 32], Vec::new());
+            let dialer = new_network(&[2; 32], vec![peer_id_of(&[1; 32])]);
+
+            let start_connect = dialer.fill_out_slots(0, now).await.unwrap();
+            let dialer_id = dialer.pending_outcome_ok(start_connect.id, ()).await;
+            let listener_id = listener.add_incoming_connection(now, ()).await;
+
+            let (dialer_handshake, listener_handshake) =
+                transfer((&dialer, dialer_id), (&listener, listener_id), now).await;
+            assert_ne!(dialer_handshake, Bandwidth::default());
+            assert_eq!(
+                dialer_handshake.bytes_sent,
+                listener_handshake.bytes_received
+            );
+            assert_eq!(
+                dialer_handshake.bytes_received,
+                listener_handshake.bytes_sent
+            );
+            assert_eq!(dialer.total_bandwidth().await, dialer_handshake);
+            assert_eq!(listener.total_bandwidth().await, listener_handshake);
+
+            // Open a notifications substream, which generates traffic on the overlay network.
+            dialer
+                .open_next_substream()
+                .await
+                .unwrap()
+                .open(now, &b"hello"[..])
+                .await;
+            let (dialer_after, listener_after) =
+                transfer((&dialer, dialer_id), (&listener, listener_id), now).await;
+            assert_ne!(dialer_after.bytes_sent, 0);
+
+            // The total includes the handshake, while the per-connection counters don't.
+            let dialer_total = dialer.total_bandwidth().await;
+            assert_eq!(
+                dialer_total.bytes_sent,
+                dialer_handshake.bytes_sent + dialer_after.bytes_sent
+            );
+            assert_eq!(
+                dialer_total.bytes_received,
+                dialer_handshake.bytes_received + dialer_after.bytes_received
+            );
+            assert_eq!(
+                listener.total_bandwidth().await.bytes_received,
+                listener_handshake.bytes_received + listener_after.bytes_received
+            );
+
+            let connections = dialer.connections_bandwidth().await;
+            assert_eq!(connections.len(), 1);
+            assert_eq!(connections[0].peer_id, peer_id_of(&[1; 32]));
+            assert_eq!(connections[0].total, dialer_after);
+            let overlay = connections[0].overlay_networks[0];
+            assert_ne!(overlay.bytes_sent, 0);
+            assert!(overlay.bytes_sent <= connections[0].total.bytes_sent);
+        });
+    }
+}
@@ -80,6 +80,7 @@ pub use noise::{NoiseKey, UnsignedNoiseKey};
 
 pub mod established;
 pub mod handshake;
+pub mod mplex;
 pub mod multistream_select;
 pub mod noise;
 pub mod yamux;
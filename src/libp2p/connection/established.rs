@@ -45,22 +45,43 @@
 //!
 //! In order to solve 5-, // TODO: .
 //!
+//! # Keep-alive
+//!
+//! In addition to answering the pings sent by the remote, the [`Established`] periodically
+//! opens outgoing substreams using the ping protocol (see [`Config::ping_protocol`]) in order to
+//! measure the round-trip time to the remote and to detect connections that are no longer
+//! responsive. The outcome of each ping is reported with [`Event::PingOutSuccess`] or
+//! [`Event::PingOutFailed`]. After [`Config::max_ping_failures`] consecutive failures,
+//! [`Established::read_write`] returns [`Error::TooManyPingFailures`].
+//!
+//! # Shutdown
+//!
+//! Call [`Established::send_goaway`] in order to notify the remote that no new substream will
+//! be accepted on this connection. In case of a protocol error, the remote is automatically
+//! notified in the same way before [`Established::read_write`] returns an error.
+//!
 
 // TODO: expand docs ^
 
 use crate::util::leb128;
 
-use super::{multistream_select, noise, yamux};
+use super::{mplex, multistream_select, noise, yamux};
+
+mod multiplexer;
 
 use alloc::{
     string::String,
     vec::{self, Vec},
 };
 use core::{
-    cmp, fmt, iter, mem,
+    cmp,
+    convert::TryFrom as _,
+    fmt, iter, mem,
+    num::NonZeroU32,
     ops::{Add, Sub},
     time::Duration,
 };
+use rand::{Rng as _, RngCore as _, SeedableRng as _};
 
 /// State machine of a fully-established connection.
 pub struct Established<TNow, TRqUd, TNotifUd> {
@@ -80,10 +101,10 @@ struct Inner<TNow, TRqUd, TNotifUd> {
     /// Consists in a collection of substreams, each of which holding a [`Substream`] object.
     /// Also includes, for each substream, a collection of buffers whose data is to be written
     /// out.
-    yamux: yamux::Yamux<Substream<TNow, TRqUd, TNotifUd>>,
+    multiplexer: multiplexer::Multiplexer<Substream<TNow, TRqUd, TNotifUd>>,
 
     /// Next substream timeout. When the current time is superior to this value, means that one of
-    /// the substreams in `yamux` might have timed out.
+    /// the substreams in `multiplexer` might have timed out.
     ///
     /// This value is not updated when a timeout is no longer necessary. As such, the value in
     /// this field might correspond to nothing (i.e. is now obsolete).
@@ -95,6 +116,31 @@ struct Inner<TNow, TRqUd, TNotifUd> {
     notifications_protocols: Vec<ConfigNotifications>,
     /// See [`Config::ping_protocol`].
     ping_protocol: String,
+    /// See [`Config::ping_interval`].
+    ping_interval: Duration,
+    /// See [`Config::ping_timeout`].
+    ping_timeout: Duration,
+    /// See [`Config::max_ping_failures`].
+    max_ping_failures: NonZeroU32,
+
+    /// When to open the next outgoing ping substream. `None` if [`Established::read_write`] has
+    /// never been called yet, as the current time isn't known when the connection is created.
+    next_ping: Option<TNow>,
+    /// Number of consecutive outgoing pings that have failed. Reset to 0 after a successful
+    /// ping.
+    num_ping_failures: u32,
+    /// Source of randomness used to generate the payloads of outgoing pings.
+    ping_payload_randomness: rand_chacha::ChaCha20Rng,
+
+    /// If `Some`, a protocol error has been detected by `multiplexer`. If the multiplexer is
+    /// yamux, a GoAway frame is being sent out, after which the error is returned by
+    /// [`Established::read_write`].
+    multiplexer_error: Option<Error>,
+
+    /// For each protocol of [`Config::notifications_protocols`], number of bytes transferred.
+    notifications_protocols_bandwidth: Vec<Bandwidth>,
+    /// For each protocol of [`Config::request_protocols`], number of bytes transferred.
+    request_protocols_bandwidth: Vec<Bandwidth>,
 }
 
 enum Substream<TNow, TRqUd, TNotifUd> {
@@ -111,6 +157,8 @@ enum Substream<TNow, TRqUd, TNotifUd> {
 
     /// Negotiating a protocol for a notifications protocol substream.
     NotificationsOutNegotiating {
+        /// Protocol that is being negotiated.
+        protocol_index: usize,
         /// When the opening will time out in the absence of response.
         timeout: TNow,
         /// State of the protocol negotiation.
@@ -123,14 +171,21 @@ enum Substream<TNow, TRqUd, TNotifUd> {
     /// A notifications protocol has been negotiated on a substream. Either a successful handshake
     /// or an abrupt closing is now expected.
     NotificationsOutHandshakeRecv {
+        /// Protocol that was negotiated.
+        protocol_index: usize,
         /// Buffer for the incoming handshake.
         handshake: leb128::FramedInProgress,
+        /// Name of the protocol that was negotiated. Can be either the main name or one of the
+        /// fallbacks.
+        negotiated_protocol: String,
         /// Data passed by the user to [`Established::open_notifications_substream`].
         user_data: TNotifUd,
     },
     /// A notifications protocol has been negotiated, and the remote accepted it. Can now send
     /// notifications.
     NotificationsOut {
+        /// Protocol that was negotiated.
+        protocol_index: usize,
         /// Data passed by the user to [`Established::open_notifications_substream`].
         user_data: TNotifUd,
     },
@@ -144,6 +199,9 @@ enum Substream<TNow, TRqUd, TNotifUd> {
         handshake: leb128::FramedInProgress,
         /// Protocol that was negotiated.
         protocol_index: usize,
+        /// Name of the protocol that was negotiated. Can be either the main name or one of the
+        /// fallbacks.
+        negotiated_protocol: String,
     },
     /// A handshake on a notifications protocol has been received. Now waiting for an action from
     /// the API user.
@@ -164,6 +222,8 @@ enum Substream<TNow, TRqUd, TNotifUd> {
 
     /// Negotiating a protocol for an outgoing request.
     RequestOutNegotiating {
+        /// Protocol that is being negotiated.
+        protocol_index: usize,
         /// When the request will time out in the absence of response.
         timeout: TNow,
         /// State of the protocol negotiation.
@@ -179,6 +239,8 @@ enum Substream<TNow, TRqUd, TNotifUd> {
     /// Outgoing request has been sent out or is queued for send out, and a response from the
     /// remote is now expected. Substream has been closed.
     RequestOut {
+        /// Protocol of the request.
+        protocol_index: usize,
         /// When the request will time out in the absence of response.
         timeout: TNow,
         /// Data passed by the user to [`Established::add_request`].
@@ -196,10 +258,38 @@ enum Substream<TNow, TRqUd, TNotifUd> {
         protocol_index: usize,
     },
     /// A request has been sent by the remote. API user must now send back the response.
-    RequestInSend,
+    RequestInSend {
+        /// Protocol that was negotiated.
+        protocol_index: usize,
+    },
 
     /// Inbound ping substream. Waiting for the ping payload to be received.
     PingIn(arrayvec::ArrayVec<u8, 32>),
+
+    /// Negotiating the ping protocol on an outgoing substream.
+    PingOutNegotiating {
+        /// When the ping will be considered as failed in the absence of response.
+        timeout: TNow,
+        /// State of the protocol negotiation.
+        negotiation: multistream_select::InProgress<vec::IntoIter<String>, String>,
+        /// Randomly-generated payload to send after the substream is open.
+        payload: [u8; 32],
+    },
+    /// Outgoing ping substream. The payload has been sent out or is queued for send out, and the
+    /// remote is now expected to send it back.
+    PingOut {
+        /// When the ping will be considered as failed in the absence of response.
+        timeout: TNow,
+        /// When the payload has been queued for send out. Used to measure the ping time.
+        sent_at: TNow,
+        /// Payload that has been sent out.
+        payload: [u8; 32],
+        /// Bytes received from the remote so far.
+        received: arrayvec::ArrayVec<u8, 32>,
+    },
+    /// Outgoing ping substream whose outcome has been reported. Waiting for the remote to close
+    /// it as well.
+    PingOutClosed,
 }
 
 impl<TNow, TRqUd, TNotifUd> Established<TNow, TRqUd, TNotifUd>
@@ -225,7 +315,6 @@ where
     ///
     /// If an error is returned, the socket should be entirely shut down.
     // TODO: should take the in and out buffers as iterators, to allow for vectored reads/writes; tricky because an impl Iterator<Item = &mut [u8]> + Clone is impossible to build
-    // TODO: in case of error, we're supposed to first send a yamux goaway frame
     pub fn read_write<'a>(
         mut self,
         now: TNow,
@@ -235,12 +324,23 @@ where
         let mut total_read = 0;
         let mut total_written = 0;
 
+        // A connection whose pings are no longer answered is considered dead.
+        if self.inner.num_ping_failures >= self.inner.max_ping_failures.get() {
+            return Err(Error::TooManyPingFailures);
+        }
+
+        // In case of protocol error, the error is only reported once the GoAway frame has been
+        // entirely written out.
+        if self.inner.multiplexer_error.is_some() && self.inner.multiplexer.goaway_sent() {
+            return Err(self.inner.multiplexer_error.take().unwrap());
+        }
+
         // First, check for timeouts.
         // Note that this might trigger timeouts for requests whose response is available in
         // `incoming_buffer`. This is intentional, as from the perspective of `read_write` the
         // response arrived after the timeout. It is the responsibility of the user to call
         // `read_write` in an appropriate way for this to not happen.
-        if let Some(event) = self.update_now(now) {
+        if let Some(event) = self.update_now(&now) {
             let wake_up_after = self.inner.next_timeout.clone();
             return Ok(ReadWrite {
                 connection: self,
@@ -254,6 +354,11 @@ where
 
         // Decoding the incoming data.
         loop {
+            // No incoming data is processed anymore after a protocol error.
+            if self.inner.multiplexer_error.is_some() {
+                break;
+            }
+
             // Transfer data from `incoming_data` to the internal buffer in `self.encryption`.
             if let Some(incoming_data) = incoming_buffer.as_mut() {
                 let num_read = self
@@ -275,29 +380,38 @@ where
 
             // TODO: handle incoming_data being None
 
-            // Ask the Yamux state machine to decode the buffer present in `self.encryption`.
-            let yamux_decode = self
+            // Ask the multiplexer to decode the buffer present in `self.encryption`.
+            let mux_decode = match self
                 .inner
-                .yamux
+                .multiplexer
                 .incoming_data(self.encryption.decoded_inbound_data())
-                .map_err(Error::Yamux)?;
-            self.inner.yamux = yamux_decode.yamux;
+            {
+                Ok(d) => d,
+                Err((err, multiplexer)) => {
+                    // In the case of yamux, a GoAway frame has been queued and is written out
+                    // below.
+                    self.inner.multiplexer = multiplexer;
+                    self.inner.multiplexer_error = Some(err);
+                    break;
+                }
+            };
+            self.inner.multiplexer = mux_decode.multiplexer;
 
-            // TODO: it is possible that the yamux reading is blocked on writing
+            // TODO: it is possible that the multiplexer reading is blocked on writing
 
-            // Analyze how Yamux has parsed the data.
+            // Analyze how the multiplexer has parsed the data.
             // This still contains references to the data in `self.encryption`.
-            match yamux_decode.detail {
-                None if yamux_decode.bytes_read == 0 => break,
+            match mux_decode.detail {
+                None if mux_decode.bytes_read == 0 => break,
                 None => {
-                    self.encryption
-                        .consume_inbound_data(yamux_decode.bytes_read);
+                    self.encryption.consume_inbound_data(mux_decode.bytes_read);
                 }
 
-                Some(yamux::IncomingDataDetail::IncomingSubstream) => {
+                Some(multiplexer::IncomingDataDetail::IncomingSubstream) => {
                     // Receive a request from the remote for a new incoming substream.
-                    // These requests are automatically accepted.
-                    // TODO: add a limit to the number of substreams
+                    // These requests are automatically accepted. The number of substreams is
+                    // bounded by the multiplexer, which automatically refuses substreams beyond its
+                    // limit.
                     let nego =
                         multistream_select::InProgress::new(multistream_select::Config::Listener {
                             supported_protocols: self
@@ -306,29 +420,32 @@ where
                                 .iter()
                                 .filter(|p| p.inbound_allowed)
                                 .map(|p| p.name.clone())
-                                .chain(
-                                    self.inner
-                                        .notifications_protocols
-                                        .iter()
-                                        .map(|p| p.name.clone()),
-                                )
+                                .chain(self.inner.notifications_protocols.iter().flat_map(|p| {
+                                    iter::once(p.name.clone())
+                                        .chain(p.fallback_protocol_names.iter().cloned())
+                                }))
                                 .chain(iter::once(self.inner.ping_protocol.clone()))
                                 .collect::<Vec<_>>()
                                 .into_iter(),
                         });
                     self.inner
-                        .yamux
+                        .multiplexer
                         .accept_pending_substream(Substream::InboundNegotiating(nego));
-                    self.encryption
-                        .consume_inbound_data(yamux_decode.bytes_read);
+                    self.encryption.consume_inbound_data(mux_decode.bytes_read);
+                }
+
+                Some(multiplexer::IncomingDataDetail::GoAway) => {
+                    // The remote will no longer accept new substreams. Substreams that are
+                    // already open continue to function normally, and new requests will be
+                    // refused by the remote.
+                    self.encryption.consume_inbound_data(mux_decode.bytes_read);
                 }
 
-                Some(yamux::IncomingDataDetail::StreamReset {
+                Some(multiplexer::IncomingDataDetail::StreamReset {
                     substream_id,
                     user_data: substream_ty,
                 }) => {
-                    self.encryption
-                        .consume_inbound_data(yamux_decode.bytes_read);
+                    self.encryption.consume_inbound_data(mux_decode.bytes_read);
                     if let Some(event) = self.on_substream_reset(substream_id, substream_ty) {
                         let wake_up_after = self.inner.next_timeout.clone();
                         return Ok(ReadWrite {
@@ -342,19 +459,18 @@ where
                     }
                 }
 
-                Some(yamux::IncomingDataDetail::StreamClosed {
+                Some(multiplexer::IncomingDataDetail::StreamClosed {
                     substream_id,
                     user_data,
                 }) => {
-                    self.encryption
-                        .consume_inbound_data(yamux_decode.bytes_read);
+                    self.encryption.consume_inbound_data(mux_decode.bytes_read);
 
                     let user_data = match user_data {
                         Some(ud) => ud,
                         None => {
                             match self
                                 .inner
-                                .yamux
+                                .multiplexer
                                 .substream_by_id(substream_id)
                                 .unwrap()
                                 .into_user_data()
@@ -367,7 +483,7 @@ where
                             }
 
                             self.inner
-                                .yamux
+                                .multiplexer
                                 .substream_by_id(substream_id)
                                 .unwrap()
                                 .close()
@@ -417,26 +533,74 @@ where
                             todo!()
                         }
                         Substream::PingIn(_) => {}
+                        Substream::PingOutNegotiating { .. } | Substream::PingOut { .. } => {
+                            // The remote has closed the substream before sending back the
+                            // payload.
+                            self.inner.num_ping_failures += 1;
+                            let wake_up_after = self.inner.next_timeout.clone();
+                            return Ok(ReadWrite {
+                                connection: self,
+                                read_bytes: total_read,
+                                written_bytes: total_written,
+                                write_close: false,
+                                wake_up_after,
+                                event: Some(Event::PingOutFailed),
+                            });
+                        }
+                        Substream::PingOutClosed => {}
                         _ => todo!("other substream kind"),
                     }
                 }
 
-                Some(yamux::IncomingDataDetail::DataFrame {
+                Some(multiplexer::IncomingDataDetail::DataFrame {
                     start_offset,
                     substream_id,
                 }) => {
                     // Data belonging to a substream has been decoded.
                     let data = &self.encryption.decoded_inbound_data()
-                        [start_offset..yamux_decode.bytes_read];
+                        [start_offset..mux_decode.bytes_read];
+                    let data_len = data.len();
+
+                    // The protocol of the substream is needed for bandwidth accounting. It is
+                    // queried both before and after the data is processed, as the protocol
+                    // might only be known after the negotiation has finished, and the
+                    // substream might have been destroyed after the data has been processed.
+                    let (protocol_before, queued_before) = {
+                        let mut substream = self
+                            .inner
+                            .multiplexer
+                            .substream_by_id(substream_id)
+                            .unwrap();
+                        let queued = substream.queued_bytes();
+                        (substream.user_data().protocol(), queued)
+                    };
 
-                    let event = self
-                        .inner
-                        .inject_substream_data(SubstreamId(substream_id), data);
+                    let event =
+                        self.inner
+                            .inject_substream_data(&now, SubstreamId(substream_id), data);
+
+                    // The data has now been processed, and the remote can be allowed to send
+                    // more. The substream might have been reset while processing the data.
+                    let (protocol_after, num_written) = if let Some(mut substream) =
+                        self.inner.multiplexer.substream_by_id(substream_id)
+                    {
+                        substream.add_remote_window(u64::try_from(data_len).unwrap());
+                        let num_written = substream.queued_bytes().saturating_sub(queued_before);
+                        (substream.user_data().protocol(), num_written)
+                    } else {
+                        (None, 0)
+                    };
+                    if let Some(bandwidth) = protocol_after
+                        .or(protocol_before)
+                        .map(|protocol| self.inner.protocol_bandwidth_mut(protocol))
+                    {
+                        bandwidth.add_received(data_len);
+                        bandwidth.add_sent(num_written);
+                    }
 
-                    // Now that the Yamux parsing has been processed, discard this data in
+                    // Now that the multiplexer parsing has been processed, discard this data in
                     // `self.encryption`.
-                    self.encryption
-                        .consume_inbound_data(yamux_decode.bytes_read);
+                    self.encryption.consume_inbound_data(mux_decode.bytes_read);
 
                     if let Some(event) = event {
                         let wake_up_after = self.inner.next_timeout.clone();
@@ -450,14 +614,14 @@ where
                         });
                     }
 
-                    if yamux_decode.bytes_read == 0 {
+                    if mux_decode.bytes_read == 0 {
                         break;
                     }
                 }
             };
         }
 
-        // The yamux state machine contains the data that needs to be written out.
+        // The multiplexer contains the data that needs to be written out.
         // Try to flush it.
         loop {
             let bytes_out = self
@@ -467,15 +631,14 @@ where
                 break;
             }
 
-            let mut buffers = self.inner.yamux.extract_out(bytes_out);
-            let mut buffers = buffers.buffers().peekable();
-            if buffers.peek().is_none() {
-                break;
-            }
-
-            let (_read, written) = self
-                .encryption
-                .encrypt(buffers, (&mut outgoing_buffer.0, &mut outgoing_buffer.1));
+            let (_read, written) = match self.inner.multiplexer.extract_out_encrypted(
+                bytes_out,
+                &mut self.encryption,
+                (&mut outgoing_buffer.0, &mut outgoing_buffer.1),
+            ) {
+                Some(v) => v,
+                None => break,
+            };
             debug_assert!(_read <= bytes_out);
             total_written += written;
             let out_buf_0_len = outgoing_buffer.0.len();
@@ -502,7 +665,7 @@ where
 
     fn on_substream_reset(
         &mut self,
-        substream_id: yamux::SubstreamId,
+        substream_id: multiplexer::SubstreamId,
         ty: Substream<TNow, TRqUd, TNotifUd>,
     ) -> Option<Event<TRqUd, TNotifUd>> {
         match ty {
@@ -535,12 +698,17 @@ where
                 })
             }
             Substream::PingIn(_) => None,
+            Substream::PingOutNegotiating { .. } | Substream::PingOut { .. } => {
+                self.inner.num_ping_failures += 1;
+                Some(Event::PingOutFailed)
+            }
+            Substream::PingOutClosed => None,
             Substream::NotificationsOut { user_data, .. } => Some(Event::NotificationsOutReset {
                 id: SubstreamId(substream_id),
                 user_data,
             }),
             Substream::NotificationsOutClosed { .. } => None,
-            Substream::RequestInSend => None,
+            Substream::RequestInSend { .. } => None,
         }
     }
 
@@ -548,21 +716,43 @@ where
     /// time.
     ///
     /// Optionally returns an event that happened as a result of the passage of time.
-    fn update_now(&mut self, now: TNow) -> Option<Event<TRqUd, TNotifUd>> {
-        if self.inner.next_timeout.as_ref().map_or(true, |t| *t > now) {
+    fn update_now(&mut self, now: &TNow) -> Option<Event<TRqUd, TNotifUd>> {
+        // The first outgoing ping is scheduled the first time the current time is known.
+        if self.inner.next_ping.is_none() {
+            let next_ping = now.clone() + self.inner.ping_interval;
+            if self
+                .inner
+                .next_timeout
+                .as_ref()
+                .map_or(true, |t| *t > next_ping)
+            {
+                self.inner.next_timeout = Some(next_ping.clone());
+            }
+            self.inner.next_ping = Some(next_ping);
+        }
+
+        if self.inner.next_timeout.as_ref().map_or(true, |t| *t > *now) {
             return None;
         }
 
+        // Open a new outgoing ping substream if necessary.
+        if self.inner.next_ping.as_ref().map_or(false, |t| *t <= *now) {
+            self.inner.next_ping = Some(now.clone() + self.inner.ping_interval);
+            self.queue_ping(now);
+        }
+
         // Find which substream has timed out. This can be `None`, as the value in
         // `self.inner.next_timeout` can be obsolete.
         let timed_out_substream = self
             .inner
-            .yamux
+            .multiplexer
             .user_datas()
             .find(|(_, substream)| match &substream {
                 Substream::RequestOutNegotiating { timeout, .. }
                 | Substream::RequestOut { timeout, .. }
-                    if *timeout <= now =>
+                | Substream::PingOutNegotiating { timeout, .. }
+                | Substream::PingOut { timeout, .. }
+                    if *timeout <= *now =>
                 {
                     true
                 }
@@ -575,7 +765,7 @@ where
         let event = if let Some(timed_out_substream) = timed_out_substream {
             let substream = self
                 .inner
-                .yamux
+                .multiplexer
                 .substream_by_id(timed_out_substream)
                 .unwrap()
                 .reset();
@@ -587,32 +777,67 @@ where
                     response: Err(RequestError::Timeout),
                     user_data,
                 },
+                Substream::PingOutNegotiating { .. } | Substream::PingOut { .. } => {
+                    self.inner.num_ping_failures += 1;
+                    Event::PingOutFailed
+                }
                 _ => unreachable!(),
             })
         } else {
             None
         };
 
-        // Update `next_timeout`. Note that some of the timeouts in `self.inner.yamux` aren't
+        // Update `next_timeout`. Note that some of the timeouts in `self.inner.multiplexer` aren't
         // necessarily strictly superior to `now`. This is normal. As only one event can be
         // returned at a time, any further timeout will be handled the next time `update_now` is
         // called.
         self.inner.next_timeout = self
             .inner
-            .yamux
+            .multiplexer
             .user_datas()
             .filter_map(|(_, substream)| match &substream {
                 Substream::NotificationsOutNegotiating { timeout, .. }
                 | Substream::RequestOutNegotiating { timeout, .. }
-                | Substream::RequestOut { timeout, .. } => Some(timeout),
+                | Substream::RequestOut { timeout, .. }
+                | Substream::PingOutNegotiating { timeout, .. }
+                | Substream::PingOut { timeout, .. } => Some(timeout),
                 _ => None,
             })
+            .chain(self.inner.next_ping.as_ref())
             .min()
             .cloned();
 
         event
     }
 
+    /// Opens a new outgoing ping substream.
+    fn queue_ping(&mut self, now: &TNow) {
+        let mut negotiation =
+            multistream_select::InProgress::new(multistream_select::Config::Dialer {
+                requested_protocol: self.inner.ping_protocol.clone(), // TODO: clone :-/
+            });
+
+        let (new_state, _, out_buffer) = negotiation.read_write_vec(&[]).unwrap();
+        match new_state {
+            multistream_select::Negotiation::InProgress(n) => negotiation = n,
+            _ => unreachable!(),
+        }
+
+        let mut payload = [0; 32];
+        self.inner.ping_payload_randomness.fill_bytes(&mut payload);
+
+        let mut substream = self
+            .inner
+            .multiplexer
+            .open_substream(Substream::PingOutNegotiating {
+                timeout: now.clone() + self.inner.ping_timeout,
+                negotiation,
+                payload,
+            });
+
+        substream.write(out_buffer);
+    }
+
     /// Sends a request to the remote.
     ///
     /// Must pass the index of the protocol within [`Config::request_protocols`].
@@ -667,23 +892,42 @@ where
             self.inner.next_timeout = Some(timeout.clone());
         }
 
-        let mut substream = self
-            .inner
-            .yamux
-            .open_substream(Substream::RequestOutNegotiating {
-                timeout,
-                negotiation,
-                request: if has_length_prefix {
-                    Some(request)
-                } else {
-                    None
-                },
-                user_data,
-            });
+        let mut substream =
+            self.inner
+                .multiplexer
+                .open_substream(Substream::RequestOutNegotiating {
+                    protocol_index,
+                    timeout,
+                    negotiation,
+                    request: if has_length_prefix {
+                        Some(request)
+                    } else {
+                        None
+                    },
+                    user_data,
+                });
 
+        let out_buffer_len = out_buffer.len();
         substream.write(out_buffer);
 
-        SubstreamId(substream.id())
+        // Allow the remote to send back the entire response without having to wait for window
+        // updates.
+        substream.add_remote_window(
+            u64::try_from(self.inner.request_protocols[protocol_index].max_response_size)
+                .unwrap_or(u64::max_value()),
+        );
+
+        let substream_id = SubstreamId(substream.id());
+        self.inner.request_protocols_bandwidth[protocol_index].add_sent(out_buffer_len);
+        substream_id
+    }
+
+    /// Notifies the remote that no new substream will be accepted on this connection, as a way
+    /// to gracefully shut it down. Substreams that are already open are unaffected.
+    ///
+    /// Has no effect if this method has already been called.
+    pub fn send_goaway(&mut self) {
+        self.inner.multiplexer.send_goaway();
     }
 
     /// Returns the user dat associated to a notifications substream.
@@ -693,10 +937,15 @@ where
         &mut self,
         id: SubstreamId,
     ) -> Option<&mut TNotifUd> {
-        match self.inner.yamux.substream_by_id(id.0)?.into_user_data() {
+        match self
+            .inner
+            .multiplexer
+            .substream_by_id(id.0)?
+            .into_user_data()
+        {
             Substream::NotificationsOutNegotiating { user_data, .. } => Some(user_data),
             Substream::NotificationsOutHandshakeRecv { user_data, .. } => Some(user_data),
-            Substream::NotificationsOut { user_data } => Some(user_data),
+            Substream::NotificationsOut { user_data, .. } => Some(user_data),
             Substream::NotificationsIn { user_data, .. } => Some(user_data),
             _ => None,
         }
@@ -723,12 +972,14 @@ where
         handshake: Vec<u8>,
         user_data: TNotifUd,
     ) -> SubstreamId {
-        let mut negotiation =
-            multistream_select::InProgress::new(multistream_select::Config::Dialer {
-                requested_protocol: self.inner.notifications_protocols[protocol_index]
-                    .name
-                    .clone(), // TODO: clone :-/
-            });
+        let mut negotiation = multistream_select::InProgress::new_dialer_with_fallbacks(
+            self.inner.notifications_protocols[protocol_index]
+                .name
+                .clone(), // TODO: clone :-/
+            self.inner.notifications_protocols[protocol_index]
+                .fallback_protocol_names
+                .clone(),
+        );
 
         // TODO: turn this assert into something that can't panic?
         assert!(
@@ -755,17 +1006,21 @@ where
 
         let mut substream =
             self.inner
-                .yamux
+                .multiplexer
                 .open_substream(Substream::NotificationsOutNegotiating {
+                    protocol_index,
                     timeout,
                     negotiation,
                     handshake,
                     user_data,
                 });
 
+        let out_buffer_len = out_buffer.len();
         substream.write(out_buffer);
 
-        SubstreamId(substream.id())
+        let substream_id = SubstreamId(substream.id());
+        self.inner.notifications_protocols_bandwidth[protocol_index].add_sent(out_buffer_len);
+        substream_id
     }
 
     /// Accepts an inbound notifications protocol. Must be called in response to a
@@ -776,7 +1031,11 @@ where
         handshake: Vec<u8>,
         user_data: TNotifUd,
     ) {
-        let mut substream = self.inner.yamux.substream_by_id(substream_id.0).unwrap();
+        let mut substream = self
+            .inner
+            .multiplexer
+            .substream_by_id(substream_id.0)
+            .unwrap();
 
         match substream.user_data() {
             Substream::NotificationsInWait { protocol_index } => {
@@ -784,14 +1043,18 @@ where
                 let max_notification_size =
                     self.inner.notifications_protocols[protocol_index].max_notification_size;
 
-                substream.write(leb128::encode_usize(handshake.len()).collect());
+                let length_prefix = leb128::encode_usize(handshake.len()).collect::<Vec<_>>();
+                let num_bytes = length_prefix.len() + handshake.len();
+                substream.write(length_prefix);
                 substream.write(handshake);
 
                 *substream.user_data() = Substream::NotificationsIn {
                     next_notification: leb128::FramedInProgress::new(max_notification_size),
                     protocol_index,
                     user_data,
-                }
+                };
+
+                self.inner.notifications_protocols_bandwidth[protocol_index].add_sent(num_bytes);
             }
             _ => panic!(),
         }
@@ -821,12 +1084,18 @@ where
     /// notifications substream isn't in the appropriate state.
     ///
     pub fn write_notification_unbounded(&mut self, id: SubstreamId, notification: Vec<u8>) {
-        let mut substream = self.inner.yamux.substream_by_id(id.0).unwrap();
-        if !matches!(substream.user_data(), Substream::NotificationsOut { .. }) {
-            panic!()
-        }
-        substream.write(leb128::encode_usize(notification.len()).collect());
-        substream.write(notification)
+        let mut substream = self.inner.multiplexer.substream_by_id(id.0).unwrap();
+        let protocol_index = match substream.user_data() {
+            Substream::NotificationsOut { protocol_index, .. } => *protocol_index,
+            _ => panic!(),
+        };
+
+        let length_prefix = leb128::encode_usize(notification.len()).collect::<Vec<_>>();
+        let num_bytes = length_prefix.len() + notification.len();
+        substream.write(length_prefix);
+        substream.write(notification);
+
+        self.inner.notifications_protocols_bandwidth[protocol_index].add_sent(num_bytes);
     }
 
     /// Returns the number of bytes waiting to be sent out on that substream.
@@ -840,7 +1109,7 @@ where
     ///
     // TODO: shouldn't require `&mut self`
     pub fn notification_substream_queued_bytes(&mut self, id: SubstreamId) -> usize {
-        let mut substream = self.inner.yamux.substream_by_id(id.0).unwrap();
+        let mut substream = self.inner.multiplexer.substream_by_id(id.0).unwrap();
         if !matches!(substream.user_data(), Substream::NotificationsOut { .. }) {
             panic!()
         }
@@ -855,7 +1124,7 @@ where
     /// notifications substream isn't in the appropriate state.
     ///
     pub fn close_notifications_substream(&mut self, id: SubstreamId) {
-        let mut substream = self.inner.yamux.substream_by_id(id.0).unwrap();
+        let mut substream = self.inner.multiplexer.substream_by_id(id.0).unwrap();
         if !matches!(substream.user_data(), Substream::NotificationsOut { .. }) {
             panic!()
         }
@@ -875,14 +1144,19 @@ where
     ) -> Result<(), RespondInRequestError> {
         let mut substream = self
             .inner
-            .yamux
+            .multiplexer
             .substream_by_id(substream_id.0)
             .ok_or(RespondInRequestError::SubstreamClosed)?;
 
         match substream.user_data() {
-            Substream::RequestInSend => {
+            Substream::RequestInSend { protocol_index } => {
+                let protocol_index = *protocol_index;
+
+                let mut num_bytes = 0;
                 if let Ok(response) = response {
-                    substream.write(leb128::encode_usize(response.len()).collect());
+                    let length_prefix = leb128::encode_usize(response.len()).collect::<Vec<_>>();
+                    num_bytes = length_prefix.len() + response.len();
+                    substream.write(length_prefix);
                     substream.write(response);
                 }
 
@@ -890,6 +1164,7 @@ where
                 *substream.user_data() = Substream::NegotiationFailed;
 
                 substream.close();
+                self.inner.request_protocols_bandwidth[protocol_index].add_sent(num_bytes);
                 Ok(())
             }
             _ => panic!(),
@@ -897,25 +1172,76 @@ where
     }
 }
 
+impl<TNow, TRqUd, TNotifUd> Established<TNow, TRqUd, TNotifUd> {
+    /// Returns the number of bytes transferred on substreams of the given notifications
+    /// protocol, including the protocol negotiation and the handshakes.
+    ///
+    /// Must pass the index of the protocol within [`Config::notifications_protocols`].
+    ///
+    /// > **Note**: Bytes exchanged on inbound substreams before a protocol has been negotiated,
+    /// >           and the overhead of the Yamux and encryption layers, aren't attributed to any
+    /// >           protocol.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `protocol_index` is out of range.
+    ///
+    pub fn notifications_protocol_bandwidth(&self, protocol_index: usize) -> Bandwidth {
+        self.inner.notifications_protocols_bandwidth[protocol_index]
+    }
+
+    /// Returns the number of bytes transferred on substreams of the given request-response
+    /// protocol, including the protocol negotiation.
+    ///
+    /// Must pass the index of the protocol within [`Config::request_protocols`].
+    ///
+    /// See also the note in the documentation of
+    /// [`Established::notifications_protocol_bandwidth`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if `protocol_index` is out of range.
+    ///
+    pub fn request_protocol_bandwidth(&self, protocol_index: usize) -> Bandwidth {
+        self.inner.request_protocols_bandwidth[protocol_index]
+    }
+}
+
 impl<TNow, TRqUd, TNotifUd> fmt::Debug for Established<TNow, TRqUd, TNotifUd>
 where
     TRqUd: fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_map()
-            .entries(self.inner.yamux.user_datas())
+            .entries(self.inner.multiplexer.user_datas())
             .finish()
     }
 }
 
 impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
+    /// Returns the bandwidth counters of the given protocol.
+    fn protocol_bandwidth_mut(&mut self, protocol: SubstreamProtocol) -> &mut Bandwidth {
+        match protocol {
+            SubstreamProtocol::Notifications(idx) => {
+                &mut self.notifications_protocols_bandwidth[idx]
+            }
+            SubstreamProtocol::Request(idx) => &mut self.request_protocols_bandwidth[idx],
+        }
+    }
+}
+
+impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd>
+where
+    TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+{
     fn inject_substream_data(
         &mut self,
+        now: &TNow,
         substream_id: SubstreamId,
         mut data: &[u8],
     ) -> Option<Event<TRqUd, TNotifUd>> {
         while !data.is_empty() {
-            let mut substream = self.yamux.substream_by_id(substream_id.0).unwrap();
+            let mut substream = self.multiplexer.substream_by_id(substream_id.0).unwrap();
 
             // In order to solve borrowing-related issues, the block below temporarily
             // replaces the state of the substream with `Poisoned`, then later puts back a
@@ -953,20 +1279,23 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                                     };
                                 } else {
                                     // TODO: make sure that data is empty?
-                                    *substream.user_data() = Substream::RequestInSend;
+                                    *substream.user_data() =
+                                        Substream::RequestInSend { protocol_index };
                                     return Some(Event::RequestIn {
                                         id: substream_id,
                                         protocol_index,
                                         request: Vec::new(),
                                     });
                                 }
-                            } else if let Some(protocol_index) = self
-                                .notifications_protocols
-                                .iter()
-                                .position(|p| p.name == protocol)
+                            } else if let Some(protocol_index) =
+                                self.notifications_protocols.iter().position(|p| {
+                                    p.name == protocol
+                                        || p.fallback_protocol_names.iter().any(|f| *f == protocol)
+                                })
                             {
                                 *substream.user_data() = Substream::NotificationsInHandshake {
                                     protocol_index,
+                                    negotiated_protocol: protocol,
                                     handshake: leb128::FramedInProgress::new(
                                         self.notifications_protocols[protocol_index]
                                             .max_handshake_size,
@@ -996,6 +1325,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                     *substream.user_data() = Substream::NegotiationFailed;
                 }
                 Substream::NotificationsOutNegotiating {
+                    protocol_index,
                     negotiation,
                     timeout,
                     handshake,
@@ -1011,19 +1341,26 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                             data = &data[read..];
                             substream.write(out_buffer);
                             *substream.user_data() = Substream::NotificationsOutNegotiating {
+                                protocol_index,
                                 negotiation: nego,
                                 timeout,
                                 handshake,
                                 user_data,
                             };
                         }
-                        Ok((multistream_select::Negotiation::Success(_), num_read, out_buffer)) => {
+                        Ok((
+                            multistream_select::Negotiation::Success(negotiated_protocol),
+                            num_read,
+                            out_buffer,
+                        )) => {
                             substream.write(out_buffer);
                             data = &data[num_read..];
                             substream.write(leb128::encode_usize(handshake.len()).collect());
                             substream.write(handshake);
                             *substream.user_data() = Substream::NotificationsOutHandshakeRecv {
+                                protocol_index,
                                 handshake: leb128::FramedInProgress::new(10 * 1024), // TODO: proper max size
+                                negotiated_protocol,
                                 user_data,
                             };
                         }
@@ -1031,7 +1368,9 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                     }
                 }
                 Substream::NotificationsOutHandshakeRecv {
+                    protocol_index,
                     handshake,
+                    negotiated_protocol,
                     user_data,
                 } => {
                     match handshake.update(&data) {
@@ -1040,16 +1379,22 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                                 todo!() // TODO:
                             }
 
-                            *substream.user_data() = Substream::NotificationsOut { user_data };
+                            *substream.user_data() = Substream::NotificationsOut {
+                                protocol_index,
+                                user_data,
+                            };
                             return Some(Event::NotificationsOutAccept {
                                 id: substream_id,
+                                negotiated_protocol,
                                 remote_handshake,
                             });
                         }
                         Ok((num_read, leb128::Framed::InProgress(handshake))) => {
                             data = &data[num_read..];
                             *substream.user_data() = Substream::NotificationsOutHandshakeRecv {
+                                protocol_index,
                                 handshake,
+                                negotiated_protocol,
                                 user_data,
                             };
                         }
@@ -1058,16 +1403,23 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                         }
                     }
                 }
-                Substream::NotificationsOut { user_data } => {
+                Substream::NotificationsOut {
+                    protocol_index,
+                    user_data,
+                } => {
                     // Receiving data on an outgoing substream is forbidden by the protocol.
                     data = &[];
-                    *substream.user_data() = Substream::NotificationsOut { user_data };
+                    *substream.user_data() = Substream::NotificationsOut {
+                        protocol_index,
+                        user_data,
+                    };
                 }
                 Substream::NotificationsOutClosed => {
                     data = &[];
                     *substream.user_data() = Substream::NotificationsOutClosed;
                 }
                 Substream::RequestOutNegotiating {
+                    protocol_index,
                     negotiation,
                     timeout,
                     request,
@@ -1083,6 +1435,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                             data = &data[_read..];
                             substream.write(out_buffer);
                             *substream.user_data() = Substream::RequestOutNegotiating {
+                                protocol_index,
                                 negotiation: nego,
                                 timeout,
                                 request,
@@ -1097,6 +1450,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                                 substream.write(request);
                             }
                             *substream.user_data() = Substream::RequestOut {
+                                protocol_index,
                                 timeout,
                                 user_data,
                                 response: leb128::FramedInProgress::new(10 * 1024 * 1024), // TODO: proper max size
@@ -1104,7 +1458,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                             let substream_id = substream.id();
                             let _already_closed = substream.close();
                             debug_assert!(_already_closed.is_none());
-                            substream = self.yamux.substream_by_id(substream_id).unwrap();
+                            substream = self.multiplexer.substream_by_id(substream_id).unwrap();
                         }
                         Ok((multistream_select::Negotiation::NotAvailable, ..)) => {
                             substream.reset();
@@ -1125,6 +1479,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                     }
                 }
                 Substream::RequestOut {
+                    protocol_index,
                     timeout,
                     user_data,
                     response,
@@ -1143,6 +1498,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                             debug_assert_eq!(num_read, data.len());
                             data = &data[num_read..];
                             *substream.user_data() = Substream::RequestOut {
+                                protocol_index,
                                 timeout,
                                 user_data,
                                 response,
@@ -1164,7 +1520,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                 } => {
                     match request.update(&data) {
                         Ok((_num_read, leb128::Framed::Finished(request))) => {
-                            *substream.user_data() = Substream::RequestInSend;
+                            *substream.user_data() = Substream::RequestInSend { protocol_index };
                             return Some(Event::RequestIn {
                                 id: substream_id,
                                 protocol_index,
@@ -1189,6 +1545,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                 Substream::NotificationsInHandshake {
                     handshake,
                     protocol_index,
+                    negotiated_protocol,
                 } => match handshake.update(&data) {
                     Ok((num_read, leb128::Framed::Finished(handshake))) => {
                         *substream.user_data() = Substream::NotificationsInWait { protocol_index };
@@ -1196,6 +1553,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                         return Some(Event::NotificationsInOpen {
                             id: substream_id,
                             protocol_index,
+                            negotiated_protocol,
                             handshake,
                         });
                     }
@@ -1204,6 +1562,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                         *substream.user_data() = Substream::NotificationsInHandshake {
                             handshake,
                             protocol_index,
+                            negotiated_protocol,
                         };
                     }
                     Err(_) => {
@@ -1279,6 +1638,77 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
 
                     *substream.user_data() = Substream::PingIn(payload);
                 }
+                Substream::PingOutNegotiating {
+                    timeout,
+                    negotiation,
+                    payload,
+                } => match negotiation.read_write_vec(data) {
+                    Ok((multistream_select::Negotiation::InProgress(nego), read, out_buffer)) => {
+                        debug_assert_eq!(read, data.len());
+                        data = &data[read..];
+                        substream.write(out_buffer);
+                        *substream.user_data() = Substream::PingOutNegotiating {
+                            timeout,
+                            negotiation: nego,
+                            payload,
+                        };
+                    }
+                    Ok((multistream_select::Negotiation::Success(_), num_read, out_buffer)) => {
+                        substream.write(out_buffer);
+                        data = &data[num_read..];
+                        substream.write(payload.to_vec());
+                        *substream.user_data() = Substream::PingOut {
+                            timeout,
+                            sent_at: now.clone(),
+                            payload,
+                            received: arrayvec::ArrayVec::new(),
+                        };
+                    }
+                    Ok((multistream_select::Negotiation::NotAvailable, ..)) | Err(_) => {
+                        substream.reset();
+                        self.num_ping_failures += 1;
+                        return Some(Event::PingOutFailed);
+                    }
+                },
+                Substream::PingOut {
+                    timeout,
+                    sent_at,
+                    payload,
+                    mut received,
+                } => {
+                    // Copy the data into `received` until 32 bytes have been received.
+                    let num_read = cmp::min(data.len(), received.remaining_capacity());
+                    received.try_extend_from_slice(&data[..num_read]).unwrap();
+                    data = &data[num_read..];
+
+                    if !received.is_full() {
+                        *substream.user_data() = Substream::PingOut {
+                            timeout,
+                            sent_at,
+                            payload,
+                            received,
+                        };
+                        continue;
+                    }
+
+                    if received.as_slice() != payload {
+                        substream.reset();
+                        self.num_ping_failures += 1;
+                        return Some(Event::PingOutFailed);
+                    }
+
+                    self.num_ping_failures = 0;
+                    *substream.user_data() = Substream::PingOutClosed;
+                    substream.close();
+                    return Some(Event::PingOutSuccess {
+                        ping_time: now.clone() - sent_at,
+                    });
+                }
+                Substream::PingOutClosed => {
+                    // Any further data sent by the remote is silently discarded.
+                    data = &[];
+                    *substream.user_data() = Substream::PingOutClosed;
+                }
                 _ => todo!("other substream kind"),
             };
         }
@@ -1287,6 +1717,45 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
     }
 }
 
+impl<TNow, TRqUd, TNotifUd> Substream<TNow, TRqUd, TNotifUd> {
+    /// Returns the protocol of this substream, if known.
+    fn protocol(&self) -> Option<SubstreamProtocol> {
+        match self {
+            Substream::NotificationsOutNegotiating { protocol_index, .. }
+            | Substream::NotificationsOutHandshakeRecv { protocol_index, .. }
+            | Substream::NotificationsOut { protocol_index, .. }
+            | Substream::NotificationsInHandshake { protocol_index, .. }
+            | Substream::NotificationsInWait { protocol_index }
+            | Substream::NotificationsIn { protocol_index, .. } => {
+                Some(SubstreamProtocol::Notifications(*protocol_index))
+            }
+            Substream::RequestOutNegotiating { protocol_index, .. }
+            | Substream::RequestOut { protocol_index, .. }
+            | Substream::RequestInRecv { protocol_index, .. }
+            | Substream::RequestInSend { protocol_index } => {
+                Some(SubstreamProtocol::Request(*protocol_index))
+            }
+            Substream::Poisoned
+            | Substream::InboundNegotiating(_)
+            | Substream::NegotiationFailed
+            | Substream::NotificationsOutClosed
+            | Substream::PingIn(_)
+            | Substream::PingOutNegotiating { .. }
+            | Substream::PingOut { .. }
+            | Substream::PingOutClosed => None,
+        }
+    }
+}
+
+/// Protocol of a substream. See [`Substream::protocol`].
+#[derive(Debug, Copy, Clone)]
+enum SubstreamProtocol {
+    /// Index within [`Config::notifications_protocols`].
+    Notifications(usize),
+    /// Index within [`Config::request_protocols`].
+    Request(usize),
+}
+
 impl<TNow, TRqUd, TNotifUd> fmt::Debug for Substream<TNow, TRqUd, TNotifUd>
 where
     TRqUd: fmt::Debug,
@@ -1321,17 +1790,46 @@ where
             Substream::RequestInRecv { protocol_index, .. } => {
                 f.debug_tuple("request-in").field(protocol_index).finish()
             }
-            Substream::RequestInSend => {
+            Substream::RequestInSend { .. } => {
                 todo!() // TODO:
             }
             Substream::PingIn(_) => f.debug_tuple("ping-in").finish(),
+            Substream::PingOutNegotiating { .. } | Substream::PingOut { .. } => {
+                f.debug_tuple("ping-out").finish()
+            }
+            Substream::PingOutClosed => f.debug_tuple("ping-out-closed").finish(),
         }
     }
 }
 
+/// Number of bytes transferred in each direction.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Bandwidth {
+    /// Number of bytes received from the remote.
+    pub bytes_received: u64,
+    /// Number of bytes sent, or queued for sending, to the remote.
+    pub bytes_sent: u64,
+}
+
+impl Bandwidth {
+    /// Adds `num_bytes` to [`Bandwidth::bytes_received`].
+    pub(crate) fn add_received(&mut self, num_bytes: usize) {
+        self.bytes_received = self
+            .bytes_received
+            .saturating_add(u64::try_from(num_bytes).unwrap_or(u64::max_value()));
+    }
+
+    /// Adds `num_bytes` to [`Bandwidth::bytes_sent`].
+    pub(crate) fn add_sent(&mut self, num_bytes: usize) {
+        self.bytes_sent = self
+            .bytes_sent
+            .saturating_add(u64::try_from(num_bytes).unwrap_or(u64::max_value()));
+    }
+}
+
 /// Identifier of a request or a notifications substream.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct SubstreamId(yamux::SubstreamId);
+pub struct SubstreamId(multiplexer::SubstreamId);
 
 /// Outcome of [`Established::read_write`].
 #[must_use]
@@ -1403,6 +1901,10 @@ pub enum Event<TRqUd, TNotifUd> {
         /// The index refers to the position of the protocol in
         /// [`Config::notifications_protocols`].
         protocol_index: usize,
+        /// Name of the protocol that was negotiated. Can be either
+        /// [`ConfigNotifications::name`] or one of the
+        /// [`ConfigNotifications::fallback_protocol_names`].
+        negotiated_protocol: String,
         /// Handshake sent by the remote. Its interpretation is out of scope of this module.
         handshake: Vec<u8>,
     },
@@ -1439,6 +1941,10 @@ pub enum Event<TRqUd, TNotifUd> {
         /// Identifier of the substream. Value that was returned by
         /// [`Established::open_notifications_substream`].
         id: SubstreamId,
+        /// Name of the protocol that was negotiated. Can be either
+        /// [`ConfigNotifications::name`] or one of the
+        /// [`ConfigNotifications::fallback_protocol_names`].
+        negotiated_protocol: String,
         /// Handshake sent back by the remote. Its interpretation is out of scope of this module.
         remote_handshake: Vec<u8>,
     },
@@ -1468,6 +1974,20 @@ pub enum Event<TRqUd, TNotifUd> {
         /// Value that was passed to [`Established::open_notifications_substream`].
         user_data: TNotifUd,
     },
+
+    /// An outgoing ping has been answered by the remote.
+    PingOutSuccess {
+        /// Time between the moment the ping payload has been queued for send out and the moment
+        /// the remote has sent it back.
+        ping_time: Duration,
+    },
+
+    /// An outgoing ping has failed, either because the remote hasn't answered in time, has
+    /// refused the substream, or has sent back an invalid payload.
+    ///
+    /// After [`Config::max_ping_failures`] consecutive failures, [`Established::read_write`]
+    /// returns [`Error::TooManyPingFailures`].
+    PingOutFailed,
 }
 
 /// Error during a connection. The connection should be shut down.
@@ -1477,6 +1997,10 @@ pub enum Error {
     Noise(noise::CipherError),
     /// Error in the yamux multiplexing protocol.
     Yamux(yamux::Error),
+    /// Error in the mplex multiplexing protocol.
+    Mplex(mplex::Error),
+    /// Too many consecutive outgoing pings have failed. The remote is most likely unreachable.
+    TooManyPingFailures,
 }
 
 /// Error that can happen during a request in a request-response scheme.
@@ -1501,12 +2025,24 @@ pub enum RequestError {
 /// Successfully negotiated connection. Ready to be turned into a [`Established`].
 pub struct ConnectionPrototype {
     encryption: noise::Noise,
+    multiplexer: multiplexer::MultiplexerKind,
 }
 
 impl ConnectionPrototype {
     /// Builds a new [`ConnectionPrototype`] of a connection using the Noise and Yamux protocols.
     pub(crate) fn from_noise_yamux(encryption: noise::Noise) -> Self {
-        ConnectionPrototype { encryption }
+        ConnectionPrototype {
+            encryption,
+            multiplexer: multiplexer::MultiplexerKind::Yamux,
+        }
+    }
+
+    /// Builds a new [`ConnectionPrototype`] of a connection using the Noise and Mplex protocols.
+    pub(crate) fn from_noise_mplex(encryption: noise::Noise) -> Self {
+        ConnectionPrototype {
+            encryption,
+            multiplexer: multiplexer::MultiplexerKind::Mplex,
+        }
     }
 
     /// Turns this prototype into an actual connection.
@@ -1516,20 +2052,40 @@ impl ConnectionPrototype {
     ) -> Established<TNow, TRqUd, TNotifUd> {
         // TODO: check conflicts between protocol names?
 
-        let yamux = yamux::Yamux::new(yamux::Config {
-            is_initiator: self.encryption.is_initiator(),
-            capacity: 64, // TODO: ?
-            randomness_seed: config.randomness_seed,
-        });
+        let mut randomness = rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed);
+
+        let multiplexer = multiplexer::Multiplexer::new(
+            self.multiplexer,
+            self.encryption.is_initiator(),
+            64, // TODO: ?
+            randomness.gen(),
+            config.max_simultaneous_substreams,
+            config.yamux_substream_receive_window,
+        );
 
         Established {
             encryption: self.encryption,
             inner: Inner {
-                yamux,
+                multiplexer,
                 next_timeout: None,
+                ping_protocol: config.ping_protocol,
+                ping_interval: config.ping_interval,
+                ping_timeout: config.ping_timeout,
+                max_ping_failures: config.max_ping_failures,
+                next_ping: None,
+                num_ping_failures: 0,
+                ping_payload_randomness: rand_chacha::ChaCha20Rng::from_seed(randomness.gen()),
+                multiplexer_error: None,
+                notifications_protocols_bandwidth: vec![
+                    Bandwidth::default();
+                    config.notifications_protocols.len()
+                ],
+                request_protocols_bandwidth: vec![
+                    Bandwidth::default();
+                    config.request_protocols.len()
+                ],
                 request_protocols: config.request_protocols,
                 notifications_protocols: config.notifications_protocols,
-                ping_protocol: config.ping_protocol,
             },
         }
     }
@@ -1551,8 +2107,23 @@ pub struct Config {
     pub notifications_protocols: Vec<ConfigNotifications>,
     /// Name of the ping protocol on the network.
     pub ping_protocol: String,
+    /// Time between two consecutive outgoing pings.
+    pub ping_interval: Duration,
+    /// Maximum time the remote can take to answer an outgoing ping before it is considered
+    /// failed.
+    pub ping_timeout: Duration,
+    /// Number of consecutive outgoing ping failures after which the connection is considered
+    /// dead. See [`Error::TooManyPingFailures`].
+    pub max_ping_failures: NonZeroU32,
+    /// Maximum number of substreams simultaneously open on this connection. Substreams that the
+    /// remote tries to open beyond this limit are automatically refused.
+    pub max_simultaneous_substreams: usize,
+    /// Number of bytes that the remote is allowed to send on each substream before having to
+    /// wait for the local node to process the data. Only applies to yamux, as mplex has no flow
+    /// control. Values below 256 kiB are equivalent to 256 kiB.
+    pub yamux_substream_receive_window: u64,
     /// Seed used for the randomness specific to this connection.
-    pub randomness_seed: (u64, u64, u64, u64),
+    pub randomness_seed: [u8; 32],
 }
 
 /// Configuration for a request-response protocol.
@@ -1596,6 +2167,13 @@ pub struct ConfigNotifications {
     /// Name of the protocol transferred on the wire.
     pub name: String,
 
+    /// Alternative names of the same protocol, in decreasing order of preference.
+    ///
+    /// When opening an outbound substream, these names are tried one after the other if the
+    /// remote doesn't support [`ConfigNotifications::name`]. Inbound substreams using any of
+    /// these names are accepted.
+    pub fallback_protocol_names: Vec<String>,
+
     /// Maximum size, in bytes, of the handshake that can be received.
     pub max_handshake_size: usize,
 
@@ -0,0 +1,340 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Abstraction over the multiplexing protocols supported by an [`super::Established`].
+//!
+//! Both the [`yamux`] and [`mplex`] modules expose the same API shape. The types in this module
+//! simply dispatch each call to the multiplexer that has been negotiated for the connection.
+
+use super::super::{mplex, noise, yamux};
+use super::Error;
+
+use alloc::vec::Vec;
+
+/// Multiplexing protocol negotiated for a connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum MultiplexerKind {
+    Yamux,
+    Mplex,
+}
+
+/// State machine of either of the supported multiplexing protocols.
+pub(super) enum Multiplexer<T> {
+    Yamux(yamux::Yamux<T>),
+    Mplex(mplex::Mplex<T>),
+}
+
+impl<T> Multiplexer<T> {
+    /// Initializes a new multiplexer of the given kind.
+    pub(super) fn new(
+        kind: MultiplexerKind,
+        is_initiator: bool,
+        capacity: usize,
+        randomness_seed: (u64, u64, u64, u64),
+        max_simultaneous_substreams: usize,
+        yamux_substream_receive_window: u64,
+    ) -> Self {
+        match kind {
+            MultiplexerKind::Yamux => Multiplexer::Yamux(yamux::Yamux::new(yamux::Config {
+                is_initiator,
+                capacity,
+                randomness_seed,
+                max_simultaneous_substreams,
+                substream_receive_window: yamux_substream_receive_window,
+            })),
+            MultiplexerKind::Mplex => Multiplexer::Mplex(mplex::Mplex::new(mplex::Config {
+                capacity,
+                randomness_seed,
+                max_simultaneous_substreams,
+            })),
+        }
+    }
+
+    /// Notifies the remote that no new substream will be accepted.
+    ///
+    /// Mplex has no equivalent to the yamux GoAway frame. Substreams opened by the remote are
+    /// silently refused instead.
+    pub(super) fn send_goaway(&mut self) {
+        match self {
+            Multiplexer::Yamux(yamux) => {
+                yamux.send_goaway(yamux::GoAwayErrorCode::NormalTermination)
+            }
+            Multiplexer::Mplex(mplex) => mplex.refuse_incoming_substreams(),
+        }
+    }
+
+    /// Returns `true` if a GoAway frame has been entirely written out. Always `true` for mplex,
+    /// as there is nothing to write out.
+    pub(super) fn goaway_sent(&self) -> bool {
+        match self {
+            Multiplexer::Yamux(yamux) => yamux.goaway_sent(),
+            Multiplexer::Mplex(_) => true,
+        }
+    }
+
+    pub(super) fn open_substream(&mut self, user_data: T) -> SubstreamMut<T> {
+        match self {
+            Multiplexer::Yamux(yamux) => SubstreamMut::Yamux(yamux.open_substream(user_data)),
+            Multiplexer::Mplex(mplex) => SubstreamMut::Mplex(mplex.open_substream(user_data)),
+        }
+    }
+
+    pub(super) fn user_datas(&self) -> impl Iterator<Item = (SubstreamId, &T)> {
+        match self {
+            Multiplexer::Yamux(yamux) => either::Left(
+                yamux
+                    .user_datas()
+                    .map(|(id, ud)| (SubstreamId::Yamux(id), ud)),
+            ),
+            Multiplexer::Mplex(mplex) => either::Right(
+                mplex
+                    .user_datas()
+                    .map(|(id, ud)| (SubstreamId::Mplex(id), ud)),
+            ),
+        }
+    }
+
+    pub(super) fn substream_by_id(&mut self, id: SubstreamId) -> Option<SubstreamMut<T>> {
+        match (self, id) {
+            (Multiplexer::Yamux(yamux), SubstreamId::Yamux(id)) => {
+                yamux.substream_by_id(id).map(SubstreamMut::Yamux)
+            }
+            (Multiplexer::Mplex(mplex), SubstreamId::Mplex(id)) => {
+                mplex.substream_by_id(id).map(SubstreamMut::Mplex)
+            }
+            _ => None,
+        }
+    }
+
+    /// Process some incoming data.
+    ///
+    /// In case of protocol error, the multiplexer is returned alongside with the error. If the
+    /// multiplexer is yamux, a GoAway frame has been queued.
+    pub(super) fn incoming_data(
+        self,
+        data: &[u8],
+    ) -> Result<IncomingDataOutcome<T>, (Error, Self)> {
+        match self {
+            Multiplexer::Yamux(yamux) => match yamux.incoming_data(data) {
+                Ok(outcome) => Ok(IncomingDataOutcome {
+                    multiplexer: Multiplexer::Yamux(outcome.yamux),
+                    bytes_read: outcome.bytes_read,
+                    detail: outcome.detail.map(|detail| match detail {
+                        yamux::IncomingDataDetail::IncomingSubstream => {
+                            IncomingDataDetail::IncomingSubstream
+                        }
+                        yamux::IncomingDataDetail::DataFrame {
+                            start_offset,
+                            substream_id,
+                        } => IncomingDataDetail::DataFrame {
+                            start_offset,
+                            substream_id: SubstreamId::Yamux(substream_id),
+                        },
+                        yamux::IncomingDataDetail::StreamClosed {
+                            substream_id,
+                            user_data,
+                        } => IncomingDataDetail::StreamClosed {
+                            substream_id: SubstreamId::Yamux(substream_id),
+                            user_data,
+                        },
+                        yamux::IncomingDataDetail::StreamReset {
+                            substream_id,
+                            user_data,
+                        } => IncomingDataDetail::StreamReset {
+                            substream_id: SubstreamId::Yamux(substream_id),
+                            user_data,
+                        },
+                        yamux::IncomingDataDetail::GoAway(_) => IncomingDataDetail::GoAway,
+                    }),
+                }),
+                Err((err, yamux)) => Err((Error::Yamux(err), Multiplexer::Yamux(yamux))),
+            },
+            Multiplexer::Mplex(mplex) => match mplex.incoming_data(data) {
+                Ok(outcome) => Ok(IncomingDataOutcome {
+                    multiplexer: Multiplexer::Mplex(outcome.mplex),
+                    bytes_read: outcome.bytes_read,
+                    detail: outcome.detail.map(|detail| match detail {
+                        mplex::IncomingDataDetail::IncomingSubstream => {
+                            IncomingDataDetail::IncomingSubstream
+                        }
+                        mplex::IncomingDataDetail::DataFrame {
+                            start_offset,
+                            substream_id,
+                        } => IncomingDataDetail::DataFrame {
+                            start_offset,
+                            substream_id: SubstreamId::Mplex(substream_id),
+                        },
+                        mplex::IncomingDataDetail::StreamClosed {
+                            substream_id,
+                            user_data,
+                        } => IncomingDataDetail::StreamClosed {
+                            substream_id: SubstreamId::Mplex(substream_id),
+                            user_data,
+                        },
+                        mplex::IncomingDataDetail::StreamReset {
+                            substream_id,
+                            user_data,
+                        } => IncomingDataDetail::StreamReset {
+                            substream_id: SubstreamId::Mplex(substream_id),
+                            user_data,
+                        },
+                    }),
+                }),
+                Err((err, mplex)) => Err((Error::Mplex(err), Multiplexer::Mplex(mplex))),
+            },
+        }
+    }
+
+    /// Extracts up to `size_bytes` bytes of data to send out, and encrypts them into
+    /// `destination` using `encryption`.
+    ///
+    /// Returns `None` if there is nothing to send out. Otherwise, returns the number of bytes
+    /// extracted from the multiplexer and the number of bytes written to `destination`.
+    pub(super) fn extract_out_encrypted<'a>(
+        &mut self,
+        size_bytes: usize,
+        encryption: &mut noise::Noise,
+        destination: (&'a mut [u8], &'a mut [u8]),
+    ) -> Option<(usize, usize)> {
+        match self {
+            Multiplexer::Yamux(yamux) => {
+                let mut buffers = yamux.extract_out(size_bytes);
+                let mut buffers = buffers.buffers().peekable();
+                buffers.peek()?;
+                Some(encryption.encrypt(buffers, destination))
+            }
+            Multiplexer::Mplex(mplex) => {
+                let mut buffers = mplex.extract_out(size_bytes);
+                let mut buffers = buffers.buffers().peekable();
+                buffers.peek()?;
+                Some(encryption.encrypt(buffers, destination))
+            }
+        }
+    }
+
+    /// Accepts the substream whose opening has been reported with
+    /// [`IncomingDataDetail::IncomingSubstream`].
+    pub(super) fn accept_pending_substream(&mut self, user_data: T) -> SubstreamMut<T> {
+        match self {
+            Multiplexer::Yamux(yamux) => {
+                SubstreamMut::Yamux(yamux.accept_pending_substream(user_data))
+            }
+            Multiplexer::Mplex(mplex) => {
+                SubstreamMut::Mplex(mplex.accept_pending_substream(user_data))
+            }
+        }
+    }
+}
+
+/// Reference to a substream within the [`Multiplexer`].
+pub(super) enum SubstreamMut<'a, T> {
+    Yamux(yamux::SubstreamMut<'a, T>),
+    Mplex(mplex::SubstreamMut<'a, T>),
+}
+
+impl<'a, T> SubstreamMut<'a, T> {
+    pub(super) fn id(&self) -> SubstreamId {
+        match self {
+            SubstreamMut::Yamux(s) => SubstreamId::Yamux(s.id()),
+            SubstreamMut::Mplex(s) => SubstreamId::Mplex(s.id()),
+        }
+    }
+
+    pub(super) fn user_data(&mut self) -> &mut T {
+        match self {
+            SubstreamMut::Yamux(s) => s.user_data(),
+            SubstreamMut::Mplex(s) => s.user_data(),
+        }
+    }
+
+    pub(super) fn into_user_data(self) -> &'a mut T {
+        match self {
+            SubstreamMut::Yamux(s) => s.into_user_data(),
+            SubstreamMut::Mplex(s) => s.into_user_data(),
+        }
+    }
+
+    pub(super) fn write(&mut self, data: Vec<u8>) {
+        match self {
+            SubstreamMut::Yamux(s) => s.write(data),
+            SubstreamMut::Mplex(s) => s.write(data),
+        }
+    }
+
+    pub(super) fn add_remote_window(&mut self, bytes: u64) {
+        match self {
+            SubstreamMut::Yamux(s) => s.add_remote_window(bytes),
+            SubstreamMut::Mplex(s) => s.add_remote_window(bytes),
+        }
+    }
+
+    pub(super) fn queued_bytes(&self) -> usize {
+        match self {
+            SubstreamMut::Yamux(s) => s.queued_bytes(),
+            SubstreamMut::Mplex(s) => s.queued_bytes(),
+        }
+    }
+
+    pub(super) fn close(self) -> Option<T> {
+        match self {
+            SubstreamMut::Yamux(s) => s.close(),
+            SubstreamMut::Mplex(s) => s.close(),
+        }
+    }
+
+    pub(super) fn reset(self) -> T {
+        match self {
+            SubstreamMut::Yamux(s) => s.reset(),
+            SubstreamMut::Mplex(s) => s.reset(),
+        }
+    }
+}
+
+/// Identifier of a substream in the context of a connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) enum SubstreamId {
+    Yamux(yamux::SubstreamId),
+    Mplex(mplex::SubstreamId),
+}
+
+pub(super) struct IncomingDataOutcome<T> {
+    /// Multiplexer on which [`Multiplexer::incoming_data`] has been called.
+    pub multiplexer: Multiplexer<T>,
+    /// Number of bytes read from the incoming buffer.
+    pub bytes_read: usize,
+    /// Detail about the incoming data. `None` if nothing of interest has happened.
+    pub detail: Option<IncomingDataDetail<T>>,
+}
+
+/// See [`yamux::IncomingDataDetail`] and [`mplex::IncomingDataDetail`].
+pub(super) enum IncomingDataDetail<T> {
+    IncomingSubstream,
+    DataFrame {
+        start_offset: usize,
+        substream_id: SubstreamId,
+    },
+    StreamClosed {
+        substream_id: SubstreamId,
+        user_data: Option<T>,
+    },
+    StreamReset {
+        substream_id: SubstreamId,
+        user_data: T,
+    },
+    /// Only ever generated by yamux.
+    GoAway,
+}
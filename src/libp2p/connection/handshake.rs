@@ -23,27 +23,40 @@
 //! protocol is supported at the moment.
 //! - A noise protocol handshake, where public keys are exchanged and symmetric encryption is
 //! initialized.
-//! - A multistream-select negotiation to negotiate the yamux protocol. Only the yamux protocol is
-//! supported at the moment. This negotiation is performed on top of the noise cipher.
+//! - A multistream-select negotiation to negotiate the multiplexing protocol. Both the yamux and
+//! the mplex protocols are supported, yamux being preferred. This negotiation is performed on top
+//! of the noise cipher.
 //!
 //! This entire handshake requires in total either three or five TCP packets (not including the
 //! TCP handshake), depending on the strategy used for the multistream-select protocol.
+//!
+//! The list of supported multiplexing protocols is also advertised as part of the noise
+//! handshake. If the remote does the same, the third step is skipped altogether, and the chosen
+//! multiplexing protocol is the first protocol of the list of the initiator of the connection
+//! that is also supported by the listener.
+//!
+//! If the [`PeerId`] of the remote is known ahead of time, for example because it is part of the
+//! multiaddress that has been dialed, it can be passed when creating the handshake. The
+//! handshake then fails as soon as the identity of the remote is received, if it doesn't match.
 
 // TODO: finish commenting on the number of round trips
 
 use super::{
     super::peer_id::PeerId,
     established::ConnectionPrototype,
-    multistream_select,
+    mplex, multistream_select,
     noise::{self, NoiseKey},
     yamux,
 };
 
-use alloc::{boxed::Box, vec};
-use core::{fmt, iter};
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::{fmt, iter, slice};
 
 mod tests;
 
+/// List of multiplexing protocols supported by the local node, by order of preference.
+const SUPPORTED_MULTIPLEXERS: &[&str] = &[yamux::PROTOCOL_NAME, mplex::PROTOCOL_NAME];
+
 /// Current state of a connection handshake.
 #[derive(Debug, derive_more::From)]
 pub enum Handshake {
@@ -58,13 +71,16 @@ pub enum Handshake {
         remote_peer_id: PeerId,
         /// Prototype for the connection.
         connection: ConnectionPrototype,
+        /// Early data payload that the remote has sent as part of the noise handshake. Empty if
+        /// the remote hasn't sent any.
+        remote_early_data: Vec<u8>,
     },
 }
 
 impl Handshake {
     /// Shortcut for [`HealthyHandshake::new`] wrapped in a [`Handshake`].
-    pub fn new(is_initiator: bool) -> Self {
-        HealthyHandshake::new(is_initiator).into()
+    pub fn new(is_initiator: bool, expected_remote_peer_id: Option<PeerId>) -> Self {
+        HealthyHandshake::new(is_initiator, expected_remote_peer_id).into()
     }
 }
 
@@ -77,14 +93,19 @@ enum HandshakeState {
     NegotiatingEncryptionProtocol {
         negotiation: multistream_select::InProgress<iter::Once<&'static str>, &'static str>,
         is_initiator: bool,
+        expected_remote_peer_id: Option<PeerId>,
     },
     NegotiatingEncryption {
         handshake: Box<noise::HandshakeInProgress>,
     },
     NegotiatingMultiplexing {
         peer_id: PeerId,
+        remote_early_data: Box<[u8]>,
         encryption: noise::Noise,
-        negotiation: multistream_select::InProgress<iter::Once<&'static str>, &'static str>,
+        negotiation: multistream_select::InProgress<
+            iter::Copied<slice::Iter<'static, &'static str>>,
+            &'static str,
+        >,
     },
 }
 
@@ -93,7 +114,11 @@ impl HealthyHandshake {
     ///
     /// Must pass `true` if the connection has been opened by the local machine, or `false` if it
     /// has been opened by the remote.
-    pub fn new(is_initiator: bool) -> Self {
+    ///
+    /// If `expected_remote_peer_id` is `Some`, the handshake fails with
+    /// [`noise::HandshakeError::PeerIdMismatch`] if the remote turns out to have a different
+    /// identity.
+    pub fn new(is_initiator: bool, expected_remote_peer_id: Option<PeerId>) -> Self {
         let negotiation = multistream_select::InProgress::new(if is_initiator {
             multistream_select::Config::Dialer {
                 requested_protocol: noise::PROTOCOL_NAME,
@@ -108,6 +133,7 @@ impl HealthyHandshake {
             state: HandshakeState::NegotiatingEncryptionProtocol {
                 negotiation,
                 is_initiator,
+                expected_remote_peer_id,
             },
         }
     }
@@ -137,6 +163,7 @@ impl HealthyHandshake {
                 HandshakeState::NegotiatingEncryptionProtocol {
                     negotiation,
                     is_initiator,
+                    expected_remote_peer_id,
                 } => {
                     // Earliest point of the handshake. The encryption is being negotiated.
                     // Delegating read/write to the negotiation.
@@ -155,6 +182,7 @@ impl HealthyHandshake {
                                 self.state = HandshakeState::NegotiatingEncryptionProtocol {
                                     negotiation: updated,
                                     is_initiator,
+                                    expected_remote_peer_id,
                                 };
                                 continue;
                             }
@@ -164,6 +192,7 @@ impl HealthyHandshake {
                                     state: HandshakeState::NegotiatingEncryptionProtocol {
                                         negotiation: updated,
                                         is_initiator,
+                                        expected_remote_peer_id,
                                     },
                                 }),
                                 total_read,
@@ -174,7 +203,10 @@ impl HealthyHandshake {
                             // Reached the point where the Noise key is required in order to
                             // continue. This Noise key is requested from the user.
                             Ok((
-                                Handshake::NoiseKeyRequired(NoiseKeyRequired { is_initiator }),
+                                Handshake::NoiseKeyRequired(NoiseKeyRequired {
+                                    is_initiator,
+                                    expected_remote_peer_id,
+                                }),
                                 total_read,
                                 total_written,
                             ))
@@ -203,22 +235,45 @@ impl HealthyHandshake {
                         noise::NoiseHandshake::Success {
                             cipher,
                             remote_peer_id,
+                            remote_stream_muxers,
+                            remote_early_data,
                         } => {
+                            // If the remote has advertised the multiplexing protocols it
+                            // supports as part of the noise handshake, the multiplexing protocol
+                            // is chosen without any further negotiation.
+                            if let Some(protocol) = early_multiplexer_choice(
+                                cipher.is_initiator(),
+                                &remote_stream_muxers,
+                            ) {
+                                return Ok((
+                                    Handshake::Success {
+                                        connection: connection_prototype(protocol, cipher),
+                                        remote_peer_id,
+                                        remote_early_data,
+                                    },
+                                    total_read,
+                                    total_written,
+                                ));
+                            }
+
                             // Encryption layer has been successfully negotiated. Start the
                             // handshake for the multiplexing protocol negotiation.
-                            let negotiation =
-                                multistream_select::InProgress::new(if cipher.is_initiator() {
-                                    multistream_select::Config::Dialer {
-                                        requested_protocol: yamux::PROTOCOL_NAME,
-                                    }
-                                } else {
+                            let negotiation = if cipher.is_initiator() {
+                                multistream_select::InProgress::new_dialer_with_fallbacks(
+                                    SUPPORTED_MULTIPLEXERS[0],
+                                    SUPPORTED_MULTIPLEXERS[1..].iter().copied(),
+                                )
+                            } else {
+                                multistream_select::InProgress::new(
                                     multistream_select::Config::Listener {
-                                        supported_protocols: iter::once(yamux::PROTOCOL_NAME),
-                                    }
-                                });
+                                        supported_protocols: SUPPORTED_MULTIPLEXERS.iter().copied(),
+                                    },
+                                )
+                            };
 
                             self.state = HandshakeState::NegotiatingMultiplexing {
                                 peer_id: remote_peer_id,
+                                remote_early_data: remote_early_data.into_boxed_slice(),
                                 encryption: cipher,
                                 negotiation,
                             };
@@ -243,6 +298,7 @@ impl HealthyHandshake {
                     negotiation,
                     mut encryption,
                     peer_id,
+                    remote_early_data,
                 } => {
                     // During the multiplexing protocol negotiation, all exchanges have to go
                     // through the Noise cipher.
@@ -290,15 +346,17 @@ impl HealthyHandshake {
                                     negotiation: updated,
                                     encryption,
                                     peer_id,
+                                    remote_early_data,
                                 },
                             }),
                             total_read,
                             total_written,
                         )),
-                        multistream_select::Negotiation::Success(_) => Ok((
+                        multistream_select::Negotiation::Success(protocol) => Ok((
                             Handshake::Success {
-                                connection: ConnectionPrototype::from_noise_yamux(encryption),
+                                connection: connection_prototype(protocol, encryption),
                                 remote_peer_id: peer_id,
+                                remote_early_data: remote_early_data.into_vec(),
                             },
                             total_read,
                             total_written,
@@ -313,6 +371,49 @@ impl HealthyHandshake {
     }
 }
 
+/// Chooses the multiplexing protocol based on the list of protocols that the remote has
+/// advertised during the noise handshake. Returns `None` if the remote hasn't advertised any
+/// protocol supported by the local node, in which case a multistream-select negotiation is
+/// necessary.
+///
+/// Both sides of the connection must come up with the same choice. The preference order of the
+/// initiator of the connection is used.
+fn early_multiplexer_choice(
+    is_initiator: bool,
+    remote_stream_muxers: &[String],
+) -> Option<&'static str> {
+    if is_initiator {
+        SUPPORTED_MULTIPLEXERS
+            .iter()
+            .find(|local| remote_stream_muxers.iter().any(|remote| remote == *local))
+            .copied()
+    } else {
+        remote_stream_muxers.iter().find_map(|remote| {
+            SUPPORTED_MULTIPLEXERS
+                .iter()
+                .find(|local| remote == *local)
+                .copied()
+        })
+    }
+}
+
+/// Builds the [`ConnectionPrototype`] corresponding to the given negotiated multiplexing
+/// protocol.
+///
+/// # Panic
+///
+/// Panics if `protocol` isn't in [`SUPPORTED_MULTIPLEXERS`].
+///
+fn connection_prototype(protocol: &str, encryption: noise::Noise) -> ConnectionPrototype {
+    if protocol == yamux::PROTOCOL_NAME {
+        ConnectionPrototype::from_noise_yamux(encryption)
+    } else if protocol == mplex::PROTOCOL_NAME {
+        ConnectionPrototype::from_noise_mplex(encryption)
+    } else {
+        unreachable!()
+    }
+}
+
 impl fmt::Debug for HealthyHandshake {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("HealthyHandshake").finish()
@@ -323,17 +424,37 @@ impl fmt::Debug for HealthyHandshake {
 /// key in order to proceed.
 pub struct NoiseKeyRequired {
     is_initiator: bool,
+    expected_remote_peer_id: Option<PeerId>,
 }
 
 impl NoiseKeyRequired {
     /// Turn this [`NoiseKeyRequired`] back into a [`HealthyHandshake`] by indicating the noise key.
     pub fn resume(self, noise_key: &NoiseKey) -> HealthyHandshake {
+        self.resume_with_early_data(noise_key, &[])
+    }
+
+    /// Same as [`NoiseKeyRequired::resume`], but also sends the given payload to the remote as
+    /// part of the noise handshake. The remote receives it in
+    /// [`Handshake::Success::remote_early_data`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if `early_data` is larger than [`noise::MAX_EARLY_DATA_LEN`].
+    ///
+    pub fn resume_with_early_data(
+        self,
+        noise_key: &NoiseKey,
+        early_data: &[u8],
+    ) -> HealthyHandshake {
         HealthyHandshake {
             state: HandshakeState::NegotiatingEncryption {
-                handshake: Box::new(noise::HandshakeInProgress::new(
-                    noise_key,
-                    self.is_initiator,
-                )),
+                handshake: Box::new(noise::HandshakeInProgress::new(noise::Config {
+                    key: noise_key,
+                    is_initiator: self.is_initiator,
+                    expected_remote_peer_id: self.expected_remote_peer_id,
+                    local_stream_muxers: SUPPORTED_MULTIPLEXERS,
+                    early_data,
+                })),
             },
         }
     }
@@ -25,8 +25,8 @@ fn handshake_basic_works() {
         let key1 = NoiseKey::new(&rand::random());
         let key2 = NoiseKey::new(&rand::random());
 
-        let mut handshake1 = Handshake::new(true);
-        let mut handshake2 = Handshake::new(false);
+        let mut handshake1 = Handshake::new(true, None);
+        let mut handshake2 = Handshake::new(false, None);
 
         let mut buf_1_to_2 = Vec::new();
         let mut buf_2_to_1 = Vec::new();
@@ -93,3 +93,124 @@ fn handshake_basic_works() {
     //test_with_buffer_sizes(1, 2048);
     //test_with_buffer_sizes(2048, 1);
 }
+
+#[test]
+fn handshake_with_early_data() {
+    fn test_with_buffer_sizes(size1: usize, size2: usize) {
+        let key1 = NoiseKey::new(&rand::random());
+        let key2 = NoiseKey::new(&rand::random());
+
+        let mut handshake1 = Handshake::new(true, None);
+        let mut handshake2 = Handshake::new(false, None);
+
+        let mut buf_1_to_2 = Vec::new();
+        let mut buf_2_to_1 = Vec::new();
+
+        while !matches!(
+            (&handshake1, &handshake2),
+            (Handshake::Success { .. }, Handshake::Success { .. })
+        ) {
+            match handshake1 {
+                Handshake::Success { .. } => {}
+                Handshake::NoiseKeyRequired(req) => {
+                    handshake1 = req.resume_with_early_data(&key1, b"hello").into()
+                }
+                Handshake::Healthy(nego) => {
+                    if buf_1_to_2.is_empty() {
+                        buf_1_to_2.resize(size1, 0);
+                        let (updated, num_read, written) = nego
+                            .read_write(&buf_2_to_1, (&mut buf_1_to_2, &mut []))
+                            .unwrap();
+                        handshake1 = updated;
+                        for _ in 0..num_read {
+                            buf_2_to_1.remove(0);
+                        }
+                        buf_1_to_2.truncate(written);
+                    } else {
+                        let (updated, num_read, _) =
+                            nego.read_write(&buf_2_to_1, (&mut [], &mut [])).unwrap();
+                        handshake1 = updated;
+                        for _ in 0..num_read {
+                            buf_2_to_1.remove(0);
+                        }
+                    }
+                }
+            }
+
+            match handshake2 {
+                Handshake::Success { .. } => {}
+                Handshake::NoiseKeyRequired(req) => handshake2 = req.resume(&key2).into(),
+                Handshake::Healthy(nego) => {
+                    if buf_2_to_1.is_empty() {
+                        buf_2_to_1.resize(size2, 0);
+                        let (updated, num_read, written) = nego
+                            .read_write(&buf_1_to_2, (&mut buf_2_to_1, &mut []))
+                            .unwrap();
+                        handshake2 = updated;
+                        for _ in 0..num_read {
+                            buf_1_to_2.remove(0);
+                        }
+                        buf_2_to_1.truncate(written);
+                    } else {
+                        let (updated, num_read, _) =
+                            nego.read_write(&buf_1_to_2, (&mut [], &mut [])).unwrap();
+                        handshake2 = updated;
+                        for _ in 0..num_read {
+                            buf_1_to_2.remove(0);
+                        }
+                    }
+                }
+            }
+        }
+
+        match (handshake1, handshake2) {
+            (
+                Handshake::Success {
+                    remote_early_data: early_data1,
+                    ..
+                },
+                Handshake::Success {
+                    remote_early_data: early_data2,
+                    ..
+                },
+            ) => {
+                assert!(early_data1.is_empty());
+                assert_eq!(early_data2, b"hello");
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    test_with_buffer_sizes(256, 256);
+}
+
+#[test]
+fn early_multiplexer_choice_agrees() {
+    use super::{early_multiplexer_choice, SUPPORTED_MULTIPLEXERS};
+
+    let local = SUPPORTED_MULTIPLEXERS
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>();
+    let reversed = local.iter().rev().cloned().collect::<Vec<_>>();
+
+    // The preference order of the initiator is used by both sides.
+    assert_eq!(
+        early_multiplexer_choice(true, &reversed),
+        Some(SUPPORTED_MULTIPLEXERS[0])
+    );
+    assert_eq!(
+        early_multiplexer_choice(false, &local),
+        Some(SUPPORTED_MULTIPLEXERS[0])
+    );
+    assert_eq!(
+        early_multiplexer_choice(false, &reversed),
+        Some(SUPPORTED_MULTIPLEXERS[1])
+    );
+
+    assert_eq!(
+        early_multiplexer_choice(true, &["/foo/1.0.0".to_string()]),
+        None
+    );
+    assert_eq!(early_multiplexer_choice(false, &[]), None);
+}
@@ -0,0 +1,1044 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Mplex multiplexing protocol.
+//!
+//! The mplex protocol is a multiplexing protocol. As such, it allows dividing a single stream of
+//! data, typically a TCP socket, into multiple individual parallel substreams. The data sent and
+//! received over that single stream is divided into frames, each of them belonging to a specific
+//! substream.
+//!
+//! Mplex is much simpler than yamux: it has no flow control, no ping frames, and no way to
+//! notify the remote that new substreams will no longer be accepted. It is supported in order to
+//! be able to communicate with implementations that don't support yamux.
+//!
+//! Specifications available at https://github.com/libp2p/specs/tree/master/mplex
+//!
+//! # Usage
+//!
+//! The API of this module mirrors the one of the [`yamux`](super::yamux) module.
+//!
+//! The [`Mplex`] object holds the state of all mplex-specific information, and the list of
+//! all currently-open substreams.
+//!
+//! Call [`Mplex::incoming_data`] when data is available on the socket. This function parses
+//! the received data, updates the internal state machine, and possibly returns an
+//! [`IncomingDataDetail`].
+//! Call [`Mplex::extract_out`] when the remote is ready to accept more data.
+//!
+//! The generic parameter of [`Mplex`] is an opaque "user data" associated to each substream.
+//!
+//! When [`SubstreamMut::write`] is called, the buffer of data to send out is stored within the
+//! [`Mplex`] object. This data will then be progressively returned by [`Mplex::extract_out`].
+//!
+//! Similarly to yamux, it is the responsibility of the user to enforce a bound to the amount of
+//! enqueued data.
+//!
+//! # Flow control
+//!
+//! The mplex protocol doesn't provide any flow control mechanism. The remote is free to send
+//! data on any substream at any time, and the only way to apply back-pressure is to stop
+//! calling [`Mplex::incoming_data`], which affects all substreams at once.
+//! [`SubstreamMut::add_remote_window`] has no effect and is provided only for API compatibility
+//! with yamux.
+
+use crate::util::leb128;
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::{cmp, convert::TryFrom as _, fmt, mem};
+use hashbrown::hash_map::{Entry, OccupiedEntry};
+
+/// Name of the protocol, typically used when negotiated it using *multistream-select*.
+pub const PROTOCOL_NAME: &str = "/mplex/6.7.0";
+
+/// Maximum size of the data of a frame, both sent and received.
+///
+/// The specifications recommend refusing frames larger than 1 MiB.
+const MAX_FRAME_SIZE: u32 = 1024 * 1024;
+
+pub struct Mplex<T> {
+    /// List of substreams currently open in the mplex state machine.
+    ///
+    /// A SipHasher is used in order to avoid hash collision attacks on substream IDs.
+    substreams: hashbrown::HashMap<SubstreamId, Substream<T>, ahash::RandomState>,
+
+    /// What kind of data is expected on the socket next.
+    incoming: Incoming,
+
+    /// Number of the next outgoing substream to open.
+    next_outbound_substream: u64,
+
+    /// Header currently being written out. Finishing to write this header is the first and
+    /// foremost priority of [`Mplex::extract_out`].
+    pending_out_header: arrayvec::ArrayVec<u8, 20>,
+
+    /// Data of the frame whose header is [`Mplex::pending_out_header`] or has just been written
+    /// out. Writing out this data is the second most highest priority after writing out
+    /// [`Mplex::pending_out_header`].
+    ///
+    /// This data is removed from the substream it belongs to when the frame header is generated,
+    /// so that the substream can be destroyed without interrupting the frame.
+    pending_out_data: VecDeque<VecWithOffset>,
+
+    /// List of substreams for which a reset frame must be sent out.
+    pending_resets: Vec<SubstreamId>,
+
+    /// See [`Config::max_simultaneous_substreams`].
+    max_simultaneous_substreams: usize,
+
+    /// If `true`, substreams opened by the remote are automatically refused.
+    refuse_incoming_substreams: bool,
+}
+
+struct Substream<T> {
+    /// True if the frame opening this substream has been queued, or if the substream has been
+    /// opened by the remote.
+    open_frame_queued: bool,
+    /// True if the writing side of the local node is closed for this substream.
+    /// Note that the data queued in [`Substream::write_buffers`] must still be sent out,
+    /// followed with a close frame.
+    local_write_closed: bool,
+    /// True if the close frame of this substream has been queued.
+    close_frame_queued: bool,
+    /// True if the writing side of the remote node is closed for this substream.
+    remote_write_closed: bool,
+    /// Buffer of buffers to be written out to the socket.
+    write_buffers: Vec<Vec<u8>>,
+    /// Data chosen by the user.
+    ///
+    /// `None` if the substream has been destroyed from the point of view of the API user, but
+    /// some data or the close frame are still waiting to be sent out.
+    user_data: Option<T>,
+}
+
+enum Incoming {
+    /// Expect a header. The field might contain some already-read bytes.
+    Header(arrayvec::ArrayVec<u8, 20>),
+    /// Expect the data of a previously-received message frame header.
+    DataFrame {
+        /// Identifier of the substream the data belongs to.
+        substream_id: SubstreamId,
+        /// Number of bytes of data remaining before the frame ends.
+        remaining_bytes: u32,
+    },
+    /// Expect the name of a substream that the remote is opening. The name has no meaning and
+    /// is discarded.
+    NewStreamName {
+        /// Identifier of the new substream.
+        substream_id: SubstreamId,
+        /// Number of bytes of data remaining before the frame ends.
+        remaining_bytes: u32,
+    },
+    /// A frame opening a new substream has been received. The reception of any further data is
+    /// blocked waiting for the API user to accept or reject this substream.
+    PendingIncomingSubstream {
+        /// Identifier of the pending substream.
+        substream_id: SubstreamId,
+    },
+}
+
+impl<T> Mplex<T> {
+    /// Initializes a new mplex state machine.
+    pub fn new(config: Config) -> Mplex<T> {
+        Mplex {
+            substreams: hashbrown::HashMap::with_capacity_and_hasher(
+                config.capacity,
+                ahash::RandomState::with_seeds(
+                    config.randomness_seed.0,
+                    config.randomness_seed.1,
+                    config.randomness_seed.2,
+                    config.randomness_seed.3,
+                ),
+            ),
+            incoming: Incoming::Header(arrayvec::ArrayVec::new()),
+            next_outbound_substream: 0,
+            pending_out_header: arrayvec::ArrayVec::new(),
+            pending_out_data: VecDeque::new(),
+            pending_resets: Vec::new(),
+            max_simultaneous_substreams: config.max_simultaneous_substreams,
+            refuse_incoming_substreams: false,
+        }
+    }
+
+    /// From now on, automatically refuse all the substreams opened by the remote. Substreams that
+    /// are already open are unaffected.
+    ///
+    /// Contrary to yamux, the mplex protocol doesn't provide any way to notify the remote of
+    /// this situation.
+    pub fn refuse_incoming_substreams(&mut self) {
+        self.refuse_incoming_substreams = true;
+    }
+
+    /// Opens a new substream.
+    ///
+    /// This method only modifies the state of `self` and reserves an identifier. The frame
+    /// opening the substream is sent out by [`Mplex::extract_out`].
+    pub fn open_substream(&mut self, user_data: T) -> SubstreamMut<T> {
+        // Grab a `VacantEntry` in `self.substreams`.
+        // Substream numbers are 60 bits long, and overflowing them would require opening a
+        // substream every nanosecond for more than 30 years. It is nonetheless properly handled
+        // by ignoring the numbers already in use.
+        let entry = loop {
+            let id_attempt = SubstreamId {
+                num: self.next_outbound_substream,
+                local_initiated: true,
+            };
+            self.next_outbound_substream = (self.next_outbound_substream + 1) & ((1 << 60) - 1);
+            if let Entry::Vacant(e) = self.substreams.entry(id_attempt) {
+                break e;
+            }
+        };
+
+        let substream_id = *entry.key();
+
+        entry.insert(Substream {
+            open_frame_queued: false,
+            local_write_closed: false,
+            close_frame_queued: false,
+            remote_write_closed: false,
+            write_buffers: Vec::with_capacity(16),
+            user_data: Some(user_data),
+        });
+
+        match self.substreams.entry(substream_id) {
+            Entry::Occupied(e) => SubstreamMut {
+                substream: e,
+                pending_resets: &mut self.pending_resets,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns an iterator to the list of all substream user datas.
+    pub fn user_datas(&self) -> impl Iterator<Item = (SubstreamId, &T)> {
+        self.substreams
+            .iter()
+            .filter_map(|(id, s)| Some((*id, s.user_data.as_ref()?)))
+    }
+
+    /// Returns a reference to a substream by its ID. Returns `None` if no substream with this ID
+    /// is open.
+    pub fn substream_by_id(&mut self, id: SubstreamId) -> Option<SubstreamMut<T>> {
+        match self.substreams.entry(id) {
+            Entry::Occupied(e) if e.get().user_data.is_some() => Some(SubstreamMut {
+                substream: e,
+                pending_resets: &mut self.pending_resets,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Process some incoming data.
+    ///
+    /// In case of protocol error, the [`Mplex`] is returned alongside with the error. The
+    /// connection should then be shut down.
+    ///
+    /// # Panic
+    ///
+    /// Panics if an incoming substream is pending. See [`IncomingDataDetail::IncomingSubstream`].
+    ///
+    pub fn incoming_data(self, data: &[u8]) -> Result<IncomingDataOutcome<T>, (Error, Mplex<T>)> {
+        let mut this = self;
+        match this.incoming_data_inner(data) {
+            Ok((bytes_read, detail)) => Ok(IncomingDataOutcome {
+                mplex: this,
+                bytes_read,
+                detail,
+            }),
+            Err(err) => Err((err, this)),
+        }
+    }
+
+    /// Implementation of [`Mplex::incoming_data`]. Returns the number of bytes read and the
+    /// detail of the outcome.
+    fn incoming_data_inner(
+        &mut self,
+        mut data: &[u8],
+    ) -> Result<(usize, Option<IncomingDataDetail<T>>), Error> {
+        let mut total_read: usize = 0;
+
+        loop {
+            match self.incoming {
+                Incoming::PendingIncomingSubstream { .. } => panic!(),
+
+                Incoming::DataFrame {
+                    remaining_bytes: 0, ..
+                } => {
+                    self.incoming = Incoming::Header(arrayvec::ArrayVec::new());
+                }
+
+                Incoming::DataFrame {
+                    substream_id,
+                    ref mut remaining_bytes,
+                } => {
+                    if data.is_empty() {
+                        break;
+                    }
+
+                    let pulled_data = cmp::min(
+                        *remaining_bytes,
+                        u32::try_from(data.len()).unwrap_or(u32::max_value()),
+                    );
+                    let pulled_data_usize = usize::try_from(pulled_data).unwrap();
+                    *remaining_bytes -= pulled_data;
+
+                    let start_offset = total_read;
+                    total_read += pulled_data_usize;
+                    data = &data[pulled_data_usize..];
+
+                    // Data concerning substreams that are unknown or that have been destroyed
+                    // is silently discarded.
+                    if self
+                        .substreams
+                        .get(&substream_id)
+                        .map_or(false, |s| s.user_data.is_some())
+                    {
+                        return Ok((
+                            total_read,
+                            Some(IncomingDataDetail::DataFrame {
+                                start_offset,
+                                substream_id,
+                            }),
+                        ));
+                    }
+                }
+
+                Incoming::NewStreamName {
+                    substream_id,
+                    ref mut remaining_bytes,
+                } => {
+                    let pulled_data = cmp::min(
+                        *remaining_bytes,
+                        u32::try_from(data.len()).unwrap_or(u32::max_value()),
+                    );
+                    *remaining_bytes -= pulled_data;
+                    total_read += usize::try_from(pulled_data).unwrap();
+                    data = &data[usize::try_from(pulled_data).unwrap()..];
+
+                    if *remaining_bytes != 0 {
+                        debug_assert!(data.is_empty());
+                        break;
+                    }
+
+                    if self.substreams.len() >= self.max_simultaneous_substreams
+                        || self.refuse_incoming_substreams
+                    {
+                        // Too many substreams are open, or the local node no longer accepts
+                        // substreams. The new substream is refused by answering with a reset.
+                        self.pending_resets.push(substream_id);
+                        self.incoming = Incoming::Header(arrayvec::ArrayVec::new());
+                    } else {
+                        self.incoming = Incoming::PendingIncomingSubstream { substream_id };
+                        return Ok((total_read, Some(IncomingDataDetail::IncomingSubstream)));
+                    }
+                }
+
+                Incoming::Header(ref mut incoming_header) => {
+                    // The size of the header isn't known in advance. Copy bytes one by one from
+                    // `data` until the header can be decoded.
+                    let decoded = loop {
+                        if let Some(decoded) = decode_header(incoming_header)? {
+                            break Some(decoded);
+                        }
+                        if data.is_empty() {
+                            break None;
+                        }
+                        // `decode_header` returns an error before the header can overflow.
+                        incoming_header.push(data[0]);
+                        total_read += 1;
+                        data = &data[1..];
+                    };
+
+                    // Not enough data to finish receiving header. Nothing more can be done.
+                    let (substream_num, flag, length) = match decoded {
+                        Some(d) => d,
+                        None => break,
+                    };
+
+                    self.incoming = Incoming::Header(arrayvec::ArrayVec::new());
+
+                    // The flag indicates, amongst other things, which side has opened the
+                    // substream. Flags `1`, `3` and `5` are sent by the side that didn't open
+                    // the substream, while flags `0`, `2`, `4` and `6` are sent by the side that
+                    // has opened the substream.
+                    let substream_id = SubstreamId {
+                        num: substream_num,
+                        local_initiated: flag % 2 == 1,
+                    };
+
+                    match flag {
+                        0 => {
+                            // New substream.
+                            if self.substreams.contains_key(&substream_id) {
+                                return Err(Error::UnexpectedNewStream(substream_num));
+                            }
+
+                            self.incoming = Incoming::NewStreamName {
+                                substream_id,
+                                remaining_bytes: length,
+                            };
+                        }
+                        1 | 2 => {
+                            // Message.
+                            if let Some(substream) = self.substreams.get(&substream_id) {
+                                if substream.remote_write_closed {
+                                    return Err(Error::WriteAfterClose);
+                                }
+                            }
+
+                            self.incoming = Incoming::DataFrame {
+                                substream_id,
+                                remaining_bytes: length,
+                            };
+                        }
+                        3 | 4 => {
+                            // Close.
+                            if length != 0 {
+                                return Err(Error::DataWithClose);
+                            }
+
+                            // It is possible that the remote is referring to a substream for
+                            // which a reset has been sent out. Since the local state machine
+                            // doesn't keep track of reset substreams, frames concerning unknown
+                            // substreams are ignored.
+                            let substream = match self.substreams.get_mut(&substream_id) {
+                                Some(s) if s.user_data.is_some() => s,
+                                _ => continue,
+                            };
+
+                            if substream.remote_write_closed {
+                                return Err(Error::WriteAfterClose);
+                            }
+                            substream.remote_write_closed = true;
+
+                            let user_data = if substream.local_write_closed {
+                                if substream.close_frame_queued {
+                                    self.substreams.remove(&substream_id).unwrap().user_data
+                                } else {
+                                    substream.user_data.take()
+                                }
+                            } else {
+                                None
+                            };
+
+                            return Ok((
+                                total_read,
+                                Some(IncomingDataDetail::StreamClosed {
+                                    substream_id,
+                                    user_data,
+                                }),
+                            ));
+                        }
+                        5 | 6 => {
+                            // Reset.
+                            if length != 0 {
+                                return Err(Error::DataWithReset);
+                            }
+
+                            match self.substreams.remove(&substream_id) {
+                                Some(Substream {
+                                    user_data: Some(user_data),
+                                    ..
+                                }) => {
+                                    return Ok((
+                                        total_read,
+                                        Some(IncomingDataDetail::StreamReset {
+                                            substream_id,
+                                            user_data,
+                                        }),
+                                    ));
+                                }
+                                _ => continue,
+                            }
+                        }
+                        _ => return Err(Error::BadFlag(flag)),
+                    }
+                }
+            }
+        }
+
+        Ok((total_read, None))
+    }
+
+    /// Returns an object that provides an iterator to a list of buffers whose content must be
+    /// sent out on the socket.
+    ///
+    /// The buffers produced by the iterator will never yield more than `size_bytes` bytes of
+    /// data. The user is expected to pass an exact amount of bytes that the next layer is ready
+    /// to accept.
+    ///
+    /// After the [`ExtractOut`] has been destroyed, the mplex state machine will automatically
+    /// consider that these `size_bytes` have been sent out, even if the iterator has been
+    /// destroyed before finishing.
+    pub fn extract_out(&mut self, size_bytes: usize) -> ExtractOut {
+        let mut buffers = Vec::with_capacity(32);
+
+        // Copy of `size_bytes`, decremented over the iterations.
+        let mut size_bytes_iter = size_bytes;
+
+        while size_bytes_iter != 0 {
+            // Finish writing `self.pending_out_header` if possible.
+            if !self.pending_out_header.is_empty() {
+                if size_bytes_iter >= self.pending_out_header.len() {
+                    size_bytes_iter -= self.pending_out_header.len();
+                    buffers.push(either::Left(mem::take(&mut self.pending_out_header)));
+                } else {
+                    let to_add = self.pending_out_header[..size_bytes_iter].to_vec();
+                    for _ in 0..size_bytes_iter {
+                        self.pending_out_header.remove(0);
+                    }
+                    buffers.push(either::Right(VecWithOffset(to_add, 0)));
+                    break;
+                }
+            }
+
+            // Then write out the data of the current frame.
+            if let Some(first) = self.pending_out_data.front_mut() {
+                let first_avail = first.0.len() - first.1;
+                if first_avail <= size_bytes_iter {
+                    size_bytes_iter -= first_avail;
+                    buffers.push(either::Right(self.pending_out_data.pop_front().unwrap()));
+                } else {
+                    buffers.push(either::Right(VecWithOffset(
+                        first.0[first.1..][..size_bytes_iter].to_vec(),
+                        0,
+                    )));
+                    first.1 += size_bytes_iter;
+                    size_bytes_iter = 0;
+                }
+                continue;
+            }
+
+            // All frames in the process of being written have been written.
+            debug_assert!(self.pending_out_header.is_empty());
+            debug_assert!(self.pending_out_data.is_empty());
+
+            // Send out resets.
+            if let Some(substream_id) = self.pending_resets.pop() {
+                self.queue_frame_header(substream_id, 6, 0);
+                continue;
+            }
+
+            // Find a substream that has something to send out.
+            // TODO: choose substreams in some sort of round-robin way
+            let (substream_id, substream) = match self.substreams.iter_mut().find(|(_, s)| {
+                !s.open_frame_queued
+                    || !s.write_buffers.is_empty()
+                    || (s.local_write_closed && !s.close_frame_queued)
+            }) {
+                Some((id, s)) => (*id, s),
+                None => break,
+            };
+
+            if !substream.open_frame_queued {
+                // The name of the substream is left empty, as it has no meaning.
+                substream.open_frame_queued = true;
+                self.queue_frame_header(substream_id, 0, 0);
+            } else if !substream.write_buffers.is_empty() {
+                // Move as many buffers as possible to `pending_out_data`, without exceeding the
+                // maximum frame size.
+                let mut frame_len = 0;
+                while let Some(buffer) = substream.write_buffers.first_mut() {
+                    let max_remaining = usize::try_from(MAX_FRAME_SIZE).unwrap() - frame_len;
+                    if max_remaining == 0 {
+                        break;
+                    }
+                    let buffer = if buffer.len() > max_remaining {
+                        let remaining = buffer.split_off(max_remaining);
+                        mem::replace(buffer, remaining)
+                    } else {
+                        substream.write_buffers.remove(0)
+                    };
+                    frame_len += buffer.len();
+                    self.pending_out_data.push_back(VecWithOffset(buffer, 0));
+                }
+
+                self.queue_frame_header(substream_id, 2, u32::try_from(frame_len).unwrap());
+            } else {
+                debug_assert!(substream.local_write_closed && !substream.close_frame_queued);
+                substream.close_frame_queued = true;
+
+                // If the substream has already been destroyed from the point of view of the API
+                // user, the close frame was the last thing remaining.
+                if substream.user_data.is_none() {
+                    debug_assert!(substream.remote_write_closed);
+                    self.substreams.remove(&substream_id);
+                }
+
+                self.queue_frame_header(substream_id, 4, 0);
+            }
+        }
+
+        debug_assert!(
+            buffers
+                .iter()
+                .fold(0, |n, b| n + AsRef::<[u8]>::as_ref(b).len())
+                <= size_bytes
+        );
+
+        ExtractOut {
+            buffers: Some(buffers),
+        }
+    }
+
+    /// Accepts the substream whose opening has been reported with
+    /// [`IncomingDataDetail::IncomingSubstream`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if no incoming substream is pending.
+    ///
+    pub fn accept_pending_substream(&mut self, user_data: T) -> SubstreamMut<T> {
+        let substream_id = match self.incoming {
+            Incoming::PendingIncomingSubstream { substream_id } => substream_id,
+            _ => panic!(),
+        };
+
+        self.incoming = Incoming::Header(arrayvec::ArrayVec::new());
+
+        let _was_before = self.substreams.insert(
+            substream_id,
+            Substream {
+                open_frame_queued: true,
+                local_write_closed: false,
+                close_frame_queued: false,
+                remote_write_closed: false,
+                write_buffers: Vec::new(),
+                user_data: Some(user_data),
+            },
+        );
+        debug_assert!(_was_before.is_none());
+
+        match self.substreams.entry(substream_id) {
+            Entry::Occupied(e) => SubstreamMut {
+                substream: e,
+                pending_resets: &mut self.pending_resets,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Refuses the substream whose opening has been reported with
+    /// [`IncomingDataDetail::IncomingSubstream`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if no incoming substream is pending.
+    ///
+    pub fn reject_pending_substream(&mut self) {
+        let substream_id = match self.incoming {
+            Incoming::PendingIncomingSubstream { substream_id } => substream_id,
+            _ => panic!(),
+        };
+
+        self.incoming = Incoming::Header(arrayvec::ArrayVec::new());
+        self.pending_resets.push(substream_id);
+    }
+
+    /// Writes a frame header in `self.pending_out_header`.
+    ///
+    /// `flag` must be the flag as if the substream had been opened by the local node. It is
+    /// adjusted if that is not the case.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `!self.pending_out_header.is_empty()`.
+    ///
+    fn queue_frame_header(&mut self, substream_id: SubstreamId, flag: u8, data_length: u32) {
+        assert!(self.pending_out_header.is_empty());
+
+        let flag = if substream_id.local_initiated || flag == 0 {
+            flag
+        } else {
+            flag - 1
+        };
+
+        for byte in leb128::encode((substream_id.num << 3) | u64::from(flag))
+            .chain(leb128::encode(data_length))
+        {
+            self.pending_out_header.push(byte);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Mplex<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.user_datas()).finish()
+    }
+}
+
+/// Tries to decode a frame header. Returns `None` if more data is needed.
+///
+/// On success, returns the substream number, the flag, and the length of the data of the frame.
+fn decode_header(header: &[u8]) -> Result<Option<(u64, u8, u32)>, Error> {
+    // Decodes a LEB128-encoded number at the start of `bytes`. Returns the number and the number
+    // of bytes it occupies, or `None` if more data is needed.
+    fn decode_leb128(bytes: &[u8], max_bytes: usize) -> Result<Option<(u64, usize)>, ()> {
+        let mut out = 0u64;
+        for (n, byte) in bytes.iter().enumerate() {
+            if n >= max_bytes {
+                return Err(());
+            }
+            out |= u64::from(*byte & 0x7f) << (7 * n);
+            if (*byte & 0x80) == 0 {
+                return Ok(Some((out, n + 1)));
+            }
+        }
+        if bytes.len() >= max_bytes {
+            return Err(());
+        }
+        Ok(None)
+    }
+
+    // The header field consists of a 60 bits substream number and a 3 bits flag, and thus
+    // always fits in 9 bytes.
+    let (header_field, header_field_len) =
+        match decode_leb128(header, 9).map_err(|()| Error::InvalidHeader)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+    // The length of the data is limited by `MAX_FRAME_SIZE`, which always fits in 3 bytes.
+    let length =
+        match decode_leb128(&header[header_field_len..], 3).map_err(|()| Error::FrameTooLarge)? {
+            Some((length, _)) => length,
+            None => return Ok(None),
+        };
+
+    let length = match u32::try_from(length) {
+        Ok(l) if l <= MAX_FRAME_SIZE => l,
+        _ => return Err(Error::FrameTooLarge),
+    };
+
+    let flag = u8::try_from(header_field & 0b111).unwrap();
+    Ok(Some((header_field >> 3, flag, length)))
+}
+
+/// Configuration for a new [`Mplex`].
+#[derive(Debug)]
+pub struct Config {
+    /// Expected number of substreams simultaneously open, both inbound and outbound substreams
+    /// combined.
+    pub capacity: usize,
+    /// Seed used for the randomness. Used to avoid HashDos attack and determines the order in
+    /// which the data on substreams is sent out.
+    pub randomness_seed: (u64, u64, u64, u64),
+    /// Maximum number of substreams simultaneously open, both inbound and outbound substreams
+    /// combined. Substreams that the remote tries to open beyond this limit are automatically
+    /// refused.
+    pub max_simultaneous_substreams: usize,
+}
+
+/// Reference to a substream within the [`Mplex`].
+pub struct SubstreamMut<'a, T> {
+    substream: OccupiedEntry<'a, SubstreamId, Substream<T>, ahash::RandomState>,
+    pending_resets: &'a mut Vec<SubstreamId>,
+}
+
+impl<'a, T> SubstreamMut<'a, T> {
+    /// Identifier of the substream.
+    pub fn id(&self) -> SubstreamId {
+        *self.substream.key()
+    }
+
+    /// Returns the user data associated to this substream.
+    pub fn user_data(&mut self) -> &mut T {
+        self.substream.get_mut().user_data.as_mut().unwrap()
+    }
+
+    /// Returns the user data associated to this substream.
+    pub fn into_user_data(self) -> &'a mut T {
+        self.substream.into_mut().user_data.as_mut().unwrap()
+    }
+
+    /// Appends data to the buffer of data to send out on this substream.
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`SubstreamMut::close`] has already been called on this substream.
+    ///
+    pub fn write(&mut self, data: Vec<u8>) {
+        let substream = self.substream.get_mut();
+        assert!(!substream.local_write_closed);
+        if !data.is_empty() {
+            substream.write_buffers.push(data);
+        }
+    }
+
+    /// Has no effect, as the mplex protocol doesn't have any flow control mechanism. Provided
+    /// for API compatibility with yamux.
+    pub fn add_remote_window(&mut self, _bytes: u64) {}
+
+    /// Returns the number of bytes queued for writing on this substream.
+    pub fn queued_bytes(&self) -> usize {
+        let substream = self.substream.get();
+        substream
+            .write_buffers
+            .iter()
+            .fold(0, |n, buf| n + buf.len())
+    }
+
+    /// Marks the substream as closed. It is no longer possible to write data on it.
+    ///
+    /// If the remote writing side is still open, this method returns `None` and the remote can
+    /// continue to send data.
+    ///
+    /// If the remote writing side is already closed, this method returns `Some` with the user
+    /// data, and the substream is now destroyed. The data queued on this substream is
+    /// nonetheless sent out.
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`SubstreamMut::close`] has already been called on this substream.
+    ///
+    pub fn close(mut self) -> Option<T> {
+        let substream = self.substream.get_mut();
+        assert!(!substream.local_write_closed);
+        substream.local_write_closed = true;
+
+        if substream.remote_write_closed {
+            substream.user_data.take()
+        } else {
+            None
+        }
+    }
+
+    /// Abruptly shuts down the substream. Its identifier is now invalid. Sends a reset frame to
+    /// the remote.
+    ///
+    /// Use this method when a protocol error happens on a substream.
+    pub fn reset(self) -> T {
+        let (substream_id, substream) = self.substream.remove_entry();
+        // The remote doesn't need to be notified if it isn't aware of the substream.
+        if substream.open_frame_queued {
+            self.pending_resets.push(substream_id);
+        }
+        substream.user_data.unwrap()
+    }
+}
+
+/// Buffers to send out. See [`Mplex::extract_out`].
+pub struct ExtractOut {
+    buffers: Option<Vec<either::Either<arrayvec::ArrayVec<u8, 20>, VecWithOffset>>>,
+}
+
+impl ExtractOut {
+    /// Returns the list of buffers to write.
+    ///
+    /// Can only be called once.
+    ///
+    /// # Panic
+    ///
+    /// Panics if called multiple times.
+    ///
+    pub fn buffers(&mut self) -> impl Iterator<Item = impl AsRef<[u8]>> {
+        self.buffers.take().unwrap().into_iter()
+    }
+}
+
+struct VecWithOffset(Vec<u8>, usize);
+impl AsRef<[u8]> for VecWithOffset {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[self.1..]
+    }
+}
+
+/// Identifier of a substream in the context of a connection.
+///
+/// In the mplex protocol, the local node and the remote allocate substream numbers
+/// independently. A substream is therefore identified by its number and by which side has
+/// opened it.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SubstreamId {
+    num: u64,
+    local_initiated: bool,
+}
+
+#[must_use]
+#[derive(Debug)]
+pub struct IncomingDataOutcome<T> {
+    /// Mplex object on which [`Mplex::incoming_data`] has been called.
+    pub mplex: Mplex<T>,
+    /// Number of bytes read from the incoming buffer. These bytes should no longer be present the
+    /// next time [`Mplex::incoming_data`] is called.
+    pub bytes_read: usize,
+    /// Detail about the incoming data. `None` if nothing of interest has happened.
+    pub detail: Option<IncomingDataDetail<T>>,
+}
+
+/// Details about the incoming data.
+#[must_use]
+#[derive(Debug)]
+pub enum IncomingDataDetail<T> {
+    /// Remote has requested to open a new substream.
+    ///
+    /// After this has been received, either [`Mplex::accept_pending_substream`] or
+    /// [`Mplex::reject_pending_substream`] needs to be called in order to accept or reject
+    /// this substream. Calling [`Mplex::incoming_data`] before this is done will lead to a
+    /// panic.
+    IncomingSubstream,
+    /// Received data corresponding to a substream.
+    DataFrame {
+        /// Offset in the buffer passed to [`Mplex::incoming_data`] where the data frame
+        /// starts. The data frame ends at the offset of [`IncomingDataOutcome::bytes_read`].
+        start_offset: usize,
+        /// Substream the data belongs to. Guaranteed to be valid.
+        substream_id: SubstreamId,
+    },
+    /// Remote has closed its writing side of the substream.
+    StreamClosed {
+        /// Substream that got closed.
+        substream_id: SubstreamId,
+        /// If `None`, the local writing side is still open and needs to be closed. If `Some`, the
+        /// local writing side is already closed and the substream is now considered destroyed.
+        user_data: Option<T>,
+    },
+    /// Remote has asked to reset a substream.
+    ///
+    /// The substream is now considered destroyed.
+    StreamReset {
+        /// Substream that has been destroyed. No longer valid.
+        substream_id: SubstreamId,
+        /// User data that was associated to this substream.
+        user_data: T,
+    },
+}
+
+/// Error while decoding the mplex stream.
+#[derive(Debug, derive_more::Display)]
+pub enum Error {
+    /// Frame header is malformed.
+    InvalidHeader,
+    /// Length of a frame is above the maximum allowed.
+    FrameTooLarge,
+    /// Unrecognized value for the flag as indicated in the header.
+    BadFlag(u8),
+    /// Remote has opened a substream whose number is already in use.
+    UnexpectedNewStream(u64),
+    /// Remote sent additional data on a substream after having closed it.
+    WriteAfterClose,
+    /// Remote has sent a close frame containing data.
+    DataWithClose,
+    /// Remote has sent a reset frame containing data.
+    DataWithReset,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Error, IncomingDataDetail, Mplex};
+
+    fn new_mplex(max_simultaneous_substreams: usize) -> Mplex<()> {
+        Mplex::new(Config {
+            capacity: 0,
+            randomness_seed: (0, 0, 0, 0),
+            max_simultaneous_substreams,
+        })
+    }
+
+    fn extract_all(mplex: &mut Mplex<()>) -> Vec<u8> {
+        let mut out = Vec::new();
+        for buffer in mplex.extract_out(usize::max_value()).buffers() {
+            out.extend_from_slice(buffer.as_ref());
+        }
+        out
+    }
+
+    #[test]
+    fn outbound_substream_lifecycle() {
+        let mut mplex = new_mplex(16);
+
+        let mut substream = mplex.open_substream(());
+        let substream_id = substream.id();
+        substream.write(b"hello".to_vec());
+        assert!(substream.close().is_none());
+
+        // New stream frame, message frame, then close frame.
+        assert_eq!(
+            extract_all(&mut mplex),
+            &[0, 0, 2, 5, b'h', b'e', b'l', b'l', b'o', 4, 0]
+        );
+
+        // Remote sends back data with the `MessageReceiver` flag, then closes.
+        let outcome = mplex.incoming_data(&[1, 2, 1, 2, 3, 0]).unwrap();
+        assert_eq!(outcome.bytes_read, 4);
+        assert!(matches!(
+            outcome.detail,
+            Some(IncomingDataDetail::DataFrame { start_offset: 2, substream_id: id })
+                if id == substream_id
+        ));
+
+        let outcome = outcome.mplex.incoming_data(&[3, 0]).unwrap();
+        assert_eq!(outcome.bytes_read, 2);
+        assert!(matches!(
+            outcome.detail,
+            Some(IncomingDataDetail::StreamClosed {
+                user_data: Some(()),
+                ..
+            })
+        ));
+        assert_eq!(outcome.mplex.user_datas().count(), 0);
+    }
+
+    #[test]
+    fn inbound_substream_and_reset() {
+        let mplex = new_mplex(16);
+
+        // Remote opens substream 1 with the name "a".
+        let outcome = mplex.incoming_data(&[8, 1, b'a']).unwrap();
+        assert_eq!(outcome.bytes_read, 3);
+        assert!(matches!(
+            outcome.detail,
+            Some(IncomingDataDetail::IncomingSubstream)
+        ));
+        let mut mplex = outcome.mplex;
+        let substream_id = mplex.accept_pending_substream(()).id();
+
+        // Local node answers with the `MessageReceiver` flag.
+        mplex.substream_by_id(substream_id).unwrap().write(vec![9]);
+        assert_eq!(extract_all(&mut mplex), &[9, 1, 9]);
+
+        // Resetting sends a `ResetReceiver` frame.
+        mplex.substream_by_id(substream_id).unwrap().reset();
+        assert_eq!(extract_all(&mut mplex), &[13, 0]);
+        assert!(mplex.substream_by_id(substream_id).is_none());
+    }
+
+    #[test]
+    fn substreams_beyond_limit_refused() {
+        let mut mplex = new_mplex(1);
+        mplex.open_substream(());
+        assert_eq!(extract_all(&mut mplex), &[0, 0]);
+
+        let outcome = mplex.incoming_data(&[8, 0]).unwrap();
+        assert_eq!(outcome.bytes_read, 2);
+        assert!(outcome.detail.is_none());
+
+        let mut mplex = outcome.mplex;
+        assert_eq!(mplex.user_datas().count(), 1);
+        assert_eq!(extract_all(&mut mplex), &[13, 0]);
+    }
+
+    #[test]
+    fn frame_too_large() {
+        let mplex = new_mplex(16);
+        let result = mplex.incoming_data(&[10, 0x80, 0x80, 0x80, 0x01]);
+        assert!(matches!(result, Err((Error::FrameTooLarge, _))));
+    }
+}
@@ -101,6 +101,18 @@ where
     pub fn new(config: Config<I, P>) -> Self {
         Negotiation::InProgress(InProgress::new(config))
     }
+
+    /// Shortcut method for [`InProgress::new_dialer_with_fallbacks`] and wrapping the
+    /// [`InProgress`] in a [`Negotiation`].
+    pub fn new_dialer_with_fallbacks(
+        requested_protocol: P,
+        fallback_protocols: impl IntoIterator<Item = P>,
+    ) -> Self {
+        Negotiation::InProgress(InProgress::new_dialer_with_fallbacks(
+            requested_protocol,
+            fallback_protocols,
+        ))
+    }
 }
 
 /// Negotiation in progress.
@@ -113,6 +125,11 @@ pub struct InProgress<I, P> {
     max_frame_len: usize,
     /// Incoming data is buffered in this `recv_buffer` before being decoded.
     recv_buffer: leb128::Framed,
+    /// Only relevant for dialers. Protocols to request, in reverse order, if the remote answers
+    /// that the currently-requested protocol isn't available.
+    dialer_fallbacks: Vec<P>,
+    /// Only relevant for dialers. True if the handshake of the remote has been received.
+    dialer_handshake_received: bool,
 }
 
 /// Current state of the negotiation.
@@ -151,9 +168,32 @@ where
 {
     /// Initializes a new handshake state machine.
     pub fn new(config: Config<I, P>) -> Self {
+        Self::with_dialer_fallbacks(config, Vec::new())
+    }
+
+    /// Initializes a new handshake state machine for a dialer.
+    ///
+    /// If the remote doesn't support `requested_protocol`, the protocols of `fallback_protocols`
+    /// are requested one by one, in order, until one of them is accepted. The protocol that has
+    /// ultimately been negotiated is reported in [`Negotiation::Success`].
+    pub fn new_dialer_with_fallbacks(
+        requested_protocol: P,
+        fallback_protocols: impl IntoIterator<Item = P>,
+    ) -> Self {
+        let mut fallbacks = fallback_protocols.into_iter().collect::<Vec<_>>();
+        fallbacks.reverse();
+        Self::with_dialer_fallbacks(Config::Dialer { requested_protocol }, fallbacks)
+    }
+
+    fn with_dialer_fallbacks(config: Config<I, P>, dialer_fallbacks: Vec<P>) -> Self {
         // Length, in bytes, of the longest protocol name.
         let max_proto_name_len = match &config {
-            Config::Dialer { requested_protocol } => requested_protocol.as_ref().len(),
+            Config::Dialer { requested_protocol } => dialer_fallbacks
+                .iter()
+                .map(|p| p.as_ref().len())
+                .chain(iter::once(requested_protocol.as_ref().len()))
+                .max()
+                .unwrap(),
             Config::Listener {
                 supported_protocols,
             } => supported_protocols
@@ -186,6 +226,8 @@ where
             },
             max_frame_len,
             recv_buffer: leb128::Framed::InProgress(leb128::FramedInProgress::new(max_frame_len)),
+            dialer_fallbacks,
+            dialer_handshake_received: false,
         }
     }
 
@@ -262,7 +304,9 @@ where
                     total_written += written;
                     num_bytes_written += written;
 
-                    if done {
+                    if done && self.dialer_handshake_received {
+                        self.state = InProgressState::ProtocolRequestAnswerExpected;
+                    } else if done {
                         self.state = InProgressState::HandshakeExpected;
                     } else {
                         self.state = InProgressState::SendProtocolRequest { num_bytes_written };
@@ -362,6 +406,7 @@ where
                     // The dialer immediately sends the request after its handshake and before
                     // waiting for the handshake from the listener. As such, after receiving the
                     // handshake, the next step is to wait for the request answer.
+                    self.dialer_handshake_received = true;
                     self.state = InProgressState::ProtocolRequestAnswerExpected;
                 }
 
@@ -442,7 +487,12 @@ where
                     cfg @ Some(Config::Dialer { .. }),
                 ) => {
                     let frame = match self.recv_buffer {
-                        leb128::Framed::Finished(f) => f,
+                        leb128::Framed::Finished(frame) => {
+                            self.recv_buffer = leb128::Framed::InProgress(
+                                leb128::FramedInProgress::new(self.max_frame_len),
+                            );
+                            frame
+                        }
                         leb128::Framed::InProgress(f) => {
                             // No frame is available.
                             debug_assert!(incoming_data.is_empty());
@@ -452,22 +502,34 @@ where
                         }
                     };
 
-                    // Extract `config` to get the protocol name. All the paths below return,
-                    // thereby `config` doesn't need to be put back in `self`.
-                    let requested_protocol = match cfg.take() {
-                        Some(Config::Dialer { requested_protocol }) => requested_protocol,
-                        _ => unreachable!(),
-                    };
-
                     if frame.last().map_or(true, |c| *c != b'\n') {
                         return Err(Error::UnexpectedProtocolRequestAnswer);
                     }
                     if &*frame == b"na\n" {
+                        // If a fallback is available, request it instead.
+                        if let Some(fallback) = self.dialer_fallbacks.pop() {
+                            *cfg = Some(Config::Dialer {
+                                requested_protocol: fallback,
+                            });
+                            self.state = InProgressState::SendProtocolRequest {
+                                num_bytes_written: 0,
+                            };
+                            continue;
+                        }
+
                         // Because of the order of checks, a protocol named `na` will never be
                         // successfully negotiated. Debugging is expected to be less confusing if
                         // the negotiation always fails.
                         return Ok((Negotiation::NotAvailable, total_read, total_written));
                     }
+
+                    // Extract `config` to get the protocol name. All the paths below return,
+                    // thereby `config` doesn't need to be put back in `self`.
+                    let requested_protocol = match cfg.take() {
+                        Some(Config::Dialer { requested_protocol }) => requested_protocol,
+                        _ => unreachable!(),
+                    };
+
                     if &frame[..frame.len() - 1] != requested_protocol.as_ref().as_bytes() {
                         return Err(Error::UnexpectedProtocolRequestAnswer);
                     }
@@ -801,4 +863,41 @@ mod tests {
         test_with_buffer_sizes(1, 2048);
         test_with_buffer_sizes(2048, 1);
     }
+
+    #[test]
+    fn negotiation_fallback_works() {
+        let mut negotiation1 =
+            Negotiation::<iter::Once<_>, _>::new_dialer_with_fallbacks("/foo/2", ["/foo/1"]);
+        let mut negotiation2 = Negotiation::new(Config::Listener {
+            supported_protocols: ["/bar", "/foo/1"].iter().copied(),
+        });
+
+        let mut buf_1_to_2 = Vec::new();
+        let mut buf_2_to_1 = Vec::new();
+
+        while !matches!(
+            (&negotiation1, &negotiation2),
+            (Negotiation::Success(_), Negotiation::Success(_))
+        ) {
+            if let Negotiation::InProgress(nego) = negotiation1 {
+                let (updated, num_read, out) = nego.read_write_vec(&buf_2_to_1).unwrap();
+                negotiation1 = updated;
+                buf_2_to_1.drain(..num_read);
+                buf_1_to_2.extend_from_slice(&out);
+            }
+
+            if let Negotiation::InProgress(nego) = negotiation2 {
+                let (updated, num_read, out) = nego.read_write_vec(&buf_1_to_2).unwrap();
+                negotiation2 = updated;
+                buf_1_to_2.drain(..num_read);
+                buf_2_to_1.extend_from_slice(&out);
+            }
+
+            assert!(!matches!(negotiation1, Negotiation::NotAvailable));
+            assert!(!matches!(negotiation2, Negotiation::NotAvailable));
+        }
+
+        assert!(matches!(negotiation1, Negotiation::Success("/foo/1")));
+        assert!(matches!(negotiation2, Negotiation::Success("/foo/1")));
+    }
 }
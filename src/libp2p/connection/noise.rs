@@ -39,6 +39,19 @@
 //! both the static and ephemeral keys, which is then used to encrypt communications. Note that
 //! the libp2p key isn't used in the key derivation.
 //!
+//! Alongside with their libp2p public key and signature, the responder and initiator can send
+//! additional information to the remote:
+//!
+//! - The list of multiplexing protocols they support, as part of the so-called *Noise
+//! extensions*. When both sides advertise such a list, the multiplexing protocol can be chosen
+//! without having to perform a separate negotiation afterwards.
+//! - An opaque *early data* payload.
+//!
+//! Since the identity of the remote is transmitted during the handshake, it is possible to
+//! compare it with the identity the local node expects, and abort the handshake on mismatch.
+//! When the local node is the initiator, the identity of the responder is known after the second
+//! packet, in other words before the local node has revealed its own identity.
+//!
 //! # Usage
 //!
 //! While this is out of scope of this module, the noise protocol must typically first be
@@ -46,16 +59,17 @@
 //! the [`PROTOCOL_NAME`] constant.
 //!
 //! In order to use noise on top of a connection which has agreed to use noise, create a
-//! [`HandshakeInProgress`], passing a [`NoiseKey`]. This [`NoiseKey`] is typically generated at
-//! startup and doesn't need to be persisted after a restart.
+//! [`HandshakeInProgress`], passing a [`Config`] containing a [`NoiseKey`]. This [`NoiseKey`] is
+//! typically generated at startup and doesn't need to be persisted after a restart.
 //!
 //! Use [`HandshakeInProgress::read_write`] when data is received from the wire or when the remote
 //! is ready to receive more data. At every call, a [`NoiseHandshake`] is returned, potentially
 //! indicating the end of the handshake.
 //!
 //! If the handshake is finished, a [`NoiseHandshake::Success`] is returned, containing the
-//! [`PeerId`] of the remote, which is known to be legitimate, and a [`Noise`] object through
-//! which all further communications should go through.
+//! [`PeerId`] of the remote, which is known to be legitimate, the information sent by the remote
+//! alongside with its identity, and a [`Noise`] object through which all further communications
+//! should go through.
 //!
 //! Use [`Noise::encrypt`] in order to send out data to the remote, and
 //! [`Noise::inject_inbound_data`] when data is received.
@@ -63,7 +77,7 @@
 
 use crate::libp2p::peer_id::{PeerId, PublicKey};
 
-use alloc::{boxed::Box, collections::VecDeque, vec, vec::Vec};
+use alloc::{boxed::Box, collections::VecDeque, string::String, vec, vec::Vec};
 use core::{cmp, convert::TryFrom as _, fmt, iter};
 use prost::Message as _;
 
@@ -75,6 +89,9 @@ mod payload_proto {
 /// Name of the protocol, typically used when negotiated it using *multistream-select*.
 pub const PROTOCOL_NAME: &str = "/noise";
 
+/// Maximum size, in bytes, of [`Config::early_data`].
+pub const MAX_EARLY_DATA_LEN: usize = 16 * 1024;
+
 /// The noise key is the key exchanged during the noise handshake. It is **not** the same as the
 /// libp2p key. The libp2p key is used only to sign the noise public key, while the ECDH is
 /// performed with the noise key.
@@ -99,8 +116,9 @@ pub const PROTOCOL_NAME: &str = "/noise";
 ///
 pub struct NoiseKey {
     key: snow::Keypair,
-    /// Handshake to encrypt then send on the wire.
-    handshake_message: Vec<u8>,
+    /// Payload of the handshake containing the libp2p public key and the signature. Must be
+    /// completed with the per-connection fields, then encrypted and sent on the wire.
+    handshake_payload: payload_proto::NoiseHandshakePayload,
     /// Ed25519 public key used for the signature in the handshake message.
     libp2p_public_ed25519_key: [u8; 32],
 }
@@ -171,20 +189,14 @@ impl UnsignedNoiseKey {
         let libp2p_pubkey_protobuf =
             PublicKey::Ed25519(libp2p_public_ed25519_key).to_protobuf_encoding();
 
-        let handshake_message = {
-            let mut protobuf = payload_proto::NoiseHandshakePayload::default();
-            protobuf.identity_key = libp2p_pubkey_protobuf;
-            protobuf.identity_sig = signature.to_vec();
-
-            let mut msg = Vec::with_capacity(protobuf.encoded_len());
-            protobuf.encode(&mut msg).unwrap();
-            msg
-        };
+        let mut handshake_payload = payload_proto::NoiseHandshakePayload::default();
+        handshake_payload.identity_key = libp2p_pubkey_protobuf;
+        handshake_payload.identity_sig = signature.to_vec();
 
         NoiseKey {
             key: self.key,
             libp2p_public_ed25519_key,
-            handshake_message,
+            handshake_payload,
         }
     }
 }
@@ -447,9 +459,39 @@ pub enum NoiseHandshake {
         cipher: Noise,
         /// [`PeerId`] of the remote.
         remote_peer_id: PeerId,
+        /// Names of the multiplexing protocols advertised by the remote, in decreasing order of
+        /// preference. Empty if the remote hasn't advertised any.
+        remote_stream_muxers: Vec<String>,
+        /// Early data sent by the remote alongside with its identity. Empty if none.
+        remote_early_data: Vec<u8>,
     },
 }
 
+/// Configuration of a Noise handshake.
+pub struct Config<'a> {
+    /// Key to use during the handshake.
+    pub key: &'a NoiseKey,
+
+    /// `true` if the connection has been opened by the local machine, or `false` if it has been
+    /// opened by the remote.
+    pub is_initiator: bool,
+
+    /// If `Some`, the handshake fails with [`HandshakeError::PeerIdMismatch`] as soon as the
+    /// identity of the remote is known, if it doesn't match this value.
+    pub expected_remote_peer_id: Option<PeerId>,
+
+    /// Names of the multiplexing protocols supported by the local node, in decreasing order of
+    /// preference. Advertised to the remote as part of the Noise extensions. Can be empty, in
+    /// which case no Noise extension is sent.
+    pub local_stream_muxers: &'a [&'a str],
+
+    /// Opaque data sent to the remote alongside with the local identity. Can be empty.
+    ///
+    /// > **Note**: When the local node is the responder, this data is sent before the identity
+    /// >           of the remote is known. Do not put anything confidential in there.
+    pub early_data: &'a [u8],
+}
+
 /// Handshake still in progress. More data needs to be sent or received.
 pub struct HandshakeInProgress {
     /// Underlying noise state machine.
@@ -463,6 +505,9 @@ pub struct HandshakeInProgress {
     /// be found in the `tx_payload` field.
     inner: snow::HandshakeState,
 
+    /// If `Some`, the [`PeerId`] the remote must have.
+    expected_remote_peer_id: Option<PeerId>,
+
     /// Unencrypted payload to send as part of the handshake.
     /// If the payload has already been sent, contains `None`.
     /// If the payload hasn't been sent yet, contains the index of the call to
@@ -486,7 +531,11 @@ pub struct HandshakeInProgress {
 
 enum RxPayload {
     /// Remote payload has been received.
-    Received(PeerId),
+    Received {
+        peer_id: PeerId,
+        stream_muxers: Vec<String>,
+        early_data: Vec<u8>,
+    },
     /// Index of the call to [`snow::HandshakeState::read_message`], from now, that is expected
     /// to contains the payload.
     NthMessage(u8),
@@ -495,17 +544,30 @@ enum RxPayload {
 impl NoiseHandshake {
     /// Shortcut function that calls [`HandshakeInProgress::new`] and wraps it into a
     /// [`NoiseHandshake`].
-    pub fn new(key: &NoiseKey, is_initiator: bool) -> Self {
-        NoiseHandshake::InProgress(HandshakeInProgress::new(key, is_initiator))
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`Config::early_data`] is larger than [`MAX_EARLY_DATA_LEN`].
+    ///
+    pub fn new(config: Config) -> Self {
+        NoiseHandshake::InProgress(HandshakeInProgress::new(config))
     }
 }
 
 impl HandshakeInProgress {
     /// Initializes a new noise handshake state machine.
-    pub fn new(key: &NoiseKey, is_initiator: bool) -> Self {
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`Config::early_data`] is larger than [`MAX_EARLY_DATA_LEN`].
+    ///
+    pub fn new(config: Config) -> Self {
+        assert!(config.early_data.len() <= MAX_EARLY_DATA_LEN);
+
         let inner = {
-            let builder = snow::Builder::new(noise_params()).local_private_key(&key.key.private);
-            if is_initiator {
+            let builder =
+                snow::Builder::new(noise_params()).local_private_key(&config.key.key.private);
+            if config.is_initiator {
                 builder.build_initiator()
             } else {
                 builder.build_responder()
@@ -513,19 +575,41 @@ impl HandshakeInProgress {
             .unwrap()
         };
 
+        // Complete the payload containing the libp2p identity with the connection-specific
+        // fields, then encode it.
+        let handshake_message = {
+            let mut protobuf = config.key.handshake_payload.clone();
+            protobuf.data = config.early_data.to_vec();
+            if !config.local_stream_muxers.is_empty() {
+                protobuf.extensions = Some(payload_proto::NoiseExtensions {
+                    webtransport_certhashes: Vec::new(),
+                    stream_muxers: config
+                        .local_stream_muxers
+                        .iter()
+                        .map(|name| String::from(*name))
+                        .collect(),
+                });
+            }
+
+            let mut msg = Vec::with_capacity(protobuf.encoded_len());
+            protobuf.encode(&mut msg).unwrap();
+            msg.into_boxed_slice()
+        };
+
         // Configure according to the XX handshake.
-        let (tx_payload, rx_payload, rx_messages_remain) = if is_initiator {
-            let tx = Some((1, key.handshake_message.clone().into_boxed_slice()));
+        let (tx_payload, rx_payload, rx_messages_remain) = if config.is_initiator {
+            let tx = Some((1, handshake_message));
             let rx = RxPayload::NthMessage(0);
             (tx, rx, 1)
         } else {
-            let tx = Some((0, key.handshake_message.clone().into_boxed_slice()));
+            let tx = Some((0, handshake_message));
             let rx = RxPayload::NthMessage(1);
             (tx, rx, 2)
         };
 
         let mut handshake = HandshakeInProgress {
             inner,
+            expected_remote_peer_id: config.expected_remote_peer_id,
             tx_payload,
             rx_payload,
             rx_messages_remain,
@@ -559,7 +643,11 @@ impl HandshakeInProgress {
             }
         };
 
-        self.tx_buffer_encrypted.resize(512, 0);
+        // The message consists of at most two public keys, two authentication tags, and the
+        // payload. 512 bytes is more than enough for everything but the payload.
+        self.tx_buffer_encrypted
+            .resize(512 + payload.as_ref().map_or(0, |p| p.len()), 0);
+        self.tx_buffer_encrypted.make_contiguous();
         debug_assert!(self.tx_buffer_encrypted.as_slices().1.is_empty());
         let written = self
             .inner
@@ -593,8 +681,12 @@ impl HandshakeInProgress {
         // `into_transport_mode()` can only panic if `!is_handshake_finished()`.
         let cipher = self.inner.into_transport_mode().unwrap();
 
-        let remote_peer_id = match self.rx_payload {
-            RxPayload::Received(peer_id) => peer_id,
+        let (remote_peer_id, remote_stream_muxers, remote_early_data) = match self.rx_payload {
+            RxPayload::Received {
+                peer_id,
+                stream_muxers,
+                early_data,
+            } => (peer_id, stream_muxers, early_data),
             // Since `is_handshake_finished()` has returned true, all messages have been
             // exchanged. As such, the remote payload cannot be in a "still waiting to come"
             // situation other than because of logic error within the code.
@@ -612,6 +704,8 @@ impl HandshakeInProgress {
                 rx_buffer_decrypted: Vec::new(), // TODO: with_capacity
             },
             remote_peer_id,
+            remote_stream_muxers,
+            remote_early_data,
         }
     }
 
@@ -728,7 +822,7 @@ impl HandshakeInProgress {
                     *n -= 1;
                     false
                 }
-                RxPayload::Received(_) => false,
+                RxPayload::Received { .. } => false,
             };
 
             if payload_expected {
@@ -760,7 +854,28 @@ impl HandshakeInProgress {
                     return Err(HandshakeError::SignatureVerificationFailed);
                 }
 
-                self.rx_payload = RxPayload::Received(remote_public_key.into_peer_id());
+                let remote_peer_id = remote_public_key.into_peer_id();
+
+                // Abort the handshake as soon as possible if the remote isn't the one that is
+                // expected. When the local node is the initiator, this happens before the
+                // local identity has been sent out.
+                if let Some(expected) = &self.expected_remote_peer_id {
+                    if *expected != remote_peer_id {
+                        return Err(HandshakeError::PeerIdMismatch {
+                            expected: expected.clone(),
+                            actual: remote_peer_id,
+                        });
+                    }
+                }
+
+                self.rx_payload = RxPayload::Received {
+                    peer_id: remote_peer_id,
+                    stream_muxers: handshake_payload
+                        .extensions
+                        .map(|ext| ext.stream_muxers)
+                        .unwrap_or_default(),
+                    early_data: handshake_payload.data,
+                };
             } else if !decoded_payload.is_empty() {
                 return Err(HandshakeError::UnexpectedPayload);
             };
@@ -802,6 +917,14 @@ pub enum HandshakeError {
     UnexpectedPayload,
     /// Signature of the noise public key by the libp2p key failed.
     SignatureVerificationFailed,
+    /// Identity of the remote doesn't match [`Config::expected_remote_peer_id`].
+    #[display(fmt = "Expected PeerId {} but remote is {}", expected, actual)]
+    PeerIdMismatch {
+        /// Value of [`Config::expected_remote_peer_id`].
+        expected: PeerId,
+        /// Actual identity of the remote.
+        actual: PeerId,
+    },
 }
 
 /// Error while decoding data.
@@ -815,7 +938,8 @@ pub struct PayloadDecodeError(prost::DecodeError);
 
 #[cfg(test)]
 mod tests {
-    use super::{NoiseHandshake, NoiseKey};
+    use super::{Config, HandshakeError, NoiseHandshake, NoiseKey};
+    use crate::libp2p::peer_id::PublicKey;
 
     #[test]
     fn handshake_basic_works() {
@@ -823,8 +947,20 @@ mod tests {
             let key1 = NoiseKey::new(&rand::random());
             let key2 = NoiseKey::new(&rand::random());
 
-            let mut handshake1 = NoiseHandshake::new(&key1, true);
-            let mut handshake2 = NoiseHandshake::new(&key2, false);
+            let mut handshake1 = NoiseHandshake::new(Config {
+                key: &key1,
+                is_initiator: true,
+                expected_remote_peer_id: None,
+                local_stream_muxers: &[],
+                early_data: &[],
+            });
+            let mut handshake2 = NoiseHandshake::new(Config {
+                key: &key2,
+                is_initiator: false,
+                expected_remote_peer_id: None,
+                local_stream_muxers: &[],
+                early_data: &[],
+            });
 
             let mut buf_1_to_2 = Vec::new();
             let mut buf_2_to_1 = Vec::new();
@@ -889,4 +1025,139 @@ mod tests {
         test_with_buffer_sizes(1, 2048);
         test_with_buffer_sizes(2048, 1);
     }
+
+    /// Drives two handshakes until they both finish or one of them errors. Returns the last
+    /// state of each handshake.
+    fn run_handshakes(
+        mut handshake1: NoiseHandshake,
+        mut handshake2: NoiseHandshake,
+    ) -> (
+        Result<NoiseHandshake, HandshakeError>,
+        Result<NoiseHandshake, HandshakeError>,
+    ) {
+        let mut buf_1_to_2 = Vec::new();
+        let mut buf_2_to_1 = Vec::new();
+
+        loop {
+            let mut progress = false;
+
+            if let NoiseHandshake::InProgress(nego) = handshake1 {
+                let mut out = vec![0; 4096];
+                match nego.read_write(&buf_2_to_1, &mut out) {
+                    Ok((updated, num_read, written)) => {
+                        handshake1 = updated;
+                        buf_2_to_1.drain(..num_read);
+                        buf_1_to_2.extend_from_slice(&out[..written]);
+                        progress |= num_read != 0 || written != 0;
+                    }
+                    Err(err) => return (Err(err), Ok(handshake2)),
+                }
+            }
+
+            if let NoiseHandshake::InProgress(nego) = handshake2 {
+                let mut out = vec![0; 4096];
+                match nego.read_write(&buf_1_to_2, &mut out) {
+                    Ok((updated, num_read, written)) => {
+                        handshake2 = updated;
+                        buf_1_to_2.drain(..num_read);
+                        buf_2_to_1.extend_from_slice(&out[..written]);
+                        progress |= num_read != 0 || written != 0;
+                    }
+                    Err(err) => return (Ok(handshake1), Err(err)),
+                }
+            }
+
+            if !progress {
+                return (Ok(handshake1), Ok(handshake2));
+            }
+        }
+    }
+
+    #[test]
+    fn extensions_and_early_data_exchanged() {
+        let key1 = NoiseKey::new(&rand::random());
+        let key2 = NoiseKey::new(&rand::random());
+        let peer_id1 = PublicKey::Ed25519(*key1.libp2p_public_ed25519_key()).into_peer_id();
+        let peer_id2 = PublicKey::Ed25519(*key2.libp2p_public_ed25519_key()).into_peer_id();
+
+        let (outcome1, outcome2) = run_handshakes(
+            NoiseHandshake::new(Config {
+                key: &key1,
+                is_initiator: true,
+                expected_remote_peer_id: Some(peer_id2.clone()),
+                local_stream_muxers: &["/yamux/1.0.0", "/mplex/6.7.0"],
+                early_data: b"hello",
+            }),
+            NoiseHandshake::new(Config {
+                key: &key2,
+                is_initiator: false,
+                expected_remote_peer_id: None,
+                local_stream_muxers: &[],
+                early_data: &[],
+            }),
+        );
+
+        match outcome1.unwrap() {
+            NoiseHandshake::Success {
+                remote_peer_id,
+                remote_stream_muxers,
+                remote_early_data,
+                ..
+            } => {
+                assert_eq!(remote_peer_id, peer_id2);
+                assert!(remote_stream_muxers.is_empty());
+                assert!(remote_early_data.is_empty());
+            }
+            _ => panic!(),
+        }
+
+        match outcome2.unwrap() {
+            NoiseHandshake::Success {
+                remote_peer_id,
+                remote_stream_muxers,
+                remote_early_data,
+                ..
+            } => {
+                assert_eq!(remote_peer_id, peer_id1);
+                assert_eq!(remote_stream_muxers, vec!["/yamux/1.0.0", "/mplex/6.7.0"]);
+                assert_eq!(remote_early_data, b"hello");
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn peer_id_mismatch_detected_early() {
+        let key1 = NoiseKey::new(&rand::random());
+        let key2 = NoiseKey::new(&rand::random());
+        let other_peer_id = PublicKey::Ed25519(rand::random()).into_peer_id();
+
+        let (outcome1, outcome2) = run_handshakes(
+            NoiseHandshake::new(Config {
+                key: &key1,
+                is_initiator: true,
+                expected_remote_peer_id: Some(other_peer_id.clone()),
+                local_stream_muxers: &[],
+                early_data: &[],
+            }),
+            NoiseHandshake::new(Config {
+                key: &key2,
+                is_initiator: false,
+                expected_remote_peer_id: None,
+                local_stream_muxers: &[],
+                early_data: &[],
+            }),
+        );
+
+        match outcome1 {
+            Err(HandshakeError::PeerIdMismatch { expected, .. }) => {
+                assert_eq!(expected, other_peer_id)
+            }
+            _ => panic!(),
+        }
+
+        // The initiator has aborted before sending its identity. The responder is still waiting
+        // for the third message of the handshake.
+        assert!(matches!(outcome2, Ok(NoiseHandshake::InProgress(_))));
+    }
 }
@@ -45,6 +45,23 @@
 //! the [`Yamux`] itself doesn't enforce any limit. Enforcing such a bound must be done based
 //! on the logic of the higher-level protocols. Failing to do so might lead to potential DoS
 //! attack vectors.
+//!
+//! # Flow control
+//!
+//! Each substream has a receive window, which is the number of bytes that the remote is allowed
+//! to send on this substream. This window starts at [`Config::substream_receive_window`] and is
+//! decreased when data is received. The [`Yamux`] never increases this window by itself.
+//! Instead, the user is expected to call [`SubstreamMut::add_remote_window`] once the received
+//! data has been processed, or in order to allow the remote to send more data in advance. Window
+//! updates are then sent to the remote in batches.
+//!
+//! Not calling [`SubstreamMut::add_remote_window`] applies back-pressure to the remote.
+//!
+//! # Shutdown
+//!
+//! Call [`Yamux::send_goaway`] in order to notify the remote that no new substream will be
+//! accepted. A GoAway frame is also automatically queued when [`Yamux::incoming_data`] detects
+//! a protocol error.
 
 // TODO: write example
 
@@ -82,6 +99,29 @@ pub struct Yamux<T> {
     /// Writing out the data of this substream is the second most highest priority after writing
     /// out [`Yamux::pending_out_header`].
     writing_out_substream: Option<(SubstreamId, usize)>,
+
+    /// See [`Config::max_simultaneous_substreams`].
+    max_simultaneous_substreams: usize,
+
+    /// Window granted to the remote on each new substream in addition to the implicit
+    /// [`DEFAULT_FRAME_SIZE`]. See [`Config::substream_receive_window`].
+    extra_substream_window: u64,
+
+    /// State of the GoAway frame sent by the local node.
+    outgoing_goaway: OutgoingGoAway,
+
+    /// If `Some`, a GoAway frame has been received from the remote.
+    received_goaway: Option<GoAwayErrorCode>,
+}
+
+enum OutgoingGoAway {
+    /// No GoAway frame has been requested.
+    NotRequired,
+    /// A GoAway frame must be sent out as soon as possible.
+    Required(GoAwayErrorCode),
+    /// A GoAway frame has been queued in [`Yamux::pending_out_header`] or has already been sent
+    /// out.
+    Queued,
 }
 
 struct Substream<T> {
@@ -90,6 +130,9 @@ struct Substream<T> {
     first_message_queued: bool,
     /// Amount of data the remote is allowed to transmit to the local node.
     remote_allowed_window: u64,
+    /// Increase to [`Substream::remote_allowed_window`] granted by the user but not yet
+    /// transmitted to the remote in the form of a window update frame.
+    pending_window_increase: u64,
     /// Amount of data the local node is allowed to transmit to the remote.
     allowed_window: u64,
     /// True if the writing side of the local node is closed for this substream.
@@ -158,9 +201,36 @@ impl<T> Yamux<T> {
             },
             pending_out_header: arrayvec::ArrayVec::new(),
             writing_out_substream: None,
+            max_simultaneous_substreams: config.max_simultaneous_substreams,
+            extra_substream_window: config
+                .substream_receive_window
+                .saturating_sub(DEFAULT_FRAME_SIZE),
+            outgoing_goaway: OutgoingGoAway::NotRequired,
+            received_goaway: None,
+        }
+    }
+
+    /// Queues a GoAway frame, notifying the remote that no new substream will be accepted.
+    /// Substreams that are already open are unaffected.
+    ///
+    /// Has no effect if a GoAway frame has already been queued or sent.
+    pub fn send_goaway(&mut self, error_code: GoAwayErrorCode) {
+        if let OutgoingGoAway::NotRequired = self.outgoing_goaway {
+            self.outgoing_goaway = OutgoingGoAway::Required(error_code);
         }
     }
 
+    /// Returns `true` if a GoAway frame has been queued using [`Yamux::send_goaway`] and has
+    /// been entirely returned by [`Yamux::extract_out`].
+    pub fn goaway_sent(&self) -> bool {
+        matches!(self.outgoing_goaway, OutgoingGoAway::Queued) && self.pending_out_header.is_empty()
+    }
+
+    /// Returns the error code of the GoAway frame sent by the remote, if any.
+    pub fn received_goaway(&self) -> Option<GoAwayErrorCode> {
+        self.received_goaway
+    }
+
     /// Opens a new substream.
     ///
     /// This method only modifies the state of `self` and reserves an identifier. No message needs
@@ -218,6 +288,7 @@ impl<T> Yamux<T> {
         entry.insert(Substream {
             first_message_queued: false,
             remote_allowed_window: DEFAULT_FRAME_SIZE,
+            pending_window_increase: self.extra_substream_window,
             allowed_window: DEFAULT_FRAME_SIZE,
             local_write_closed: false,
             remote_write_closed: false,
@@ -251,13 +322,39 @@ impl<T> Yamux<T> {
 
     /// Process some incoming data.
     ///
+    /// In case of protocol error, a GoAway frame is automatically queued and the [`Yamux`] is
+    /// returned alongside with the error. The GoAway frame should be sent out, using
+    /// [`Yamux::extract_out`], before the connection is shut down. See [`Yamux::goaway_sent`].
+    ///
     /// # Panic
     ///
     /// Panics if pending incoming substream.
     ///
     // TODO: explain that reading might be blocked on writing
     // TODO: reword panic reason
-    pub fn incoming_data(mut self, mut data: &[u8]) -> Result<IncomingDataOutcome<T>, Error> {
+    pub fn incoming_data(
+        mut self,
+        data: &[u8],
+    ) -> Result<IncomingDataOutcome<T>, (Error, Yamux<T>)> {
+        match self.incoming_data_inner(data) {
+            Ok((bytes_read, detail)) => Ok(IncomingDataOutcome {
+                yamux: self,
+                bytes_read,
+                detail,
+            }),
+            Err(err) => {
+                self.send_goaway(GoAwayErrorCode::ProtocolError);
+                Err((err, self))
+            }
+        }
+    }
+
+    /// Implementation of [`Yamux::incoming_data`]. Returns the number of bytes read and the
+    /// detail of the outcome.
+    fn incoming_data_inner(
+        &mut self,
+        mut data: &[u8],
+    ) -> Result<(usize, Option<IncomingDataDetail<T>>), Error> {
         let mut total_read: usize = 0;
 
         while !data.is_empty() {
@@ -280,23 +377,21 @@ impl<T> Yamux<T> {
 
                     if substream.local_write_closed {
                         let user_data = self.substreams.remove(&substream_id.0).unwrap().user_data;
-                        return Ok(IncomingDataOutcome {
-                            yamux: self,
-                            bytes_read: total_read,
-                            detail: Some(IncomingDataDetail::StreamClosed {
+                        return Ok((
+                            total_read,
+                            Some(IncomingDataDetail::StreamClosed {
                                 substream_id,
                                 user_data: Some(user_data),
                             }),
-                        });
+                        ));
                     } else {
-                        return Ok(IncomingDataOutcome {
-                            yamux: self,
-                            bytes_read: total_read,
-                            detail: Some(IncomingDataDetail::StreamClosed {
+                        return Ok((
+                            total_read,
+                            Some(IncomingDataDetail::StreamClosed {
                                 substream_id,
                                 user_data: None,
                             }),
-                        });
+                        ));
                     }
                 }
 
@@ -330,14 +425,13 @@ impl<T> Yamux<T> {
                             }
                         }
 
-                        return Ok(IncomingDataOutcome {
-                            yamux: self,
-                            bytes_read: total_read,
-                            detail: Some(IncomingDataDetail::DataFrame {
+                        return Ok((
+                            total_read,
+                            Some(IncomingDataDetail::DataFrame {
                                 substream_id,
                                 start_offset,
                             }),
-                        });
+                        ));
                     } else {
                         if *remaining_bytes == 0 {
                             self.incoming = Incoming::Header(arrayvec::ArrayVec::new());
@@ -423,8 +517,26 @@ impl<T> Yamux<T> {
                             }
                         }
                         3 => {
-                            // TODO: go away
-                            todo!()
+                            // GoAway frame. The length field contains the error code.
+                            if flags_field != 0 {
+                                return Err(Error::BadGoAwayFlags(flags_field));
+                            }
+
+                            let error_code = match length_field {
+                                0 => GoAwayErrorCode::NormalTermination,
+                                1 => GoAwayErrorCode::ProtocolError,
+                                2 => GoAwayErrorCode::InternalError,
+                                other => return Err(Error::UnknownGoAwayErrorCode(other)),
+                            };
+
+                            self.incoming = Incoming::Header(arrayvec::ArrayVec::new());
+
+                            if self.received_goaway.is_some() {
+                                return Err(Error::MultipleGoAways);
+                            }
+
+                            self.received_goaway = Some(error_code);
+                            return Ok((total_read, Some(IncomingDataDetail::GoAway(error_code))));
                         }
                         // Handled below.
                         0 | 1 => {}
@@ -446,14 +558,13 @@ impl<T> Yamux<T> {
                         self.incoming = Incoming::Header(arrayvec::ArrayVec::new());
 
                         if let Some(removed) = self.substreams.remove(&substream_id.0) {
-                            return Ok(IncomingDataOutcome {
-                                yamux: self,
-                                bytes_read: total_read,
-                                detail: Some(IncomingDataDetail::StreamReset {
+                            return Ok((
+                                total_read,
+                                Some(IncomingDataDetail::StreamReset {
                                     substream_id,
                                     user_data: removed.user_data,
                                 }),
-                            });
+                            ));
                         } else {
                             // The remote might have sent a RST frame concerning a substream for
                             // which we have sent a RST frame earlier. Considering that we don't
@@ -477,6 +588,29 @@ impl<T> Yamux<T> {
                         // Remote has sent a SYN flag.
                         if self.substreams.contains_key(&substream_id.0) {
                             return Err(Error::UnexpectedSyn(substream_id.0));
+                        } else if self.substreams.len() >= self.max_simultaneous_substreams
+                            || !matches!(self.outgoing_goaway, OutgoingGoAway::NotRequired)
+                        {
+                            // Too many substreams are open, or the local node has announced that
+                            // it no longer accepts substreams. The new substream is refused by
+                            // answering with a RST flag. Since `self.pending_out_header` is
+                            // guaranteed to be empty, this frame can be written immediately.
+                            let is_data_frame = incoming_header[1] == 0;
+                            debug_assert!(self.pending_out_header.is_empty());
+                            self.queue_window_size_frame_header(0x8, substream_id.0, 0);
+
+                            // Any data contained in the frame is discarded, as the substream is
+                            // unknown.
+                            self.incoming = if is_data_frame {
+                                Incoming::DataFrame {
+                                    substream_id,
+                                    remaining_bytes: length_field,
+                                    fin: false,
+                                }
+                            } else {
+                                Incoming::Header(arrayvec::ArrayVec::new())
+                            };
+                            continue;
                         } else {
                             self.incoming = Incoming::PendingIncomingSubstream {
                                 substream_id,
@@ -493,11 +627,7 @@ impl<T> Yamux<T> {
                                 fin: (flags_field & 0x4) != 0,
                             };
 
-                            return Ok(IncomingDataOutcome {
-                                yamux: self,
-                                bytes_read: total_read,
-                                detail: Some(IncomingDataDetail::IncomingSubstream),
-                            });
+                            return Ok((total_read, Some(IncomingDataDetail::IncomingSubstream)));
                         }
                     };
 
@@ -515,16 +645,6 @@ impl<T> Yamux<T> {
                                 .remote_allowed_window
                                 .checked_sub(u64::from(length_field))
                                 .ok_or(Error::CreditsExceeded)?;
-
-                            // TODO: temporary hack
-                            let syn_ack_flag = !substream.first_message_queued;
-                            substream.first_message_queued = true;
-                            substream.remote_allowed_window += 256 * 1024;
-                            self.queue_window_size_frame_header(
-                                syn_ack_flag,
-                                substream_id.0,
-                                256 * 1024,
-                            );
                         }
 
                         self.incoming = Incoming::DataFrame {
@@ -560,11 +680,7 @@ impl<T> Yamux<T> {
             }
         }
 
-        Ok(IncomingDataOutcome {
-            yamux: self,
-            bytes_read: total_read,
-            detail: None,
-        })
+        Ok((total_read, None))
     }
 
     /// Returns an object that provides an iterator to a list of buffers whose content must be
@@ -654,6 +770,39 @@ impl<T> Yamux<T> {
             debug_assert!(self.pending_out_header.is_empty());
             debug_assert!(self.writing_out_substream.is_none());
 
+            // Send out the GoAway frame, if required.
+            if let OutgoingGoAway::Required(error_code) = self.outgoing_goaway {
+                self.outgoing_goaway = OutgoingGoAway::Queued;
+                self.queue_goaway_frame_header(error_code);
+                continue;
+            }
+
+            // Send out window updates. In order to avoid sending too many frames, a window
+            // update is only sent once a large enough increase has been accumulated.
+            if let Some((id, sub)) = self
+                .substreams
+                .iter_mut()
+                .find(|(_, s)| s.pending_window_increase >= WINDOW_UPDATE_THRESHOLD)
+                .map(|(id, sub)| (*id, sub))
+            {
+                let increase =
+                    u32::try_from(sub.pending_window_increase).unwrap_or(u32::max_value());
+                sub.pending_window_increase -= u64::from(increase);
+                sub.remote_allowed_window += u64::from(increase);
+                let syn_ack_flag = !sub.first_message_queued;
+                sub.first_message_queued = true;
+                self.queue_window_size_frame_header(
+                    if syn_ack_flag {
+                        self.syn_or_ack_flag(id)
+                    } else {
+                        0
+                    },
+                    id,
+                    increase,
+                );
+                continue;
+            }
+
             // Start writing more data from another substream.
             // TODO: choose substreams in some sort of round-robin way
             if let Some((id, sub)) = self
@@ -705,6 +854,7 @@ impl<T> Yamux<T> {
                     Substream {
                         first_message_queued: false,
                         remote_allowed_window: DEFAULT_FRAME_SIZE,
+                        pending_window_increase: self.extra_substream_window,
                         allowed_window: DEFAULT_FRAME_SIZE + u64::from(extra_window),
                         local_write_closed: false,
                         remote_write_closed: data_frame_size == 0 && fin,
@@ -776,13 +926,7 @@ impl<T> Yamux<T> {
 
         let mut flags: u16 = 0;
         if syn_ack_flag {
-            if (substream_id.get() % 2) == (self.next_outbound_substream.get() % 2) {
-                // SYN
-                flags |= 0x1;
-            } else {
-                // ACK
-                flags |= 0x2;
-            }
+            flags |= self.syn_or_ack_flag(substream_id);
         }
         if fin_flag {
             flags |= 0x4;
@@ -803,6 +947,17 @@ impl<T> Yamux<T> {
         debug_assert_eq!(self.pending_out_header.len(), 12);
     }
 
+    /// Returns the flag (`SYN` or `ACK`) to put in the first frame sent on the given substream.
+    fn syn_or_ack_flag(&self, substream_id: NonZeroU32) -> u16 {
+        if (substream_id.get() % 2) == (self.next_outbound_substream.get() % 2) {
+            // SYN
+            0x1
+        } else {
+            // ACK
+            0x2
+        }
+    }
+
     /// Writes a window size update frame header in `self.pending_out_header`.
     ///
     /// # Panic
@@ -811,23 +966,12 @@ impl<T> Yamux<T> {
     ///
     fn queue_window_size_frame_header(
         &mut self,
-        syn_ack_flag: bool,
+        flags: u16,
         substream_id: NonZeroU32,
         window_size: u32,
     ) {
         assert!(self.pending_out_header.is_empty());
 
-        let mut flags: u16 = 0;
-        if syn_ack_flag {
-            if (substream_id.get() % 2) == (self.next_outbound_substream.get() % 2) {
-                // SYN
-                flags |= 0x1;
-            } else {
-                // ACK
-                flags |= 0x2;
-            }
-        }
-
         self.pending_out_header.push(0);
         self.pending_out_header.push(1);
         self.pending_out_header
@@ -842,6 +986,36 @@ impl<T> Yamux<T> {
 
         debug_assert_eq!(self.pending_out_header.len(), 12);
     }
+
+    /// Writes a GoAway frame header in `self.pending_out_header`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `!self.pending_out_header.is_empty()`.
+    ///
+    fn queue_goaway_frame_header(&mut self, error_code: GoAwayErrorCode) {
+        assert!(self.pending_out_header.is_empty());
+
+        let error_code: u32 = match error_code {
+            GoAwayErrorCode::NormalTermination => 0,
+            GoAwayErrorCode::ProtocolError => 1,
+            GoAwayErrorCode::InternalError => 2,
+        };
+
+        self.pending_out_header.push(0);
+        self.pending_out_header.push(3);
+        self.pending_out_header
+            .try_extend_from_slice(&0u16.to_be_bytes())
+            .unwrap();
+        self.pending_out_header
+            .try_extend_from_slice(&0u32.to_be_bytes())
+            .unwrap();
+        self.pending_out_header
+            .try_extend_from_slice(&error_code.to_be_bytes())
+            .unwrap();
+
+        debug_assert_eq!(self.pending_out_header.len(), 12);
+    }
 }
 
 impl<T> fmt::Debug for Yamux<T>
@@ -878,6 +1052,16 @@ pub struct Config {
     /// Seed used for the randomness. Used to avoid HashDos attack and determines the order in
     /// which the data on substreams is sent out.
     pub randomness_seed: (u64, u64, u64, u64),
+    /// Maximum number of substreams simultaneously open, both inbound and outbound substreams
+    /// combined. Substreams that the remote tries to open beyond this limit are automatically
+    /// refused.
+    pub max_simultaneous_substreams: usize,
+    /// Number of bytes that the remote is allowed to send on each substream before having to
+    /// wait for a window update.
+    ///
+    /// The yamux protocol gives an implicit window of 256 kiB to each new substream, and windows
+    /// can't be shrunk. Values below 256 kiB are therefore equivalent to 256 kiB.
+    pub substream_receive_window: u64,
 }
 
 /// Reference to a substream within the [`Yamux`].
@@ -917,6 +1101,16 @@ impl<'a, T> SubstreamMut<'a, T> {
         substream.write_buffers.push(data);
     }
 
+    /// Allows the remote to send `bytes` more bytes of data on this substream.
+    ///
+    /// This method should typically be called after data received on this substream has been
+    /// processed, with the number of bytes that have been processed. Increases smaller than a
+    /// certain threshold are accumulated and sent out in a single window update.
+    pub fn add_remote_window(&mut self, bytes: u64) {
+        let substream = self.substream.get_mut();
+        substream.pending_window_increase = substream.pending_window_increase.saturating_add(bytes);
+    }
+
     /// Returns the number of bytes queued for writing on this substream.
     pub fn queued_bytes(&self) -> usize {
         let substream = self.substream.get();
@@ -1041,6 +1235,20 @@ pub enum IncomingDataDetail<T> {
         /// User data that was associated to this substream.
         user_data: T,
     },
+    /// Remote has sent a GoAway frame, indicating that it will no longer accept new substreams.
+    /// Substreams that are already open are unaffected.
+    GoAway(GoAwayErrorCode),
+}
+
+/// Error code found in a GoAway frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GoAwayErrorCode {
+    /// The connection is shut down gracefully.
+    NormalTermination,
+    /// The connection is shut down because of a protocol error.
+    ProtocolError,
+    /// The connection is shut down because of an internal error.
+    InternalError,
 }
 
 /// Error while decoding the yamux stream.
@@ -1066,7 +1274,173 @@ pub enum Error {
     WriteAfterFin,
     /// Remote has sent a data frame containing data at the same time as a RST flag.
     DataWithRst,
+    /// Received a GoAway frame with flags.
+    BadGoAwayFlags(u16),
+    /// Received a GoAway frame with an unknown error code.
+    UnknownGoAwayErrorCode(u32),
+    /// Received more than one GoAway frame.
+    MultipleGoAways,
 }
 
 /// By default, all new substreams have this implicit window size.
 const DEFAULT_FRAME_SIZE: u64 = 256 * 1024;
+
+/// Minimum accumulated window increase before a window update frame is sent out.
+const WINDOW_UPDATE_THRESHOLD: u64 = DEFAULT_FRAME_SIZE / 2;
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Error, GoAwayErrorCode, IncomingDataDetail, Yamux};
+
+    fn new_yamux(max_simultaneous_substreams: usize) -> Yamux<()> {
+        Yamux::new(Config {
+            is_initiator: true,
+            capacity: 0,
+            randomness_seed: (0, 0, 0, 0),
+            max_simultaneous_substreams,
+            substream_receive_window: 256 * 1024,
+        })
+    }
+
+    fn extract_all(yamux: &mut Yamux<()>) -> Vec<u8> {
+        let mut out = Vec::new();
+        for buffer in yamux.extract_out(usize::max_value()).buffers() {
+            out.extend_from_slice(buffer.as_ref());
+        }
+        out
+    }
+
+    #[test]
+    fn goaway_sent_on_protocol_error() {
+        let yamux = new_yamux(16);
+        let (error, mut yamux) = yamux.incoming_data(&[1; 12]).unwrap_err();
+        assert!(matches!(error, Error::UnknownVersion(1)));
+        assert!(!yamux.goaway_sent());
+        assert_eq!(
+            extract_all(&mut yamux),
+            &[0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+        assert!(yamux.goaway_sent());
+    }
+
+    #[test]
+    fn graceful_goaway() {
+        let mut yamux = new_yamux(16);
+        yamux.send_goaway(GoAwayErrorCode::NormalTermination);
+        yamux.send_goaway(GoAwayErrorCode::InternalError);
+        assert_eq!(
+            extract_all(&mut yamux),
+            &[0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert!(yamux.goaway_sent());
+        assert!(extract_all(&mut yamux).is_empty());
+    }
+
+    #[test]
+    fn goaway_received() {
+        let yamux = new_yamux(16);
+        let outcome = yamux
+            .incoming_data(&[0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2])
+            .unwrap();
+        assert_eq!(outcome.bytes_read, 12);
+        assert!(matches!(
+            outcome.detail,
+            Some(IncomingDataDetail::GoAway(GoAwayErrorCode::InternalError))
+        ));
+        assert_eq!(
+            outcome.yamux.received_goaway(),
+            Some(GoAwayErrorCode::InternalError)
+        );
+    }
+
+    #[test]
+    fn substreams_beyond_limit_refused() {
+        let mut yamux = new_yamux(1);
+        yamux.open_substream(());
+
+        // Remote opens substream 2 with a data frame containing 3 bytes, which must be refused
+        // and its data discarded.
+        let outcome = yamux
+            .incoming_data(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 1, 2, 3])
+            .unwrap();
+        assert_eq!(outcome.bytes_read, 15);
+        assert!(outcome.detail.is_none());
+
+        let mut yamux = outcome.yamux;
+        assert_eq!(yamux.user_datas().len(), 1);
+        assert_eq!(
+            extract_all(&mut yamux),
+            &[0, 1, 0, 8, 0, 0, 0, 2, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn window_update_after_consumption() {
+        let yamux = new_yamux(16);
+
+        // Remote opens substream 2 with a data frame containing 3 bytes.
+        let data = [0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 1, 2, 3];
+        let outcome = yamux.incoming_data(&data).unwrap();
+        assert!(matches!(
+            outcome.detail,
+            Some(IncomingDataDetail::IncomingSubstream)
+        ));
+        let mut yamux = outcome.yamux;
+        yamux.accept_pending_substream(());
+
+        let outcome = yamux.incoming_data(&data[outcome.bytes_read..]).unwrap();
+        let substream_id = match outcome.detail {
+            Some(IncomingDataDetail::DataFrame { substream_id, .. }) => substream_id,
+            _ => panic!(),
+        };
+        let mut yamux = outcome.yamux;
+
+        // No window update is sent before the data has been consumed, and small increases are
+        // accumulated.
+        assert!(extract_all(&mut yamux).is_empty());
+        yamux
+            .substream_by_id(substream_id)
+            .unwrap()
+            .add_remote_window(3);
+        assert!(extract_all(&mut yamux).is_empty());
+
+        yamux
+            .substream_by_id(substream_id)
+            .unwrap()
+            .add_remote_window(200 * 1024 - 3);
+        assert_eq!(
+            extract_all(&mut yamux),
+            &[0, 1, 0, 2, 0, 0, 0, 2, 0, 3, 0x20, 0]
+        );
+    }
+
+    #[test]
+    fn larger_substream_window_granted() {
+        let mut yamux = Yamux::new(Config {
+            is_initiator: true,
+            capacity: 0,
+            randomness_seed: (0, 0, 0, 0),
+            max_simultaneous_substreams: 16,
+            substream_receive_window: 1024 * 1024,
+        });
+
+        // The window in excess of the implicit 256 kiB is granted along with the SYN flag.
+        yamux.open_substream(());
+        assert_eq!(
+            extract_all(&mut yamux),
+            &[0, 1, 0, 1, 0, 0, 0, 1, 0, 0x0c, 0, 0]
+        );
+        assert!(extract_all(&mut yamux).is_empty());
+    }
+
+    #[test]
+    fn credits_exceeded() {
+        let mut yamux = new_yamux(16);
+        let id = yamux.open_substream(()).id();
+        assert_eq!(id.0.get(), 1);
+
+        // The remote isn't allowed to send more than 256 kiB without a window update.
+        let result = yamux.incoming_data(&[0, 0, 0, 2, 0, 0, 0, 1, 0, 4, 0, 1]);
+        assert!(matches!(result, Err((Error::CreditsExceeded, _))));
+    }
+}
@@ -24,6 +24,9 @@
 //! - A list of active inbound connections.
 //! - A list of [`Multiaddr`]s onto which the node is believed to be reachable.
 //!   - For each multiaddr, optionally an active connection or pending dialing attempt.
+//!   - For each multiaddr, the number of consecutive failed dialing attempts, and the moment
+//!     before which it shouldn't be dialed again.
+//! - A reputation score, and optionally the moment until which the node is banned.
 //! - A list of overlay networks the node is believed to belong to.
 //!   - For each overlay network the node belongs to, one optional inbound and one optional
 //!     outbound substream.
@@ -41,6 +44,30 @@
 //! to discover the identities and addresses of nodes that belong to these various overlay
 //! networks.
 //!
+//! # Backoff
+//!
+//! When a dialing attempt fails, use [`PendingMut::remove_and_backoff`] in order to prevent the
+//! target address from being dialed again for a certain duration. This duration starts at
+//! [`Config::dial_backoff_base`] and is doubled after each consecutive failure, up to
+//! [`Config::dial_backoff_max`]. A successful connection resets the backoff of the address.
+//!
+//! Addresses that are in a backoff period are ignored by [`Peerset::random_not_connected`] and
+//! [`NodeMutKnown::dialable_addresses`].
+//!
+//! # Reputation
+//!
+//! Each node has a reputation, starting at 0, that can be modified with
+//! [`NodeMutKnown::change_reputation`]. The reputation slowly decays towards 0 as time passes,
+//! at a rate of approximately 2% per second.
+//!
+//! If the reputation of a node falls below [`Config::ban_threshold`], the node is banned for
+//! [`Config::ban_duration`]. Banned nodes are never returned by
+//! [`Peerset::random_not_connected`]. It is the responsibility of the user to close the existing
+//! connections to a node that has just been banned.
+//!
+//! Amongst the nodes that can be dialed, [`Peerset::random_not_connected`] always chooses one
+//! with the highest reputation.
+//!
 
 // TODO: finish documentation
 
@@ -51,7 +78,11 @@ use alloc::{
     collections::{btree_map, BTreeMap, BTreeSet},
     vec::Vec,
 };
-use core::mem;
+use core::{
+    cmp, mem,
+    ops::{Add, Sub},
+    time::Duration,
+};
 use hashbrown::{HashMap, HashSet};
 use parity_multiaddr::Multiaddr;
 use rand::{seq::IteratorRandom as _, RngCore as _, SeedableRng as _};
@@ -67,10 +98,23 @@ pub struct Config {
 
     /// Seed for the randomness used to decide how peers are chosen.
     pub randomness_seed: [u8; 32],
+
+    /// Duration during which an address isn't dialed after a first failed dialing attempt.
+    /// Doubled after each consecutive failure.
+    pub dial_backoff_base: Duration,
+
+    /// Maximum duration during which an address isn't dialed after a failed dialing attempt.
+    pub dial_backoff_max: Duration,
+
+    /// Reputation below which a node gets banned. Should be negative.
+    pub ban_threshold: i32,
+
+    /// Duration of a ban. See [`Config::ban_threshold`].
+    pub ban_duration: Duration,
 }
 
 /// See the [module-level documentation](self).
-pub struct Peerset<TPeer, TConn, TPending, TSub, TPendingSub> {
+pub struct Peerset<TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
     /// List of allocated overlay networks.
     overlay_networks: HashSet<OverlayNetworkId, RandomState>,
 
@@ -81,7 +125,7 @@ pub struct Peerset<TPeer, TConn, TPending, TSub, TPendingSub> {
     peer_ids: HashMap<PeerId, usize, RandomState>,
 
     /// List of all known peers and their data.
-    peers: slab::Slab<Peer<TPeer>>,
+    peers: slab::Slab<Peer<TNow, TPeer>>,
 
     /// Active and pending connections.
     connections: slab::Slab<Connection<TConn, TPending>>,
@@ -108,12 +152,120 @@ pub struct Peerset<TPeer, TConn, TPending, TSub, TPendingSub> {
     /// Container that holds tuples of `(connection_index, overlay_id, direction)`.
     connection_overlays:
         BTreeMap<(usize, OverlayNetworkId, SubstreamDirection), SubstreamState<TSub, TPendingSub>>,
+
+    /// See [`Config::dial_backoff_base`].
+    dial_backoff_base: Duration,
+
+    /// See [`Config::dial_backoff_max`].
+    dial_backoff_max: Duration,
+
+    /// See [`Config::ban_threshold`].
+    ban_threshold: i32,
+
+    /// See [`Config::ban_duration`].
+    ban_duration: Duration,
 }
 
-struct Peer<TPeer> {
+struct Peer<TNow, TPeer> {
     peer_id: PeerId,
     user_data: TPeer,
-    addresses: Vec<Multiaddr>,
+    addresses: Vec<Address<TNow>>,
+    /// Reputation of the node, as of [`Peer::reputation_updated`].
+    reputation: i32,
+    /// Moment when [`Peer::reputation`] has last been decayed. `None` if the reputation has
+    /// never been modified.
+    reputation_updated: Option<TNow>,
+    /// If `Some`, the node is banned until the given moment.
+    banned_until: Option<TNow>,
+}
+
+impl<TNow, TPeer> Peer<TNow, TPeer>
+where
+    TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+{
+    /// Returns the reputation of the node at the given moment, after decay.
+    fn reputation(&self, now: &TNow) -> i32 {
+        match &self.reputation_updated {
+            Some(updated) => decay_reputation(self.reputation, elapsed_secs(updated, now)),
+            None => self.reputation,
+        }
+    }
+
+    /// Applies the decay to [`Peer::reputation`] and updates [`Peer::reputation_updated`].
+    fn update_reputation(&mut self, now: &TNow) {
+        let updated = match &self.reputation_updated {
+            Some(updated) => updated.clone(),
+            None => {
+                self.reputation_updated = Some(now.clone());
+                return;
+            }
+        };
+
+        let secs = elapsed_secs(&updated, now);
+        self.reputation = decay_reputation(self.reputation, secs);
+        // Only whole seconds are accounted for, in order to not lose the fractional part of the
+        // elapsed time if this function is called frequently.
+        self.reputation_updated = Some(updated + Duration::from_secs(secs));
+    }
+}
+
+impl<TNow: Ord, TPeer> Peer<TNow, TPeer> {
+    /// Returns `true` if the node is banned at the given moment.
+    fn is_banned(&self, now: &TNow) -> bool {
+        self.banned_until
+            .as_ref()
+            .map_or(false, |until| *until > *now)
+    }
+}
+
+/// Returns the number of whole seconds between `earlier` and `now`. Returns 0 if `now` is
+/// before `earlier`.
+fn elapsed_secs<TNow>(earlier: &TNow, now: &TNow) -> u64
+where
+    TNow: Clone + Sub<TNow, Output = Duration> + Ord,
+{
+    if *now <= *earlier {
+        return 0;
+    }
+
+    (now.clone() - earlier.clone()).as_secs()
+}
+
+/// Applies `secs` seconds of decay to the given reputation value.
+fn decay_reputation(mut reputation: i32, secs: u64) -> i32 {
+    // Each second, the reputation gets closer to 0 by 2%, and by at least 1. After this many
+    // seconds, any `i32` has reached 0.
+    if secs >= 1200 {
+        return 0;
+    }
+
+    for _ in 0..secs {
+        if reputation == 0 {
+            break;
+        }
+
+        let diff = reputation / 50;
+        reputation -= if diff == 0 { reputation.signum() } else { diff };
+    }
+
+    reputation
+}
+
+struct Address<TNow> {
+    multiaddr: Multiaddr,
+    /// Number of dialing attempts that have failed since the last successful one.
+    num_consecutive_failures: u32,
+    /// If `Some`, this address shouldn't be dialed before the given moment.
+    backoff_until: Option<TNow>,
+}
+
+impl<TNow: Ord> Address<TNow> {
+    /// Returns `true` if the address isn't in a backoff period.
+    fn is_dialable(&self, now: &TNow) -> bool {
+        self.backoff_until
+            .as_ref()
+            .map_or(true, |until| *until <= *now)
+    }
 }
 
 struct Connection<TConn, TPending> {
@@ -155,7 +307,9 @@ enum SubstreamState<TSub, TPendingSub> {
     Poisoned,
 }
 
-impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending, TSub, TPendingSub> {
+impl<TNow, TPeer, TConn, TPending, TSub, TPendingSub>
+    Peerset<TNow, TPeer, TConn, TPending, TSub, TPendingSub>
+{
     /// Creates a [`Peerset`] with the given configuration.
     pub fn new(config: Config) -> Self {
         let mut rng = rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed);
@@ -186,6 +340,10 @@ impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending,
             overlay_peers: BTreeSet::new(),
             peers_overlays: BTreeSet::new(),
             connection_overlays: BTreeMap::new(),
+            dial_backoff_base: config.dial_backoff_base,
+            dial_backoff_max: config.dial_backoff_max,
+            ban_threshold: config.ban_threshold,
+            ban_duration: config.ban_duration,
         }
     }
 
@@ -251,7 +409,7 @@ impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending,
     pub fn random_connected_closed_node(
         &mut self,
         overlay_network_id: OverlayNetworkId,
-    ) -> Option<NodeMutKnown<TPeer, TConn, TPending, TSub, TPendingSub>> {
+    ) -> Option<NodeMutKnown<TNow, TPeer, TConn, TPending, TSub, TPendingSub>> {
         let peer_connections = &self.peer_connections;
         let connection_overlays = &self.connection_overlays;
         let connections = &self.connections;
@@ -296,17 +454,24 @@ impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending,
     ///
     /// - Peerset has no connection nor pending connection towards this node.
     /// - Node belongs to the given overlay network.
-    /// - Node has at least one known address.
+    /// - Node has at least one known address that isn't in a backoff period.
+    /// - Node isn't banned.
+    ///
+    /// If multiple nodes match, the node is chosen amongst the ones with the highest reputation.
     ///
     /// Returns `None` if no such node is available.
     pub fn random_not_connected(
         &mut self,
+        now: &TNow,
         overlay_network_id: OverlayNetworkId,
-    ) -> Option<NodeMutKnown<TPeer, TConn, TPending, TSub, TPendingSub>> {
+    ) -> Option<NodeMutKnown<TNow, TPeer, TConn, TPending, TSub, TPendingSub>>
+    where
+        TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+    {
         let peers = &self.peers;
         let peer_connections = &self.peer_connections;
 
-        let peer_index = self
+        let candidates = self
             .overlay_peers
             .range((overlay_network_id, 0)..=(overlay_network_id, usize::max_value()))
             .map(|(_, index)| *index)
@@ -319,13 +484,19 @@ impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending,
                     return false;
                 }
 
-                if peers.get(*peer_index).unwrap().addresses.is_empty() {
-                    return false;
-                }
-
-                // TODO: check pending too
-                true
+                // Note that pending connections are also found in `peer_connections`, and
+                // have therefore already been filtered out above.
+                let peer = peers.get(*peer_index).unwrap();
+                !peer.is_banned(now) && peer.addresses.iter().any(|addr| addr.is_dialable(now))
             })
+            .map(|peer_index| (peer_index, peers[peer_index].reputation(now)))
+            .collect::<Vec<_>>();
+
+        let best_reputation = candidates.iter().map(|(_, rep)| *rep).max()?;
+        let peer_index = candidates
+            .into_iter()
+            .filter(|(_, rep)| *rep == best_reputation)
+            .map(|(peer_index, _)| peer_index)
             .choose(&mut self.rng)?;
 
         Some(NodeMutKnown {
@@ -338,7 +509,7 @@ impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending,
     pub fn pending_mut(
         &mut self,
         id: ConnectionId,
-    ) -> Option<PendingMut<TPeer, TConn, TPending, TSub, TPendingSub>> {
+    ) -> Option<PendingMut<TNow, TPeer, TConn, TPending, TSub, TPendingSub>> {
         if self
             .connections
             .get(id.0)
@@ -354,7 +525,7 @@ impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending,
     pub fn connection_mut(
         &mut self,
         id: ConnectionId,
-    ) -> Option<ConnectionMut<TPeer, TConn, TPending, TSub, TPendingSub>> {
+    ) -> Option<ConnectionMut<TNow, TPeer, TConn, TPending, TSub, TPendingSub>> {
         if self
             .connections
             .get(id.0)
@@ -370,7 +541,7 @@ impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending,
     pub fn pending_or_connection_mut(
         &mut self,
         id: ConnectionId,
-    ) -> Option<PendingOrConnectionMut<TPeer, TConn, TPending, TSub, TPendingSub>> {
+    ) -> Option<PendingOrConnectionMut<TNow, TPeer, TConn, TPending, TSub, TPendingSub>> {
         if let Some(c) = self.connections.get(id.0) {
             match c.ty {
                 ConnectionTy::Connected { .. } => {
@@ -394,7 +565,7 @@ impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending,
     pub fn node_mut(
         &mut self,
         peer_id: PeerId,
-    ) -> NodeMut<TPeer, TConn, TPending, TSub, TPendingSub> {
+    ) -> NodeMut<TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
         if let Some(peer_index) = self.peer_ids.get(&peer_id).cloned() {
             NodeMut::Known(NodeMutKnown {
                 peerset: self,
@@ -410,11 +581,11 @@ impl<TPeer, TConn, TPending, TSub, TPendingSub> Peerset<TPeer, TConn, TPending,
 }
 
 /// Access to a connection in the [`Peerset`].
-pub enum PendingOrConnectionMut<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
+pub enum PendingOrConnectionMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
     /// Connection is in the pending state.
-    Pending(PendingMut<'a, TPeer, TConn, TPending, TSub, TPendingSub>),
+    Pending(PendingMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>),
     /// Connection is in the established state.
-    Connection(ConnectionMut<'a, TPeer, TConn, TPending, TSub, TPendingSub>),
+    Connection(ConnectionMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>),
 }
 
 /// Identifier for a connection in a [`Peerset`].
@@ -422,13 +593,13 @@ pub enum PendingOrConnectionMut<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
 pub struct ConnectionId(usize);
 
 /// Access to a connection in the [`Peerset`].
-pub struct ConnectionMut<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
-    peerset: &'a mut Peerset<TPeer, TConn, TPending, TSub, TPendingSub>,
+pub struct ConnectionMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
+    peerset: &'a mut Peerset<TNow, TPeer, TConn, TPending, TSub, TPendingSub>,
     id: ConnectionId,
 }
 
-impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
-    ConnectionMut<'a, TPeer, TConn, TPending, TSub, TPendingSub>
+impl<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
+    ConnectionMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
 {
     /// Returns the identifier of this connection.
     pub fn id(&self) -> ConnectionId {
@@ -692,13 +863,13 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
 }
 
 /// Access to a connection in the [`Peerset`].
-pub struct PendingMut<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
-    peerset: &'a mut Peerset<TPeer, TConn, TPending, TSub, TPendingSub>,
+pub struct PendingMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
+    peerset: &'a mut Peerset<TNow, TPeer, TConn, TPending, TSub, TPendingSub>,
     id: ConnectionId,
 }
 
-impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
-    PendingMut<'a, TPeer, TConn, TPending, TSub, TPendingSub>
+impl<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
+    PendingMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
 {
     /// [`PeerId`] the connection is trying to connect to.
     pub fn peer_id(&self) -> &PeerId {
@@ -719,12 +890,23 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
     pub fn into_established(
         self,
         map: impl FnOnce(TPending) -> TConn,
-    ) -> ConnectionMut<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
+    ) -> ConnectionMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
         let connec = self.peerset.connections.get_mut(self.id.0).unwrap();
-        let old_user_data = match mem::replace(&mut connec.ty, ConnectionTy::Poisoned) {
-            ConnectionTy::Pending { user_data, .. } => user_data,
+        let (old_user_data, target) = match mem::replace(&mut connec.ty, ConnectionTy::Poisoned) {
+            ConnectionTy::Pending { user_data, target } => (user_data, target),
             _ => unreachable!(),
         };
+
+        // The address is reachable. Reset its backoff.
+        if let Some(address) = self.peerset.peers[connec.peer_index]
+            .addresses
+            .iter_mut()
+            .find(|a| a.multiaddr == target)
+        {
+            address.num_consecutive_failures = 0;
+            address.backoff_until = None;
+        }
+
         let new_user_data = map(old_user_data);
         connec.ty = ConnectionTy::Connected {
             user_data: new_user_data,
@@ -764,6 +946,37 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
         self.remove_inner(true)
     }
 
+    /// Same as [`PendingMut::remove`], but additionally marks the dialing attempt as failed. The
+    /// target address will not be returned by [`NodeMutKnown::dialable_addresses`] until the
+    /// end of its backoff period.
+    ///
+    /// See the [module-level documentation](self) for more information about the backoff.
+    pub fn remove_and_backoff(self, now: &TNow) -> TPending
+    where
+        TNow: Clone + Add<Duration, Output = TNow>,
+    {
+        let peer_index = self.peerset.connections[self.id.0].peer_index;
+        let target = self.address().clone();
+
+        let backoff_base = self.peerset.dial_backoff_base;
+        let backoff_max = self.peerset.dial_backoff_max;
+        if let Some(address) = self.peerset.peers[peer_index]
+            .addresses
+            .iter_mut()
+            .find(|a| a.multiaddr == target)
+        {
+            // The exponent is capped in order to avoid overflows.
+            let multiplier = 1u32 << cmp::min(address.num_consecutive_failures, 16);
+            let backoff = backoff_base
+                .checked_mul(multiplier)
+                .map_or(backoff_max, |b| cmp::min(b, backoff_max));
+            address.num_consecutive_failures = address.num_consecutive_failures.saturating_add(1);
+            address.backoff_until = Some(now.clone() + backoff);
+        }
+
+        self.remove_inner(false)
+    }
+
     fn remove_inner(self, purge_addr: bool) -> TPending {
         let connection = self.peerset.connections.remove(self.id.0);
         let _was_in = self
@@ -798,7 +1011,7 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
                 .get_mut(connection.peer_index)
                 .unwrap()
                 .addresses;
-            let pos = addrs.iter().position(|a| *a == address).unwrap();
+            let pos = addrs.iter().position(|a| a.multiaddr == address).unwrap();
             addrs.remove(pos);
             // TODO: remove peer if addrs is empty?
         }
@@ -808,22 +1021,22 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
 }
 
 /// Access to a node in the [`Peerset`].
-pub enum NodeMut<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
+pub enum NodeMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
     /// Node is already known to the data structure.
-    Known(NodeMutKnown<'a, TPeer, TConn, TPending, TSub, TPendingSub>),
+    Known(NodeMutKnown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>),
     /// Node isn't known by the data structure.
-    Unknown(NodeMutUnknown<'a, TPeer, TConn, TPending, TSub, TPendingSub>),
+    Unknown(NodeMutUnknown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>),
 }
 
-impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
-    NodeMut<'a, TPeer, TConn, TPending, TSub, TPendingSub>
+impl<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
+    NodeMut<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
 {
     /// If [`NodeMut::Unknown`], calls the passed closure in order to obtain a user data and
     /// inserts the node in the data structure.
     pub fn or_insert_with(
         self,
         insert: impl FnOnce() -> TPeer,
-    ) -> NodeMutKnown<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
+    ) -> NodeMutKnown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
         match self {
             NodeMut::Known(k) => k,
             NodeMut::Unknown(k) => k.insert(insert()),
@@ -831,7 +1044,7 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
     }
 
     /// Shortcut for `or_insert_with(Default::default)`.
-    pub fn or_default(self) -> NodeMutKnown<'a, TPeer, TConn, TPending, TSub, TPendingSub>
+    pub fn or_default(self) -> NodeMutKnown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
     where
         TPeer: Default,
     {
@@ -839,7 +1052,9 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
     }
 
     /// Shortcut method. If [`NodeMut::Known`], returns a `Some` containing it.
-    pub fn into_known(self) -> Option<NodeMutKnown<'a, TPeer, TConn, TPending, TSub, TPendingSub>> {
+    pub fn into_known(
+        self,
+    ) -> Option<NodeMutKnown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>> {
         match self {
             NodeMut::Known(k) => Some(k),
             NodeMut::Unknown(_) => None,
@@ -848,28 +1063,35 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
 }
 
 /// Access to a node is already known to the data structure.
-pub struct NodeMutKnown<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
-    peerset: &'a mut Peerset<TPeer, TConn, TPending, TSub, TPendingSub>,
+pub struct NodeMutKnown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
+    peerset: &'a mut Peerset<TNow, TPeer, TConn, TPending, TSub, TPendingSub>,
     peer_index: usize,
 }
 
-impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
-    NodeMutKnown<'a, TPeer, TConn, TPending, TSub, TPendingSub>
+impl<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
+    NodeMutKnown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
 {
     /// Returns the network identity of the node.
     pub fn peer_id(&self) -> &PeerId {
         &self.peerset.peers[self.peer_index].peer_id
     }
 
-    /// Adds in the data structure an inbound connection with this node.
-    pub fn add_inbound_connection(&mut self, connection: TConn) -> ConnectionId {
-        let index = self.peerset.connections.insert(Connection {
+    /// Adds in the data structure an inbound connection with this node. The user data of the
+    /// connection is built by `connection`, which is passed the identifier of the new connection.
+    pub fn add_inbound_connection(
+        &mut self,
+        connection: impl FnOnce(ConnectionId) -> TConn,
+    ) -> ConnectionId {
+        let entry = self.peerset.connections.vacant_entry();
+        let index = entry.key();
+        entry.insert(Connection {
             peer_index: self.peer_index,
             ty: ConnectionTy::Connected {
-                user_data: connection,
+                user_data: connection(ConnectionId(index)),
                 inbound: true,
             },
         });
+        self.peerset.num_established_connections += 1;
 
         debug_assert_eq!(
             self.peerset
@@ -968,11 +1190,15 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
     /// Has no effect if this address is already in the list.
     pub fn add_known_address(&mut self, address: Multiaddr) {
         let list = &mut self.peerset.peers[self.peer_index].addresses;
-        if list.iter().any(|a| *a == address) {
+        if list.iter().any(|a| a.multiaddr == address) {
             return;
         }
 
-        list.push(address);
+        list.push(Address {
+            multiaddr: address,
+            num_consecutive_failures: 0,
+            backoff_until: None,
+        });
     }
 
     /// Removes an address from the list of known addresses.
@@ -982,7 +1208,7 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
     // TODO: must not remove if pending connection to this address
     pub fn remove_known_address(&mut self, address: &Multiaddr) -> Result<(), ()> {
         let addresses = &mut self.peerset.peers[self.peer_index].addresses;
-        if let Some(pos) = addresses.iter().position(|a| a == address) {
+        if let Some(pos) = addresses.iter().position(|a| a.multiaddr == *address) {
             addresses.remove(pos);
             Ok(())
         } else {
@@ -992,7 +1218,10 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
 
     /// Returns an iterator to the list of addresses known for this peer.
     pub fn known_addresses<'b>(&'b self) -> impl ExactSizeIterator<Item = &'b Multiaddr> + 'b {
-        self.peerset.peers[self.peer_index].addresses.iter()
+        self.peerset.peers[self.peer_index]
+            .addresses
+            .iter()
+            .map(|a| &a.multiaddr)
     }
 
     /// Returns an iterator to the list of addresses known for this peer.
@@ -1009,6 +1238,68 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
         })
     }
 
+    /// Returns an iterator to the list of addresses known for this peer that can be dialed.
+    ///
+    /// Filters out addresses to which there is an ongoing connection attempt, and addresses
+    /// that are in a backoff period. See the [module-level documentation](self).
+    pub fn dialable_addresses<'b>(
+        &'b self,
+        now: &'b TNow,
+    ) -> impl Iterator<Item = &'b Multiaddr> + 'b
+    where
+        TNow: Ord,
+    {
+        self.peerset.peers[self.peer_index]
+            .addresses
+            .iter()
+            .filter(move |addr| addr.is_dialable(now))
+            .map(|addr| &addr.multiaddr)
+            .filter(move |addr| self.known_addresses_no_pending().any(|a| a == *addr))
+    }
+
+    /// Returns the reputation of the node at the given moment.
+    ///
+    /// See the [module-level documentation](self) for more information about the reputation.
+    pub fn reputation(&self, now: &TNow) -> i32
+    where
+        TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+    {
+        self.peerset.peers[self.peer_index].reputation(now)
+    }
+
+    /// Adds `delta` to the reputation of the node.
+    ///
+    /// If the reputation falls below [`Config::ban_threshold`], the node is banned for
+    /// [`Config::ban_duration`] starting from `now`. Returns `true` if the node is banned after
+    /// this call, in which case its existing connections should be closed.
+    ///
+    /// See the [module-level documentation](self) for more information about the reputation.
+    pub fn change_reputation(&mut self, now: &TNow, delta: i32) -> bool
+    where
+        TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+    {
+        let ban_threshold = self.peerset.ban_threshold;
+        let ban_duration = self.peerset.ban_duration;
+
+        let peer = &mut self.peerset.peers[self.peer_index];
+        peer.update_reputation(now);
+        peer.reputation = peer.reputation.saturating_add(delta);
+
+        if peer.reputation < ban_threshold {
+            peer.banned_until = Some(now.clone() + ban_duration);
+        }
+
+        peer.is_banned(now)
+    }
+
+    /// Returns `true` if the node is banned at the given moment.
+    pub fn is_banned(&self, now: &TNow) -> bool
+    where
+        TNow: Ord,
+    {
+        self.peerset.peers[self.peer_index].is_banned(now)
+    }
+
     /// Adds the node to an overlay network.
     ///
     /// Has no effect if this node is already in this overlay network.
@@ -1061,13 +1352,13 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
 }
 
 /// Access to a node that isn't known to the data structure.
-pub struct NodeMutUnknown<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
-    peerset: &'a mut Peerset<TPeer, TConn, TPending, TSub, TPendingSub>,
+pub struct NodeMutUnknown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
+    peerset: &'a mut Peerset<TNow, TPeer, TConn, TPending, TSub, TPendingSub>,
     peer_id: PeerId,
 }
 
-impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
-    NodeMutUnknown<'a, TPeer, TConn, TPending, TSub, TPendingSub>
+impl<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
+    NodeMutUnknown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub>
 {
     /// Returns the [`PeerId`] of that node.
     pub fn peer_id(&self) -> &PeerId {
@@ -1078,11 +1369,14 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
     pub fn insert(
         self,
         user_data: TPeer,
-    ) -> NodeMutKnown<'a, TPeer, TConn, TPending, TSub, TPendingSub> {
+    ) -> NodeMutKnown<'a, TNow, TPeer, TConn, TPending, TSub, TPendingSub> {
         let peer_index = self.peerset.peers.insert(Peer {
             peer_id: self.peer_id.clone(),
             user_data,
             addresses: Vec::new(),
+            reputation: 0,
+            reputation_updated: None,
+            banned_until: None,
         });
 
         let _was_in = self.peerset.peer_ids.insert(self.peer_id, peer_index);
@@ -1097,9 +1391,168 @@ impl<'a, TPeer, TConn, TPending, TSub, TPendingSub>
 
 #[cfg(test)]
 mod tests {
+    use crate::libp2p::peer_id::{self, PeerId};
+
+    use core::time::Duration;
+    use parity_multiaddr::Multiaddr;
+
     #[test]
     fn substream_direction_order() {
         // A lot of code above assumes that `In` < `Out`.
         assert!(super::SubstreamDirection::In < super::SubstreamDirection::Out);
     }
+
+    #[test]
+    fn dial_backoff() {
+        let mut peerset = super::Peerset::<Duration, (), (), (), (), ()>::new(super::Config {
+            peers_capacity: 1,
+            overlay_networks_capacity: 1,
+            randomness_seed: [0; 32],
+            dial_backoff_base: Duration::from_secs(1),
+            dial_backoff_max: Duration::from_secs(3),
+            ban_threshold: -1000,
+            ban_duration: Duration::from_secs(10),
+        });
+
+        let overlay_network_id = peerset.add_overlay_network();
+        let peer_id = PeerId::from_public_key(&peer_id::PublicKey::Ed25519([0; 32]));
+        let address = "/ip4/1.2.3.4/tcp/30333".parse::<Multiaddr>().unwrap();
+
+        let mut node = peerset.node_mut(peer_id).or_insert_with(|| ());
+        node.add_to_overlay(overlay_network_id);
+        node.add_known_address(address.clone());
+
+        // Fails to connect three times in a row. The backoff is successively 1, 2 and 3 (capped)
+        // seconds.
+        let mut now = Duration::from_secs(0);
+        for backoff in [1, 2, 3].iter().copied() {
+            let mut node = peerset
+                .random_not_connected(&now, overlay_network_id)
+                .unwrap();
+            assert_eq!(node.dialable_addresses(&now).count(), 1);
+            let id = node.add_outbound_attempt(address.clone(), ());
+            assert!(peerset
+                .random_not_connected(&now, overlay_network_id)
+                .is_none());
+            peerset.pending_mut(id).unwrap().remove_and_backoff(&now);
+
+            now += Duration::from_secs(backoff) - Duration::from_millis(1);
+            assert!(peerset
+                .random_not_connected(&now, overlay_network_id)
+                .is_none());
+            now += Duration::from_millis(1);
+        }
+
+        // A successful connection resets the backoff.
+        let id = peerset
+            .random_not_connected(&now, overlay_network_id)
+            .unwrap()
+            .add_outbound_attempt(address.clone(), ());
+        let connection_id = peerset
+            .pending_mut(id)
+            .unwrap()
+            .into_established(|()| ())
+            .id();
+        peerset.connection_mut(connection_id).unwrap().remove();
+        let id = peerset
+            .random_not_connected(&now, overlay_network_id)
+            .unwrap()
+            .add_outbound_attempt(address, ());
+        peerset.pending_mut(id).unwrap().remove_and_backoff(&now);
+        now += Duration::from_secs(1);
+        assert!(peerset
+            .random_not_connected(&now, overlay_network_id)
+            .is_some());
+    }
+
+    #[test]
+    fn dial_backoff_overflow() {
+        let mut peerset = super::Peerset::<Duration, (), (), (), (), ()>::new(super::Config {
+            peers_capacity: 1,
+            overlay_networks_capacity: 1,
+            randomness_seed: [0; 32],
+            dial_backoff_base: Duration::from_secs(u64::max_value() / 2 + 1),
+            dial_backoff_max: Duration::from_secs(3),
+            ban_threshold: -1000,
+            ban_duration: Duration::from_secs(10),
+        });
+
+        let overlay_network_id = peerset.add_overlay_network();
+        let peer_id = PeerId::from_public_key(&peer_id::PublicKey::Ed25519([0; 32]));
+        let address = "/ip4/1.2.3.4/tcp/30333".parse::<Multiaddr>().unwrap();
+
+        let mut node = peerset.node_mut(peer_id).or_insert_with(|| ());
+        node.add_to_overlay(overlay_network_id);
+        node.add_known_address(address.clone());
+
+        // Doubling the backoff base overflows after the first failure. The backoff is capped
+        // instead.
+        let mut now = Duration::from_secs(0);
+        for _ in 0..3 {
+            let id = peerset
+                .random_not_connected(&now, overlay_network_id)
+                .unwrap()
+                .add_outbound_attempt(address.clone(), ());
+            peerset.pending_mut(id).unwrap().remove_and_backoff(&now);
+            now += Duration::from_secs(3);
+        }
+    }
+
+    #[test]
+    fn reputation_and_ban() {
+        let mut peerset = super::Peerset::<Duration, (), (), (), (), ()>::new(super::Config {
+            peers_capacity: 2,
+            overlay_networks_capacity: 1,
+            randomness_seed: [0; 32],
+            dial_backoff_base: Duration::from_secs(1),
+            dial_backoff_max: Duration::from_secs(3),
+            ban_threshold: -1000,
+            ban_duration: Duration::from_secs(10),
+        });
+
+        let overlay_network_id = peerset.add_overlay_network();
+        let peer_a = PeerId::from_public_key(&peer_id::PublicKey::Ed25519([0; 32]));
+        let peer_b = PeerId::from_public_key(&peer_id::PublicKey::Ed25519([1; 32]));
+        for peer_id in [peer_a.clone(), peer_b.clone()].iter() {
+            let mut node = peerset.node_mut(peer_id.clone()).or_insert_with(|| ());
+            node.add_to_overlay(overlay_network_id);
+            node.add_known_address("/ip4/1.2.3.4/tcp/30333".parse::<Multiaddr>().unwrap());
+        }
+
+        // Nodes with a better reputation are always chosen first.
+        let mut now = Duration::from_secs(0);
+        let mut node_a = peerset.node_mut(peer_a.clone()).into_known().unwrap();
+        assert!(!node_a.change_reputation(&now, -100));
+        assert_eq!(node_a.reputation(&now), -100);
+        for _ in 0..16 {
+            let node = peerset
+                .random_not_connected(&now, overlay_network_id)
+                .unwrap();
+            assert_eq!(*node.peer_id(), peer_b);
+        }
+
+        // The reputation decays over time.
+        now += Duration::from_secs(1);
+        let node_a = peerset.node_mut(peer_a.clone()).into_known().unwrap();
+        assert_eq!(node_a.reputation(&now), -98);
+
+        // Banned nodes are never chosen, even if their reputation is the highest.
+        let mut node_b = peerset.node_mut(peer_b.clone()).into_known().unwrap();
+        assert!(node_b.change_reputation(&now, -2000));
+        assert!(node_b.is_banned(&now));
+        for _ in 0..16 {
+            let node = peerset
+                .random_not_connected(&now, overlay_network_id)
+                .unwrap();
+            assert_eq!(*node.peer_id(), peer_a);
+        }
+
+        // The ban expires after the configured duration.
+        now += Duration::from_secs(10);
+        let node_b = peerset.node_mut(peer_b.clone()).into_known().unwrap();
+        assert!(!node_b.is_banned(&now));
+        assert_eq!(node_b.reputation(&now), -1638);
+        assert_eq!(super::decay_reputation(i32::min_value(), 1199), 0);
+        assert_eq!(super::decay_reputation(i32::max_value(), 1199), 0);
+    }
 }
@@ -33,7 +33,9 @@ mod call_proof;
 mod grandpa;
 mod grandpa_warp_sync;
 mod identify;
+mod state_request;
 mod storage_proof;
+mod transactions;
 
 pub use self::block_announces::*;
 pub use self::block_request::*;
@@ -41,7 +43,9 @@ pub use self::call_proof::*;
 pub use self::grandpa::*;
 pub use self::grandpa_warp_sync::*;
 pub use self::identify::*;
+pub use self::state_request::*;
 pub use self::storage_proof::*;
+pub use self::transactions::*;
 
 // Protobuf schemas are gathered here.
 mod schema {
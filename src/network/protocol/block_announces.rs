@@ -75,7 +75,9 @@ pub struct BlockAnnounceRef<'a> {
 pub fn encode_block_announce<'a>(
     announce: BlockAnnounceRef<'a>,
 ) -> impl Iterator<Item = impl AsRef<[u8]> + 'a> + 'a {
-    let is_best = if announce.is_best { [1u8] } else { [0u8] };
+    // The last byte is the SCALE-compact encoding of the length of the unused trailing data
+    // (see the `TODO` in `BlockAnnounceRef`), which is always empty.
+    let is_best = if announce.is_best { [1u8, 0] } else { [0u8, 0] };
     announce
         .header
         .scale_encoding()
@@ -36,10 +36,105 @@ impl<'a> GrandpaNotificationRef<'a> {
     /// encoding of that object.
     pub fn scale_encoding(&self) -> impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone {
         match self {
-            GrandpaNotificationRef::Neighbor(n) => {
-                iter::once(either::Left(&[2u8])).chain(n.scale_encoding().map(either::Right))
+            GrandpaNotificationRef::Neighbor(n) => either::Left(
+                iter::once(either::Left(either::Left(&[2u8])))
+                    .chain(n.scale_encoding().map(|b| either::Left(either::Right(b)))),
+            ),
+            GrandpaNotificationRef::Vote(vote) => {
+                let mut out = Vec::with_capacity(1 + 8 + 8 + 1 + 32 + 4 + 64 + 32);
+                out.push(0u8);
+                out.extend_from_slice(&vote.round_number.to_le_bytes());
+                out.extend_from_slice(&vote.set_id.to_le_bytes());
+                let (kind, target_hash, target_number) = match &vote.message {
+                    MessageRef::Prevote(m) => (0u8, m.target_hash, m.target_number),
+                    MessageRef::Precommit(m) => (1u8, m.target_hash, m.target_number),
+                    MessageRef::PrimaryPropose(m) => (2u8, m.target_hash, m.target_number),
+                };
+                out.push(kind);
+                out.extend_from_slice(target_hash);
+                out.extend_from_slice(&target_number.to_le_bytes());
+                out.extend_from_slice(vote.signature);
+                out.extend_from_slice(vote.authority_public_key);
+                debug_assert_eq!(out.len(), out.capacity());
+                either::Right(iter::once(either::Right(either::Left(out))))
+            }
+            GrandpaNotificationRef::Commit(commit) => {
+                let mut out = Vec::with_capacity(
+                    1 + 8
+                        + 8
+                        + 32
+                        + 4
+                        + 5
+                        + commit.message.precommits.len() * (32 + 4)
+                        + 5
+                        + commit.message.auth_data.len() * (64 + 32),
+                );
+                out.push(1u8);
+                out.extend_from_slice(&commit.round_number.to_le_bytes());
+                out.extend_from_slice(&commit.set_id.to_le_bytes());
+                out.extend_from_slice(commit.message.target_hash);
+                out.extend_from_slice(&commit.message.target_number.to_le_bytes());
+                out.extend_from_slice(
+                    crate::util::encode_scale_compact_usize(commit.message.precommits.len())
+                        .as_ref(),
+                );
+                for precommit in &commit.message.precommits {
+                    out.extend_from_slice(precommit.target_hash);
+                    out.extend_from_slice(&precommit.target_number.to_le_bytes());
+                }
+                out.extend_from_slice(
+                    crate::util::encode_scale_compact_usize(commit.message.auth_data.len())
+                        .as_ref(),
+                );
+                for (signature, public_key) in &commit.message.auth_data {
+                    out.extend_from_slice(*signature);
+                    out.extend_from_slice(*public_key);
+                }
+                either::Right(iter::once(either::Right(either::Left(out))))
+            }
+            GrandpaNotificationRef::CatchUpRequest(request) => {
+                let mut out = [0; 1 + 8 + 8];
+                out[0] = 3;
+                out[1..9].copy_from_slice(&request.round_number.to_le_bytes());
+                out[9..].copy_from_slice(&request.set_id.to_le_bytes());
+                either::Right(iter::once(either::Right(either::Right(out))))
+            }
+            GrandpaNotificationRef::CatchUp(catch_up) => {
+                let mut out = Vec::with_capacity(
+                    1 + 8
+                        + 8
+                        + 5
+                        + catch_up.prevotes.len() * (32 + 4 + 64 + 32)
+                        + 5
+                        + catch_up.precommits.len() * (32 + 4 + 64 + 32)
+                        + 32
+                        + 4,
+                );
+                out.push(4u8);
+                out.extend_from_slice(&catch_up.set_id.to_le_bytes());
+                out.extend_from_slice(&catch_up.round_number.to_le_bytes());
+                out.extend_from_slice(
+                    crate::util::encode_scale_compact_usize(catch_up.prevotes.len()).as_ref(),
+                );
+                for prevote in &catch_up.prevotes {
+                    out.extend_from_slice(prevote.target_hash);
+                    out.extend_from_slice(&prevote.target_number.to_le_bytes());
+                    out.extend_from_slice(prevote.signature);
+                    out.extend_from_slice(prevote.authority_public_key);
+                }
+                out.extend_from_slice(
+                    crate::util::encode_scale_compact_usize(catch_up.precommits.len()).as_ref(),
+                );
+                for precommit in &catch_up.precommits {
+                    out.extend_from_slice(precommit.target_hash);
+                    out.extend_from_slice(&precommit.target_number.to_le_bytes());
+                    out.extend_from_slice(precommit.signature);
+                    out.extend_from_slice(precommit.authority_public_key);
+                }
+                out.extend_from_slice(catch_up.base_hash);
+                out.extend_from_slice(&catch_up.base_number.to_le_bytes());
+                either::Right(iter::once(either::Right(either::Left(out))))
             }
-            _ => todo!(),
         }
     }
 }
@@ -53,6 +148,30 @@ pub struct VoteMessageRef<'a> {
     pub authority_public_key: &'a [u8; 32],
 }
 
+impl<'a> VoteMessageRef<'a> {
+    /// Returns `true` if [`VoteMessageRef::signature`] is a valid signature of the message
+    /// made by [`VoteMessageRef::authority_public_key`].
+    ///
+    /// > **Note**: This method doesn't check whether the public key belongs to an authority.
+    pub fn is_signature_valid(&self) -> bool {
+        let (kind, target_hash, target_number) = match &self.message {
+            MessageRef::Prevote(m) => (0, m.target_hash, m.target_number),
+            MessageRef::Precommit(m) => (1, m.target_hash, m.target_number),
+            MessageRef::PrimaryPropose(m) => (2, m.target_hash, m.target_number),
+        };
+
+        is_signature_valid(
+            kind,
+            target_hash,
+            target_number,
+            self.round_number,
+            self.set_id,
+            self.signature,
+            self.authority_public_key,
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MessageRef<'a> {
     Prevote(UnsignedPrevoteRef<'a>),
@@ -146,6 +265,76 @@ pub struct PrevoteRef<'a> {
     pub authority_public_key: &'a [u8; 32],
 }
 
+impl<'a> CatchUpRef<'a> {
+    /// Returns `true` if the signatures of all the prevotes and precommits are valid.
+    ///
+    /// > **Note**: This method doesn't check whether the public keys belong to authorities.
+    pub fn are_signatures_valid(&self) -> bool {
+        self.prevotes.iter().all(|prevote| {
+            is_signature_valid(
+                0,
+                prevote.target_hash,
+                prevote.target_number,
+                self.round_number,
+                self.set_id,
+                prevote.signature,
+                prevote.authority_public_key,
+            )
+        }) && self.precommits.iter().all(|precommit| {
+            is_signature_valid(
+                1,
+                precommit.target_hash,
+                precommit.target_number,
+                self.round_number,
+                self.set_id,
+                precommit.signature,
+                precommit.authority_public_key,
+            )
+        })
+    }
+}
+
+/// Checks the ed25519 signature of a GrandPa vote. `kind` is `0` for prevotes, `1` for
+/// precommits, and `2` for primary proposals.
+fn is_signature_valid(
+    kind: u8,
+    target_hash: &[u8; 32],
+    target_number: u32,
+    round_number: u64,
+    set_id: u64,
+    signature: &[u8; 64],
+    public_key: &[u8; 32],
+) -> bool {
+    let public_key = match ed25519_zebra::VerificationKey::try_from(*public_key) {
+        Ok(pk) => pk,
+        Err(_) => return false,
+    };
+
+    let msg = vote_signed_message(kind, target_hash, target_number, round_number, set_id);
+
+    public_key
+        .verify(&ed25519_zebra::Signature::from(*signature), &msg)
+        .is_ok()
+}
+
+/// Builds the message that a GrandPa authority signs when emitting a vote. `kind` is `0` for
+/// prevotes, `1` for precommits, and `2` for primary proposals.
+pub fn vote_signed_message(
+    kind: u8,
+    target_hash: &[u8; 32],
+    target_number: u32,
+    round_number: u64,
+    set_id: u64,
+) -> [u8; 1 + 32 + 4 + 8 + 8] {
+    let mut msg = [0; 1 + 32 + 4 + 8 + 8];
+    msg[0] = kind;
+    msg[1..33].copy_from_slice(target_hash);
+    msg[33..37].copy_from_slice(&target_number.to_le_bytes());
+    msg[37..45].copy_from_slice(&round_number.to_le_bytes());
+    msg[45..].copy_from_slice(&set_id.to_le_bytes());
+    msg
+}
+
 /// Attempt to decode the given SCALE-encoded Grandpa notification.
 pub fn decode_grandpa_notification(
     scale_encoded: &[u8],
@@ -446,6 +635,45 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn vote_encode_decode() {
+        let vote = super::GrandpaNotificationRef::Vote(super::VoteMessageRef {
+            round_number: 12,
+            set_id: 3,
+            message: super::MessageRef::Precommit(super::UnsignedPrecommitRef {
+                target_hash: &[5; 32],
+                target_number: 1000,
+            }),
+            signature: &[6; 64],
+            authority_public_key: &[7; 32],
+        });
+
+        let encoded = vote.scale_encoding().fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert_eq!(super::decode_grandpa_notification(&encoded).unwrap(), vote);
+    }
+
+    #[test]
+    fn catch_up_request_encode_decode() {
+        let request = super::GrandpaNotificationRef::CatchUpRequest(super::CatchUpRequest {
+            round_number: 123456,
+            set_id: 8,
+        });
+
+        let encoded = request.scale_encoding().fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert_eq!(
+            super::decode_grandpa_notification(&encoded).unwrap(),
+            request
+        );
+    }
+
     #[test]
     fn basic_decode_commit() {
         let actual = super::decode_grandpa_notification(&[
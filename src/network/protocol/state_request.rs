@@ -0,0 +1,116 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::{schema, ProtobufDecodeError};
+
+use alloc::vec::Vec;
+use core::{convert::TryFrom as _, iter};
+use prost::Message as _;
+
+/// Description of a state request that can be sent to a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateRequestConfig<TStartIter> {
+    /// Hash of the block to request the storage of.
+    pub block_hash: [u8; 32],
+    /// Path of the key to start the enumeration from, excluded.
+    ///
+    /// Empty in order to start from the beginning of the storage. Contains one key in order to
+    /// continue enumerating the main trie, or two keys (the key of the child trie within the
+    /// main trie followed by the key within the child trie) in order to continue enumerating a
+    /// child trie.
+    pub start: TStartIter,
+}
+
+/// Builds the bytes corresponding to a state request.
+///
+/// The request always asks for the raw keys and values rather than for a proof.
+pub fn build_state_request(
+    config: StateRequestConfig<impl Iterator<Item = impl AsRef<[u8]>>>,
+) -> impl Iterator<Item = impl AsRef<[u8]>> {
+    // Note: while the API of this function allows for a zero-cost implementation, the protobuf
+    // library doesn't permit to avoid allocations.
+
+    let request = schema::StateRequest {
+        block: config.block_hash.to_vec(),
+        start: config.start.map(|k| k.as_ref().to_vec()).collect(),
+        no_proof: true,
+    };
+
+    let request_bytes = {
+        let mut buf = Vec::with_capacity(request.encoded_len());
+        request.encode(&mut buf).unwrap();
+        buf
+    };
+
+    iter::once(request_bytes)
+}
+
+/// Decodes a response to a state request.
+// TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
+pub fn decode_state_response(
+    response_bytes: &[u8],
+) -> Result<Vec<StateResponseEntry>, DecodeStateResponseError> {
+    let response = schema::StateResponse::decode(response_bytes)
+        .map_err(ProtobufDecodeError)
+        .map_err(DecodeStateResponseError::ProtobufDecode)?;
+
+    response
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let state_root = if entry.state_root.is_empty() {
+                None
+            } else {
+                Some(
+                    <[u8; 32]>::try_from(&entry.state_root[..])
+                        .map_err(|_| DecodeStateResponseError::InvalidStateRootLength)?,
+                )
+            };
+
+            Ok(StateResponseEntry {
+                state_root,
+                entries: entry
+                    .entries
+                    .into_iter()
+                    .map(|e| (e.key, e.value))
+                    .collect(),
+                complete: entry.complete,
+            })
+        })
+        .collect()
+}
+
+/// Content of a trie found in a state response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateResponseEntry {
+    /// Root of the child trie whose content is found in [`StateResponseEntry::entries`], or
+    /// `None` for the main trie.
+    pub state_root: Option<[u8; 32]>,
+    /// List of keys and values of the trie, in lexicographic order.
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// `true` if [`StateResponseEntry::entries`] contains the last key of the trie.
+    pub complete: bool,
+}
+
+/// Error potentially returned by [`decode_state_response`].
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeStateResponseError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode(ProtobufDecodeError),
+    /// State root of a child trie isn't 32 bytes.
+    InvalidStateRootLength,
+}
@@ -55,6 +55,45 @@ pub fn build_storage_proof_request(
     iter::once(request_bytes)
 }
 
+/// Description of a child trie storage proof request that can be sent to a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChildStorageProofRequestConfig<TChildTrie, TKeysIter> {
+    /// Hash of the block to request the storage of.
+    pub block_hash: [u8; 32],
+    /// Key of the child trie within the main trie, including its `:child_storage:` prefix.
+    pub child_trie: TChildTrie,
+    /// List of storage keys within the child trie to query.
+    pub keys: TKeysIter,
+}
+
+/// Builds the bytes corresponding to a child trie storage proof request.
+///
+/// The response to this request can be decoded with [`decode_storage_proof_response`].
+pub fn build_child_storage_proof_request(
+    config: ChildStorageProofRequestConfig<
+        impl AsRef<[u8]>,
+        impl Iterator<Item = impl AsRef<[u8]>>,
+    >,
+) -> impl Iterator<Item = impl AsRef<[u8]>> {
+    let request = schema::Request {
+        request: Some(schema::request::Request::RemoteReadChildRequest(
+            schema::RemoteReadChildRequest {
+                block: config.block_hash.to_vec(),
+                storage_key: config.child_trie.as_ref().to_vec(),
+                keys: config.keys.map(|k| k.as_ref().to_vec()).collect(),
+            },
+        )),
+    };
+
+    let request_bytes = {
+        let mut buf = Vec::with_capacity(request.encoded_len());
+        request.encode(&mut buf).unwrap();
+        buf
+    };
+
+    iter::once(request_bytes)
+}
+
 /// Decodes a response to a storage proof request.
 // TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
 pub fn decode_storage_proof_response(
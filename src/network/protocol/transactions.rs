@@ -0,0 +1,59 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use alloc::vec::Vec;
+
+/// Decodes a transactions notification.
+///
+/// A transactions notification consists in a SCALE-encoded list of SCALE-encoded extrinsics.
+/// This function returns the list of extrinsics, each without its SCALE-compact length prefix.
+pub fn decode_transactions(bytes: &[u8]) -> Result<Vec<&[u8]>, DecodeTransactionsError> {
+    // Note that the number of elements isn't used to pre-allocate the list, as it is
+    // untrusted.
+    nom::combinator::all_consuming(nom::combinator::map(
+        nom::combinator::verify(
+            nom::sequence::pair(
+                crate::util::nom_scale_compact_usize,
+                nom::multi::many0(nom::multi::length_data(
+                    crate::util::nom_scale_compact_usize,
+                )),
+            ),
+            |(num_elems, list): &(usize, Vec<&[u8]>)| *num_elems == list.len(),
+        ),
+        |(_, list)| list,
+    ))(bytes)
+    .map(|(_, list)| list)
+    .map_err(DecodeTransactionsError)
+}
+
+/// Error potentially returned by [`decode_transactions`].
+#[derive(Debug, derive_more::Display)]
+pub struct DecodeTransactionsError<'a>(nom::Err<nom::error::Error<&'a [u8]>>);
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode_basic() {
+        let decoded = super::decode_transactions(&[8, 8, 1, 2, 4, 3]).unwrap();
+        assert_eq!(decoded, vec![&[1, 2][..], &[3][..]]);
+    }
+
+    #[test]
+    fn decode_too_short() {
+        assert!(super::decode_transactions(&[8, 8, 1, 2]).is_err());
+    }
+}
@@ -15,24 +15,29 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::header;
 use crate::libp2p::{
     self, connection, discovery::kademlia, multiaddr, peer_id, PeerId, QueueNotificationError,
 };
 use crate::network::protocol;
 use crate::util;
 
+use ahash::RandomState;
 use alloc::{
     format,
     string::{String, ToString as _},
     vec::Vec,
 };
 use core::{
+    convert::TryFrom as _,
     fmt, iter,
-    num::NonZeroUsize,
+    num::{NonZeroU32, NonZeroUsize},
     ops::{Add, Sub},
     time::Duration,
 };
 use futures::{channel::mpsc, lock::Mutex, prelude::*};
+use hashbrown::HashMap;
+use rand::{RngCore as _, SeedableRng as _};
 
 /// Configuration for a [`ChainNetwork`].
 pub struct Config<TPeer> {
@@ -77,6 +82,21 @@ pub struct Config<TPeer> {
     /// This value is important if [`ChainNetwork::next_event`] is called at a slower than the
     /// calls to [`ChainNetwork::read_write`] generate events.
     pub pending_api_events_buffer_size: NonZeroUsize,
+
+    /// Duration during which an address isn't dialed again after a first failed dialing
+    /// attempt. Doubled after each consecutive failure.
+    pub dial_backoff_base: Duration,
+
+    /// Maximum duration during which an address isn't dialed again after a failed dialing
+    /// attempt.
+    pub dial_backoff_max: Duration,
+
+    /// Reputation below which a peer gets banned. See [`ChainNetwork::report_peer`].
+    pub ban_threshold: i32,
+
+    /// Duration during which a peer whose reputation has fallen below
+    /// [`Config::ban_threshold`] stays banned.
+    pub ban_duration: Duration,
 }
 
 /// Configuration for a specific overlay network.
@@ -137,9 +157,9 @@ pub struct ChainNetwork<TNow, TPeer, TConn> {
     /// See [`Config::chains`].
     chain_configs: Vec<ChainConfig>,
 
-    /// For each item in [`ChainNetwork::chain_configs`].
+    /// For each item in [`ChainNetwork::chain_configs`], the state of the GrandPa gossiping.
     // TODO: merge with chain_configs?
-    chain_grandpa_config: Vec<Option<Mutex<GrandpaState>>>,
+    chain_grandpa_config: Vec<Option<Mutex<GrandpaGossip>>>,
 
     pending_in_accept: Mutex<Option<(libp2p::ConnectionId, usize, Vec<u8>)>>,
 
@@ -147,8 +167,46 @@ pub struct ChainNetwork<TNow, TPeer, TConn> {
     substreams_open_rx: Mutex<mpsc::Receiver<()>>,
 }
 
+/// State of the GrandPa gossiping of a chain.
+struct GrandpaGossip {
+    /// State of the local node, as last reported with [`ChainNetwork::set_local_grandpa_state`].
+    local_state: GrandpaState,
+
+    /// List of peers with an outbound GrandPa substream.
+    peers: HashMap<PeerId, GrandpaPeer, RandomState>,
+}
+
+/// See [`GrandpaGossip::peers`].
+#[derive(Default)]
+struct GrandpaPeer {
+    /// State of the peer, as reported by its latest neighbor packet. `None` if no neighbor
+    /// packet has been received yet.
+    view: Option<GrandpaState>,
+
+    /// Catch-up request sent to this peer and whose response hasn't been received yet.
+    pending_catch_up_request: Option<protocol::CatchUpRequest>,
+}
+
+impl GrandpaState {
+    /// Returns `true` if a vote of the given round and set is relevant to a node in this state.
+    ///
+    /// Votes are considered relevant if they concern the same authorities set, and a round
+    /// equal to the current round, the one before, or the one after.
+    fn accepts_vote(&self, set_id: u64, round_number: u64) -> bool {
+        self.set_id == set_id
+            && round_number.saturating_add(1) >= self.round_number
+            && round_number <= self.round_number.saturating_add(1)
+    }
+
+    /// Returns `true` if a commit of the given set and target is relevant to a node in this
+    /// state.
+    fn accepts_commit(&self, set_id: u64, target_number: u32) -> bool {
+        self.set_id == set_id && target_number > self.commit_finalized_height
+    }
+}
+
 // Update this when a new request response protocol is added.
-const REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN: usize = 4;
+const REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN: usize = 5;
 // Update this when a new notifications protocol is added.
 const NOTIFICATIONS_PROTOCOLS_PER_CHAIN: usize = 3;
 
@@ -251,27 +309,65 @@ where
                 inbound_allowed: false,
                 timeout: Duration::from_secs(20),
             }))
+            .chain(iter::once(libp2p::ConfigRequestResponse {
+                name: format!("/{}/state/1", chain.protocol_id),
+                inbound_config: libp2p::ConfigRequestResponseIn::Payload { max_size: 1024 },
+                max_response_size: 16 * 1024 * 1024,
+                // We don't support inbound state requests (yet).
+                inbound_allowed: false,
+                timeout: Duration::from_secs(20),
+            }))
         }))
         .collect();
 
         let (substreams_open_tx, substreams_open_rx) = mpsc::channel(0);
 
+        let mut randomness = rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed);
+
         let chain_grandpa_config = config
             .chains
             .iter()
-            .map(|chain| chain.grandpa_protocol_config.clone().map(Mutex::new))
+            .map(|chain| {
+                let local_state = chain.grandpa_protocol_config?;
+                let peers = HashMap::with_capacity_and_hasher(
+                    usize::try_from(chain.in_slots + chain.out_slots).unwrap_or(0),
+                    RandomState::with_seeds(
+                        randomness.next_u64(),
+                        randomness.next_u64(),
+                        randomness.next_u64(),
+                        randomness.next_u64(),
+                    ),
+                );
+                Some(Mutex::new(GrandpaGossip { local_state, peers }))
+            })
             .collect();
 
+        let libp2p_randomness_seed = {
+            let mut seed = [0; 32];
+            randomness.fill_bytes(&mut seed);
+            seed
+        };
+
         ChainNetwork {
             libp2p: libp2p::Network::new(libp2p::Config {
                 known_nodes: config.known_nodes,
                 listen_addresses: config.listen_addresses,
                 request_response_protocols,
                 noise_key: config.noise_key,
-                randomness_seed: config.randomness_seed,
+                randomness_seed: libp2p_randomness_seed,
                 pending_api_events_buffer_size: config.pending_api_events_buffer_size,
                 overlay_networks,
                 ping_protocol: "/ipfs/ping/1.0.0".into(),
+                ping_interval: Duration::from_secs(15),
+                ping_timeout: Duration::from_secs(20),
+                max_ping_failures: NonZeroU32::new(3).unwrap(),
+                max_simultaneous_substreams: 256,
+                yamux_substream_receive_window: 256 * 1024,
+                handshake_timeout: Duration::from_secs(8),
+                dial_backoff_base: config.dial_backoff_base,
+                dial_backoff_max: config.dial_backoff_max,
+                ban_threshold: config.ban_threshold,
+                ban_duration: config.ban_duration,
             }),
             chain_configs: config.chains,
             chain_grandpa_config,
@@ -295,17 +391,15 @@ where
         self.chain_configs.len()
     }
 
-    pub fn add_incoming_connection(
-        &self,
-        local_listen_address: &multiaddr::Multiaddr,
-        remote_addr: multiaddr::Multiaddr,
-        user_data: TConn,
-    ) -> ConnectionId {
-        ConnectionId(self.libp2p.add_incoming_connection(
-            local_listen_address,
-            remote_addr,
-            user_data,
-        ))
+    /// Adds an incoming connection, for example a socket that has been accepted by a
+    /// listener. The connection must then be driven with [`ChainNetwork::read_write`].
+    ///
+    /// See [`libp2p::Network::add_incoming_connection`] for more information.
+    pub async fn add_incoming_connection(&self, now: TNow, user_data: TConn) -> ConnectionId
+    where
+        TPeer: Default,
+    {
+        ConnectionId(self.libp2p.add_incoming_connection(now, user_data).await)
     }
 
     /// Update the state of the local node with regards to GrandPa rounds.
@@ -328,19 +422,23 @@ where
     ///
     pub async fn set_local_grandpa_state(&self, chain_index: usize, grandpa_state: GrandpaState) {
         // TODO: futures cancellation policy?
-        // TODO: right now we just try to send to everyone no matter which substream is open, which is wasteful
 
-        let grandpa_config = self.chain_grandpa_config[chain_index].as_ref().unwrap();
-        *grandpa_config.lock().await = grandpa_state;
-
-        // Grab the list of peers to send to. This is done *after* updating
-        // `chain_grandpa_config`, so that new substreams that are opened after grabbing this
-        // list are guaranteed to get informed of the updated state.
+        // Update the local state and grab the list of peers to send to while holding the lock,
+        // so that new substreams that are opened afterwards are guaranteed to get informed of
+        // the updated state.
         //
         // It is possible that some of the peers in this list have *just* been opened and are
         // already aware of the new state. We ignore this problem and just send another neighbor
         // packet.
-        let target_peers = self.libp2p.peers_list_lock().await.collect::<Vec<_>>();
+        let target_peers = {
+            let mut grandpa_gossip = self.chain_grandpa_config[chain_index]
+                .as_ref()
+                .unwrap()
+                .lock()
+                .await;
+            grandpa_gossip.local_state = grandpa_state;
+            grandpa_gossip.peers.keys().cloned().collect::<Vec<_>>()
+        };
 
         // Bytes of the neighbor packet to send out.
         let packet = protocol::GrandpaNotificationRef::Neighbor(protocol::NeighborPacket {
@@ -354,15 +452,198 @@ where
             a
         });
 
-        // Now sending out.
-        for target in target_peers {
+        self.send_grandpa_notification_to(chain_index, target_peers, packet)
+            .await;
+    }
+
+    /// Sends a GrandPa vote to all the peers whose state, as reported by their latest neighbor
+    /// packet, indicates that they are interested in it.
+    ///
+    /// This method should be used both to send out the votes of the local node and to propagate
+    /// the votes reported through [`Event::GrandpaVote`], after they have been verified.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range, or if the chain has GrandPa disabled.
+    ///
+    pub async fn gossip_grandpa_vote(
+        &self,
+        chain_index: usize,
+        vote: protocol::VoteMessageRef<'_>,
+    ) {
+        let target_peers = {
+            let grandpa_gossip = self.chain_grandpa_config[chain_index]
+                .as_ref()
+                .unwrap()
+                .lock()
+                .await;
+            grandpa_gossip
+                .peers
+                .iter()
+                .filter(|(_, peer)| {
+                    peer.view.map_or(false, |view| {
+                        view.accepts_vote(vote.set_id, vote.round_number)
+                    })
+                })
+                .map(|(peer_id, _)| peer_id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let notification = protocol::GrandpaNotificationRef::Vote(vote)
+            .scale_encoding()
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        self.send_grandpa_notification_to(chain_index, target_peers, notification)
+            .await;
+    }
+
+    /// Sends a GrandPa commit message to all the peers whose state, as reported by their latest
+    /// neighbor packet, indicates that they are interested in it.
+    ///
+    /// This method should be used both to send out the commits of the local node and to
+    /// propagate the commits reported through [`Event::GrandpaCommitMessage`], after they have
+    /// been verified.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range, or if the chain has GrandPa disabled.
+    ///
+    pub async fn gossip_grandpa_commit(
+        &self,
+        chain_index: usize,
+        commit: protocol::CommitMessageRef<'_>,
+    ) {
+        let target_peers = {
+            let grandpa_gossip = self.chain_grandpa_config[chain_index]
+                .as_ref()
+                .unwrap()
+                .lock()
+                .await;
+            grandpa_gossip
+                .peers
+                .iter()
+                .filter(|(_, peer)| {
+                    peer.view.map_or(false, |view| {
+                        view.accepts_commit(commit.set_id, commit.message.target_number)
+                    })
+                })
+                .map(|(peer_id, _)| peer_id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let notification = protocol::GrandpaNotificationRef::Commit(commit)
+            .scale_encoding()
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        self.send_grandpa_notification_to(chain_index, target_peers, notification)
+            .await;
+    }
+
+    /// Sends a GrandPa catch-up request to the given peer.
+    ///
+    /// The response, if any, is later reported through [`Event::GrandpaCatchUp`]. Only one
+    /// catch-up request can be pending per peer; sending a new request overrides the previous
+    /// one.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range, or if the chain has GrandPa disabled.
+    ///
+    pub async fn send_grandpa_catch_up_request(
+        &self,
+        target: &peer_id::PeerId,
+        chain_index: usize,
+        request: protocol::CatchUpRequest,
+    ) -> Result<(), QueueNotificationError> {
+        // TODO: not futures-cancellation-safe
+        let grandpa_gossip = self.chain_grandpa_config[chain_index].as_ref().unwrap();
+
+        // The request is marked as pending before being sent, so that a response that arrives
+        // quickly isn't discarded. The lock is released before sending the notification, as
+        // sending might take a long time.
+        {
+            let mut grandpa_gossip = grandpa_gossip.lock().await;
+            let peer = grandpa_gossip
+                .peers
+                .get_mut(target)
+                .ok_or(QueueNotificationError::NoSubstream)?;
+            peer.pending_catch_up_request = Some(request.clone());
+        }
+
+        let notification = protocol::GrandpaNotificationRef::CatchUpRequest(request.clone())
+            .scale_encoding()
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        let result = self
+            .libp2p
+            .queue_notification(
+                target,
+                chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
+                notification,
+            )
+            .await;
+
+        if result.is_err() {
+            if let Some(peer) = grandpa_gossip.lock().await.peers.get_mut(target) {
+                if peer.pending_catch_up_request.as_ref() == Some(&request) {
+                    peer.pending_catch_up_request = None;
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Sends a GrandPa catch-up message to the given peer, generally as a response to a
+    /// [`Event::GrandpaCatchUpRequest`].
+    ///
+    /// > **Note**: The message passed as parameter isn't verified in any way by this method.
+    pub async fn send_grandpa_catch_up(
+        &self,
+        target: &peer_id::PeerId,
+        chain_index: usize,
+        catch_up: protocol::CatchUpRef<'_>,
+    ) -> Result<(), QueueNotificationError> {
+        let notification = protocol::GrandpaNotificationRef::CatchUp(catch_up)
+            .scale_encoding()
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        self.libp2p
+            .queue_notification(
+                target,
+                chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
+                notification,
+            )
+            .await
+    }
+
+    /// Sends the given GrandPa notification to each of the given peers, ignoring errors.
+    async fn send_grandpa_notification_to(
+        &self,
+        chain_index: usize,
+        targets: Vec<peer_id::PeerId>,
+        notification: Vec<u8>,
+    ) {
+        for target in targets {
             // Ignore sending errors.
             let _ = self
                 .libp2p
                 .queue_notification(
                     &target,
                     chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
-                    packet.clone(),
+                    notification.clone(),
                 )
                 .await;
         }
@@ -445,6 +726,67 @@ where
         protocol::decode_storage_proof_response(&response).map_err(StorageProofRequestError::Decode)
     }
 
+    /// Sends a storage request concerning a child trie to the given peer.
+    ///
+    /// See also [`ChainNetwork::storage_proof_request`].
+    pub async fn child_storage_proof_request(
+        &self,
+        now: TNow,
+        target: peer_id::PeerId,
+        chain_index: usize,
+        config: protocol::ChildStorageProofRequestConfig<
+            impl AsRef<[u8]>,
+            impl Iterator<Item = impl AsRef<[u8]>>,
+        >,
+    ) -> Result<Vec<Vec<u8>>, StorageProofRequestError> {
+        let request_data =
+            protocol::build_child_storage_proof_request(config).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+        let response = self
+            .libp2p
+            .request(
+                now,
+                target,
+                self.protocol_index(chain_index, 1),
+                request_data,
+            )
+            .map_err(StorageProofRequestError::Request)
+            .await?;
+        protocol::decode_storage_proof_response(&response).map_err(StorageProofRequestError::Decode)
+    }
+
+    /// Sends a state request to the given peer.
+    ///
+    /// The response contains the keys and values of the storage of the requested block, in
+    /// lexicographic order and starting after [`protocol::StateRequestConfig::start`]. Only a
+    /// part of the storage is typically returned, and multiple requests are necessary in order
+    /// to download all of it. The entries aren't accompanied with any proof.
+    pub async fn state_request(
+        &self,
+        now: TNow,
+        target: peer_id::PeerId,
+        chain_index: usize,
+        config: protocol::StateRequestConfig<impl Iterator<Item = impl AsRef<[u8]>>>,
+    ) -> Result<Vec<protocol::StateResponseEntry>, StateRequestError> {
+        let request_data = protocol::build_state_request(config).fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+        let response = self
+            .libp2p
+            .request(
+                now,
+                target,
+                self.protocol_index(chain_index, 4),
+                request_data,
+            )
+            .map_err(StateRequestError::Request)
+            .await?;
+        protocol::decode_state_response(&response).map_err(StateRequestError::Decode)
+    }
+
     /// Sends a call proof request to the given peer.
     ///
     /// This request is similar to [`ChainNetwork::storage_proof_request`]. Instead of requesting
@@ -498,6 +840,37 @@ where
             .await
     }
 
+    /// Sends a block announce to the given peer.
+    ///
+    /// The announce is only sent if a block announces substream is currently open with the
+    /// target. An error is returned otherwise.
+    ///
+    /// > **Note**: The header passed as parameter isn't verified in any way by this method.
+    pub async fn send_block_announce(
+        &self,
+        target: &peer_id::PeerId,
+        chain_index: usize,
+        header: header::HeaderRef<'_>,
+        is_best: bool,
+    ) -> Result<(), QueueNotificationError> {
+        let notification =
+            protocol::encode_block_announce(protocol::BlockAnnounceRef { header, is_best }).fold(
+                Vec::new(),
+                |mut a, b| {
+                    a.extend_from_slice(b.as_ref());
+                    a
+                },
+            );
+
+        self.libp2p
+            .queue_notification(
+                target,
+                chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
+                notification,
+            )
+            .await
+    }
+
     /// After calling [`ChainNetwork::fill_out_slots`], notifies the [`ChainNetwork`] of the
     /// success of the dialing attempt.
     ///
@@ -514,14 +887,32 @@ where
     /// After calling [`ChainNetwork::fill_out_slots`], notifies the [`ChainNetwork`] of the
     /// failure of the dialing attempt.
     ///
+    /// The target address isn't dialed again until the end of a backoff period.
+    ///
     /// See also [`ChainNetwork::pending_outcome_ok`].
     ///
     /// # Panic
     ///
     /// Panics if the [`PendingId`] is invalid.
     ///
-    pub async fn pending_outcome_err(&self, id: PendingId) {
-        self.libp2p.pending_outcome_err(id.0).await
+    pub async fn pending_outcome_err(&self, id: PendingId, now: TNow) {
+        self.libp2p.pending_outcome_err(id.0, now).await
+    }
+
+    /// After calling [`ChainNetwork::fill_out_slots`], notifies the [`ChainNetwork`] that the
+    /// target address of the dialing attempt couldn't be resolved, for example because of a DNS
+    /// failure.
+    ///
+    /// Similarly to [`ChainNetwork::pending_outcome_err`], the address isn't dialed again until
+    /// the end of a backoff period. It isn't forgotten, as resolution failures are frequently
+    /// temporary.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`PendingId`] is invalid.
+    ///
+    pub async fn pending_outcome_resolution_err(&self, id: PendingId, now: TNow) {
+        self.libp2p.pending_outcome_resolution_err(id.0, now).await
     }
 
     /// Returns the next event produced by the service.
@@ -557,6 +948,17 @@ where
                     mut out_overlay_network_indices,
                     ..
                 } => {
+                    // TODO: not futures-cancellation-safe
+                    for overlay_network_index in &out_overlay_network_indices {
+                        if overlay_network_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN != 2 {
+                            continue;
+                        }
+                        let chain_index = overlay_network_index / NOTIFICATIONS_PROTOCOLS_PER_CHAIN;
+                        if let Some(grandpa_gossip) = &self.chain_grandpa_config[chain_index] {
+                            grandpa_gossip.lock().await.peers.remove(&peer_id);
+                        }
+                    }
+
                     out_overlay_network_indices
                         .retain(|i| (i % NOTIFICATIONS_PROTOCOLS_PER_CHAIN) == 0);
                     for elem in &mut out_overlay_network_indices {
@@ -591,6 +993,7 @@ where
                     peer_id,
                     overlay_network_index,
                     remote_handshake,
+                    negotiated_protocol,
                 } => {
                     let chain_index = overlay_network_index / NOTIFICATIONS_PROTOCOLS_PER_CHAIN;
                     if overlay_network_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN == 0 {
@@ -601,6 +1004,7 @@ where
                         return Event::ChainConnected {
                             peer_id,
                             chain_index,
+                            block_announces_protocol: negotiated_protocol,
                             best_hash: *remote_handshake.best_hash,
                             best_number: remote_handshake.best_number,
                             role: remote_handshake.role,
@@ -608,14 +1012,20 @@ where
                     } else if overlay_network_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN == 1 {
                         // Nothing to do.
                     } else if overlay_network_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN == 2 {
-                        // Grandpa notification has been opened. Send neighbor packet.
+                        // Grandpa notification has been opened. Start tracking the state of the
+                        // peer and send neighbor packet.
                         // TODO: below is not futures-cancellation-safe!
-                        let grandpa_config = self.chain_grandpa_config[chain_index]
-                            .as_ref()
-                            .unwrap()
-                            .lock()
-                            .await
-                            .clone();
+                        let grandpa_config = {
+                            let mut grandpa_gossip = self.chain_grandpa_config[chain_index]
+                                .as_ref()
+                                .unwrap()
+                                .lock()
+                                .await;
+                            grandpa_gossip
+                                .peers
+                                .insert(peer_id.clone(), GrandpaPeer::default());
+                            grandpa_gossip.local_state
+                        };
                         let packet =
                             protocol::GrandpaNotificationRef::Neighbor(protocol::NeighborPacket {
                                 round_number: grandpa_config.round_number,
@@ -651,8 +1061,11 @@ where
                             peer_id,
                             chain_index,
                         };
-                    // TODO: don't unwrap
-                    } else {
+                    } else if overlay_network_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN == 2 {
+                        // TODO: not futures-cancellation-safe
+                        if let Some(grandpa_gossip) = &self.chain_grandpa_config[chain_index] {
+                            grandpa_gossip.lock().await.peers.remove(&peer_id);
+                        }
                     }
 
                     // TODO:
@@ -661,6 +1074,10 @@ where
                     id,
                     overlay_network_index,
                     remote_handshake,
+                    // Inbound substreams are accepted automatically and aren't reported to the
+                    // user. All the protocol names of a given overlay network currently use the
+                    // same handshake format, and the handshake sent back doesn't depend on it.
+                    negotiated_protocol: _,
                 } => {
                     if (overlay_network_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN) == 0 {
                         let remote_handshake =
@@ -704,6 +1121,14 @@ where
                         unreachable!()
                     }
                 }
+                libp2p::Event::PingOutSuccess {
+                    peer_id, ping_time, ..
+                } => {
+                    return Event::PingOutSuccess { peer_id, ping_time };
+                }
+                libp2p::Event::PingOutFailed { peer_id, .. } => {
+                    return Event::PingOutFailed { peer_id };
+                }
                 libp2p::Event::NotificationsIn {
                     id,
                     peer_id,
@@ -723,26 +1148,161 @@ where
 
                     let chain_index = overlay_network_index / NOTIFICATIONS_PROTOCOLS_PER_CHAIN;
                     if overlay_network_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN == 0 {
-                        // TODO: don't unwrap
-                        let announce = protocol::decode_block_announce(&notification).unwrap();
+                        if protocol::decode_block_announce(&notification).is_err() {
+                            return Event::ProtocolMisbehavior {
+                                chain_index,
+                                peer_id,
+                                reputation_change: ReputationChange::BadBlockAnnounce,
+                            };
+                        }
+
                         return Event::BlockAnnounce {
                             chain_index,
                             peer_id,
                             announce: EncodedBlockAnnounce(notification),
                         };
                     } else if overlay_network_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN == 1 {
-                        // TODO: transaction announce
+                        if protocol::decode_transactions(&notification).is_err() {
+                            return Event::ProtocolMisbehavior {
+                                chain_index,
+                                peer_id,
+                                reputation_change: ReputationChange::BadTransactions,
+                            };
+                        }
+
+                        return Event::Transactions {
+                            chain_index,
+                            peer_id,
+                            transactions: EncodedTransactions(notification),
+                        };
                     } else if overlay_network_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN == 2 {
-                        // TODO: don't unwrap
                         let decoded_notif =
-                            protocol::decode_grandpa_notification(&notification).unwrap();
-                        // Commit messages are the only type of message that is important for
-                        // light clients. Anything else is presently ignored.
-                        if let protocol::GrandpaNotificationRef::Commit(_) = decoded_notif {
-                            return Event::GrandpaCommitMessage {
-                                chain_index,
-                                message: EncodedGrandpaCommitMessage(notification),
+                            match protocol::decode_grandpa_notification(&notification) {
+                                Ok(n) => n,
+                                Err(_) => {
+                                    return Event::ProtocolMisbehavior {
+                                        chain_index,
+                                        peer_id,
+                                        reputation_change: ReputationChange::BadGrandpaMessage,
+                                    }
+                                }
                             };
+
+                        // TODO: not futures-cancellation-safe
+                        let mut grandpa_gossip = match &self.chain_grandpa_config[chain_index] {
+                            Some(g) => g.lock().await,
+                            None => continue,
+                        };
+                        let local_state = grandpa_gossip.local_state;
+
+                        // Messages that aren't relevant to the local node are silently ignored.
+                        // Messages with invalid signatures are reported as misbehaviors.
+                        match decoded_notif {
+                            protocol::GrandpaNotificationRef::Neighbor(packet) => {
+                                let peer = match grandpa_gossip.peers.get_mut(&peer_id) {
+                                    Some(p) => p,
+                                    None => continue,
+                                };
+
+                                // The state of a peer is never supposed to go backwards.
+                                if let Some(view) = &peer.view {
+                                    if (packet.set_id, packet.round_number)
+                                        < (view.set_id, view.round_number)
+                                    {
+                                        continue;
+                                    }
+                                }
+
+                                let state = GrandpaState {
+                                    round_number: packet.round_number,
+                                    set_id: packet.set_id,
+                                    commit_finalized_height: packet.commit_finalized_height,
+                                };
+                                peer.view = Some(state);
+                                return Event::GrandpaNeighborPacket {
+                                    chain_index,
+                                    peer_id,
+                                    state,
+                                };
+                            }
+                            protocol::GrandpaNotificationRef::Vote(vote) => {
+                                if !local_state.accepts_vote(vote.set_id, vote.round_number) {
+                                    continue;
+                                }
+
+                                if !vote.is_signature_valid() {
+                                    return Event::ProtocolMisbehavior {
+                                        chain_index,
+                                        peer_id,
+                                        reputation_change: ReputationChange::BadGrandpaMessage,
+                                    };
+                                }
+
+                                return Event::GrandpaVote {
+                                    chain_index,
+                                    peer_id,
+                                    message: EncodedGrandpaVoteMessage(notification),
+                                };
+                            }
+                            protocol::GrandpaNotificationRef::Commit(commit) => {
+                                if !local_state
+                                    .accepts_commit(commit.set_id, commit.message.target_number)
+                                {
+                                    continue;
+                                }
+
+                                return Event::GrandpaCommitMessage {
+                                    chain_index,
+                                    peer_id,
+                                    message: EncodedGrandpaCommitMessage(notification),
+                                };
+                            }
+                            protocol::GrandpaNotificationRef::CatchUpRequest(request) => {
+                                // Only requests concerning the current set and a round before
+                                // the current round can be answered.
+                                if request.set_id != local_state.set_id
+                                    || request.round_number >= local_state.round_number
+                                {
+                                    continue;
+                                }
+
+                                return Event::GrandpaCatchUpRequest {
+                                    chain_index,
+                                    peer_id,
+                                    request,
+                                };
+                            }
+                            protocol::GrandpaNotificationRef::CatchUp(catch_up) => {
+                                // Catch-up messages are only accepted as a response to a request.
+                                let request = match grandpa_gossip
+                                    .peers
+                                    .get_mut(&peer_id)
+                                    .and_then(|p| p.pending_catch_up_request.take())
+                                {
+                                    Some(r) => r,
+                                    None => continue,
+                                };
+
+                                if catch_up.set_id != request.set_id
+                                    || catch_up.round_number < request.round_number
+                                {
+                                    continue;
+                                }
+
+                                if !catch_up.are_signatures_valid() {
+                                    return Event::ProtocolMisbehavior {
+                                        chain_index,
+                                        peer_id,
+                                        reputation_change: ReputationChange::BadGrandpaMessage,
+                                    };
+                                }
+
+                                return Event::GrandpaCatchUp {
+                                    chain_index,
+                                    peer_id,
+                                    message: EncodedGrandpaCatchUp(notification),
+                                };
+                            }
                         }
                     } else {
                         unreachable!()
@@ -812,17 +1372,24 @@ where
     }
 
     /// Spawns new outgoing connections in order to fill empty outgoing slots.
+    ///
+    /// See [`libp2p::Network::fill_out_slots`].
     // TODO: give more control, with number of slots and node choice
-    pub async fn fill_out_slots<'a>(&self, chain_index: usize) -> Option<StartConnect> {
+    pub async fn fill_out_slots<'a>(
+        &self,
+        chain_index: usize,
+        now: TNow,
+    ) -> Option<StartConnect<TNow>> {
         let inner = self
             .libp2p
-            .fill_out_slots(chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN)
+            .fill_out_slots(chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN, now)
             .await?;
 
         Some(StartConnect {
             id: PendingId(inner.id),
             multiaddr: inner.multiaddr,
             expected_peer_id: inner.expected_peer_id,
+            timeout: inner.timeout,
         })
     }
 
@@ -856,6 +1423,119 @@ where
     pub async fn peers_list(&self) -> impl Iterator<Item = PeerId> {
         self.libp2p.peers_list_lock().await
     }
+
+    /// Returns the number of bytes transferred on all the connections since the
+    /// [`ChainNetwork`] has been created.
+    pub async fn total_bandwidth(&self) -> libp2p::Bandwidth {
+        self.libp2p.total_bandwidth().await
+    }
+
+    /// Returns the number of bytes transferred on each established connection, in total and
+    /// for each protocol.
+    ///
+    /// Protocols on which no byte has been transferred are omitted.
+    pub async fn connections_bandwidth(&self) -> Vec<ConnectionBandwidth> {
+        let overlay_networks = self.libp2p.overlay_networks().collect::<Vec<_>>();
+        let request_response_protocols =
+            self.libp2p.request_response_protocols().collect::<Vec<_>>();
+
+        self.libp2p
+            .connections_bandwidth()
+            .await
+            .into_iter()
+            .map(|connection| {
+                let notifications = connection
+                    .overlay_networks
+                    .iter()
+                    .zip(overlay_networks.iter())
+                    .map(|(bandwidth, config)| (config.protocol_name.clone(), *bandwidth));
+                let requests = connection
+                    .request_response_protocols
+                    .iter()
+                    .zip(request_response_protocols.iter())
+                    .map(|(bandwidth, config)| (config.name.clone(), *bandwidth));
+
+                ConnectionBandwidth {
+                    peer_id: connection.peer_id,
+                    total: connection.total,
+                    protocols: notifications
+                        .chain(requests)
+                        .filter(|(_, bandwidth)| *bandwidth != libp2p::Bandwidth::default())
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Modifies the reputation of the given peer, for example after it has sent an invalid
+    /// block or justification.
+    ///
+    /// If the reputation of the peer falls below a certain threshold, all the connections to
+    /// this peer are closed and no new connection is opened with it for a certain duration.
+    /// Returns `true` if the peer is banned.
+    ///
+    /// Has no effect and returns `false` if the peer isn't known.
+    pub async fn report_peer(
+        &self,
+        now: TNow,
+        peer_id: &PeerId,
+        reputation_change: ReputationChange,
+    ) -> bool {
+        self.libp2p
+            .report_peer(now, peer_id, reputation_change.value())
+            .await
+    }
+}
+
+/// Number of bytes transferred on an established connection. See
+/// [`ChainNetwork::connections_bandwidth`].
+#[derive(Debug, Clone)]
+pub struct ConnectionBandwidth {
+    /// Identity of the remote.
+    pub peer_id: PeerId,
+    /// Number of bytes transferred on the socket since the end of the handshake, including the
+    /// overhead of the encryption and multiplexing layers.
+    pub total: libp2p::Bandwidth,
+    /// Name of each protocol on which data has been transferred, and the number of bytes
+    /// transferred.
+    ///
+    /// > **Note**: The same protocol name can appear multiple times, as some protocols (such as
+    /// >           GrandPa) have the same name for all chains.
+    pub protocols: Vec<(String, libp2p::Bandwidth)>,
+}
+
+/// Reason for modifying the reputation of a peer. See [`ChainNetwork::report_peer`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ReputationChange {
+    /// Peer has sent a block that has failed verification.
+    BadBlock,
+    /// Peer has sent a justification that has failed verification.
+    BadJustification,
+    /// Peer has sent a block announce that couldn't be decoded.
+    BadBlockAnnounce,
+    /// Peer has sent a GrandPa message that couldn't be decoded or whose signature is invalid.
+    BadGrandpaMessage,
+    /// Peer has sent a list of transactions that couldn't be decoded.
+    BadTransactions,
+    /// Peer has sent a transaction that has failed to be applied.
+    InvalidTransaction,
+    /// A request sent to the peer has failed or has been answered with an invalid response.
+    RequestFailed,
+}
+
+impl ReputationChange {
+    /// Returns the value to add to the reputation of the peer.
+    pub fn value(&self) -> i32 {
+        match self {
+            ReputationChange::BadBlock => -500,
+            ReputationChange::BadJustification => -500,
+            ReputationChange::BadBlockAnnounce => -300,
+            ReputationChange::BadGrandpaMessage => -100,
+            ReputationChange::BadTransactions => -50,
+            ReputationChange::InvalidTransaction => -20,
+            ReputationChange::RequestFailed => -20,
+        }
+    }
 }
 
 /// User must start connecting to the given multiaddress.
@@ -864,11 +1544,15 @@ where
 /// later be called in order to inform of the outcome of the connection.
 #[derive(Debug)]
 #[must_use]
-pub struct StartConnect {
+pub struct StartConnect<TNow> {
     pub id: PendingId,
     pub multiaddr: multiaddr::Multiaddr,
     /// [`PeerId`] that is expected to be reached with this connection attempt.
     pub expected_peer_id: PeerId,
+    /// When the connection attempt, including the handshake, is considered failed. If the
+    /// target hasn't been reached at this point, the API user should give up and call
+    /// [`ChainNetwork::pending_outcome_err`].
+    pub timeout: TNow,
 }
 
 /// Event generated by [`ChainNetwork::next_event`].
@@ -886,6 +1570,9 @@ pub enum Event<'a, TNow, TPeer, TConn> {
     ChainConnected {
         chain_index: usize,
         peer_id: peer_id::PeerId,
+        /// Name of the block announces protocol negotiated with the peer. Can be either the
+        /// latest version of the protocol or one of its fallbacks.
+        block_announces_protocol: String,
         /// Role the node reports playing on the network.
         role: protocol::Role,
         /// Height of the best block according to this node.
@@ -904,12 +1591,56 @@ pub enum Event<'a, TNow, TPeer, TConn> {
         announce: EncodedBlockAnnounce,
     },
 
+    /// Received a GrandPa neighbor packet from the network. The state of the peer has been
+    /// updated and is now taken into account when gossiping GrandPa messages.
+    GrandpaNeighborPacket {
+        chain_index: usize,
+        peer_id: peer_id::PeerId,
+        /// State of the peer, as reported by the neighbor packet.
+        state: GrandpaState,
+    },
+
+    /// Received a GrandPa vote from the network.
+    ///
+    /// The vote concerns the local authorities set and a round close to the local round, as
+    /// reported with [`ChainNetwork::set_local_grandpa_state`], and its signature is valid.
+    /// Whether the signer is part of the authorities set isn't verified.
+    GrandpaVote {
+        chain_index: usize,
+        peer_id: peer_id::PeerId,
+        message: EncodedGrandpaVoteMessage,
+    },
+
     /// Received a GrandPa commit message from the network.
+    ///
+    /// The commit concerns the local authorities set and a block higher than the local
+    /// finalized block, as reported with [`ChainNetwork::set_local_grandpa_state`]. Its
+    /// signatures haven't been verified.
     GrandpaCommitMessage {
         chain_index: usize,
+        peer_id: peer_id::PeerId,
         message: EncodedGrandpaCommitMessage,
     },
 
+    /// Received a GrandPa catch-up request from the network. The request concerns the local
+    /// authorities set and a round before the local round.
+    ///
+    /// Can be answered using [`ChainNetwork::send_grandpa_catch_up`].
+    GrandpaCatchUpRequest {
+        chain_index: usize,
+        peer_id: peer_id::PeerId,
+        request: protocol::CatchUpRequest,
+    },
+
+    /// Received a response to a catch-up request sent with
+    /// [`ChainNetwork::send_grandpa_catch_up_request`]. The signatures of all the votes are
+    /// valid, but whether the signers are part of the authorities set isn't verified.
+    GrandpaCatchUp {
+        chain_index: usize,
+        peer_id: peer_id::PeerId,
+        message: EncodedGrandpaCatchUp,
+    },
+
     /// A remote has sent a request for identification information.
     ///
     /// You are strongly encouraged to call [`IdentifyRequestIn::respond`].
@@ -919,10 +1650,34 @@ pub enum Event<'a, TNow, TPeer, TConn> {
         /// Object allowing sending back the answer.
         request: IdentifyRequestIn<'a, TNow, TPeer, TConn>,
     },
-    /*Transactions {
+
+    /// Received a list of transactions from the network.
+    Transactions {
+        chain_index: usize,
         peer_id: peer_id::PeerId,
         transactions: EncodedTransactions,
-    }*/
+    },
+
+    /// A ping sent to the given peer has been answered.
+    PingOutSuccess {
+        peer_id: peer_id::PeerId,
+        /// Round-trip time of the ping.
+        ping_time: Duration,
+    },
+
+    /// A ping sent to the given peer has failed. The connection is automatically closed after
+    /// several consecutive failures.
+    PingOutFailed { peer_id: peer_id::PeerId },
+
+    /// The given peer has sent an invalid message, which has been discarded.
+    ///
+    /// The reputation of the peer isn't modified automatically. Call
+    /// [`ChainNetwork::report_peer`] in order to do so.
+    ProtocolMisbehavior {
+        chain_index: usize,
+        peer_id: peer_id::PeerId,
+        reputation_change: ReputationChange,
+    },
 }
 
 /// Undecoded but valid block announce handshake.
@@ -958,6 +1713,24 @@ impl fmt::Debug for EncodedBlockAnnounce {
     }
 }
 
+/// Undecoded but valid transactions notification.
+#[derive(Clone)]
+pub struct EncodedTransactions(Vec<u8>);
+
+impl EncodedTransactions {
+    /// Returns the list of transactions. Each transaction is returned without its SCALE-compact
+    /// length prefix.
+    pub fn decode(&self) -> Vec<&[u8]> {
+        protocol::decode_transactions(&self.0).unwrap()
+    }
+}
+
+impl fmt::Debug for EncodedTransactions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
 /// Undecoded but valid GrandPa commit message.
 #[derive(Clone)]
 pub struct EncodedGrandpaCommitMessage(Vec<u8>);
@@ -978,6 +1751,46 @@ impl fmt::Debug for EncodedGrandpaCommitMessage {
     }
 }
 
+/// Undecoded but valid GrandPa vote message.
+#[derive(Clone)]
+pub struct EncodedGrandpaVoteMessage(Vec<u8>);
+
+impl EncodedGrandpaVoteMessage {
+    /// Returns the decoded version of the vote message.
+    pub fn decode(&self) -> protocol::VoteMessageRef {
+        match protocol::decode_grandpa_notification(&self.0) {
+            Ok(protocol::GrandpaNotificationRef::Vote(msg)) => msg,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Debug for EncodedGrandpaVoteMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
+/// Undecoded but valid GrandPa catch-up message.
+#[derive(Clone)]
+pub struct EncodedGrandpaCatchUp(Vec<u8>);
+
+impl EncodedGrandpaCatchUp {
+    /// Returns the decoded version of the catch-up message.
+    pub fn decode(&self) -> protocol::CatchUpRef {
+        match protocol::decode_grandpa_notification(&self.0) {
+            Ok(protocol::GrandpaNotificationRef::CatchUp(msg)) => msg,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Debug for EncodedGrandpaCatchUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
 /// Successfull outcome to [`ChainNetwork::kademlia_discovery_round`].
 #[must_use]
 pub struct DiscoveryInsert<'a, TNow, TPeer, TConn> {
@@ -1170,6 +1983,13 @@ pub enum StorageProofRequestError {
     Decode(protocol::DecodeStorageProofResponseError),
 }
 
+/// Error returned by [`ChainNetwork::state_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum StateRequestError {
+    Request(libp2p::RequestError),
+    Decode(protocol::DecodeStateResponseError),
+}
+
 /// Error returned by [`ChainNetwork::call_proof_request`].
 #[derive(Debug, derive_more::Display)]
 pub enum CallProofRequestError {
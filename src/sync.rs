@@ -53,3 +53,6 @@ pub mod all_forks;
 pub mod grandpa_warp_sync;
 pub mod optimistic;
 pub mod para;
+pub mod state;
+
+mod storage_diff;
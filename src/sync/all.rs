@@ -40,7 +40,7 @@
 // TODO: this module needs considerable clean-up
 
 use crate::{
-    chain::chain_information,
+    chain::{blocks_tree, chain_information},
     executor::{host, vm::ExecHint},
     header,
     sync::{all_forks, grandpa_warp_sync, optimistic},
@@ -95,6 +95,27 @@ pub struct Config {
     /// situations where determinism/reproducibility is desired.
     pub source_selection_randomness_seed: u64,
 
+    /// List of hashes of blocks that must never be part of the chain.
+    ///
+    /// Announced blocks whose hash is in this list are ignored, responses to block requests that
+    /// contain such blocks are treated as failed requests, and blocks whose hash is in this list
+    /// always fail to verify.
+    ///
+    /// > **Note**: This list is typically found in the chain specification, see
+    /// >           [`crate::chain_spec::ChainSpec::bad_blocks_hashes`].
+    pub bad_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes. At each of these heights, only the block with the
+    /// corresponding hash can be part of the chain. This forces the chain onto a specific fork.
+    ///
+    /// Announced blocks that conflict with an entry of this list are ignored, responses to block
+    /// requests that contain such blocks are treated as failed requests, and such blocks always
+    /// fail to verify.
+    ///
+    /// > **Note**: This list is typically found in the chain specification, see
+    /// >           [`crate::chain_spec::ChainSpec::fork_blocks`].
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
     /// If `Some`, the block bodies and storage are also synchronized. Contains the extra
     /// configuration.
     pub full: Option<ConfigFull>,
@@ -149,6 +170,8 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
                     blocks_request_granularity: config.blocks_request_granularity,
                     download_ahead_blocks: config.download_ahead_blocks,
                     source_selection_randomness_seed: config.source_selection_randomness_seed,
+                    bad_blocks: config.bad_blocks.clone(),
+                    fork_blocks: config.fork_blocks.clone(),
                     full: config.full.map(|cfg| optimistic::ConfigFull {
                         finalized_runtime: cfg.finalized_runtime,
                     }),
@@ -165,6 +188,8 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
                 sources: slab::Slab::with_capacity(config.sources_capacity),
                 requests: slab::Slab::with_capacity(config.sources_capacity),
                 highest_block_on_network: 0,
+                bad_blocks: config.bad_blocks,
+                fork_blocks: config.fork_blocks,
             },
         }
     }
@@ -350,7 +375,7 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
                 let outer_source_id_entry = self.shared.sources.vacant_entry();
                 let outer_source_id = SourceId(outer_source_id_entry.key());
 
-                let (source, request) = all_forks.add_source(
+                let (source_id, next_requests) = all_forks.add_source(
                     AllForksSourceExtra {
                         user_data,
                         outer_source_id,
@@ -359,18 +384,17 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
                     best_block_number,
                     best_block_hash,
                 );
-                let source_id = source.id();
                 outer_source_id_entry.insert(SourceMapping::AllForks(source_id));
 
-                let next_actions = if let Some(request) = request {
-                    vec![self.shared.all_forks_request_to_request(
+                let mut next_actions = Vec::with_capacity(next_requests.len());
+                for (source_id, request_id, request) in next_requests {
+                    next_actions.push(self.shared.all_forks_request_to_request(
                         &mut all_forks,
                         source_id,
+                        request_id,
                         request,
-                    )]
-                } else {
-                    Vec::new()
-                };
+                    ));
+                }
 
                 self.inner = IdleInner::AllForks(all_forks);
                 (outer_source_id, next_actions)
@@ -457,6 +481,30 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
         }
     }
 
+    /// Allows requesting again the justifications of the non-finalized blocks whose
+    /// justification is missing, such as blocks that change the list of GrandPa authorities, and
+    /// of the current best block.
+    ///
+    /// This method should be called periodically, for example every few seconds, in order to
+    /// make sure that finality doesn't stall. It has no effect if the state machine isn't
+    /// near the head of the chain.
+    pub fn retry_justification_requests(&mut self) -> Vec<Action> {
+        match &mut self.inner {
+            IdleInner::AllForks(sync) => {
+                let mut next_actions = Vec::new();
+                for (source_id, request_id, request) in sync.retry_justification_requests() {
+                    next_actions.push(
+                        self.shared
+                            .all_forks_request_to_request(sync, source_id, request_id, request),
+                    );
+                }
+                next_actions
+            }
+            IdleInner::Optimistic(_) | IdleInner::GrandpaWarpSync(_) => Vec::new(),
+            IdleInner::Poisoned => unreachable!(),
+        }
+    }
+
     /// Injects a block announcement made by a source into the state machine.
     pub fn block_announce(
         mut self,
@@ -465,6 +513,12 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
         is_best: bool,
     ) -> BlockAnnounceOutcome<TRq, TSrc, TBl> {
         if let Ok(header) = header::decode(&announced_scale_encoded_header) {
+            // Blocks that are marked as bad or that conflict with a forced fork are ignored
+            // altogether, in order to not download the chain that is built on top of them.
+            if self.shared.is_bad_block(header.number, &header.hash()) {
+                return BlockAnnounceOutcome::BadBlock(self);
+            }
+
             if header.number > self.shared.highest_block_on_network {
                 self.shared.highest_block_on_network = header.number;
             }
@@ -514,16 +568,30 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
                     }
                     all_forks::BlockAnnounceOutcome::Disjoint {
                         mut sync,
-                        next_request,
+                        next_requests,
                     } => {
-                        let next_actions = match next_request {
-                            Some(nr) => {
-                                vec![self
-                                    .shared
-                                    .all_forks_request_to_request(&mut sync, source_id, nr)]
-                            }
-                            None => Vec::new(),
-                        };
+                        let mut next_actions = Vec::with_capacity(next_requests.len());
+                        for (source_id, request_id, request) in next_requests {
+                            next_actions.push(self.shared.all_forks_request_to_request(
+                                &mut sync, source_id, request_id, request,
+                            ));
+                        }
+                        self.inner = IdleInner::AllForks(sync);
+                        BlockAnnounceOutcome::Disjoint {
+                            sync: self,
+                            next_actions,
+                        }
+                    }
+                    all_forks::BlockAnnounceOutcome::BlockBodyDownloadStart {
+                        mut sync,
+                        next_requests,
+                    } => {
+                        let mut next_actions = Vec::with_capacity(next_requests.len());
+                        for (source_id, request_id, request) in next_requests {
+                            next_actions.push(self.shared.all_forks_request_to_request(
+                                &mut sync, source_id, request_id, request,
+                            ));
+                        }
                         self.inner = IdleInner::AllForks(sync);
                         BlockAnnounceOutcome::Disjoint {
                             sync: self,
@@ -584,6 +652,24 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
         debug_assert!(self.shared.requests.contains(request_id.0));
         let request = self.shared.requests.remove(request_id.0);
 
+        // A response containing a block that is marked as bad or that conflicts with a forced
+        // fork is treated the same way as a failed request, as the source is most likely
+        // following the fork that must be avoided.
+        let blocks = blocks
+            .map(|blocks| blocks.collect::<Vec<_>>())
+            .and_then(|blocks| {
+                let has_bad_block = blocks.iter().any(|block| {
+                    header::decode(&block.scale_encoded_header).map_or(false, |header| {
+                        self.shared.is_bad_block(header.number, &header.hash())
+                    })
+                });
+                if has_bad_block {
+                    Err(())
+                } else {
+                    Ok(blocks.into_iter())
+                }
+            });
+
         match (self.inner, request) {
             (IdleInner::GrandpaWarpSync(_), _) => panic!(), // Grandpa warp sync never starts block requests.
             (IdleInner::Optimistic(mut sync), RequestMapping::Optimistic(request_id)) => {
@@ -622,9 +708,9 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
                     }),
                 }
             }
-            (IdleInner::AllForks(sync), RequestMapping::AllForks(source_id)) => {
+            (IdleInner::AllForks(sync), RequestMapping::AllForks(inner_request_id)) => {
                 match sync.ancestry_search_response(
-                    source_id,
+                    inner_request_id,
                     blocks.map(|iter| {
                         iter.map(|block| all_forks::RequestSuccessBlock {
                             scale_encoded_header: block.scale_encoded_header,
@@ -640,17 +726,15 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
                     }
                     all_forks::AncestrySearchResponseOutcome::NotFinalizedChain {
                         mut sync,
-                        next_request,
+                        next_requests,
                         discarded_unverified_block_headers,
                     } => {
-                        let next_actions = match next_request {
-                            Some(nr) => {
-                                vec![self
-                                    .shared
-                                    .all_forks_request_to_request(&mut sync, source_id, nr)]
-                            }
-                            None => Vec::new(),
-                        };
+                        let mut next_actions = Vec::with_capacity(next_requests.len());
+                        for (source_id, request_id, request) in next_requests {
+                            next_actions.push(self.shared.all_forks_request_to_request(
+                                &mut sync, source_id, request_id, request,
+                            ));
+                        }
                         BlocksRequestResponseOutcome::NotFinalizedChain {
                             sync: Idle {
                                 inner: IdleInner::AllForks(sync),
@@ -662,42 +746,111 @@ impl<TRq, TSrc, TBl> Idle<TRq, TSrc, TBl> {
                     }
                     all_forks::AncestrySearchResponseOutcome::Inconclusive {
                         mut sync,
-                        next_request,
+                        next_requests,
+                        penalized_source,
                     } => {
-                        let next_actions = match next_request {
-                            Some(nr) => {
-                                vec![self
-                                    .shared
-                                    .all_forks_request_to_request(&mut sync, source_id, nr)]
-                            }
-                            None => Vec::new(),
-                        };
+                        let penalized_source = penalized_source.map(|source_id| {
+                            sync.source_mut(source_id)
+                                .unwrap()
+                                .into_user_data()
+                                .outer_source_id
+                        });
+
+                        let mut next_actions = Vec::with_capacity(next_requests.len());
+                        for (source_id, request_id, request) in next_requests {
+                            next_actions.push(self.shared.all_forks_request_to_request(
+                                &mut sync, source_id, request_id, request,
+                            ));
+                        }
                         BlocksRequestResponseOutcome::Inconclusive {
                             sync: Idle {
                                 inner: IdleInner::AllForks(sync),
                                 ..self
                             },
                             next_actions,
+                            penalized_source,
                         }
                     }
                     all_forks::AncestrySearchResponseOutcome::AllAlreadyInChain {
                         mut sync,
-                        next_request,
+                        next_requests,
+                        justification_verification,
                     } => {
-                        let next_actions = match next_request {
-                            Some(nr) => {
-                                vec![self
-                                    .shared
-                                    .all_forks_request_to_request(&mut sync, source_id, nr)]
-                            }
-                            None => Vec::new(),
-                        };
+                        let mut next_actions = Vec::with_capacity(next_requests.len());
+                        for (source_id, request_id, request) in next_requests {
+                            next_actions.push(self.shared.all_forks_request_to_request(
+                                &mut sync, source_id, request_id, request,
+                            ));
+                        }
                         BlocksRequestResponseOutcome::AllAlreadyInChain {
                             sync: Idle {
                                 inner: IdleInner::AllForks(sync),
                                 ..self
                             },
                             next_actions,
+                            is_new_finalized: justification_verification.is_success(),
+                        }
+                    }
+                    all_forks::AncestrySearchResponseOutcome::BlockBodyDownloadStart {
+                        mut sync,
+                        next_requests,
+                    } => {
+                        let mut next_actions = Vec::with_capacity(next_requests.len());
+                        for (source_id, request_id, request) in next_requests {
+                            next_actions.push(self.shared.all_forks_request_to_request(
+                                &mut sync, source_id, request_id, request,
+                            ));
+                        }
+                        BlocksRequestResponseOutcome::Queued {
+                            sync: Idle {
+                                inner: IdleInner::AllForks(sync),
+                                ..self
+                            },
+                            next_actions,
+                        }
+                    }
+                }
+            }
+            (IdleInner::AllForks(sync), RequestMapping::AllForksBlockBody(inner_request_id)) => {
+                // Only the first block of the response is relevant. An empty response is
+                // treated the same way as a failed request.
+                match sync.block_body_response(
+                    inner_request_id,
+                    blocks
+                        .and_then(|mut iter| iter.next().ok_or(()))
+                        .map(|block| block.scale_encoded_extrinsics.into_iter()),
+                ) {
+                    all_forks::BlockBodyResponseOutcome::Verify(verify) => {
+                        BlocksRequestResponseOutcome::VerifyBody(BodyVerify {
+                            inner: verify,
+                            shared: self.shared,
+                        })
+                    }
+                    all_forks::BlockBodyResponseOutcome::Inconclusive {
+                        mut sync,
+                        next_requests,
+                        penalized_source,
+                    } => {
+                        let penalized_source = penalized_source.map(|source_id| {
+                            sync.source_mut(source_id)
+                                .unwrap()
+                                .into_user_data()
+                                .outer_source_id
+                        });
+
+                        let mut next_actions = Vec::with_capacity(next_requests.len());
+                        for (source_id, request_id, request) in next_requests {
+                            next_actions.push(self.shared.all_forks_request_to_request(
+                                &mut sync, source_id, request_id, request,
+                            ));
+                        }
+                        BlocksRequestResponseOutcome::Inconclusive {
+                            sync: Idle {
+                                inner: IdleInner::AllForks(sync),
+                                ..self
+                            },
+                            next_actions,
+                            penalized_source,
                         }
                     }
                 }
@@ -1014,6 +1167,9 @@ pub enum BlockAnnounceOutcome<TRq, TSrc, TBl> {
     AlreadyInChain(Idle<TRq, TSrc, TBl>),
     /// Announced block is known to not be a descendant of the finalized block.
     NotFinalizedChain(Idle<TRq, TSrc, TBl>),
+    /// Announced block is in the list of [`Config::bad_blocks`] or conflicts with an entry of
+    /// [`Config::fork_blocks`], and has been ignored.
+    BadBlock(Idle<TRq, TSrc, TBl>),
     /// Header cannot be verified now, and has been stored for later.
     Disjoint {
         sync: Idle<TRq, TSrc, TBl>,
@@ -1032,6 +1188,10 @@ pub enum BlocksRequestResponseOutcome<TRq, TSrc, TBl> {
     /// Ready to start verifying one or more headers returned in the ancestry search.
     VerifyHeader(HeaderVerify<TRq, TSrc, TBl>),
 
+    /// Ready to start verifying the body of a block. Never happens if [`Config::full`] is
+    /// `None`.
+    VerifyBody(BodyVerify<TRq, TSrc, TBl>),
+
     /// Blocks have been queued and will be processed later.
     Queued {
         sync: Idle<TRq, TSrc, TBl>,
@@ -1063,6 +1223,11 @@ pub enum BlocksRequestResponseOutcome<TRq, TSrc, TBl> {
 
         /// Next requests that must be started.
         next_actions: Vec<Action>,
+
+        /// If `Some`, the source the request was sent to was expected to know about the
+        /// requested block, because it has announced this block or one of its descendants, but
+        /// has failed to provide it. The API user is encouraged to penalize this source.
+        penalized_source: Option<SourceId>,
     },
 
     /// All blocks in the ancestry search response were already in the list of verified blocks.
@@ -1074,6 +1239,9 @@ pub enum BlocksRequestResponseOutcome<TRq, TSrc, TBl> {
 
         /// Next requests that must be started.
         next_actions: Vec<Action>,
+
+        /// True if a justification was attached to the block, and has finalized it.
+        is_new_finalized: bool,
     },
 }
 
@@ -1154,17 +1322,23 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
             HeaderVerifyInner::Optimistic(optimistic::ProcessOne::Idle { .. }) => unreachable!(),
             HeaderVerifyInner::Optimistic(optimistic::ProcessOne::NewBest { .. })
             | HeaderVerifyInner::Optimistic(optimistic::ProcessOne::Finalized { .. }) => {
-                let (mut sync, new_best_number, is_new_finalized) = match self.inner {
+                let (mut sync, new_best_number, is_new_finalized, equivocation) = match self.inner {
                     HeaderVerifyInner::Optimistic(optimistic::ProcessOne::NewBest {
                         sync,
                         new_best_number,
+                        equivocation,
                         ..
-                    }) => (sync, new_best_number, false),
+                    }) => (sync, new_best_number, false, equivocation),
                     HeaderVerifyInner::Optimistic(optimistic::ProcessOne::Finalized {
                         sync,
                         finalized_blocks,
-                        ..
-                    }) => (sync, finalized_blocks.last().unwrap().header.number, true),
+                        equivocation,
+                    }) => (
+                        sync,
+                        finalized_blocks.last().unwrap().header.number,
+                        true,
+                        equivocation,
+                    ),
                     _ => unreachable!(),
                 };
 
@@ -1175,6 +1349,7 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                     return HeaderVerifyOutcome::Success {
                         is_new_best: true,
                         is_new_finalized,
+                        equivocation,
                         sync: Idle {
                             inner: IdleInner::AllForks(all_forks),
                             shared: self.shared,
@@ -1193,6 +1368,7 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                     optimistic::ProcessOne::Idle { sync } => HeaderVerifyOutcome::Success {
                         is_new_best: true,
                         is_new_finalized,
+                        equivocation,
                         sync: Idle {
                             inner: IdleInner::Optimistic(sync),
                             shared: self.shared,
@@ -1205,6 +1381,7 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                         HeaderVerifyOutcome::Success {
                             is_new_finalized,
                             is_new_best: true,
+                            equivocation,
                             sync: self.into(),
                             next_actions,
                         }
@@ -1225,7 +1402,9 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                         }
                         .into(),
                         next_actions,
-                        error: verify::header_only::Error::BadBlockNumber, // TODO: this is the completely wrong error; needs some deeper API changes
+                        error: blocks_tree::HeaderVerifyError::VerificationFailed(
+                            verify::header_only::Error::BadBlockNumber,
+                        ), // TODO: this is the completely wrong error; needs some deeper API changes
                         user_data,
                     },
                     other => {
@@ -1233,7 +1412,9 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                         HeaderVerifyOutcome::Error {
                             sync: self.into(),
                             next_actions,
-                            error: verify::header_only::Error::BadBlockNumber, // TODO: this is the completely wrong error; needs some deeper API changes
+                            error: blocks_tree::HeaderVerifyError::VerificationFailed(
+                                verify::header_only::Error::BadBlockNumber,
+                            ), // TODO: this is the completely wrong error; needs some deeper API changes
                             user_data,
                         }
                     }
@@ -1247,50 +1428,30 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                 unreachable!()
             }
             HeaderVerifyInner::AllForks(verify) => {
-                let source_id = verify.source_id();
                 match verify.perform(now_from_unix_epoch, user_data) {
                     all_forks::HeaderVerifyOutcome::Success {
                         is_new_best,
+                        equivocation,
                         mut sync,
-                        next_request,
-                        requests_replace,
+                        next_requests,
+                        cancelled_requests,
                         justification_verification,
                     } => {
-                        let mut next_actions = Vec::with_capacity(
-                            requests_replace.len() + if next_request.is_some() { 1 } else { 0 },
-                        );
-
-                        for (source, new_request) in requests_replace {
-                            // TODO: O(n)
-                            let outer_request_id = self
-                                .shared
-                                .requests
-                                .iter()
-                                .find(|(_, s)| **s == RequestMapping::AllForks(source))
-                                .map(|(id, _)| RequestId(id))
-                                .unwrap();
-                            self.shared.requests.remove(outer_request_id.0);
-                            next_actions.push(Action::Cancel(outer_request_id));
-
-                            if let Some(new_request) = new_request {
-                                next_actions.push(self.shared.all_forks_request_to_request(
-                                    &mut sync,
-                                    source,
-                                    new_request,
-                                ));
-                            }
+                        let mut next_actions =
+                            Vec::with_capacity(cancelled_requests.len() + next_requests.len());
+                        for (_, request_id) in cancelled_requests {
+                            next_actions.push(self.shared.all_forks_cancel_request(request_id));
                         }
-
-                        if let Some(request) = next_request {
-                            next_actions.push(
-                                self.shared
-                                    .all_forks_request_to_request(&mut sync, source_id, request),
-                            );
+                        for (source_id, request_id, request) in next_requests {
+                            next_actions.push(self.shared.all_forks_request_to_request(
+                                &mut sync, source_id, request_id, request,
+                            ));
                         }
 
                         HeaderVerifyOutcome::Success {
                             is_new_best,
                             is_new_finalized: justification_verification.is_success(),
+                            equivocation,
                             sync: Idle {
                                 inner: IdleInner::AllForks(sync),
                                 shared: self.shared,
@@ -1301,31 +1462,20 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                     }
                     all_forks::HeaderVerifyOutcome::SuccessContinue {
                         is_new_best,
+                        equivocation,
                         next_block,
-                        requests_replace,
+                        cancelled_requests,
                         justification_verification,
                     } => {
-                        let mut next_actions = Vec::with_capacity(requests_replace.len());
-                        for (source, new_request) in requests_replace {
-                            // TODO: O(n)
-                            let outer_request_id = self
-                                .shared
-                                .requests
-                                .iter()
-                                .find(|(_, s)| **s == RequestMapping::AllForks(source))
-                                .map(|(id, _)| RequestId(id))
-                                .unwrap();
-                            self.shared.requests.remove(outer_request_id.0);
-                            next_actions.push(Action::Cancel(outer_request_id));
-
-                            if let Some(_new_request) = new_request {
-                                todo!() // Need to call `all_forks_request_to_request`
-                            }
+                        let mut next_actions = Vec::with_capacity(cancelled_requests.len());
+                        for (_, request_id) in cancelled_requests {
+                            next_actions.push(self.shared.all_forks_cancel_request(request_id));
                         }
 
                         HeaderVerifyOutcome::Success {
                             is_new_best,
                             is_new_finalized: justification_verification.is_success(),
+                            equivocation,
                             sync: HeaderVerify {
                                 inner: HeaderVerifyInner::AllForks(next_block),
                                 shared: self.shared,
@@ -1338,39 +1488,18 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                         mut sync,
                         error,
                         user_data,
-                        next_request,
-                        requests_replace,
+                        next_requests,
+                        cancelled_requests,
                     } => {
-                        let mut next_actions = Vec::with_capacity(
-                            requests_replace.len() + if next_request.is_some() { 1 } else { 0 },
-                        );
-
-                        for (source, new_request) in requests_replace {
-                            // TODO: O(n)
-                            let outer_request_id = self
-                                .shared
-                                .requests
-                                .iter()
-                                .find(|(_, s)| **s == RequestMapping::AllForks(source))
-                                .map(|(id, _)| RequestId(id))
-                                .unwrap();
-                            self.shared.requests.remove(outer_request_id.0);
-                            next_actions.push(Action::Cancel(outer_request_id));
-
-                            if let Some(new_request) = new_request {
-                                next_actions.push(self.shared.all_forks_request_to_request(
-                                    &mut sync,
-                                    source,
-                                    new_request,
-                                ));
-                            }
+                        let mut next_actions =
+                            Vec::with_capacity(cancelled_requests.len() + next_requests.len());
+                        for (_, request_id) in cancelled_requests {
+                            next_actions.push(self.shared.all_forks_cancel_request(request_id));
                         }
-
-                        if let Some(request) = next_request {
-                            next_actions.push(
-                                self.shared
-                                    .all_forks_request_to_request(&mut sync, source_id, request),
-                            );
+                        for (source_id, request_id, request) in next_requests {
+                            next_actions.push(self.shared.all_forks_request_to_request(
+                                &mut sync, source_id, request_id, request,
+                            ));
                         }
 
                         HeaderVerifyOutcome::Error {
@@ -1388,24 +1517,11 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                         next_block,
                         error,
                         user_data,
-                        requests_replace,
+                        cancelled_requests,
                     } => {
-                        let mut next_actions = Vec::with_capacity(requests_replace.len());
-                        for (source, new_request) in requests_replace {
-                            // TODO: O(n)
-                            let outer_request_id = self
-                                .shared
-                                .requests
-                                .iter()
-                                .find(|(_, s)| **s == RequestMapping::AllForks(source))
-                                .map(|(id, _)| RequestId(id))
-                                .unwrap();
-                            self.shared.requests.remove(outer_request_id.0);
-                            next_actions.push(Action::Cancel(outer_request_id));
-
-                            if let Some(_new_request) = new_request {
-                                todo!() // Need to call `all_forks_request_to_request`
-                            }
+                        let mut next_actions = Vec::with_capacity(cancelled_requests.len());
+                        for (_, request_id) in cancelled_requests {
+                            next_actions.push(self.shared.all_forks_cancel_request(request_id));
                         }
 
                         HeaderVerifyOutcome::Error {
@@ -1433,6 +1549,9 @@ pub enum HeaderVerifyOutcome<TRq, TSrc, TBl> {
         is_new_best: bool,
         /// True if the newly-verified block is considered the latest finalized block.
         is_new_finalized: bool,
+        /// If `Some`, the author of the newly-verified block has already produced a different
+        /// block in the same slot. The block has nonetheless been inserted in the chain.
+        equivocation: Option<blocks_tree::EquivocationProof>,
         /// State machine yielded back. Use to continue the processing.
         sync: AllSync<TRq, TSrc, TBl>,
         /// Next requests that must be started.
@@ -1444,7 +1563,7 @@ pub enum HeaderVerifyOutcome<TRq, TSrc, TBl> {
         /// State machine yielded back. Use to continue the processing.
         sync: AllSync<TRq, TSrc, TBl>,
         /// Error that happened.
-        error: verify::header_only::Error,
+        error: blocks_tree::HeaderVerifyError,
         /// User data that was passed to [`HeaderVerify::perform`] and is unused.
         user_data: TBl,
         /// Next requests that must be started.
@@ -1452,6 +1571,236 @@ pub enum HeaderVerifyOutcome<TRq, TSrc, TBl> {
     },
 }
 
+/// Block body verification to be performed.
+pub struct BodyVerify<TRq, TSrc, TBl> {
+    inner: all_forks::BodyVerify<AllForksSourceExtra<TRq, TSrc>, TBl>,
+    shared: Shared,
+}
+
+impl<TRq, TSrc, TBl> BodyVerify<TRq, TSrc, TBl> {
+    /// Perform the verification.
+    pub fn perform(
+        self,
+        now_from_unix_epoch: Duration,
+        user_data: TBl,
+    ) -> BodyVerifyOutcome<TRq, TSrc, TBl> {
+        BodyVerifyOutcome::from_all_forks(
+            self.inner.perform(now_from_unix_epoch, user_data),
+            self.shared,
+        )
+    }
+}
+
+/// Outcome of calling [`BodyVerify::perform`].
+pub enum BodyVerifyOutcome<TRq, TSrc, TBl> {
+    /// Block has been successfully verified and inserted in the chain.
+    Success {
+        /// True if the newly-verified block is considered the new best block.
+        is_new_best: bool,
+        /// True if the newly-verified block is considered the latest finalized block.
+        is_new_finalized: bool,
+        /// If `Some`, the author of the newly-verified block has already produced a different
+        /// block in the same slot. The block has nonetheless been inserted in the chain.
+        equivocation: Option<blocks_tree::EquivocationProof>,
+        /// State machine yielded back. Use to continue the processing.
+        sync: AllSync<TRq, TSrc, TBl>,
+        /// Next requests that must be started.
+        next_actions: Vec<Action>,
+        /// SCALE-encoded header of the newly-verified block.
+        scale_encoded_header: Vec<u8>,
+        /// List of SCALE-encoded extrinsics of the newly-verified block.
+        block_body: Vec<Vec<u8>>,
+        /// List of changes to the storage top trie that the block performs compared to its
+        /// parent.
+        storage_top_trie_changes: hashbrown::HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+        /// List of changes to the offchain storage that this block performs.
+        offchain_storage_changes: hashbrown::HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+    },
+
+    /// Block verification failed.
+    Error {
+        /// State machine yielded back. Use to continue the processing.
+        sync: AllSync<TRq, TSrc, TBl>,
+        /// Error that happened.
+        error: all_forks::BodyVerifyError,
+        /// User data that was passed to [`BodyVerify::perform`] and is unused.
+        user_data: TBl,
+        /// Next requests that must be started.
+        next_actions: Vec<Action>,
+    },
+
+    /// Loading a storage value of the finalized block is required in order to continue.
+    FinalizedStorageGet(StorageGet<TRq, TSrc, TBl>),
+
+    /// Fetching the list of keys of the finalized block with a given prefix is required in order
+    /// to continue.
+    FinalizedStoragePrefixKeys(StoragePrefixKeys<TRq, TSrc, TBl>),
+
+    /// Fetching the key of the finalized block storage that follows a given one is required in
+    /// order to continue.
+    FinalizedStorageNextKey(StorageNextKey<TRq, TSrc, TBl>),
+}
+
+impl<TRq, TSrc, TBl> BodyVerifyOutcome<TRq, TSrc, TBl> {
+    fn from_all_forks(
+        inner: all_forks::BlockBodyVerify<AllForksSourceExtra<TRq, TSrc>, TBl>,
+        mut shared: Shared,
+    ) -> Self {
+        match inner {
+            all_forks::BlockBodyVerify::Success {
+                is_new_best,
+                equivocation,
+                justification_verification,
+                mut sync,
+                next_requests,
+                cancelled_requests,
+                scale_encoded_header,
+                block_body,
+                storage_top_trie_changes,
+                offchain_storage_changes,
+            } => {
+                let mut next_actions =
+                    Vec::with_capacity(cancelled_requests.len() + next_requests.len());
+                for (_, request_id) in cancelled_requests {
+                    next_actions.push(shared.all_forks_cancel_request(request_id));
+                }
+                for (source_id, request_id, request) in next_requests {
+                    next_actions.push(
+                        shared.all_forks_request_to_request(
+                            &mut sync, source_id, request_id, request,
+                        ),
+                    );
+                }
+
+                BodyVerifyOutcome::Success {
+                    is_new_best,
+                    is_new_finalized: justification_verification.is_success(),
+                    equivocation,
+                    sync: Idle {
+                        inner: IdleInner::AllForks(sync),
+                        shared,
+                    }
+                    .into(),
+                    next_actions,
+                    scale_encoded_header,
+                    block_body,
+                    storage_top_trie_changes,
+                    offchain_storage_changes,
+                }
+            }
+            all_forks::BlockBodyVerify::Error {
+                mut sync,
+                error,
+                user_data,
+                next_requests,
+            } => {
+                let mut next_actions = Vec::with_capacity(next_requests.len());
+                for (source_id, request_id, request) in next_requests {
+                    next_actions.push(
+                        shared.all_forks_request_to_request(
+                            &mut sync, source_id, request_id, request,
+                        ),
+                    );
+                }
+
+                BodyVerifyOutcome::Error {
+                    sync: Idle {
+                        inner: IdleInner::AllForks(sync),
+                        shared,
+                    }
+                    .into(),
+                    error,
+                    user_data,
+                    next_actions,
+                }
+            }
+            all_forks::BlockBodyVerify::FinalizedStorageGet(inner) => {
+                BodyVerifyOutcome::FinalizedStorageGet(StorageGet { inner, shared })
+            }
+            all_forks::BlockBodyVerify::FinalizedStoragePrefixKeys(inner) => {
+                BodyVerifyOutcome::FinalizedStoragePrefixKeys(StoragePrefixKeys { inner, shared })
+            }
+            all_forks::BlockBodyVerify::FinalizedStorageNextKey(inner) => {
+                BodyVerifyOutcome::FinalizedStorageNextKey(StorageNextKey { inner, shared })
+            }
+        }
+    }
+}
+
+/// Loading a storage value of the finalized block is required in order to continue.
+#[must_use]
+pub struct StorageGet<TRq, TSrc, TBl> {
+    inner: all_forks::StorageGet<AllForksSourceExtra<TRq, TSrc>, TBl>,
+    shared: Shared,
+}
+
+impl<TRq, TSrc, TBl> StorageGet<TRq, TSrc, TBl> {
+    /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl Iterator<Item = impl AsRef<[u8]> + '_> + '_ {
+        self.inner.key()
+    }
+
+    /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
+    ///
+    /// This method is a shortcut for calling `key` and concatenating the returned slices.
+    pub fn key_as_vec(&self) -> Vec<u8> {
+        self.inner.key_as_vec()
+    }
+
+    /// Injects the corresponding storage value.
+    pub fn inject_value(self, value: Option<&[u8]>) -> BodyVerifyOutcome<TRq, TSrc, TBl> {
+        BodyVerifyOutcome::from_all_forks(self.inner.inject_value(value), self.shared)
+    }
+}
+
+/// Fetching the list of keys of the finalized block with a given prefix is required in order
+/// to continue.
+#[must_use]
+pub struct StoragePrefixKeys<TRq, TSrc, TBl> {
+    inner: all_forks::StoragePrefixKeys<AllForksSourceExtra<TRq, TSrc>, TBl>,
+    shared: Shared,
+}
+
+impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
+    /// Returns the prefix whose keys to load.
+    pub fn prefix(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.prefix()
+    }
+
+    /// Injects the list of keys.
+    pub fn inject_keys(
+        self,
+        keys: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> BodyVerifyOutcome<TRq, TSrc, TBl> {
+        BodyVerifyOutcome::from_all_forks(self.inner.inject_keys(keys), self.shared)
+    }
+}
+
+/// Fetching the key of the finalized block storage that follows a given one is required in
+/// order to continue.
+#[must_use]
+pub struct StorageNextKey<TRq, TSrc, TBl> {
+    inner: all_forks::StorageNextKey<AllForksSourceExtra<TRq, TSrc>, TBl>,
+    shared: Shared,
+}
+
+impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
+    /// Returns the key whose next key must be passed back.
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.key()
+    }
+
+    /// Injects the key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the key passed as parameter isn't strictly superior to the requested key.
+    ///
+    pub fn inject_key(self, key: Option<impl AsRef<[u8]>>) -> BodyVerifyOutcome<TRq, TSrc, TBl> {
+        BodyVerifyOutcome::from_all_forks(self.inner.inject_key(key), self.shared)
+    }
+}
+
 enum IdleInner<TRq, TSrc, TBl> {
     Optimistic(optimistic::OptimisticSync<(), OptimisticSourceExtra<TSrc>, TBl>),
     /// > **Note**: Must never contain [`grandpa_warp_sync::GrandpaWarpSync::Finished`].
@@ -1484,9 +1833,23 @@ struct Shared {
     requests: slab::Slab<RequestMapping>,
     // TODO: this is an insecure way to do things; see https://github.com/paritytech/smoldot/issues/490
     highest_block_on_network: u64,
+    /// See [`Config::bad_blocks`]. Passed to the underlying syncing strategies.
+    bad_blocks: Vec<[u8; 32]>,
+    /// See [`Config::fork_blocks`]. Passed to the underlying syncing strategies.
+    fork_blocks: Vec<(u64, [u8; 32])>,
 }
 
 impl Shared {
+    /// Returns true if the block with the given height and hash is in the list of bad blocks,
+    /// or conflicts with a forced fork block.
+    fn is_bad_block(&self, height: u64, hash: &[u8; 32]) -> bool {
+        self.bad_blocks.iter().any(|h| h == hash)
+            || self
+                .fork_blocks
+                .iter()
+                .any(|(n, h)| *n == height && h != hash)
+    }
+
     fn optimistic_action_to_request<TSrc, TBl>(
         &mut self,
         action: optimistic::RequestAction<(), OptimisticSourceExtra<TSrc>, TBl>,
@@ -1541,9 +1904,15 @@ impl Shared {
         &mut self,
         all_forks: &mut all_forks::AllForksSync<AllForksSourceExtra<TRq, TSrc>, TBl>,
         source_id: all_forks::SourceId,
+        inner_request_id: all_forks::RequestId,
         request: all_forks::Request,
     ) -> Action {
-        let request_id = RequestId(self.requests.insert(RequestMapping::AllForks(source_id)));
+        let request_id = RequestId(self.requests.insert(match request {
+            all_forks::Request::BodyRequest { .. } => {
+                RequestMapping::AllForksBlockBody(inner_request_id)
+            }
+            _ => RequestMapping::AllForks(inner_request_id),
+        }));
 
         let outer_source_id = all_forks
             .source_mut(source_id)
@@ -1551,16 +1920,34 @@ impl Shared {
             .into_user_data()
             .outer_source_id;
 
-        let (first_block, num_blocks) = match request {
+        let (first_block, num_blocks, request_bodies, request_justification) = match request {
             all_forks::Request::AncestrySearch {
                 first_block_hash,
                 num_blocks,
-            } => (BlocksRequestFirstBlock::Hash(first_block_hash), num_blocks),
+            } => (
+                BlocksRequestFirstBlock::Hash(first_block_hash),
+                num_blocks,
+                false,
+                false,
+            ),
             all_forks::Request::HeaderRequest { hash, .. } => (
                 BlocksRequestFirstBlock::Hash(hash),
                 NonZeroU64::new(1).unwrap(),
+                false,
+                false,
+            ),
+            all_forks::Request::JustificationRequest { hash, .. } => (
+                BlocksRequestFirstBlock::Hash(hash),
+                NonZeroU64::new(1).unwrap(),
+                false,
+                true,
+            ),
+            all_forks::Request::BodyRequest { hash, .. } => (
+                BlocksRequestFirstBlock::Hash(hash),
+                NonZeroU64::new(1).unwrap(),
+                true,
+                false,
             ),
-            all_forks::Request::BodyRequest { .. } => todo!(),
         };
 
         Action::Start {
@@ -1570,13 +1957,32 @@ impl Shared {
                 first_block,
                 ascending: false,
                 num_blocks,
-                request_bodies: false,
+                request_bodies,
                 request_headers: true,
-                request_justification: true, // TODO: only do this on blocks that change the GP authorities?
+                request_justification,
             },
         }
     }
 
+    /// Removes from [`Shared::requests`] the request corresponding to the given all-forks
+    /// request, and returns the action that cancels it.
+    fn all_forks_cancel_request(&mut self, inner_request_id: all_forks::RequestId) -> Action {
+        // TODO: O(n)
+        let outer_request_id = self
+            .requests
+            .iter()
+            .find(|(_, s)| match s {
+                RequestMapping::AllForks(id) | RequestMapping::AllForksBlockBody(id) => {
+                    *id == inner_request_id
+                }
+                _ => false,
+            })
+            .map(|(id, _)| RequestId(id))
+            .unwrap();
+        self.requests.remove(outer_request_id.0);
+        Action::Cancel(outer_request_id)
+    }
+
     fn grandpa_warp_sync_request_to_request<TSrc>(
         &mut self,
         grandpa_warp_sync: &grandpa_warp_sync::WarpSyncRequest<GrandpaWarpSyncSourceExtra<TSrc>>,
@@ -1619,14 +2025,20 @@ impl Shared {
             sources_capacity: 1024,
             blocks_capacity: 1024,
             max_disjoint_headers: 1024,
-            max_requests_per_block: NonZeroU32::new(128).unwrap(),
-            full: false,
+            max_requests_per_block: NonZeroU32::new(1).unwrap(),
+            max_requests_per_source: NonZeroU32::new(4).unwrap(),
+            max_known_blocks_per_source: 1024,
+            bad_blocks: self.bad_blocks.clone(),
+            fork_blocks: self.fork_blocks.clone(),
+            full: disassembled
+                .finalized_runtime
+                .map(|finalized_runtime| all_forks::ConfigFull { finalized_runtime }),
         });
 
         let mut all_forks_demands = Vec::with_capacity(disassembled.sources.len());
 
         for source in disassembled.sources {
-            let (updated_source_id, request) = all_forks.add_source(
+            let (updated_source_id, next_requests) = all_forks.add_source(
                 AllForksSourceExtra {
                     user_data: source.user_data.user_data,
                     outer_source_id: source.user_data.outer_source_id,
@@ -1635,7 +2047,6 @@ impl Shared {
                 source.best_block_number,
                 source.user_data.best_block_hash,
             );
-            let updated_source_id = updated_source_id.id();
 
             debug_assert_eq!(
                 self.sources[source.user_data.outer_source_id.0],
@@ -1645,9 +2056,7 @@ impl Shared {
             self.sources[source.user_data.outer_source_id.0] =
                 SourceMapping::AllForks(updated_source_id);
 
-            if let Some(request) = request {
-                all_forks_demands.push((updated_source_id, request));
-            }
+            all_forks_demands.extend(next_requests);
         }
 
         debug_assert!(self
@@ -1660,8 +2069,13 @@ impl Shared {
             next_actions.push(Action::Cancel(RequestId(request_id)));
         }
         self.requests.clear();
-        for (source_id, demand) in all_forks_demands {
-            next_actions.push(self.all_forks_request_to_request(&mut all_forks, source_id, demand));
+        for (source_id, request_id, demand) in all_forks_demands {
+            next_actions.push(self.all_forks_request_to_request(
+                &mut all_forks,
+                source_id,
+                request_id,
+                demand,
+            ));
         }
 
         (all_forks, next_actions)
@@ -1688,14 +2102,18 @@ impl Shared {
             sources_capacity: 1024,
             blocks_capacity: 1024,
             max_disjoint_headers: 1024,
-            max_requests_per_block: NonZeroU32::new(128).unwrap(),
-            full: false,
+            max_requests_per_block: NonZeroU32::new(1).unwrap(),
+            max_requests_per_source: NonZeroU32::new(4).unwrap(),
+            max_known_blocks_per_source: 1024,
+            bad_blocks: self.bad_blocks.clone(),
+            fork_blocks: self.fork_blocks.clone(),
+            full: None,
         });
 
         let mut all_forks_demands = Vec::with_capacity(grandpa.sources.len());
 
         for source in grandpa.sources {
-            let (updated_source_id, request) = all_forks.add_source(
+            let (updated_source_id, next_requests) = all_forks.add_source(
                 AllForksSourceExtra {
                     user_data: source.user_data,
                     outer_source_id: source.outer_source_id,
@@ -1704,13 +2122,10 @@ impl Shared {
                 source.best_block_number,
                 source.best_block_hash,
             );
-            let updated_source_id = updated_source_id.id();
 
             self.sources[source.outer_source_id.0] = SourceMapping::AllForks(updated_source_id);
 
-            if let Some(request) = request {
-                all_forks_demands.push((updated_source_id, request));
-            }
+            all_forks_demands.extend(next_requests);
         }
 
         debug_assert!(self
@@ -1720,8 +2135,13 @@ impl Shared {
 
         let mut next_actions = Vec::with_capacity(self.requests.len() + all_forks_demands.len());
         self.requests.clear();
-        for (source_id, demand) in all_forks_demands {
-            next_actions.push(self.all_forks_request_to_request(&mut all_forks, source_id, demand));
+        for (source_id, request_id, demand) in all_forks_demands {
+            next_actions.push(self.all_forks_request_to_request(
+                &mut all_forks,
+                source_id,
+                request_id,
+                demand,
+            ));
         }
 
         (all_forks, next_actions)
@@ -1732,7 +2152,10 @@ impl Shared {
 enum RequestMapping {
     Optimistic(optimistic::RequestId),
     GrandpaWarpSync,
-    AllForks(all_forks::SourceId),
+    AllForks(all_forks::RequestId),
+    /// Same as [`RequestMapping::AllForks`], but for a request whose response contains the body
+    /// of a block.
+    AllForksBlockBody(all_forks::RequestId),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -48,7 +48,19 @@
 //! non-finalized blocks (`full` equal to `false`), or the headers, and bodies, and storage
 //! (`full` equal to `true`).
 //!
-//! In full mode, .
+//! In full mode, the header of a block is verified at the same time as its body. Once the parent
+//! of a block has been verified, a [`Request::BodyRequest`] is emitted in order to download the
+//! body of this block, and the response must be passed to [`AllForksSync::block_body_response`].
+//! The body is then executed on top of the storage of the parent block, and the changes to the
+//! storage that the block performs are reported back to the API user, who is expected to store
+//! them in a database.
+//!
+//! The state machine keeps in memory the storage changes of all the non-finalized blocks. The
+//! storage of the latest finalized block, however, must be provided by the API user, through
+//! [`BlockBodyVerify::FinalizedStorageGet`], [`BlockBodyVerify::FinalizedStoragePrefixKeys`],
+//! and [`BlockBodyVerify::FinalizedStorageNextKey`]. Consequently, whenever blocks get
+//! finalized, the API user must immediately apply their storage changes to the storage of the
+//! finalized block that it maintains.
 //!
 //! # Bounded and unbounded containers
 //!
@@ -58,10 +70,10 @@
 //! The state in this state machine can be put into three categories:
 //!
 //! - Each source of blocks has a certain fixed-size state associated to it (containing for
-//!   instance its best block number and height). Each source also has up to one in-flight
-//!   request, which might incur more memory usage. Managing this additional request is out of
-//!   scope of this module. The user of this module is expected to limit the number of
-//!   simultaneous sources.
+//!   instance its best block number and height). Each source also has up to
+//!   [`Config::max_requests_per_source`] in-flight requests, which might incur more memory
+//!   usage. Managing these additional requests is out of scope of this module. The user of this
+//!   module is expected to limit the number of simultaneous sources.
 //!
 //! - A set of verified blocks that descend from the latest finalized block. This set is
 //!   unbounded. The consensus and finalization algorithms of the chain are supposed to limit
@@ -69,9 +81,13 @@
 //!
 //! - A set of blocks that can't be verified yet. Receiving a block announce inserts an element
 //!   in this set. In order to handle situations where a malicious source announces lots of
-//!   invalid blocks, this set must be bounded. Once it has reached a certain size, the blocks
-//!   with the highest block number are discarded if their parent is also in this set or being
-//!   downloaded from a source.
+//!   invalid blocks, this set must be bounded. Once it has reached
+//!   [`Config::max_disjoint_headers`], the blocks with the highest block number are discarded,
+//!   unless they are being downloaded from a source.
+//!
+//! - For each source, the list of blocks it has announced. This list is bounded by
+//!   [`Config::max_known_blocks_per_source`]. Sources that announce blocks that they later fail
+//!   to provide are reported to the API user.
 //!
 //! Consequently, and assuming that the number of simultaneous sources is bounded, and that
 //! the consensus and finalization algorithms of the chain are properly configured, malicious
@@ -79,24 +95,58 @@
 //! Malicious sources, however, can potentially increase the number of block requests required to
 //! download a long fork. This is, at most, an annoyance, and not a vulnerability.
 //!
+//! # Requests
+//!
+//! Requests are never started by the API user. Instead, the various methods of the state machine
+//! return the list of requests that must be started, each associated with a [`RequestId`].
+//!
+//! Each request targets a specific block. A request is only ever sent to a source that is known
+//! to know about the targeted block, in other words a source that has announced this block or
+//! one of its children. When multiple sources are eligible, the one with the fewest requests
+//! in progress is chosen. The same block is never requested twice from the same source at the
+//! same time, and at most [`Config::max_requests_per_block`] requests target the same block.
+//!
+//! # Justifications
+//!
+//! Justifications are not requested alongside with block headers. Instead, once a block that
+//! contains a change in the list of GrandPa authorities has been verified, a
+//! [`Request::JustificationRequest`] is emitted for this block. Without this justification, it
+//! wouldn't be possible to verify the justifications of the descendants of this block, and
+//! finality would stall if the commit messages gossiped on the network are missed.
+//!
+//! Sources might not have the justification yet when it is first requested. Call
+//! [`AllForksSync::retry_justification_requests`] periodically in order to try again, and to
+//! additionally request the justification of the current best block.
+//!
 
 // TODO: finish ^
 
 use crate::{
     chain::{blocks_tree, chain_information},
-    header, verify,
+    executor::host,
+    header,
+    sync::storage_diff,
+    trie::{self, calculate_root},
+    util, verify,
 };
 
-use alloc::{collections::VecDeque, vec::Vec};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
 use core::{
-    iter, mem,
+    convert::TryFrom as _,
+    iter,
     num::{NonZeroU32, NonZeroU64},
     time::Duration,
 };
+use hashbrown::HashMap;
 
 mod pending_blocks;
 mod sources;
+mod tests;
 
+pub use pending_blocks::RequestId;
 pub use sources::SourceId;
 
 /// Configuration for the [`AllForksSync`].
@@ -147,8 +197,41 @@ pub struct Config {
     /// The higher the value, the more bandwidth is potentially wasted.
     pub max_requests_per_block: NonZeroU32,
 
-    /// If true, the block bodies and storage are also synchronized.
-    pub full: bool,
+    /// Maximum number of simultaneous pending requests made towards the same source.
+    ///
+    /// A higher value makes it possible to download blocks from fewer sources at a faster rate,
+    /// at the cost of increasing the load on each of these sources.
+    pub max_requests_per_source: NonZeroU32,
+
+    /// Maximum number of blocks that each source is known to know about, not including the best
+    /// block of the source. A good default is 1024.
+    ///
+    /// Each block announced by a source is tracked, in order to know which source can be asked
+    /// for which block. Once this limit is reached, the blocks with the highest height are
+    /// forgotten first.
+    pub max_known_blocks_per_source: usize,
+
+    /// List of hashes of blocks that must never be part of the chain.
+    ///
+    /// > **Note**: This list is typically found in the chain specification.
+    pub bad_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes. At each of these heights, only the block with the
+    /// corresponding hash can be part of the chain.
+    ///
+    /// > **Note**: This list is typically found in the chain specification.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
+    /// If `Some`, the block bodies and storage are also synchronized. Contains the extra
+    /// configuration.
+    pub full: Option<ConfigFull>,
+}
+
+/// See [`Config::full`].
+#[derive(Debug)]
+pub struct ConfigFull {
+    /// Compiled runtime code of the finalized block.
+    pub finalized_runtime: host::HostVmPrototype,
 }
 
 pub struct AllForksSync<TSrc, TBl> {
@@ -163,11 +246,16 @@ pub struct AllForksSync<TSrc, TBl> {
 
 /// Extra fields. In a separate structure in order to be moved around.
 struct Inner<TSrc> {
-    /// See [`Config::full`].
+    /// True if [`Config::full`] was `Some`.
     full: bool,
 
+    /// See [`ConfigFull::finalized_runtime`]. Always `None` if not [`Inner::full`].
+    ///
+    /// While a block body is being verified, this might temporarily be `None` as well, as the
+    /// runtime is lent to the verification.
+    finalized_runtime: Option<host::HostVmPrototype>,
+
     /// List of sources. Controlled by the API user.
-    // TODO: somehow limit the size of this container, to avoid growing forever if the source continuously announces fake blocks?
     sources: sources::AllForksSources<Source<TSrc>>,
 
     /// List of blocks whose existence is known but can't be verified yet.
@@ -175,6 +263,18 @@ struct Inner<TSrc> {
     /// information in [`Inner::sources`].
     /// For each request, contains as user data the source that has emitted it.
     pending_blocks: pending_blocks::PendingBlocks<PendingBlock, SourceId>,
+
+    /// See [`Config::max_requests_per_source`].
+    /// Since it is always compared with `usize`s, converted to `usize` ahead of time.
+    max_requests_per_source: usize,
+
+    /// See [`Config::max_disjoint_headers`].
+    max_disjoint_headers: usize,
+
+    /// List of non-finalized blocks in [`AllForksSync::chain`] whose justification should be
+    /// downloaded. The value is `true` if a request has already been started for this block
+    /// since the last call to [`AllForksSync::retry_justification_requests`].
+    justification_requests: BTreeMap<(u64, [u8; 32]), bool>,
 }
 
 struct PendingBlock {
@@ -183,13 +283,32 @@ struct PendingBlock {
 
 struct Block<TBl> {
     header: header::Header,
+    /// `Some` if and only if [`Inner::full`].
+    full: Option<BlockFull>,
     user_data: TBl,
 }
 
+/// Extra fields of a block in full mode.
+struct BlockFull {
+    /// Changes to the storage made by this block compared to its parent.
+    storage_top_trie_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+
+    /// If the block modifies the runtime, contains the compiled new runtime. `None` if the
+    /// runtime is the same as the one of the parent.
+    ///
+    /// While a child of this block is being verified, this might temporarily be `None` as well,
+    /// as the runtime is lent to the verification.
+    runtime: Option<host::HostVmPrototype>,
+
+    /// Cache of calculation for the storage trie of this block. Passed to the verification of
+    /// the first child of this block in order to speed it up.
+    top_trie_root_calculation_cache: Option<calculate_root::CalculationCache>,
+}
+
 /// Extra fields specific to each blocks source.
 struct Source<TSrc> {
-    /// What the source is busy doing.
-    occupation: Option<pending_blocks::RequestId>,
+    /// Number of requests in [`Inner::pending_blocks`] whose user data is this source.
+    num_ongoing_requests: usize,
     user_data: TSrc,
 }
 
@@ -201,20 +320,29 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
         let chain = blocks_tree::NonFinalizedTree::new(blocks_tree::Config {
             chain_information: config.chain_information,
             blocks_capacity: config.blocks_capacity,
+            bad_blocks: config.bad_blocks,
+            fork_blocks: config.fork_blocks,
+            fork_choice: None,
         });
 
         Self {
             chain,
             inner: Inner {
-                full: config.full,
+                full: config.full.is_some(),
+                finalized_runtime: config.full.map(|full| full.finalized_runtime),
                 sources: sources::AllForksSources::new(
                     config.sources_capacity,
                     finalized_block_height,
+                    config.max_known_blocks_per_source,
                 ),
                 pending_blocks: pending_blocks::PendingBlocks::new(pending_blocks::Config {
                     blocks_capacity: config.blocks_capacity,
                     max_requests_per_block: config.max_requests_per_block,
                 }),
+                max_requests_per_source: usize::try_from(config.max_requests_per_source.get())
+                    .unwrap_or(usize::max_value()),
+                max_disjoint_headers: config.max_disjoint_headers,
+                justification_requests: BTreeMap::new(),
             },
         }
     }
@@ -259,22 +387,26 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
     /// The `user_data` parameter is opaque and decided entirely by the user. It can later be
     /// retrieved using [`SourceMutAccess::user_data`].
     ///
-    /// Returns the newly-created source entry, plus optionally a request that should be started
-    /// towards this source.
+    /// Returns the identifier of the newly-created source, plus a list of requests that should
+    /// be started. These requests aren't necessarily targeting this new source.
     pub fn add_source(
         &mut self,
         user_data: TSrc,
         best_block_number: u64,
         best_block_hash: [u8; 32],
-    ) -> (SourceMutAccess<TSrc, TBl>, Option<Request>) {
-        let new_source = self.inner.sources.add_source(
-            Source {
-                occupation: None,
-                user_data,
-            },
-            best_block_number,
-            best_block_hash,
-        );
+    ) -> (SourceId, Vec<(SourceId, RequestId, Request)>) {
+        let source_id = self
+            .inner
+            .sources
+            .add_source(
+                Source {
+                    num_ongoing_requests: 0,
+                    user_data,
+                },
+                best_block_number,
+                best_block_hash,
+            )
+            .id();
 
         if best_block_number > self.chain.finalized_block_header().number
             && self
@@ -288,18 +420,11 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
                 .or_insert(PendingBlock {
                     justification: None,
                 });
+            self.enforce_pending_blocks_limit();
         }
 
-        let source_id = new_source.id();
-        let request = self.source_next_request(source_id);
-
-        (
-            SourceMutAccess {
-                parent: self,
-                source_id,
-            },
-            request,
-        )
+        let next_requests = self.next_requests();
+        (source_id, next_requests)
     }
 
     /// Grants access to a source, using its identifier.
@@ -314,7 +439,10 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
         }
     }
 
-    /// Call in response to a [`Request::AncestrySearch`].
+    /// Call in response to a [`Request::AncestrySearch`] or a [`Request::JustificationRequest`].
+    ///
+    /// The [`RequestId`] is the one that was returned alongside with the request. It is
+    /// immediately considered invalid.
     ///
     /// The headers are expected to be sorted in decreasing order. The first element of the
     /// iterator should be the block with the hash passed through
@@ -326,29 +454,25 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
     ///
     /// # Panic
     ///
-    /// Panics if the source wasn't known locally as downloading something.
+    /// Panics if the [`RequestId`] is invalid.
     ///
     pub fn ancestry_search_response(
         mut self,
-        source_id: SourceId,
+        request_id: RequestId,
         received_blocks: Result<
             impl Iterator<Item = RequestSuccessBlock<impl AsRef<[u8]>, impl AsRef<[u8]>>>,
             (),
         >,
     ) -> AncestrySearchResponseOutcome<TSrc, TBl> {
-        // Sets the `occupation` of `source_id` back to `Idle`.
-        let (_, requested_block_height, requested_block_hash) = match mem::take(
-            &mut self
-                .inner
-                .sources
-                .source_mut(source_id)
-                .unwrap()
-                .user_data()
-                .occupation,
-        ) {
-            Some(request_id) => self.inner.pending_blocks.finish_request(request_id),
-            None => panic!(),
-        };
+        // Remove the request from the state machine.
+        let (source_id, requested_block_height, requested_block_hash) =
+            self.inner.pending_blocks.finish_request(request_id);
+        self.inner
+            .sources
+            .source_mut(source_id)
+            .unwrap()
+            .user_data()
+            .num_ongoing_requests -= 1;
 
         // Set to true below if any block is inserted in `disjoint_headers`.
         let mut any_progress = false;
@@ -388,15 +512,26 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
                 HeaderFromSourceOutcome::HeaderVerify(this) => {
                     return AncestrySearchResponseOutcome::Verify(this);
                 }
-                HeaderFromSourceOutcome::TooOld(this) => {
+                HeaderFromSourceOutcome::BlockBodyDownloadStart(mut this) => {
+                    let next_requests = this.next_requests();
+                    return AncestrySearchResponseOutcome::BlockBodyDownloadStart {
+                        sync: this,
+                        next_requests,
+                    };
+                }
+                HeaderFromSourceOutcome::TooOld(mut this) => {
                     // Block is below the finalized block number.
                     // Ancestry searches never request any block earlier than the finalized block
                     // number. `TooOld` can happen if the source is misbehaving, but also if the
                     // finalized block has been updated between the moment the request was emitted
                     // and the moment the response is received.
                     debug_assert_eq!(index_in_response, 0);
-                    self = this;
-                    break;
+                    let next_requests = this.next_requests();
+                    return AncestrySearchResponseOutcome::AllAlreadyInChain {
+                        sync: this,
+                        next_requests,
+                        justification_verification: JustificationVerification::NoJustification,
+                    };
                 }
                 HeaderFromSourceOutcome::NotFinalizedChain(this) => {
                     // Block isn't part of the finalized chain.
@@ -406,22 +541,30 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
                     // response is received.
                     self = this;
 
-                    let next_request = self.source_next_request(source_id);
+                    let next_requests = self.next_requests();
                     return AncestrySearchResponseOutcome::NotFinalizedChain {
                         sync: self,
-                        next_request,
+                        next_requests,
                         discarded_unverified_block_headers: Vec::new(), // TODO:
                     };
                 }
                 HeaderFromSourceOutcome::AlreadyInChain(mut this) => {
                     // Block is already in chain. Can happen if a different response or
                     // announcement has arrived and been processed between the moment the request
-                    // was emitted and the moment the response is received.
+                    // was emitted and the moment the response is received, or in response to a
+                    // `Request::JustificationRequest`.
                     debug_assert_eq!(index_in_response, 0);
-                    let next_request = this.source_next_request(source_id);
+                    let justification_verification = match received_block
+                        .scale_encoded_justification
+                    {
+                        Some(justification) => this.verify_justification(justification.as_ref()),
+                        None => JustificationVerification::NoJustification,
+                    };
+                    let next_requests = this.next_requests();
                     return AncestrySearchResponseOutcome::AllAlreadyInChain {
                         sync: this,
-                        next_request,
+                        next_requests,
+                        justification_verification,
                     };
                 }
                 HeaderFromSourceOutcome::Disjoint(this) => {
@@ -435,7 +578,7 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
 
         // If this is reached, then the ancestry search was inconclusive. Only disjoint blocks
         // have been received.
-        if !any_progress {
+        let penalized_source = if !any_progress {
             // TODO: distinguish errors from empty requests?
             // Avoid sending the same request to the same source over and over again.
             self.inner
@@ -443,61 +586,347 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
                 .source_mut(source_id)
                 .unwrap()
                 .remove_known_block(requested_block_height, requested_block_hash);
-        }
 
-        let next_request = self.source_next_request(source_id);
+            // The source was assumed to know this block, either because it has announced it or
+            // one of its children, but it was unable to provide it.
+            Some(source_id)
+        } else {
+            None
+        };
+
+        let next_requests = self.next_requests();
         AncestrySearchResponseOutcome::Inconclusive {
             sync: self,
-            next_request,
+            next_requests,
+            penalized_source,
         }
     }
 
-    /// Finds a request that the given source could start performing.
+    /// Assigns to sources as many requests as possible, and returns the list of newly-started
+    /// requests.
     ///
-    /// If `Some` is returned, updates `self` and returns the request that must be started.
+    /// Each desired query is assigned to the source that knows about the targeted block, that
+    /// isn't already requesting this block, that is below its limit of simultaneous requests,
+    /// and that has the fewest requests in progress.
     #[must_use]
-    fn source_next_request(&mut self, source_id: SourceId) -> Option<Request> {
-        let mut source_access = self.inner.sources.source_mut(source_id).unwrap();
+    fn next_requests(&mut self) -> Vec<(SourceId, RequestId, Request)> {
+        let mut out = Vec::new();
+
+        loop {
+            // Find a query and a source that is appropriate for this query.
+            // TODO: this restarts from scratch at each iteration; optimize
+            let inner = &self.inner;
+            let query_to_start = inner.pending_blocks.desired_queries().find_map(|query| {
+                let source_id = inner
+                    .sources
+                    .knows_block_sources(query.first_block_height, &query.first_block_hash)
+                    .filter(|source_id| {
+                        inner
+                            .sources
+                            .source_user_data(*source_id)
+                            .unwrap()
+                            .num_ongoing_requests
+                            < inner.max_requests_per_source
+                    })
+                    .filter(|source_id| {
+                        !inner
+                            .pending_blocks
+                            .block_requests(query.first_block_height, &query.first_block_hash)
+                            .any(|(_, requesting_source)| *requesting_source == *source_id)
+                    })
+                    .min_by_key(|source_id| {
+                        inner
+                            .sources
+                            .source_user_data(*source_id)
+                            .unwrap()
+                            .num_ongoing_requests
+                    })?;
+                Some((source_id, query))
+            });
+
+            let (source_id, query_to_start) = match query_to_start {
+                Some(q) => q,
+                None => break,
+            };
+
+            // Start the request.
+            let request_id = self
+                .inner
+                .pending_blocks
+                .block_mut(
+                    query_to_start.first_block_height,
+                    query_to_start.first_block_hash,
+                )
+                .into_occupied()
+                .unwrap()
+                .add_descending_request(source_id, query_to_start.num_blocks);
+            self.inner
+                .sources
+                .source_mut(source_id)
+                .unwrap()
+                .user_data()
+                .num_ongoing_requests += 1;
 
-        // Don't start more than one request at a time.
-        // TODO: in the future, allow multiple requests
-        if source_access.user_data().occupation.is_some() {
-            return None;
+            out.push((
+                source_id,
+                request_id,
+                Request::AncestrySearch {
+                    first_block_hash: query_to_start.first_block_hash,
+                    num_blocks: query_to_start.num_blocks,
+                },
+            ));
         }
 
-        // TODO: need to periodically query for justifications of non-finalized blocks that change GrandPa authorities
+        // Body requests, in full mode.
+        // The body of a block is downloaded once its parent has been verified.
+        if self.inner.full {
+            let finalized_block_hash = self.chain.finalized_block_hash();
+            let candidates = self
+                .inner
+                .pending_blocks
+                .unverified_headers()
+                .filter(|(height, hash, _)| {
+                    self.inner
+                        .pending_blocks
+                        .block_requests(*height, hash)
+                        .next()
+                        .is_none()
+                })
+                .collect::<Vec<_>>();
+
+            for (height, hash, parent_hash) in candidates {
+                if parent_hash != finalized_block_hash
+                    && self
+                        .chain
+                        .non_finalized_block_by_hash(&parent_hash)
+                        .is_none()
+                {
+                    continue;
+                }
+
+                let source_id = match self.source_for_request(height, &hash) {
+                    Some(s) => s,
+                    None => continue,
+                };
 
-        // Find a query that is appropriate for this source.
-        let query_to_start = self
-            .inner
-            .pending_blocks
-            .desired_queries()
-            .find(|desired_query| {
-                // The source can only operate on blocks that it knows about.
-                // `continue` if this block isn't known by this source.
-                source_access.knows_block(
-                    desired_query.first_block_height,
-                    &desired_query.first_block_hash,
-                )
-            })?;
+                let request_id = self
+                    .inner
+                    .pending_blocks
+                    .block_mut(height, hash)
+                    .into_occupied()
+                    .unwrap()
+                    .add_descending_request(source_id, NonZeroU64::new(1).unwrap());
+                self.inner
+                    .sources
+                    .source_mut(source_id)
+                    .unwrap()
+                    .user_data()
+                    .num_ongoing_requests += 1;
+
+                out.push((
+                    source_id,
+                    request_id,
+                    Request::BodyRequest {
+                        number: height,
+                        hash,
+                    },
+                ));
+            }
+        }
 
-        // Start the request.
-        let request_id: pending_blocks::RequestId = self
+        // Justification requests.
+        let to_request = self
             .inner
-            .pending_blocks
-            .block_mut(
-                query_to_start.first_block_height,
-                query_to_start.first_block_hash,
-            )
-            .into_occupied()
-            .unwrap()
-            .add_descending_request(source_id, query_to_start.num_blocks);
-        source_access.user_data().occupation = Some(request_id);
+            .justification_requests
+            .iter()
+            .filter(|(_, already_requested)| !**already_requested)
+            .map(|(block, _)| *block)
+            .collect::<Vec<_>>();
+        for (height, hash) in to_request {
+            // Don't start a request if one is already in progress for this block.
+            if self
+                .inner
+                .pending_blocks
+                .block_requests(height, &hash)
+                .next()
+                .is_some()
+            {
+                continue;
+            }
 
-        Some(Request::AncestrySearch {
-            first_block_hash: query_to_start.first_block_hash,
-            num_blocks: query_to_start.num_blocks,
-        })
+            let source_id = match self.source_for_request(height, &hash) {
+                Some(s) => s,
+                None => continue,
+            };
+
+            let request_id = self
+                .inner
+                .pending_blocks
+                .add_unpending_block_request(height, hash, source_id);
+            self.inner
+                .sources
+                .source_mut(source_id)
+                .unwrap()
+                .user_data()
+                .num_ongoing_requests += 1;
+            *self
+                .inner
+                .justification_requests
+                .get_mut(&(height, hash))
+                .unwrap() = true;
+
+            out.push((
+                source_id,
+                request_id,
+                Request::JustificationRequest {
+                    number: height,
+                    hash,
+                },
+            ));
+        }
+
+        out
+    }
+
+    /// Returns the source that a request concerning the given block should be sent to, or `None`
+    /// if no source is appropriate.
+    ///
+    /// The chosen source knows about the block, is below its limit of simultaneous requests, and
+    /// has the fewest requests in progress.
+    fn source_for_request(&self, height: u64, hash: &[u8; 32]) -> Option<SourceId> {
+        let inner = &self.inner;
+        inner
+            .sources
+            .knows_block_sources(height, hash)
+            .filter(|source_id| {
+                inner
+                    .sources
+                    .source_user_data(*source_id)
+                    .unwrap()
+                    .num_ongoing_requests
+                    < inner.max_requests_per_source
+            })
+            .min_by_key(|source_id| {
+                inner
+                    .sources
+                    .source_user_data(*source_id)
+                    .unwrap()
+                    .num_ongoing_requests
+            })
+    }
+
+    /// Allows requesting again the justifications of the blocks whose justification is missing,
+    /// and additionally requests the justification of the current best block if it isn't
+    /// finalized.
+    ///
+    /// Returns the list of requests that must be started.
+    ///
+    /// This method should be called periodically, for example every few seconds, in order to
+    /// make sure that finality doesn't stall.
+    pub fn retry_justification_requests(&mut self) -> Vec<(SourceId, RequestId, Request)> {
+        for already_requested in self.inner.justification_requests.values_mut() {
+            *already_requested = false;
+        }
+
+        // The justification of the previous best block is no longer interesting, unless this
+        // block contains a GrandPa authorities change.
+        self.prune_justification_requests();
+
+        let best_block = self.chain.best_block_header();
+        if best_block.number > self.chain.finalized_block_header().number {
+            let best_block_hash = self.chain.best_block_hash();
+            self.inner
+                .justification_requests
+                .insert((best_block.number, best_block_hash), false);
+        }
+
+        self.next_requests()
+    }
+
+    /// Removes from [`Inner::justification_requests`] the blocks whose justification is no
+    /// longer needed, in other words the blocks that are no longer in the chain, and the blocks
+    /// that are neither the current best block nor contain a GrandPa authorities change.
+    ///
+    /// Must be called whenever the finalized block changes.
+    fn prune_justification_requests(&mut self) {
+        let finalized_block_height = self.chain.finalized_block_header().number;
+        let best_block_hash = self.chain.best_block_hash();
+        let chain = &mut self.chain;
+        self.inner
+            .justification_requests
+            .retain(|(height, hash), _| {
+                if *height <= finalized_block_height {
+                    return false;
+                }
+
+                match chain.non_finalized_block_by_hash(hash) {
+                    Some(mut block) => {
+                        *hash == best_block_hash
+                            || header::DigestRef::from(&block.user_data_mut().header.digest)
+                                .has_grandpa_authorities_change()
+                    }
+                    None => false,
+                }
+            });
+    }
+
+    /// Verifies the given justification and, on success, applies it to the chain.
+    fn verify_justification(&mut self, justification: &[u8]) -> JustificationVerification<TBl> {
+        match self.chain.verify_justification(justification) {
+            Ok(success) => {
+                // Blocks are yielded from child to parent. In full mode, the runtime of the new
+                // finalized block is the one of the highest finalized block that has modified
+                // the runtime, if any.
+                let mut finalized = Vec::new();
+                let mut new_finalized_runtime = None;
+                for block in success.apply() {
+                    if let Some(runtime) = block.full.and_then(|full| full.runtime) {
+                        if new_finalized_runtime.is_none() {
+                            new_finalized_runtime = Some(runtime);
+                        }
+                    }
+                    finalized.push((block.header, block.user_data));
+                }
+                if let Some(new_finalized_runtime) = new_finalized_runtime {
+                    self.inner.finalized_runtime = Some(new_finalized_runtime);
+                }
+
+                // Blocks below the new finalized block are no longer interesting.
+                let finalized_block_height = self.chain.finalized_block_header().number;
+                self.inner
+                    .pending_blocks
+                    .remove_up_to_height(finalized_block_height);
+                self.inner
+                    .sources
+                    .set_finalized_block_height(finalized_block_height);
+                self.prune_justification_requests();
+
+                JustificationVerification::NewFinalized(finalized)
+            }
+            Err(err) => JustificationVerification::JustificationVerificationError(err),
+        }
+    }
+
+    /// Removes blocks from [`Inner::pending_blocks`] until its size is below
+    /// [`Config::max_disjoint_headers`].
+    ///
+    /// Blocks with the highest height are removed first, as blocks closer to the finalized block
+    /// are necessary in order to make progress. Blocks being downloaded are never removed, which
+    /// might lead to the limit being temporarily exceeded.
+    fn enforce_pending_blocks_limit(&mut self) {
+        while self.inner.pending_blocks.num_blocks() > self.inner.max_disjoint_headers {
+            let (height, hash) = match self.inner.pending_blocks.least_interesting_block() {
+                Some(b) => b,
+                None => break,
+            };
+
+            self.inner
+                .pending_blocks
+                .block_mut(height, hash)
+                .into_occupied()
+                .unwrap()
+                .remove_uninteresting();
+        }
     }
 
     /// Update the source with a newly-announced block.
@@ -532,6 +961,13 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
             HeaderFromSourceOutcome::HeaderVerify(verify) => {
                 BlockAnnounceOutcome::HeaderVerify(verify)
             }
+            HeaderFromSourceOutcome::BlockBodyDownloadStart(mut sync) => {
+                let next_requests = sync.next_requests();
+                BlockAnnounceOutcome::BlockBodyDownloadStart {
+                    sync,
+                    next_requests,
+                }
+            }
             HeaderFromSourceOutcome::TooOld(sync) => BlockAnnounceOutcome::TooOld(sync),
             HeaderFromSourceOutcome::AlreadyInChain(sync) => {
                 BlockAnnounceOutcome::AlreadyInChain(sync)
@@ -540,8 +976,11 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
                 BlockAnnounceOutcome::NotFinalizedChain(sync)
             }
             HeaderFromSourceOutcome::Disjoint(mut sync) => {
-                let next_request = sync.source_next_request(source_id);
-                BlockAnnounceOutcome::Disjoint { sync, next_request }
+                let next_requests = sync.next_requests();
+                BlockAnnounceOutcome::Disjoint {
+                    sync,
+                    next_requests,
+                }
             }
         }
     }
@@ -597,8 +1036,6 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
         // TODO: somehow optimize? the encoded block is normally known from it being decoded
         let scale_encoded_header = header.scale_encoding_vec();
 
-        // TODO: if pending_blocks.num_blocks() > some_max { remove uninteresting block }
-
         let mut block_access = self
             .inner
             .pending_blocks
@@ -621,12 +1058,22 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
             // TODO: don't do this
             block_access.update_header(scale_encoded_header.clone()); // TODO: clone :(
 
-            HeaderFromSourceOutcome::HeaderVerify(HeaderVerify {
-                parent: self,
-                source_id,
-                verifiable_blocks: iter::once((header.number, *header_hash, scale_encoded_header))
+            if self.inner.full {
+                // In full mode, the header is verified at the same time as the body, which must
+                // be downloaded first.
+                HeaderFromSourceOutcome::BlockBodyDownloadStart(self)
+            } else {
+                HeaderFromSourceOutcome::HeaderVerify(HeaderVerify {
+                    parent: self,
+                    source_id,
+                    verifiable_blocks: iter::once((
+                        header.number,
+                        *header_hash,
+                        scale_encoded_header,
+                    ))
                     .collect(),
-            })
+                })
+            }
         } else if header.number == self.chain.finalized_block_header().number + 1 {
             // Checked above.
             debug_assert_ne!(*header.parent_hash, self.chain.finalized_block_hash());
@@ -648,62 +1095,120 @@ impl<TSrc, TBl> AllForksSync<TSrc, TBl> {
                     justification: None,
                 });
 
+            self.enforce_pending_blocks_limit();
             HeaderFromSourceOutcome::Disjoint(self)
         }
     }
 
-    /*/// Call in response to a [`BlockAnnounceOutcome::BlockBodyDownloadStart`].
+    /// Call in response to a [`Request::BodyRequest`].
+    ///
+    /// The [`RequestId`] is the one that was returned alongside with the request. It is
+    /// immediately considered invalid.
+    ///
+    /// The body must be passed as a list of SCALE-encoded extrinsics. Pass `Err(())` if the
+    /// request has failed.
     ///
     /// # Panic
     ///
-    /// Panics if the source wasn't known locally as downloading something.
+    /// Panics if the [`RequestId`] is invalid.
     ///
     pub fn block_body_response(
         mut self,
-        now_from_unix_epoch: Duration,
-        source_id: SourceId,
-        block_body: impl Iterator<Item = impl AsRef<[u8]>>,
-    ) -> (BlockBodyVerify<TSrc, TBl>, Option<Request>) {
-        // TODO: unfinished
-
-        todo!()
+        request_id: RequestId,
+        block_body: Result<impl Iterator<Item = impl AsRef<[u8]>>, ()>,
+    ) -> BlockBodyResponseOutcome<TSrc, TBl> {
+        // If not full, there shouldn't be any block body download happening in the first place.
+        debug_assert!(self.inner.full);
 
-        /*// TODO: update occupation
+        // Remove the request from the state machine.
+        let (source_id, block_height, block_hash) =
+            self.inner.pending_blocks.finish_request(request_id);
+        self.inner
+            .sources
+            .source_mut(source_id)
+            .unwrap()
+            .user_data()
+            .num_ongoing_requests -= 1;
 
-        // Removes traces of the request from the state machine.
-        let block_header_hash = if let Some((h, _)) = self
+        // The block might have been verified, discarded, or marked as bad while the request was
+        // in progress, in which case the response is simply ignored.
+        let scale_encoded_header = match self
             .inner
-            .pending_body_downloads
-            .iter_mut()
-            .find(|(_, (_, s))| *s == Some(source_id))
+            .pending_blocks
+            .block_mut(block_height, block_hash)
+            .into_occupied()
+            .and_then(|block| block.scale_encoded_header().map(|h| h.to_vec()))
         {
-            let hash = *h;
-            let header = self.inner.pending_body_downloads.remove(&hash).unwrap().0;
-            (header, hash)
-        } else {
-            panic!()
+            Some(h) => h,
+            None => {
+                let next_requests = self.next_requests();
+                return BlockBodyResponseOutcome::Inconclusive {
+                    sync: self,
+                    next_requests,
+                    penalized_source: None,
+                };
+            }
         };
 
-        // Sanity check.
-        debug_assert_eq!(block_header_hash.1, block_header_hash.0.hash());
-
-        // If not full, there shouldn't be any block body download happening in the first place.
-        debug_assert!(self.inner.full);
+        // Headers in `pending_blocks` have been successfully decoded in the past.
+        let (parent_hash, extrinsics_root) = {
+            let decoded = header::decode(&scale_encoded_header).unwrap();
+            (*decoded.parent_hash, *decoded.extrinsics_root)
+        };
 
-        match self
-            .chain
-            .verify_body(
-                block_header_hash.0.scale_encoding()
-                    .fold(Vec::new(), |mut a, b| { a.extend_from_slice(b.as_ref()); a }), now_from_unix_epoch) // TODO: stupid extra allocation
+        // Similarly, the parent of the block might have been discarded as the result of a
+        // finalization.
+        if parent_hash != self.chain.finalized_block_hash()
+            && self
+                .chain
+                .non_finalized_block_by_hash(&parent_hash)
+                .is_none()
         {
-            blocks_tree::BodyVerifyStep1::BadParent { .. }
-            | blocks_tree::BodyVerifyStep1::InvalidHeader(..)
-            | blocks_tree::BodyVerifyStep1::Duplicate(_) => unreachable!(),
-            blocks_tree::BodyVerifyStep1::ParentRuntimeRequired(_runtime_req) => {
-                todo!()
+            let next_requests = self.next_requests();
+            return BlockBodyResponseOutcome::Inconclusive {
+                sync: self,
+                next_requests,
+                penalized_source: None,
+            };
+        }
+
+        // Make sure that the body matches the header before verifying it. Otherwise, a source
+        // could make a valid block fail its verification by providing a wrong body.
+        let block_body = match block_body {
+            Ok(body) => Some(body.map(|e| e.as_ref().to_vec()).collect::<Vec<_>>()),
+            Err(()) => None,
+        }
+        .filter(|body| extrinsics_trie_root(body) == extrinsics_root);
+
+        let block_body = match block_body {
+            Some(b) => b,
+            None => {
+                // TODO: distinguish errors from empty requests?
+                // Avoid sending the same request to the same source over and over again.
+                self.inner
+                    .sources
+                    .source_mut(source_id)
+                    .unwrap()
+                    .remove_known_block(block_height, block_hash);
+
+                let next_requests = self.next_requests();
+                return BlockBodyResponseOutcome::Inconclusive {
+                    sync: self,
+                    next_requests,
+                    penalized_source: Some(source_id),
+                };
             }
-        }*/
-    }*/
+        };
+
+        BlockBodyResponseOutcome::Verify(BodyVerify {
+            parent: self,
+            source_id,
+            block_height,
+            block_hash,
+            scale_encoded_header,
+            block_body,
+        })
+    }
 }
 
 /// Request that should be performed towards a source.
@@ -741,6 +1246,20 @@ pub enum Request {
         hash: [u8; 32],
     },
 
+    /// The justification of the block with the given hash is requested. The response must be
+    /// passed to [`AllForksSync::ancestry_search_response`], and must contain the header of the
+    /// block.
+    JustificationRequest {
+        /// Height of the block.
+        ///
+        /// > **Note**: This value is passed because it is always known, but the hash alone is
+        /// >           expected to be enough to fetch the block justification.
+        number: u64,
+
+        /// Hash of the block whose justification to obtain.
+        hash: [u8; 32],
+    },
+
     /// The body of the block with the given hash is requested.
     ///
     /// Can only happen if [`Config::full`].
@@ -780,8 +1299,9 @@ impl<'a, TSrc, TBl> SourceMutAccess<'a, TSrc, TBl> {
     }
 
     /// Returns true if the source has earlier announced the block passed as parameter or one of
-    /// its descendants.
-    // TODO: document precisely what it means
+    /// its children, or if the source has provided this block or one of its children in a
+    /// response. Sources aren't assumed to know about the other ancestors of the blocks they
+    /// have announced.
     // TODO: shouldn't take &mut self but just &self
     pub fn knows_block(&mut self, height: u64, hash: &[u8; 32]) -> bool {
         self.parent
@@ -794,16 +1314,16 @@ impl<'a, TSrc, TBl> SourceMutAccess<'a, TSrc, TBl> {
 
     /// Removes the source from the [`AllForksSync`].
     ///
-    /// Removing the source implicitly cancels the request that is associated to it (if any).
+    /// Removing the source implicitly cancels the requests that are associated to it (if any).
     ///
-    /// Returns the user data that was originally passed to [`AllForksSync::add_source`], plus
-    /// an `Option`.
-    /// If this `Option` is `Some`, it contains a request that must be started towards the source
-    /// indicated by the [`SourceId`].
+    /// Returns the user data that was originally passed to [`AllForksSync::add_source`], the
+    /// list of requests of this source that are now cancelled, and a list of requests that must
+    /// be started towards other sources.
     ///
     /// > **Note**: For example, if the source that has just been removed was performing an
-    /// >           ancestry search, the `Option` might contain that same ancestry search.
-    pub fn remove(self) -> (TSrc, Option<(SourceId, Request)>) {
+    /// >           ancestry search, the list of new requests might contain that same ancestry
+    /// >           search towards a different source.
+    pub fn remove(self) -> SourceRemoveOutcome<TSrc> {
         let source = self
             .parent
             .inner
@@ -812,13 +1332,27 @@ impl<'a, TSrc, TBl> SourceMutAccess<'a, TSrc, TBl> {
             .unwrap()
             .remove();
 
-        if let Some(request_id) = source.occupation {
-            // TODO: self.parent.inner.pending_blocks.finish_request(request_id);
-            todo!()
+        // TODO: O(n)
+        let cancelled_requests = self
+            .parent
+            .inner
+            .pending_blocks
+            .requests()
+            .filter(|(_, requesting_source)| **requesting_source == self.source_id)
+            .map(|(request_id, _)| request_id)
+            .collect::<Vec<_>>();
+        debug_assert_eq!(cancelled_requests.len(), source.num_ongoing_requests);
+        for request_id in &cancelled_requests {
+            self.parent.inner.pending_blocks.finish_request(*request_id);
         }
 
-        // TODO: None hardcoded
-        (source.user_data, None)
+        let next_requests = self.parent.next_requests();
+
+        SourceRemoveOutcome {
+            user_data: source.user_data,
+            cancelled_requests,
+            next_requests,
+        }
     }
 
     /// Returns the user data associated to the source. This is the value originally passed
@@ -846,6 +1380,18 @@ impl<'a, TSrc, TBl> SourceMutAccess<'a, TSrc, TBl> {
     }
 }
 
+/// Outcome of calling [`SourceMutAccess::remove`].
+pub struct SourceRemoveOutcome<TSrc> {
+    /// User data that was originally passed to [`AllForksSync::add_source`].
+    pub user_data: TSrc,
+
+    /// Requests that the removed source was performing. These [`RequestId`]s are now invalid.
+    pub cancelled_requests: Vec<RequestId>,
+
+    /// Requests that must be started towards other sources.
+    pub next_requests: Vec<(SourceId, RequestId, Request)>,
+}
+
 /// Outcome of calling [`AllForksSync::header_from_source`].
 ///
 /// Not public.
@@ -853,6 +1399,10 @@ enum HeaderFromSourceOutcome<TSrc, TBl> {
     /// Header is ready to be verified.
     HeaderVerify(HeaderVerify<TSrc, TBl>),
 
+    /// In full mode, the parent of the block has been verified, and its body must now be
+    /// downloaded.
+    BlockBodyDownloadStart(AllForksSync<TSrc, TBl>),
+
     /// Announced block is too old to be part of the finalized chain.
     ///
     /// It is assumed that all sources will eventually agree on the same finalized chain. Blocks
@@ -874,6 +1424,14 @@ pub enum BlockAnnounceOutcome<TSrc, TBl> {
     /// Header is ready to be verified.
     HeaderVerify(HeaderVerify<TSrc, TBl>),
 
+    /// In full mode, the parent of the announced block has been verified, and the body of the
+    /// announced block must now be downloaded in order to verify it.
+    BlockBodyDownloadStart {
+        sync: AllForksSync<TSrc, TBl>,
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
+    },
+
     /// Announced block is too old to be part of the finalized chain.
     ///
     /// It is assumed that all sources will eventually agree on the same finalized chain. Blocks
@@ -889,8 +1447,8 @@ pub enum BlockAnnounceOutcome<TSrc, TBl> {
     /// Header cannot be verified now, and has been stored for later.
     Disjoint {
         sync: AllForksSync<TSrc, TBl>,
-        /// Next request that the same source should now perform.
-        next_request: Option<Request>,
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
     },
     /// Failed to decode announce header.
     InvalidHeader {
@@ -904,6 +1462,16 @@ pub enum AncestrySearchResponseOutcome<TSrc, TBl> {
     /// Ready to start verifying one or more headers returned in the ancestry search.
     Verify(HeaderVerify<TSrc, TBl>),
 
+    /// In full mode, the ancestry search has connected one or more blocks to the chain of
+    /// verified blocks. The bodies of these blocks must now be downloaded in order to verify
+    /// them.
+    BlockBodyDownloadStart {
+        sync: AllForksSync<TSrc, TBl>,
+
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
+    },
+
     /// Source has given blocks that aren't part of the finalized chain.
     ///
     /// This doesn't necessarily mean that the source is malicious or uses a different chain. It
@@ -912,8 +1480,8 @@ pub enum AncestrySearchResponseOutcome<TSrc, TBl> {
     NotFinalizedChain {
         sync: AllForksSync<TSrc, TBl>,
 
-        /// Next request that the same source should now perform.
-        next_request: Option<Request>,
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
 
         /// List of block headers that were pending verification and that have now been discarded
         /// since it has been found out that they don't belong to the finalized chain.
@@ -925,19 +1493,34 @@ pub enum AncestrySearchResponseOutcome<TSrc, TBl> {
     Inconclusive {
         sync: AllForksSync<TSrc, TBl>,
 
-        /// Next request that the same source should now perform.
-        next_request: Option<Request>,
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
+
+        /// If `Some`, the source that the request was sent to was expected to know about the
+        /// requested block, because it has announced this block or one of its children, but has
+        /// failed to provide it.
+        ///
+        /// The request isn't sent again to this source. The API user is encouraged to penalize
+        /// this source, for example by lowering its reputation or by disconnecting from it if
+        /// this happens repeatedly.
+        penalized_source: Option<SourceId>,
     },
 
-    /// All blocks in the ancestry search response were already in the list of verified blocks.
+    /// All blocks in the ancestry search response were already in the list of verified blocks,
+    /// or are older than the latest finalized block.
     ///
     /// This can happen if a block announce or different ancestry search response has been
-    /// processed in between the request and response.
+    /// processed in between the request and response, and is the normal outcome of a
+    /// [`Request::JustificationRequest`].
     AllAlreadyInChain {
         sync: AllForksSync<TSrc, TBl>,
 
-        /// Next request that the same source should now perform.
-        next_request: Option<Request>,
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
+
+        /// If a justification was attached to the block, it has been verified. Contains the
+        /// outcome.
+        justification_verification: JustificationVerification<TBl>,
     },
 }
 
@@ -981,19 +1564,29 @@ impl<TSrc, TBl> HeaderVerify<TSrc, TBl> {
             Ok(blocks_tree::HeaderVerifySuccess::Insert {
                 insert,
                 is_new_best,
+                equivocation,
                 ..
             }) => {
+                // The justification of blocks that change the list of GrandPa authorities is
+                // necessary in order to verify the justifications of their descendants.
+                if insert.header().digest.has_grandpa_authorities_change() {
+                    self.parent
+                        .inner
+                        .justification_requests
+                        .insert((to_verify_height, to_verify_hash), false);
+                }
+
                 // TODO: cloning the header :-/
                 let block = Block {
                     header: insert.header().into(),
+                    full: None,
                     user_data,
                 };
                 insert.insert(block);
-                Ok(is_new_best)
-            }
-            Err(blocks_tree::HeaderVerifyError::VerificationFailed(error)) => {
-                Err((error, user_data))
+                Ok((is_new_best, equivocation))
             }
+            Err(error @ blocks_tree::HeaderVerifyError::VerificationFailed(_))
+            | Err(error @ blocks_tree::HeaderVerifyError::BadBlock) => Err((error, user_data)),
             Ok(blocks_tree::HeaderVerifySuccess::Duplicate)
             | Err(blocks_tree::HeaderVerifyError::BadParent { .. })
             | Err(blocks_tree::HeaderVerifyError::InvalidHeader(_)) => unreachable!(),
@@ -1023,43 +1616,46 @@ impl<TSrc, TBl> HeaderVerify<TSrc, TBl> {
         };
 
         let justification_verification = if let Some(justification) = justification {
-            match self.parent.chain.verify_justification(&justification) {
-                Ok(success) => JustificationVerification::NewFinalized(
-                    success.apply().map(|b| (b.header, b.user_data)).collect(),
-                ),
-                Err(err) => JustificationVerification::JustificationVerificationError(err),
-            }
+            self.parent.verify_justification(&justification)
         } else {
             JustificationVerification::NoJustification
         };
 
-        let requests_replace = cancelled_requests
+        let cancelled_requests = cancelled_requests
             .into_iter()
-            .map(|(_, replace_source_id)| {
-                let new_request = self.parent.source_next_request(replace_source_id);
-                (replace_source_id, new_request)
+            .map(|(request_id, source_id)| {
+                self.parent
+                    .inner
+                    .sources
+                    .source_mut(source_id)
+                    .unwrap()
+                    .user_data()
+                    .num_ongoing_requests -= 1;
+                (source_id, request_id)
             })
             .collect();
 
         match (result, self.verifiable_blocks.is_empty()) {
-            (Ok(is_new_best), false) => HeaderVerifyOutcome::SuccessContinue {
+            (Ok((is_new_best, equivocation)), false) => HeaderVerifyOutcome::SuccessContinue {
                 is_new_best,
+                equivocation,
                 justification_verification,
                 next_block: HeaderVerify {
                     parent: self.parent,
                     source_id: self.source_id,
                     verifiable_blocks: self.verifiable_blocks,
                 },
-                requests_replace,
+                cancelled_requests,
             },
-            (Ok(is_new_best), true) => {
-                let next_request = self.parent.source_next_request(self.source_id);
+            (Ok((is_new_best, equivocation)), true) => {
+                let next_requests = self.parent.next_requests();
                 HeaderVerifyOutcome::Success {
                     is_new_best,
+                    equivocation,
                     justification_verification,
                     sync: self.parent,
-                    next_request,
-                    requests_replace,
+                    next_requests,
+                    cancelled_requests,
                 }
             }
             (Err((error, user_data)), false) => HeaderVerifyOutcome::ErrorContinue {
@@ -1070,16 +1666,16 @@ impl<TSrc, TBl> HeaderVerify<TSrc, TBl> {
                 },
                 error,
                 user_data,
-                requests_replace,
+                cancelled_requests,
             },
             (Err((error, user_data)), true) => {
-                let next_request = self.parent.source_next_request(self.source_id);
+                let next_requests = self.parent.next_requests();
                 HeaderVerifyOutcome::Error {
                     sync: self.parent,
                     error,
                     user_data,
-                    next_request,
-                    requests_replace,
+                    next_requests,
+                    cancelled_requests,
                 }
             }
         }
@@ -1095,32 +1691,36 @@ pub enum HeaderVerifyOutcome<TSrc, TBl> {
     Success {
         /// True if the newly-verified block is considered the new best block.
         is_new_best: bool,
+        /// If `Some`, the author of the newly-verified block has already produced a different
+        /// block in the same slot. The block has nonetheless been inserted in the chain.
+        equivocation: Option<blocks_tree::EquivocationProof>,
         /// If a justification was attached to this block, it has also been verified. Contains the
         /// outcome.
         justification_verification: JustificationVerification<TBl>,
         /// State machine yielded back. Use to continue the processing.
         sync: AllForksSync<TSrc, TBl>,
-        /// Next request that must be performed on the source.
-        next_request: Option<Request>,
-        /// For each element in this list, the request made towards this source should be replaced
-        /// with a new one (if the request is `Some`) or cancelled (if the request is `None`).
-        /// Sources are guaranteed to never be the source that has sent back the header.
-        requests_replace: Vec<(SourceId, Option<Request>)>,
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
+        /// Requests that were targeting this block and that are now cancelled. These
+        /// [`RequestId`]s are now invalid.
+        cancelled_requests: Vec<(SourceId, RequestId)>,
     },
 
     /// Header has been successfully verified. A follow-up header is ready to be verified.
     SuccessContinue {
         /// True if the newly-verified block is considered the new best block.
         is_new_best: bool,
+        /// If `Some`, the author of the newly-verified block has already produced a different
+        /// block in the same slot. The block has nonetheless been inserted in the chain.
+        equivocation: Option<blocks_tree::EquivocationProof>,
         /// If a justification was attached to this block, it has also been verified. Contains the
         /// outcome.
         justification_verification: JustificationVerification<TBl>,
         /// Next verification.
         next_block: HeaderVerify<TSrc, TBl>,
-        /// For each element in this list, the request made towards this source should be replaced
-        /// with a new one (if the request is `Some`) or cancelled (if the request is `None`).
-        /// Sources are guaranteed to never be the source that has sent back the header.
-        requests_replace: Vec<(SourceId, Option<Request>)>,
+        /// Requests that were targeting this block and that are now cancelled. These
+        /// [`RequestId`]s are now invalid.
+        cancelled_requests: Vec<(SourceId, RequestId)>,
     },
 
     /// Header verification failed.
@@ -1128,15 +1728,14 @@ pub enum HeaderVerifyOutcome<TSrc, TBl> {
         /// State machine yielded back. Use to continue the processing.
         sync: AllForksSync<TSrc, TBl>,
         /// Error that happened.
-        error: verify::header_only::Error,
+        error: blocks_tree::HeaderVerifyError,
         /// User data that was passed to [`HeaderVerify::perform`] and is unused.
         user_data: TBl,
-        /// Next request that must be performed on the source.
-        next_request: Option<Request>,
-        /// For each element in this list, the request made towards this source should be replaced
-        /// with a new one (if the request is `Some`) or cancelled (if the request is `None`).
-        /// Sources are guaranteed to never be the source that has sent back the header.
-        requests_replace: Vec<(SourceId, Option<Request>)>,
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
+        /// Requests that were targeting this block and that are now cancelled. These
+        /// [`RequestId`]s are now invalid.
+        cancelled_requests: Vec<(SourceId, RequestId)>,
     },
 
     /// Header verification failed. A follow-up header is ready to be verified.
@@ -1144,13 +1743,12 @@ pub enum HeaderVerifyOutcome<TSrc, TBl> {
         /// Next verification.
         next_block: HeaderVerify<TSrc, TBl>,
         /// Error that happened.
-        error: verify::header_only::Error,
+        error: blocks_tree::HeaderVerifyError,
         /// User data that was passed to [`HeaderVerify::perform`] and is unused.
         user_data: TBl,
-        /// For each element in this list, the request made towards this source should be replaced
-        /// with a new one (if the request is `Some`) or cancelled (if the request is `None`).
-        /// Sources are guaranteed to never be the source that has sent back the header.
-        requests_replace: Vec<(SourceId, Option<Request>)>,
+        /// Requests that were targeting this block and that are now cancelled. These
+        /// [`RequestId`]s are now invalid.
+        cancelled_requests: Vec<(SourceId, RequestId)>,
     },
 }
 
@@ -1172,35 +1770,133 @@ impl<TBl> JustificationVerification<TBl> {
     }
 }
 
-/// State of the processing of blocks.
-pub enum BlockBodyVerify<TSrc, TBl> {
-    #[doc(hidden)]
-    Foo(core::marker::PhantomData<(TSrc, TBl)>),
-    // TODO: finish
-    /*/// Processing of the block is over.
-    ///
-    /// There might be more blocks remaining. Call [`AllForksSync::process_one`] again.
-    NewBest {
-        /// The state machine.
-        /// The [`AllForksSync::process_one`] method takes ownership of the
-        /// [`AllForksSync`]. This field yields it back.
+/// Outcome of calling [`AllForksSync::block_body_response`].
+pub enum BlockBodyResponseOutcome<TSrc, TBl> {
+    /// Body is ready to be verified.
+    Verify(BodyVerify<TSrc, TBl>),
+
+    /// The body couldn't be verified. The block stays in the state machine, and its body will
+    /// be requested again later.
+    Inconclusive {
         sync: AllForksSync<TSrc, TBl>,
 
-        new_best_number: u64,
-        new_best_hash: [u8; 32],
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
+
+        /// If `Some`, the source that the request was sent to has failed to provide the body,
+        /// or has provided a body that doesn't match the header of the block.
+        ///
+        /// The request isn't sent again to this source. The API user is encouraged to penalize
+        /// this source.
+        penalized_source: Option<SourceId>,
     },
+}
+
+/// Block body verification to be performed.
+///
+/// Internally holds the [`AllForksSync`].
+pub struct BodyVerify<TSrc, TBl> {
+    parent: AllForksSync<TSrc, TBl>,
+    /// Source that has provided the body.
+    source_id: SourceId,
+    /// Height of the block to verify.
+    block_height: u64,
+    /// Hash of the block to verify.
+    block_hash: [u8; 32],
+    /// Header of the block to verify.
+    scale_encoded_header: Vec<u8>,
+    /// Body of the block to verify. Guaranteed to match the extrinsics root of the header.
+    block_body: Vec<Vec<u8>>,
+}
 
-    /// Processing of the block is over. The block has been finalized.
+impl<TSrc, TBl> BodyVerify<TSrc, TBl> {
+    /// Source that has provided the body of the block to verify.
+    pub fn source_id(&self) -> SourceId {
+        self.source_id
+    }
+
+    /// Grants access to the user data of a source, using its identifier.
+    pub fn source_user_data_mut(&mut self, id: SourceId) -> Option<&mut TSrc> {
+        Some(self.parent.source_mut(id)?.into_user_data())
+    }
+
+    /// Start the verification.
     ///
-    /// There might be more blocks remaining. Call [`AllForksSync::process_one`] again.
-    Finalized {
-        /// The state machine.
-        /// The [`AllForksSync::process_one`] method takes ownership of the
-        /// [`AllForksSync`]. This field yields it back.
+    /// Must be passed the current UNIX time in order to verify that the block doesn't pretend to
+    /// come from the future.
+    pub fn perform(
+        self,
+        now_from_unix_epoch: Duration,
+        user_data: TBl,
+    ) -> BlockBodyVerify<TSrc, TBl> {
+        let step = self
+            .parent
+            .chain
+            .verify_body(self.scale_encoded_header.clone(), now_from_unix_epoch);
+
+        BlockBodyVerify::from(
+            BodyVerifyInner::Step1(step),
+            BodyVerifyShared {
+                inner: self.parent.inner,
+                block_height: self.block_height,
+                block_hash: self.block_hash,
+                scale_encoded_header: self.scale_encoded_header,
+                block_body: self.block_body,
+                block_user_data: Some(user_data),
+                parent_runtime_block: None,
+                parent_to_finalized_storage_diff: BTreeMap::new(),
+            },
+        )
+    }
+
+    // Note: no `cancel` method is provided, as it would leave the `AllForksSync` in a weird
+    // state.
+}
+
+/// State of the verification of a block body.
+#[must_use]
+pub enum BlockBodyVerify<TSrc, TBl> {
+    /// Block has been successfully verified and inserted in the chain.
+    Success {
+        /// True if the newly-verified block is considered the new best block.
+        is_new_best: bool,
+        /// If `Some`, the author of the newly-verified block has already produced a different
+        /// block in the same slot. The block has nonetheless been inserted in the chain.
+        equivocation: Option<blocks_tree::EquivocationProof>,
+        /// If a justification was attached to this block, it has also been verified. Contains the
+        /// outcome.
+        ///
+        /// > **Note**: The newly-finalized blocks might include the block that has just been
+        /// >           verified. The block should be stored before applying the finalization.
+        justification_verification: JustificationVerification<TBl>,
+        /// State machine yielded back. Use to continue the processing.
         sync: AllForksSync<TSrc, TBl>,
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
+        /// Requests that were targeting this block and that are now cancelled. These
+        /// [`RequestId`]s are now invalid.
+        cancelled_requests: Vec<(SourceId, RequestId)>,
+        /// SCALE-encoded header of the newly-verified block.
+        scale_encoded_header: Vec<u8>,
+        /// List of SCALE-encoded extrinsics of the newly-verified block.
+        block_body: Vec<Vec<u8>>,
+        /// List of changes to the storage top trie that the block performs compared to its
+        /// parent.
+        storage_top_trie_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+        /// List of changes to the offchain storage that this block performs.
+        offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+    },
 
-        /// Blocks that have been finalized. Includes the block that has just been verified.
-        finalized_blocks: Vec<Block<TBl>>,
+    /// Block verification failed. The block and all its descendants have been discarded.
+    Error {
+        /// State machine yielded back. Use to continue the processing.
+        sync: AllForksSync<TSrc, TBl>,
+        /// Error that happened.
+        error: BodyVerifyError,
+        /// User data that was passed to [`BodyVerify::perform`] and is unused.
+        user_data: TBl,
+        /// Requests that must now be started.
+        next_requests: Vec<(SourceId, RequestId, Request)>,
     },
 
     /// Loading a storage value of the finalized block is required in order to continue.
@@ -1212,5 +1908,454 @@ pub enum BlockBodyVerify<TSrc, TBl> {
 
     /// Fetching the key of the finalized block storage that follows a given one is required in
     /// order to continue.
-    FinalizedStorageNextKey(StorageNextKey<TSrc, TBl>),*/
+    FinalizedStorageNextKey(StorageNextKey<TSrc, TBl>),
+}
+
+/// Error that can happen when verifying a block body.
+#[derive(Debug, derive_more::Display)]
+pub enum BodyVerifyError {
+    /// The block is in the list of [`Config::bad_blocks`], or conflicts with an entry of
+    /// [`Config::fork_blocks`].
+    #[display(fmt = "The block has been marked as bad.")]
+    BadBlock,
+    /// The block verification has failed. The block is invalid and should be thrown away.
+    VerificationFailed(verify::header_body::Error),
+}
+
+enum BodyVerifyInner<TBl> {
+    Step1(blocks_tree::BodyVerifyStep1<Block<TBl>>),
+    Step2(blocks_tree::BodyVerifyStep2<Block<TBl>>),
+}
+
+struct BodyVerifyShared<TSrc, TBl> {
+    /// See [`AllForksSync::inner`].
+    inner: Inner<TSrc>,
+    /// Height of the block being verified.
+    block_height: u64,
+    /// Hash of the block being verified.
+    block_hash: [u8; 32],
+    /// Header of the block being verified.
+    scale_encoded_header: Vec<u8>,
+    /// Body of the block being verified.
+    block_body: Vec<Vec<u8>>,
+    /// User data of the block being verified. Always `Some` until the verification is over.
+    block_user_data: Option<TBl>,
+    /// Hash of the block whose runtime has been lent to the verification, or `None` if it is
+    /// [`Inner::finalized_runtime`].
+    parent_runtime_block: Option<[u8; 32]>,
+    /// Changes in the storage of the parent of the block being verified compared to the
+    /// finalized block.
+    /// The `BTreeMap`'s keys are storage keys, and its values are new values or `None` if the
+    /// value has been erased from the storage.
+    parent_to_finalized_storage_diff: storage_diff::StorageDiff,
+}
+
+impl<TSrc, TBl> BodyVerifyShared<TSrc, TBl> {
+    /// Puts back the runtime that has been lent to the verification where it was taken from.
+    fn restore_parent_runtime(
+        &mut self,
+        chain: &mut blocks_tree::NonFinalizedTree<Block<TBl>>,
+        parent_runtime: host::HostVmPrototype,
+    ) {
+        if let Some(parent_runtime_block) = &self.parent_runtime_block {
+            let full = chain
+                .non_finalized_block_by_hash(parent_runtime_block)
+                .unwrap()
+                .into_user_data()
+                .full
+                .as_mut()
+                .unwrap();
+            debug_assert!(full.runtime.is_none());
+            full.runtime = Some(parent_runtime);
+        } else {
+            debug_assert!(self.inner.finalized_runtime.is_none());
+            self.inner.finalized_runtime = Some(parent_runtime);
+        }
+    }
+}
+
+impl<TSrc, TBl> BlockBodyVerify<TSrc, TBl> {
+    fn from(mut inner: BodyVerifyInner<TBl>, mut shared: BodyVerifyShared<TSrc, TBl>) -> Self {
+        // This loop drives the process of the verification.
+        // `inner` is updated at each iteration until a state that cannot be resolved internally
+        // is found.
+        loop {
+            match inner {
+                BodyVerifyInner::Step1(blocks_tree::BodyVerifyStep1::ParentRuntimeRequired(
+                    mut req,
+                )) => {
+                    let num_ancestors = req.num_non_finalized_ancestors();
+
+                    // Build the difference between the storage of the parent and the storage of
+                    // the finalized block, by applying the changes of each non-finalized
+                    // ancestor, starting from the oldest.
+                    for n in (0..num_ancestors).rev() {
+                        let block = req.nth_ancestor(n).unwrap().into_user_data();
+                        for (key, value) in &block.full.as_ref().unwrap().storage_top_trie_changes {
+                            shared
+                                .parent_to_finalized_storage_diff
+                                .insert(key.clone(), value.clone());
+                        }
+                    }
+
+                    // The runtime of the parent is the one of the closest ancestor that has
+                    // modified the runtime, or the one of the finalized block if there is none.
+                    let mut parent_runtime = None;
+                    for n in 0..num_ancestors {
+                        let block = req.nth_ancestor(n).unwrap().into_user_data();
+                        if let Some(runtime) = block.full.as_mut().unwrap().runtime.take() {
+                            shared.parent_runtime_block = Some(block.header.hash());
+                            parent_runtime = Some(runtime);
+                            break;
+                        }
+                    }
+                    let parent_runtime = match parent_runtime {
+                        Some(r) => r,
+                        None => shared.inner.finalized_runtime.take().unwrap(),
+                    };
+
+                    let top_trie_root_calculation_cache = req.parent_block().and_then(|b| {
+                        b.into_user_data()
+                            .full
+                            .as_mut()
+                            .unwrap()
+                            .top_trie_root_calculation_cache
+                            .take()
+                    });
+
+                    inner = BodyVerifyInner::Step2(req.resume(
+                        parent_runtime,
+                        shared.block_body.iter(),
+                        top_trie_root_calculation_cache,
+                    ));
+                }
+
+                BodyVerifyInner::Step2(blocks_tree::BodyVerifyStep2::Finished {
+                    storage_top_trie_changes,
+                    offchain_storage_changes,
+                    top_trie_root_calculation_cache,
+                    parent_runtime,
+                    new_runtime,
+                    insert,
+                }) => {
+                    // Successfully verified block!
+                    debug_assert_eq!(
+                        new_runtime.is_some(),
+                        storage_top_trie_changes.contains_key(&b":code"[..])
+                            || storage_top_trie_changes.contains_key(&b":heappages"[..])
+                    );
+
+                    let is_new_best = insert.is_new_best();
+                    let equivocation = insert.equivocation();
+
+                    // The justification of blocks that change the list of GrandPa authorities
+                    // is necessary in order to verify the justifications of their descendants.
+                    let has_grandpa_authorities_change =
+                        insert.header().digest.has_grandpa_authorities_change();
+
+                    let mut chain = {
+                        let header = insert.header().into();
+                        insert.insert(Block {
+                            header,
+                            full: Some(BlockFull {
+                                // TODO: cloning the storage changes :-/
+                                storage_top_trie_changes: storage_top_trie_changes.clone(),
+                                runtime: new_runtime,
+                                top_trie_root_calculation_cache: Some(
+                                    top_trie_root_calculation_cache,
+                                ),
+                            }),
+                            user_data: shared.block_user_data.take().unwrap(),
+                        })
+                    };
+
+                    shared.restore_parent_runtime(&mut chain, parent_runtime);
+
+                    let mut sync = AllForksSync {
+                        chain,
+                        inner: shared.inner,
+                    };
+
+                    // Remove the verified block from `pending_blocks`. The children of this block
+                    // returned by `remove_verify_success` are ignored, as their bodies are
+                    // requested by `next_requests` below.
+                    let outcome = sync
+                        .inner
+                        .pending_blocks
+                        .block_mut(shared.block_height, shared.block_hash)
+                        .into_occupied()
+                        .unwrap()
+                        .remove_verify_success();
+
+                    if has_grandpa_authorities_change {
+                        sync.inner
+                            .justification_requests
+                            .insert((shared.block_height, shared.block_hash), false);
+                    }
+
+                    let justification_verification =
+                        if let Some(justification) = outcome.user_data.justification {
+                            sync.verify_justification(&justification)
+                        } else {
+                            JustificationVerification::NoJustification
+                        };
+
+                    let mut cancelled_requests =
+                        Vec::with_capacity(outcome.cancelled_requests.len());
+                    for (request_id, source_id) in outcome.cancelled_requests {
+                        sync.inner
+                            .sources
+                            .source_mut(source_id)
+                            .unwrap()
+                            .user_data()
+                            .num_ongoing_requests -= 1;
+                        cancelled_requests.push((source_id, request_id));
+                    }
+
+                    let next_requests = sync.next_requests();
+
+                    break BlockBodyVerify::Success {
+                        is_new_best,
+                        equivocation,
+                        justification_verification,
+                        sync,
+                        next_requests,
+                        cancelled_requests,
+                        scale_encoded_header: shared.scale_encoded_header,
+                        block_body: shared.block_body,
+                        storage_top_trie_changes,
+                        offchain_storage_changes,
+                    };
+                }
+
+                BodyVerifyInner::Step2(blocks_tree::BodyVerifyStep2::StorageGet(req)) => {
+                    // The underlying verification process is asking for a storage entry in the
+                    // parent block.
+                    //
+                    // The requested value is either found in the diff between the parent and the
+                    // finalized block, in which case it can be returned immediately to continue
+                    // the verification, or in the finalized block, in which case the user needs
+                    // to be queried.
+                    if let Some(value) = storage_diff::get(
+                        &shared.parent_to_finalized_storage_diff,
+                        &req.key_as_vec(),
+                    ) {
+                        inner = BodyVerifyInner::Step2(req.inject_value(value.map(iter::once)));
+                        continue;
+                    }
+
+                    break BlockBodyVerify::FinalizedStorageGet(StorageGet { inner: req, shared });
+                }
+
+                BodyVerifyInner::Step2(blocks_tree::BodyVerifyStep2::StorageNextKey(req)) => {
+                    // The underlying verification process is asking for the key that follows
+                    // the requested one.
+                    break BlockBodyVerify::FinalizedStorageNextKey(StorageNextKey {
+                        inner: req,
+                        shared,
+                        key_overwrite: None,
+                    });
+                }
+
+                BodyVerifyInner::Step2(blocks_tree::BodyVerifyStep2::StoragePrefixKeys(req)) => {
+                    // The underlying verification process is asking for all the keys that start
+                    // with a certain prefix.
+                    // The first step is to ask the user for that information when it comes to
+                    // the finalized block.
+                    break BlockBodyVerify::FinalizedStoragePrefixKeys(StoragePrefixKeys {
+                        inner: req,
+                        shared,
+                    });
+                }
+
+                BodyVerifyInner::Step2(blocks_tree::BodyVerifyStep2::RuntimeCompilation(c)) => {
+                    // The underlying verification process requires compiling a runtime code.
+                    inner = BodyVerifyInner::Step2(c.build());
+                }
+
+                BodyVerifyInner::Step2(blocks_tree::BodyVerifyStep2::Error {
+                    mut chain,
+                    error,
+                    parent_runtime,
+                }) => {
+                    shared.restore_parent_runtime(&mut chain, parent_runtime);
+                    break Self::error(chain, shared, BodyVerifyError::VerificationFailed(error));
+                }
+
+                BodyVerifyInner::Step1(blocks_tree::BodyVerifyStep1::BadBlock(chain)) => {
+                    break Self::error(chain, shared, BodyVerifyError::BadBlock);
+                }
+
+                // The header of the block has been decoded and its parent has been verified in
+                // `block_body_response`, and blocks in `pending_blocks` are never part of the
+                // chain.
+                BodyVerifyInner::Step1(blocks_tree::BodyVerifyStep1::Duplicate(_))
+                | BodyVerifyInner::Step1(blocks_tree::BodyVerifyStep1::InvalidHeader(..))
+                | BodyVerifyInner::Step1(blocks_tree::BodyVerifyStep1::BadParent { .. }) => {
+                    unreachable!()
+                }
+            }
+        }
+    }
+
+    /// Builds a [`BlockBodyVerify::Error`] after the block has failed to verify.
+    fn error(
+        chain: blocks_tree::NonFinalizedTree<Block<TBl>>,
+        shared: BodyVerifyShared<TSrc, TBl>,
+        error: BodyVerifyError,
+    ) -> Self {
+        let mut sync = AllForksSync {
+            chain,
+            inner: shared.inner,
+        };
+
+        sync.inner
+            .pending_blocks
+            .block_mut(shared.block_height, shared.block_hash)
+            .into_occupied()
+            .unwrap()
+            .remove_verify_failed();
+
+        let next_requests = sync.next_requests();
+
+        BlockBodyVerify::Error {
+            sync,
+            error,
+            user_data: shared.block_user_data.unwrap(),
+            next_requests,
+        }
+    }
+}
+
+/// Loading a storage value is required in order to continue.
+#[must_use]
+pub struct StorageGet<TSrc, TBl> {
+    inner: blocks_tree::StorageGet<Block<TBl>>,
+    shared: BodyVerifyShared<TSrc, TBl>,
+}
+
+impl<TSrc, TBl> StorageGet<TSrc, TBl> {
+    /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl Iterator<Item = impl AsRef<[u8]> + '_> + '_ {
+        self.inner.key()
+    }
+
+    /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
+    ///
+    /// This method is a shortcut for calling `key` and concatenating the returned slices.
+    pub fn key_as_vec(&self) -> Vec<u8> {
+        self.inner.key_as_vec()
+    }
+
+    /// Injects the corresponding storage value.
+    pub fn inject_value(self, value: Option<&[u8]>) -> BlockBodyVerify<TSrc, TBl> {
+        let inner = self.inner.inject_value(value.map(iter::once));
+        BlockBodyVerify::from(BodyVerifyInner::Step2(inner), self.shared)
+    }
+}
+
+/// Fetching the list of keys with a given prefix is required in order to continue.
+#[must_use]
+pub struct StoragePrefixKeys<TSrc, TBl> {
+    inner: blocks_tree::StoragePrefixKeys<Block<TBl>>,
+    shared: BodyVerifyShared<TSrc, TBl>,
+}
+
+impl<TSrc, TBl> StoragePrefixKeys<TSrc, TBl> {
+    /// Returns the prefix whose keys to load.
+    pub fn prefix(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.prefix()
+    }
+
+    /// Injects the list of keys.
+    pub fn inject_keys(
+        self,
+        keys: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> BlockBodyVerify<TSrc, TBl> {
+        let keys = storage_diff::prefix_keys(
+            &self.shared.parent_to_finalized_storage_diff,
+            self.inner.prefix().as_ref(),
+            keys,
+        );
+
+        let inner = self.inner.inject_keys(keys.iter());
+        BlockBodyVerify::from(BodyVerifyInner::Step2(inner), self.shared)
+    }
+}
+
+/// Fetching the key that follows a given one is required in order to continue.
+#[must_use]
+pub struct StorageNextKey<TSrc, TBl> {
+    inner: blocks_tree::StorageNextKey<Block<TBl>>,
+    shared: BodyVerifyShared<TSrc, TBl>,
+
+    /// If `Some`, ask for the key inside of this field rather than the one of `inner`. Used in
+    /// corner-case situations where the key provided by the user has been erased from storage.
+    key_overwrite: Option<Vec<u8>>,
+}
+
+impl<TSrc, TBl> StorageNextKey<TSrc, TBl> {
+    /// Returns the key whose next key must be passed back.
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        if let Some(key_overwrite) = &self.key_overwrite {
+            either::Left(key_overwrite)
+        } else {
+            either::Right(self.inner.key())
+        }
+    }
+
+    /// Injects the key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the key passed as parameter isn't strictly superior to the requested key.
+    ///
+    pub fn inject_key(self, key: Option<impl AsRef<[u8]>>) -> BlockBodyVerify<TSrc, TBl> {
+        let key = key.as_ref().map(|k| k.as_ref());
+
+        // The key provided by the user as parameter is the next key in the storage of the
+        // finalized block.
+        // `parent_to_finalized_storage_diff` needs to be taken into account in order to provide
+        // the next key in the parent block instead.
+
+        let inner_key = self.inner.key();
+        let requested_key = if let Some(key_overwrite) = &self.key_overwrite {
+            key_overwrite
+        } else {
+            inner_key.as_ref()
+        };
+
+        let outcome = match storage_diff::next_key(
+            &self.shared.parent_to_finalized_storage_diff,
+            requested_key,
+            key,
+        ) {
+            storage_diff::NextKey::Found(outcome) => outcome,
+            storage_diff::NextKey::AskAgain(key_overwrite) => {
+                drop(inner_key); // Solves borrowing errors.
+                return BlockBodyVerify::FinalizedStorageNextKey(StorageNextKey {
+                    inner: self.inner,
+                    shared: self.shared,
+                    key_overwrite: Some(key_overwrite),
+                });
+            }
+        };
+
+        drop(inner_key); // Solves borrowing errors.
+        let inner = self.inner.inject_key(outcome);
+        BlockBodyVerify::from(BodyVerifyInner::Step2(inner), self.shared)
+    }
+}
+
+/// Calculates the Merkle value of the root of the trie containing the given list of extrinsics,
+/// in other words the value that the `extrinsics_root` field of the header of the block should
+/// contain.
+fn extrinsics_trie_root(extrinsics: &[Vec<u8>]) -> [u8; 32] {
+    // TODO: optimize this
+    let mut trie = trie::Trie::new();
+    for (index, extrinsic) in extrinsics.iter().enumerate() {
+        let key = util::encode_scale_compact_usize(index);
+        trie.insert(key.as_ref(), extrinsic.clone());
+    }
+    trie.root_merkle_value(None)
 }
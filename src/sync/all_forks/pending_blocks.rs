@@ -45,7 +45,11 @@
 //! - Calling [`OccupiedBlockEntry::remove_verify_failed`] marks a block and all its descendants
 //! as invalid. This may or may not remove the block itself and all its descendants.
 //! - Calling [`OccupiedBlockEntry::remove_uninteresting`] removes a block in order to reduce
-//! the memory usage of the data structure.
+//! the memory usage of the data structure. Use [`PendingBlocks::least_interesting_block`] to
+//! determine which block to remove.
+//!
+//! Additionally, [`PendingBlocks::remove_up_to_height`] removes all the blocks below a certain
+//! height, which is useful after the finalized block has been updated.
 //!
 //! # Requests
 //!
@@ -58,6 +62,10 @@
 //! [`PendingBlocks::desired_queries`].
 //! Call [`PendingBlocks::finish_request`] to destroy a request after it has finished.
 //!
+//! Removing a block, except through [`OccupiedBlockEntry::remove_verify_success`], doesn't
+//! remove the requests associated to it. These requests stay valid and must still be destroyed
+//! with [`PendingBlocks::finish_request`] once they have finished.
+//!
 
 use crate::header;
 
@@ -207,6 +215,49 @@ impl<TBl, TRq> PendingBlocks<TBl, TRq> {
         )
     }
 
+    /// Adds a new request to the collection targeting a block that isn't necessarily in the
+    /// collection. Allocates a new [`RequestId`].
+    ///
+    /// Contrary to [`OccupiedBlockEntry::add_descending_request`], this method is meant to be used
+    /// for requests concerning blocks that aren't pending, for example in order to download the
+    /// justification of a block that has already been verified.
+    pub fn add_unpending_block_request(
+        &mut self,
+        height: u64,
+        hash: [u8; 32],
+        user_data: TRq,
+    ) -> RequestId {
+        let request_id = RequestId(self.requests.insert(Request {
+            target_block: (height, hash),
+            user_data,
+        }));
+
+        self.blocks_requests.insert((height, hash, request_id));
+        request_id
+    }
+
+    /// Returns the list of all ongoing requests, with their user data.
+    pub fn requests(&'_ self) -> impl Iterator<Item = (RequestId, &'_ TRq)> + '_ {
+        self.requests
+            .iter()
+            .map(|(id, request)| (RequestId(id), &request.user_data))
+    }
+
+    /// Returns the list of ongoing requests whose first requested block is the one passed as
+    /// parameter.
+    pub fn block_requests(
+        &'_ self,
+        height: u64,
+        hash: &[u8; 32],
+    ) -> impl Iterator<Item = (RequestId, &'_ TRq)> + '_ {
+        self.blocks_requests
+            .range(
+                (height, *hash, RequestId(usize::min_value()))
+                    ..=(height, *hash, RequestId(usize::max_value())),
+            )
+            .map(move |(_, _, id)| (*id, &self.requests[id.0].user_data))
+    }
+
     /// Returns an iterator yielding blocks that should be requested from the sources of blocks.
     ///
     /// Considering this state machine is unable to differentiate between blocks that are
@@ -256,6 +307,50 @@ impl<TBl, TRq> PendingBlocks<TBl, TRq> {
         // TODO: all query the parents of `UnverifiedHeader` blocks if they're not present
     }
 
+    /// Returns the list of blocks whose header is known but that haven't been verified yet.
+    ///
+    /// Yields the height, hash, and parent hash of each block. Blocks marked as bad aren't
+    /// returned.
+    pub fn unverified_headers(&'_ self) -> impl Iterator<Item = (u64, [u8; 32], [u8; 32])> + '_ {
+        // TODO: O(n)
+        self.blocks
+            .iter()
+            .filter(|(_, block)| matches!(block.inner, BlockInner::UnverifiedHeader { .. }))
+            .map(|((height, hash), block)| (*height, *hash, *block.inner.parent_hash().unwrap()))
+    }
+
+    /// Returns the block that should be removed first in order to reduce the memory usage of
+    /// the data structure, or `None` if no block is appropriate for removal.
+    ///
+    /// Blocks with the highest height are considered as the least interesting, as blocks closer
+    /// to the finalized block are necessary in order to make progress. Blocks that have an
+    /// ongoing request are never returned.
+    pub fn least_interesting_block(&self) -> Option<(u64, [u8; 32])> {
+        // TODO: O(n)
+        self.blocks
+            .keys()
+            .rev()
+            .find(|(height, hash)| {
+                self.blocks_requests
+                    .range(
+                        (*height, *hash, RequestId(usize::min_value()))
+                            ..=(*height, *hash, RequestId(usize::max_value())),
+                    )
+                    .next()
+                    .is_none()
+            })
+            .copied()
+    }
+
+    /// Removes from the collection all the blocks whose height is inferior or equal to the value
+    /// passed as parameter.
+    ///
+    /// Ongoing requests aren't affected.
+    pub fn remove_up_to_height(&mut self, height: u64) {
+        // `split_off` returns the entries above or equal to the key, which are the ones to keep.
+        self.blocks = self.blocks.split_off(&(height + 1, [0; 32]));
+    }
+
     /// Gives access to a block, either present or absent.
     pub fn block_mut(&mut self, height: u64, hash: [u8; 32]) -> BlockEntry<TBl, TRq> {
         let key = (height, hash);
@@ -266,74 +361,41 @@ impl<TBl, TRq> PendingBlocks<TBl, TRq> {
         }
     }
 
-    /*/// Passed a known entry in `blocks`. Removes this entry and any known children of this block.
-    ///
-    /// # Panic
-    ///
-    /// Panics if `(number, hash)` isn't an entry in [`PendingBlocks::blocks]`.
-    ///
-    fn discard_chain(&mut self, number: u64, hash: [u8; 32]) -> Vec<(u64, [u8; 32])> {
-        // TODO: keep a list of banned blocks for later? this is required by chain specs anyway
-
+    /// Passed a block height and hash. Removes any known descendant of this block from the
+    /// collection. The block itself isn't removed.
+    fn discard_descendants(&mut self, height: u64, hash: [u8; 32]) {
         // The implementation consists in iterating over the increasing block number, and removing
         // all blocks whose parent was removed at the previous iteration.
 
-        // Return value of the function.
-        let mut result = Vec::with_capacity(64);
+        // List of blocks whose children must be discarded at the next iteration.
+        let mut parents = Vec::with_capacity(16);
+        parents.push(hash);
 
-        // List of blocks to discard at the next iteration.
-        let mut blocks_to_discard = Vec::with_capacity(16);
-        blocks_to_discard.push(hash);
-
-        for number in number.. {
+        for number in (height + 1).. {
             // The `for` loop would be infinite unless we put an explicit `break`.
-            if blocks_to_discard.is_empty() {
+            if parents.is_empty() {
                 break;
             }
 
-            // Find in `disjoint_headers` any block whose parent is in `blocks_to_discard`.
-            let blocks_to_discard_next = {
-                let mut blocks_to_discard_next = Vec::with_capacity(16);
-                for ((_, hash), block) in self
-                    .blocks
-                    .range((number + 1, [0; 32])..=(number + 1, [0xff; 32]))
-                {
-                    let decoded = header::decode(&block.scale_encoded_header).unwrap();
-                    if blocks_to_discard.iter().any(|b| b == decoded.parent_hash) {
-                        blocks_to_discard_next.push(*hash);
-                    }
-                }
-                blocks_to_discard_next
-            };
-
-            // Now discard `blocks_to_discard`.
-            for to_discard in mem::replace(&mut blocks_to_discard, blocks_to_discard_next) {
-                let mut discarded_block = self.blocks.remove(&(number, to_discard)).unwrap();
-
-                let requests_ids = self
-                    .blocks_requests
-                    .range(
-                        (number, to_discard, RequestId(usize::min_value()))
-                            ..=(number, to_discard, RequestId(usize::max_value())),
-                    )
-                    .map(|(_, _, id)| *id)
-                    .collect::<Vec<_>>();
-
-                for request_id in requests_ids {
-                    let _was_in = self
-                        .blocks_requests
-                        .remove(&(number, to_discard, request_id));
-                    debug_assert!(_was_in);
-
-                    let request = self.requests.remove(&request_id.0).unwrap();
-                }
+            let to_discard = self
+                .blocks
+                .range((number, [0x0; 32])..=(number, [0xff; 32]))
+                .filter(|(_, block)| {
+                    block.inner.parent_hash().map_or(false, |parent_hash| {
+                        parents.iter().any(|p| p == parent_hash)
+                    })
+                })
+                .map(|((_, hash), _)| *hash)
+                .collect::<Vec<_>>();
 
-                result.push((number, to_discard, discarded_block));
+            for hash in &to_discard {
+                let _was_in = self.blocks.remove(&(number, *hash));
+                debug_assert!(_was_in.is_some());
             }
-        }
 
-        result
-    }*/
+            parents = to_discard;
+        }
+    }
 
     /// Returns the list of children of the given block that are in the collection.
     fn children_mut<'a>(
@@ -413,6 +475,17 @@ impl<'a, TBl, TRq> OccupiedBlockEntry<'a, TBl, TRq> {
         &mut block.user_data
     }
 
+    /// Returns the SCALE-encoded header of the block, if it is known and the block hasn't been
+    /// marked as bad.
+    pub fn scale_encoded_header(&self) -> Option<&[u8]> {
+        match &self.parent.blocks.get(&self.key).unwrap().inner {
+            BlockInner::UnverifiedHeader {
+                scale_encoded_header,
+            } => Some(scale_encoded_header),
+            BlockInner::Unknown | BlockInner::KnownBad { .. } => None,
+        }
+    }
+
     /// Sets the header of the block.
     pub fn update_header(&mut self, header: Vec<u8>) {
         debug_assert_eq!(header::hash_from_scale_encoded_header(&header), self.key.1);
@@ -507,44 +580,33 @@ impl<'a, TBl, TRq> OccupiedBlockEntry<'a, TBl, TRq> {
 
     /// Removes the block from the collection, as its verification has failed.
     ///
-    /// In addition to removing the block itself, all its descendants are marked as "bad" and
-    /// might be removed as well.
+    /// In addition to removing the block itself, all its descendants are removed as well. If the
+    /// header of the block is known, the block is kept in the collection and marked as bad in
+    /// order to be able to immediately reject its children in the future.
+    ///
+    /// Ongoing requests aren't affected.
     pub fn remove_verify_failed(self) {
-        // Actually remove from list.
-        let removed_block: Block<_> = self.parent.blocks.remove(&self.key).unwrap();
-
-        let cancelled_requests = {
-            let ids = self
-                .parent
-                .blocks_requests
-                .range(
-                    (self.key.0, self.key.1, RequestId(usize::min_value()))
-                        ..=(self.key.0, self.key.1, RequestId(usize::max_value())),
-                )
-                .map(|(_, _, id)| *id)
-                .collect::<Vec<_>>();
+        self.parent.discard_descendants(self.key.0, self.key.1);
 
-            let mut cancelled_requests = Vec::with_capacity(ids.len());
-            for id in ids {
-                self.parent
-                    .blocks_requests
-                    .remove(&(self.key.0, self.key.1, id));
-                let request = self.parent.requests.remove(id.0);
-                debug_assert_eq!(request.target_block, self.key);
-                cancelled_requests.push((id, request.user_data));
+        let block = self.parent.blocks.get_mut(&self.key).unwrap();
+        match block.inner.parent_hash().copied() {
+            Some(parent_hash) => {
+                block.inner = BlockInner::KnownBad { parent_hash };
             }
-            cancelled_requests
-        };
-
-        // TODO: remove children of the block as well
-        // TODO: remove ongoing requests?
-        todo!()
+            None => {
+                self.parent.blocks.remove(&self.key).unwrap();
+            }
+        }
     }
 
     /// Removes the block from the collection in order to leave space.
-    pub fn remove_uninteresting(self) {
-        // TODO: temporary implementation
-        self.remove_verify_failed();
+    ///
+    /// Contrary to [`OccupiedBlockEntry::remove_verify_failed`], the descendants of this block
+    /// are kept.
+    ///
+    /// Ongoing requests aren't affected.
+    pub fn remove_uninteresting(self) -> TBl {
+        self.parent.blocks.remove(&self.key).unwrap().user_data
     }
 
     /// Adds a new request to the collection. Allocates a new [`RequestId`].
@@ -612,3 +674,150 @@ impl<'a, TBl, TRq> VacantBlockEntry<'a, TBl, TRq> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::header;
+    use core::num::{NonZeroU32, NonZeroU64};
+
+    /// Builds a header with the given parent and height. Returns its hash and SCALE encoding.
+    fn build_header(parent_hash: [u8; 32], number: u64) -> ([u8; 32], Vec<u8>) {
+        let encoded = header::HeaderRef {
+            parent_hash: &parent_hash,
+            number,
+            state_root: &[0; 32],
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::empty(),
+        }
+        .scale_encoding_vec();
+        (header::hash_from_scale_encoded_header(&encoded), encoded)
+    }
+
+    fn new_collection() -> super::PendingBlocks<(), ()> {
+        super::PendingBlocks::new(super::Config {
+            blocks_capacity: 32,
+            max_requests_per_block: NonZeroU32::new(1).unwrap(),
+        })
+    }
+
+    #[test]
+    fn least_interesting_block_is_highest_without_request() {
+        let mut blocks = new_collection();
+        blocks.block_mut(5, [5; 32]).or_insert(());
+        blocks.block_mut(6, [6; 32]).or_insert(());
+        blocks.block_mut(7, [7; 32]).or_insert(());
+        assert_eq!(blocks.least_interesting_block(), Some((7, [7; 32])));
+
+        let request_id = blocks
+            .block_mut(7, [7; 32])
+            .into_occupied()
+            .unwrap()
+            .add_descending_request((), NonZeroU64::new(1).unwrap());
+        assert_eq!(blocks.least_interesting_block(), Some((6, [6; 32])));
+
+        blocks
+            .block_mut(6, [6; 32])
+            .into_occupied()
+            .unwrap()
+            .remove_uninteresting();
+        assert_eq!(blocks.num_blocks(), 2);
+        assert_eq!(blocks.least_interesting_block(), Some((5, [5; 32])));
+
+        // Requests stay valid even if their block is removed.
+        blocks
+            .block_mut(7, [7; 32])
+            .into_occupied()
+            .unwrap()
+            .remove_uninteresting();
+        assert_eq!(blocks.finish_request(request_id), ((), 7, [7; 32]));
+    }
+
+    #[test]
+    fn remove_verify_failed_discards_descendants() {
+        let mut blocks = new_collection();
+
+        let (hash1, header1) = build_header([0; 32], 1);
+        let (hash2, header2) = build_header(hash1, 2);
+        let (hash3, header3) = build_header(hash2, 3);
+        let (other_hash2, other_header2) = build_header([0xaa; 32], 2);
+
+        for (number, hash, header) in [
+            (1, hash1, header1),
+            (2, hash2, header2),
+            (3, hash3, header3),
+            (2, other_hash2, other_header2),
+        ] {
+            blocks
+                .block_mut(number, hash)
+                .or_insert(())
+                .update_header(header);
+        }
+        assert_eq!(blocks.num_blocks(), 4);
+
+        blocks
+            .block_mut(1, hash1)
+            .into_occupied()
+            .unwrap()
+            .remove_verify_failed();
+
+        // Block 1 is kept as bad, its descendants are removed, and the unrelated block stays.
+        assert_eq!(blocks.num_blocks(), 2);
+        assert!(blocks.block_mut(1, hash1).into_occupied().is_some());
+        assert!(blocks.block_mut(2, hash2).into_occupied().is_none());
+        assert!(blocks.block_mut(3, hash3).into_occupied().is_none());
+        assert!(blocks.block_mut(2, other_hash2).into_occupied().is_some());
+
+        // Bad blocks aren't requested.
+        assert_eq!(blocks.desired_queries().count(), 0);
+    }
+
+    #[test]
+    fn remove_up_to_height() {
+        let mut blocks = new_collection();
+        blocks.block_mut(5, [5; 32]).or_insert(());
+        blocks.block_mut(6, [6; 32]).or_insert(());
+        blocks.block_mut(7, [7; 32]).or_insert(());
+
+        blocks.remove_up_to_height(6);
+        assert_eq!(blocks.num_blocks(), 1);
+        assert!(blocks.block_mut(7, [7; 32]).into_occupied().is_some());
+    }
+
+    #[test]
+    fn unverified_headers() {
+        let mut blocks = new_collection();
+
+        let (hash1, header1) = build_header([0; 32], 1);
+        let (hash2, header2) = build_header(hash1, 2);
+        blocks.block_mut(3, [3; 32]).or_insert(());
+        blocks
+            .block_mut(1, hash1)
+            .or_insert(())
+            .update_header(header1.clone());
+        blocks
+            .block_mut(2, hash2)
+            .or_insert(())
+            .update_header(header2);
+
+        assert_eq!(
+            blocks.unverified_headers().collect::<Vec<_>>(),
+            vec![(1, hash1, [0; 32]), (2, hash2, hash1)]
+        );
+        assert_eq!(
+            blocks
+                .block_mut(1, hash1)
+                .into_occupied()
+                .unwrap()
+                .scale_encoded_header(),
+            Some(&header1[..])
+        );
+
+        // Bad blocks and their descendants are no longer returned.
+        blocks
+            .block_mut(1, hash1)
+            .into_occupied()
+            .unwrap()
+            .remove_verify_failed();
+        assert_eq!(blocks.unverified_headers().count(), 0);
+    }
+}
@@ -55,11 +55,23 @@ pub struct AllForksSources<TSrc> {
     /// Height of the finalized block. All sources whose best block number is superior to this
     /// value is expected to know the entire finalized chain.
     finalized_block_height: u64,
+
+    /// Maximum number of entries in [`AllForksSources::known_blocks1`] per source, not counting
+    /// the best block of the source.
+    max_known_blocks_per_source: usize,
 }
 
 impl<TSrc> AllForksSources<TSrc> {
     /// Creates a new container. Must be passed the height of the known finalized block.
-    pub fn new(sources_capacity: usize, finalized_block_height: u64) -> Self {
+    ///
+    /// Each source can know at most `max_known_blocks_per_source` blocks in addition to its best
+    /// block. When this limit is reached, the known blocks with the highest height are forgotten
+    /// first.
+    pub fn new(
+        sources_capacity: usize,
+        finalized_block_height: u64,
+        max_known_blocks_per_source: usize,
+    ) -> Self {
         AllForksSources {
             sources: hashbrown::HashMap::with_capacity_and_hasher(
                 sources_capacity,
@@ -69,6 +81,7 @@ impl<TSrc> AllForksSources<TSrc> {
             known_blocks1: Default::default(),
             known_blocks2: Default::default(),
             finalized_block_height,
+            max_known_blocks_per_source,
         }
     }
 
@@ -119,6 +132,7 @@ impl<TSrc> AllForksSources<TSrc> {
             Source {
                 best_block_number,
                 best_block_hash,
+                num_known_blocks: 0,
                 user_data,
             },
         );
@@ -128,6 +142,7 @@ impl<TSrc> AllForksSources<TSrc> {
                 .insert((new_id, best_block_number, best_block_hash));
             self.known_blocks2
                 .insert((best_block_number, best_block_hash, new_id));
+            self.sources.get_mut(&new_id).unwrap().num_known_blocks = 1;
         }
 
         SourceMutAccess {
@@ -153,6 +168,7 @@ impl<TSrc> AllForksSources<TSrc> {
             self.known_blocks2.remove(&(height, hash, source_id));
             let _was_in = self.known_blocks1.remove(&(source_id, height, hash));
             debug_assert!(_was_in);
+            self.sources.get_mut(&source_id).unwrap().num_known_blocks -= 1;
         }
     }
 
@@ -195,11 +211,40 @@ impl<TSrc> AllForksSources<TSrc> {
             self.known_blocks2.remove(&(height, hash, source_id));
             let _was_in = self.known_blocks1.remove(&(source_id, height, hash));
             debug_assert!(_was_in);
+            self.sources.get_mut(&source_id).unwrap().num_known_blocks -= 1;
         }
 
         self.finalized_block_height = height;
     }
 
+    /// Returns the list of sources that know the block with the given height and hash, ordered
+    /// by increasing [`SourceId`].
+    ///
+    /// Only the sources for which this block has been explicitly registered are returned, as
+    /// explained in [`SourceMutAccess::knows_block`]. Sources that only know about a descendant
+    /// of this block aren't returned.
+    ///
+    /// See also [`SourceMutAccess::knows_block`]. Contrary to this other method, no panic happens
+    /// if `height` is inferior or equal to the finalized block height, and an empty list is
+    /// returned instead.
+    pub fn knows_block_sources(
+        &'_ self,
+        height: u64,
+        hash: &[u8; 32],
+    ) -> impl Iterator<Item = SourceId> + '_ {
+        self.known_blocks2
+            .range(
+                (height, *hash, SourceId(u64::min_value()))
+                    ..=(height, *hash, SourceId(u64::max_value())),
+            )
+            .map(|(_, _, source_id)| *source_id)
+    }
+
+    /// Returns the user data of the given source, or `None` if the identifier is invalid.
+    pub fn source_user_data(&self, id: SourceId) -> Option<&TSrc> {
+        self.sources.get(&id).map(|source| &source.user_data)
+    }
+
     /// Grants access to a source, using its identifier.
     pub fn source_mut(&mut self, id: SourceId) -> Option<SourceMutAccess<TSrc>> {
         if self.sources.contains_key(&id) {
@@ -229,6 +274,9 @@ impl<TSrc: fmt::Debug> fmt::Debug for AllForksSources<TSrc> {
 struct Source<TSrc> {
     best_block_number: u64,
     best_block_hash: [u8; 32],
+    /// Number of entries of [`AllForksSources::known_blocks1`] that concern this source,
+    /// including its best block.
+    num_known_blocks: usize,
     user_data: TSrc,
 }
 
@@ -249,15 +297,50 @@ impl<'a, TSrc> SourceMutAccess<'a, TSrc> {
     /// Registers a new block that the source is aware of.
     ///
     /// Has no effect if `height` is inferior or equal to the finalized block height.
+    ///
+    /// If the source already knows the maximum number of blocks, the known block with the
+    /// highest height, excluding the best block of the source, is forgotten.
     pub fn add_known_block(&mut self, height: u64, hash: [u8; 32]) {
-        if height > self.parent.finalized_block_height {
-            self.parent
+        if height <= self.parent.finalized_block_height {
+            return;
+        }
+
+        if !self
+            .parent
+            .known_blocks1
+            .insert((self.source_id, height, hash))
+        {
+            return;
+        }
+        self.parent
+            .known_blocks2
+            .insert((height, hash, self.source_id));
+        let source = self.parent.sources.get_mut(&self.source_id).unwrap();
+        source.num_known_blocks += 1;
+
+        // Enforce the limit to the number of known blocks.
+        let best_block = (source.best_block_number, source.best_block_hash);
+        let num_non_best_known_blocks = source.num_known_blocks
+            - if self
+                .parent
                 .known_blocks1
-                .insert((self.source_id, height, hash));
-            self.parent
-                .known_blocks2
-                .insert((height, hash, self.source_id));
+                .contains(&(self.source_id, best_block.0, best_block.1))
+            {
+                1
+            } else {
+                0
+            };
+        if num_non_best_known_blocks <= self.parent.max_known_blocks_per_source {
+            return;
         }
+        let (_, to_remove_height, to_remove_hash) = *self
+            .parent
+            .known_blocks1
+            .range((self.source_id, 0, [0; 32])..=(self.source_id, u64::max_value(), [0xff; 32]))
+            .rev()
+            .find(|(_, n, h)| (*n, *h) != best_block)
+            .unwrap();
+        self.remove_known_block(to_remove_height, to_remove_hash);
     }
 
     /// Removes a block from the list of blocks the source is aware of.
@@ -276,15 +359,22 @@ impl<'a, TSrc> SourceMutAccess<'a, TSrc> {
             .known_blocks2
             .remove(&(height, hash, self.source_id));
         debug_assert_eq!(_was_in1, _was_in2);
+        if _was_in1 {
+            self.parent
+                .sources
+                .get_mut(&self.source_id)
+                .unwrap()
+                .num_known_blocks -= 1;
+        }
     }
 
     /// Sets the best block of this source.
     pub fn set_best_block(&mut self, height: u64, hash: [u8; 32]) {
-        self.add_known_block(height, hash);
-
         let source = self.parent.sources.get_mut(&self.source_id).unwrap();
         source.best_block_number = height;
         source.best_block_hash = hash;
+
+        self.add_known_block(height, hash);
     }
 
     /// Returns true if [`SourceMutAccess::add_known_block`] or [`SourceMutAccess::set_best_block`]
@@ -361,7 +451,7 @@ impl<'a, TSrc: fmt::Debug> fmt::Debug for SourceMutAccess<'a, TSrc> {
 mod tests {
     #[test]
     fn basic_works() {
-        let mut sources = super::AllForksSources::new(256, 10);
+        let mut sources = super::AllForksSources::new(256, 10, 1024);
         assert!(sources.is_empty());
         assert_eq!(sources.num_blocks(), 0);
 
@@ -406,4 +496,82 @@ mod tests {
         assert!(sources.is_empty());
         assert_eq!(sources.num_sources(), 0);
     }
+
+    #[test]
+    fn knows_block_sources() {
+        let mut sources = super::AllForksSources::new(256, 10, 1024);
+
+        let source1 = sources.add_source((), 12, [1; 32]).id();
+        let source2 = sources.add_source((), 12, [1; 32]).id();
+        let source3 = sources.add_source((), 13, [2; 32]).id();
+        sources
+            .source_mut(source3)
+            .unwrap()
+            .add_known_block(12, [1; 32]);
+
+        assert_eq!(
+            sources
+                .knows_block_sources(12, &[1; 32])
+                .collect::<Vec<_>>(),
+            vec![source1, source2, source3]
+        );
+        assert_eq!(
+            sources
+                .knows_block_sources(13, &[2; 32])
+                .collect::<Vec<_>>(),
+            vec![source3]
+        );
+        assert_eq!(sources.knows_block_sources(5, &[1; 32]).count(), 0);
+
+        let () = sources.source_mut(source2).unwrap().remove();
+        assert_eq!(
+            sources
+                .knows_block_sources(12, &[1; 32])
+                .collect::<Vec<_>>(),
+            vec![source1, source3]
+        );
+    }
+
+    #[test]
+    fn max_known_blocks_per_source() {
+        let mut sources = super::AllForksSources::new(256, 10, 2);
+
+        let source1 = sources.add_source((), 20, [20; 32]).id();
+        sources
+            .source_mut(source1)
+            .unwrap()
+            .add_known_block(11, [11; 32]);
+        sources
+            .source_mut(source1)
+            .unwrap()
+            .add_known_block(12, [12; 32]);
+        assert_eq!(sources.num_blocks(), 3);
+
+        // Adding a third block evicts the highest non-best block.
+        sources
+            .source_mut(source1)
+            .unwrap()
+            .add_known_block(13, [13; 32]);
+        assert_eq!(sources.num_blocks(), 3);
+        let mut source = sources.source_mut(source1).unwrap();
+        assert!(source.knows_block(20, &[20; 32]));
+        assert!(source.knows_block(11, &[11; 32]));
+        assert!(source.knows_block(12, &[12; 32]));
+        assert!(!source.knows_block(13, &[13; 32]));
+
+        // The new best block is always kept.
+        source.set_best_block(25, [25; 32]);
+        assert!(source.knows_block(25, &[25; 32]));
+        assert!(!source.knows_block(20, &[20; 32]));
+
+        // Blocks removed through finality no longer count towards the limit.
+        sources.set_finalized_block_height(12);
+        let mut source = sources.source_mut(source1).unwrap();
+        source.add_known_block(13, [13; 32]);
+        source.add_known_block(14, [14; 32]);
+        assert!(source.knows_block(13, &[13; 32]));
+        assert!(source.knows_block(14, &[14; 32]));
+        assert!(source.knows_block(25, &[25; 32]));
+        assert_eq!(sources.num_blocks(), 3);
+    }
 }
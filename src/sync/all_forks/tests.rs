@@ -0,0 +1,499 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{
+    AllForksSync, AncestrySearchResponseOutcome, BlockAnnounceOutcome, BlockBodyResponseOutcome,
+    BlockBodyVerify, Config, ConfigFull, HeaderVerifyOutcome, Request, RequestId,
+    RequestSuccessBlock, SourceId,
+};
+use crate::{
+    chain::chain_information,
+    executor::{host, vm, DEFAULT_HEAP_PAGES},
+    header,
+};
+
+use alloc::collections::BTreeMap;
+use core::{iter, num::NonZeroU32, time::Duration};
+
+fn genesis() -> header::Header {
+    header::Header {
+        parent_hash: [0; 32],
+        number: 0,
+        state_root: [0; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::empty().into(),
+    }
+}
+
+/// Builds a child of `parent` with the given digest items.
+fn child(parent: &header::Header, digest_items: &[header::DigestItem]) -> header::Header {
+    header::Header {
+        parent_hash: parent.hash(),
+        number: parent.number + 1,
+        state_root: [0; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::from_slice(digest_items).unwrap().into(),
+    }
+}
+
+fn new_sync(
+    max_requests_per_block: u32,
+    max_requests_per_source: u32,
+    full: Option<ConfigFull>,
+) -> AllForksSync<u32, ()> {
+    AllForksSync::new(Config {
+        chain_information: chain_information::ChainInformation {
+            finalized_block_header: genesis(),
+            consensus: chain_information::ChainInformationConsensus::AllAuthorized,
+            finality: chain_information::ChainInformationFinality::Outsourced,
+        },
+        sources_capacity: 16,
+        blocks_capacity: 16,
+        max_disjoint_headers: 1024,
+        max_requests_per_block: NonZeroU32::new(max_requests_per_block).unwrap(),
+        max_requests_per_source: NonZeroU32::new(max_requests_per_source).unwrap(),
+        max_known_blocks_per_source: 1024,
+        bad_blocks: Vec::new(),
+        fork_blocks: Vec::new(),
+        full,
+    })
+}
+
+/// Requests returned by the [`AllForksSync`].
+type Requests = Vec<(SourceId, RequestId, Request)>;
+
+/// Turns a list of ancestry search requests into a list of sources and requested blocks.
+fn ancestry_searches(requests: Requests) -> Vec<(SourceId, [u8; 32])> {
+    requests
+        .into_iter()
+        .map(|(source_id, _, request)| match request {
+            Request::AncestrySearch {
+                first_block_hash, ..
+            } => (source_id, first_block_hash),
+            _ => panic!(),
+        })
+        .collect()
+}
+
+#[test]
+fn next_requests_scheduling() {
+    let mut sync = new_sync(2, 1, None);
+
+    // The first two sources that know about a block are both sent a request.
+    let (source1, requests) = sync.add_source(1, 10, [1; 32]);
+    let request1 = requests[0].1;
+    assert_eq!(ancestry_searches(requests), vec![(source1, [1; 32])]);
+    let (source2, requests) = sync.add_source(2, 10, [1; 32]);
+    assert_eq!(ancestry_searches(requests), vec![(source2, [1; 32])]);
+
+    // `max_requests_per_block` has been reached.
+    let (source3, requests) = sync.add_source(3, 10, [1; 32]);
+    assert!(requests.is_empty());
+
+    // A block of unknown ancestry is announced by the first source, whose parent must now be
+    // downloaded. The first source has already reached `max_requests_per_source`.
+    let announced = header::Header {
+        parent_hash: [2; 32],
+        number: 12,
+        state_root: [0; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::empty().into(),
+    };
+    let sync = match sync.block_announce(source1, announced.scale_encoding_vec(), false) {
+        BlockAnnounceOutcome::Disjoint {
+            sync,
+            next_requests,
+        } => {
+            assert!(next_requests.is_empty());
+            sync
+        }
+        _ => panic!(),
+    };
+
+    // The third source also announces this block. It is idle, and is sent a request.
+    let mut sync = match sync.block_announce(source3, announced.scale_encoding_vec(), false) {
+        BlockAnnounceOutcome::Disjoint {
+            sync,
+            next_requests,
+        } => {
+            assert_eq!(ancestry_searches(next_requests), vec![(source3, [2; 32])]);
+            sync
+        }
+        _ => panic!(),
+    };
+    assert!(sync.source_mut(source1).unwrap().knows_block(11, &[2; 32]));
+
+    // The first source fails its request. It is no longer busy and is sent a request for the
+    // parent of the announced block, but not again for the block it has failed to provide.
+    match sync.ancestry_search_response(
+        request1,
+        Err::<iter::Empty<RequestSuccessBlock<Vec<u8>, Vec<u8>>>, _>(()),
+    ) {
+        AncestrySearchResponseOutcome::Inconclusive {
+            next_requests,
+            penalized_source,
+            ..
+        } => {
+            assert_eq!(penalized_source, Some(source1));
+            assert_eq!(ancestry_searches(next_requests), vec![(source1, [2; 32])]);
+        }
+        _ => panic!(),
+    }
+}
+
+/// Announces the given header from the given source and verifies it. Returns the requests that
+/// must be started.
+fn announce_and_verify(
+    sync: AllForksSync<u32, ()>,
+    source_id: SourceId,
+    header: &header::Header,
+) -> (AllForksSync<u32, ()>, Requests) {
+    match sync.block_announce(source_id, header.scale_encoding_vec(), true) {
+        BlockAnnounceOutcome::HeaderVerify(verify) => match verify.perform(Duration::new(0, 0), ())
+        {
+            HeaderVerifyOutcome::Success {
+                sync,
+                next_requests,
+                ..
+            } => (sync, next_requests),
+            _ => panic!(),
+        },
+        _ => panic!(),
+    }
+}
+
+/// Answers a justification request with the header of the block but without justification.
+fn respond_without_justification(
+    sync: AllForksSync<u32, ()>,
+    request_id: RequestId,
+    header: &header::Header,
+) -> AllForksSync<u32, ()> {
+    let response = iter::once(RequestSuccessBlock {
+        scale_encoded_header: header.scale_encoding_vec(),
+        scale_encoded_justification: None::<Vec<u8>>,
+    });
+    match sync.ancestry_search_response(request_id, Ok(response)) {
+        AncestrySearchResponseOutcome::AllAlreadyInChain {
+            sync,
+            next_requests,
+            ..
+        } => {
+            assert!(next_requests.is_empty());
+            sync
+        }
+        _ => panic!(),
+    }
+}
+
+/// Returns the request ids and block heights of the justification requests in the list.
+fn justification_requests(requests: &[(SourceId, RequestId, Request)]) -> Vec<(RequestId, u64)> {
+    requests
+        .iter()
+        .filter_map(|(_, request_id, request)| match request {
+            Request::JustificationRequest { number, .. } => Some((*request_id, *number)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn justification_requests_scheduling() {
+    let authorities_change = [header::DigestItem::GrandpaConsensus(
+        header::GrandpaConsensusLog::ScheduledChange(header::GrandpaScheduledChange {
+            next_authorities: Vec::new(),
+            delay: 0,
+        }),
+    )];
+    let block1 = child(&genesis(), &authorities_change);
+    let block2 = child(&block1, &[]);
+    let block3 = child(&block2, &[]);
+
+    let mut sync = new_sync(1, 4, None);
+    let (source, _) = sync.add_source(1, 1, block1.hash());
+
+    // The justification of a block that changes the list of GrandPa authorities is requested
+    // as soon as the block has been verified.
+    let (sync, requests) = announce_and_verify(sync, source, &block1);
+    let requested = justification_requests(&requests);
+    assert_eq!(
+        requested.iter().map(|(_, n)| *n).collect::<Vec<_>>(),
+        vec![1]
+    );
+
+    // The source doesn't have the justification yet. It isn't requested again until
+    // `retry_justification_requests` is called.
+    let sync = respond_without_justification(sync, requested[0].0, &block1);
+    let (mut sync, requests) = announce_and_verify(sync, source, &block2);
+    assert!(justification_requests(&requests).is_empty());
+
+    // Retrying also requests the justification of the best block.
+    let requested = justification_requests(&sync.retry_justification_requests());
+    assert_eq!(
+        requested.iter().map(|(_, n)| *n).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    let sync = respond_without_justification(sync, requested[0].0, &block1);
+    let sync = respond_without_justification(sync, requested[1].0, &block2);
+
+    // Once the best block changes, the justification of the previous best block is no longer
+    // requested, contrary to the one of the block that changes the list of authorities.
+    let (mut sync, _) = announce_and_verify(sync, source, &block3);
+    let requested = justification_requests(&sync.retry_justification_requests());
+    assert_eq!(
+        requested.iter().map(|(_, n)| *n).collect::<Vec<_>>(),
+        vec![1, 3]
+    );
+}
+
+/// Minimal runtime whose `Core_execute_block` function successively calls
+/// `ext_storage_get_version_1("a")`, `ext_storage_next_key_version_1("a")`,
+/// `ext_storage_clear_prefix_version_1("b")` and `ext_storage_set_version_1("a", "v")`, then
+/// returns an empty output.
+///
+/// Assembled from the following text format:
+///
+/// ```text
+/// (module
+///   (import "env" "ext_storage_get_version_1" (func $get (param i64) (result i64)))
+///   (import "env" "ext_storage_next_key_version_1" (func $next_key (param i64) (result i64)))
+///   (import "env" "ext_storage_clear_prefix_version_1" (func $clear_prefix (param i64)))
+///   (import "env" "ext_storage_set_version_1" (func $set (param i64 i64)))
+///   (memory (export "memory") 1)
+///   (global (export "__heap_base") i32 (i32.const 1024))
+///   (data (i32.const 0) "abv")
+///   (func (export "Core_execute_block") (param i32 i32) (result i64)
+///     (drop (call $get (i64.const 0x1_0000_0000)))
+///     (drop (call $next_key (i64.const 0x1_0000_0000)))
+///     (call $clear_prefix (i64.const 0x1_0000_0001))
+///     (call $set (i64.const 0x1_0000_0000) (i64.const 0x1_0000_0002))
+///     (i64.const 0)))
+/// ```
+const TEST_RUNTIME: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x15, 0x04, 0x60, 0x01, 0x7e, 0x01, 0x7e,
+    0x60, 0x01, 0x7e, 0x00, 0x60, 0x02, 0x7e, 0x7e, 0x00, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7e, 0x02,
+    0x8f, 0x01, 0x04, 0x03, 0x65, 0x6e, 0x76, 0x19, 0x65, 0x78, 0x74, 0x5f, 0x73, 0x74, 0x6f, 0x72,
+    0x61, 0x67, 0x65, 0x5f, 0x67, 0x65, 0x74, 0x5f, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x5f,
+    0x31, 0x00, 0x00, 0x03, 0x65, 0x6e, 0x76, 0x1e, 0x65, 0x78, 0x74, 0x5f, 0x73, 0x74, 0x6f, 0x72,
+    0x61, 0x67, 0x65, 0x5f, 0x6e, 0x65, 0x78, 0x74, 0x5f, 0x6b, 0x65, 0x79, 0x5f, 0x76, 0x65, 0x72,
+    0x73, 0x69, 0x6f, 0x6e, 0x5f, 0x31, 0x00, 0x00, 0x03, 0x65, 0x6e, 0x76, 0x22, 0x65, 0x78, 0x74,
+    0x5f, 0x73, 0x74, 0x6f, 0x72, 0x61, 0x67, 0x65, 0x5f, 0x63, 0x6c, 0x65, 0x61, 0x72, 0x5f, 0x70,
+    0x72, 0x65, 0x66, 0x69, 0x78, 0x5f, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x5f, 0x31, 0x00,
+    0x01, 0x03, 0x65, 0x6e, 0x76, 0x19, 0x65, 0x78, 0x74, 0x5f, 0x73, 0x74, 0x6f, 0x72, 0x61, 0x67,
+    0x65, 0x5f, 0x73, 0x65, 0x74, 0x5f, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x5f, 0x31, 0x00,
+    0x02, 0x03, 0x02, 0x01, 0x03, 0x05, 0x03, 0x01, 0x00, 0x01, 0x06, 0x07, 0x01, 0x7f, 0x00, 0x41,
+    0x80, 0x08, 0x0b, 0x07, 0x2d, 0x03, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x0b,
+    0x5f, 0x5f, 0x68, 0x65, 0x61, 0x70, 0x5f, 0x62, 0x61, 0x73, 0x65, 0x03, 0x00, 0x12, 0x43, 0x6f,
+    0x72, 0x65, 0x5f, 0x65, 0x78, 0x65, 0x63, 0x75, 0x74, 0x65, 0x5f, 0x62, 0x6c, 0x6f, 0x63, 0x6b,
+    0x00, 0x04, 0x0a, 0x2e, 0x01, 0x2c, 0x00, 0x42, 0x80, 0x80, 0x80, 0x80, 0x10, 0x10, 0x00, 0x1a,
+    0x42, 0x80, 0x80, 0x80, 0x80, 0x10, 0x10, 0x01, 0x1a, 0x42, 0x81, 0x80, 0x80, 0x80, 0x10, 0x10,
+    0x02, 0x42, 0x80, 0x80, 0x80, 0x80, 0x10, 0x42, 0x82, 0x80, 0x80, 0x80, 0x10, 0x10, 0x03, 0x42,
+    0x00, 0x0b, 0x0b, 0x09, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x03, 0x61, 0x62, 0x76,
+];
+
+fn new_full_sync() -> AllForksSync<u32, ()> {
+    let finalized_runtime =
+        host::HostVmPrototype::new(TEST_RUNTIME, DEFAULT_HEAP_PAGES, vm::ExecHint::Oneshot)
+            .unwrap();
+    new_sync(1, 4, Some(ConfigFull { finalized_runtime }))
+}
+
+/// Storage of the finalized block used by the full mode tests.
+fn finalized_storage() -> BTreeMap<Vec<u8>, Vec<u8>> {
+    [
+        (&b"a"[..], &b"1"[..]),
+        (b"b", b"2"),
+        (b"ba", b"3"),
+        (b"c", b"4"),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_vec(), v.to_vec()))
+    .collect()
+}
+
+/// Builds a child of `parent` with an empty body.
+fn child_with_empty_body(parent: &header::Header) -> header::Header {
+    let mut header = child(parent, &[]);
+    header.extrinsics_root = super::extrinsics_trie_root(&[]);
+    header
+}
+
+/// Announces the given header, whose parent has already been verified, and returns the
+/// identifier of the request for its body.
+fn announce_and_request_body(
+    sync: AllForksSync<u32, ()>,
+    source_id: SourceId,
+    header: &header::Header,
+) -> (AllForksSync<u32, ()>, RequestId) {
+    match sync.block_announce(source_id, header.scale_encoding_vec(), true) {
+        BlockAnnounceOutcome::BlockBodyDownloadStart {
+            sync,
+            next_requests,
+        } => match &next_requests[..] {
+            [(s, request_id, Request::BodyRequest { hash, .. })]
+                if *s == source_id && *hash == header.hash() =>
+            {
+                (sync, *request_id)
+            }
+            _ => panic!(),
+        },
+        _ => panic!(),
+    }
+}
+
+/// Query of the storage of the finalized block performed during a body verification.
+#[derive(Debug, PartialEq, Eq)]
+enum StorageQuery {
+    Get(Vec<u8>),
+    PrefixKeys(Vec<u8>),
+    NextKey(Vec<u8>),
+}
+
+/// Changes to the storage performed by a block.
+type StorageChanges = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Drives the given body verification to its end, answering storage queries using
+/// `finalized_storage`. Returns the storage changes of the block and the queries that have been
+/// performed.
+fn verify_body(
+    mut verify: BlockBodyVerify<u32, ()>,
+    finalized_storage: &BTreeMap<Vec<u8>, Vec<u8>>,
+) -> (AllForksSync<u32, ()>, StorageChanges, Vec<StorageQuery>) {
+    let mut queries = Vec::new();
+
+    loop {
+        verify = match verify {
+            BlockBodyVerify::Success {
+                sync,
+                storage_top_trie_changes,
+                ..
+            } => {
+                let changes = storage_top_trie_changes.into_iter().collect();
+                return (sync, changes, queries);
+            }
+            BlockBodyVerify::Error { error, .. } => panic!("{}", error),
+            BlockBodyVerify::FinalizedStorageGet(req) => {
+                let key = req.key_as_vec();
+                let value = finalized_storage.get(&key).map(|v| &v[..]);
+                queries.push(StorageQuery::Get(key));
+                req.inject_value(value)
+            }
+            BlockBodyVerify::FinalizedStoragePrefixKeys(req) => {
+                let prefix = req.prefix().as_ref().to_vec();
+                let keys = finalized_storage
+                    .keys()
+                    .filter(|k| k.starts_with(&prefix))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                queries.push(StorageQuery::PrefixKeys(prefix));
+                req.inject_keys(keys.iter())
+            }
+            BlockBodyVerify::FinalizedStorageNextKey(req) => {
+                let key = req.key().as_ref().to_vec();
+                let next_key = finalized_storage
+                    .range(key.clone()..)
+                    .map(|(k, _)| k.clone())
+                    .find(|k| *k > key);
+                queries.push(StorageQuery::NextKey(key));
+                req.inject_key(next_key)
+            }
+        };
+    }
+}
+
+#[test]
+fn body_not_matching_header_rejected() {
+    let block1 = child_with_empty_body(&genesis());
+
+    let mut sync = new_full_sync();
+    let (source, _) = sync.add_source(1, 0, genesis().hash());
+    let (sync, request_id) = announce_and_request_body(sync, source, &block1);
+
+    // The body doesn't match the extrinsics root of the header. The source is penalized, and
+    // the body isn't requested from it again.
+    match sync.block_body_response(request_id, Ok(iter::once(&b"extrinsic"[..]))) {
+        BlockBodyResponseOutcome::Inconclusive {
+            mut sync,
+            next_requests,
+            penalized_source,
+        } => {
+            assert_eq!(penalized_source, Some(source));
+            assert!(next_requests.is_empty());
+            assert!(!sync
+                .source_mut(source)
+                .unwrap()
+                .knows_block(1, &block1.hash()));
+        }
+        BlockBodyResponseOutcome::Verify(_) => panic!(),
+    }
+}
+
+#[test]
+fn body_verification_storage_round_trips() {
+    let block1 = child_with_empty_body(&genesis());
+    let block2 = child_with_empty_body(&block1);
+    let finalized_storage = finalized_storage();
+
+    let mut sync = new_full_sync();
+    let (source, _) = sync.add_source(1, 0, genesis().hash());
+
+    // The parent of the first block is the finalized block. All the storage queries are
+    // answered by the API user.
+    let (sync, request_id) = announce_and_request_body(sync, source, &block1);
+    let verify = match sync.block_body_response(request_id, Ok(iter::empty::<Vec<u8>>())) {
+        BlockBodyResponseOutcome::Verify(verify) => verify.perform(Duration::new(0, 0), ()),
+        BlockBodyResponseOutcome::Inconclusive { .. } => panic!(),
+    };
+    let (sync, changes, queries) = verify_body(verify, &finalized_storage);
+    assert_eq!(
+        queries,
+        vec![
+            StorageQuery::Get(b"a".to_vec()),
+            StorageQuery::NextKey(b"a".to_vec()),
+            StorageQuery::PrefixKeys(b"b".to_vec()),
+        ]
+    );
+    let expected_changes = [(&b"a"[..], Some(&b"v"[..])), (b"b", None), (b"ba", None)]
+        .iter()
+        .map(|(k, v)| (k.to_vec(), v.map(|v| v.to_vec())))
+        .collect::<StorageChanges>();
+    assert_eq!(changes, expected_changes);
+
+    // The parent of the second block is the first block. Its storage is the one of the
+    // finalized block modified by the changes of the first block. The value of `a` is known
+    // without querying the API user, and the keys erased by the first block are skipped.
+    let (sync, request_id) = announce_and_request_body(sync, source, &block2);
+    let verify = match sync.block_body_response(request_id, Ok(iter::empty::<Vec<u8>>())) {
+        BlockBodyResponseOutcome::Verify(verify) => verify.perform(Duration::new(0, 0), ()),
+        BlockBodyResponseOutcome::Inconclusive { .. } => panic!(),
+    };
+    let (_, changes, queries) = verify_body(verify, &finalized_storage);
+    assert_eq!(
+        queries,
+        vec![
+            StorageQuery::NextKey(b"a".to_vec()),
+            StorageQuery::NextKey(b"b".to_vec()),
+            StorageQuery::NextKey(b"ba".to_vec()),
+            StorageQuery::PrefixKeys(b"b".to_vec()),
+        ]
+    );
+    assert_eq!(
+        changes,
+        iter::once((b"a".to_vec(), Some(b"v".to_vec()))).collect()
+    );
+}
@@ -47,14 +47,14 @@
 
 use crate::{
     chain::{blocks_tree, chain_information},
-    executor::host,
+    executor::{self, host, vm},
     header,
+    sync::storage_diff,
     trie::calculate_root,
     verify,
 };
 
 use alloc::{
-    borrow::ToOwned as _,
     collections::{BTreeMap, VecDeque},
     vec::Vec,
 };
@@ -67,9 +67,11 @@ use core::{
     num::{NonZeroU32, NonZeroU64},
     time::Duration,
 };
-use hashbrown::{HashMap, HashSet};
+use hashbrown::HashMap;
 use rand::{seq::IteratorRandom as _, SeedableRng as _};
 
+mod tests;
+
 /// Configuration for the [`OptimisticSync`].
 #[derive(Debug)]
 pub struct Config {
@@ -109,6 +111,17 @@ pub struct Config {
     /// situations where determinism/reproducibility is desired.
     pub source_selection_randomness_seed: u64,
 
+    /// List of hashes of blocks that must never be part of the chain.
+    ///
+    /// > **Note**: This list is typically found in the chain specification.
+    pub bad_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes. At each of these heights, only the block with the
+    /// corresponding hash can be part of the chain.
+    ///
+    /// > **Note**: This list is typically found in the chain specification.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
     /// If `Some`, the block bodies and storage are also synchronized. Contains the extra
     /// configuration.
     pub full: Option<ConfigFull>,
@@ -153,7 +166,7 @@ struct OptimisticSyncInner<TRq, TSrc, TBl> {
     /// Changes in the storage of the best block compared to the finalized block.
     /// The `BTreeMap`'s keys are storage keys, and its values are new values or `None` if the
     /// value has been erased from the storage.
-    best_to_finalized_storage_diff: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    best_to_finalized_storage_diff: storage_diff::StorageDiff,
 
     /// Compiled runtime code of the best block. `None` if it is the same as
     /// [`OptimisticSyncInner::finalized_runtime`].
@@ -252,6 +265,9 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
             chain_information: config.chain_information,
             blocks_capacity: usize::try_from(config.blocks_request_granularity.get())
                 .unwrap_or(usize::max_value()),
+            bad_blocks: config.bad_blocks,
+            fork_blocks: config.fork_blocks,
+            fork_choice: None,
         };
 
         let chain = blocks_tree::NonFinalizedTree::new(blocks_tree_config.clone());
@@ -327,10 +343,127 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
         self.chain.best_block_hash()
     }
 
+    /// Returns `true` if the block with the given hash is the finalized block or one of the
+    /// non-finalized blocks that have been verified, in other words the current best block or
+    /// one of its ancestors.
+    pub fn has_verified_block(&mut self, block_hash: &[u8; 32]) -> bool {
+        *block_hash == self.chain.finalized_block_hash()
+            || self.chain.non_finalized_block_by_hash(block_hash).is_some()
+    }
+
+    /// Returns the changes that the best block and its non-finalized ancestors perform on the
+    /// storage of the finalized block. Values are `None` if the key has been erased.
+    ///
+    /// Always empty if [`Config::full`] is `None`.
+    pub fn best_block_storage_diff(&self) -> impl Iterator<Item = (&[u8], Option<&[u8]>)> {
+        self.inner
+            .best_to_finalized_storage_diff
+            .iter()
+            .map(|(key, value)| (&key[..], value.as_deref()))
+    }
+
+    /// Sets the latest finalized block of the chain.
+    ///
+    /// This is meant to be used for chains whose finality is determined by an external mechanism,
+    /// such as parachains whose finalized block is obtained by looking at the relay chain. Blocks
+    /// of chains that use GrandPa are finalized automatically when a justification is received.
+    ///
+    /// The block must be one of the non-finalized blocks that have been verified, in other words
+    /// the current best block or one of its ancestors.
+    ///
+    /// On success, returns the list of blocks that have been finalized, in increasing block
+    /// number. The block whose hash is `block_hash` is the last element of this list.
+    pub fn set_finalized_block(
+        &mut self,
+        block_hash: &[u8; 32],
+    ) -> Result<Vec<Block<TBl>>, blocks_tree::SetFinalizedError> {
+        // Since `set_finalized_block` returns the blocks in decreasing block number, we have to
+        // revert the list in order to get them in increasing block number instead.
+        let finalized_blocks = self
+            .chain
+            .set_finalized_block(block_hash)?
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect::<Vec<_>>();
+
+        self.inner.finalized_chain_information.chain_information =
+            self.chain.as_chain_information().into();
+
+        // The blocks that remain non-finalized always form a single chain between the finalized
+        // block and the best block. The storage diff is rebuilt from the changes of these blocks,
+        // starting from the best block.
+        self.inner.best_to_finalized_storage_diff.clear();
+        let mut non_finalized_runtime_change = false;
+        if !self.chain.is_empty() {
+            let best_block_hash = self.chain.best_block_hash();
+            let mut block = self
+                .chain
+                .non_finalized_block_by_hash(&best_block_hash)
+                .unwrap();
+            loop {
+                for (key, value) in &block.user_data_mut().storage_top_trie_changes {
+                    if key == b":code" || key == b":heappages" {
+                        non_finalized_runtime_change = true;
+                    }
+
+                    self.inner
+                        .best_to_finalized_storage_diff
+                        .entry(key.clone())
+                        .or_insert_with(|| value.clone());
+                }
+
+                block = match block.parent_block() {
+                    Ok(parent) => parent,
+                    Err(_) => break,
+                };
+            }
+        }
+
+        // In full mode, find out whether one of the newly-finalized blocks has modified the
+        // runtime, in which case `finalized_runtime` must be updated.
+        if let Some(finalized_runtime) = &self.inner.finalized_runtime {
+            let mut heap_pages = finalized_runtime.heap_pages();
+            let mut new_code = None;
+            for block in &finalized_blocks {
+                // Blocks that modify `:heappages` without modifying `:code`, or that erase
+                // `:code`, fail verification and thus can't be found here.
+                if let Some(Some(code)) = block.storage_top_trie_changes.get(&b":code"[..]) {
+                    if let Some(value) = block.storage_top_trie_changes.get(&b":heappages"[..]) {
+                        // The value has already been successfully parsed during the verification.
+                        heap_pages =
+                            executor::storage_heap_pages_to_value(value.as_deref()).unwrap();
+                    }
+                    new_code = Some(code);
+                }
+            }
+
+            if let Some(new_code) = new_code {
+                self.inner.finalized_runtime = Some(if !non_finalized_runtime_change {
+                    // `best_runtime` is the runtime of the new finalized block.
+                    self.inner.best_runtime.take().unwrap()
+                } else {
+                    // The runtime of the new finalized block is neither the one of the previous
+                    // finalized block nor the one of the best block, and must be compiled again.
+                    // The code has already been successfully compiled during the verification.
+                    host::HostVmPrototype::new(
+                        new_code,
+                        heap_pages,
+                        vm::ExecHint::CompileAheadOfTime,
+                    )
+                    .unwrap()
+                });
+            }
+        }
+
+        Ok(finalized_blocks)
+    }
+
     /// Disassembles the state machine into its raw components.
     pub fn disassemble(self) -> Disassemble<TRq, TSrc> {
         Disassemble {
             chain_information: self.inner.finalized_chain_information.chain_information,
+            finalized_runtime: self.inner.finalized_runtime,
             sources: self
                 .inner
                 .sources
@@ -644,18 +777,20 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
                     inner: self.inner,
                     block_body: block.scale_encoded_extrinsics,
                     block_user_data: Some(block.user_data),
+                    equivocation: None,
                     source_id,
                 },
             )
         } else {
             // TODO: return an object instead of verifying immediately
-            let error = match self
+            let outcome = match self
                 .chain
                 .verify_header(block.scale_encoded_header, now_from_unix_epoch)
             {
                 Ok(blocks_tree::HeaderVerifySuccess::Duplicate) => todo!(),
                 Ok(blocks_tree::HeaderVerifySuccess::Insert {
                     insert,
+                    equivocation,
                     ..  // TODO: check is_new_best?
                 }) => {
                     let header = insert.header().into();
@@ -668,7 +803,7 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
                         offchain_storage_changes: Default::default(),
                         user_data: block.user_data,
                     });
-                    None
+                    Ok(equivocation)
                 }
                 Err(err) => {
                     if let Some(src) = self.inner.sources.get_mut(&source_id) {
@@ -678,19 +813,21 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
                     self.inner.best_to_finalized_storage_diff = Default::default();
                     self.inner.best_runtime = None;
                     self.inner.top_trie_root_calculation_cache = None;
-                    Some(err)
+                    Err(err)
                 }
             };
 
-            if let Some(error) = error {
-                let previous_best_height = self.chain.best_block_header().number;
-                ProcessOne::Reset {
-                    sync: self,
-                    previous_best_height,
-                    reason: ResetCause::HeaderError(error),
+            match outcome {
+                Err(error) => {
+                    let previous_best_height = self.chain.best_block_header().number;
+                    ProcessOne::Reset {
+                        sync: self,
+                        previous_best_height,
+                        source_id,
+                        reason: ResetCause::HeaderError(error),
+                    }
                 }
-            } else {
-                ProcessOne::from(
+                Ok(equivocation) => ProcessOne::from(
                     Inner::JustificationVerif(self.chain),
                     ProcessOneShared {
                         pending_encoded_justification: block.scale_encoded_justification,
@@ -698,9 +835,10 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
                         inner: self.inner,
                         block_body: Vec::new(),
                         block_user_data: None,
+                        equivocation,
                         source_id,
                     },
-                )
+                ),
             }
         }
     }
@@ -739,6 +877,10 @@ pub enum ProcessOne<TRq, TSrc, TBl> {
         /// Height of the best block before the reset.
         previous_best_height: u64,
 
+        /// Source the block or justification that failed verification has been obtained from.
+        /// Might refer to a source that has since been removed.
+        source_id: SourceId,
+
         /// Problem that happened and caused the reset.
         reason: ResetCause,
     },
@@ -754,6 +896,10 @@ pub enum ProcessOne<TRq, TSrc, TBl> {
 
         new_best_number: u64,
         new_best_hash: [u8; 32],
+
+        /// If `Some`, the author of the newly-verified block has already produced a different
+        /// block in the same slot. The block has nonetheless been inserted in the chain.
+        equivocation: Option<blocks_tree::EquivocationProof>,
     },
 
     /// Processing of the block is over. The block has been finalized.
@@ -767,6 +913,10 @@ pub enum ProcessOne<TRq, TSrc, TBl> {
 
         /// Blocks that have been finalized. Includes the block that has just been verified.
         finalized_blocks: Vec<Block<TBl>>,
+
+        /// If `Some`, the author of the newly-verified block has already produced a different
+        /// block in the same slot. The block has nonetheless been inserted in the chain.
+        equivocation: Option<blocks_tree::EquivocationProof>,
     },
 
     /// Loading a storage value of the finalized block is required in order to continue.
@@ -796,6 +946,9 @@ struct ProcessOneShared<TRq, TSrc, TBl> {
     block_body: Vec<Vec<u8>>,
     /// User data of the block being verified.
     block_user_data: Option<TBl>,
+    /// Equivocation that the block being verified is part of, if any. Filled once the block has
+    /// been inserted in the chain.
+    equivocation: Option<blocks_tree::EquivocationProof>,
     /// Source the block has been downloaded from. Might be obsolete.
     source_id: SourceId,
 }
@@ -872,6 +1025,8 @@ impl<TRq, TSrc, TBl> ProcessOne<TRq, TSrc, TBl> {
                             .insert(key.clone(), value.clone());
                     }
 
+                    shared.equivocation = insert.equivocation();
+
                     let chain = {
                         let header = insert.header().into();
                         insert.insert(Block {
@@ -916,6 +1071,7 @@ impl<TRq, TSrc, TBl> ProcessOne<TRq, TSrc, TBl> {
                                             ..shared.inner
                                         },
                                     },
+                                    source_id: shared.source_id,
                                     reason: ResetCause::JustificationError(error),
                                 };
                             }
@@ -958,6 +1114,7 @@ impl<TRq, TSrc, TBl> ProcessOne<TRq, TSrc, TBl> {
                                 inner: shared.inner,
                             },
                             finalized_blocks,
+                            equivocation: shared.equivocation,
                         };
                     } else {
                         let new_best_hash = chain.best_block_hash();
@@ -969,6 +1126,7 @@ impl<TRq, TSrc, TBl> ProcessOne<TRq, TSrc, TBl> {
                             },
                             new_best_hash,
                             new_best_number,
+                            equivocation: shared.equivocation,
                         };
                     }
                 }
@@ -982,14 +1140,11 @@ impl<TRq, TSrc, TBl> ProcessOne<TRq, TSrc, TBl> {
                     // As such, the requested value is either found in one of this diff, in which
                     // case it can be returned immediately to continue the verification, or in
                     // the finalized block, in which case the user needs to be queried.
-                    if let Some(value) = shared
-                        .inner
-                        .best_to_finalized_storage_diff
-                        .get(&req.key_as_vec())
-                    {
-                        inner = Inner::Step2(
-                            req.inject_value(value.as_ref().map(|v| iter::once(&v[..]))),
-                        );
+                    if let Some(value) = storage_diff::get(
+                        &shared.inner.best_to_finalized_storage_diff,
+                        &req.key_as_vec(),
+                    ) {
+                        inner = Inner::Step2(req.inject_value(value.map(iter::once)));
                         continue 'verif_steps;
                     }
 
@@ -1026,7 +1181,7 @@ impl<TRq, TSrc, TBl> ProcessOne<TRq, TSrc, TBl> {
                     continue 'verif_steps;
                 }
 
-                // The three variants below correspond to problems during the verification.
+                // The four variants below correspond to problems during the verification.
                 //
                 // When that happens:
                 //
@@ -1052,9 +1207,32 @@ impl<TRq, TSrc, TBl> ProcessOne<TRq, TSrc, TBl> {
                                 ..shared.inner
                             },
                         },
+                        source_id: shared.source_id,
                         reason: ResetCause::InvalidHeader(error),
                     };
                 }
+                Inner::Step1(blocks_tree::BodyVerifyStep1::BadBlock(old_chain)) => {
+                    if let Some(source) = shared.inner.sources.get_mut(&shared.source_id) {
+                        source.banned = true;
+                    }
+                    break ProcessOne::Reset {
+                        previous_best_height: old_chain.best_block_header().number,
+                        sync: OptimisticSync {
+                            chain: blocks_tree::NonFinalizedTree::new(
+                                shared.inner.finalized_chain_information.clone(),
+                            ),
+                            inner: OptimisticSyncInner {
+                                best_to_finalized_storage_diff: Default::default(),
+                                best_runtime: None,
+                                top_trie_root_calculation_cache: None,
+                                cancelling_requests: true,
+                                ..shared.inner
+                            },
+                        },
+                        source_id: shared.source_id,
+                        reason: ResetCause::HeaderError(blocks_tree::HeaderVerifyError::BadBlock),
+                    };
+                }
                 Inner::Step1(blocks_tree::BodyVerifyStep1::Duplicate(old_chain))
                 | Inner::Step1(blocks_tree::BodyVerifyStep1::BadParent {
                     chain: old_chain, ..
@@ -1076,6 +1254,7 @@ impl<TRq, TSrc, TBl> ProcessOne<TRq, TSrc, TBl> {
                                 ..shared.inner
                             },
                         },
+                        source_id: shared.source_id,
                         reason: ResetCause::NonCanonical,
                     };
                 }
@@ -1104,6 +1283,7 @@ impl<TRq, TSrc, TBl> ProcessOne<TRq, TSrc, TBl> {
                                 ..shared.inner
                             },
                         },
+                        source_id: shared.source_id,
                         reason: ResetCause::HeaderBodyError(error),
                     };
                 }
@@ -1157,26 +1337,11 @@ impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
         self,
         keys: impl Iterator<Item = impl AsRef<[u8]>>,
     ) -> ProcessOne<TRq, TSrc, TBl> {
-        let mut keys = keys
-            .map(|k| k.as_ref().to_owned())
-            .collect::<HashSet<_, fnv::FnvBuildHasher>>();
-
-        {
-            let prefix = self.inner.prefix();
-            for (k, v) in self
-                .shared
-                .inner
-                .best_to_finalized_storage_diff
-                .range(prefix.as_ref().to_owned()..)
-                .take_while(|(k, _)| k.starts_with(prefix.as_ref()))
-            {
-                if v.is_some() {
-                    keys.insert(k.clone());
-                } else {
-                    keys.remove(k);
-                }
-            }
-        }
+        let keys = storage_diff::prefix_keys(
+            &self.shared.inner.best_to_finalized_storage_diff,
+            self.inner.prefix().as_ref(),
+            keys,
+        );
 
         let inner = self.inner.inject_keys(keys.iter());
         ProcessOne::from(Inner::Step2(inner), self.shared)
@@ -1224,56 +1389,20 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
             inner_key.as_ref()
         };
 
-        if let Some(key) = key {
-            assert!(key > requested_key);
-        }
-
-        let in_diff = self
-            .shared
-            .inner
-            .best_to_finalized_storage_diff
-            .range(requested_key.to_vec()..) // TODO: don't use to_vec()
-            .map(|(k, v)| (k, v.is_some()))
-            .find(|(k, _)| &***k > requested_key);
-
-        let outcome = match (key, in_diff) {
-            (Some(a), Some((b, true))) if a <= &b[..] => Some(a),
-            (Some(a), Some((b, false))) if a < &b[..] => Some(a),
-            (Some(a), Some((b, false))) => {
-                debug_assert!(a >= &b[..]);
-                debug_assert_ne!(&b[..], requested_key);
-
-                // The next key according to the finalized block storage has been erased since
-                // then. It is necessary to ask the user again, this time for the key after the
-                // one that has been erased.
-                // This `clone()` is necessary, as `b` borrows from
-                // `self.shared.best_to_finalized_storage_diff`.
-                let key_overwrite = Some(b.clone());
+        let outcome = match storage_diff::next_key(
+            &self.shared.inner.best_to_finalized_storage_diff,
+            requested_key,
+            key,
+        ) {
+            storage_diff::NextKey::Found(outcome) => outcome,
+            storage_diff::NextKey::AskAgain(key_overwrite) => {
                 drop(inner_key); // Solves borrowing errors.
                 return ProcessOne::FinalizedStorageNextKey(StorageNextKey {
                     inner: self.inner,
                     shared: self.shared,
-                    key_overwrite,
+                    key_overwrite: Some(key_overwrite),
                 });
             }
-            (Some(a), Some((b, true))) => {
-                debug_assert!(a >= &b[..]);
-                Some(&b[..])
-            }
-
-            (Some(a), None) => Some(a),
-            (None, Some((b, true))) => Some(&b[..]),
-            (None, Some((b, false))) => {
-                debug_assert!(&b[..] > requested_key);
-                self.shared
-                    .inner
-                    .best_to_finalized_storage_diff
-                    .range(b.clone()..) // TODO: don't clone?
-                    .filter(|(_, value)| value.is_some())
-                    .map(|(k, _)| &k[..])
-                    .next()
-            }
-            (None, None) => None,
         };
 
         drop(inner_key); // Solves borrowing errors.
@@ -1440,6 +1569,9 @@ pub struct Disassemble<TRq, TSrc> {
     /// Information about the latest finalized block and its ancestors.
     pub chain_information: chain_information::ChainInformation,
 
+    /// Compiled runtime code of the finalized block. `None` if not in full mode.
+    pub finalized_runtime: Option<host::HostVmPrototype>,
+
     /// List of sources that were within the state machine.
     pub sources: Vec<DisassembleSource<TSrc>>,
 
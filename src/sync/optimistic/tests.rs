@@ -0,0 +1,163 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{Config, OptimisticSync, ProcessOne, RequestAction, RequestSuccessBlock, SourceId};
+use crate::{
+    chain::{blocks_tree, chain_information},
+    header,
+};
+
+use core::{num::NonZeroU32, time::Duration};
+
+fn genesis() -> header::Header {
+    header::Header {
+        parent_hash: [0; 32],
+        number: 0,
+        state_root: [0; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::empty().into(),
+    }
+}
+
+/// Builds a chain of `num` blocks on top of [`genesis`].
+fn chain(num: usize) -> Vec<header::Header> {
+    let mut chain = Vec::<header::Header>::with_capacity(num);
+    for _ in 0..num {
+        let parent = chain.last().cloned().unwrap_or_else(genesis);
+        chain.push(header::Header {
+            parent_hash: parent.hash(),
+            number: parent.number + 1,
+            state_root: [0; 32],
+            extrinsics_root: [0; 32],
+            digest: header::DigestRef::empty().into(),
+        });
+    }
+    chain
+}
+
+/// Builds a light [`OptimisticSync`] whose finalized block is [`genesis`], then downloads and
+/// verifies the given blocks from a single source.
+fn sync_with_blocks(blocks: &[header::Header]) -> (OptimisticSync<(), u32, ()>, SourceId) {
+    let mut sync = OptimisticSync::new(Config {
+        chain_information: chain_information::ChainInformation {
+            finalized_block_header: genesis(),
+            consensus: chain_information::ChainInformationConsensus::AllAuthorized,
+            finality: chain_information::ChainInformationFinality::Outsourced,
+        },
+        sources_capacity: 16,
+        blocks_capacity: 16,
+        blocks_request_granularity: NonZeroU32::new(16).unwrap(),
+        download_ahead_blocks: 16,
+        source_selection_randomness_seed: 0,
+        bad_blocks: Vec::new(),
+        fork_blocks: Vec::new(),
+        full: None,
+    });
+
+    let source_id = sync.add_source(1, blocks.len() as u64);
+    let request_id = match sync.next_request_action() {
+        Some(RequestAction::Start {
+            start,
+            block_height,
+            ..
+        }) => {
+            assert_eq!(block_height.get(), 1);
+            start.start(())
+        }
+        _ => panic!(),
+    };
+
+    let response = blocks.iter().map(|header| RequestSuccessBlock {
+        scale_encoded_header: header.scale_encoding_vec(),
+        scale_encoded_justification: None,
+        scale_encoded_extrinsics: Vec::new(),
+        user_data: (),
+    });
+    let _ = sync.finish_request(request_id, Ok(response));
+
+    let mut process = sync.process_one(Duration::new(0, 0));
+    let sync = loop {
+        process = match process {
+            ProcessOne::Idle { sync } => break sync,
+            ProcessOne::NewBest { sync, .. } => sync.process_one(Duration::new(0, 0)),
+            _ => panic!(),
+        };
+    };
+
+    assert_eq!(sync.best_block_hash(), blocks.last().unwrap().hash());
+    (sync, source_id)
+}
+
+#[test]
+fn set_finalized_block_returns_blocks_in_increasing_order() {
+    let blocks = chain(3);
+    let (mut sync, _) = sync_with_blocks(&blocks);
+
+    let finalized = sync.set_finalized_block(&blocks[1].hash()).unwrap();
+    assert_eq!(
+        finalized
+            .iter()
+            .map(|b| b.header.hash())
+            .collect::<Vec<_>>(),
+        vec![blocks[0].hash(), blocks[1].hash()]
+    );
+
+    // The best block is left untouched.
+    assert_eq!(sync.finalized_block_header().hash(), blocks[1].hash());
+    assert_eq!(
+        sync.as_chain_information().finalized_block_header.hash(),
+        blocks[1].hash()
+    );
+    assert_eq!(sync.best_block_hash(), blocks[2].hash());
+
+    // Blocks older than the new finalized block are no longer part of the chain.
+    assert!(!sync.has_verified_block(&blocks[0].hash()));
+    assert!(sync.has_verified_block(&blocks[1].hash()));
+    assert!(sync.has_verified_block(&blocks[2].hash()));
+
+    // Finalizing the best block leaves no non-finalized block.
+    let finalized = sync.set_finalized_block(&blocks[2].hash()).unwrap();
+    assert_eq!(
+        finalized
+            .iter()
+            .map(|b| b.header.hash())
+            .collect::<Vec<_>>(),
+        vec![blocks[2].hash()]
+    );
+    assert_eq!(sync.finalized_block_header().hash(), blocks[2].hash());
+    assert_eq!(sync.best_block_hash(), blocks[2].hash());
+}
+
+#[test]
+fn set_finalized_block_unknown_block() {
+    let blocks = chain(2);
+    let (mut sync, _) = sync_with_blocks(&blocks);
+    sync.set_finalized_block(&blocks[0].hash()).unwrap();
+
+    // Neither a block that is already finalized nor a block that has never been verified can be
+    // finalized. The chain is left untouched.
+    for hash in [genesis().hash(), blocks[0].hash(), [0xff; 32]].iter() {
+        assert!(matches!(
+            sync.set_finalized_block(hash),
+            Err(blocks_tree::SetFinalizedError::UnknownBlock)
+        ));
+    }
+    assert_eq!(sync.finalized_block_header().hash(), blocks[0].hash());
+    assert_eq!(sync.best_block_hash(), blocks[1].hash());
+}
@@ -0,0 +1,1191 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Downloading the entire storage of a block.
+//!
+//! Some syncing strategies, such as GrandPa warp syncing, only provide the header of a recent
+//! block but not its storage. The state machine in this module downloads from sources the entire
+//! storage of a block, both the main trie and the child tries, and verifies it against the state
+//! root found in the header of this block.
+//!
+//! # Usage
+//!
+//! Create a [`StateSync`] with [`StateSync::new`] and add sources with
+//! [`StateSync::add_source`]. Then call [`StateSync::desired_request`] in order to know which
+//! request to start next, start this request, and call [`StateSync::add_request`]. Once the
+//! request has finished, inject its response with [`StateSync::state_response`] or
+//! [`StateSync::storage_proof_response`] depending on the type of request. Only one request can
+//! be in progress at any given time.
+//!
+//! # State requests and storage proofs
+//!
+//! The storage is preferably downloaded through state requests (see
+//! [`protocol::build_state_request`]). The responses to these requests contain large batches of
+//! keys and values, but no proof. The downloaded storage can only be verified once it has been
+//! entirely downloaded. If this verification fails, all the sources that have provided parts of
+//! the storage are no longer used and the download starts again from the beginning.
+//!
+//! A source that fails to answer a state request is assumed to not support this protocol, and
+//! is no longer sent state requests. If none of the sources support state requests, the download
+//! falls back to enumerating the keys of the storage through storage proofs (see
+//! [`trie::prefix_proof`]), then downloading their values through storage proofs as well. This is
+//! considerably slower, but every response is verified as soon as it is received.
+
+use crate::{
+    network::protocol,
+    trie::{self, calculate_root, prefix_proof, proof_verify},
+};
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{convert::TryFrom as _, mem};
+
+/// Prefix of the keys of the main trie that contain the root of a child trie.
+const CHILD_STORAGE_PREFIX: &[u8] = b":child_storage:";
+
+/// Maximum number of keys to request the value of in a single storage proof request.
+const MAX_KEYS_PER_STORAGE_PROOF: usize = 64;
+
+/// Configuration for the [`StateSync`].
+#[derive(Debug)]
+pub struct Config {
+    /// Hash of the block whose storage must be downloaded.
+    pub block_hash: [u8; 32],
+
+    /// State root found in the header of the block whose storage must be downloaded.
+    pub state_root: [u8; 32],
+
+    /// Pre-allocated capacity for the number of sources.
+    pub sources_capacity: usize,
+
+    /// Maximum number of entries, main trie and child tries combined, that can be received
+    /// through state requests. The storage received through state requests can only be verified
+    /// once it has been entirely downloaded, and this limit prevents sources from sending an
+    /// infinite amount of data. If the limit is reached, all the sources involved are no longer
+    /// used and the download starts over.
+    pub max_state_entries: usize,
+}
+
+/// Identifier for a source in the [`StateSync`].
+//
+// Implementation note: this represents the index within the `Slab` used for the list of sources.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SourceId(usize);
+
+/// Download of the storage of a block in progress.
+pub struct StateSync<TSrc, TRq> {
+    /// See [`Config::block_hash`].
+    block_hash: [u8; 32],
+
+    /// See [`Config::state_root`].
+    state_root: [u8; 32],
+
+    /// See [`Config::max_state_entries`].
+    max_state_entries: usize,
+
+    /// List of sources. Never cleared, except when the state machine is destroyed.
+    sources: slab::Slab<Source<TSrc>>,
+
+    /// Request currently in progress, if any, and the source it has been sent to.
+    in_progress_request: Option<(SourceId, TRq)>,
+
+    /// Progress of the download.
+    download: Download,
+}
+
+struct Source<TSrc> {
+    /// Opaque value passed to [`StateSync::add_source`].
+    user_data: TSrc,
+
+    /// `true` if the source has failed to answer a state request in the past. It is assumed to
+    /// not support state requests.
+    state_requests_unsupported: bool,
+
+    /// `true` if the source has sent an invalid response or failed to answer a storage proof
+    /// request. Banned sources are never sent requests again.
+    banned: bool,
+
+    /// `true` if the source has answered a state request whose content is part of the
+    /// not-yet-verified storage in [`Download::State`].
+    contributed: bool,
+}
+
+enum Download {
+    /// Downloading the storage through state requests.
+    State {
+        /// Path of the last key that has been received. Passed as
+        /// [`protocol::StateRequestConfig::start`] in the next request.
+        start: Vec<Vec<u8>>,
+        /// Keys and values of the main trie received so far.
+        main_trie: BTreeMap<Vec<u8>, Vec<u8>>,
+        /// Keys and values of the child tries received so far, indexed by their root.
+        child_tries: BTreeMap<[u8; 32], BTreeMap<Vec<u8>, Vec<u8>>>,
+    },
+
+    /// Downloading the storage through storage proofs.
+    Proofs {
+        /// Keys and values of the main trie downloaded so far.
+        main_trie: BTreeMap<Vec<u8>, Vec<u8>>,
+        /// Keys and values of the child tries downloaded so far, indexed by the key of the child
+        /// trie within the main trie.
+        child_tries: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Vec<u8>>>,
+        /// Trie currently being downloaded, as the key of the child trie within the main trie
+        /// and its root. `None` for the main trie.
+        current_child_trie: Option<(Vec<u8>, [u8; 32])>,
+        /// Child tries to download after the current one. Filled once the main trie has been
+        /// fully downloaded.
+        remaining_child_tries: Vec<(Vec<u8>, [u8; 32])>,
+        /// Progress of the download of the current trie.
+        step: ProofsStep,
+    },
+}
+
+enum ProofsStep {
+    /// Enumerating the keys of the trie.
+    Keys(prefix_proof::PrefixScan),
+    /// Downloading the values of the given keys.
+    Values(Vec<Vec<u8>>),
+}
+
+impl<TSrc, TRq> StateSync<TSrc, TRq> {
+    /// Initializes a new state machine.
+    pub fn new(config: Config) -> Self {
+        StateSync {
+            block_hash: config.block_hash,
+            state_root: config.state_root,
+            max_state_entries: config.max_state_entries,
+            sources: slab::Slab::with_capacity(config.sources_capacity),
+            in_progress_request: None,
+            download: Download::State {
+                start: Vec::new(),
+                main_trie: BTreeMap::new(),
+                child_tries: BTreeMap::new(),
+            },
+        }
+    }
+
+    /// Returns the hash of the block whose storage is being downloaded.
+    pub fn block_hash(&self) -> &[u8; 32] {
+        &self.block_hash
+    }
+
+    /// Adds a new source to the state machine.
+    pub fn add_source(&mut self, user_data: TSrc) -> SourceId {
+        SourceId(self.sources.insert(Source {
+            user_data,
+            state_requests_unsupported: false,
+            banned: false,
+            contributed: false,
+        }))
+    }
+
+    /// Removes a source from the state machine. Returns the user data that was passed to
+    /// [`StateSync::add_source`].
+    ///
+    /// If a request towards this source was in progress, it is considered as cancelled and its
+    /// user data is returned as well.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn remove_source(&mut self, source_id: SourceId) -> (TSrc, Option<TRq>) {
+        let source = self.sources.remove(source_id.0);
+
+        let request = match self.in_progress_request.take() {
+            Some((s, rq)) if s == source_id => Some(rq),
+            other => {
+                self.in_progress_request = other;
+                None
+            }
+        };
+
+        self.update_download_mode();
+        (source.user_data, request)
+    }
+
+    /// Returns the list of sources in this state machine.
+    pub fn sources(&'_ self) -> impl Iterator<Item = SourceId> + '_ {
+        self.sources.iter().map(|(id, _)| SourceId(id))
+    }
+
+    /// Returns the user data associated to the given source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_user_data_mut(&mut self, source_id: SourceId) -> &mut TSrc {
+        &mut self.sources[source_id.0].user_data
+    }
+
+    /// Returns the request that should be started next, and the source it should be sent to.
+    ///
+    /// Returns `None` if a request is already in progress or if no source is available.
+    pub fn desired_request(&'_ self) -> Option<(SourceId, &'_ TSrc, RequestDetail)> {
+        if self.in_progress_request.is_some() {
+            return None;
+        }
+
+        let (source_id, source) = self.sources.iter().find(|(_, source)| {
+            !source.banned
+                && (!source.state_requests_unsupported
+                    || matches!(self.download, Download::Proofs { .. }))
+        })?;
+
+        let detail = match &self.download {
+            Download::State { start, .. } => RequestDetail::State {
+                block_hash: self.block_hash,
+                start: start.clone(),
+            },
+            Download::Proofs {
+                current_child_trie,
+                step,
+                ..
+            } => {
+                let keys = match step {
+                    ProofsStep::Keys(scan) => scan
+                        .requested_keys()
+                        .map(|nibbles| trie::nibbles_to_bytes_extend(nibbles).collect())
+                        .collect(),
+                    ProofsStep::Values(keys) => keys
+                        .iter()
+                        .take(MAX_KEYS_PER_STORAGE_PROOF)
+                        .cloned()
+                        .collect(),
+                };
+
+                match current_child_trie {
+                    None => RequestDetail::StorageProof {
+                        block_hash: self.block_hash,
+                        keys,
+                    },
+                    Some((child_trie, _)) => RequestDetail::ChildStorageProof {
+                        block_hash: self.block_hash,
+                        child_trie: child_trie.clone(),
+                        keys,
+                    },
+                }
+            }
+        };
+
+        Some((SourceId(source_id), &source.user_data, detail))
+    }
+
+    /// Registers a request that has been started. The request must be the one that has been
+    /// returned by [`StateSync::desired_request`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    /// Panics if a request is already in progress.
+    ///
+    pub fn add_request(&mut self, source_id: SourceId, user_data: TRq) {
+        assert!(self.sources.contains(source_id.0));
+        assert!(self.in_progress_request.is_none());
+        self.in_progress_request = Some((source_id, user_data));
+    }
+
+    /// Injects the response to a [`RequestDetail::State`] request.
+    ///
+    /// Pass `Err(())` if the request has failed. The source is then assumed to not support state
+    /// requests.
+    ///
+    /// # Panic
+    ///
+    /// Panics if no request is in progress.
+    ///
+    pub fn state_response(
+        mut self,
+        response: Result<Vec<protocol::StateResponseEntry>, ()>,
+    ) -> (TRq, ResponseOutcome<TSrc, TRq>) {
+        let (source_id, user_data) = self.in_progress_request.take().unwrap();
+
+        let response = match response {
+            Ok(r) => r,
+            Err(()) => {
+                self.sources[source_id.0].state_requests_unsupported = true;
+                self.update_download_mode();
+                return (
+                    user_data,
+                    ResponseOutcome::Error {
+                        sync: self,
+                        error: Error::RequestFailed,
+                    },
+                );
+            }
+        };
+
+        let outcome = match &mut self.download {
+            Download::State {
+                start,
+                main_trie,
+                child_tries,
+            } => process_state_response(
+                start,
+                main_trie,
+                child_tries,
+                self.max_state_entries,
+                response,
+            ),
+            // The download mode can only switch when no source supports state requests anymore,
+            // which can't happen while a state request is in progress.
+            Download::Proofs { .. } => unreachable!(),
+        };
+
+        match outcome {
+            Ok(false) => {
+                self.sources[source_id.0].contributed = true;
+                (user_data, ResponseOutcome::Queued(self))
+            }
+            Ok(true) => {
+                self.sources[source_id.0].contributed = true;
+                let (main_trie, child_tries) = match mem::replace(
+                    &mut self.download,
+                    Download::State {
+                        start: Vec::new(),
+                        main_trie: BTreeMap::new(),
+                        child_tries: BTreeMap::new(),
+                    },
+                ) {
+                    Download::State {
+                        main_trie,
+                        child_tries,
+                        ..
+                    } => (main_trie, child_tries),
+                    Download::Proofs { .. } => unreachable!(),
+                };
+
+                match verify_state(&self.state_root, &main_trie, &child_tries) {
+                    Ok(()) => {
+                        let child_tries = child_tries_by_key(&main_trie, child_tries);
+                        (
+                            user_data,
+                            ResponseOutcome::Finished(self.success(main_trie, child_tries)),
+                        )
+                    }
+                    Err(error) => {
+                        // It is unknown which source has sent erroneous data. All the sources
+                        // involved are no longer used, and the download starts over.
+                        self.restart_state_download();
+                        (user_data, ResponseOutcome::Error { sync: self, error })
+                    }
+                }
+            }
+            Err(Error::TooManyEntries) => {
+                // Similarly to a state root mismatch, it is unknown which source is at fault.
+                self.sources[source_id.0].contributed = true;
+                self.download = Download::State {
+                    start: Vec::new(),
+                    main_trie: BTreeMap::new(),
+                    child_tries: BTreeMap::new(),
+                };
+                self.restart_state_download();
+                (
+                    user_data,
+                    ResponseOutcome::Error {
+                        sync: self,
+                        error: Error::TooManyEntries,
+                    },
+                )
+            }
+            Err(error) => {
+                self.sources[source_id.0].banned = true;
+                self.update_download_mode();
+                (user_data, ResponseOutcome::Error { sync: self, error })
+            }
+        }
+    }
+
+    /// Injects the response to a [`RequestDetail::StorageProof`] or
+    /// [`RequestDetail::ChildStorageProof`] request.
+    ///
+    /// Pass `Err(())` if the request has failed. The source is then no longer used.
+    ///
+    /// # Panic
+    ///
+    /// Panics if no request is in progress.
+    ///
+    pub fn storage_proof_response(
+        mut self,
+        response: Result<Vec<Vec<u8>>, ()>,
+    ) -> (TRq, ResponseOutcome<TSrc, TRq>) {
+        let (source_id, user_data) = self.in_progress_request.take().unwrap();
+
+        let proof = match response {
+            Ok(p) => p,
+            Err(()) => {
+                self.sources[source_id.0].banned = true;
+                return (
+                    user_data,
+                    ResponseOutcome::Error {
+                        sync: self,
+                        error: Error::RequestFailed,
+                    },
+                );
+            }
+        };
+
+        let (main_trie, child_tries, current_child_trie, remaining_child_tries, step) =
+            match &mut self.download {
+                Download::Proofs {
+                    main_trie,
+                    child_tries,
+                    current_child_trie,
+                    remaining_child_tries,
+                    step,
+                } => (
+                    main_trie,
+                    child_tries,
+                    current_child_trie,
+                    remaining_child_tries,
+                    step,
+                ),
+                // Storage proof requests are only ever started in `Proofs` mode, and the mode
+                // never switches back.
+                Download::State { .. } => unreachable!(),
+            };
+
+        let trie_root = match current_child_trie {
+            Some((_, root)) => *root,
+            None => self.state_root,
+        };
+
+        let result = match mem::replace(step, ProofsStep::Values(Vec::new())) {
+            ProofsStep::Keys(scan) => match scan.resume(proof.iter().map(|v| &v[..])) {
+                Ok(prefix_proof::ResumeOutcome::InProgress(scan)) => {
+                    *step = ProofsStep::Keys(scan);
+                    Ok(())
+                }
+                Ok(prefix_proof::ResumeOutcome::Success { keys }) => {
+                    *step = ProofsStep::Values(keys);
+                    Ok(())
+                }
+                Err((scan, err)) => {
+                    *step = ProofsStep::Keys(scan);
+                    Err(Error::InvalidProof(err))
+                }
+            },
+            ProofsStep::Values(mut keys) => {
+                let num_keys = keys.len().min(MAX_KEYS_PER_STORAGE_PROOF);
+                let values = keys[..num_keys]
+                    .iter()
+                    .map(|key| {
+                        match proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                            requested_key: key,
+                            trie_root_hash: &trie_root,
+                            proof: proof.iter().map(|v| &v[..]),
+                        }) {
+                            Ok(Some(value)) => Ok(value.to_vec()),
+                            // The key has been found when enumerating the trie, and thus must
+                            // have a value.
+                            Ok(None) => Err(Error::MissingStorageValue),
+                            Err(err) => Err(Error::InvalidProof(err)),
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>();
+
+                match values {
+                    Ok(values) => {
+                        let trie = match current_child_trie {
+                            Some((child_trie, _)) => child_tries.get_mut(child_trie).unwrap(),
+                            None => &mut *main_trie,
+                        };
+                        trie.extend(keys.drain(..num_keys).zip(values));
+                        *step = ProofsStep::Values(keys);
+                        Ok(())
+                    }
+                    Err(err) => {
+                        *step = ProofsStep::Values(keys);
+                        Err(err)
+                    }
+                }
+            }
+        };
+
+        if let Err(error) = result {
+            self.sources[source_id.0].banned = true;
+            return (user_data, ResponseOutcome::Error { sync: self, error });
+        }
+
+        // Switch to the next trie if the current one has been fully downloaded.
+        if matches!(step, ProofsStep::Values(keys) if keys.is_empty()) {
+            if current_child_trie.is_none() {
+                *remaining_child_tries = child_tries_roots(main_trie).collect();
+            }
+
+            match remaining_child_tries.pop() {
+                Some((child_trie, root)) => {
+                    child_tries.insert(child_trie.clone(), BTreeMap::new());
+                    *step = ProofsStep::Keys(prefix_proof::prefix_scan(prefix_proof::Config {
+                        prefix: &[],
+                        trie_root_hash: root,
+                    }));
+                    *current_child_trie = Some((child_trie, root));
+                }
+                None => {
+                    let main_trie = mem::take(main_trie);
+                    let child_tries = mem::take(child_tries);
+                    return (
+                        user_data,
+                        ResponseOutcome::Finished(self.success(main_trie, child_tries)),
+                    );
+                }
+            }
+        }
+
+        (user_data, ResponseOutcome::Queued(self))
+    }
+
+    /// Bans all the sources that have contributed to the storage downloaded through state
+    /// requests. Must be called after this storage has been discarded.
+    fn restart_state_download(&mut self) {
+        for (_, source) in &mut self.sources {
+            if source.contributed {
+                source.banned = true;
+                source.contributed = false;
+            }
+        }
+        self.update_download_mode();
+    }
+
+    /// Switches to downloading through storage proofs if no source that supports state requests
+    /// remains.
+    fn update_download_mode(&mut self) {
+        if !matches!(self.download, Download::State { .. }) {
+            return;
+        }
+
+        let any_state_source = self
+            .sources
+            .iter()
+            .any(|(_, s)| !s.banned && !s.state_requests_unsupported);
+        let any_proofs_source = self.sources.iter().any(|(_, s)| !s.banned);
+        if any_state_source || !any_proofs_source {
+            return;
+        }
+
+        // The content downloaded through state requests so far is unverified and is discarded.
+        for (_, source) in &mut self.sources {
+            source.contributed = false;
+        }
+
+        self.download = Download::Proofs {
+            main_trie: BTreeMap::new(),
+            child_tries: BTreeMap::new(),
+            current_child_trie: None,
+            remaining_child_tries: Vec::new(),
+            step: ProofsStep::Keys(prefix_proof::prefix_scan(prefix_proof::Config {
+                prefix: &[],
+                trie_root_hash: self.state_root,
+            })),
+        };
+    }
+
+    fn success(
+        mut self,
+        main_trie: BTreeMap<Vec<u8>, Vec<u8>>,
+        child_tries: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Vec<u8>>>,
+    ) -> Success<TSrc> {
+        Success {
+            block_hash: self.block_hash,
+            main_trie,
+            child_tries,
+            sources: self.sources.drain().map(|s| s.user_data).collect(),
+        }
+    }
+}
+
+/// Request that should be started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestDetail {
+    /// A state request must be started. See [`protocol::build_state_request`].
+    State {
+        /// Hash of the block whose storage is requested.
+        block_hash: [u8; 32],
+        /// See [`protocol::StateRequestConfig::start`].
+        start: Vec<Vec<u8>>,
+    },
+    /// A storage proof request must be started. See [`protocol::build_storage_proof_request`].
+    StorageProof {
+        /// Hash of the block whose storage is requested.
+        block_hash: [u8; 32],
+        /// Keys whose proof is requested.
+        keys: Vec<Vec<u8>>,
+    },
+    /// A child trie storage proof request must be started. See
+    /// [`protocol::build_child_storage_proof_request`].
+    ChildStorageProof {
+        /// Hash of the block whose storage is requested.
+        block_hash: [u8; 32],
+        /// Key of the child trie within the main trie.
+        child_trie: Vec<u8>,
+        /// Keys within the child trie whose proof is requested.
+        keys: Vec<Vec<u8>>,
+    },
+}
+
+/// Outcome of injecting a response into the [`StateSync`].
+pub enum ResponseOutcome<TSrc, TRq> {
+    /// Response has been processed. The download continues.
+    Queued(StateSync<TSrc, TRq>),
+
+    /// Response couldn't be processed. The source that has sent it, or all the sources involved
+    /// in case of a state root mismatch or of [`Error::TooManyEntries`], will not be sent any
+    /// request anymore, unless the error is [`Error::RequestFailed`] after a state request. The
+    /// download continues.
+    Error {
+        /// State machine yielded back.
+        sync: StateSync<TSrc, TRq>,
+        /// Problem that happened.
+        error: Error,
+    },
+
+    /// The storage has been entirely downloaded and verified.
+    Finished(Success<TSrc>),
+}
+
+/// Storage of a block that has been successfully downloaded.
+#[derive(Debug)]
+pub struct Success<TSrc> {
+    /// Hash of the block whose storage has been downloaded.
+    pub block_hash: [u8; 32],
+
+    /// Keys and values of the main trie. Includes the keys that contain the roots of the child
+    /// tries.
+    pub main_trie: BTreeMap<Vec<u8>, Vec<u8>>,
+
+    /// Keys and values of each child trie, indexed by the key of the child trie within the main
+    /// trie.
+    pub child_tries: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Vec<u8>>>,
+
+    /// List of the sources that were in the state machine.
+    pub sources: Vec<TSrc>,
+}
+
+/// Problem with a response.
+#[derive(Debug, derive_more::Display)]
+pub enum Error {
+    /// The request has failed.
+    RequestFailed,
+    /// State response doesn't start with the main trie, or contains the main trie more than once.
+    BadResponseLayout,
+    /// State response contains a child trie that isn't referenced by the main trie.
+    UnknownChildTrie,
+    /// State response doesn't contain any entry yet indicates that the storage isn't complete.
+    EmptyResponse,
+    /// State response contains keys that aren't in increasing order or that don't come after
+    /// the start of the request.
+    KeysNotAdvancing,
+    /// Storage downloaded through state requests exceeds [`Config::max_state_entries`].
+    TooManyEntries,
+    /// Downloaded storage doesn't match the state root of the block.
+    StateRootMismatch,
+    /// Downloaded content of a child trie doesn't match its root.
+    ChildTrieRootMismatch,
+    /// Main trie references a child trie whose content hasn't been downloaded.
+    MissingChildTrie,
+    /// Invalid storage proof.
+    #[display(fmt = "{}", _0)]
+    InvalidProof(proof_verify::Error),
+    /// Storage proof doesn't contain the value of a key that is known to exist.
+    MissingStorageValue,
+}
+
+/// Validates a state response and merges its content with the storage received so far.
+///
+/// Returns `true` if the storage has been entirely received. On error, nothing is modified.
+fn process_state_response(
+    start: &mut Vec<Vec<u8>>,
+    main_trie: &mut BTreeMap<Vec<u8>, Vec<u8>>,
+    child_tries: &mut BTreeMap<[u8; 32], BTreeMap<Vec<u8>, Vec<u8>>>,
+    max_entries: usize,
+    response: Vec<protocol::StateResponseEntry>,
+) -> Result<bool, Error> {
+    // The first entry is always the main trie, and the other ones are child tries.
+    match response.first() {
+        Some(entry) if entry.state_root.is_none() => {}
+        _ => return Err(Error::BadResponseLayout),
+    }
+
+    if response.iter().skip(1).any(|e| e.state_root.is_none()) {
+        return Err(Error::BadResponseLayout);
+    }
+
+    // Child tries must be referenced by the main trie, either in a previous response or in this
+    // one.
+    for child_root in response.iter().filter_map(|e| e.state_root.as_ref()) {
+        let in_response = child_tries_roots_iter(response[0].entries.iter().map(|(k, v)| (k, v)))
+            .any(|(_, root)| root == *child_root);
+        if !in_response && !child_tries.contains_key(child_root) {
+            return Err(Error::UnknownChildTrie);
+        }
+    }
+
+    if response.iter().all(|e| e.entries.is_empty()) && response.iter().any(|e| !e.complete) {
+        return Err(Error::EmptyResponse);
+    }
+
+    // Responses start after the key passed in the request. Making sure that keys advance
+    // guarantees that the download can't loop indefinitely.
+    for (index, entry) in response.iter().enumerate() {
+        let lower_bound = match (index, start.len()) {
+            (0, 1..=2) => Some(&start[0]),
+            (1, 2) => Some(&start[1]),
+            _ => None,
+        };
+
+        let mut previous = lower_bound;
+        for (key, _) in &entry.entries {
+            if previous.map_or(false, |p| key <= p) {
+                return Err(Error::KeysNotAdvancing);
+            }
+            previous = Some(key);
+        }
+    }
+
+    let num_entries = main_trie.len()
+        + child_tries.values().map(|t| t.len()).sum::<usize>()
+        + response.iter().map(|e| e.entries.len()).sum::<usize>();
+    if num_entries > max_entries {
+        return Err(Error::TooManyEntries);
+    }
+
+    // The start key of the next request is the path of the last key received. If the main trie
+    // has no entry in this response, the response was entirely about a child trie and the
+    // position in the main trie stays the same.
+    if start.len() == 2 && response[0].entries.is_empty() {
+        start.truncate(1);
+    } else {
+        start.clear();
+    }
+
+    let mut complete = true;
+    for entry in response {
+        if !entry.complete {
+            if let Some((key, _)) = entry.entries.last() {
+                start.push(key.clone());
+            }
+            complete = false;
+        }
+
+        match entry.state_root {
+            None => {
+                for (_, root) in child_tries_roots_iter(entry.entries.iter().map(|(k, v)| (k, v))) {
+                    child_tries.entry(root).or_default();
+                }
+                main_trie.extend(entry.entries);
+            }
+            Some(root) => {
+                child_tries.entry(root).or_default().extend(entry.entries);
+            }
+        }
+    }
+
+    Ok(complete)
+}
+
+/// Verifies the storage downloaded through state requests against the state root.
+fn verify_state(
+    state_root: &[u8; 32],
+    main_trie: &BTreeMap<Vec<u8>, Vec<u8>>,
+    child_tries_by_root: &BTreeMap<[u8; 32], BTreeMap<Vec<u8>, Vec<u8>>>,
+) -> Result<(), Error> {
+    if trie_root(main_trie) != *state_root {
+        return Err(Error::StateRootMismatch);
+    }
+
+    for (root, child_trie) in child_tries_by_root {
+        if trie_root(child_trie) != *root {
+            return Err(Error::ChildTrieRootMismatch);
+        }
+    }
+
+    if child_tries_roots(main_trie).any(|(_, root)| !child_tries_by_root.contains_key(&root)) {
+        return Err(Error::MissingChildTrie);
+    }
+
+    Ok(())
+}
+
+/// Turns the list of child tries indexed by their root into a list of child tries indexed by
+/// their key within the main trie.
+///
+/// Must only be called after [`verify_state`] has succeeded.
+fn child_tries_by_key(
+    main_trie: &BTreeMap<Vec<u8>, Vec<u8>>,
+    mut child_tries_by_root: BTreeMap<[u8; 32], BTreeMap<Vec<u8>, Vec<u8>>>,
+) -> BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Vec<u8>>> {
+    // Multiple child tries can in principle have the same root, in which case their content is
+    // only sent once.
+    let mut references = BTreeMap::<[u8; 32], usize>::new();
+    for (_, root) in child_tries_roots(main_trie) {
+        *references.entry(root).or_insert(0) += 1;
+    }
+
+    let mut child_tries = BTreeMap::new();
+    for (key, root) in child_tries_roots(main_trie) {
+        let remaining = references.get_mut(&root).unwrap();
+        *remaining -= 1;
+        let content = if *remaining == 0 {
+            child_tries_by_root.remove(&root).unwrap()
+        } else {
+            child_tries_by_root.get(&root).unwrap().clone()
+        };
+        child_tries.insert(key, content);
+    }
+
+    child_tries
+}
+
+/// Returns the list of child tries referenced by the main trie, as the key of each child trie
+/// within the main trie and its root.
+fn child_tries_roots(
+    main_trie: &'_ BTreeMap<Vec<u8>, Vec<u8>>,
+) -> impl Iterator<Item = (Vec<u8>, [u8; 32])> + '_ {
+    let iter = main_trie
+        .range::<[u8], _>((
+            core::ops::Bound::Included(CHILD_STORAGE_PREFIX),
+            core::ops::Bound::Unbounded,
+        ))
+        .take_while(|(k, _)| k.starts_with(CHILD_STORAGE_PREFIX));
+    child_tries_roots_iter(iter).map(|(k, root)| (k.clone(), root))
+}
+
+/// Filters the given list of keys and values of the main trie and only returns the entries that
+/// contain the root of a child trie.
+fn child_tries_roots_iter<'a>(
+    entries: impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
+) -> impl Iterator<Item = (&'a Vec<u8>, [u8; 32])> {
+    entries
+        .filter(|(k, _)| k.starts_with(CHILD_STORAGE_PREFIX))
+        .filter_map(|(k, v)| Some((k, <[u8; 32]>::try_from(&v[..]).ok()?)))
+}
+
+/// Calculates the root of the trie made of the given keys and values.
+fn trie_root(entries: &BTreeMap<Vec<u8>, Vec<u8>>) -> [u8; 32] {
+    let mut calculation = calculate_root::root_merkle_value(None);
+
+    loop {
+        match calculation {
+            calculate_root::RootMerkleValueCalculation::Finished { hash, .. } => break hash,
+            calculate_root::RootMerkleValueCalculation::AllKeys(keys) => {
+                calculation = keys.inject(entries.keys().map(|k| k.iter().cloned()));
+            }
+            calculate_root::RootMerkleValueCalculation::StorageValue(value) => {
+                let key = value.key().collect::<Vec<u8>>();
+                calculation = value.inject(entries.get(&key));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::StateResponseEntry;
+
+    struct TestStorage {
+        main_trie: BTreeMap<Vec<u8>, Vec<u8>>,
+        child_trie: BTreeMap<Vec<u8>, Vec<u8>>,
+        child_root: [u8; 32],
+        state_root: [u8; 32],
+    }
+
+    fn storage() -> TestStorage {
+        let mut child_trie = BTreeMap::new();
+        child_trie.insert(b"k1".to_vec(), b"v1".to_vec());
+        child_trie.insert(b"k2".to_vec(), b"v2".to_vec());
+        let child_root = trie_root(&child_trie);
+
+        let mut main_trie = BTreeMap::new();
+        main_trie.insert(b":child_storage:default:x".to_vec(), child_root.to_vec());
+        main_trie.insert(b"a".to_vec(), b"1".to_vec());
+        main_trie.insert(b"b".to_vec(), b"2".to_vec());
+        let state_root = trie_root(&main_trie);
+
+        TestStorage {
+            main_trie,
+            child_trie,
+            child_root,
+            state_root,
+        }
+    }
+
+    fn entry(
+        state_root: Option<[u8; 32]>,
+        entries: &[(&[u8], &[u8])],
+        complete: bool,
+    ) -> StateResponseEntry {
+        StateResponseEntry {
+            state_root,
+            entries: entries
+                .iter()
+                .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                .collect(),
+            complete,
+        }
+    }
+
+    fn expect_queued<TSrc, TRq>(outcome: ResponseOutcome<TSrc, TRq>) -> StateSync<TSrc, TRq> {
+        match outcome {
+            ResponseOutcome::Queued(sync) => sync,
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn download_with_state_requests() {
+        let TestStorage {
+            main_trie,
+            child_trie,
+            child_root,
+            state_root,
+        } = storage();
+        let child_key: &[u8] = b":child_storage:default:x";
+
+        let mut sync = StateSync::<(), ()>::new(Config {
+            block_hash: [1; 32],
+            state_root,
+            sources_capacity: 1,
+            max_state_entries: 1024,
+        });
+        let source = sync.add_source(());
+
+        // First response stops in the middle of the child trie.
+        let (source_id, _, detail) = sync.desired_request().unwrap();
+        assert_eq!(source_id, source);
+        assert_eq!(
+            detail,
+            RequestDetail::State {
+                block_hash: [1; 32],
+                start: Vec::new()
+            }
+        );
+        sync.add_request(source, ());
+        let (_, outcome) = sync.state_response(Ok(vec![
+            entry(None, &[(child_key, &child_root[..])], false),
+            entry(Some(child_root), &[(b"k1", b"v1")], false),
+        ]));
+        let mut sync = expect_queued(outcome);
+
+        // Second response finishes the child trie.
+        let (_, _, detail) = sync.desired_request().unwrap();
+        assert_eq!(
+            detail,
+            RequestDetail::State {
+                block_hash: [1; 32],
+                start: vec![child_key.to_vec(), b"k1".to_vec()]
+            }
+        );
+        sync.add_request(source, ());
+        let (_, outcome) = sync.state_response(Ok(vec![
+            entry(None, &[], false),
+            entry(Some(child_root), &[(b"k2", b"v2")], true),
+        ]));
+        let mut sync = expect_queued(outcome);
+
+        // Third response finishes the main trie.
+        let (_, _, detail) = sync.desired_request().unwrap();
+        assert_eq!(
+            detail,
+            RequestDetail::State {
+                block_hash: [1; 32],
+                start: vec![child_key.to_vec()]
+            }
+        );
+        sync.add_request(source, ());
+        let (_, outcome) =
+            sync.state_response(Ok(vec![entry(None, &[(b"a", b"1"), (b"b", b"2")], true)]));
+
+        match outcome {
+            ResponseOutcome::Finished(success) => {
+                assert_eq!(success.main_trie, main_trie);
+                assert_eq!(success.child_tries.len(), 1);
+                assert_eq!(success.child_tries[child_key], child_trie);
+                assert_eq!(success.sources.len(), 1);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn state_root_mismatch_restarts() {
+        let state_root = storage().state_root;
+
+        let mut sync = StateSync::<(), ()>::new(Config {
+            block_hash: [1; 32],
+            state_root,
+            sources_capacity: 2,
+            max_state_entries: 1024,
+        });
+        let source1 = sync.add_source(());
+        sync.add_request(source1, ());
+        let (_, outcome) =
+            sync.state_response(Ok(vec![entry(None, &[(b"a", b"bad value")], true)]));
+
+        let mut sync = match outcome {
+            ResponseOutcome::Error {
+                sync,
+                error: Error::StateRootMismatch,
+            } => sync,
+            _ => panic!(),
+        };
+
+        // The source that has sent the invalid storage is no longer used.
+        assert!(sync.desired_request().is_none());
+
+        let source2 = sync.add_source(());
+        let (source_id, _, detail) = sync.desired_request().unwrap();
+        assert_eq!(source_id, source2);
+        assert_eq!(
+            detail,
+            RequestDetail::State {
+                block_hash: [1; 32],
+                start: Vec::new()
+            }
+        );
+    }
+
+    #[test]
+    fn falls_back_to_storage_proofs() {
+        let state_root = storage().state_root;
+
+        let mut sync = StateSync::<(), ()>::new(Config {
+            block_hash: [1; 32],
+            state_root,
+            sources_capacity: 1,
+            max_state_entries: 1024,
+        });
+        let source = sync.add_source(());
+        sync.add_request(source, ());
+        let (_, outcome) = sync.state_response(Err(()));
+
+        let sync = match outcome {
+            ResponseOutcome::Error {
+                sync,
+                error: Error::RequestFailed,
+            } => sync,
+            _ => panic!(),
+        };
+
+        // The only source doesn't support state requests, and the root node is now requested
+        // through a storage proof.
+        let (source_id, _, detail) = sync.desired_request().unwrap();
+        assert_eq!(source_id, source);
+        assert_eq!(
+            detail,
+            RequestDetail::StorageProof {
+                block_hash: [1; 32],
+                keys: vec![Vec::new()]
+            }
+        );
+    }
+
+    #[test]
+    fn keys_not_advancing_rejected() {
+        let state_root = storage().state_root;
+
+        let mut sync = StateSync::<(), ()>::new(Config {
+            block_hash: [1; 32],
+            state_root,
+            sources_capacity: 2,
+            max_state_entries: 1024,
+        });
+        let source1 = sync.add_source(());
+        sync.add_request(source1, ());
+        let (_, outcome) = sync.state_response(Ok(vec![entry(None, &[(b"b", b"2")], false)]));
+        let mut sync = expect_queued(outcome);
+
+        // The second response starts again from a key that has already been received.
+        sync.add_request(source1, ());
+        let (_, outcome) = sync.state_response(Ok(vec![entry(None, &[(b"b", b"2")], false)]));
+        let mut sync = match outcome {
+            ResponseOutcome::Error {
+                sync,
+                error: Error::KeysNotAdvancing,
+            } => sync,
+            _ => panic!(),
+        };
+        assert!(sync.desired_request().is_none());
+
+        // The download continues from where it was with another source.
+        let source2 = sync.add_source(());
+        let (source_id, _, detail) = sync.desired_request().unwrap();
+        assert_eq!(source_id, source2);
+        assert_eq!(
+            detail,
+            RequestDetail::State {
+                block_hash: [1; 32],
+                start: vec![b"b".to_vec()]
+            }
+        );
+
+        // Keys within a response must also be in increasing order.
+        sync.add_request(source2, ());
+        let (_, outcome) =
+            sync.state_response(Ok(vec![entry(None, &[(b"d", b"4"), (b"c", b"3")], false)]));
+        assert!(matches!(
+            outcome,
+            ResponseOutcome::Error {
+                error: Error::KeysNotAdvancing,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn too_many_entries_restarts() {
+        let state_root = storage().state_root;
+
+        let mut sync = StateSync::<(), ()>::new(Config {
+            block_hash: [1; 32],
+            state_root,
+            sources_capacity: 3,
+            max_state_entries: 2,
+        });
+        let source1 = sync.add_source(());
+        let source2 = sync.add_source(());
+
+        sync.add_request(source1, ());
+        let (_, outcome) = sync.state_response(Ok(vec![entry(None, &[(b"a", b"1")], false)]));
+        let mut sync = expect_queued(outcome);
+
+        sync.add_request(source2, ());
+        let (_, outcome) =
+            sync.state_response(Ok(vec![entry(None, &[(b"b", b"2"), (b"c", b"3")], false)]));
+        let mut sync = match outcome {
+            ResponseOutcome::Error {
+                sync,
+                error: Error::TooManyEntries,
+            } => sync,
+            _ => panic!(),
+        };
+
+        // Both sources involved are no longer used, and the download starts over.
+        assert!(sync.desired_request().is_none());
+        let source3 = sync.add_source(());
+        let (source_id, _, detail) = sync.desired_request().unwrap();
+        assert_eq!(source_id, source3);
+        assert_eq!(
+            detail,
+            RequestDetail::State {
+                block_hash: [1; 32],
+                start: Vec::new()
+            }
+        );
+    }
+}
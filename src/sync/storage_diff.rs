@@ -0,0 +1,252 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage of a non-finalized block, accessed through the storage of the finalized block.
+//!
+//! In full mode, syncing strategies verify blocks on top of non-finalized blocks, while the API
+//! user only gives access to the storage of the finalized block. The difference between the
+//! storage of the finalized block and the one of the block of interest is tracked as a diff,
+//! where `None` indicates that the key has been erased. The functions in this module combine
+//! this diff with the answers of the API user.
+
+use alloc::{borrow::ToOwned as _, collections::BTreeMap, vec::Vec};
+use hashbrown::HashSet;
+
+/// Difference between the storage of the finalized block and the storage of another block.
+pub(super) type StorageDiff = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Returns the value of `key` in the storage of the block of `diff`, or `None` if the value is
+/// the same as in the storage of the finalized block, in which case the API user must be asked.
+pub(super) fn get<'a>(diff: &'a StorageDiff, key: &[u8]) -> Option<Option<&'a [u8]>> {
+    diff.get(key).map(|value| value.as_ref().map(|v| &v[..]))
+}
+
+/// Turns the list of keys starting with `prefix` in the storage of the finalized block into the
+/// list of keys starting with `prefix` in the storage of the block of `diff`.
+pub(super) fn prefix_keys(
+    diff: &StorageDiff,
+    prefix: &[u8],
+    finalized_keys: impl Iterator<Item = impl AsRef<[u8]>>,
+) -> HashSet<Vec<u8>, fnv::FnvBuildHasher> {
+    let mut keys = finalized_keys
+        .map(|k| k.as_ref().to_owned())
+        .collect::<HashSet<_, fnv::FnvBuildHasher>>();
+
+    for (k, v) in diff
+        .range(prefix.to_owned()..)
+        .take_while(|(k, _)| k.starts_with(prefix))
+    {
+        if v.is_some() {
+            keys.insert(k.clone());
+        } else {
+            keys.remove(k);
+        }
+    }
+
+    keys
+}
+
+/// Outcome of [`next_key`].
+pub(super) enum NextKey<'a> {
+    /// Key that follows the requested key in the storage of the block of the diff, if any.
+    Found(Option<&'a [u8]>),
+    /// The next key in the storage of the finalized block has been erased. The API user must be
+    /// asked for the key that follows the one contained in this variant in the storage of the
+    /// finalized block, and [`next_key`] called again with this key as `requested_key`.
+    AskAgain(Vec<u8>),
+}
+
+/// Turns the key that follows `requested_key` in the storage of the finalized block into the
+/// key that follows `requested_key` in the storage of the block of `diff`.
+///
+/// # Panic
+///
+/// Panics if `finalized_next_key` isn't strictly superior to `requested_key`.
+///
+pub(super) fn next_key<'a>(
+    diff: &'a StorageDiff,
+    requested_key: &[u8],
+    finalized_next_key: Option<&'a [u8]>,
+) -> NextKey<'a> {
+    if let Some(key) = finalized_next_key {
+        assert!(key > requested_key);
+    }
+
+    let in_diff = diff
+        .range(requested_key.to_vec()..) // TODO: don't use to_vec()
+        .map(|(k, v)| (k, v.is_some()))
+        .find(|(k, _)| &***k > requested_key);
+
+    match (finalized_next_key, in_diff) {
+        (Some(a), Some((b, true))) if a <= &b[..] => NextKey::Found(Some(a)),
+        (Some(a), Some((b, false))) if a < &b[..] => NextKey::Found(Some(a)),
+        (Some(a), Some((b, false))) => {
+            debug_assert!(a >= &b[..]);
+            debug_assert_ne!(&b[..], requested_key);
+
+            // The next key according to the finalized block storage has been erased since
+            // then. It is necessary to ask the user again, this time for the key after the
+            // one that has been erased.
+            NextKey::AskAgain(b.clone())
+        }
+        (Some(a), Some((b, true))) => {
+            debug_assert!(a >= &b[..]);
+            NextKey::Found(Some(&b[..]))
+        }
+
+        (Some(a), None) => NextKey::Found(Some(a)),
+        (None, Some((b, true))) => NextKey::Found(Some(&b[..])),
+        (None, Some((b, false))) => {
+            debug_assert!(&b[..] > requested_key);
+            NextKey::Found(
+                diff.range(b.clone()..) // TODO: don't clone?
+                    .filter(|(_, value)| value.is_some())
+                    .map(|(k, _)| &k[..])
+                    .next(),
+            )
+        }
+        (None, None) => NextKey::Found(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NextKey, StorageDiff};
+    use alloc::{collections::BTreeMap, vec::Vec};
+
+    /// Storage of the finalized block used by all the tests.
+    fn finalized() -> BTreeMap<Vec<u8>, Vec<u8>> {
+        [&b"a"[..], b"ab", b"abc", b"b", b"ba", b"c", b"d"]
+            .iter()
+            .map(|k| (k.to_vec(), b"finalized".to_vec()))
+            .collect()
+    }
+
+    /// Diff erasing, overwriting and inserting keys, including erased keys that follow each
+    /// other.
+    fn diff() -> StorageDiff {
+        let mut diff = StorageDiff::new();
+        diff.insert(b"ab".to_vec(), None);
+        diff.insert(b"abc".to_vec(), None);
+        diff.insert(b"abd".to_vec(), Some(b"diff".to_vec()));
+        diff.insert(b"b".to_vec(), Some(b"diff".to_vec()));
+        diff.insert(b"bb".to_vec(), Some(b"diff".to_vec()));
+        diff.insert(b"c".to_vec(), None);
+        diff.insert(b"d".to_vec(), None);
+        diff.insert(b"z".to_vec(), None);
+        diff
+    }
+
+    /// Storage that the combination of [`finalized`] and [`diff`] must represent.
+    fn expected() -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let mut storage = finalized();
+        for (key, value) in diff() {
+            match value {
+                Some(value) => storage.insert(key, value),
+                None => storage.remove(&key),
+            };
+        }
+        storage
+    }
+
+    /// Queries the storage of the finalized block the same way as the API user would.
+    fn finalized_next_key<'a>(
+        finalized: &'a BTreeMap<Vec<u8>, Vec<u8>>,
+        key: &[u8],
+    ) -> Option<&'a [u8]> {
+        finalized
+            .range(key.to_vec()..)
+            .map(|(k, _)| &k[..])
+            .find(|k| *k > key)
+    }
+
+    #[test]
+    fn get_round_trip() {
+        let finalized = finalized();
+        let diff = diff();
+        let expected = expected();
+
+        for key in [&b"a"[..], b"ab", b"abd", b"b", b"c", b"e", b"z"].iter() {
+            let value = match super::get(&diff, key) {
+                Some(value) => value,
+                None => finalized.get(*key).map(|v| &v[..]),
+            };
+            assert_eq!(value, expected.get(*key).map(|v| &v[..]));
+        }
+    }
+
+    #[test]
+    fn prefix_keys_round_trip() {
+        let finalized = finalized();
+        let diff = diff();
+        let expected = expected();
+
+        for prefix in [&b""[..], b"a", b"ab", b"b", b"c", b"z"].iter() {
+            let keys = super::prefix_keys(
+                &diff,
+                prefix,
+                finalized.keys().filter(|k| k.starts_with(prefix)),
+            );
+            let mut keys = keys.into_iter().collect::<Vec<_>>();
+            keys.sort();
+
+            let expected = expected
+                .keys()
+                .filter(|k| k.starts_with(prefix))
+                .cloned()
+                .collect::<Vec<_>>();
+            assert_eq!(keys, expected);
+        }
+    }
+
+    #[test]
+    fn next_key_round_trip() {
+        let finalized = finalized();
+        let diff = diff();
+        let expected = expected();
+
+        for requested in [
+            &b""[..],
+            b"a",
+            b"aa",
+            b"ab",
+            b"abc",
+            b"b",
+            b"bc",
+            b"c",
+            b"d",
+        ]
+        .iter()
+        {
+            let mut to_ask = requested.to_vec();
+            let mut num_requests = 0;
+            let found = loop {
+                num_requests += 1;
+                assert!(num_requests <= finalized.len() + 1);
+                match super::next_key(&diff, &to_ask, finalized_next_key(&finalized, &to_ask)) {
+                    NextKey::Found(found) => break found,
+                    NextKey::AskAgain(key) => {
+                        assert!(key > to_ask);
+                        to_ask = key;
+                    }
+                }
+            };
+
+            assert_eq!(found, finalized_next_key(&expected, requested));
+        }
+    }
+}
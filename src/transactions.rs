@@ -0,0 +1,29 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Transactions handling.
+//!
+//! Transactions are pieces of data that are submitted by users (through the JSON-RPC interface
+//! or through the peer-to-peer network) and that are meant to be included in the body of a
+//! future block.
+//!
+//! Before a transaction can be included in a block, it must first be validated by the runtime.
+//! See the [`validate`] module. Transactions that have been received but not included in a
+//! block yet are typically stored in a pool. See the [`pool`] module.
+
+pub mod pool;
+pub mod validate;
@@ -0,0 +1,598 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pool of transactions waiting to be included in a block.
+//!
+//! The [`Pool`] contains a list of transactions, each associated with a user data of type `TTx`.
+//! Transactions are identified by a [`TransactionId`] that is allocated when the transaction is
+//! inserted.
+//!
+//! # Validation
+//!
+//! Newly-inserted transactions aren't validated. The API user is expected to call
+//! [`Pool::unvalidated_transactions`] in order to determine which transactions need to be
+//! validated, perform the validation (see the [`validate`](super::validate) module), then
+//! report the outcome with [`Pool::set_validation_result`]. Transactions that turn out to be
+//! invalid are expected to be removed with [`Pool::remove`].
+//!
+//! The outcome of a validation is only relevant for the block it has been performed against.
+//! Whenever the best block is updated with [`Pool::set_best_block`], all transactions that
+//! haven't been validated against this new best block are returned again by
+//! [`Pool::unvalidated_transactions`], so that they can be revalidated. Transactions whose
+//! longevity has expired are removed from the pool.
+//!
+//! # Inclusion
+//!
+//! When a block is added to the chain, [`Pool::remove_included`] should be called with the body
+//! of this block in order to remove the transactions that have been included in it.
+//!
+//! # Ordering
+//!
+//! Validated transactions indicate a list of *tags* that they provide and a list of tags that
+//! they require. A transaction can only be included in a block after the transactions that
+//! provide all the tags it requires, and two transactions that provide the same tag are
+//! mutually exclusive.
+//!
+//! [`Pool::ready_transactions`] returns the list of transactions that can be included, in
+//! order, in a block built on top of the current best block. Amongst the transactions that
+//! don't depend on each other, the ones with the highest priority are returned first.
+
+use super::validate::ValidTransaction;
+
+use alloc::{collections::BinaryHeap, vec::Vec};
+use core::{cmp, fmt};
+use hashbrown::{HashMap, HashSet};
+
+/// Configuration for [`Pool::new`].
+pub struct Config {
+    /// Number of transactions to initially allocate memory for.
+    pub capacity: usize,
+
+    /// Hash of the current best block.
+    pub best_block_hash: [u8; 32],
+
+    /// Height of the current best block.
+    pub best_block_height: u64,
+}
+
+/// Identifier of a transaction stored within the [`Pool`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TransactionId(usize);
+
+/// Data structure containing transactions. See the module-level documentation for more info.
+pub struct Pool<TTx> {
+    /// Actual list of transactions.
+    transactions: slab::Slab<Transaction<TTx>>,
+
+    /// Index of the transactions by hash.
+    by_hash: HashMap<[u8; 32], TransactionId, fnv::FnvBuildHasher>,
+
+    /// See [`Config::best_block_hash`].
+    best_block_hash: [u8; 32],
+
+    /// See [`Config::best_block_height`].
+    best_block_height: u64,
+}
+
+struct Transaction<TTx> {
+    /// Bytes corresponding to the SCALE-encoded transaction.
+    scale_encoded: Vec<u8>,
+
+    /// Hash of [`Transaction::scale_encoded`].
+    hash: [u8; 32],
+
+    /// Outcome of the latest validation of this transaction, if any.
+    validation: Option<Validation>,
+
+    /// User data chosen by the API user.
+    user_data: TTx,
+}
+
+struct Validation {
+    /// Hash of the block the validation has been performed against.
+    block_hash: [u8; 32],
+
+    /// Height of the block the validation has been performed against.
+    block_height: u64,
+
+    /// Outcome of the validation.
+    result: ValidTransaction,
+}
+
+impl<TTx> Pool<TTx> {
+    /// Initializes a new transactions pool.
+    pub fn new(config: Config) -> Self {
+        Pool {
+            transactions: slab::Slab::with_capacity(config.capacity),
+            by_hash: HashMap::with_capacity_and_hasher(config.capacity, Default::default()),
+            best_block_hash: config.best_block_hash,
+            best_block_height: config.best_block_height,
+        }
+    }
+
+    /// Returns true if the pool is empty.
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Returns the number of transactions in the pool.
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Returns the hash and height of the current best block, as passed to [`Config`] or
+    /// [`Pool::set_best_block`].
+    pub fn best_block(&self) -> (&[u8; 32], u64) {
+        (&self.best_block_hash, self.best_block_height)
+    }
+
+    /// Inserts a new non-validated transaction in the pool.
+    ///
+    /// Returns an error if a transaction with the same bytes is already in the pool.
+    pub fn add_unvalidated(
+        &mut self,
+        scale_encoded: Vec<u8>,
+        user_data: TTx,
+    ) -> Result<TransactionId, AddError> {
+        let hash = hash(&scale_encoded);
+        if let Some(id) = self.by_hash.get(&hash) {
+            return Err(AddError::AlreadyInPool(*id));
+        }
+
+        let id = TransactionId(self.transactions.insert(Transaction {
+            scale_encoded,
+            hash,
+            validation: None,
+            user_data,
+        }));
+
+        self.by_hash.insert(hash, id);
+        Ok(id)
+    }
+
+    /// Removes a transaction from the pool. Returns its SCALE encoding and user data.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the identifier is invalid.
+    ///
+    pub fn remove(&mut self, id: TransactionId) -> (Vec<u8>, TTx) {
+        let tx = self.transactions.remove(id.0);
+        let _removed = self.by_hash.remove(&tx.hash);
+        debug_assert_eq!(_removed, Some(id));
+        (tx.scale_encoded, tx.user_data)
+    }
+
+    /// Returns the list of all transactions within the pool.
+    pub fn transactions(&'_ self) -> impl Iterator<Item = (TransactionId, &'_ TTx)> + '_ {
+        self.transactions
+            .iter()
+            .map(|(id, tx)| (TransactionId(id), &tx.user_data))
+    }
+
+    /// Returns the identifier of the transaction with the given hash, if any.
+    ///
+    /// The hash of a transaction is the blake2 hash of its SCALE encoding.
+    pub fn find_by_hash(&self, hash: &[u8; 32]) -> Option<TransactionId> {
+        self.by_hash.get(hash).copied()
+    }
+
+    /// Returns the hash of the given transaction.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the identifier is invalid.
+    ///
+    pub fn hash(&self, id: TransactionId) -> &[u8; 32] {
+        &self.transactions[id.0].hash
+    }
+
+    /// Returns the bytes corresponding to the SCALE encoding of the given transaction.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the identifier is invalid.
+    ///
+    pub fn scale_encoding(&self, id: TransactionId) -> &[u8] {
+        &self.transactions[id.0].scale_encoded
+    }
+
+    /// Returns the user data associated with the given transaction.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the identifier is invalid.
+    ///
+    pub fn user_data(&self, id: TransactionId) -> &TTx {
+        &self.transactions[id.0].user_data
+    }
+
+    /// Returns the user data associated with the given transaction.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the identifier is invalid.
+    ///
+    pub fn user_data_mut(&mut self, id: TransactionId) -> &mut TTx {
+        &mut self.transactions[id.0].user_data
+    }
+
+    /// Returns the outcome of the latest validation of the given transaction, alongside with
+    /// the hash and height of the block it has been performed against. Returns `None` if the
+    /// transaction has never been validated.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the identifier is invalid.
+    ///
+    pub fn validation(&self, id: TransactionId) -> Option<(&[u8; 32], u64, &ValidTransaction)> {
+        self.transactions[id.0]
+            .validation
+            .as_ref()
+            .map(|v| (&v.block_hash, v.block_height, &v.result))
+    }
+
+    /// Returns the list of transactions that have never been validated or that haven't been
+    /// validated against the current best block.
+    pub fn unvalidated_transactions(
+        &'_ self,
+    ) -> impl Iterator<Item = (TransactionId, &'_ [u8])> + '_ {
+        let best_block_hash = &self.best_block_hash;
+        self.transactions
+            .iter()
+            .filter(move |(_, tx)| {
+                tx.validation
+                    .as_ref()
+                    .map_or(true, |v| v.block_hash != *best_block_hash)
+            })
+            .map(|(id, tx)| (TransactionId(id), &tx.scale_encoded[..]))
+    }
+
+    /// Stores the outcome of the validation of the given transaction against the given block.
+    ///
+    /// Transactions whose validation has failed are expected to be removed with
+    /// [`Pool::remove`] instead.
+    ///
+    /// If the outcome of a validation against a block of a higher height has already been
+    /// stored, this function has no effect. This makes it possible to validate transactions in
+    /// parallel of updates to the best block.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the identifier is invalid.
+    ///
+    pub fn set_validation_result(
+        &mut self,
+        id: TransactionId,
+        block_hash: [u8; 32],
+        block_height: u64,
+        result: ValidTransaction,
+    ) {
+        let tx = &mut self.transactions[id.0];
+
+        if let Some(existing) = &tx.validation {
+            if existing.block_height > block_height {
+                return;
+            }
+        }
+
+        tx.validation = Some(Validation {
+            block_hash,
+            block_height,
+            result,
+        });
+    }
+
+    /// Updates the current best block of the chain.
+    ///
+    /// All the transactions that haven't been validated against this block are from now on
+    /// returned by [`Pool::unvalidated_transactions`].
+    ///
+    /// Transactions whose longevity has expired are removed from the pool and returned.
+    pub fn set_best_block(
+        &mut self,
+        best_block_hash: [u8; 32],
+        best_block_height: u64,
+    ) -> Vec<(TransactionId, Vec<u8>, TTx)> {
+        self.best_block_hash = best_block_hash;
+        self.best_block_height = best_block_height;
+
+        let expired = self
+            .transactions
+            .iter()
+            .filter(|(_, tx)| {
+                tx.validation.as_ref().map_or(false, |v| {
+                    v.block_height.saturating_add(v.result.longevity) < best_block_height
+                })
+            })
+            .map(|(id, _)| TransactionId(id))
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .map(|id| {
+                let (scale_encoded, user_data) = self.remove(id);
+                (id, scale_encoded, user_data)
+            })
+            .collect()
+    }
+
+    /// Removes from the pool the transactions found in the given block body.
+    ///
+    /// The block body is a list of SCALE-encoded extrinsics, as found in a block. Extrinsics
+    /// that aren't found in the pool are ignored.
+    ///
+    /// Returns the list of transactions that have been removed.
+    pub fn remove_included(
+        &mut self,
+        block_body: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> Vec<(TransactionId, Vec<u8>, TTx)> {
+        let mut out = Vec::new();
+        for extrinsic in block_body {
+            if let Some(id) = self.find_by_hash(&hash(extrinsic.as_ref())) {
+                let (scale_encoded, user_data) = self.remove(id);
+                out.push((id, scale_encoded, user_data));
+            }
+        }
+        out
+    }
+
+    /// Returns the list of validated transactions that can be included, in this order, in a
+    /// block built on top of the current best block.
+    ///
+    /// A transaction is returned only if all the tags it requires are provided by transactions
+    /// returned before it. Transactions that provide a tag already provided by a transaction
+    /// returned earlier are skipped. Amongst all the transactions that are ready to be
+    /// included, the ones with the highest priority are returned first.
+    ///
+    /// > **Note**: Transactions that haven't been validated against the current best block
+    /// >           are included based on the outcome of their latest validation.
+    pub fn ready_transactions(&'_ self) -> impl Iterator<Item = (TransactionId, &'_ [u8])> + '_ {
+        // For each tag, the list of transactions that require it.
+        let mut requiring: HashMap<&[u8], Vec<usize>, fnv::FnvBuildHasher> = Default::default();
+        // For each transaction, the number of required tags that haven't been provided yet.
+        let mut missing: HashMap<usize, usize, fnv::FnvBuildHasher> = Default::default();
+        // Transactions whose requirements are all satisfied.
+        let mut ready = BinaryHeap::new();
+
+        for (id, tx) in &self.transactions {
+            let validation = match &tx.validation {
+                Some(v) => &v.result,
+                None => continue,
+            };
+
+            let mut requires = validation
+                .requires
+                .iter()
+                .map(|tag| &tag[..])
+                .collect::<Vec<_>>();
+            requires.sort_unstable();
+            requires.dedup();
+
+            if requires.is_empty() {
+                ready.push(ReadyEntry::new(id, validation.priority));
+            } else {
+                missing.insert(id, requires.len());
+                for tag in requires {
+                    requiring.entry(tag).or_default().push(id);
+                }
+            }
+        }
+
+        let mut provided: HashSet<&[u8], fnv::FnvBuildHasher> = Default::default();
+        let mut out = Vec::with_capacity(self.transactions.len());
+
+        while let Some(entry) = ready.pop() {
+            let tx = &self.transactions[entry.id];
+            let validation = &tx.validation.as_ref().unwrap().result;
+
+            if validation
+                .provides
+                .iter()
+                .any(|tag| provided.contains(&tag[..]))
+            {
+                continue;
+            }
+
+            out.push((TransactionId(entry.id), &tx.scale_encoded[..]));
+
+            for tag in &validation.provides {
+                if !provided.insert(&tag[..]) {
+                    continue;
+                }
+
+                for dependent in requiring.remove(&tag[..]).into_iter().flatten() {
+                    let missing_count = missing.get_mut(&dependent).unwrap();
+                    *missing_count -= 1;
+                    if *missing_count == 0 {
+                        let priority = self.transactions[dependent]
+                            .validation
+                            .as_ref()
+                            .unwrap()
+                            .result
+                            .priority;
+                        ready.push(ReadyEntry::new(dependent, priority));
+                    }
+                }
+            }
+        }
+
+        out.into_iter()
+    }
+}
+
+impl<TTx: fmt::Debug> fmt::Debug for Pool<TTx> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.transactions
+                    .iter()
+                    .map(|(id, tx)| (TransactionId(id), &tx.user_data)),
+            )
+            .finish()
+    }
+}
+
+/// Error potentially returned by [`Pool::add_unvalidated`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum AddError {
+    /// The transaction is already in the pool.
+    #[display(fmt = "Transaction already in pool")]
+    AlreadyInPool(TransactionId),
+}
+
+/// Entry in the priority queue of [`Pool::ready_transactions`].
+#[derive(PartialEq, Eq)]
+struct ReadyEntry {
+    priority: u64,
+    id: usize,
+}
+
+impl ReadyEntry {
+    fn new(id: usize, priority: u64) -> Self {
+        ReadyEntry { priority, id }
+    }
+}
+
+impl Ord for ReadyEntry {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        // Highest priority first. In case of equality, the transaction that has been inserted
+        // in the pool first (i.e. has the lowest identifier most of the time) comes first.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+impl PartialOrd for ReadyEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Calculates the hash of a transaction.
+fn hash(scale_encoded: &[u8]) -> [u8; 32] {
+    let mut out = [0; 32];
+    out.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], scale_encoded).as_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddError, Config, Pool};
+    use crate::transactions::validate::ValidTransaction;
+
+    fn valid(priority: u64, requires: &[&[u8]], provides: &[&[u8]]) -> ValidTransaction {
+        ValidTransaction {
+            priority,
+            requires: requires.iter().map(|t| t.to_vec()).collect(),
+            provides: provides.iter().map(|t| t.to_vec()).collect(),
+            longevity: u64::max_value(),
+            propagate: true,
+        }
+    }
+
+    fn new_pool() -> Pool<()> {
+        Pool::new(Config {
+            capacity: 16,
+            best_block_hash: [0; 32],
+            best_block_height: 0,
+        })
+    }
+
+    #[test]
+    fn duplicate_rejected() {
+        let mut pool = new_pool();
+        let id = pool.add_unvalidated(vec![1, 2, 3], ()).unwrap();
+        assert_eq!(
+            pool.add_unvalidated(vec![1, 2, 3], ()),
+            Err(AddError::AlreadyInPool(id))
+        );
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn ready_ordering() {
+        let mut pool = new_pool();
+        let a = pool.add_unvalidated(vec![1], ()).unwrap();
+        let b = pool.add_unvalidated(vec![2], ()).unwrap();
+        let c = pool.add_unvalidated(vec![3], ()).unwrap();
+        let d = pool.add_unvalidated(vec![4], ()).unwrap();
+        let e = pool.add_unvalidated(vec![5], ()).unwrap();
+        let unvalidated = pool.add_unvalidated(vec![6], ()).unwrap();
+
+        // `b` requires `a`, `c` has the highest priority, `d` conflicts with `a`, and `e`
+        // requires a tag that nothing provides.
+        pool.set_validation_result(a, [0; 32], 0, valid(1, &[], &[b"a"]));
+        pool.set_validation_result(b, [0; 32], 0, valid(100, &[b"a"], &[b"b"]));
+        pool.set_validation_result(c, [0; 32], 0, valid(10, &[], &[b"c"]));
+        pool.set_validation_result(d, [0; 32], 0, valid(0, &[], &[b"a"]));
+        pool.set_validation_result(e, [0; 32], 0, valid(1000, &[b"z"], &[]));
+
+        let ready = pool
+            .ready_transactions()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(ready, vec![c, a, b]);
+
+        assert_eq!(
+            pool.unvalidated_transactions()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>(),
+            vec![unvalidated]
+        );
+    }
+
+    #[test]
+    fn revalidation_and_longevity() {
+        let mut pool = new_pool();
+        let a = pool.add_unvalidated(vec![1], ()).unwrap();
+        let b = pool.add_unvalidated(vec![2], ()).unwrap();
+        pool.set_validation_result(
+            a,
+            [0; 32],
+            0,
+            ValidTransaction {
+                longevity: 2,
+                ..valid(1, &[], &[b"a"])
+            },
+        );
+        pool.set_validation_result(b, [0; 32], 0, valid(1, &[], &[b"b"]));
+        assert_eq!(pool.unvalidated_transactions().count(), 0);
+
+        assert!(pool.set_best_block([1; 32], 1).is_empty());
+        assert_eq!(pool.unvalidated_transactions().count(), 2);
+
+        let removed = pool.set_best_block([3; 32], 3);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].0, a);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn remove_included() {
+        let mut pool = new_pool();
+        let a = pool.add_unvalidated(vec![1, 2], ()).unwrap();
+        let b = pool.add_unvalidated(vec![3, 4], ()).unwrap();
+
+        let removed = pool.remove_included([&[5, 6][..], &[1, 2][..]].iter());
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].0, a);
+        assert_eq!(pool.find_by_hash(pool.hash(b)), Some(b));
+        assert_eq!(pool.len(), 1);
+    }
+}
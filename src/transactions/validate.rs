@@ -0,0 +1,502 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runtime call to obtain the validity status of a transaction.
+//!
+//! Validating a transaction consists in calling the `TaggedTransactionQueue_validate_transaction`
+//! runtime function on top of the storage of a certain block. The runtime indicates whether the
+//! transaction is valid and, if so, provides information about how to order it relative to
+//! the other transactions of the pool. See [`ValidTransaction`].
+//!
+//! Calling the runtime might require accessing the storage of the block the validation is
+//! performed against. Any modification to the storage made by the runtime during the call is
+//! discarded.
+
+use crate::{
+    executor::{host, runtime_host},
+    util,
+};
+
+use alloc::vec::Vec;
+use core::iter;
+
+/// Configuration for a transaction validation process.
+pub struct Config<'a> {
+    /// Runtime used to validate the transaction. Must be built using the Wasm code found at the
+    /// `:code` key of the block storage.
+    pub runtime: host::HostVmPrototype,
+
+    /// Hash of the block the validation is performed against.
+    pub block_hash: &'a [u8; 32],
+
+    /// Source of the transaction.
+    pub source: TransactionSource,
+
+    /// SCALE-encoded transaction, as found in the body of a block (i.e. without any length
+    /// prefix).
+    pub scale_encoded_transaction: &'a [u8],
+}
+
+/// Source of the transaction.
+///
+/// The runtime might apply different rules depending on the source of the transaction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransactionSource {
+    /// Transaction is already included in a block.
+    ///
+    /// It isn't possible to tell where the transaction is coming from, since it's already
+    /// included in a received block.
+    InBlock,
+
+    /// Transaction is coming from a local source, for example the JSON-RPC interface of the
+    /// node.
+    Local,
+
+    /// Transaction has been received externally, for example from the peer-to-peer network.
+    External,
+}
+
+/// Information concerning a valid transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidTransaction {
+    /// Priority of the transaction.
+    ///
+    /// Priority determines the ordering of two transactions that have all their dependencies
+    /// (required tags) satisfied.
+    pub priority: u64,
+
+    /// Transaction dependencies.
+    ///
+    /// A non-empty list signifies that some other transactions which provide the given tags
+    /// must be included in the chain before this one.
+    pub requires: Vec<Vec<u8>>,
+
+    /// Tags provided by the transaction.
+    ///
+    /// Including the transaction in the chain enables other transactions that depend on
+    /// (require) these tags to be included as well. Two transactions that provide the same tag
+    /// are mutually exclusive.
+    pub provides: Vec<Vec<u8>>,
+
+    /// Number of blocks, starting from the block the validation has been performed against,
+    /// during which the transaction is considered valid.
+    ///
+    /// After this period, the transaction must be either revalidated or dropped.
+    pub longevity: u64,
+
+    /// If `false`, the transaction should not be gossiped to other nodes of the network, but
+    /// can still be included in a block authored by the local node.
+    pub propagate: bool,
+}
+
+/// Errors that can occur while checking the validity of a transaction.
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum TransactionValidityError {
+    /// The transaction is invalid.
+    Invalid(InvalidTransaction),
+    /// Transaction validity can't be determined.
+    Unknown(UnknownTransaction),
+}
+
+/// An invalid transaction validity.
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum InvalidTransaction {
+    /// The call of the transaction is not expected.
+    Call,
+    /// General error to do with the inability to pay some fees (e.g. account balance too low).
+    Payment,
+    /// General error to do with the transaction not yet being valid (e.g. nonce too high).
+    Future,
+    /// General error to do with the transaction being outdated (e.g. nonce too low).
+    Stale,
+    /// General error to do with the transaction's proofs (e.g. signature).
+    ///
+    /// # Possible causes
+    ///
+    /// When using a signed extension that provides additional data for signing, it is required
+    /// that the signing and the verifying side use the same additional data. Additional
+    /// data will only be used to generate the signature, but will not be part of the transaction
+    /// itself. As the verifying side does not know which additional data was used while signing
+    /// it will only be able to assume a bad signature and cannot express a more meaningful error.
+    BadProof,
+    /// The transaction birth block is ancient.
+    AncientBirthBlock,
+    /// The transaction would exhaust the resources of current block.
+    ///
+    /// The transaction might be valid, but there are not enough resources
+    /// left in the current block.
+    ExhaustsResources,
+    /// Any other custom invalid validity that is not covered by this enum.
+    Custom(u8),
+    /// An extrinsic with a Mandatory dispatch resulted in Error. This is indicative of either a
+    /// malicious validator or a buggy `provide_inherent`. In any case, it can result in dangerously
+    /// overweight blocks and therefore if found, invalidates the block.
+    BadMandatory,
+    /// A transaction with a mandatory dispatch. This is invalid; only inherent extrinsics are
+    /// allowed to have mandatory dispatches.
+    MandatoryDispatch,
+}
+
+/// An unknown transaction validity.
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum UnknownTransaction {
+    /// Could not lookup some information that is required to validate the transaction.
+    CannotLookup,
+    /// No validator found for the given unsigned transaction.
+    NoUnsignedValidator,
+    /// Any other custom unknown validity that is not covered by this enum.
+    Custom(u8),
+}
+
+/// Problem encountered during a call to [`validate_transaction`].
+#[derive(Debug, derive_more::Display)]
+pub enum Error {
+    /// Error while starting the Wasm virtual machine.
+    #[display(fmt = "{}", _0)]
+    WasmStart(host::StartErr),
+    /// Error while running the Wasm virtual machine.
+    #[display(fmt = "{}", _0)]
+    WasmVm(runtime_host::ErrorDetail),
+    /// Output of `TaggedTransactionQueue_validate_transaction` couldn't be decoded.
+    BadOutput,
+}
+
+/// Validates a transaction by calling `TaggedTransactionQueue_validate_transaction`.
+// TODO: this assumes version 3 of the `TaggedTransactionQueue` runtime API; older runtimes expect a different parameter
+pub fn validate_transaction(config: Config) -> Query {
+    let vm = runtime_host::run(runtime_host::Config {
+        virtual_machine: config.runtime,
+        function_to_call: "TaggedTransactionQueue_validate_transaction",
+        parameter: {
+            // The function expects a SCALE-encoded `(TransactionSource, Extrinsic, BlockHash)`,
+            // where the extrinsic is itself a SCALE-encoded `Vec<u8>`.
+            let source = [match config.source {
+                TransactionSource::InBlock => 0,
+                TransactionSource::Local => 1,
+                TransactionSource::External => 2,
+            }];
+            let len = util::encode_scale_compact_usize(config.scale_encoded_transaction.len());
+            iter::once(either::Left(either::Left(source)))
+                .chain(iter::once(either::Left(either::Right(len))))
+                .chain(iter::once(either::Right(config.scale_encoded_transaction)))
+                .chain(iter::once(either::Right(&config.block_hash[..])))
+        },
+        top_trie_root_calculation_cache: None,
+        storage_top_trie_changes: Default::default(),
+        offchain_storage_changes: Default::default(),
+    });
+
+    match vm {
+        Ok(vm) => Query::from_inner(vm),
+        Err((err, virtual_machine)) => Query::Finished {
+            result: Err(Error::WasmStart(err)),
+            virtual_machine,
+        },
+    }
+}
+
+/// Current state of the operation.
+#[must_use]
+pub enum Query {
+    /// Validating the transaction is over.
+    Finished {
+        /// Outcome of the validation. The outer `Result` indicates whether the runtime call
+        /// has succeeded, while the inner `Result` contains the validity of the transaction
+        /// according to the runtime.
+        result: Result<Result<ValidTransaction, TransactionValidityError>, Error>,
+        /// Value of [`Config::runtime`] passed back.
+        virtual_machine: host::HostVmPrototype,
+    },
+    /// Loading a storage value is required in order to continue.
+    StorageGet(StorageGet),
+    /// Fetching the list of keys with a given prefix is required in order to continue.
+    PrefixKeys(PrefixKeys),
+    /// Fetching the key that follows a given one is required in order to continue.
+    NextKey(NextKey),
+}
+
+impl Query {
+    fn from_inner(inner: runtime_host::RuntimeHostVm) -> Self {
+        match inner {
+            runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                let decoded = {
+                    let output = success.virtual_machine.value();
+                    let result = nom::combinator::all_consuming(validate_transaction_output)(
+                        output.as_ref(),
+                    )
+                    .map(|(_, out)| out)
+                    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| Error::BadOutput);
+                    result
+                };
+
+                Query::Finished {
+                    result: decoded,
+                    virtual_machine: success.virtual_machine.into_prototype(),
+                }
+            }
+            runtime_host::RuntimeHostVm::Finished(Err(err)) => Query::Finished {
+                result: Err(Error::WasmVm(err.detail)),
+                virtual_machine: err.prototype,
+            },
+            runtime_host::RuntimeHostVm::StorageGet(inner) => Query::StorageGet(StorageGet(inner)),
+            runtime_host::RuntimeHostVm::PrefixKeys(inner) => Query::PrefixKeys(PrefixKeys(inner)),
+            runtime_host::RuntimeHostVm::NextKey(inner) => Query::NextKey(NextKey(inner)),
+        }
+    }
+}
+
+/// Loading a storage value is required in order to continue.
+#[must_use]
+pub struct StorageGet(runtime_host::StorageGet);
+
+impl StorageGet {
+    /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl Iterator<Item = impl AsRef<[u8]> + '_> + '_ {
+        self.0.key()
+    }
+
+    /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
+    ///
+    /// This method is a shortcut for calling `key` and concatenating the returned slices.
+    pub fn key_as_vec(&self) -> Vec<u8> {
+        self.0.key_as_vec()
+    }
+
+    /// Injects the corresponding storage value.
+    pub fn inject_value(self, value: Option<impl Iterator<Item = impl AsRef<[u8]>>>) -> Query {
+        Query::from_inner(self.0.inject_value(value))
+    }
+}
+
+/// Fetching the list of keys with a given prefix is required in order to continue.
+#[must_use]
+pub struct PrefixKeys(runtime_host::PrefixKeys);
+
+impl PrefixKeys {
+    /// Returns the prefix whose keys to load.
+    pub fn prefix(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.0.prefix()
+    }
+
+    /// Injects the list of keys.
+    pub fn inject_keys(self, keys: impl Iterator<Item = impl AsRef<[u8]>>) -> Query {
+        Query::from_inner(self.0.inject_keys(keys))
+    }
+}
+
+/// Fetching the key that follows a given one is required in order to continue.
+#[must_use]
+pub struct NextKey(runtime_host::NextKey);
+
+impl NextKey {
+    /// Returns the key whose next key must be passed back.
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.0.key()
+    }
+
+    /// Injects the key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the key passed as parameter isn't strictly superior to the requested key.
+    ///
+    pub fn inject_key(self, key: Option<impl AsRef<[u8]>>) -> Query {
+        Query::from_inner(self.0.inject_key(key))
+    }
+}
+
+fn validate_transaction_output(
+    bytes: &[u8],
+) -> nom::IResult<&[u8], Result<ValidTransaction, TransactionValidityError>> {
+    nom::error::context(
+        "validate transaction output",
+        nom::branch::alt((
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag(&[0]), valid_transaction),
+                Ok,
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[1]),
+                    transaction_validity_error,
+                ),
+                Err,
+            ),
+        )),
+    )(bytes)
+}
+
+fn valid_transaction(bytes: &[u8]) -> nom::IResult<&[u8], ValidTransaction> {
+    nom::error::context(
+        "valid transaction",
+        nom::combinator::map(
+            nom::sequence::tuple((
+                nom::number::complete::le_u64,
+                tags,
+                tags,
+                nom::number::complete::le_u64,
+                nom::branch::alt((
+                    nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| false),
+                    nom::combinator::map(nom::bytes::complete::tag(&[1]), |_| true),
+                )),
+            )),
+            |(priority, requires, provides, longevity, propagate)| ValidTransaction {
+                priority,
+                requires,
+                provides,
+                longevity,
+                propagate,
+            },
+        ),
+    )(bytes)
+}
+
+fn tags(bytes: &[u8]) -> nom::IResult<&[u8], Vec<Vec<u8>>> {
+    nom::combinator::flat_map(util::nom_scale_compact_usize, |num_tags| {
+        nom::multi::many_m_n(
+            num_tags,
+            num_tags,
+            nom::combinator::map(
+                nom::multi::length_data(util::nom_scale_compact_usize),
+                |tag: &[u8]| tag.to_vec(),
+            ),
+        )
+    })(bytes)
+}
+
+pub(crate) fn transaction_validity_error(
+    bytes: &[u8],
+) -> nom::IResult<&[u8], TransactionValidityError> {
+    nom::error::context(
+        "transaction validity error",
+        nom::branch::alt((
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag(&[0]), invalid_transaction),
+                TransactionValidityError::Invalid,
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag(&[1]), unknown_transaction),
+                TransactionValidityError::Unknown,
+            ),
+        )),
+    )(bytes)
+}
+
+fn invalid_transaction(bytes: &[u8]) -> nom::IResult<&[u8], InvalidTransaction> {
+    nom::error::context(
+        "invalid transaction",
+        nom::branch::alt((
+            nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| {
+                InvalidTransaction::Call
+            }),
+            nom::combinator::map(nom::bytes::complete::tag(&[1]), |_| {
+                InvalidTransaction::Payment
+            }),
+            nom::combinator::map(nom::bytes::complete::tag(&[2]), |_| {
+                InvalidTransaction::Future
+            }),
+            nom::combinator::map(nom::bytes::complete::tag(&[3]), |_| {
+                InvalidTransaction::Stale
+            }),
+            nom::combinator::map(nom::bytes::complete::tag(&[4]), |_| {
+                InvalidTransaction::BadProof
+            }),
+            nom::combinator::map(nom::bytes::complete::tag(&[5]), |_| {
+                InvalidTransaction::AncientBirthBlock
+            }),
+            nom::combinator::map(nom::bytes::complete::tag(&[6]), |_| {
+                InvalidTransaction::ExhaustsResources
+            }),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[7]),
+                    nom::bytes::complete::take(1u32),
+                ),
+                |n: &[u8]| InvalidTransaction::Custom(n[0]),
+            ),
+            nom::combinator::map(nom::bytes::complete::tag(&[8]), |_| {
+                InvalidTransaction::BadMandatory
+            }),
+            nom::combinator::map(nom::bytes::complete::tag(&[9]), |_| {
+                InvalidTransaction::MandatoryDispatch
+            }),
+        )),
+    )(bytes)
+}
+
+fn unknown_transaction(bytes: &[u8]) -> nom::IResult<&[u8], UnknownTransaction> {
+    nom::error::context(
+        "unknown transaction",
+        nom::branch::alt((
+            nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| {
+                UnknownTransaction::CannotLookup
+            }),
+            nom::combinator::map(nom::bytes::complete::tag(&[1]), |_| {
+                UnknownTransaction::NoUnsignedValidator
+            }),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[2]),
+                    nom::bytes::complete::take(1u32),
+                ),
+                |n: &[u8]| UnknownTransaction::Custom(n[0]),
+            ),
+        )),
+    )(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode_valid_transaction() {
+        let output = [
+            0, 5, 0, 0, 0, 0, 0, 0, 0, 4, 16, 1, 2, 3, 4, 4, 8, 4, 3, 255, 255, 255, 255, 255, 255,
+            255, 255, 1,
+        ];
+
+        let (_, decoded) =
+            nom::combinator::all_consuming(super::validate_transaction_output)(&output[..])
+                .unwrap();
+
+        assert_eq!(
+            decoded,
+            Ok(super::ValidTransaction {
+                priority: 5,
+                requires: vec![vec![1, 2, 3, 4]],
+                provides: vec![vec![4, 3]],
+                longevity: u64::max_value(),
+                propagate: true,
+            })
+        );
+    }
+
+    #[test]
+    fn decode_invalid_transaction() {
+        let output = [1, 0, 7, 12];
+
+        let (_, decoded) =
+            nom::combinator::all_consuming(super::validate_transaction_output)(&output[..])
+                .unwrap();
+
+        assert_eq!(
+            decoded,
+            Err(super::TransactionValidityError::Invalid(
+                super::InvalidTransaction::Custom(12)
+            ))
+        );
+    }
+}
@@ -82,6 +82,15 @@ pub struct VerifySuccess {
     /// If true, the block has a change of authorities that must be reflected when verifying the
     /// following block.
     pub authorities_change: bool,
+
+    /// Slot number the block belongs to.
+    ///
+    /// > **Note**: This is a simple reminder. The value can also be found in the header of the
+    /// >           block.
+    pub slot_number: u64,
+
+    /// Public key of the authority that has signed the block.
+    pub authority_public_key: [u8; 32],
 }
 
 /// Failure to verify a block.
@@ -183,22 +192,25 @@ pub fn verify_header<'a>(
         usize::try_from(slot_number % u64::try_from(config.current_authorities.len()).unwrap())
             .unwrap();
 
+    let authority_public_key = *config
+        .current_authorities
+        .nth(signing_authority)
+        .unwrap()
+        .public_key;
+
     // This `unwrap()` can only panic if `public_key` is the wrong length, which we know can't
     // happen as it's of type `[u8; 32]`.
-    let authority_public_key = schnorrkel::PublicKey::from_bytes(
-        config
-            .current_authorities
-            .nth(signing_authority)
-            .unwrap()
-            .public_key,
-    )
-    .unwrap();
+    let signing_public_key = schnorrkel::PublicKey::from_bytes(&authority_public_key).unwrap();
 
     // Now verifying the signature in the seal.
-    authority_public_key
+    signing_public_key
         .verify_simple(b"substrate", &pre_seal_hash, &seal_signature)
         .map_err(|_| VerifyError::BadSignature)?;
 
     // Success! 🚀
-    Ok(VerifySuccess { authorities_change })
+    Ok(VerifySuccess {
+        authorities_change,
+        slot_number,
+        authority_public_key,
+    })
 }
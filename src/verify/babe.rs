@@ -171,6 +171,9 @@ pub struct VerifySuccess {
     /// >           block.
     pub slot_number: u64,
 
+    /// Public key of the authority that has signed the block.
+    pub authority_public_key: [u8; 32],
+
     /// If `Some`, the verified block contains an epoch transition describing the new "next epoch".
     /// When verifying blocks that are children of this one, the value in this field must be
     /// provided as [`VerifyConfig::parent_block_next_epoch`], and the value previously in
@@ -398,13 +401,11 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
     if let Some((vrf_output, vrf_proof)) = vrf_output_and_proof {
         // In order to verify the VRF output, we first need to create a transcript containing all
         // the data to verify the VRF against.
-        let transcript = {
-            let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
-            transcript.append_u64(b"slot number", slot_number);
-            transcript.append_u64(b"current epoch", block_epoch_info.epoch_index);
-            transcript.append_message(b"chain randomness", &block_epoch_info.randomness[..]);
-            transcript
-        };
+        let transcript = vrf_transcript(
+            block_epoch_info.randomness,
+            slot_number,
+            block_epoch_info.epoch_index,
+        );
 
         // These `unwrap()`s can only panic if `vrf_output` or `vrf_proof` are of the wrong
         // length, which we know can't happen as they're of types `[u8; 32]` and `[u8; 64]`.
@@ -423,9 +424,7 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
                 block_epoch_info.authorities.clone().map(|a| a.weight),
                 signing_authority.weight,
             );
-            if u128::from_le_bytes(vrf_in_out.make_bytes::<[u8; 16]>(b"substrate-babe-vrf"))
-                >= threshold
-            {
+            if vrf_output_to_u128(&vrf_in_out) >= threshold {
                 return Err(VerifyError::OverPrimaryClaimThreshold);
             }
         }
@@ -437,23 +436,13 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
     // claim. If the block is a secondary slot claim, we need to make sure that the author
     // is indeed the one that is expected.
     if !primary_slot_claim {
-        // Expected author is determined based on `blake2(randomness | slot_number)`.
-        let hash = {
-            let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
-            hash.update(block_epoch_info.randomness);
-            hash.update(&slot_number.to_le_bytes());
-            hash.finalize()
-        };
-
-        // The expected authority index is `hash % num_authorities`.
-        let expected_authority_index = {
-            let hash = num_bigint::BigUint::from_bytes_be(hash.as_bytes());
-            let authorities_len = num_bigint::BigUint::from(block_epoch_info.authorities.len());
-            debug_assert_ne!(block_epoch_info.authorities.len(), 0);
-            hash % authorities_len
-        };
+        let expected_authority_index = calculate_secondary_slot_author(
+            block_epoch_info.randomness,
+            slot_number,
+            block_epoch_info.authorities.len(),
+        );
 
-        if u32::try_from(expected_authority_index).map_or(true, |v| v != authority_index) {
+        if usize::try_from(authority_index).map_or(true, |v| v != expected_authority_index) {
             return Err(VerifyError::BadSecondarySlotAuthor);
         }
     }
@@ -462,9 +451,58 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
     Ok(VerifySuccess {
         epoch_transition_target,
         slot_number,
+        authority_public_key: *signing_authority.public_key,
     })
 }
 
+/// Builds the transcript that VRF outputs of the given slot and epoch must be generated against.
+pub(crate) fn vrf_transcript(
+    epoch_randomness: &[u8; 32],
+    slot_number: u64,
+    epoch_index: u64,
+) -> merlin::Transcript {
+    let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
+    transcript.append_u64(b"slot number", slot_number);
+    transcript.append_u64(b"current epoch", epoch_index);
+    transcript.append_message(b"chain randomness", &epoch_randomness[..]);
+    transcript
+}
+
+/// Turns a VRF output into the value to compare with the threshold returned by
+/// [`calculate_primary_threshold`].
+pub(crate) fn vrf_output_to_u128(vrf_in_out: &schnorrkel::vrf::VRFInOut) -> u128 {
+    u128::from_le_bytes(vrf_in_out.make_bytes::<[u8; 16]>(b"substrate-babe-vrf"))
+}
+
+/// Calculates the index, within the list of authorities of the epoch, of the authority allowed
+/// to claim the given slot as a secondary slot claim.
+///
+/// # Panic
+///
+/// Panics if `num_authorities` is 0.
+///
+pub(crate) fn calculate_secondary_slot_author(
+    epoch_randomness: &[u8; 32],
+    slot_number: u64,
+    num_authorities: usize,
+) -> usize {
+    assert_ne!(num_authorities, 0);
+
+    // Expected author is determined based on `blake2(randomness | slot_number)`.
+    let hash = {
+        let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
+        hash.update(epoch_randomness);
+        hash.update(&slot_number.to_le_bytes());
+        hash.finalize()
+    };
+
+    // The expected authority index is `hash % num_authorities`.
+    let hash = num_bigint::BigUint::from_bytes_be(hash.as_bytes());
+    let index = hash % num_bigint::BigUint::from(num_authorities);
+    // The modulo guarantees that the index fits in a `usize`.
+    index.to_usize().unwrap()
+}
+
 /// Calculates the primary selection threshold for a given authority, taking
 /// into account `c` (`1 - c` represents the probability of a slot being empty).
 ///
@@ -478,7 +516,7 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
 /// Panics if `authorities_weights` is empty.
 /// Panics if `authority_weight` is 0.
 ///
-fn calculate_primary_threshold(
+pub(crate) fn calculate_primary_threshold(
     c: (u64, u64),
     authorities_weights: impl ExactSizeIterator<Item = u64>,
     authority_weight: u64, // TODO: use a NonZeroU64 once crate::header also has weights that use NonZeroU64
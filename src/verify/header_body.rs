@@ -138,6 +138,15 @@ pub enum SuccessConsensus {
     Aura {
         /// True if the list of authorities is modified by this block.
         authorities_change: bool,
+
+        /// Slot number the block belongs to.
+        ///
+        /// > **Note**: This is a simple reminder. The value can also be found in the header of the
+        /// >           block.
+        slot_number: u64,
+
+        /// Public key of the authority that has signed the block.
+        authority_public_key: [u8; 32],
     },
 
     /// Chain is using the Babe consensus engine.
@@ -148,6 +157,9 @@ pub enum SuccessConsensus {
         /// >           block.
         slot_number: u64,
 
+        /// Public key of the authority that has signed the block.
+        authority_public_key: [u8; 32],
+
         /// If `Some`, the verified block contains an epoch transition describing the new
         /// "next epoch". When verifying blocks that are children of this one, the value in this
         /// field must be provided as [`ConfigConsensus::Babe::parent_block_next_epoch`], and the
@@ -212,6 +224,8 @@ pub fn verify(
             match result {
                 Ok(s) => SuccessConsensus::Aura {
                     authorities_change: s.authorities_change,
+                    slot_number: s.slot_number,
+                    authority_public_key: s.authority_public_key,
                 },
                 Err(err) => {
                     return Verify::Finished(Err((
@@ -247,6 +261,7 @@ pub fn verify(
                 Ok(s) => SuccessConsensus::Babe {
                     epoch_transition_target: s.epoch_transition_target,
                     slot_number: s.slot_number,
+                    authority_public_key: s.authority_public_key,
                 },
                 Err(err) => {
                     return Verify::Finished(Err((
@@ -97,6 +97,15 @@ pub enum Success {
     Aura {
         /// True if the list of authorities is modified by this block.
         authorities_change: bool,
+
+        /// Slot number the block belongs to.
+        ///
+        /// > **Note**: This is a simple reminder. The value can also be found in the header of the
+        /// >           block.
+        slot_number: u64,
+
+        /// Public key of the authority that has signed the block.
+        authority_public_key: [u8; 32],
     },
 
     /// Chain is using the Babe consensus engine.
@@ -107,6 +116,9 @@ pub enum Success {
         /// >           block.
         slot_number: u64,
 
+        /// Public key of the authority that has signed the block.
+        authority_public_key: [u8; 32],
+
         /// If `Some`, the verified block contains an epoch transition describing the new
         /// "next epoch". When verifying blocks that are children of this one, the value in this
         /// field must be provided as [`ConfigConsensus::Babe::parent_block_next_epoch`], and the
@@ -190,6 +202,8 @@ pub fn verify(config: Config) -> Result<Success, Error> {
             match result {
                 Ok(s) => Ok(Success::Aura {
                     authorities_change: s.authorities_change,
+                    slot_number: s.slot_number,
+                    authority_public_key: s.authority_public_key,
                 }),
                 Err(err) => Err(Error::AuraVerification(err)),
             }
@@ -217,6 +231,7 @@ pub fn verify(config: Config) -> Result<Success, Error> {
                 Ok(s) => Ok(Success::Babe {
                     epoch_transition_target: s.epoch_transition_target,
                     slot_number: s.slot_number,
+                    authority_public_key: s.authority_public_key,
                 }),
                 Err(err) => Err(Error::BabeVerification(err)),
             }